@@ -0,0 +1,9 @@
+CREATE TABLE findings (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    severity VARCHAR NOT NULL,
+    title VARCHAR NOT NULL,
+    family VARCHAR,
+    key INTEGER,
+    details VARCHAR,
+    created DATETIME NOT NULL
+);
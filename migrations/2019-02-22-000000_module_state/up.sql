@@ -0,0 +1,8 @@
+CREATE TABLE module_state (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    module VARCHAR NOT NULL,
+    key VARCHAR NOT NULL,
+    value VARCHAR NOT NULL,
+    version VARCHAR NOT NULL,
+    CONSTRAINT module_state_unique UNIQUE (module, key)
+);
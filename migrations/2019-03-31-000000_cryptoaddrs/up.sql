@@ -0,0 +1,8 @@
+CREATE TABLE cryptoaddrs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    value VARCHAR NOT NULL,
+    currency VARCHAR NOT NULL,
+    label VARCHAR,
+    unscoped BOOLEAN DEFAULT 0 NOT NULL,
+    CONSTRAINT cryptoaddr_unique UNIQUE (currency, value)
+);
@@ -0,0 +1,20 @@
+PRAGMA foreign_keys=off;
+
+CREATE TABLE _findings_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    severity VARCHAR NOT NULL,
+    title VARCHAR NOT NULL,
+    family VARCHAR,
+    key INTEGER,
+    details VARCHAR,
+    created DATETIME NOT NULL
+);
+
+INSERT INTO _findings_new (id, severity, title, family, key, details, created)
+  SELECT id, severity, title, family, key, details, created
+  FROM findings;
+
+DROP TABLE findings;
+ALTER TABLE _findings_new RENAME TO findings;
+
+PRAGMA foreign_keys=on;
@@ -0,0 +1,7 @@
+CREATE TABLE confidences (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    family VARCHAR NOT NULL,
+    key INTEGER NOT NULL,
+    confidence INTEGER NOT NULL,
+    CONSTRAINT confidence_unique UNIQUE (family, key)
+);
@@ -0,0 +1,15 @@
+DROP TRIGGER domains_delete_confidence;
+DROP TRIGGER subdomains_delete_confidence;
+DROP TRIGGER ipaddrs_delete_confidence;
+DROP TRIGGER subdomain_ipaddrs_delete_confidence;
+DROP TRIGGER urls_delete_confidence;
+DROP TRIGGER emails_delete_confidence;
+DROP TRIGGER phonenumbers_delete_confidence;
+DROP TRIGGER devices_delete_confidence;
+DROP TRIGGER networks_delete_confidence;
+DROP TRIGGER network_devices_delete_confidence;
+DROP TRIGGER accounts_delete_confidence;
+DROP TRIGGER breaches_delete_confidence;
+DROP TRIGGER breach_emails_delete_confidence;
+DROP TRIGGER ports_delete_confidence;
+DROP TRIGGER cryptoaddrs_delete_confidence;
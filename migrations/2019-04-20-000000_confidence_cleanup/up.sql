@@ -0,0 +1,94 @@
+-- confidences don't have a foreign key, remove them together with their entity.
+-- This also covers entities that are removed by a cascading delete.
+DELETE FROM confidences WHERE NOT (
+    (family = 'domains' AND key IN (SELECT id FROM domains))
+    OR (family = 'subdomains' AND key IN (SELECT id FROM subdomains))
+    OR (family = 'ipaddrs' AND key IN (SELECT id FROM ipaddrs))
+    OR (family = 'subdomain_ipaddrs' AND key IN (SELECT id FROM subdomain_ipaddrs))
+    OR (family = 'urls' AND key IN (SELECT id FROM urls))
+    OR (family = 'emails' AND key IN (SELECT id FROM emails))
+    OR (family = 'phonenumbers' AND key IN (SELECT id FROM phonenumbers))
+    OR (family = 'devices' AND key IN (SELECT id FROM devices))
+    OR (family = 'networks' AND key IN (SELECT id FROM networks))
+    OR (family = 'network_devices' AND key IN (SELECT id FROM network_devices))
+    OR (family = 'accounts' AND key IN (SELECT id FROM accounts))
+    OR (family = 'breaches' AND key IN (SELECT id FROM breaches))
+    OR (family = 'breach_emails' AND key IN (SELECT id FROM breach_emails))
+    OR (family = 'ports' AND key IN (SELECT id FROM ports))
+    OR (family = 'cryptoaddrs' AND key IN (SELECT id FROM cryptoaddrs))
+);
+
+CREATE TRIGGER domains_delete_confidence AFTER DELETE ON domains
+BEGIN
+    DELETE FROM confidences WHERE family = 'domains' AND key = OLD.id;
+END;
+
+CREATE TRIGGER subdomains_delete_confidence AFTER DELETE ON subdomains
+BEGIN
+    DELETE FROM confidences WHERE family = 'subdomains' AND key = OLD.id;
+END;
+
+CREATE TRIGGER ipaddrs_delete_confidence AFTER DELETE ON ipaddrs
+BEGIN
+    DELETE FROM confidences WHERE family = 'ipaddrs' AND key = OLD.id;
+END;
+
+CREATE TRIGGER subdomain_ipaddrs_delete_confidence AFTER DELETE ON subdomain_ipaddrs
+BEGIN
+    DELETE FROM confidences WHERE family = 'subdomain_ipaddrs' AND key = OLD.id;
+END;
+
+CREATE TRIGGER urls_delete_confidence AFTER DELETE ON urls
+BEGIN
+    DELETE FROM confidences WHERE family = 'urls' AND key = OLD.id;
+END;
+
+CREATE TRIGGER emails_delete_confidence AFTER DELETE ON emails
+BEGIN
+    DELETE FROM confidences WHERE family = 'emails' AND key = OLD.id;
+END;
+
+CREATE TRIGGER phonenumbers_delete_confidence AFTER DELETE ON phonenumbers
+BEGIN
+    DELETE FROM confidences WHERE family = 'phonenumbers' AND key = OLD.id;
+END;
+
+CREATE TRIGGER devices_delete_confidence AFTER DELETE ON devices
+BEGIN
+    DELETE FROM confidences WHERE family = 'devices' AND key = OLD.id;
+END;
+
+CREATE TRIGGER networks_delete_confidence AFTER DELETE ON networks
+BEGIN
+    DELETE FROM confidences WHERE family = 'networks' AND key = OLD.id;
+END;
+
+CREATE TRIGGER network_devices_delete_confidence AFTER DELETE ON network_devices
+BEGIN
+    DELETE FROM confidences WHERE family = 'network_devices' AND key = OLD.id;
+END;
+
+CREATE TRIGGER accounts_delete_confidence AFTER DELETE ON accounts
+BEGIN
+    DELETE FROM confidences WHERE family = 'accounts' AND key = OLD.id;
+END;
+
+CREATE TRIGGER breaches_delete_confidence AFTER DELETE ON breaches
+BEGIN
+    DELETE FROM confidences WHERE family = 'breaches' AND key = OLD.id;
+END;
+
+CREATE TRIGGER breach_emails_delete_confidence AFTER DELETE ON breach_emails
+BEGIN
+    DELETE FROM confidences WHERE family = 'breach_emails' AND key = OLD.id;
+END;
+
+CREATE TRIGGER ports_delete_confidence AFTER DELETE ON ports
+BEGIN
+    DELETE FROM confidences WHERE family = 'ports' AND key = OLD.id;
+END;
+
+CREATE TRIGGER cryptoaddrs_delete_confidence AFTER DELETE ON cryptoaddrs
+BEGIN
+    DELETE FROM confidences WHERE family = 'cryptoaddrs' AND key = OLD.id;
+END;
@@ -0,0 +1,6 @@
+-- urls that were added before they got canonicalized are fixed up in rust,
+-- every row is a data migration that runs once after the sql migrations
+CREATE TABLE data_migrations (
+    name VARCHAR PRIMARY KEY NOT NULL
+);
+INSERT INTO data_migrations (name) VALUES ('canonical_urls');
@@ -0,0 +1,6 @@
+CREATE TABLE download_stats (
+    module_id INTEGER NOT NULL REFERENCES modules(id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    count INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (module_id, day)
+);
@@ -0,0 +1,10 @@
+CREATE TABLE module_transfers (
+    id SERIAL PRIMARY KEY,
+    module_id INTEGER NOT NULL REFERENCES modules(id) ON DELETE CASCADE,
+    old_author VARCHAR NOT NULL,
+    new_author VARCHAR NOT NULL,
+    name VARCHAR NOT NULL,
+    transferred TIMESTAMP NOT NULL DEFAULT (NOW() AT TIME ZONE 'UTC')
+);
+
+CREATE INDEX module_transfers_old_author_name_idx ON module_transfers (old_author, name);
@@ -0,0 +1,18 @@
+CREATE TABLE upload_sessions (
+    id SERIAL PRIMARY KEY,
+    author VARCHAR NOT NULL,
+    name VARCHAR NOT NULL,
+    checksum VARCHAR NOT NULL,
+    parts INTEGER NOT NULL,
+    allow_unchanged BOOLEAN NOT NULL DEFAULT FALSE,
+    created TIMESTAMP NOT NULL DEFAULT (NOW() AT TIME ZONE 'UTC')
+);
+
+CREATE INDEX upload_sessions_created_idx ON upload_sessions (created);
+
+CREATE TABLE upload_parts (
+    session_id INTEGER NOT NULL REFERENCES upload_sessions(id) ON DELETE CASCADE,
+    idx INTEGER NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (session_id, idx)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE upload_sessions DROP COLUMN prerelease;
+ALTER TABLE releases DROP COLUMN prerelease;
@@ -0,0 +1,2 @@
+ALTER TABLE releases ADD COLUMN prerelease BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE upload_sessions ADD COLUMN prerelease BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1 @@
+ALTER TABLE releases ADD COLUMN readme TEXT;
@@ -0,0 +1 @@
+ALTER TABLE upload_sessions ADD COLUMN skip_syntax_check BOOLEAN NOT NULL DEFAULT false;
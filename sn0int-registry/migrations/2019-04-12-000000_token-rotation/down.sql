@@ -0,0 +1,2 @@
+ALTER TABLE auth_tokens DROP COLUMN previous_token_expires;
+ALTER TABLE auth_tokens DROP COLUMN previous_token_hash;
@@ -0,0 +1,2 @@
+ALTER TABLE auth_tokens ADD COLUMN previous_token_hash VARCHAR;
+ALTER TABLE auth_tokens ADD COLUMN previous_token_expires TIMESTAMP;
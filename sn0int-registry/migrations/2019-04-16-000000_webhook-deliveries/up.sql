@@ -0,0 +1,14 @@
+CREATE TABLE webhook_deliveries (
+    id SERIAL PRIMARY KEY,
+    url VARCHAR NOT NULL,
+    payload TEXT NOT NULL,
+    signature VARCHAR,
+    attempt INTEGER NOT NULL,
+    status INTEGER,
+    response TEXT,
+    delivered BOOLEAN NOT NULL,
+    replay_of INTEGER REFERENCES webhook_deliveries(id) ON DELETE SET NULL,
+    created TIMESTAMP NOT NULL DEFAULT (NOW() AT TIME ZONE 'UTC')
+);
+
+CREATE INDEX webhook_deliveries_delivered_idx ON webhook_deliveries (delivered);
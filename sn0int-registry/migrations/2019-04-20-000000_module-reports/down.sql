@@ -0,0 +1,2 @@
+DROP TABLE module_reports;
+ALTER TABLE modules DROP COLUMN hidden;
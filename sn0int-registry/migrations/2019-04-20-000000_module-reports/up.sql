@@ -0,0 +1,12 @@
+ALTER TABLE modules ADD COLUMN hidden BOOLEAN NOT NULL DEFAULT false;
+CREATE TABLE module_reports (
+    id SERIAL PRIMARY KEY,
+    module_id INTEGER NOT NULL REFERENCES modules(id) ON DELETE CASCADE,
+    reporter VARCHAR NOT NULL,
+    reason TEXT NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT (NOW() AT TIME ZONE 'UTC'),
+    resolved BOOLEAN NOT NULL DEFAULT false,
+    UNIQUE (module_id, reporter)
+);
+
+CREATE INDEX module_reports_resolved_idx ON module_reports (resolved);
@@ -0,0 +1 @@
+ALTER TABLE releases ADD COLUMN yanked BOOLEAN NOT NULL DEFAULT false;
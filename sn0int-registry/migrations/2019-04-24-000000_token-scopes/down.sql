@@ -0,0 +1,2 @@
+ALTER TABLE auth_tokens DROP COLUMN expires_at;
+ALTER TABLE auth_tokens DROP COLUMN scopes;
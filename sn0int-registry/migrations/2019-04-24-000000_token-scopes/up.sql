@@ -0,0 +1,2 @@
+ALTER TABLE auth_tokens ADD COLUMN scopes VARCHAR[] NOT NULL DEFAULT '{publish,yank,read}';
+ALTER TABLE auth_tokens ADD COLUMN expires_at TIMESTAMP;
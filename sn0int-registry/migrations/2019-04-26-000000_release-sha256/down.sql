@@ -0,0 +1,2 @@
+ALTER TABLE releases ALTER COLUMN sha256 DROP NOT NULL;
+ALTER TABLE releases RENAME COLUMN sha256 TO checksum;
@@ -0,0 +1,3 @@
+UPDATE releases SET checksum = encode(sha256(convert_to(code, 'UTF8')), 'hex') WHERE checksum IS NULL;
+ALTER TABLE releases RENAME COLUMN checksum TO sha256;
+ALTER TABLE releases ALTER COLUMN sha256 SET NOT NULL;
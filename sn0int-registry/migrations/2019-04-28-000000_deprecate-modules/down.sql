@@ -0,0 +1,3 @@
+ALTER TABLE modules DROP COLUMN successor_id;
+ALTER TABLE modules DROP COLUMN deprecated_msg;
+ALTER TABLE modules DROP COLUMN deprecated;
@@ -0,0 +1,3 @@
+ALTER TABLE modules ADD COLUMN deprecated BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE modules ADD COLUMN deprecated_msg TEXT;
+ALTER TABLE modules ADD COLUMN successor_id INTEGER REFERENCES modules(id) ON DELETE SET NULL;
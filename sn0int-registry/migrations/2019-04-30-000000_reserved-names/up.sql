@@ -0,0 +1,12 @@
+CREATE TABLE reserved_names (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR UNIQUE NOT NULL,
+    prefix BOOLEAN NOT NULL DEFAULT false,
+    owners VARCHAR[] NOT NULL DEFAULT '{}'
+);
+
+INSERT INTO reserved_names (name, prefix, owners) VALUES
+    ('sn0int', true, '{kpcyrd}'),
+    ('official', true, '{}'),
+    ('admin', true, '{}'),
+    ('registry', true, '{}');
@@ -0,0 +1 @@
+ALTER TABLE modules ADD COLUMN deleted_at TIMESTAMP;
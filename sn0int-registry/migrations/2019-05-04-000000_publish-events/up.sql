@@ -0,0 +1,7 @@
+CREATE TABLE publish_events (
+    id SERIAL PRIMARY KEY,
+    author VARCHAR NOT NULL,
+    created TIMESTAMP NOT NULL DEFAULT (NOW() AT TIME ZONE 'UTC')
+);
+
+CREATE INDEX publish_events_author_created_idx ON publish_events (author, created);
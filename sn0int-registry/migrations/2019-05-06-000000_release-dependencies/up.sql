@@ -0,0 +1,9 @@
+CREATE TABLE release_dependencies (
+    id SERIAL PRIMARY KEY,
+    release_id INTEGER NOT NULL REFERENCES releases(id) ON DELETE CASCADE,
+    author VARCHAR NOT NULL,
+    name VARCHAR NOT NULL,
+    version_req VARCHAR NOT NULL
+);
+
+CREATE INDEX release_dependencies_release_id_idx ON release_dependencies (release_id);
@@ -0,0 +1,4 @@
+CREATE TABLE authors (
+    name VARCHAR PRIMARY KEY,
+    verified BOOLEAN NOT NULL DEFAULT false
+);
@@ -0,0 +1 @@
+DROP INDEX auth_tokens_previous_token_hash_idx;
@@ -0,0 +1 @@
+CREATE INDEX auth_tokens_previous_token_hash_idx ON auth_tokens (previous_token_hash);
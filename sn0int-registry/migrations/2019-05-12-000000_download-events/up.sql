@@ -0,0 +1,6 @@
+CREATE TABLE download_events (
+    release_id INTEGER NOT NULL REFERENCES releases(id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    count INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (release_id, day)
+);
@@ -0,0 +1,12 @@
+CREATE TABLE download_stats (
+    module_id INTEGER NOT NULL REFERENCES modules(id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    count INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (module_id, day)
+);
+
+INSERT INTO download_stats (module_id, day, count)
+SELECT releases.module_id, download_events.day, SUM(download_events.count)
+FROM download_events
+INNER JOIN releases ON releases.id = download_events.release_id
+GROUP BY releases.module_id, download_events.day;
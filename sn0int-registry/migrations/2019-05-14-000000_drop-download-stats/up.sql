@@ -0,0 +1,15 @@
+-- days from before download_events existed are attributed to the newest
+-- release, downloads are only summed per module
+INSERT INTO download_events (release_id, day, count)
+SELECT DISTINCT ON (download_stats.module_id, download_stats.day) releases.id, download_stats.day, download_stats.count
+FROM download_stats
+INNER JOIN releases ON releases.module_id = download_stats.module_id
+WHERE NOT EXISTS (
+    SELECT 1 FROM download_events
+    INNER JOIN releases AS counted ON counted.id = download_events.release_id
+    WHERE counted.module_id = download_stats.module_id
+        AND download_events.day = download_stats.day
+)
+ORDER BY download_stats.module_id, download_stats.day, releases.id DESC;
+
+DROP TABLE download_stats;
@@ -1,11 +1,44 @@
+use crate::id::ModuleID;
+use crate::metadata::{Capability, Dependency, Metadata, Source};
+
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct WhoamiResponse {
     pub user: String,
 }
 
+/// The new session, the previous one stays valid for a grace period
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RotateResponse {
+    pub session: String,
+}
+
+/// Create an additional session, without scopes the scopes of the current
+/// session are used
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenRequest {
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// Seconds until the new session expires
+    pub expires_in: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenResponse {
+    pub session: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PublishRequest {
     pub code: String,
+    #[serde(default)]
+    pub allow_unchanged: bool,
+    /// Prereleases don't become the latest version of the module
+    #[serde(default)]
+    pub prerelease: bool,
+    /// Publish code the registry can't compile, eg. for a newer runtime
+    #[serde(default)]
+    pub skip_syntax_check: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -15,12 +48,161 @@ pub struct PublishResponse {
     pub version: String,
 }
 
+/// Code larger than this is uploaded in multiple parts, no part may be larger
+pub const UPLOAD_PART_SIZE: usize = 256 * 1024;
+/// The maximum number of parts of a single upload
+pub const MAX_UPLOAD_PARTS: u32 = 64;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadStartRequest {
+    /// The sha256 of the complete code, hex encoded
+    pub checksum: String,
+    pub parts: u32,
+    #[serde(default)]
+    pub allow_unchanged: bool,
+    #[serde(default)]
+    pub prerelease: bool,
+    #[serde(default)]
+    pub skip_syntax_check: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadPartRequest {
+    pub data: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadStatusResponse {
+    pub session: i32,
+    pub parts: u32,
+    /// Parts that haven't arrived yet, starting at 0
+    pub missing: Vec<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransferRequest {
+    pub new_author: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransferResponse {
+    pub previous_author: String,
+    pub author: String,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReleaseResponse {
+    pub version: String,
+    pub downloads: i64,
+    /// rfc 3339 in utc
+    pub published: String,
+    pub prerelease: bool,
+    pub yanked: bool,
+}
+
+/// A release that isn't yanked, the code has to be downloaded separately
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestReleaseResponse {
+    pub version: String,
+    pub downloads: i64,
+    /// rfc 3339 in utc
+    pub published: String,
+    /// Hex encoded sha256 of the code
+    pub sha256: String,
+    pub prerelease: bool,
+}
+
+/// A module and all of its installable releases, fetched in one request
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModuleManifestResponse {
+    pub author: String,
+    pub name: String,
+    pub description: String,
+    pub latest: Option<String>,
+    #[serde(default)]
+    pub deprecated: Option<Deprecation>,
+    /// Newest first, empty if nothing has been published yet
+    pub releases: Vec<ManifestReleaseResponse>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VersionsResponse {
+    pub author: String,
+    pub name: String,
+    /// Newest first
+    pub releases: Vec<ReleaseResponse>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeleteResponse {
+    pub author: String,
+    pub name: String,
+    pub deleted: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct YankRequest {
+    pub yanked: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct YankResponse {
+    pub author: String,
+    pub name: String,
+    pub version: String,
+    pub yanked: bool,
+    pub latest: Option<String>,
+}
+
+/// Deprecated modules can still be installed, but the successor should be
+/// used instead if there is one
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Deprecation {
+    pub message: Option<String>,
+    pub successor: Option<ModuleID>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeprecateRequest {
+    pub deprecated: bool,
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(default)]
+    pub successor: Option<ModuleID>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeprecateResponse {
+    pub author: String,
+    pub name: String,
+    pub deprecated: Option<Deprecation>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReportRequest {
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReportResponse {
+    pub author: String,
+    pub name: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DownloadResponse {
     pub author: String,
     pub name: String,
     pub version: String,
     pub code: String,
+    /// Hex encoded sha256 of the code, older registries don't send it
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub deprecated: Option<Deprecation>,
+    #[serde(default)]
+    pub dependencies: Vec<Dependency>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -29,6 +211,72 @@ pub struct ModuleInfoResponse {
     pub name: String,
     pub description: String,
     pub latest: Option<String>,
+    #[serde(default)]
+    pub deprecated: Option<Deprecation>,
+    #[serde(default)]
+    pub verified: bool,
+}
+
+/// The maximum number of modules that can be queried in a single batch request
+pub const MAX_BATCH_SIZE: usize = 100;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchInfoRequest {
+    pub modules: Vec<ModuleID>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchInfoResponse {
+    pub modules: Vec<ModuleInfoResponse>,
+    /// Requested modules that don't exist, or have been moved
+    pub unknown: Vec<ModuleID>,
+}
+
+/// The parsed header of a release, stored at publish time
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub description: String,
+    pub version: String,
+    pub license: String,
+    pub source: Option<Source>,
+    pub keyring_access: Vec<String>,
+    pub keep_state: bool,
+    pub capabilities: Option<Vec<Capability>>,
+    #[serde(default)]
+    pub produces: Option<Vec<String>>,
+}
+
+impl From<&Metadata> for Manifest {
+    fn from(metadata: &Metadata) -> Manifest {
+        Manifest {
+            description: metadata.description.clone(),
+            version: metadata.version.clone(),
+            license: metadata.license.as_str().to_string(),
+            source: metadata.source.clone(),
+            keyring_access: metadata.keyring_access.clone(),
+            keep_state: metadata.keep_state,
+            capabilities: metadata.capabilities.clone(),
+            produces: metadata.produces.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestResponse {
+    pub author: String,
+    pub name: String,
+    pub latest: String,
+    pub manifest: Manifest,
+    #[serde(default)]
+    pub readme: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReadmeResponse {
+    pub author: String,
+    pub name: String,
+    pub version: String,
+    pub readme: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -39,6 +287,11 @@ pub struct SearchResponse {
     pub latest: String,
     pub downloads: i64,
     pub featured: bool,
+    #[serde(default)]
+    pub deprecated: Option<Deprecation>,
+    /// The author of the module is verified
+    #[serde(default)]
+    pub verified: bool,
 }
 
 impl SearchResponse {
@@ -47,7 +300,56 @@ impl SearchResponse {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TopModuleResponse {
+    pub author: String,
+    pub name: String,
+    pub description: String,
+    pub latest: String,
+    pub downloads: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthorModuleResponse {
+    pub name: String,
+    pub description: String,
+    pub latest: String,
+    pub featured: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthorResponse {
+    pub author: String,
+    pub modules: Vec<AuthorModuleResponse>,
+    /// Number of modules across all pages
+    pub total: i64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LatestResponse {
+    /// unix timestamp, kept for older clients
     pub time: Option<u64>,
+    /// rfc 3339 in utc
+    #[serde(default)]
+    pub published: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecentReleaseResponse {
+    pub author: String,
+    pub name: String,
+    pub description: String,
+    pub version: String,
+    /// rfc 3339 in utc
+    pub published: String,
+    pub featured: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlatformStatsResponse {
+    pub modules: i64,
+    pub releases: i64,
+    pub downloads: i64,
+    /// Authors that published a release in the last 90 days
+    pub active_authors: i64,
 }
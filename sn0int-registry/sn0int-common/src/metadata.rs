@@ -1,5 +1,7 @@
 use crate::errors::*;
+use crate::id::ModuleID;
 
+use std::net::IpAddr;
 use std::str::FromStr;
 
 
@@ -10,6 +12,12 @@ pub enum EntryType {
     Source,
     KeyringAccess,
     License,
+    KeepState,
+    Capabilities,
+    Produces,
+    Option,
+    Depends,
+    Readme,
 }
 
 impl FromStr for EntryType {
@@ -22,6 +30,12 @@ impl FromStr for EntryType {
             "Source" => Ok(EntryType::Source),
             "Keyring-Access" => Ok(EntryType::KeyringAccess),
             "License" => Ok(EntryType::License),
+            "Keep-State" => Ok(EntryType::KeepState),
+            "Capabilities" => Ok(EntryType::Capabilities),
+            "Produces" => Ok(EntryType::Produces),
+            "Option" => Ok(EntryType::Option),
+            "Depends" => Ok(EntryType::Depends),
+            "Readme" => Ok(EntryType::Readme),
             x => bail!("Unknown EntryType: {:?}", x),
         }
     }
@@ -69,6 +83,179 @@ impl FromStr for Source {
     }
 }
 
+/// Features a module has to declare before it's allowed to use them
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all="lowercase")]
+pub enum Capability {
+    Network,
+    Keyring,
+    Filesystem,
+    Delete,
+}
+
+impl Capability {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Capability::Network => "network",
+            Capability::Keyring => "keyring",
+            Capability::Filesystem => "filesystem",
+            Capability::Delete => "delete",
+        }
+    }
+}
+
+impl FromStr for Capability {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Capability> {
+        match s {
+            "network" => Ok(Capability::Network),
+            "keyring" => Ok(Capability::Keyring),
+            "filesystem" => Ok(Capability::Filesystem),
+            "delete" => Ok(Capability::Delete),
+            x => bail!("Unknown Capability: {:?}", x),
+        }
+    }
+}
+
+/// The type of an option, values are validated before the module runs
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum OptionType {
+    String,
+    Int,
+    Ip,
+    Domain,
+    Enum(Vec<String>),
+}
+
+fn valid_option_name(name: &str) -> bool {
+    !name.is_empty() && name.chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+}
+
+fn valid_domain(domain: &str) -> bool {
+    domain.len() <= 253 && domain.split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    })
+}
+
+impl OptionType {
+    /// Check the value and return it in its normalized form
+    pub fn coerce(&self, value: &str) -> Result<String> {
+        match self {
+            OptionType::String => Ok(value.to_string()),
+            OptionType::Int => {
+                let x = value.trim().parse::<i64>()
+                    .map_err(|_| format_err!("Expected an integer, got {:?}", value))?;
+                Ok(x.to_string())
+            },
+            OptionType::Ip => {
+                let x = value.trim().parse::<IpAddr>()
+                    .map_err(|_| format_err!("Expected an ip address, got {:?}", value))?;
+                Ok(x.to_string())
+            },
+            OptionType::Domain => {
+                let x = value.trim().trim_end_matches('.').to_lowercase();
+                if !valid_domain(&x) {
+                    bail!("Expected a domain, got {:?}", value);
+                }
+                Ok(x)
+            },
+            OptionType::Enum(variants) => {
+                if !variants.iter().any(|x| x == value) {
+                    bail!("Expected one of {}, got {:?}", variants.join(", "), value);
+                }
+                Ok(value.to_string())
+            },
+        }
+    }
+}
+
+impl FromStr for OptionType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<OptionType> {
+        match s {
+            "string" => Ok(OptionType::String),
+            "int" => Ok(OptionType::Int),
+            "ip" => Ok(OptionType::Ip),
+            "domain" => Ok(OptionType::Domain),
+            x if x.starts_with("enum:") => {
+                let variants = x[5..].split(',')
+                    .map(String::from)
+                    .collect::<Vec<_>>();
+                if variants.iter().any(|x| x.is_empty()) {
+                    bail!("Enum has an empty variant: {:?}", x);
+                }
+                Ok(OptionType::Enum(variants))
+            },
+            x => bail!("Unknown option type: {:?}", x),
+        }
+    }
+}
+
+/// An option the module reads with `getopt`, declared as `-- Option: name type`
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct OptionDecl {
+    pub name: String,
+    pub kind: OptionType,
+}
+
+impl FromStr for OptionDecl {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<OptionDecl> {
+        let mut parts = s.split_whitespace();
+        let (name, kind) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(name), Some(kind), None) => (name, kind),
+            _ => bail!("Option must be declared as `name type`: {:?}", s),
+        };
+
+        if !valid_option_name(name) {
+            bail!("Invalid option name: {:?}", name);
+        }
+
+        Ok(OptionDecl {
+            name: name.to_string(),
+            kind: kind.parse()?,
+        })
+    }
+}
+
+/// Another module this module relies on, declared as `-- Depends: author/name [version]`
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Dependency {
+    pub author: String,
+    pub name: String,
+    /// A semver requirement, eg. `^0.3`. `*` if no version is given
+    pub version_req: String,
+}
+
+impl FromStr for Dependency {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Dependency> {
+        let s = s.trim();
+        let (module, version_req) = match s.find(char::is_whitespace) {
+            Some(idx) => (&s[..idx], s[idx..].trim()),
+            None => (s, "*"),
+        };
+
+        let module = module.parse::<ModuleID>()
+            .map_err(|_| format_err!("Dependency must be declared as `author/name [version]`: {:?}", s))?;
+
+        Ok(Dependency {
+            author: module.author,
+            name: module.name,
+            version_req: version_req.to_string(),
+        })
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum License {
     MIT,
@@ -79,6 +266,19 @@ pub enum License {
     WTFPL,
 }
 
+impl License {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            License::MIT => "MIT",
+            License::GPL3 => "GPL-3.0",
+            License::LGPL3 => "LGPL-3.0",
+            License::BSD2 => "BSD-2-Clause",
+            License::BSD3 => "BSD-3-Clause",
+            License::WTFPL => "WTFPL",
+        }
+    }
+}
+
 impl FromStr for License {
     type Err = Error;
 
@@ -102,6 +302,18 @@ pub struct Metadata {
     pub source: Option<Source>,
     pub keyring_access: Vec<String>,
     pub license: License,
+    pub keep_state: bool,
+    /// None if the module predates capability declarations
+    pub capabilities: Option<Vec<Capability>>,
+    /// The entity types the module adds to the database, eg. `subdomain`.
+    /// None if the module predates these declarations
+    pub produces: Option<Vec<String>>,
+    /// Options that aren't declared are passed to the module unchecked
+    pub options: Vec<OptionDecl>,
+    /// Modules that should be installed and run before this one
+    pub dependencies: Vec<Dependency>,
+    /// Longer documentation, every `Readme` line is one line of text
+    pub readme: Option<String>,
 }
 
 impl FromStr for Metadata {
@@ -120,6 +332,12 @@ impl FromStr for Metadata {
                 EntryType::Source => data.source = Some(v),
                 EntryType::KeyringAccess => data.keyring_access.push(v),
                 EntryType::License => data.license = Some(v),
+                EntryType::KeepState => data.keep_state = Some(v),
+                EntryType::Capabilities => data.capabilities.push(v),
+                EntryType::Produces => data.produces.push(v),
+                EntryType::Option => data.options.push(v),
+                EntryType::Depends => data.depends.push(v),
+                EntryType::Readme => data.readme.push(v),
             }
         }
 
@@ -134,6 +352,12 @@ pub struct NewMetadata<'a> {
     pub source: Option<&'a str>,
     pub keyring_access: Vec<&'a str>,
     pub license: Option<&'a str>,
+    pub keep_state: Option<&'a str>,
+    pub capabilities: Vec<&'a str>,
+    pub produces: Vec<&'a str>,
+    pub options: Vec<&'a str>,
+    pub depends: Vec<&'a str>,
+    pub readme: Vec<&'a str>,
 }
 
 impl<'a> NewMetadata<'a> {
@@ -149,6 +373,76 @@ impl<'a> NewMetadata<'a> {
             .collect();
         let license = self.license.ok_or_else(|| format_err!("License is required"))?;
         let license = license.parse()?;
+        let keep_state = match self.keep_state {
+            Some("true") => true,
+            Some("false") | None => false,
+            Some(x) => bail!("Keep-State must be true or false: {:?}", x),
+        };
+
+        let capabilities = if self.capabilities.is_empty() {
+            None
+        } else {
+            let mut capabilities = Vec::new();
+            for line in self.capabilities {
+                for x in line.split(',').map(str::trim) {
+                    // `Capabilities: none` declares that nothing is needed
+                    if x.is_empty() || x == "none" {
+                        continue;
+                    }
+                    let x = x.parse()?;
+                    if !capabilities.contains(&x) {
+                        capabilities.push(x);
+                    }
+                }
+            }
+            Some(capabilities)
+        };
+
+        let produces = if self.produces.is_empty() {
+            None
+        } else {
+            let mut produces = Vec::new();
+            for line in self.produces {
+                for x in line.split(',').map(str::trim) {
+                    // `Produces: none` declares that nothing is added
+                    if x.is_empty() || x == "none" {
+                        continue;
+                    }
+                    if !x.chars().all(|c| c.is_ascii_lowercase() || c == '-') {
+                        bail!("Invalid entity type: {:?}", x);
+                    }
+                    let x = x.to_string();
+                    if !produces.contains(&x) {
+                        produces.push(x);
+                    }
+                }
+            }
+            Some(produces)
+        };
+
+        let mut options: Vec<OptionDecl> = Vec::new();
+        for line in self.options {
+            let option = line.parse::<OptionDecl>()?;
+            if options.iter().any(|x| x.name == option.name) {
+                bail!("Option is declared twice: {:?}", option.name);
+            }
+            options.push(option);
+        }
+
+        let mut dependencies: Vec<Dependency> = Vec::new();
+        for line in self.depends {
+            let dependency = line.parse::<Dependency>()?;
+            if dependencies.iter().any(|x| x.author == dependency.author && x.name == dependency.name) {
+                bail!("Dependency is declared twice: {}/{}", dependency.author, dependency.name);
+            }
+            dependencies.push(dependency);
+        }
+
+        let readme = if self.readme.is_empty() {
+            None
+        } else {
+            Some(self.readme.join("\n"))
+        };
 
         Ok(Metadata {
             description: description.to_string(),
@@ -156,6 +450,12 @@ impl<'a> NewMetadata<'a> {
             source,
             keyring_access,
             license,
+            keep_state,
+            capabilities,
+            produces,
+            options,
+            dependencies,
+            readme,
         })
     }
 }
@@ -196,6 +496,12 @@ mod tests {
             license: License::WTFPL,
             source: Some(Source::Domains),
             keyring_access: Vec::new(),
+            keep_state: false,
+            capabilities: None,
+            produces: None,
+            options: Vec::new(),
+            dependencies: Vec::new(),
+            readme: None,
         });
     }
 
@@ -212,9 +518,106 @@ mod tests {
             license: License::WTFPL,
             source: None,
             keyring_access: Vec::new(),
+            keep_state: false,
+            capabilities: None,
+            produces: None,
+            options: Vec::new(),
+            dependencies: Vec::new(),
+            readme: None,
         });
     }
 
+    #[test]
+    fn verify_keep_state() {
+        let metadata = Metadata::from_str(r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- License: WTFPL
+-- Keep-State: true
+
+"#).expect("parse");
+        assert!(metadata.keep_state);
+
+        let metadata = Metadata::from_str(r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- License: WTFPL
+-- Keep-State: yes
+
+"#);
+        assert!(metadata.is_err());
+    }
+
+    #[test]
+    fn verify_readme() {
+        let metadata = Metadata::from_str(r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- License: WTFPL
+-- Readme: Resolves every subdomain.
+-- Readme: Set `resolver` to use a specific nameserver.
+
+"#).expect("parse");
+        assert_eq!(metadata.readme, Some("Resolves every subdomain.\nSet `resolver` to use a specific nameserver.".to_string()));
+    }
+
+    #[test]
+    fn verify_capabilities() {
+        let metadata = Metadata::from_str(r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- License: WTFPL
+-- Capabilities: network, keyring
+-- Capabilities: network
+
+"#).expect("parse");
+        assert_eq!(metadata.capabilities, Some(vec![Capability::Network, Capability::Keyring]));
+
+        let metadata = Metadata::from_str(r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- License: WTFPL
+-- Capabilities: none
+
+"#).expect("parse");
+        assert_eq!(metadata.capabilities, Some(vec![]));
+
+        let metadata = Metadata::from_str(r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- License: WTFPL
+-- Capabilities: network, root
+
+"#);
+        assert!(metadata.is_err());
+    }
+
+    #[test]
+    fn verify_produces() {
+        let metadata = Metadata::from_str(r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- License: WTFPL
+-- Produces: subdomain, ipaddr
+-- Produces: subdomain-ipaddr, subdomain
+
+"#).expect("parse");
+        assert_eq!(metadata.produces, Some(vec![
+            "subdomain".to_string(),
+            "ipaddr".to_string(),
+            "subdomain-ipaddr".to_string(),
+        ]));
+
+        let metadata = Metadata::from_str(r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- License: WTFPL
+-- Produces: none
+
+"#).expect("parse");
+        assert_eq!(metadata.produces, Some(vec![]));
+
+        let metadata = Metadata::from_str(r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- License: WTFPL
+-- Produces: Sub domain
+
+"#);
+        assert!(metadata.is_err());
+    }
+
     #[test]
     fn verify_require_license() {
         let metadata = Metadata::from_str(r#"-- Description: Hello world, this is my description
@@ -259,4 +662,95 @@ mod tests {
         let x = Source::from_str("accounts:github.com").unwrap();
         assert_eq!(x, Source::Accounts(Some("github.com".into())));
     }
+
+    #[test]
+    fn verify_options() {
+        let metadata = Metadata::from_str(r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- License: WTFPL
+-- Option: port int
+-- Option: mode enum:fast,slow
+
+"#).expect("parse");
+        assert_eq!(metadata.options, vec![
+            OptionDecl {
+                name: "port".to_string(),
+                kind: OptionType::Int,
+            },
+            OptionDecl {
+                name: "mode".to_string(),
+                kind: OptionType::Enum(vec!["fast".to_string(), "slow".to_string()]),
+            },
+        ]);
+    }
+
+    #[test]
+    fn verify_options_invalid() {
+        for option in &["port", "port integer", "port int extra", "Port int", "mode enum:", "mode enum:a,,b"] {
+            assert!(option.parse::<OptionDecl>().is_err(), "{:?}", option);
+        }
+
+        let metadata = Metadata::from_str(r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- License: WTFPL
+-- Option: port int
+-- Option: port string
+
+"#);
+        assert!(metadata.is_err());
+    }
+
+    #[test]
+    fn verify_depends() {
+        let metadata = Metadata::from_str(r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- License: WTFPL
+-- Depends: kpcyrd/ctlogs
+-- Depends: kpcyrd/dns-resolve >=0.2, <0.4
+
+"#).expect("parse");
+        assert_eq!(metadata.dependencies, vec![
+            Dependency {
+                author: "kpcyrd".to_string(),
+                name: "ctlogs".to_string(),
+                version_req: "*".to_string(),
+            },
+            Dependency {
+                author: "kpcyrd".to_string(),
+                name: "dns-resolve".to_string(),
+                version_req: ">=0.2, <0.4".to_string(),
+            },
+        ]);
+
+        for dependency in &["", "ctlogs", "kpcyrd/", "kpcyrd/ctlogs/x ^0.1"] {
+            assert!(dependency.parse::<Dependency>().is_err(), "{:?}", dependency);
+        }
+
+        let metadata = Metadata::from_str(r#"-- Description: Hello world, this is my description
+-- Version: 1.0.0
+-- License: WTFPL
+-- Depends: kpcyrd/ctlogs
+-- Depends: kpcyrd/ctlogs ^0.2
+
+"#);
+        assert!(metadata.is_err());
+    }
+
+    #[test]
+    fn verify_option_coerce() {
+        assert_eq!(OptionType::String.coerce(" anything ").unwrap(), " anything ");
+        assert_eq!(OptionType::Int.coerce("0443").unwrap(), "443");
+        assert!(OptionType::Int.coerce("443a").is_err());
+        assert_eq!(OptionType::Ip.coerce("2001:DB8::0:1").unwrap(), "2001:db8::1");
+        assert!(OptionType::Ip.coerce("192.0.2.256").is_err());
+        assert_eq!(OptionType::Domain.coerce("Example.COM.").unwrap(), "example.com");
+        assert!(OptionType::Domain.coerce("exa mple.com").is_err());
+        assert!(OptionType::Domain.coerce("-example.com").is_err());
+        assert!(OptionType::Domain.coerce("example..com").is_err());
+
+        let mode = "enum:fast,slow".parse::<OptionType>().unwrap();
+        assert_eq!(mode.coerce("slow").unwrap(), "slow");
+        let err = mode.coerce("medium").unwrap_err();
+        assert_eq!(err.to_string(), "Expected one of fast, slow, got \"medium\"");
+    }
 }
@@ -4,7 +4,7 @@ use oauth2::basic::BasicClient;
 use oauth2::prelude::*;
 use oauth2::{AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, RedirectUrl, TokenUrl};
 use crate::github::GithubAuthenticator;
-use crate::models::AuthToken;
+use crate::models::{default_scopes, AuthToken};
 use url::Url;
 use std::env;
 
@@ -70,11 +70,22 @@ impl Authenticator {
         let client = GithubAuthenticator::from_env()?;
         let user = client.get_username(&access_token)?;
 
-        AuthToken::create(&AuthToken {
-            id: state,
-            author: user,
-            access_token,
-        }, connection)?;
+        match AuthToken::read_opt(&state, connection)? {
+            // logging in again with an existing session refreshes the github token
+            Some(session) if session.author == user => {
+                AuthToken::update_access_token(&state, &access_token, connection)?;
+            },
+            Some(_) => bail!("Session belongs to a different user"),
+            None => AuthToken::create(&AuthToken {
+                id: state,
+                author: user,
+                access_token,
+                previous_token_hash: None,
+                previous_token_expires: None,
+                scopes: default_scopes(),
+                expires_at: None,
+            }, connection)?,
+        }
 
         Ok(())
     }
@@ -5,17 +5,43 @@ use rocket::http::Status;
 use rocket::{Request, Outcome};
 use rocket::request::{self, FromRequest};
 use crate::github::GithubAuthenticator;
+use std::env;
 
 
 pub struct AuthHeader(String);
 
+/// Returned by `AuthHeader::verify_scope` if the token may not do this
+#[derive(Debug, Fail)]
+#[fail(display="Auth token doesn't have the {:?} scope", _0)]
+pub struct MissingScope(pub String);
+
 impl AuthHeader {
-    pub fn verify(&self, connection: &Connection) -> Result<String> {
-        let session = AuthToken::read(&self.0, &connection)?;
+    /// The token of the session, this may be the previous id of a token
+    /// that has been rotated recently
+    pub fn session(&self, connection: &Connection) -> Result<AuthToken> {
+        AuthToken::read_valid(&self.0, &connection)?
+            .ok_or_else(|| format_err!("Auth token doesn't exist or has expired"))
+    }
+
+    fn username(session: &AuthToken) -> Result<String> {
         let client = GithubAuthenticator::from_env()?;
         client.get_username(&session.access_token)
             .map_err(Error::from)
     }
+
+    pub fn verify(&self, connection: &Connection) -> Result<String> {
+        let session = self.session(connection)?;
+        AuthHeader::username(&session)
+    }
+
+    /// Verify the token and make sure it has the scope
+    pub fn verify_scope(&self, scope: &str, connection: &Connection) -> Result<String> {
+        let session = self.session(connection)?;
+        if !session.has_scope(scope) {
+            return Err(MissingScope(scope.to_string()).into());
+        }
+        AuthHeader::username(&session)
+    }
 }
 
 impl<'a, 'r> FromRequest<'a, 'r> for AuthHeader {
@@ -29,3 +55,43 @@ impl<'a, 'r> FromRequest<'a, 'r> for AuthHeader {
         }
     }
 }
+
+/// Github users that may access the admin endpoints
+#[derive(Debug, Default)]
+pub struct Admins(Vec<String>);
+
+impl Admins {
+    /// Read ADMIN_USERS, a comma separated list of github usernames
+    pub fn from_env() -> Admins {
+        let users = env::var("ADMIN_USERS")
+            .map(|users| Admins::parse(&users))
+            .unwrap_or_default();
+        Admins(users)
+    }
+
+    fn parse(users: &str) -> Vec<String> {
+        users.split(',')
+            .map(|user| user.trim().to_string())
+            .filter(|user| !user.is_empty())
+            .collect()
+    }
+
+    pub fn contains(&self, user: &str) -> bool {
+        self.0.iter().any(|admin| admin == user)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_admins() {
+        let admins = Admins(Admins::parse("alice, bob,,"));
+        assert!(admins.contains("alice"));
+        assert!(admins.contains("bob"));
+        assert!(!admins.contains(""));
+        assert!(!admins.contains("mallory"));
+        assert!(!Admins::default().contains("alice"));
+    }
+}
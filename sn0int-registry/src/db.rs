@@ -1,4 +1,5 @@
 use crate::errors::*;
+use crate::models;
 use std::io;
 use std::thread;
 use std::time::Duration;
@@ -12,7 +13,7 @@ use rocket::{Request, State, Outcome};
 
 
 type ManagedPgConn = r2d2::ConnectionManager<PgConnection>;
-type Pool = r2d2::Pool<ManagedPgConn>;
+pub type Pool = r2d2::Pool<ManagedPgConn>;
 
 pub fn init(database_url: &str) -> Pool {
     let manager = r2d2::ConnectionManager::<PgConnection>::new(database_url);
@@ -63,5 +64,27 @@ pub fn wait_for_db(url: &str, attempts: u8) -> Result<PgConnection> {
 pub fn setup_db(url: &str, attempts: u8) -> Result<()> {
     let connection = wait_for_db(url, attempts)?;
     embedded_migrations::run_with_output(&connection, &mut io::stdout())?;
+
+    let total = models::Release::backfill_manifests(100, &connection, |n| {
+        eprintln!("Parsed {} manifests", n);
+    })?;
+    if total > 0 {
+        eprintln!("Done, backfilled manifests of {} releases", total);
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Run a test in a transaction that is rolled back afterwards, the
+    /// database is taken from `DATABASE_URL`
+    pub fn with_test_db(f: impl FnOnce(&PgConnection) -> Result<()>) {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set");
+        setup_db(&url, 1).unwrap();
+        let connection = PgConnection::establish(&url).unwrap();
+        connection.test_transaction::<_, Error, _>(|| f(&connection));
+    }
+}
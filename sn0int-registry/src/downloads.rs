@@ -0,0 +1,147 @@
+use crate::errors::*;
+use crate::db::Pool;
+use crate::models::Release;
+use diesel::pg::PgConnection;
+use std::collections::HashMap;
+use std::env;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+const DEFAULT_THRESHOLD: i64 = 1000;
+
+#[derive(Debug, Default)]
+struct Pending {
+    counts: HashMap<i32, i64>,
+    total: i64,
+}
+
+/// Collects downloads in memory and writes them to the database in batches.
+/// Downloads that weren't flushed yet are lost if the process crashes.
+#[derive(Debug, Clone)]
+pub struct DownloadCounter {
+    pending: Arc<Mutex<Pending>>,
+    interval: Duration,
+    threshold: i64,
+}
+
+impl DownloadCounter {
+    pub fn new(interval: Duration, threshold: i64) -> DownloadCounter {
+        DownloadCounter {
+            pending: Arc::new(Mutex::new(Pending::default())),
+            interval,
+            threshold,
+        }
+    }
+
+    /// Read DOWNLOAD_FLUSH_INTERVAL (in seconds) and DOWNLOAD_FLUSH_THRESHOLD
+    pub fn from_env() -> Result<DownloadCounter> {
+        let interval = match env::var("DOWNLOAD_FLUSH_INTERVAL") {
+            Ok(x) => Duration::from_secs(x.parse::<u64>()
+                .context("DOWNLOAD_FLUSH_INTERVAL is invalid")?),
+            Err(_) => DEFAULT_INTERVAL,
+        };
+        let threshold = match env::var("DOWNLOAD_FLUSH_THRESHOLD") {
+            Ok(x) => x.parse::<i64>()
+                .context("DOWNLOAD_FLUSH_THRESHOLD is invalid")?,
+            Err(_) => DEFAULT_THRESHOLD,
+        };
+
+        if interval.as_secs() == 0 || threshold <= 0 {
+            bail!("Download flush interval and threshold must be greater than zero");
+        }
+
+        Ok(DownloadCounter::new(interval, threshold))
+    }
+
+    /// Add a download to the buffer, returns true if the buffer should be flushed
+    fn queue(&self, release_id: i32) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        *pending.counts.entry(release_id).or_insert(0) += 1;
+        pending.total += 1;
+        pending.total >= self.threshold
+    }
+
+    fn take(&self) -> HashMap<i32, i64> {
+        let mut pending = self.pending.lock().unwrap();
+        pending.total = 0;
+        mem::replace(&mut pending.counts, HashMap::new())
+    }
+
+    /// Put counts back that couldn't be written, they are retried on the next flush
+    fn requeue(&self, counts: HashMap<i32, i64>) {
+        let mut pending = self.pending.lock().unwrap();
+        for (id, count) in counts {
+            *pending.counts.entry(id).or_insert(0) += count;
+            pending.total += count;
+        }
+    }
+
+    /// Count a download, the buffer is flushed right away once it reaches the threshold
+    pub fn record(&self, release_id: i32, connection: &PgConnection) {
+        if self.queue(release_id) {
+            if let Err(err) = self.flush(connection) {
+                warn!("Failed to flush download counts: {}", err);
+            }
+        }
+    }
+
+    /// Write all buffered downloads, on failure they stay in the buffer
+    pub fn flush(&self, connection: &PgConnection) -> Result<()> {
+        let counts = self.take();
+        if counts.is_empty() {
+            return Ok(());
+        }
+
+        if let Err(err) = Release::flush_download_counts(&counts, connection) {
+            self.requeue(counts);
+            return Err(err);
+        }
+
+        debug!("Flushed downloads of {} releases", counts.len());
+        Ok(())
+    }
+
+    /// Flush the buffer in the background on every interval
+    pub fn spawn_flusher(&self, pool: Pool) {
+        let counter = self.clone();
+        thread::spawn(move || loop {
+            thread::sleep(counter.interval);
+
+            let result = pool.get()
+                .map_err(Error::from)
+                .and_then(|connection| counter.flush(&connection));
+            if let Err(err) = result {
+                warn!("Failed to flush download counts: {}", err);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_threshold() {
+        let counter = DownloadCounter::new(DEFAULT_INTERVAL, 3);
+        assert!(!counter.queue(1));
+        assert!(!counter.queue(2));
+        assert!(counter.queue(1));
+        assert_eq!(counter.take(), hashmap!{1 => 2, 2 => 1});
+        assert!(!counter.queue(1));
+    }
+
+    #[test]
+    fn test_requeue() {
+        let counter = DownloadCounter::new(DEFAULT_INTERVAL, 3);
+        counter.queue(1);
+        let counts = counter.take();
+        counter.queue(1);
+        counter.requeue(counts);
+        assert_eq!(counter.take(), hashmap!{1 => 2});
+    }
+}
@@ -17,8 +17,10 @@ use rocket::fairing::AdHoc;
 use rocket::http::Header;
 use rocket_contrib::json::{Json, JsonValue};
 use rocket_contrib::templates::Template;
+use diesel::Connection;
 use dotenv::dotenv;
 
+use hlua_badtouch as hlua;
 use std::env;
 use crate::errors::*;
 
@@ -26,12 +28,17 @@ pub mod assets;
 pub mod auth;
 pub mod auth2;
 pub mod db;
+pub mod downloads;
 pub mod errors;
 pub mod github;
 pub mod models;
+pub mod ratelimit;
 pub mod routes;
 #[allow(unused_imports)]
 pub mod schema;
+pub mod stats;
+pub mod syntax;
+pub mod webhook;
 
 
 #[catch(400)]
@@ -48,6 +55,11 @@ fn not_found() -> Json<JsonValue> {
     }))
 }
 
+#[catch(429)]
+fn too_many_requests() -> ratelimit::TooManyRequests {
+    ratelimit::TooManyRequests
+}
+
 #[catch(500)]
 fn internal_error() -> Json<JsonValue> {
     Json(json!({
@@ -64,8 +76,30 @@ fn run() -> Result<()> {
     db::setup_db(&database_url, 60)
         .context("Failed to setup db")?;
 
+    match env::args().nth(1).as_deref() {
+        Some("reindex") => return reindex(&database_url),
+        Some("purge") => return purge(&database_url, env::args().nth(2).as_deref()),
+        _ => (),
+    }
+
+    let limiter = ratelimit::RateLimiter::from_env()?;
+    let limits = models::PublishLimits::from_env()?;
+    let moderation = models::ModerationConfig::from_env()?;
+
+    let pool = db::init(&database_url);
+
+    let downloads = downloads::DownloadCounter::from_env()?;
+    downloads.spawn_flusher(pool.clone());
+
     rocket::ignite()
-        .manage(db::init(&database_url))
+        .manage(pool.clone())
+        .manage(limiter)
+        .manage(limits)
+        .manage(moderation)
+        .manage(webhook::Webhook::from_env(pool))
+        .manage(auth2::Admins::from_env())
+        .manage(stats::StatsCache::default())
+        .manage(downloads)
         .attach(Template::fairing())
         .attach(AdHoc::on_response("Security Headers", |_, resp| {
             resp.set_header(Header::new("Strict-Transport-Security", "max-age=63072000; includeSubDomains; preload"));
@@ -80,10 +114,40 @@ fn run() -> Result<()> {
             routes::api::quickstart,
             routes::api::search,
             routes::api::info,
+            routes::api::info_batch,
+            routes::api::versions,
+            routes::api::manifest,
+            routes::api::module_manifest,
+            routes::api::readme,
             routes::api::download,
             routes::api::publish,
+            routes::api::upload_start,
+            routes::api::upload_info,
+            routes::api::upload_part,
+            routes::api::upload_finalize,
+            routes::api::transfer,
+            routes::api::yank,
+            routes::api::deprecate,
+            routes::api::delete_module,
+            routes::api::restore_module,
+            routes::api::report,
             routes::api::whoami,
+            routes::api::rotate,
+            routes::api::token,
+            routes::api::logout,
             routes::api::latest,
+            routes::api::leaderboard,
+            routes::api::trending,
+            routes::api::author,
+            routes::api::recent,
+            routes::api::stats,
+            routes::admin::webhooks,
+            routes::admin::replay_webhook,
+            routes::admin::reports,
+            routes::admin::verify_author,
+            routes::admin::unverify_author,
+            routes::admin::hide_module,
+            routes::admin::unhide_module,
         ])
         .mount("/auth", routes![
             routes::auth::get,
@@ -92,6 +156,7 @@ fn run() -> Result<()> {
         ])
         .mount("/", routes![
             routes::assets::index,
+            routes::assets::module,
             routes::assets::favicon,
             routes::assets::style,
             routes::health::health,
@@ -99,6 +164,7 @@ fn run() -> Result<()> {
         .register(catchers![
             bad_request,
             not_found,
+            too_many_requests,
             internal_error,
         ])
         .launch();
@@ -106,6 +172,40 @@ fn run() -> Result<()> {
     Ok(())
 }
 
+fn reindex(database_url: &str) -> Result<()> {
+    let connection = diesel::pg::PgConnection::establish(database_url)
+        .context("Failed to connect to db")?;
+
+    let total = models::Module::refresh_search_vectors(models::REINDEX_BATCH_SIZE, &connection, |n| {
+        eprintln!("Refreshed {} modules", n);
+    })?;
+    eprintln!("Done, refreshed search vectors of {} modules", total);
+
+    Ok(())
+}
+
+/// Modules are purged this many days after they have been soft deleted
+const DEFAULT_PURGE_GRACE_DAYS: u64 = 30;
+
+fn purge(database_url: &str, grace_days: Option<&str>) -> Result<()> {
+    let grace_days = match grace_days {
+        Some(x) => x.parse::<u64>()
+            .context("Grace period is invalid")?,
+        None => DEFAULT_PURGE_GRACE_DAYS,
+    };
+
+    let connection = diesel::pg::PgConnection::establish(database_url)
+        .context("Failed to connect to db")?;
+
+    let total = models::Module::purge_expired(grace_days, &connection)?;
+    eprintln!("Done, purged {} modules that were deleted more than {} days ago", total, grace_days);
+
+    let total = models::PublishEvent::cleanup(std::time::SystemTime::now(), &connection)?;
+    eprintln!("Removed {} expired publish events", total);
+
+    Ok(())
+}
+
 fn main() {
     if let Err(err) = run() {
         eprintln!("Error: {}", err);
@@ -1,18 +1,57 @@
 use crate::errors::*;
+use chrono::{DateTime, SecondsFormat, Utc};
 use diesel::prelude::*;
-use diesel::pg::PgConnection;
-use diesel::sql_types::BigInt;
+use diesel::pg::{Pg, PgConnection};
+use diesel::query_builder::BoxedSelectStatement;
+use diesel::query_source::joins::{Join, JoinOn, JoinTo, LeftOuter};
+use diesel::sql_types::{Array, BigInt, Bool, Integer, Nullable, Text, Timestamp, Varchar};
 use diesel_full_text_search::{plainto_tsquery, TsQueryExtensions};
 use crate::schema::*;
-use std::time::SystemTime;
+use hex;
+use serde::Serializer;
+use serde_json;
+use sha2::{Digest, Sha256};
+use sn0int_common::api::{Manifest, MAX_UPLOAD_PARTS, UPLOAD_PART_SIZE};
+use sn0int_common::id::ModuleID;
+use semver::{Version, VersionReq};
+use sn0int_common::metadata::{Dependency, Metadata};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::time::{Duration, SystemTime};
 
 
+/// How long the previous id of a rotated session stays valid
+pub const TOKEN_ROTATION_GRACE: Duration = Duration::from_secs(3600);
+
+/// Publish, upload and transfer modules
+pub const SCOPE_PUBLISH: &str = "publish";
+/// Yank and unyank releases
+pub const SCOPE_YANK: &str = "yank";
+/// Everything else that needs an account, like reporting modules
+pub const SCOPE_READ: &str = "read";
+/// Tokens without explicit scopes have full access
+pub const ALL_SCOPES: &[&str] = &[SCOPE_PUBLISH, SCOPE_YANK, SCOPE_READ];
+
+pub fn default_scopes() -> Vec<String> {
+    ALL_SCOPES.iter()
+        .map(|scope| scope.to_string())
+        .collect()
+}
+
 #[derive(AsChangeset, Serialize, Deserialize, Queryable, Insertable)]
 #[table_name="auth_tokens"]
 pub struct AuthToken {
     pub id: String,
     pub author: String,
     pub access_token: String,
+    pub previous_token_hash: Option<String>,
+    pub previous_token_expires: Option<SystemTime>,
+    pub scopes: Vec<String>,
+    /// Tokens without expiry are valid until they are revoked
+    pub expires_at: Option<SystemTime>,
 }
 
 impl AuthToken {
@@ -36,13 +75,264 @@ impl AuthToken {
             .map_err(Error::from)
     }
 
+    /// Read a token that can still be used, expired tokens are treated
+    /// like tokens that don't exist. The previous id of a rotated token is
+    /// accepted until its grace period is over.
+    pub fn read_valid(id: &str, connection: &PgConnection) -> Result<Option<AuthToken>> {
+        let now = SystemTime::now();
+        let token = match AuthToken::read_opt(id, connection)? {
+            Some(token) => Some(token),
+            None => auth_tokens::table
+                .filter(auth_tokens::previous_token_hash.eq(checksum(id)))
+                .filter(auth_tokens::previous_token_expires.gt(now))
+                .first::<AuthToken>(connection)
+                .optional()?,
+        };
+        Ok(token.filter(|token| !token.is_expired(now)))
+    }
+
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        match self.expires_at {
+            Some(expires_at) => expires_at <= now,
+            None => false,
+        }
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|x| x == scope)
+    }
+
+    /// Store a new github access token after the user logged in again
+    pub fn update_access_token(id: &str, access_token: &str, connection: &PgConnection) -> Result<()> {
+        diesel::update(auth_tokens::table.find(id))
+            .set(auth_tokens::access_token.eq(access_token))
+            .execute(connection)?;
+        Ok(())
+    }
+
+    /// Replace the id that clients send in the Auth header with a new random
+    /// one, the author and scopes stay the same. With a grace period the
+    /// previous id keeps working until it expires, only its hash is kept.
+    pub fn rotate(id: &str, grace: Option<Duration>, connection: &PgConnection) -> Result<AuthToken> {
+        let new_id = random_session_id()?;
+        let (previous_token_hash, previous_token_expires) = match grace {
+            Some(grace) => (Some(checksum(id)), Some(SystemTime::now() + grace)),
+            None => (None, None),
+        };
+
+        diesel::update(auth_tokens::table.find(id))
+            .set((
+                auth_tokens::id.eq(new_id),
+                auth_tokens::previous_token_hash.eq(previous_token_hash),
+                auth_tokens::previous_token_expires.eq(previous_token_expires),
+            ))
+            .get_result::<AuthToken>(connection)
+            .map_err(Error::from)
+    }
+
+    /// Create an additional token for the same account, eg. for a CI job.
+    /// It can't have scopes this token doesn't have and expires no later
+    /// than this token. Without scopes the scopes of this token are used.
+    pub fn mint(&self, scopes: &[String], expires_in: Option<Duration>, connection: &PgConnection) -> Result<AuthToken> {
+        for scope in scopes {
+            if !ALL_SCOPES.contains(&scope.as_str()) {
+                return Err(ScopeRejected::Unknown(scope.to_string()).into());
+            }
+            if !self.has_scope(scope) {
+                return Err(ScopeRejected::NotGranted(scope.to_string()).into());
+            }
+        }
+        let scopes = if scopes.is_empty() {
+            self.scopes.clone()
+        } else {
+            scopes.to_vec()
+        };
+
+        let expires_at = match (expires_in.map(|x| SystemTime::now() + x), self.expires_at) {
+            (Some(requested), Some(limit)) => Some(requested.min(limit)),
+            (requested, limit) => requested.or(limit),
+        };
+
+        let token = AuthToken {
+            id: random_session_id()?,
+            author: self.author.clone(),
+            access_token: self.access_token.clone(),
+            previous_token_hash: None,
+            previous_token_expires: None,
+            scopes,
+            expires_at,
+        };
+        AuthToken::create(&token, connection)?;
+        Ok(token)
+    }
+
     pub fn delete(id: &str, connection: &PgConnection) -> Result<()> {
         diesel::delete(auth_tokens::table.find(id))
             .execute(connection)?;
         Ok(())
     }
+
+    /// Delete the token so it can't be used anymore and log whose token it was
+    pub fn revoke(id: &str, connection: &PgConnection) -> Result<()> {
+        match AuthToken::read_opt(id, connection)? {
+            Some(token) => info!("Revoking auth token of {:?}", token.author),
+            None => info!("Revoking auth token that doesn't exist"),
+        }
+        AuthToken::delete(id, connection)
+    }
+}
+
+/// Modules are reindexed in transactions of this size, so a full reindex
+/// doesn't lock the whole table
+pub const REINDEX_BATCH_SIZE: i64 = 100;
+
+/// Keep this in sync with the modules_vector_update trigger
+const REFRESH_SEARCH_VECTOR: &str = "UPDATE modules SET search_vector = to_tsvector('pg_catalog.english', name || ' ' || author || ' ' || description) WHERE id = ANY($1)";
+
+/// Rank each authors modules by downloads and keep the top one, modules that
+/// have never been published or that are hidden are skipped. Deprecated
+/// modules are only picked if the author has nothing else.
+const TOP_PER_AUTHOR: &str = "SELECT id, author, name, description, latest, featured, deprecated, deprecated_msg, successor_id, deleted_at, downloads FROM (
+    SELECT modules.id, modules.author, modules.name, modules.description, modules.latest, modules.featured,
+    modules.deprecated, modules.deprecated_msg, modules.successor_id, modules.deleted_at,
+        COALESCE(SUM(releases.downloads), 0)::BIGINT AS downloads,
+        ROW_NUMBER() OVER (
+            PARTITION BY modules.author
+            ORDER BY modules.deprecated ASC, COALESCE(SUM(releases.downloads), 0) DESC, modules.name ASC
+        ) AS rank
+    FROM modules
+    LEFT JOIN releases ON releases.module_id = modules.id
+    WHERE modules.latest IS NOT NULL
+        AND NOT modules.hidden
+        AND modules.deleted_at IS NULL
+    GROUP BY modules.id
+) AS ranked
+WHERE rank = 1
+ORDER BY downloads DESC, author ASC
+LIMIT $1";
+
+/// Published modules ranked by the downloads of their releases in the last
+/// $1 days, today included. Days are counted in utc like the download events.
+const TRENDING_MODULES: &str = "SELECT modules.id, modules.author, modules.name, modules.description, modules.latest, modules.featured,
+    modules.deprecated, modules.deprecated_msg, modules.successor_id, modules.deleted_at,
+    SUM(download_events.count)::BIGINT AS downloads
+FROM modules
+INNER JOIN releases ON releases.module_id = modules.id
+INNER JOIN download_events ON download_events.release_id = releases.id
+WHERE download_events.day > (NOW() AT TIME ZONE 'UTC')::DATE - $1::INT
+    AND modules.latest IS NOT NULL
+    AND NOT modules.hidden
+    AND modules.deleted_at IS NULL
+GROUP BY modules.id
+HAVING SUM(download_events.count) > 0
+ORDER BY downloads DESC, modules.name ASC
+LIMIT $2";
+
+/// Published modules that match any word of the description of the given
+/// module, ranked by how well they match and then by downloads
+const SIMILAR_MODULES: &str = "WITH source AS (
+    SELECT modules.id, to_tsquery('simple', string_agg(quote_literal(lexeme), ' | ')) AS query
+    FROM modules, unnest(tsvector_to_array(to_tsvector('pg_catalog.english', modules.description))) AS lexeme
+    WHERE modules.id = $1
+    GROUP BY modules.id
+)
+SELECT modules.id, modules.author, modules.name, modules.description, modules.latest, modules.featured,
+    modules.deprecated, modules.deprecated_msg, modules.successor_id, modules.deleted_at,
+    COALESCE(SUM(releases.downloads), 0)::BIGINT AS downloads
+FROM modules
+CROSS JOIN source
+LEFT JOIN releases ON releases.module_id = modules.id
+WHERE modules.id != source.id
+    AND modules.latest IS NOT NULL
+    AND NOT modules.hidden
+    AND modules.deleted_at IS NULL
+    AND NOT modules.deprecated
+    AND modules.search_vector @@ source.query
+GROUP BY modules.id, source.query
+ORDER BY ts_rank(modules.search_vector, source.query) DESC, downloads DESC, modules.name ASC
+LIMIT $2";
+
+/// Days are counted in utc
+const BUMP_DOWNLOAD_EVENTS: &str = "INSERT INTO download_events (release_id, day, count)
+VALUES ($1, (NOW() AT TIME ZONE 'UTC')::DATE, $2)
+ON CONFLICT (release_id, day) DO UPDATE SET count = download_events.count + $2";
+
+/// Every day in the range is returned, days without downloads are zero.
+/// The downloads of all releases of the module are summed up.
+const DOWNLOAD_SERIES: &str = "SELECT days.day, COALESCE(downloads.count, 0)::BIGINT AS count
+FROM generate_series($2::DATE::TIMESTAMP, $3::DATE::TIMESTAMP, INTERVAL '1 day') AS days(day)
+LEFT JOIN (
+    SELECT download_events.day, SUM(download_events.count) AS count
+    FROM download_events
+    INNER JOIN releases ON releases.id = download_events.release_id
+    WHERE releases.module_id = $1
+    GROUP BY download_events.day
+) AS downloads ON downloads.day = days.day::DATE
+ORDER BY days.day ASC";
+
+/// Authors count as active if they published a release in the last 90 days
+const PLATFORM_STATS: &str = "SELECT
+    (SELECT COUNT(*) FROM modules WHERE deleted_at IS NULL) AS modules,
+    (SELECT COUNT(*) FROM releases
+        INNER JOIN modules ON modules.id = releases.module_id
+        WHERE modules.deleted_at IS NULL) AS releases,
+    (SELECT COALESCE(SUM(releases.downloads), 0)::BIGINT FROM releases
+        INNER JOIN modules ON modules.id = releases.module_id
+        WHERE modules.deleted_at IS NULL) AS downloads,
+    (SELECT COUNT(DISTINCT modules.author) FROM modules
+        INNER JOIN releases ON releases.module_id = modules.id
+        WHERE releases.published > (NOW() AT TIME ZONE 'UTC') - INTERVAL '90 days'
+        AND modules.deleted_at IS NULL) AS active_authors";
+
+#[derive(QueryableByName, Serialize, Clone, PartialEq, Debug)]
+pub struct PlatformStats {
+    #[sql_type="BigInt"]
+    pub modules: i64,
+    #[sql_type="BigInt"]
+    pub releases: i64,
+    #[sql_type="BigInt"]
+    pub downloads: i64,
+    #[sql_type="BigInt"]
+    pub active_authors: i64,
+}
+
+#[derive(QueryableByName)]
+struct DailyDownloads {
+    #[sql_type="Timestamp"]
+    day: SystemTime,
+    #[sql_type="BigInt"]
+    count: i64,
 }
 
+#[derive(QueryableByName)]
+struct ModuleWithDownloads {
+    #[sql_type="Integer"]
+    id: i32,
+    #[sql_type="Varchar"]
+    author: String,
+    #[sql_type="Varchar"]
+    name: String,
+    #[sql_type="Text"]
+    description: String,
+    #[sql_type="Nullable<Varchar>"]
+    latest: Option<String>,
+    #[sql_type="Bool"]
+    featured: bool,
+    #[sql_type="Bool"]
+    deprecated: bool,
+    #[sql_type="Nullable<Text>"]
+    deprecated_msg: Option<String>,
+    #[sql_type="Nullable<Integer>"]
+    successor_id: Option<i32>,
+    #[sql_type="Nullable<Timestamp>"]
+    deleted_at: Option<SystemTime>,
+    #[sql_type="BigInt"]
+    downloads: i64,
+}
+
+/// A module and the sum of its downloads
+type ModuleRow = (i32, String, String, String, Option<String>, bool, bool, Option<String>, Option<i32>, Option<SystemTime>, i64);
+
 /// Make sure we never select search_vector
 type AllModuleColumns = (
     modules::id,
@@ -51,8 +341,17 @@ type AllModuleColumns = (
     modules::description,
     modules::latest,
     modules::featured,
+    modules::deprecated,
+    modules::deprecated_msg,
+    modules::successor_id,
+    modules::deleted_at,
 );
 
+type ModuleWithStatsQuery<'a> = BoxedSelectStatement<'a,
+    (<AllModuleColumns as Expression>::SqlType, BigInt, BigInt),
+    JoinOn<Join<modules::table, releases::table, LeftOuter>, <modules::table as JoinTo<releases::table>>::OnClause>,
+    Pg>;
+
 pub const ALL_MODULE_COLUMNS: AllModuleColumns = (
     modules::id,
     modules::author,
@@ -60,6 +359,10 @@ pub const ALL_MODULE_COLUMNS: AllModuleColumns = (
     modules::description,
     modules::latest,
     modules::featured,
+    modules::deprecated,
+    modules::deprecated_msg,
+    modules::successor_id,
+    modules::deleted_at,
 );
 
 #[derive(AsChangeset, Identifiable, Queryable, Serialize, PartialEq, Debug)]
@@ -71,6 +374,13 @@ pub struct Module {
     pub description: String,
     pub latest: Option<String>,
     pub featured: bool,
+    pub deprecated: bool,
+    pub deprecated_msg: Option<String>,
+    /// The module that should be used instead of this deprecated module
+    pub successor_id: Option<i32>,
+    /// Soft deleted modules are purged after a grace period
+    #[serde(serialize_with="serialize_rfc3339_opt")]
+    pub deleted_at: Option<SystemTime>,
 }
 
 impl Module {
@@ -82,14 +392,18 @@ impl Module {
             .map_err(Error::from)
     }
 
+    /// Soft deleted modules are treated like modules that don't exist
     pub fn find(author: &str, name: &str, connection: &PgConnection) -> Result<Module> {
         modules::table.filter(modules::columns::author.eq(author))
                         .filter(modules::columns::name.eq(name))
+                        .filter(modules::deleted_at.is_null())
                         .select(ALL_MODULE_COLUMNS)
                         .first::<Self>(connection)
                         .map_err(Error::from)
     }
 
+    /// Soft deleted modules are returned too, check `deleted_at` to tell them
+    /// apart from modules that never existed
     pub fn find_opt(author: &str, name: &str, connection: &PgConnection) -> Result<Option<Module>> {
         modules::table.filter(modules::columns::author.eq(author))
                         .filter(modules::columns::name.eq(name))
@@ -99,19 +413,54 @@ impl Module {
                         .map_err(Error::from)
     }
 
+    /// Load multiple modules with a single query, missing and soft deleted
+    /// modules are skipped
+    pub fn find_many(ids: &[ModuleID], connection: &PgConnection) -> Result<Vec<Module>> {
+        let authors = ids.iter()
+            .map(|id| id.author.as_str())
+            .collect::<Vec<_>>();
+        let names = ids.iter()
+            .map(|id| id.name.as_str())
+            .collect::<Vec<_>>();
+
+        // this may also match other combinations of the requested authors and names
+        let candidates = modules::table.filter(modules::columns::author.eq_any(authors))
+                        .filter(modules::columns::name.eq_any(names))
+                        .filter(modules::deleted_at.is_null())
+                        .select(ALL_MODULE_COLUMNS)
+                        .load::<Self>(connection)?;
+
+        Ok(select_requested(candidates, ids))
+    }
+
+    /// New modules fail with `NameReserved` if the name is reserved and the
+    /// author isn't one of the owners, soft deleted modules fail with
+    /// `ModuleDeleted` until they are restored
     pub fn update_or_create(author: &str, name: &str, description: &str, connection: &PgConnection) -> Result<Module> {
         match Self::find_opt(author, name, connection)? {
+            Some(ref module) if module.deleted_at.is_some() => Err(ModuleDeleted {
+                author: author.to_string(),
+                name: name.to_string(),
+            }.into()),
             Some(module) => diesel::update(modules::table.filter(modules::columns::id.eq(module.id)))
                             .set(modules::columns::description.eq(description))
                             .returning(ALL_MODULE_COLUMNS)
                             .get_result(connection)
                             .map_err(Error::from),
-            None => Self::create(&NewModule {
-                author,
-                name,
-                description,
-                latest: None,
-            }, connection),
+            None => {
+                if !ReservedName::is_allowed(name, author, connection)? {
+                    return Err(NameReserved {
+                        name: name.to_string(),
+                    }.into());
+                }
+
+                Self::create(&NewModule {
+                    author,
+                    name,
+                    description,
+                    latest: None,
+                }, connection)
+            },
         }
     }
 
@@ -130,64 +479,498 @@ impl Module {
             .map_err(Error::from)
     }
 
+    /// The module and all of its releases that aren't yanked, newest first,
+    /// loaded in a single query without the code. None if the module doesn't
+    /// exist or has been deleted.
+    pub fn manifest(author: &str, name: &str, connection: &PgConnection) -> Result<Option<ModuleManifest>> {
+        let rows = modules::table
+            .left_join(releases::table.on(releases::module_id.eq(modules::id)
+                .and(releases::yanked.eq(false))))
+            .select((
+                ALL_MODULE_COLUMNS,
+                (
+                    releases::version,
+                    releases::downloads,
+                    releases::published,
+                    releases::sha256,
+                    releases::prerelease,
+                ).nullable(),
+            ))
+            .filter(modules::author.eq(author))
+            .filter(modules::name.eq(name))
+            .filter(modules::deleted_at.is_null())
+            .order((releases::published.desc(), releases::id.desc()))
+            .load::<(Module, Option<ManifestRelease>)>(connection)?;
+
+        let mut rows = rows.into_iter();
+        let (module, first) = match rows.next() {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        // a module without releases is a single row without a release
+        let releases = first.into_iter()
+            .chain(rows.filter_map(|(_, release)| release))
+            .collect();
+
+        Ok(Some(ModuleManifest {
+            module,
+            releases,
+        }))
+    }
+
+    /// Modules with the sum of their downloads and the number of releases
+    fn stats_query<'a>() -> ModuleWithStatsQuery<'a> {
+        modules::table
+            .left_join(releases::table)
+            .group_by(modules::id)
+            .select((
+                ALL_MODULE_COLUMNS,
+                diesel::dsl::sql::<BigInt>("COALESCE(SUM(releases.downloads), 0)::BIGINT"),
+                diesel::dsl::sql::<BigInt>("COUNT(releases.id)"),
+            ))
+            .into_boxed()
+    }
+
+    fn first_with_stats(query: ModuleWithStatsQuery<'_>, connection: &PgConnection) -> Result<ModuleWithStats> {
+        let (module, total_downloads, num_releases) = query
+            .first::<(Module, i64, i64)>(connection)?;
+
+        Ok(ModuleWithStats {
+            module,
+            total_downloads,
+            num_releases,
+        })
+    }
+
+    /// Like `find`, but also sums the downloads and counts the releases
+    pub fn find_with_stats(author: &str, name: &str, connection: &PgConnection) -> Result<ModuleWithStats> {
+        let query = Module::stats_query()
+            .filter(modules::author.eq(author))
+            .filter(modules::name.eq(name))
+            .filter(modules::deleted_at.is_null());
+        Module::first_with_stats(query, connection)
+    }
+
+    /// Like `id`, but also sums the downloads and counts the releases
+    pub fn id_with_stats(id: i32, connection: &PgConnection) -> Result<ModuleWithStats> {
+        let query = Module::stats_query()
+            .filter(modules::id.eq(id));
+        Module::first_with_stats(query, connection)
+    }
+
     pub fn delete(id: i32, connection: &PgConnection) -> Result<()> {
         diesel::delete(modules::table.find(id))
             .execute(connection)?;
         Ok(())
     }
 
-    pub fn add_version(&self, version: &str, code: &str, connection: &PgConnection) -> Result<()> {
+    /// Hide the module everywhere without deleting its releases, it's purged
+    /// once the grace period is over unless it's restored before that
+    pub fn soft_delete(&self, connection: &PgConnection) -> Result<Module> {
+        diesel::update(modules::table.find(self.id))
+            .set(modules::deleted_at.eq(SystemTime::now()))
+            .returning(ALL_MODULE_COLUMNS)
+            .get_result(connection)
+            .map_err(Error::from)
+    }
+
+    pub fn restore(&self, connection: &PgConnection) -> Result<Module> {
+        diesel::update(modules::table.find(self.id))
+            .set(modules::deleted_at.eq(None::<SystemTime>))
+            .returning(ALL_MODULE_COLUMNS)
+            .get_result(connection)
+            .map_err(Error::from)
+    }
+
+    /// Delete modules that have been soft deleted more than `grace_days` ago,
+    /// including their releases and download stats. Returns the number of
+    /// deleted modules.
+    pub fn purge_expired(grace_days: u64, connection: &PgConnection) -> Result<usize> {
+        let cutoff = SystemTime::now() - Duration::from_secs(grace_days * 24 * 3600);
+
+        connection.transaction::<_, Error, _>(|| {
+            let ids = modules::table
+                .select(modules::id)
+                .filter(modules::deleted_at.lt(cutoff))
+                .for_update()
+                .load::<i32>(connection)?;
+
+            // releases don't cascade, everything else does
+            diesel::delete(releases::table.filter(releases::module_id.eq_any(&ids)))
+                .execute(connection)?;
+            diesel::delete(modules::table.filter(modules::id.eq_any(&ids)))
+                .execute(connection)
+                .map_err(Error::from)
+        })
+    }
+
+    /// Add a new release, fails with `UnchangedRelease` if the code is
+    /// identical to the latest release unless `allow_unchanged` is set,
+    /// with `CodeTooLarge` if the code exceeds the limit, with
+    /// `InvalidDependency` if a declared dependency doesn't exist and with
+    /// `VersionExists` if the version has already been published.
+    /// Prereleases don't replace the latest release, and neither do versions
+    /// that are lower than the latest release.
+    pub fn add_version(&self, version: &str, code: &str, options: &PublishOptions, connection: &PgConnection) -> Result<()> {
+        ensure_code_size(code, options.limits.max_code_size)?;
+        ensure_version(version)?;
+
+        let checksum = checksum(code);
+        let manifest = encode_manifest(code)?;
+        let dependencies = code.parse::<Metadata>()?.dependencies;
+        ensure_dependencies(&dependencies, connection)?;
+
+        if !options.allow_unchanged {
+            let latest = match &self.latest {
+                Some(latest) => Release::try_find(self.id, latest, connection)?
+                    .filter(|release| !release.yanked),
+                None => None,
+            };
+            ensure_changed(latest.as_ref(), code)?;
+        }
+
         let _release = Release::create(&NewRelease {
             module_id: self.id,
             version,
             code,
-        }, connection)?;
+            sha256: &checksum,
+            manifest: &manifest,
+            prerelease: options.prerelease,
+            readme: options.readme,
+        }, &dependencies, connection).map_err(|err| version_exists(err, version))?;
 
-        diesel::update(modules::table.filter(modules::columns::id.eq(self.id)))
-            .set(modules::columns::latest.eq(version))
-            .execute(connection)?;
+        if options.prerelease {
+            return Ok(());
+        }
+
+        Module::refresh_latest(self.id, connection)?;
 
         Ok(())
     }
 
-    pub fn search(query: &str, connection: &PgConnection) -> Result<Vec<(Module, i64)>> {
-        let q = plainto_tsquery(query);
+    /// Point `latest` to the highest release that is neither a prerelease nor
+    /// yanked, or to nothing if there is none left
+    pub fn refresh_latest(module_id: i32, connection: &PgConnection) -> Result<Option<String>> {
+        let latest = Release::latest_stable(module_id, connection)?
+            .map(|release| release.version);
+
+        diesel::update(modules::table.find(module_id))
+            .set(modules::latest.eq(&latest))
+            .execute(connection)?;
+
+        Ok(latest)
+    }
+
+    /// Search modules that aren't hidden, deprecated modules are listed last
+    /// and featured modules first.
+    /// The results can be limited to one author, to featured modules or to
+    /// modules of verified authors, an empty query lists every module that
+    /// matches these filters by downloads.
+    pub fn search(query: &str, author: Option<&str>, featured_only: bool, verified_only: bool, connection: &PgConnection) -> Result<Vec<(Module, i64)>> {
+        let query = query.trim();
+        if query.is_empty() && author.is_none() && !featured_only && !verified_only {
+            return Ok(Vec::new());
+        }
+
+        let mut select = modules::table.select((
+                modules::id,
+                modules::author,
+                modules::name,
+                modules::description,
+                modules::latest,
+                modules::featured,
+                modules::deprecated,
+                modules::deprecated_msg,
+                modules::successor_id,
+                modules::deleted_at,
+                diesel::dsl::sql::<BigInt>("COALESCE(sum(releases.downloads), 0) AS sum"),
+            ))
+            .left_join(releases::table)
+            // most authors don't have a row, the join must not drop their modules
+            .left_join(authors::table.on(authors::name.eq(modules::author)))
+            .group_by(modules::id)
+            .filter(modules::hidden.eq(false))
+            .filter(modules::deleted_at.is_null())
+            .order(modules::deprecated.asc())
+            .into_boxed();
+
+        if !query.is_empty() {
+            select = select.filter(plainto_tsquery(query).matches(modules::search_vector))
+                .then_order_by(modules::featured.desc());
+        }
+        if let Some(author) = author {
+            select = select.filter(modules::author.eq(author));
+        }
+        if featured_only {
+            select = select.filter(modules::featured.eq(true));
+        }
+        if verified_only {
+            select = select.filter(authors::verified.eq(true));
+        }
+
+        let x: Vec<ModuleRow> = select
+            .then_order_by(diesel::dsl::sql::<BigInt>("sum").desc())
+            .load(connection)?;
+
+        Ok(x.into_iter().map(|(id, author, name, description, latest, featured, deprecated, deprecated_msg, successor_id, deleted_at, downloads)| (
+            Module {
+                id,
+                author,
+                name,
+                description,
+                latest,
+                featured,
+                deprecated,
+                deprecated_msg,
+                successor_id,
+                deleted_at,
+            },
+            downloads,
+        )).collect())
+    }
+
+    /// A page of the modules of an author, sorted by downloads, and the total
+    /// number of modules. Modules without a stable release and hidden modules
+    /// are skipped.
+    pub fn by_author(author: &str, connection: &PgConnection, limit: i64, offset: i64) -> Result<(Vec<Module>, i64)> {
+        let total = modules::table
+            .filter(modules::author.eq(author))
+            .filter(modules::latest.is_not_null())
+            .filter(modules::hidden.eq(false))
+            .filter(modules::deleted_at.is_null())
+            .count()
+            .get_result::<i64>(connection)?;
 
-        let x: Vec<(i32, String, String, String, Option<String>, bool, i64)> = modules::table.select((
+        let x: Vec<ModuleRow> = modules::table.select((
                 modules::id,
                 modules::author,
                 modules::name,
                 modules::description,
                 modules::latest,
                 modules::featured,
+                modules::deprecated,
+                modules::deprecated_msg,
+                modules::successor_id,
+                modules::deleted_at,
                 diesel::dsl::sql::<BigInt>("sum(releases.downloads) AS sum"),
             ))
             .left_join(releases::table)
             .group_by(modules::id)
-            .filter(q.matches(modules::search_vector))
+            .filter(modules::author.eq(author))
+            .filter(modules::latest.is_not_null())
+            .filter(modules::hidden.eq(false))
+            .filter(modules::deleted_at.is_null())
             .order((
-                modules::featured.desc(),
                 diesel::dsl::sql::<BigInt>("sum").desc(),
+                modules::name.asc(),
             ))
+            .limit(limit)
+            .offset(offset)
             .load(connection)?;
 
-        Ok(x.into_iter().map(|(id, author, name, description, latest, featured, downloads)| (
-            Module {
+        let modules = x.into_iter()
+            .map(|(id, author, name, description, latest, featured, deprecated, deprecated_msg, successor_id, deleted_at, _downloads)| Module {
                 id,
                 author,
                 name,
                 description,
                 latest,
                 featured,
+                deprecated,
+                deprecated_msg,
+                successor_id,
+                deleted_at,
+            })
+            .collect();
+
+        Ok((modules, total))
+    }
+
+    /// Modules with a description similar to this module, for a "you might
+    /// also like" section. Modules without a stable release, hidden and
+    /// deprecated modules are skipped.
+    pub fn similar(module_id: i32, connection: &PgConnection, limit: i64) -> Result<Vec<Module>> {
+        let x = diesel::sql_query(SIMILAR_MODULES)
+            .bind::<Integer, _>(module_id)
+            .bind::<BigInt, _>(limit)
+            .load::<ModuleWithDownloads>(connection)?;
+
+        Ok(x.into_iter().map(|x| Module {
+            id: x.id,
+            author: x.author,
+            name: x.name,
+            description: x.description,
+            latest: x.latest,
+            featured: x.featured,
+            deprecated: x.deprecated,
+            deprecated_msg: x.deprecated_msg,
+            successor_id: x.successor_id,
+            deleted_at: x.deleted_at,
+        }).collect())
+    }
+
+    pub fn top_per_author(connection: &PgConnection, limit: i64) -> Result<Vec<(Module, i64)>> {
+        let x = diesel::sql_query(TOP_PER_AUTHOR)
+            .bind::<BigInt, _>(limit)
+            .load::<ModuleWithDownloads>(connection)?;
+
+        Ok(x.into_iter().map(|x| (
+            Module {
+                id: x.id,
+                author: x.author,
+                name: x.name,
+                description: x.description,
+                latest: x.latest,
+                featured: x.featured,
+                deprecated: x.deprecated,
+                deprecated_msg: x.deprecated_msg,
+                successor_id: x.successor_id,
+                deleted_at: x.deleted_at,
             },
-            downloads,
+            x.downloads,
+        )).collect())
+    }
+
+    /// The modules with the most downloads in the last `days` days, modules
+    /// without downloads in that window are skipped
+    pub fn trending(days: i64, limit: i64, connection: &PgConnection) -> Result<Vec<(Module, i64)>> {
+        if days < 1 {
+            bail!("Window must be at least one day");
+        }
+
+        let x = diesel::sql_query(TRENDING_MODULES)
+            .bind::<BigInt, _>(days)
+            .bind::<BigInt, _>(limit)
+            .load::<ModuleWithDownloads>(connection)?;
+
+        Ok(x.into_iter().map(|x| (
+            Module {
+                id: x.id,
+                author: x.author,
+                name: x.name,
+                description: x.description,
+                latest: x.latest,
+                featured: x.featured,
+                deprecated: x.deprecated,
+                deprecated_msg: x.deprecated_msg,
+                successor_id: x.successor_id,
+                deleted_at: x.deleted_at,
+            },
+            x.downloads,
         )).collect())
     }
 
+    /// Recompute the search_vector of this module
+    pub fn reindex(&self, connection: &PgConnection) -> Result<()> {
+        diesel::sql_query(REFRESH_SEARCH_VECTOR)
+            .bind::<Array<Integer>, _>(vec![self.id])
+            .execute(connection)?;
+        Ok(())
+    }
+
+    /// Recompute search_vector for all modules in batches of
+    /// `REINDEX_BATCH_SIZE` and log the progress
+    pub fn reindex_all(connection: &PgConnection) -> Result<usize> {
+        let total = Module::refresh_search_vectors(REINDEX_BATCH_SIZE, connection, |n| {
+            info!("Reindexed {} modules", n);
+        })?;
+        info!("Reindexed search vectors of {} modules", total);
+        Ok(total)
+    }
+
+    /// Recompute search_vector for all modules, one transaction per batch.
+    /// The callback is invoked after each batch with the number of modules
+    /// that have been updated so far.
+    pub fn refresh_search_vectors<F: FnMut(usize)>(batch_size: i64, connection: &PgConnection, mut progress: F) -> Result<usize> {
+        let mut total = 0;
+        let mut last_id = 0;
+
+        loop {
+            let ids = modules::table
+                .select(modules::id)
+                .filter(modules::id.gt(last_id))
+                .order(modules::id.asc())
+                .limit(batch_size)
+                .load::<i32>(connection)?;
+
+            last_id = match ids.last() {
+                Some(id) => *id,
+                None => break,
+            };
+
+            total += connection.transaction::<_, Error, _>(|| {
+                diesel::sql_query(REFRESH_SEARCH_VECTOR)
+                    .bind::<Array<Integer>, _>(&ids)
+                    .execute(connection)
+                    .map_err(Error::from)
+            })?;
+
+            progress(total);
+        }
+
+        Ok(total)
+    }
+
+    /// Totals across all modules, computed in a single query
+    pub fn platform_stats(connection: &PgConnection) -> Result<PlatformStats> {
+        diesel::sql_query(PLATFORM_STATS)
+            .get_result::<PlatformStats>(connection)
+            .map_err(Error::from)
+    }
+
+    /// Downloads per day from `from` to `to`, both inclusive. Days start at
+    /// midnight utc.
+    pub fn download_series(module_id: i32, from: SystemTime, to: SystemTime, connection: &PgConnection) -> Result<Vec<(SystemTime, i64)>> {
+        if from > to {
+            bail!("Start of the range is after the end");
+        }
+
+        let x = diesel::sql_query(DOWNLOAD_SERIES)
+            .bind::<Integer, _>(module_id)
+            .bind::<Timestamp, _>(from)
+            .bind::<Timestamp, _>(to)
+            .load::<DailyDownloads>(connection)?;
+
+        Ok(x.into_iter()
+            .map(|x| (x.day, x.count))
+            .collect())
+    }
+
+    /// Hand the module to a different author, releases and download stats
+    /// stay attached to the module. The previous location is recorded so
+    /// lookups of the old name can point to the new one.
+    pub fn transfer_ownership(&self, new_author: &str, connection: &PgConnection) -> Result<Module> {
+        ensure_transferable(self, new_author)?;
+
+        connection.transaction::<_, Error, _>(|| {
+            if Module::find_opt(new_author, &self.name, connection)?.is_some() {
+                return Err(TransferRejected::NameTaken(new_author.to_string(), self.name.clone()).into());
+            }
+
+            let module = diesel::update(modules::table.find(self.id))
+                .set(modules::author.eq(new_author))
+                .returning(ALL_MODULE_COLUMNS)
+                .get_result::<Module>(connection)?;
+
+            diesel::insert_into(module_transfers::table)
+                .values(&NewModuleTransfer {
+                    module_id: self.id,
+                    old_author: &self.author,
+                    new_author,
+                    name: &self.name,
+                })
+                .execute(connection)?;
+
+            Ok(module)
+        })
+    }
+
     pub fn quickstart(connection: &PgConnection) -> Result<Vec<Module>> {
         modules::table
             .select(ALL_MODULE_COLUMNS)
             .filter(modules::featured)
+            .filter(modules::hidden.eq(false))
+            .filter(modules::deleted_at.is_null())
             .order((
                 modules::author.asc(),
                 modules::name.asc(),
@@ -195,6 +978,119 @@ impl Module {
             .load(connection)
             .map_err(Error::from)
     }
+
+    /// Hidden modules don't show up in search, quickstart and other listings,
+    /// but they can still be viewed and downloaded
+    pub fn is_hidden(&self, connection: &PgConnection) -> Result<bool> {
+        modules::table.find(self.id)
+            .select(modules::hidden)
+            .first::<bool>(connection)
+            .map_err(Error::from)
+    }
+
+    pub fn hide(&self, connection: &PgConnection) -> Result<()> {
+        diesel::update(modules::table.find(self.id))
+            .set(modules::hidden.eq(true))
+            .execute(connection)?;
+        Ok(())
+    }
+
+    /// Unhiding a module resolves its open reports, otherwise the next
+    /// report would hide it again right away
+    pub fn unhide(&self, connection: &PgConnection) -> Result<()> {
+        connection.transaction::<_, Error, _>(|| {
+            diesel::update(modules::table.find(self.id))
+                .set(modules::hidden.eq(false))
+                .execute(connection)?;
+            ModuleReport::resolve(self.id, connection)?;
+            Ok(())
+        })
+    }
+
+    /// Deprecated modules can still be installed, but they are listed after
+    /// other modules in search and clients warn about them. The successor is
+    /// suggested as a replacement.
+    pub fn deprecate(&self, msg: Option<&str>, successor: Option<i32>, connection: &PgConnection) -> Result<Module> {
+        ensure_deprecation_msg(msg)?;
+        if let Some(successor) = successor {
+            if successor == self.id {
+                return Err(InvalidDeprecation::OwnSuccessor.into());
+            }
+            if Module::id_opt(successor, connection)?.is_none() {
+                return Err(InvalidDeprecation::UnknownSuccessor.into());
+            }
+        }
+
+        diesel::update(modules::table.find(self.id))
+            .set((
+                modules::deprecated.eq(true),
+                modules::deprecated_msg.eq(msg),
+                modules::successor_id.eq(successor),
+            ))
+            .returning(ALL_MODULE_COLUMNS)
+            .get_result(connection)
+            .map_err(Error::from)
+    }
+
+    pub fn undeprecate(&self, connection: &PgConnection) -> Result<Module> {
+        diesel::update(modules::table.find(self.id))
+            .set((
+                modules::deprecated.eq(false),
+                modules::deprecated_msg.eq(None::<String>),
+                modules::successor_id.eq(None::<i32>),
+            ))
+            .returning(ALL_MODULE_COLUMNS)
+            .get_result(connection)
+            .map_err(Error::from)
+    }
+}
+
+pub const MAX_DEPRECATION_MSG_LEN: usize = 1000;
+
+/// Returned by `Module::deprecate` if the deprecation is invalid
+#[derive(Debug, Fail, PartialEq)]
+pub enum InvalidDeprecation {
+    #[fail(display="Message can not be longer than {} bytes", _0)]
+    MessageTooLong(usize),
+    #[fail(display="Module can not be its own successor")]
+    OwnSuccessor,
+    #[fail(display="Successor does not exist")]
+    UnknownSuccessor,
+}
+
+fn ensure_deprecation_msg(msg: Option<&str>) -> Result<()> {
+    match msg {
+        Some(msg) if msg.len() > MAX_DEPRECATION_MSG_LEN => Err(InvalidDeprecation::MessageTooLong(MAX_DEPRECATION_MSG_LEN).into()),
+        _ => Ok(()),
+    }
+}
+
+/// A module with the sum of the downloads and the number of its releases,
+/// both are zero if nothing has been published yet
+#[derive(Serialize, PartialEq, Debug)]
+pub struct ModuleWithStats {
+    pub module: Module,
+    pub total_downloads: i64,
+    pub num_releases: i64,
+}
+
+/// Everything a client needs to pick a release, see `Module::manifest`
+#[derive(Serialize, PartialEq, Debug)]
+pub struct ModuleManifest {
+    pub module: Module,
+    pub releases: Vec<ManifestRelease>,
+}
+
+/// A release that isn't yanked, without the code
+#[derive(Queryable, Serialize, PartialEq, Debug)]
+pub struct ManifestRelease {
+    pub version: String,
+    pub downloads: i32,
+    #[serde(serialize_with="serialize_rfc3339")]
+    pub published: SystemTime,
+    /// Hex encoded sha256 of the code
+    pub sha256: String,
+    pub prerelease: bool,
 }
 
 #[derive(Insertable)]
@@ -206,6 +1102,23 @@ pub struct NewModule<'a> {
     latest: Option<&'a str>,
 }
 
+/// Timestamps in api responses are rfc 3339 in utc with second precision,
+/// eg. `2019-04-12T18:30:00Z`
+pub fn rfc3339(time: &SystemTime) -> String {
+    DateTime::<Utc>::from(*time).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn serialize_rfc3339<S: Serializer>(time: &SystemTime, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&rfc3339(time))
+}
+
+fn serialize_rfc3339_opt<S: Serializer>(time: &Option<SystemTime>, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    match time {
+        Some(time) => serializer.serialize_some(&rfc3339(time)),
+        None => serializer.serialize_none(),
+    }
+}
+
 #[derive(AsChangeset, Identifiable, Queryable, Associations, Serialize, PartialEq, Debug)]
 #[belongs_to(Module)]
 #[table_name="releases"]
@@ -215,44 +1128,443 @@ pub struct Release {
     pub version: String,
     pub downloads: i32,
     pub code: String,
+    #[serde(serialize_with="serialize_rfc3339")]
     pub published: SystemTime,
+    /// Hex encoded sha256 of the code
+    pub sha256: String,
+    pub manifest: Option<String>,
+    pub prerelease: bool,
+    pub readme: Option<String>,
+    /// Yanked releases can still be downloaded by their version, but they are
+    /// never the latest release
+    pub yanked: bool,
 }
 
-impl Release {
-    pub fn create(release: &NewRelease, connection: &PgConnection) -> Result<Release> {
-        diesel::insert_into(releases::table)
-            .values(release)
-            .get_result(connection)
-            .map_err(Error::from)
-        /*
-        releases::table.filter(releases::columns::module_id.eq(release.module_id))
-                        .filter(releases::columns::version.eq(&release.version))
-                        .select(releases::columns::id)
-                        .first::<i32>(connection)
-                        .map_err(Error::from)
-        */
-    }
+/// Releases are listed with at most this many per page
+pub const MAX_RELEASE_PAGE: i64 = 100;
 
-    pub fn find(module_id: i32, version: &str, connection: &PgConnection) -> Result<Release> {
-        releases::table.filter(releases::columns::module_id.eq(module_id))
-                        .filter(releases::columns::version.eq(version))
-                        .first::<Release>(connection)
-                        .map_err(Error::from)
-    }
+/// A release without the code, for version listings
+#[derive(Queryable, Serialize, PartialEq, Debug)]
+pub struct ReleaseSummary {
+    pub version: String,
+    pub downloads: i32,
+    #[serde(serialize_with="serialize_rfc3339")]
+    pub published: SystemTime,
+    pub prerelease: bool,
+    pub yanked: bool,
+}
 
-    pub fn try_find(module_id: i32, version: &str, connection: &PgConnection) -> Result<Option<Release>> {
-        releases::table.filter(releases::columns::module_id.eq(module_id))
-                        .filter(releases::columns::version.eq(version))
-                        .first::<Release>(connection)
-                        .optional()
-                        .map_err(Error::from)
-    }
+/// Returned by `Module::add_version` if the code didn't change since the latest release
+#[derive(Debug, Fail)]
+#[fail(display="Code is identical to the latest release ({})", version)]
+pub struct UnchangedRelease {
+    pub version: String,
+}
 
-    pub fn id(id: i32, connection: &PgConnection) -> Result<Release> {
-        releases::table.find(id)
-            .first::<Release>(connection)
-            .map_err(Error::from)
-    }
+/// Returned by `Module::add_version` if the code exceeds `PublishLimits::max_code_size`
+#[derive(Debug, Fail, PartialEq)]
+#[fail(display="Code is {} bytes, releases can be at most {} bytes", size, limit)]
+pub struct CodeTooLarge {
+    pub size: usize,
+    pub limit: usize,
+}
+
+/// The largest release that can be assembled from a chunked upload
+pub const DEFAULT_MAX_CODE_SIZE: usize = MAX_UPLOAD_PARTS as usize * UPLOAD_PART_SIZE;
+
+pub const DEFAULT_MAX_PUBLISHES: i64 = 20;
+/// The sliding window of the publish rate limit
+pub const PUBLISH_WINDOW: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublishLimits {
+    pub max_code_size: usize,
+    /// Releases an author can publish within `PUBLISH_WINDOW`, zero disables the limit
+    pub max_publishes: i64,
+}
+
+impl Default for PublishLimits {
+    fn default() -> PublishLimits {
+        PublishLimits {
+            max_code_size: DEFAULT_MAX_CODE_SIZE,
+            max_publishes: DEFAULT_MAX_PUBLISHES,
+        }
+    }
+}
+
+/// How a release is published, see `Module::add_version`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PublishOptions<'a> {
+    pub readme: Option<&'a str>,
+    /// Don't replace the latest release
+    pub prerelease: bool,
+    /// Accept code that is identical to the latest release
+    pub allow_unchanged: bool,
+    pub limits: PublishLimits,
+}
+
+impl PublishLimits {
+    /// Read MAX_CODE_SIZE, in bytes, and PUBLISH_RATELIMIT_PER_HOUR
+    pub fn from_env() -> Result<PublishLimits> {
+        let max_code_size = match env::var("MAX_CODE_SIZE") {
+            Ok(x) => x.parse::<usize>()
+                .context("MAX_CODE_SIZE is invalid")?,
+            Err(_) => DEFAULT_MAX_CODE_SIZE,
+        };
+
+        if max_code_size == 0 {
+            bail!("MAX_CODE_SIZE must be greater than zero");
+        }
+
+        let max_publishes = match env::var("PUBLISH_RATELIMIT_PER_HOUR") {
+            Ok(x) => x.parse::<i64>()
+                .context("PUBLISH_RATELIMIT_PER_HOUR is invalid")?,
+            Err(_) => DEFAULT_MAX_PUBLISHES,
+        };
+
+        if max_publishes < 0 {
+            bail!("PUBLISH_RATELIMIT_PER_HOUR can not be negative");
+        }
+
+        Ok(PublishLimits {
+            max_code_size,
+            max_publishes,
+        })
+    }
+}
+
+/// Returned by `Module::add_version` if the version has already been published,
+/// enforced by the unique constraint on (module_id, version)
+#[derive(Debug, Fail, PartialEq)]
+#[fail(display="Version {:?} already exists", version)]
+pub struct VersionExists {
+    pub version: String,
+}
+
+/// Returned by `Module::add_version` if the version isn't valid semver
+#[derive(Debug, Fail, PartialEq)]
+#[fail(display="Version {:?} is not a valid semver version: {}", version, reason)]
+pub struct InvalidVersion {
+    pub version: String,
+    pub reason: String,
+}
+
+fn ensure_version(version: &str) -> Result<Version> {
+    Version::parse(version)
+        .map_err(|err| InvalidVersion {
+            version: version.to_string(),
+            reason: err.to_string(),
+        }.into())
+}
+
+/// Pick the release with the highest version, versions that aren't valid
+/// semver are only picked if there is nothing else
+fn highest_version(releases: &[(i32, String)]) -> Option<i32> {
+    releases.iter()
+        .max_by_key(|(id, version)| (Version::parse(version).ok(), *id))
+        .map(|(id, _)| *id)
+}
+
+fn version_exists(err: Error, version: &str) -> Error {
+    match err.downcast_ref::<diesel::result::Error>() {
+        Some(diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _)) => VersionExists {
+            version: version.to_string(),
+        }.into(),
+        _ => err,
+    }
+}
+
+fn ensure_code_size(code: &str, limit: usize) -> Result<()> {
+    if code.len() > limit {
+        return Err(CodeTooLarge {
+            size: code.len(),
+            limit,
+        }.into());
+    }
+    Ok(())
+}
+
+pub const MAX_NAME_LEN: usize = 32;
+
+/// Returned by `validate_name` if an author or module name doesn't match
+/// `^[a-z0-9][a-z0-9_-]{0,31}$`
+#[derive(Debug, Fail, PartialEq)]
+#[fail(display="Invalid {}: {:?}, only lowercase letters, digits, '-' and '_' are allowed, starting with a letter or digit, at most 32 characters", field, value)]
+pub struct InvalidName {
+    pub field: &'static str,
+    pub value: String,
+}
+
+/// Drop candidates that only matched another combination of author and name
+fn select_requested(candidates: Vec<Module>, ids: &[ModuleID]) -> Vec<Module> {
+    candidates.into_iter()
+        .filter(|module| ids.iter()
+            .any(|id| id.author == module.author && id.name == module.name))
+        .collect()
+}
+
+/// Module authors and names end up in urls and lookups, make sure they stay boring
+pub fn validate_name(field: &'static str, value: &str) -> ::std::result::Result<(), InvalidName> {
+    let valid = !value.is_empty()
+        && value.len() <= MAX_NAME_LEN
+        && value.chars().enumerate().all(|(i, c)| match c {
+            'a'..='z' | '0'..='9' => true,
+            '-' | '_' => i > 0,
+            _ => false,
+        });
+
+    if valid {
+        Ok(())
+    } else {
+        Err(InvalidName {
+            field,
+            value: value.to_string(),
+        })
+    }
+}
+
+/// Returned by `Module::transfer_ownership` if the transfer isn't possible
+#[derive(Debug, Fail, PartialEq)]
+pub enum TransferRejected {
+    #[fail(display="Module is already owned by {:?}", _0)]
+    SameAuthor(String),
+    #[fail(display="{}/{} already exists", _0, _1)]
+    NameTaken(String, String),
+}
+
+fn ensure_transferable(module: &Module, new_author: &str) -> Result<()> {
+    if module.author == new_author {
+        return Err(TransferRejected::SameAuthor(new_author.to_string()).into());
+    }
+    validate_name("author", new_author)?;
+    Ok(())
+}
+
+/// 32 random bytes, hex encoded
+fn random_session_id() -> Result<String> {
+    let mut buf = [0; 32];
+    File::open("/dev/urandom")?
+        .read_exact(&mut buf)?;
+    Ok(hex::encode(buf))
+}
+
+/// Returned by `AuthToken::mint` if a requested scope can't be granted
+#[derive(Debug, Fail, PartialEq)]
+pub enum ScopeRejected {
+    #[fail(display="Unknown scope: {:?}", _0)]
+    Unknown(String),
+    #[fail(display="Auth token doesn't have the {:?} scope", _0)]
+    NotGranted(String),
+}
+
+pub fn checksum(code: &str) -> String {
+    hex::encode(Sha256::digest(code.as_bytes()))
+}
+
+/// Returned by `Release::create` if the sha256 doesn't match the code
+#[derive(Debug, Fail, PartialEq)]
+#[fail(display="sha256 of the code is {}, expected {}", actual, expected)]
+pub struct HashMismatch {
+    pub expected: String,
+    pub actual: String,
+}
+
+fn ensure_sha256(code: &str, expected: &str) -> Result<()> {
+    let actual = checksum(code);
+    if actual != expected {
+        return Err(HashMismatch {
+            expected: expected.to_string(),
+            actual,
+        }.into());
+    }
+    Ok(())
+}
+
+/// Hash the code without the `-- Version` line of its header, so a release
+/// that only bumps the version counts as unchanged
+fn content_checksum(code: &str) -> String {
+    let mut header = true;
+    let content = code.lines()
+        .filter(|line| {
+            if line.is_empty() {
+                header = false;
+            }
+            !(header && line.starts_with("-- Version: "))
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    checksum(&content)
+}
+
+fn ensure_changed(latest: Option<&Release>, code: &str) -> Result<()> {
+    if let Some(latest) = latest {
+        if content_checksum(&latest.code) == content_checksum(code) {
+            return Err(UnchangedRelease {
+                version: latest.version.clone(),
+            }.into());
+        }
+    }
+    Ok(())
+}
+
+/// Returned by `Module::add_version` if a declared dependency can't be used
+#[derive(Debug, Fail, PartialEq)]
+pub enum InvalidDependency {
+    #[fail(display="Invalid version requirement for {}: {:?}", _0, _1)]
+    VersionReq(String, String),
+    /// Every dependency that doesn't exist, releases that don't exist yet
+    /// are allowed
+    #[fail(display="Unknown dependencies: {}", _0)]
+    Unknown(String),
+}
+
+fn ensure_dependencies(dependencies: &[Dependency], connection: &PgConnection) -> Result<()> {
+    for dependency in dependencies {
+        if VersionReq::parse(&dependency.version_req).is_err() {
+            let module = format!("{}/{}", dependency.author, dependency.name);
+            return Err(InvalidDependency::VersionReq(module, dependency.version_req.clone()).into());
+        }
+    }
+
+    if dependencies.is_empty() {
+        return Ok(());
+    }
+
+    let ids = dependencies.iter()
+        .map(|x| ModuleID {
+            author: x.author.clone(),
+            name: x.name.clone(),
+        })
+        .collect::<Vec<_>>();
+    let found = Module::find_many(&ids, connection)?;
+
+    let unknown = ids.iter()
+        .filter(|id| !found.iter().any(|m| m.author == id.author && m.name == id.name))
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>();
+    if !unknown.is_empty() {
+        return Err(InvalidDependency::Unknown(unknown.join(", ")).into());
+    }
+
+    Ok(())
+}
+
+fn parse_manifest(code: &str) -> Result<Manifest> {
+    let metadata = code.parse::<Metadata>()?;
+    Ok(Manifest::from(&metadata))
+}
+
+fn encode_manifest(code: &str) -> Result<String> {
+    let manifest = parse_manifest(code)?;
+    serde_json::to_string(&manifest)
+        .map_err(Error::from)
+}
+
+impl Release {
+    /// The sha256 of the release has to match the code. The dependencies are
+    /// stored as they are, they are validated by `Module::add_version`
+    pub fn create(release: &NewRelease, dependencies: &[Dependency], connection: &PgConnection) -> Result<Release> {
+        ensure_sha256(release.code, release.sha256)?;
+
+        connection.transaction::<_, Error, _>(|| {
+            let release = diesel::insert_into(releases::table)
+                .values(release)
+                .get_result::<Release>(connection)?;
+
+            let rows = dependencies.iter()
+                .map(|x| NewReleaseDependency {
+                    release_id: release.id,
+                    author: &x.author,
+                    name: &x.name,
+                    version_req: &x.version_req,
+                })
+                .collect::<Vec<_>>();
+            diesel::insert_into(release_dependencies::table)
+                .values(&rows)
+                .execute(connection)?;
+
+            Ok(release)
+        })
+        /*
+        releases::table.filter(releases::columns::module_id.eq(release.module_id))
+                        .filter(releases::columns::version.eq(&release.version))
+                        .select(releases::columns::id)
+                        .first::<i32>(connection)
+                        .map_err(Error::from)
+        */
+    }
+
+    /// The modules this release declared with `Depends`, in declaration order
+    pub fn dependencies(&self, connection: &PgConnection) -> Result<Vec<Dependency>> {
+        let rows = release_dependencies::table
+            .select((release_dependencies::author, release_dependencies::name, release_dependencies::version_req))
+            .filter(release_dependencies::release_id.eq(self.id))
+            .order_by(release_dependencies::id)
+            .load::<(String, String, String)>(connection)?;
+
+        Ok(rows.into_iter()
+            .map(|(author, name, version_req)| Dependency {
+                author,
+                name,
+                version_req,
+            })
+            .collect())
+    }
+
+    pub fn find(module_id: i32, version: &str, connection: &PgConnection) -> Result<Release> {
+        releases::table.filter(releases::columns::module_id.eq(module_id))
+                        .filter(releases::columns::version.eq(version))
+                        .first::<Release>(connection)
+                        .map_err(Error::from)
+    }
+
+    pub fn try_find(module_id: i32, version: &str, connection: &PgConnection) -> Result<Option<Release>> {
+        releases::table.filter(releases::columns::module_id.eq(module_id))
+                        .filter(releases::columns::version.eq(version))
+                        .first::<Release>(connection)
+                        .optional()
+                        .map_err(Error::from)
+    }
+
+    /// The release with the highest version that isn't a prerelease or yanked
+    pub fn latest_stable(module_id: i32, connection: &PgConnection) -> Result<Option<Release>> {
+        let versions = releases::table
+            .select((releases::id, releases::version))
+            .filter(releases::module_id.eq(module_id))
+            .filter(releases::prerelease.eq(false))
+            .filter(releases::yanked.eq(false))
+            .load::<(i32, String)>(connection)?;
+
+        match highest_version(&versions) {
+            Some(id) => Release::id_opt(id, connection),
+            None => Ok(None),
+        }
+    }
+
+    /// Every release of a module, newest first. The code isn't loaded and
+    /// `limit` is capped at `MAX_RELEASE_PAGE`.
+    pub fn list_for_module(module_id: i32, offset: i64, limit: i64, connection: &PgConnection) -> Result<Vec<ReleaseSummary>> {
+        releases::table
+            .select((
+                releases::version,
+                releases::downloads,
+                releases::published,
+                releases::prerelease,
+                releases::yanked,
+            ))
+            .filter(releases::module_id.eq(module_id))
+            .order((releases::published.desc(), releases::id.desc()))
+            .offset(offset.max(0))
+            .limit(limit.max(1).min(MAX_RELEASE_PAGE))
+            .load::<ReleaseSummary>(connection)
+            .map_err(Error::from)
+    }
+
+    pub fn id(id: i32, connection: &PgConnection) -> Result<Release> {
+        releases::table.find(id)
+            .first::<Release>(connection)
+            .map_err(Error::from)
+    }
 
     pub fn id_opt(id: i32, connection: &PgConnection) -> Result<Option<Release>> {
         releases::table.find(id)
@@ -267,20 +1579,154 @@ impl Release {
         Ok(())
     }
 
+    /// Yank or unyank the release and update the latest release of the
+    /// module, returns the new latest version
+    pub fn set_yanked(&self, yanked: bool, connection: &PgConnection) -> Result<Option<String>> {
+        connection.transaction::<_, Error, _>(|| {
+            diesel::update(releases::table.find(self.id))
+                .set(releases::yanked.eq(yanked))
+                .execute(connection)?;
+
+            Module::refresh_latest(self.module_id, connection)
+        })
+    }
+
+    /// Count a download for this release and its daily downloads
     pub fn bump_downloads(&self, connection: &PgConnection) -> Result<()> {
-        diesel::update(releases::table.filter(releases::id.eq(self.id)))
-            .set(releases::downloads.eq(releases::downloads + 1))
-            .execute(connection)?;
-        Ok(())
+        let mut counts = HashMap::new();
+        counts.insert(self.id, 1);
+        Release::flush_download_counts(&counts, connection)
+    }
+
+    /// Add the buffered downloads of each release id in a single
+    /// transaction, either all of them are counted or none. Releases that
+    /// were deleted in the meantime are skipped.
+    pub fn flush_download_counts(counts: &HashMap<i32, i64>, connection: &PgConnection) -> Result<()> {
+        connection.transaction::<_, Error, _>(|| {
+            for (id, count) in counts {
+                let count = i32::try_from(*count)
+                    .context("Download count is too large")?;
+                if count <= 0 {
+                    continue;
+                }
+
+                let updated = diesel::update(releases::table.filter(releases::id.eq(id)))
+                    .set(releases::downloads.eq(releases::downloads + count))
+                    .execute(connection)?;
+
+                if updated > 0 {
+                    diesel::sql_query(BUMP_DOWNLOAD_EVENTS)
+                        .bind::<Integer, _>(*id)
+                        .bind::<Integer, _>(count)
+                        .execute(connection)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Read the manifest without loading the code, unless the release
+    /// hasn't been backfilled yet
+    pub fn manifest(module_id: i32, version: &str, connection: &PgConnection) -> Result<Manifest> {
+        let (id, manifest) = releases::table
+            .select((releases::id, releases::manifest))
+            .filter(releases::module_id.eq(module_id))
+            .filter(releases::version.eq(version))
+            .first::<(i32, Option<String>)>(connection)?;
+
+        match manifest {
+            Some(manifest) => serde_json::from_str(&manifest)
+                .map_err(Error::from),
+            None => parse_manifest(&Release::id(id, connection)?.code),
+        }
+    }
+
+    /// Read the readme without loading the code
+    pub fn readme(module_id: i32, version: &str, connection: &PgConnection) -> Result<Option<String>> {
+        releases::table
+            .select(releases::readme)
+            .filter(releases::module_id.eq(module_id))
+            .filter(releases::version.eq(version))
+            .first::<Option<String>>(connection)
+            .map_err(Error::from)
+    }
+
+    /// Parse the manifest of releases published before the manifest column
+    /// existed, releases that fail to parse are skipped
+    pub fn backfill_manifests<F: FnMut(usize)>(batch_size: i64, connection: &PgConnection, mut progress: F) -> Result<usize> {
+        let mut total = 0;
+        let mut last_id = 0;
+
+        loop {
+            let rows = releases::table
+                .select((releases::id, releases::code))
+                .filter(releases::id.gt(last_id))
+                .filter(releases::manifest.is_null())
+                .order(releases::id.asc())
+                .limit(batch_size)
+                .load::<(i32, String)>(connection)?;
+
+            last_id = match rows.last() {
+                Some((id, _)) => *id,
+                None => break,
+            };
+
+            total += connection.transaction::<_, Error, _>(|| {
+                let mut n = 0;
+                for (id, code) in &rows {
+                    let manifest = match encode_manifest(code) {
+                        Ok(manifest) => manifest,
+                        Err(err) => {
+                            warn!("Failed to parse manifest of release {}: {}", id, err);
+                            continue;
+                        },
+                    };
+                    n += diesel::update(releases::table.find(id))
+                        .set(releases::manifest.eq(manifest))
+                        .execute(connection)?;
+                }
+                Ok(n)
+            })?;
+
+            progress(total);
+        }
+
+        Ok(total)
     }
 
     pub fn latest(connection: &PgConnection) -> Result<Option<Release>> {
         releases::table
+            .filter(releases::yanked.eq(false))
             .order_by(releases::published.desc())
             .first::<Release>(connection)
             .optional()
             .map_err(Error::from)
     }
+
+    /// Releases published in `[from, to)` across all modules, oldest first
+    pub fn published_between(from: SystemTime, to: SystemTime, connection: &PgConnection) -> Result<Vec<Release>> {
+        releases::table
+            .filter(releases::published.ge(from))
+            .filter(releases::published.lt(to))
+            .order_by((releases::published.asc(), releases::id.asc()))
+            .load::<Release>(connection)
+            .map_err(Error::from)
+    }
+
+    /// The most recently published releases across all modules that aren't
+    /// hidden or yanked, newest first
+    pub fn recent(connection: &PgConnection, limit: i64) -> Result<Vec<(Module, Release)>> {
+        releases::table
+            .inner_join(modules::table)
+            .filter(modules::hidden.eq(false))
+            .filter(modules::deleted_at.is_null())
+            .filter(releases::yanked.eq(false))
+            .select((ALL_MODULE_COLUMNS, releases::all_columns))
+            .order_by(releases::published.desc())
+            .limit(limit)
+            .load::<(Module, Release)>(connection)
+            .map_err(Error::from)
+    }
 }
 
 #[derive(Insertable)]
@@ -289,4 +1735,2065 @@ pub struct NewRelease<'a> {
     module_id: i32,
     version: &'a str,
     code: &'a str,
+    sha256: &'a str,
+    manifest: &'a str,
+    prerelease: bool,
+    readme: Option<&'a str>,
+}
+
+#[derive(Insertable)]
+#[table_name="release_dependencies"]
+pub struct NewReleaseDependency<'a> {
+    release_id: i32,
+    author: &'a str,
+    name: &'a str,
+    version_req: &'a str,
+}
+
+#[derive(Identifiable, Queryable, Serialize, PartialEq, Debug)]
+#[table_name="module_transfers"]
+pub struct ModuleTransfer {
+    pub id: i32,
+    pub module_id: i32,
+    pub old_author: String,
+    pub new_author: String,
+    pub name: String,
+    pub transferred: SystemTime,
+}
+
+impl ModuleTransfer {
+    /// Find the current location of a module that used to be `author/name`
+    pub fn moved(author: &str, name: &str, connection: &PgConnection) -> Result<Option<Module>> {
+        module_transfers::table
+            .inner_join(modules::table)
+            .filter(module_transfers::old_author.eq(author))
+            .filter(module_transfers::name.eq(name))
+            .filter(modules::deleted_at.is_null())
+            .order_by(module_transfers::id.desc())
+            .select(ALL_MODULE_COLUMNS)
+            .first::<Module>(connection)
+            .optional()
+            .map_err(Error::from)
+    }
+}
+
+#[derive(Insertable)]
+#[table_name="module_transfers"]
+pub struct NewModuleTransfer<'a> {
+    module_id: i32,
+    old_author: &'a str,
+    new_author: &'a str,
+    name: &'a str,
+}
+
+/// Verification is kept per author, authors without a row aren't verified
+#[derive(Identifiable, Queryable, Serialize, PartialEq, Debug)]
+#[table_name="authors"]
+#[primary_key(name)]
+pub struct Author {
+    pub name: String,
+    pub verified: bool,
+}
+
+impl Author {
+    /// Verify or unverify an author, this applies to all of their modules
+    pub fn set_verified(name: &str, verified: bool, connection: &PgConnection) -> Result<Author> {
+        validate_name("author", name)?;
+
+        diesel::insert_into(authors::table)
+            .values((
+                authors::name.eq(name),
+                authors::verified.eq(verified),
+            ))
+            .on_conflict(authors::name)
+            .do_update()
+            .set(authors::verified.eq(verified))
+            .get_result(connection)
+            .map_err(Error::from)
+    }
+
+    pub fn is_verified(name: &str, connection: &PgConnection) -> Result<bool> {
+        let verified = authors::table
+            .select(authors::verified)
+            .filter(authors::name.eq(name))
+            .first::<bool>(connection)
+            .optional()?;
+        Ok(verified.unwrap_or(false))
+    }
+
+    /// The verified authors among `names`
+    pub fn verified_among(names: &[&str], connection: &PgConnection) -> Result<Vec<String>> {
+        authors::table
+            .select(authors::name)
+            .filter(authors::name.eq_any(names))
+            .filter(authors::verified.eq(true))
+            .load::<String>(connection)
+            .map_err(Error::from)
+    }
+}
+
+/// A module name, or a prefix of module names, that only the listed owners
+/// can publish. A reservation without owners can't be used by anybody.
+#[derive(Identifiable, Queryable, Serialize, PartialEq, Debug)]
+#[table_name="reserved_names"]
+pub struct ReservedName {
+    pub id: i32,
+    pub name: String,
+    pub prefix: bool,
+    pub owners: Vec<String>,
+}
+
+impl ReservedName {
+    pub fn create(name: &str, prefix: bool, owners: &[String], connection: &PgConnection) -> Result<ReservedName> {
+        diesel::insert_into(reserved_names::table)
+            .values(&NewReservedName {
+                name,
+                prefix,
+                owners,
+            })
+            .get_result(connection)
+            .map_err(Error::from)
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        if self.prefix {
+            name.starts_with(&self.name)
+        } else {
+            name == self.name
+        }
+    }
+
+    /// Check if `author` may create a module called `name`, every reservation
+    /// that matches the name has to list the author as owner
+    pub fn is_allowed(name: &str, author: &str, connection: &PgConnection) -> Result<bool> {
+        let reservations = reserved_names::table
+            .filter(reserved_names::name.eq(name).or(reserved_names::prefix))
+            .load::<ReservedName>(connection)?;
+
+        Ok(reservations.iter()
+            .filter(|reservation| reservation.matches(name))
+            .all(|reservation| reservation.owners.iter().any(|owner| owner == author)))
+    }
+}
+
+#[derive(Insertable)]
+#[table_name="reserved_names"]
+pub struct NewReservedName<'a> {
+    name: &'a str,
+    prefix: bool,
+    owners: &'a [String],
+}
+
+/// Returned by `Module::update_or_create` if the module has been soft deleted
+#[derive(Debug, Fail, PartialEq)]
+#[fail(display="{}/{} has been deleted, restore it to publish new releases", author, name)]
+pub struct ModuleDeleted {
+    pub author: String,
+    pub name: String,
+}
+
+/// Returned by `Module::update_or_create` if a new module would use a
+/// reserved name
+#[derive(Debug, Fail, PartialEq)]
+#[fail(display="Module name {:?} is reserved", name)]
+pub struct NameReserved {
+    pub name: String,
+}
+
+/// Returned by `PublishEvent::check` if the author published too many
+/// releases within `PUBLISH_WINDOW`
+#[derive(Debug, Fail, PartialEq)]
+#[fail(display="Too many releases published, retry in {} seconds", retry_after)]
+pub struct RateLimited {
+    /// Seconds until the oldest publish in the window expires
+    pub retry_after: u64,
+}
+
+#[derive(Insertable)]
+#[table_name="publish_events"]
+pub struct NewPublishEvent<'a> {
+    author: &'a str,
+    created: SystemTime,
+}
+
+/// First key of the advisory lock that serializes the publishes of an author,
+/// the second key is the hash of the author
+const PUBLISH_LOCK: i32 = 0x7075_626c;
+
+/// Every publish of an author, for the publish rate limit
+pub struct PublishEvent;
+
+impl PublishEvent {
+    /// Fail with `RateLimited` if `author` already published `limit`
+    /// releases in the window before `now`. This locks the author until the
+    /// transaction ends, so concurrent publishes can't both pass the check
+    /// before either is recorded. Call it in the same transaction as `record`.
+    pub fn check(author: &str, now: SystemTime, limit: i64, connection: &PgConnection) -> Result<()> {
+        if limit == 0 {
+            return Ok(());
+        }
+
+        diesel::sql_query("SELECT pg_advisory_xact_lock($1, hashtext($2))")
+            .bind::<Integer, _>(PUBLISH_LOCK)
+            .bind::<Text, _>(author)
+            .execute(connection)?;
+
+        let recent = publish_events::table
+            .select(publish_events::created)
+            .filter(publish_events::author.eq(author))
+            .filter(publish_events::created.gt(now - PUBLISH_WINDOW))
+            .order(publish_events::created.desc())
+            .limit(limit)
+            .load::<SystemTime>(connection)?;
+
+        if (recent.len() as i64) < limit {
+            return Ok(());
+        }
+
+        // a slot frees up once the oldest of the last `limit` publishes leaves the window
+        let oldest = recent[recent.len() - 1];
+        let wait = (oldest + PUBLISH_WINDOW).duration_since(now)
+            .unwrap_or_else(|_| Duration::from_secs(0));
+        let retry_after = wait.as_secs() + if wait.subsec_nanos() > 0 { 1 } else { 0 };
+        Err(RateLimited {
+            retry_after: retry_after.max(1),
+        }.into())
+    }
+
+    pub fn record(author: &str, now: SystemTime, connection: &PgConnection) -> Result<()> {
+        diesel::insert_into(publish_events::table)
+            .values(&NewPublishEvent {
+                author,
+                created: now,
+            })
+            .execute(connection)?;
+        Ok(())
+    }
+
+    /// Events outside of the window aren't needed anymore
+    pub fn cleanup(now: SystemTime, connection: &PgConnection) -> Result<usize> {
+        diesel::delete(publish_events::table.filter(publish_events::created.le(now - PUBLISH_WINDOW)))
+            .execute(connection)
+            .map_err(Error::from)
+    }
+}
+
+/// Modules with this many open reports are hidden until they are reviewed
+pub const DEFAULT_REPORT_THRESHOLD: i64 = 3;
+
+/// Reasons are shown to admins as is, keep them reasonably short
+pub const MAX_REPORT_REASON_LEN: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModerationConfig {
+    /// Zero disables hiding modules automatically
+    pub hide_threshold: i64,
+}
+
+impl Default for ModerationConfig {
+    fn default() -> ModerationConfig {
+        ModerationConfig {
+            hide_threshold: DEFAULT_REPORT_THRESHOLD,
+        }
+    }
+}
+
+impl ModerationConfig {
+    /// Read REPORT_HIDE_THRESHOLD, the number of open reports that hide a module
+    pub fn from_env() -> Result<ModerationConfig> {
+        let hide_threshold = match env::var("REPORT_HIDE_THRESHOLD") {
+            Ok(x) => x.parse::<i64>()
+                .context("REPORT_HIDE_THRESHOLD is invalid")?,
+            Err(_) => DEFAULT_REPORT_THRESHOLD,
+        };
+
+        if hide_threshold < 0 {
+            bail!("REPORT_HIDE_THRESHOLD can not be negative");
+        }
+
+        Ok(ModerationConfig {
+            hide_threshold,
+        })
+    }
+
+    pub fn should_hide(&self, open_reports: i64) -> bool {
+        self.hide_threshold > 0 && open_reports >= self.hide_threshold
+    }
+}
+
+#[derive(Identifiable, Queryable, Serialize, PartialEq, Debug)]
+#[table_name="module_reports"]
+pub struct ModuleReport {
+    pub id: i32,
+    pub module_id: i32,
+    pub reporter: String,
+    pub reason: String,
+    #[serde(serialize_with="serialize_rfc3339")]
+    pub created: SystemTime,
+    pub resolved: bool,
+}
+
+/// An open report and the module it's about, for the admin listing
+#[derive(Serialize, PartialEq, Debug)]
+pub struct OpenReport {
+    pub module: Module,
+    pub hidden: bool,
+    pub report: ModuleReport,
+}
+
+impl ModuleReport {
+    /// Report a module, every user has a single report per module and
+    /// reporting again replaces the reason. The module is hidden once it has
+    /// too many open reports, returns true if the module is hidden.
+    pub fn submit(module: &Module, reporter: &str, reason: &str, config: &ModerationConfig, connection: &PgConnection) -> Result<bool> {
+        use diesel::pg::upsert::excluded;
+
+        connection.transaction::<_, Error, _>(|| {
+            diesel::insert_into(module_reports::table)
+                .values(&NewModuleReport {
+                    module_id: module.id,
+                    reporter,
+                    reason,
+                })
+                .on_conflict((module_reports::module_id, module_reports::reporter))
+                .do_update()
+                .set((
+                    module_reports::reason.eq(excluded(module_reports::reason)),
+                    module_reports::created.eq(excluded(module_reports::created)),
+                    module_reports::resolved.eq(false),
+                ))
+                .execute(connection)?;
+
+            if module.is_hidden(connection)? {
+                return Ok(true);
+            }
+
+            let open = ModuleReport::open(module.id, connection)?;
+            if config.should_hide(open) {
+                info!("Hiding {:?}/{:?} after {} reports", module.author, module.name, open);
+                module.hide(connection)?;
+                return Ok(true);
+            }
+
+            Ok(false)
+        })
+    }
+
+    /// The number of reports that haven't been reviewed yet
+    pub fn open(module_id: i32, connection: &PgConnection) -> Result<i64> {
+        module_reports::table
+            .filter(module_reports::module_id.eq(module_id))
+            .filter(module_reports::resolved.eq(false))
+            .count()
+            .get_result(connection)
+            .map_err(Error::from)
+    }
+
+    pub fn resolve(module_id: i32, connection: &PgConnection) -> Result<usize> {
+        diesel::update(module_reports::table
+                .filter(module_reports::module_id.eq(module_id))
+                .filter(module_reports::resolved.eq(false)))
+            .set(module_reports::resolved.eq(true))
+            .execute(connection)
+            .map_err(Error::from)
+    }
+
+    /// Open reports of all modules, newest first
+    pub fn list(limit: i64, connection: &PgConnection) -> Result<Vec<OpenReport>> {
+        let x = module_reports::table
+            .inner_join(modules::table)
+            .filter(module_reports::resolved.eq(false))
+            .select((ALL_MODULE_COLUMNS, modules::hidden, module_reports::all_columns))
+            .order_by(module_reports::id.desc())
+            .limit(limit)
+            .load::<(Module, bool, ModuleReport)>(connection)?;
+
+        Ok(x.into_iter().map(|(module, hidden, report)| OpenReport {
+            module,
+            hidden,
+            report,
+        }).collect())
+    }
+}
+
+#[derive(Insertable)]
+#[table_name="module_reports"]
+pub struct NewModuleReport<'a> {
+    module_id: i32,
+    reporter: &'a str,
+    reason: &'a str,
+}
+
+/// Incomplete uploads are discarded after this time
+pub const UPLOAD_SESSION_TIMEOUT: Duration = Duration::from_secs(24 * 3600);
+
+/// Parts that are uploaded again replace the previous upload
+const UPSERT_UPLOAD_PART: &str = "INSERT INTO upload_parts (session_id, idx, data)
+VALUES ($1, $2, $3)
+ON CONFLICT (session_id, idx) DO UPDATE SET data = excluded.data";
+
+/// Returned by `UploadSession::assemble` if the upload can't be turned into a release yet
+#[derive(Debug, Fail, PartialEq)]
+pub enum UploadRejected {
+    #[fail(display="Upload is incomplete, missing parts: {:?}", _0)]
+    Incomplete(Vec<i32>),
+    #[fail(display="Checksum of the uploaded code doesn't match")]
+    ChecksumMismatch,
+}
+
+fn missing_parts(parts: i32, received: &[i32]) -> Vec<i32> {
+    (0..parts)
+        .filter(|idx| !received.contains(idx))
+        .collect()
+}
+
+/// `received` has to be sorted by index
+fn assemble_parts(parts: i32, expected: &str, received: Vec<(i32, String)>) -> Result<String> {
+    let idxs = received.iter()
+        .map(|(idx, _)| *idx)
+        .collect::<Vec<_>>();
+    let missing = missing_parts(parts, &idxs);
+    if !missing.is_empty() {
+        return Err(UploadRejected::Incomplete(missing).into());
+    }
+
+    let code = received.into_iter()
+        .map(|(_, data)| data)
+        .collect::<String>();
+    if checksum(&code) != expected {
+        return Err(UploadRejected::ChecksumMismatch.into());
+    }
+
+    Ok(code)
+}
+
+#[derive(Identifiable, Queryable, Serialize, PartialEq, Debug)]
+#[table_name="upload_sessions"]
+pub struct UploadSession {
+    pub id: i32,
+    pub author: String,
+    pub name: String,
+    pub checksum: String,
+    pub parts: i32,
+    pub allow_unchanged: bool,
+    pub created: SystemTime,
+    pub prerelease: bool,
+    pub skip_syntax_check: bool,
+}
+
+impl UploadSession {
+    pub fn create(session: &NewUploadSession, connection: &PgConnection) -> Result<UploadSession> {
+        diesel::insert_into(upload_sessions::table)
+            .values(session)
+            .get_result(connection)
+            .map_err(Error::from)
+    }
+
+    /// Sessions of other authors and expired sessions are not returned
+    pub fn find_opt(id: i32, author: &str, name: &str, connection: &PgConnection) -> Result<Option<UploadSession>> {
+        upload_sessions::table
+            .filter(upload_sessions::id.eq(id))
+            .filter(upload_sessions::author.eq(author))
+            .filter(upload_sessions::name.eq(name))
+            .filter(upload_sessions::created.gt(SystemTime::now() - UPLOAD_SESSION_TIMEOUT))
+            .first::<UploadSession>(connection)
+            .optional()
+            .map_err(Error::from)
+    }
+
+    pub fn add_part(&self, idx: i32, data: &str, connection: &PgConnection) -> Result<()> {
+        if idx < 0 || idx >= self.parts {
+            bail!("Part {} is out of range, upload has {} parts", idx, self.parts);
+        }
+
+        diesel::sql_query(UPSERT_UPLOAD_PART)
+            .bind::<Integer, _>(self.id)
+            .bind::<Integer, _>(idx)
+            .bind::<Text, _>(data)
+            .execute(connection)?;
+        Ok(())
+    }
+
+    pub fn missing(&self, connection: &PgConnection) -> Result<Vec<i32>> {
+        let received = upload_parts::table
+            .select(upload_parts::idx)
+            .filter(upload_parts::session_id.eq(self.id))
+            .load::<i32>(connection)?;
+        Ok(missing_parts(self.parts, &received))
+    }
+
+    /// Concat all parts and verify the checksum of the result
+    pub fn assemble(&self, connection: &PgConnection) -> Result<String> {
+        let received = upload_parts::table
+            .select((upload_parts::idx, upload_parts::data))
+            .filter(upload_parts::session_id.eq(self.id))
+            .order(upload_parts::idx.asc())
+            .load::<(i32, String)>(connection)?;
+        assemble_parts(self.parts, &self.checksum, received)
+    }
+
+    pub fn delete(&self, connection: &PgConnection) -> Result<()> {
+        diesel::delete(upload_sessions::table.find(self.id))
+            .execute(connection)?;
+        Ok(())
+    }
+
+    /// Delete sessions that haven't been finalized in time, including their parts
+    pub fn expire(connection: &PgConnection) -> Result<usize> {
+        diesel::delete(upload_sessions::table
+                .filter(upload_sessions::created.le(SystemTime::now() - UPLOAD_SESSION_TIMEOUT)))
+            .execute(connection)
+            .map_err(Error::from)
+    }
+}
+
+#[derive(Insertable)]
+#[table_name="upload_sessions"]
+pub struct NewUploadSession<'a> {
+    pub author: &'a str,
+    pub name: &'a str,
+    pub checksum: &'a str,
+    pub parts: i32,
+    pub allow_unchanged: bool,
+    pub prerelease: bool,
+    pub skip_syntax_check: bool,
+}
+
+/// Failed deliveries are resolved once the same payload has been delivered to
+/// the same url, either by a later retry or by a replay
+const UNRESOLVED_DELIVERY: &str = "NOT EXISTS (SELECT 1 FROM webhook_deliveries AS r WHERE r.url = webhook_deliveries.url AND r.payload = webhook_deliveries.payload AND r.delivered)";
+
+/// Every attempt to deliver a webhook, with the payload as it has been signed
+#[derive(Identifiable, Queryable, Serialize, PartialEq, Debug)]
+#[table_name="webhook_deliveries"]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub url: String,
+    pub payload: String,
+    pub signature: Option<String>,
+    pub attempt: i32,
+    pub status: Option<i32>,
+    pub response: Option<String>,
+    pub delivered: bool,
+    pub replay_of: Option<i32>,
+    #[serde(serialize_with="serialize_rfc3339")]
+    pub created: SystemTime,
+}
+
+impl WebhookDelivery {
+    pub fn create(delivery: &NewWebhookDelivery, connection: &PgConnection) -> Result<WebhookDelivery> {
+        diesel::insert_into(webhook_deliveries::table)
+            .values(delivery)
+            .get_result(connection)
+            .map_err(Error::from)
+    }
+
+    pub fn find_opt(id: i32, connection: &PgConnection) -> Result<Option<WebhookDelivery>> {
+        webhook_deliveries::table.find(id)
+            .first::<WebhookDelivery>(connection)
+            .optional()
+            .map_err(Error::from)
+    }
+
+    /// The most recent deliveries first, optionally only failed deliveries
+    /// that haven't been resolved yet
+    pub fn list(failed: bool, limit: i64, connection: &PgConnection) -> Result<Vec<WebhookDelivery>> {
+        let mut query = webhook_deliveries::table
+            .order(webhook_deliveries::id.desc())
+            .limit(limit)
+            .into_boxed();
+
+        if failed {
+            query = query
+                .filter(webhook_deliveries::delivered.eq(false))
+                .filter(diesel::dsl::sql::<Bool>(UNRESOLVED_DELIVERY));
+        }
+
+        query.load::<WebhookDelivery>(connection)
+            .map_err(Error::from)
+    }
+}
+
+#[derive(Insertable, Debug)]
+#[table_name="webhook_deliveries"]
+pub struct NewWebhookDelivery<'a> {
+    pub url: &'a str,
+    pub payload: &'a str,
+    pub signature: Option<&'a str>,
+    pub attempt: i32,
+    pub status: Option<i32>,
+    pub response: Option<&'a str>,
+    pub delivered: bool,
+    pub replay_of: Option<i32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::tests::with_test_db;
+    use sn0int_common::metadata::Capability;
+    use std::time::UNIX_EPOCH;
+
+    fn release(version: &str, code: &str) -> Release {
+        Release {
+            id: 1,
+            module_id: 1,
+            version: version.to_string(),
+            downloads: 0,
+            code: code.to_string(),
+            published: SystemTime::now(),
+            sha256: checksum(code),
+            manifest: None,
+            prerelease: false,
+            readme: None,
+            yanked: false,
+        }
+    }
+
+    #[test]
+    fn test_validate_name() {
+        for name in &["kpcyrd", "ctlogs", "url-scan", "dns_bruteforce", "0day", "a", &"a".repeat(32)] {
+            assert_eq!(validate_name("name", name), Ok(()), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_validate_name_rejected() {
+        for name in &["", "-foo", "_foo", "Foo", "fooBar", "foo.bar", "foo/bar", "foo bar", "ünicode", &"a".repeat(33)] {
+            assert!(validate_name("name", name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_validate_name_field() {
+        let err = validate_name("author", "-kpcyrd").unwrap_err();
+        assert_eq!(err.field, "author");
+        assert!(err.to_string().starts_with("Invalid author: \"-kpcyrd\""));
+    }
+
+    #[test]
+    fn test_highest_version() {
+        let releases = vec![
+            (1, "0.9.0".to_string()),
+            (2, "1.0.0".to_string()),
+            (3, "0.9.1".to_string()),
+            (4, "1.0.0-rc.1".to_string()),
+            (5, "1.0".to_string()),
+        ];
+        assert_eq!(highest_version(&releases), Some(2));
+        assert_eq!(highest_version(&releases[2..]), Some(4));
+        assert_eq!(highest_version(&releases[4..]), Some(5));
+        assert_eq!(highest_version(&[]), None);
+    }
+
+    #[test]
+    fn test_invalid_version() {
+        assert!(ensure_version("0.1.0").is_ok());
+
+        let err = ensure_version("1.0").unwrap_err();
+        let err = err.downcast_ref::<InvalidVersion>().unwrap();
+        assert_eq!(err.version, "1.0");
+        assert!(err.to_string().starts_with("Version \"1.0\" is not a valid semver version"));
+    }
+
+    #[test]
+    fn test_code_size_limit() {
+        assert!(ensure_code_size(&"A".repeat(1024), 1024).is_ok());
+
+        let err = ensure_code_size(&"A".repeat(1025), 1024).unwrap_err();
+        let err = err.downcast_ref::<CodeTooLarge>().unwrap();
+        assert_eq!(*err, CodeTooLarge {
+            size: 1025,
+            limit: 1024,
+        });
+        assert!(err.to_string().contains("at most 1024 bytes"));
+    }
+
+    #[test]
+    fn test_manifest() {
+        let manifest = parse_manifest("-- Description: Hello world\n-- Version: 0.1.0\n-- License: MIT\n-- Capabilities: network\n\nfunction run() end\n").unwrap();
+        assert_eq!(manifest.description, "Hello world");
+        assert_eq!(manifest.version, "0.1.0");
+        assert_eq!(manifest.license, "MIT");
+        assert_eq!(manifest.capabilities, Some(vec![Capability::Network]));
+
+        let encoded = encode_manifest("-- Description: Hello world\n-- Version: 0.1.0\n-- License: MIT\n\n").unwrap();
+        assert!(!encoded.contains("function"));
+        assert!(parse_manifest("function run() end\n").is_err());
+    }
+
+    fn module(author: &str, name: &str) -> Module {
+        Module {
+            id: 1,
+            author: author.to_string(),
+            name: name.to_string(),
+            description: String::new(),
+            latest: Some("0.1.0".to_string()),
+            featured: false,
+            deprecated: false,
+            deprecated_msg: None,
+            successor_id: None,
+            deleted_at: None,
+        }
+    }
+
+    #[test]
+    fn test_deprecation_msg() {
+        assert!(ensure_deprecation_msg(None).is_ok());
+        assert!(ensure_deprecation_msg(Some("Use kpcyrd/ctlogs2 instead")).is_ok());
+        let err = ensure_deprecation_msg(Some(&"x".repeat(MAX_DEPRECATION_MSG_LEN + 1))).unwrap_err();
+        assert_eq!(err.downcast_ref::<InvalidDeprecation>(), Some(&InvalidDeprecation::MessageTooLong(MAX_DEPRECATION_MSG_LEN)));
+    }
+
+    #[test]
+    fn test_transfer_accepted() {
+        assert!(ensure_transferable(&module("kpcyrd", "ctlogs"), "someone-else").is_ok());
+    }
+
+    #[test]
+    fn test_transfer_rejected() {
+        let module = module("kpcyrd", "ctlogs");
+
+        let err = ensure_transferable(&module, "kpcyrd").unwrap_err();
+        assert_eq!(err.downcast_ref::<TransferRejected>(), Some(&TransferRejected::SameAuthor("kpcyrd".to_string())));
+
+        let err = ensure_transferable(&module, "Not/Valid").unwrap_err();
+        assert_eq!(err.downcast_ref::<InvalidName>().map(|x| x.field), Some("author"));
+    }
+
+    #[test]
+    fn test_select_requested() {
+        let ids = vec![
+            "kpcyrd/ctlogs".parse::<ModuleID>().unwrap(),
+            "someone/url-scan".parse::<ModuleID>().unwrap(),
+        ];
+        let candidates = vec![
+            module("kpcyrd", "ctlogs"),
+            module("kpcyrd", "url-scan"),
+            module("someone", "ctlogs"),
+            module("someone", "url-scan"),
+        ];
+
+        let selected = select_requested(candidates, &ids);
+        assert_eq!(selected, vec![module("kpcyrd", "ctlogs"), module("someone", "url-scan")]);
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_transfer_ownership() {
+        with_test_db(|connection| {
+            let code = "-- Description: Transfer\n-- Version: 0.1.0\n-- License: MIT\n\nfunction run() end\n";
+            let module = Module::update_or_create("transfer-old", "transfer-test", "Transfer", connection)?;
+            module.add_version("0.1.0", code, &PublishOptions::default(), connection)?;
+            Release::find(module.id, "0.1.0", connection)?.bump_downloads(connection)?;
+
+            // the new author already has a module with that name
+            let taken = Module::update_or_create("transfer-taken", "transfer-test", "Transfer", connection)?;
+            let err = module.transfer_ownership(&taken.author, connection).unwrap_err();
+            assert_eq!(err.downcast_ref::<TransferRejected>(), Some(&TransferRejected::NameTaken("transfer-taken".to_string(), "transfer-test".to_string())));
+            assert_eq!(Module::id(module.id, connection)?.author, "transfer-old");
+
+            module.transfer_ownership("transfer-new", connection)?;
+            assert!(Module::find_opt("transfer-old", "transfer-test", connection)?.is_none());
+            let moved = Module::find("transfer-new", "transfer-test", connection)?;
+            assert_eq!(moved.id, module.id);
+            assert_eq!(moved.latest.as_deref(), Some("0.1.0"));
+            assert_eq!(ModuleTransfer::moved("transfer-old", "transfer-test", connection)?, Some(moved));
+
+            // releases and their downloads stay attached to the module
+            let release = Release::find(module.id, "0.1.0", connection)?;
+            assert_eq!(release.downloads, 1);
+            Ok(())
+        });
+    }
+
+    fn reservation(name: &str, prefix: bool, owners: &[&str]) -> ReservedName {
+        ReservedName {
+            id: 1,
+            name: name.to_string(),
+            prefix,
+            owners: owners.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_reserved_name_matches() {
+        assert!(reservation("sn0int", true, &[]).matches("sn0int-ctlogs"));
+        assert!(reservation("sn0int", true, &[]).matches("sn0int"));
+        assert!(!reservation("sn0int", true, &[]).matches("my-sn0int"));
+        assert!(reservation("ctlogs", false, &[]).matches("ctlogs"));
+        assert!(!reservation("ctlogs", false, &[]).matches("ctlogs2"));
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_reserved_names() {
+        with_test_db(|connection| {
+            // seeded by the migration
+            assert!(!ReservedName::is_allowed("sn0int-core", "squatter", connection)?);
+            assert!(ReservedName::is_allowed("sn0int-core", "kpcyrd", connection)?);
+
+            ReservedName::create("wombat-reserved", false, &["alice".to_string()], connection)?;
+            assert!(ReservedName::is_allowed("wombat-reserved", "alice", connection)?);
+            assert!(!ReservedName::is_allowed("wombat-reserved", "bob", connection)?);
+            assert!(ReservedName::is_allowed("wombat-reserved2", "bob", connection)?);
+
+            let err = Module::update_or_create("bob", "wombat-reserved", "Squatting", connection).unwrap_err();
+            assert_eq!(err.downcast_ref::<NameReserved>(), Some(&NameReserved {
+                name: "wombat-reserved".to_string(),
+            }));
+            assert!(Module::find_opt("bob", "wombat-reserved", connection)?.is_none());
+
+            let module = Module::update_or_create("alice", "wombat-reserved", "Reserved", connection)?;
+            assert_eq!(module.author, "alice");
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_find_with_stats() {
+        with_test_db(|connection| {
+            let module = Module::update_or_create("stats-author", "with-stats", "Stats", connection)?;
+            // modules without releases don't fail the join
+            let stats = Module::find_with_stats("stats-author", "with-stats", connection)?;
+            assert_eq!(stats.module, module);
+            assert_eq!((stats.total_downloads, stats.num_releases), (0, 0));
+
+            module.add_version("0.1.0", "-- Description: Stats\n-- Version: 0.1.0\n-- License: MIT\n\nfunction run() end\n", &PublishOptions::default(), connection)?;
+            module.add_version("0.2.0", "-- Description: Stats\n-- Version: 0.2.0\n-- License: MIT\n\nfunction run() end\n", &PublishOptions::default(), connection)?;
+            Release::find(module.id, "0.1.0", connection)?.bump_downloads(connection)?;
+            Release::find(module.id, "0.2.0", connection)?.bump_downloads(connection)?;
+            Release::find(module.id, "0.2.0", connection)?.bump_downloads(connection)?;
+
+            let stats = Module::id_with_stats(module.id, connection)?;
+            assert_eq!(stats.module.latest.as_deref(), Some("0.2.0"));
+            assert_eq!((stats.total_downloads, stats.num_releases), (3, 2));
+            assert_eq!(Module::find_with_stats("stats-author", "with-stats", connection)?, stats);
+
+            assert!(Module::find_with_stats("stats-author", "missing", connection).is_err());
+
+            let module = Module::find("stats-author", "with-stats", connection)?;
+            module.soft_delete(connection)?;
+            assert!(Module::find_with_stats("stats-author", "with-stats", connection).is_err());
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_verified_authors() {
+        with_test_db(|connection| {
+            for (author, name) in &[("verified-author", "quokka-a"), ("verified-author", "quokka-b"), ("unverified-author", "quokka-c")] {
+                let module = Module::update_or_create(author, name, "Query the quokka api", connection)?;
+                module.add_version("0.1.0", "-- Description: Query the quokka api\n-- Version: 0.1.0\n-- License: MIT\n\nfunction run() end\n", &PublishOptions::default(), connection)?;
+            }
+            let search = |verified_only| -> Result<Vec<String>> {
+                let mut names = Module::search("quokka", None, false, verified_only, connection)?
+                    .into_iter()
+                    .map(|(x, _)| x.name)
+                    .collect::<Vec<_>>();
+                names.sort();
+                Ok(names)
+            };
+
+            assert!(!Author::is_verified("verified-author", connection)?);
+            assert_eq!(search(true)?, Vec::<String>::new());
+
+            let author = Author::set_verified("verified-author", true, connection)?;
+            assert!(author.verified);
+            assert!(Author::is_verified("verified-author", connection)?);
+            assert_eq!(Author::verified_among(&["verified-author", "unverified-author"], connection)?, vec!["verified-author"]);
+            assert_eq!(search(true)?, vec!["quokka-a", "quokka-b"]);
+            // the join doesn't drop authors without a row
+            assert_eq!(search(false)?, vec!["quokka-a", "quokka-b", "quokka-c"]);
+
+            Author::set_verified("verified-author", false, connection)?;
+            assert_eq!(search(true)?, Vec::<String>::new());
+
+            assert!(Author::set_verified("Not Valid", true, connection).is_err());
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_release_dependencies() {
+        with_test_db(|connection| {
+            let base = Module::update_or_create("deps-author", "wombat-base", "Wombat base", connection)?;
+            let module = Module::update_or_create("deps-author", "wombat-ext", "Wombat ext", connection)?;
+            let code = |depends: &str| format!("-- Description: Wombat ext\n-- Version: 0.1.0\n-- License: MIT\n{}\nfunction run() end\n", depends);
+
+            let err = module.add_version("0.1.0", &code("-- Depends: deps-author/wombat-base\n-- Depends: deps-author/missing\n-- Depends: other/missing ^1\n"), &PublishOptions::default(), connection).unwrap_err();
+            assert_eq!(err.downcast_ref::<InvalidDependency>(), Some(&InvalidDependency::Unknown("deps-author/missing, other/missing".to_string())));
+            let err = module.add_version("0.1.0", &code("-- Depends: deps-author/wombat-base latest\n"), &PublishOptions::default(), connection).unwrap_err();
+            assert_eq!(err.downcast_ref::<InvalidDependency>(), Some(&InvalidDependency::VersionReq("deps-author/wombat-base".to_string(), "latest".to_string())));
+            assert!(Release::try_find(module.id, "0.1.0", connection)?.is_none());
+
+            // the base module has no release that matches yet
+            module.add_version("0.1.0", &code("-- Depends: deps-author/wombat-base ^0.3\n-- Depends: deps-author/wombat-ext\n"), &PublishOptions::default(), connection)?;
+            let release = Release::find(module.id, "0.1.0", connection)?;
+            assert_eq!(release.dependencies(connection)?, vec![
+                Dependency {
+                    author: "deps-author".to_string(),
+                    name: "wombat-base".to_string(),
+                    version_req: "^0.3".to_string(),
+                },
+                Dependency {
+                    author: "deps-author".to_string(),
+                    name: "wombat-ext".to_string(),
+                    version_req: "*".to_string(),
+                },
+            ]);
+
+            base.add_version("0.1.0", &code(""), &PublishOptions::default(), connection)?;
+            assert_eq!(Release::find(base.id, "0.1.0", connection)?.dependencies(connection)?, vec![]);
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_module_manifest() {
+        with_test_db(|connection| {
+            let module = Module::update_or_create("manifest-author", "pangolin", "Pangolin", connection)?;
+            // modules without releases have an empty list instead of failing
+            let manifest = Module::manifest("manifest-author", "pangolin", connection)?.unwrap();
+            assert_eq!(manifest.module, module);
+            assert!(manifest.releases.is_empty());
+
+            let code = |version: &str| format!("-- Description: Pangolin\n-- Version: {}\n-- License: MIT\n\nfunction run() end\n", version);
+            for version in &["0.1.0", "0.2.0", "0.3.0"] {
+                module.add_version(version, &code(version), &PublishOptions::default(), connection)?;
+            }
+            Release::find(module.id, "0.3.0", connection)?.set_yanked(true, connection)?;
+            Release::find(module.id, "0.1.0", connection)?.bump_downloads(connection)?;
+
+            let manifest = Module::manifest("manifest-author", "pangolin", connection)?.unwrap();
+            assert_eq!(manifest.module.latest.as_deref(), Some("0.2.0"));
+            let releases = manifest.releases.iter()
+                .map(|x| (x.version.as_str(), x.downloads, x.sha256.clone()))
+                .collect::<Vec<_>>();
+            assert_eq!(releases, vec![
+                ("0.2.0", 0, checksum(&code("0.2.0"))),
+                ("0.1.0", 1, checksum(&code("0.1.0"))),
+            ]);
+
+            assert_eq!(Module::manifest("manifest-author", "missing", connection)?, None);
+            module.soft_delete(connection)?;
+            assert_eq!(Module::manifest("manifest-author", "pangolin", connection)?, None);
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_soft_delete() {
+        with_test_db(|connection| {
+            let module = Module::update_or_create("delete-author", "platypus-delete", "Query the platypus api", connection)?;
+            module.add_version("0.1.0", "-- Description: Query the platypus api\n-- Version: 0.1.0\n-- License: MIT\n\nfunction run() end\n", &PublishOptions::default(), connection)?;
+            diesel::update(modules::table.find(module.id))
+                .set(modules::featured.eq(true))
+                .execute(connection)?;
+            let listed = || -> Result<(bool, bool)> {
+                let searched = Module::search("platypus", None, false, false, connection)?
+                    .iter()
+                    .any(|(x, _)| x.id == module.id);
+                let quickstart = Module::quickstart(connection)?
+                    .iter()
+                    .any(|x| x.id == module.id);
+                Ok((searched, quickstart))
+            };
+            assert_eq!(listed()?, (true, true));
+
+            let deleted = module.soft_delete(connection)?;
+            assert!(deleted.deleted_at.is_some());
+            assert_eq!(listed()?, (false, false));
+            assert!(Module::find("delete-author", "platypus-delete", connection).is_err());
+            // find_opt still tells deleted modules apart from missing ones
+            assert!(Module::find_opt("delete-author", "platypus-delete", connection)?.unwrap().deleted_at.is_some());
+            assert!(Module::find_opt("delete-author", "platypus-missing", connection)?.is_none());
+
+            let err = Module::update_or_create("delete-author", "platypus-delete", "Query the platypus api", connection).unwrap_err();
+            assert!(err.downcast_ref::<ModuleDeleted>().is_some());
+
+            let restored = deleted.restore(connection)?;
+            assert_eq!(restored.deleted_at, None);
+            assert_eq!(listed()?, (true, true));
+            assert_eq!(Release::find(module.id, "0.1.0", connection)?.version, "0.1.0");
+
+            // modules are only purged after the grace period
+            restored.soft_delete(connection)?;
+            Module::purge_expired(1, connection)?;
+            assert!(Module::find_opt("delete-author", "platypus-delete", connection)?.is_some());
+
+            diesel::update(modules::table.find(module.id))
+                .set(modules::deleted_at.eq(SystemTime::now() - Duration::from_secs(2 * 24 * 3600)))
+                .execute(connection)?;
+            assert!(Module::purge_expired(1, connection)? >= 1);
+            assert!(Module::find_opt("delete-author", "platypus-delete", connection)?.is_none());
+            assert!(Release::try_find(module.id, "0.1.0", connection)?.is_none());
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_publish_rate_limit() {
+        with_test_db(|connection| {
+            let start = SystemTime::now();
+            let minute = Duration::from_secs(60);
+
+            // one publish per minute, the limit is hit with the 4th
+            for i in 0..3 {
+                let now = start + minute * i;
+                PublishEvent::check("burst-author", now, 3, connection)?;
+                PublishEvent::record("burst-author", now, connection)?;
+            }
+
+            // the author is locked until the transaction ends
+            let other = PgConnection::establish(&env::var("DATABASE_URL")?)?;
+            let locked = |author: &str| diesel::select(diesel::dsl::sql::<Bool>("pg_try_advisory_xact_lock(")
+                    .bind::<Integer, _>(PUBLISH_LOCK)
+                    .sql(", hashtext(")
+                    .bind::<Text, _>(author)
+                    .sql("))"))
+                .get_result::<bool>(&other)
+                .map(|acquired| !acquired);
+            assert!(locked("burst-author")?);
+            assert!(!locked("other-author")?);
+            let now = start + minute * 3;
+            let err = PublishEvent::check("burst-author", now, 3, connection).unwrap_err();
+            assert_eq!(err.downcast_ref::<RateLimited>(), Some(&RateLimited {
+                retry_after: PUBLISH_WINDOW.as_secs() - 180,
+            }));
+
+            // other authors have their own limit, zero disables it
+            PublishEvent::check("other-author", now, 3, connection)?;
+            PublishEvent::check("burst-author", now, 0, connection)?;
+
+            // the first publish leaves the window
+            PublishEvent::check("burst-author", start + PUBLISH_WINDOW, 3, connection)?;
+
+            assert!(PublishEvent::cleanup(start + PUBLISH_WINDOW, connection)? >= 1);
+            PublishEvent::check("burst-author", now, 3, connection)?;
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_reindex() {
+        with_test_db(|connection| {
+            let module = Module::update_or_create("reindex-author", "reindex-test", "Query the axolotl api", connection)?;
+            module.add_version("0.1.0", "-- Description: Query the axolotl api\n-- Version: 0.1.0\n-- License: MIT\n\nfunction run() end\n", &PublishOptions::default(), connection)?;
+            let found = || -> Result<bool> {
+                let modules = Module::search("axolotl", None, false, false, connection)?;
+                Ok(modules.iter().any(|(x, _)| x.id == module.id))
+            };
+            // the trigger doesn't touch the vector if only the vector changes
+            let clear = || diesel::sql_query("UPDATE modules SET search_vector = ''::tsvector WHERE id = $1")
+                .bind::<Integer, _>(module.id)
+                .execute(connection);
+
+            assert!(found()?);
+            clear()?;
+            assert!(!found()?);
+            module.reindex(connection)?;
+            assert!(found()?);
+
+            clear()?;
+            assert!(Module::reindex_all(connection)? >= 1);
+            assert!(found()?);
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_search_vector_updates() {
+        with_test_db(|connection| {
+            let found = |query: &str| -> Result<bool> {
+                let modules = Module::search(query, None, false, false, connection)?;
+                Ok(modules.iter().any(|(module, _)| module.name == "vector-test"))
+            };
+
+            let module = Module::update_or_create("someone", "vector-test", "Query the xylophone api", connection)?;
+            module.add_version("0.1.0", "-- Description: Query the xylophone api\n-- Version: 0.1.0\n-- License: MIT\n\nfunction run() end\n", &PublishOptions::default(), connection)?;
+            assert!(found("xylophone")?);
+
+            let module = Module::update_or_create("someone", "vector-test", "Query the zeppelin api", connection)?;
+            assert!(found("zeppelin")?);
+            assert!(!found("xylophone")?);
+
+            module.transfer_ownership("quokka", connection)?;
+            assert!(found("quokka")?);
+            assert!(!found("someone")?);
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_search_filters() {
+        with_test_db(|connection| {
+            let code = "-- Description: Query the wombat api\n-- Version: 0.1.0\n-- License: MIT\n\nfunction run() end\n";
+            for (author, name) in &[("filter-a", "wombat-one"), ("filter-a", "wombat-two"), ("filter-b", "wombat-three")] {
+                let module = Module::update_or_create(author, name, "Query the wombat api", connection)?;
+                module.add_version("0.1.0", code, &PublishOptions { allow_unchanged: true, ..Default::default() }, connection)?;
+            }
+            let module = Module::find("filter-a", "wombat-two", connection)?;
+            Release::find(module.id, "0.1.0", connection)?.bump_downloads(connection)?;
+            diesel::update(modules::table.find(module.id))
+                .set(modules::featured.eq(true))
+                .execute(connection)?;
+            Module::update_or_create("filter-a", "wombat-unpublished", "Query the wombat api", connection)?;
+
+            let names = |query: &str, author: Option<&str>, featured_only: bool| -> Result<Vec<String>> {
+                Ok(Module::search(query, author, featured_only, false, connection)?
+                    .into_iter()
+                    .map(|(module, _)| module.name)
+                    .filter(|name| name.starts_with("wombat"))
+                    .collect())
+            };
+
+            let mut all = names("wombat", None, false)?;
+            assert_eq!(all.remove(0), "wombat-two");
+            all.sort();
+            assert_eq!(all, vec!["wombat-one", "wombat-three", "wombat-unpublished"]);
+
+            let mut by_author = names("wombat", Some("filter-a"), false)?;
+            by_author.sort();
+            assert_eq!(by_author, vec!["wombat-one", "wombat-two", "wombat-unpublished"]);
+            assert_eq!(names("wombat", None, true)?, vec!["wombat-two"]);
+            assert_eq!(names("wombat", Some("filter-b"), true)?, Vec::<String>::new());
+
+            // without a query the modules of the author are sorted by downloads
+            let by_downloads = names("", Some("filter-a"), false)?;
+            assert_eq!(by_downloads[0], "wombat-two");
+            assert_eq!(by_downloads.len(), 3);
+            assert!(names("  ", None, false)?.is_empty());
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_deprecate() {
+        with_test_db(|connection| {
+            let code = "-- Description: Query the numbat api\n-- Version: 0.1.0\n-- License: MIT\n\nfunction run() end\n";
+            for name in &["numbat-old", "numbat-new"] {
+                let module = Module::update_or_create("deprecate-author", name, "Query the numbat api", connection)?;
+                module.add_version("0.1.0", code, &PublishOptions { allow_unchanged: true, ..Default::default() }, connection)?;
+            }
+            let old = Module::find("deprecate-author", "numbat-old", connection)?;
+            let new = Module::find("deprecate-author", "numbat-new", connection)?;
+            // the deprecated module has more downloads but is still ranked last
+            Release::find(old.id, "0.1.0", connection)?.bump_downloads(connection)?;
+
+            let err = old.deprecate(None, Some(old.id), connection).unwrap_err();
+            assert_eq!(err.downcast_ref::<InvalidDeprecation>(), Some(&InvalidDeprecation::OwnSuccessor));
+
+            let old = old.deprecate(Some("Use numbat-new"), Some(new.id), connection)?;
+            assert!(old.deprecated);
+            assert_eq!(old.deprecated_msg.as_deref(), Some("Use numbat-new"));
+            assert_eq!(old.successor_id, Some(new.id));
+
+            let names = Module::search("numbat", Some("deprecate-author"), false, false, connection)?
+                .into_iter()
+                .map(|(module, _)| module.name)
+                .collect::<Vec<_>>();
+            assert_eq!(names, vec!["numbat-new", "numbat-old"]);
+
+            // deprecated modules can still be installed
+            assert!(Release::find(old.id, "0.1.0", connection).is_ok());
+
+            let old = old.undeprecate(connection)?;
+            assert!(!old.deprecated);
+            assert_eq!(old.deprecated_msg, None);
+            assert_eq!(old.successor_id, None);
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_trending() {
+        with_test_db(|connection| {
+            let code = "-- Description: Trending\n-- Version: 0.1.0\n-- License: MIT\n\nfunction run() end\n";
+            let mut ids = Vec::new();
+            for name in &["trending-new", "trending-old", "trending-idle"] {
+                let module = Module::update_or_create("trending-author", name, "Trending", connection)?;
+                module.add_version("0.1.0", code, &PublishOptions { allow_unchanged: true, ..Default::default() }, connection)?;
+                ids.push(module.id);
+            }
+
+            // downloads of today are counted
+            let release = Release::find(ids[0], "0.1.0", connection)?;
+            for _ in 0..2 {
+                release.bump_downloads(connection)?;
+            }
+            let old = Release::find(ids[1], "0.1.0", connection)?;
+            let idle = Release::find(ids[2], "0.1.0", connection)?;
+
+            // the first day of the window is included, the day before isn't
+            diesel::sql_query("INSERT INTO download_events (release_id, day, count) VALUES
+                ($1, (NOW() AT TIME ZONE 'UTC')::DATE - 6, 1),
+                ($1, (NOW() AT TIME ZONE 'UTC')::DATE - 7, 100),
+                ($2, (NOW() AT TIME ZONE 'UTC')::DATE - 30, 1000),
+                ($3, (NOW() AT TIME ZONE 'UTC')::DATE, 0)")
+                .bind::<Integer, _>(release.id)
+                .bind::<Integer, _>(old.id)
+                .bind::<Integer, _>(idle.id)
+                .execute(connection)?;
+
+            let trending = |days: i64| -> Result<Vec<(String, i64)>> {
+                Ok(Module::trending(days, 100, connection)?
+                    .into_iter()
+                    .filter(|(module, _)| module.author == "trending-author")
+                    .map(|(module, downloads)| (module.name, downloads))
+                    .collect())
+            };
+
+            assert_eq!(trending(1)?, vec![("trending-new".to_string(), 2)]);
+            assert_eq!(trending(7)?, vec![("trending-new".to_string(), 3)]);
+            assert_eq!(trending(31)?, vec![
+                ("trending-old".to_string(), 1000),
+                ("trending-new".to_string(), 103),
+            ]);
+            assert!(Module::trending(0, 100, connection).is_err());
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_top_per_author() {
+        with_test_db(|connection| {
+            let code = "-- Description: Top\n-- Version: 0.1.0\n-- License: MIT\n\nfunction run() end\n";
+            let publish = |author: &str, name: &str, downloads: i64| -> Result<Module> {
+                let module = Module::update_or_create(author, name, "Top", connection)?;
+                module.add_version("0.1.0", code, &PublishOptions::default(), connection)?;
+                let release = Release::find(module.id, "0.1.0", connection)?;
+                Release::flush_download_counts(&hashmap!{release.id => downloads}, connection)?;
+                Ok(module)
+            };
+
+            publish("top-author", "popular-old", 100)?
+                .deprecate(None, None, connection)?;
+            publish("top-author", "less-popular", 10)?;
+            publish("top-deprecated", "only-old", 5)?
+                .deprecate(None, None, connection)?;
+
+            let top = Module::top_per_author(connection, 100)?
+                .into_iter()
+                .filter(|(module, _)| module.author.starts_with("top-"))
+                .map(|(module, downloads)| (module.name, downloads))
+                .collect::<Vec<_>>();
+            // deprecated modules are ranked last, but authors with nothing else are still listed
+            assert_eq!(top, vec![
+                ("less-popular".to_string(), 10),
+                ("only-old".to_string(), 5),
+            ]);
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_similar() {
+        with_test_db(|connection| {
+            let code = "-- Description: Similar\n-- Version: 0.1.0\n-- License: MIT\n\nfunction run() end\n";
+            let publish = |name: &str, description: &str| -> Result<Module> {
+                let module = Module::update_or_create("similar-author", name, description, connection)?;
+                module.add_version("0.1.0", code, &PublishOptions::default(), connection)?;
+                Ok(module)
+            };
+
+            let module = publish("source", "Collect xylophone subdomains")?;
+            publish("both", "Find subdomains in the xylophone")?;
+            publish("one", "xylophone certificates")?;
+            publish("unrelated", "Track zeppelins")?;
+            publish("deprecated", "Collect xylophone subdomains")?
+                .deprecate(None, None, connection)?;
+            Module::update_or_create("similar-author", "unpublished", "xylophone subdomains", connection)?;
+
+            let similar = Module::similar(module.id, connection, 10)?
+                .into_iter()
+                .map(|module| module.name)
+                .collect::<Vec<_>>();
+            assert_eq!(similar, vec!["both", "one"]);
+
+            let similar = Module::similar(module.id, connection, 1)?;
+            assert_eq!(similar.len(), 1);
+            assert_eq!(similar[0].name, "both");
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_by_author() {
+        with_test_db(|connection| {
+            let code = "-- Description: Page\n-- Version: 0.1.0\n-- License: MIT\n\nfunction run() end\n";
+            for (name, downloads) in &[("a", 1), ("b", 3), ("c", 2)] {
+                let module = Module::update_or_create("page-author", name, "Page", connection)?;
+                module.add_version("0.1.0", code, &PublishOptions::default(), connection)?;
+                let release = Release::find(module.id, "0.1.0", connection)?;
+                for _ in 0..*downloads {
+                    release.bump_downloads(connection)?;
+                }
+            }
+            // never published and a different author
+            Module::update_or_create("page-author", "unpublished", "Page", connection)?;
+            let module = Module::update_or_create("other-author", "d", "Page", connection)?;
+            module.add_version("0.1.0", code, &PublishOptions::default(), connection)?;
+
+            let page = |limit, offset| -> Result<(Vec<String>, i64)> {
+                let (modules, total) = Module::by_author("page-author", connection, limit, offset)?;
+                Ok((modules.into_iter().map(|m| m.name).collect(), total))
+            };
+
+            assert_eq!(page(2, 0)?, (vec!["b".to_string(), "c".to_string()], 3));
+            assert_eq!(page(2, 2)?, (vec!["a".to_string()], 3));
+            assert_eq!(page(2, 3)?, (vec![], 3));
+            assert_eq!(page(3, 0)?.0.len(), 3);
+            assert_eq!(Module::by_author("nobody", connection, 2, 0)?.1, 0);
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_flush_download_counts() {
+        with_test_db(|connection| {
+            let module = Module::update_or_create("flush-author", "flush-test", "Flush", connection)?;
+            module.add_version("0.1.0", "-- Description: Flush\n-- Version: 0.1.0\n-- License: MIT\n\nfunction run() end\n", &PublishOptions::default(), connection)?;
+            let release = Release::find(module.id, "0.1.0", connection)?;
+            release.bump_downloads(connection)?;
+
+            let counter = crate::downloads::DownloadCounter::new(Duration::from_secs(60), 1000);
+            for _ in 0..5 {
+                counter.record(release.id, connection);
+            }
+            // nothing is written before the flush
+            assert_eq!(Release::find(module.id, "0.1.0", connection)?.downloads, 1);
+
+            counter.flush(connection)?;
+            assert_eq!(Release::find(module.id, "0.1.0", connection)?.downloads, 6);
+            // the buffer is empty after a flush
+            counter.flush(connection)?;
+            assert_eq!(Release::find(module.id, "0.1.0", connection)?.downloads, 6);
+
+            let now = SystemTime::now();
+            let series = Module::download_series(module.id, now, now, connection)?;
+            assert_eq!(series.iter().map(|(_, count)| *count).collect::<Vec<_>>(), vec![6]);
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_download_series() {
+        with_test_db(|connection| {
+            let code = |version: &str| format!("-- Description: Series\n-- Version: {}\n-- License: MIT\n\nfunction run() end\n-- {}\n", version, version);
+            let module = Module::update_or_create("series-author", "series-test", "Series", connection)?;
+            module.add_version("0.1.0", &code("0.1.0"), &PublishOptions::default(), connection)?;
+            module.add_version("0.2.0", &code("0.2.0"), &PublishOptions::default(), connection)?;
+            let other = Module::update_or_create("series-author", "series-other", "Series", connection)?;
+            other.add_version("0.1.0", &code("0.1.0"), &PublishOptions::default(), connection)?;
+
+            let old = Release::find(module.id, "0.1.0", connection)?;
+            let new = Release::find(module.id, "0.2.0", connection)?;
+            let unrelated = Release::find(other.id, "0.1.0", connection)?;
+            old.bump_downloads(connection)?;
+            new.bump_downloads(connection)?;
+            new.bump_downloads(connection)?;
+            unrelated.bump_downloads(connection)?;
+            diesel::sql_query("INSERT INTO download_events (release_id, day, count) VALUES
+                ($1, (NOW() AT TIME ZONE 'UTC')::DATE - 2, 5)")
+                .bind::<Integer, _>(old.id)
+                .execute(connection)?;
+
+            // every release of the module is counted, days without downloads are zero
+            let now = SystemTime::now();
+            let day = Duration::from_secs(24 * 3600);
+            let series = Module::download_series(module.id, now - day * 3, now, connection)?;
+            assert_eq!(series.iter().map(|(_, count)| *count).collect::<Vec<_>>(), vec![0, 5, 0, 3]);
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_platform_stats() {
+        with_test_db(|connection| {
+            let before = Module::platform_stats(connection)?;
+
+            let module = Module::update_or_create("stats-author", "stats-test", "Stats", connection)?;
+            module.add_version("0.1.0", "-- Description: Stats\n-- Version: 0.1.0\n-- License: MIT\n\nfunction run() end\n", &PublishOptions::default(), connection)?;
+            let module = Module::find("stats-author", "stats-test", connection)?;
+            module.add_version("0.2.0", "-- Description: Stats\n-- Version: 0.2.0\n-- License: MIT\n\nfunction run() end\n-- 0.2.0\n", &PublishOptions::default(), connection)?;
+            Release::find(module.id, "0.1.0", connection)?.bump_downloads(connection)?;
+
+            let after = Module::platform_stats(connection)?;
+            assert_eq!(after, PlatformStats {
+                modules: before.modules + 1,
+                releases: before.releases + 2,
+                downloads: before.downloads + 1,
+                active_authors: before.active_authors + 1,
+            });
+
+            // deleted modules don't count
+            module.soft_delete(connection)?;
+            assert_eq!(Module::platform_stats(connection)?, before);
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_prerelease() {
+        with_test_db(|connection| {
+            let code = |version: &str| format!("-- Description: Beta\n-- Version: {}\n-- License: MIT\n\n-- {}\nfunction run() end\n", version, version);
+            let latest = || -> Result<(Option<String>, Option<String>)> {
+                let module = Module::find("someone", "beta-test", connection)?;
+                let stable = Release::latest_stable(module.id, connection)?
+                    .map(|release| release.version);
+                Ok((module.latest, stable))
+            };
+
+            let module = Module::update_or_create("someone", "beta-test", "Beta", connection)?;
+            module.add_version("0.1.0-beta", &code("0.1.0-beta"), &PublishOptions { prerelease: true, ..Default::default() }, connection)?;
+            assert_eq!(latest()?, (None, None));
+
+            let module = Module::find("someone", "beta-test", connection)?;
+            module.add_version("0.1.0", &code("0.1.0"), &PublishOptions::default(), connection)?;
+            assert_eq!(latest()?, (Some("0.1.0".to_string()), Some("0.1.0".to_string())));
+
+            let module = Module::find("someone", "beta-test", connection)?;
+            module.add_version("0.2.0-beta", &code("0.2.0-beta"), &PublishOptions { prerelease: true, ..Default::default() }, connection)?;
+            assert_eq!(latest()?, (Some("0.1.0".to_string()), Some("0.1.0".to_string())));
+            assert!(Release::find(module.id, "0.2.0-beta", connection)?.prerelease);
+
+            let module = Module::find("someone", "beta-test", connection)?;
+            module.add_version("0.2.0", &code("0.2.0"), &PublishOptions::default(), connection)?;
+            assert_eq!(latest()?, (Some("0.2.0".to_string()), Some("0.2.0".to_string())));
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_latest_out_of_order() {
+        with_test_db(|connection| {
+            let code = |version: &str| format!("-- Description: Hotfix\n-- Version: {}\n-- License: MIT\n\n-- {}\nfunction run() end\n", version, version);
+            let publish = |version: &str| -> Result<Option<String>> {
+                let module = Module::update_or_create("someone", "hotfix-test", "Hotfix", connection)?;
+                module.add_version(version, &code(version), &PublishOptions::default(), connection)?;
+                Ok(Module::find("someone", "hotfix-test", connection)?.latest)
+            };
+
+            assert_eq!(publish("0.9.0")?, Some("0.9.0".to_string()));
+            assert_eq!(publish("1.0.0")?, Some("1.0.0".to_string()));
+            // a hotfix for an older version doesn't replace the latest release
+            assert_eq!(publish("0.9.1")?, Some("1.0.0".to_string()));
+            assert_eq!(publish("1.0.1")?, Some("1.0.1".to_string()));
+
+            let err = publish("1.1").unwrap_err();
+            assert!(err.downcast_ref::<InvalidVersion>().is_some());
+            let module = Module::find("someone", "hotfix-test", connection)?;
+            assert!(Release::try_find(module.id, "1.1", connection)?.is_none());
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_list_releases() {
+        with_test_db(|connection| {
+            let code = |version: &str| format!("-- Description: History\n-- Version: {}\n-- License: MIT\n\nfunction run() end\n", version);
+            let module = Module::update_or_create("someone", "history-test", "History", connection)?;
+            for version in &["0.1.0", "0.2.0", "0.3.0-beta", "0.3.0"] {
+                module.add_version(version, &code(version), &PublishOptions { prerelease: version.contains('-'), ..Default::default() }, connection)?;
+            }
+            Release::find(module.id, "0.2.0", connection)?.bump_downloads(connection)?;
+            Release::find(module.id, "0.2.0", connection)?.set_yanked(true, connection)?;
+
+            let versions = |offset, limit| -> Result<Vec<(String, i32, bool, bool)>> {
+                Ok(Release::list_for_module(module.id, offset, limit, connection)?
+                    .into_iter()
+                    .map(|r| (r.version, r.downloads, r.prerelease, r.yanked))
+                    .collect())
+            };
+
+            assert_eq!(versions(0, 10)?, vec![
+                ("0.3.0".to_string(), 0, false, false),
+                ("0.3.0-beta".to_string(), 0, true, false),
+                ("0.2.0".to_string(), 1, false, true),
+                ("0.1.0".to_string(), 0, false, false),
+            ]);
+            assert_eq!(versions(1, 2)?.into_iter().map(|x| x.0).collect::<Vec<_>>(), vec!["0.3.0-beta", "0.2.0"]);
+            assert!(versions(4, 10)?.is_empty());
+            // the page size is capped
+            assert_eq!(versions(0, 0)?.len(), 1);
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_yank() {
+        with_test_db(|connection| {
+            let code = |version: &str| format!("-- Description: Yank\n-- Version: {}\n-- License: MIT\n\n-- {}\nfunction run() end\n", version, version);
+            let latest = || -> Result<Option<String>> {
+                Ok(Module::find("someone", "yank-test", connection)?.latest)
+            };
+
+            for version in &["0.1.0", "0.2.0"] {
+                let module = Module::update_or_create("someone", "yank-test", "Yank", connection)?;
+                module.add_version(version, &code(version), &PublishOptions::default(), connection)?;
+            }
+            let module = Module::find("someone", "yank-test", connection)?;
+            assert_eq!(latest()?, Some("0.2.0".to_string()));
+
+            // yanking the latest release falls back to the previous one
+            let release = Release::find(module.id, "0.2.0", connection)?;
+            assert_eq!(release.set_yanked(true, connection)?, Some("0.1.0".to_string()));
+            assert_eq!(latest()?, Some("0.1.0".to_string()));
+
+            // yanked releases can still be downloaded with their version
+            let release = Release::find(module.id, "0.2.0", connection)?;
+            assert!(release.yanked);
+            assert_eq!(release.code, code("0.2.0"));
+
+            // a fixed release can be published on top of the yanked one
+            let module = Module::find("someone", "yank-test", connection)?;
+            module.add_version("0.2.1", &code("0.2.0"), &PublishOptions::default(), connection)?;
+            assert_eq!(latest()?, Some("0.2.1".to_string()));
+
+            for version in &["0.1.0", "0.2.1"] {
+                Release::find(module.id, version, connection)?.set_yanked(true, connection)?;
+            }
+            assert_eq!(latest()?, None);
+
+            assert_eq!(release.set_yanked(false, connection)?, Some("0.2.0".to_string()));
+            assert_eq!(latest()?, Some("0.2.0".to_string()));
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_version_exists() {
+        with_test_db(|connection| {
+            let code = "-- Description: Republish\n-- Version: 0.1.0\n-- License: MIT\n\nfunction run() end\n";
+            let module = Module::update_or_create("someone", "republish-test", "Republish", connection)?;
+            module.add_version("0.1.0", code, &PublishOptions::default(), connection)?;
+
+            // the failed insert aborts the transaction, this has to be the last query
+            let module = Module::find("someone", "republish-test", connection)?;
+            let err = module.add_version("0.1.0", code, &PublishOptions { allow_unchanged: true, ..Default::default() }, connection).unwrap_err();
+            assert_eq!(err.downcast_ref::<VersionExists>(), Some(&VersionExists {
+                version: "0.1.0".to_string(),
+            }));
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_unchanged_release() {
+        with_test_db(|connection| {
+            let code = |version: &str, body: &str| format!("-- Description: Unchanged\n-- Version: {}\n-- License: MIT\n\n{}\n", version, body);
+            let module = Module::update_or_create("someone", "unchanged-test", "Unchanged", connection)?;
+            module.add_version("0.1.0", &code("0.1.0", "function run() end"), &PublishOptions::default(), connection)?;
+            let module = Module::find("someone", "unchanged-test", connection)?;
+
+            // bumping only the version isn't a change
+            let err = module.add_version("0.2.0", &code("0.2.0", "function run() end"), &PublishOptions::default(), connection).unwrap_err();
+            let err = err.downcast_ref::<UnchangedRelease>().expect("UnchangedRelease");
+            assert_eq!(err.version, "0.1.0");
+            assert!(Release::try_find(module.id, "0.2.0", connection)?.is_none());
+
+            module.add_version("0.2.0", &code("0.2.0", "function run() end"), &PublishOptions { allow_unchanged: true, ..Default::default() }, connection)?;
+            let module = Module::find("someone", "unchanged-test", connection)?;
+            module.add_version("0.3.0", &code("0.3.0", "function run()\n    info('changed')\nend"), &PublishOptions::default(), connection)?;
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_published_format() {
+        let mut release = release("0.1.0", "");
+        release.published = UNIX_EPOCH + Duration::from_millis(1_555_093_800_250);
+        assert_eq!(rfc3339(&release.published), "2019-04-12T18:30:00Z");
+
+        let json = serde_json::to_value(&release).unwrap();
+        assert_eq!(json["published"], "2019-04-12T18:30:00Z");
+
+        // always utc, the offset is written as `Z`
+        let parsed = DateTime::parse_from_rfc3339(&rfc3339(&release.published)).unwrap();
+        assert_eq!(parsed.offset().local_minus_utc(), 0);
+        assert_eq!(parsed.timestamp(), 1_555_093_800);
+        assert_eq!(rfc3339(&UNIX_EPOCH), "1970-01-01T00:00:00Z");
+    }
+
+    #[test]
+    #[ignore]
+    fn test_published_between() {
+        with_test_db(|connection| {
+            let code = |version: &str| format!("-- Description: Window\n-- Version: {}\n-- License: MIT\n\n-- {}\nfunction run() end\n", version, version);
+            let day = |n: u64| UNIX_EPOCH + Duration::from_secs(1_555_000_000 + n * 86400);
+
+            let module = Module::update_or_create("someone", "window-test", "Window", connection)?;
+            for (i, version) in ["0.1.0", "0.2.0", "0.3.0"].iter().enumerate() {
+                let module = Module::find("someone", "window-test", connection)?;
+                module.add_version(version, &code(version), &PublishOptions::default(), connection)?;
+                diesel::update(releases::table)
+                    .filter(releases::module_id.eq(module.id))
+                    .filter(releases::version.eq(version))
+                    .set(releases::published.eq(day(i as u64)))
+                    .execute(connection)?;
+            }
+
+            let versions = |from, to| -> Result<Vec<String>> {
+                Ok(Release::published_between(from, to, connection)?
+                    .into_iter()
+                    .filter(|release| release.module_id == module.id)
+                    .map(|release| release.version)
+                    .collect())
+            };
+            assert_eq!(versions(day(0), day(2))?, vec!["0.1.0", "0.2.0"]);
+            assert_eq!(versions(day(1), day(3))?, vec!["0.2.0", "0.3.0"]);
+            assert_eq!(versions(day(3), day(4))?, Vec::<String>::new());
+
+            let release = Release::find(module.id, "0.2.0", connection)?;
+            assert_eq!(rfc3339(&release.published), "2019-04-12T16:26:40Z");
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_readme() {
+        with_test_db(|connection| {
+            let code = |version: &str| format!("-- Description: Readme\n-- Version: {}\n-- License: MIT\n\n-- {}\nfunction run() end\n", version, version);
+
+            let module = Module::update_or_create("someone", "readme-test", "Readme", connection)?;
+            module.add_version("0.1.0", &code("0.1.0"), &PublishOptions { readme: Some("First"), ..Default::default() }, connection)?;
+            let module = Module::find("someone", "readme-test", connection)?;
+            module.add_version("0.2.0", &code("0.2.0"), &PublishOptions::default(), connection)?;
+
+            assert_eq!(Release::readme(module.id, "0.1.0", connection)?, Some("First".to_string()));
+            assert_eq!(Release::readme(module.id, "0.2.0", connection)?, None);
+            assert!(Release::readme(module.id, "0.3.0", connection).is_err());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(checksum("abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert!(ensure_sha256("abc", &checksum("abc")).is_ok());
+
+        let err = ensure_sha256("abd", &checksum("abc")).unwrap_err();
+        let err = err.downcast_ref::<HashMismatch>().unwrap();
+        assert_eq!(err.expected, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(err.actual, checksum("abd"));
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_stored_sha256() {
+        with_test_db(|connection| {
+            let code = "-- Description: Digest\n-- Version: 0.1.0\n-- License: MIT\n\nfunction run() end\n";
+            let module = Module::update_or_create("someone", "digest-test", "Digest", connection)?;
+            module.add_version("0.1.0", code, &PublishOptions::default(), connection)?;
+
+            let release = Release::find(module.id, "0.1.0", connection)?;
+            assert_eq!(release.sha256, "c79a9254522d3178924fb1a2fe258c847abe97d0c6e5d40e90b2653f0f9904b7");
+            assert_eq!(Release::id(release.id, connection)?.sha256, release.sha256);
+
+            let err = Release::create(&NewRelease {
+                module_id: module.id,
+                version: "0.2.0",
+                code,
+                sha256: &checksum("tampered"),
+                manifest: "{}",
+                prerelease: false,
+                readme: None,
+            }, &[], connection).unwrap_err();
+            assert!(err.downcast_ref::<HashMismatch>().is_some());
+            assert!(Release::try_find(module.id, "0.2.0", connection)?.is_none());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_missing_parts() {
+        assert_eq!(missing_parts(4, &[0, 2]), vec![1, 3]);
+        assert_eq!(missing_parts(2, &[1, 0]), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn test_assemble_parts() {
+        let code = "-- Version: 0.1.0\nfunction run() end\n";
+        let parts = vec![
+            (0, code[..10].to_string()),
+            (1, code[10..].to_string()),
+        ];
+        assert_eq!(assemble_parts(2, &checksum(code), parts).unwrap(), code);
+    }
+
+    #[test]
+    fn test_assemble_parts_rejected() {
+        let code = "-- Version: 0.1.0\n";
+
+        let err = assemble_parts(3, &checksum(code), vec![(1, code.to_string())]).unwrap_err();
+        assert_eq!(err.downcast_ref::<UploadRejected>(), Some(&UploadRejected::Incomplete(vec![0, 2])));
+
+        let err = assemble_parts(1, &checksum(code), vec![(0, "-- Version: 0.2.0\n".to_string())]).unwrap_err();
+        assert_eq!(err.downcast_ref::<UploadRejected>(), Some(&UploadRejected::ChecksumMismatch));
+    }
+
+    fn token(scopes: &[&str], expires_at: Option<SystemTime>) -> AuthToken {
+        AuthToken {
+            id: "session".to_string(),
+            author: "someone".to_string(),
+            access_token: "token".to_string(),
+            previous_token_hash: None,
+            previous_token_expires: None,
+            scopes: scopes.iter().map(|x| x.to_string()).collect(),
+            expires_at,
+        }
+    }
+
+    #[test]
+    fn test_auth_token_scopes() {
+        let token = token(ALL_SCOPES, None);
+        assert!(token.has_scope(SCOPE_PUBLISH));
+        assert!(token.has_scope(SCOPE_YANK));
+        assert!(token.has_scope(SCOPE_READ));
+        assert!(!token.has_scope("admin"));
+        assert_eq!(default_scopes(), vec!["publish", "yank", "read"]);
+
+        let token = self::token(&[SCOPE_READ], None);
+        assert!(!token.has_scope(SCOPE_PUBLISH));
+        assert!(token.has_scope(SCOPE_READ));
+    }
+
+    #[test]
+    fn test_auth_token_expired() {
+        let now = SystemTime::now();
+        assert!(!token(ALL_SCOPES, None).is_expired(now));
+        assert!(!token(ALL_SCOPES, Some(now + Duration::from_secs(1))).is_expired(now));
+        assert!(token(ALL_SCOPES, Some(now)).is_expired(now));
+        assert!(token(ALL_SCOPES, Some(now - Duration::from_secs(1))).is_expired(now));
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_valid_auth_token() {
+        with_test_db(|connection| {
+            AuthToken::create(&token(&[SCOPE_READ], Some(SystemTime::now() + Duration::from_secs(3600))), connection)?;
+            let session = AuthToken::read_valid("session", connection)?.expect("token is missing");
+            assert_eq!(session.scopes, vec!["read"]);
+
+            diesel::update(auth_tokens::table.find("session"))
+                .set(auth_tokens::expires_at.eq(SystemTime::now() - Duration::from_secs(1)))
+                .execute(connection)?;
+            assert!(AuthToken::read_valid("session", connection)?.is_none());
+            // the expired token is still stored
+            assert!(AuthToken::read_opt("session", connection)?.is_some());
+
+            AuthToken::revoke("session", connection)?;
+            assert!(AuthToken::read_opt("session", connection)?.is_none());
+
+            // tokens that are created without scopes have full access
+            diesel::sql_query("INSERT INTO auth_tokens (id, author, access_token) VALUES ('legacy', 'someone', 'token')")
+                .execute(connection)?;
+            let session = AuthToken::read_valid("legacy", connection)?.expect("token is missing");
+            assert_eq!(session.scopes, default_scopes());
+            assert_eq!(session.expires_at, None);
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_rotate_auth_token() {
+        with_test_db(|connection| {
+            AuthToken::create(&AuthToken {
+                id: "rotate-session".to_string(),
+                author: "someone".to_string(),
+                access_token: "old-token".to_string(),
+                previous_token_hash: None,
+                previous_token_expires: None,
+                scopes: default_scopes(),
+                expires_at: None,
+            }, connection)?;
+
+            let token = AuthToken::rotate("rotate-session", Some(Duration::from_secs(3600)), connection)?;
+            assert_ne!(token.id, "rotate-session");
+            assert_eq!(token.id.len(), 64);
+            assert_eq!(token.author, "someone");
+            assert_eq!(token.access_token, "old-token");
+            assert_eq!(token.previous_token_hash, Some(checksum("rotate-session")));
+            let new_id = token.id;
+
+            // both ids resolve to the same session during the grace period
+            assert_eq!(AuthToken::read_valid(&new_id, connection)?.map(|x| x.id), Some(new_id.clone()));
+            assert_eq!(AuthToken::read_valid("rotate-session", connection)?.map(|x| x.id), Some(new_id.clone()));
+            assert!(AuthToken::read_valid("other-session", connection)?.is_none());
+
+            // the grace period is over
+            diesel::update(auth_tokens::table.find(&new_id))
+                .set(auth_tokens::previous_token_expires.eq(SystemTime::now() - Duration::from_secs(1)))
+                .execute(connection)?;
+            assert!(AuthToken::read_valid(&new_id, connection)?.is_some());
+            assert!(AuthToken::read_valid("rotate-session", connection)?.is_none());
+
+            // without grace period the old id is invalid right away
+            let token = AuthToken::rotate(&new_id, None, connection)?;
+            assert!(AuthToken::read_valid(&token.id, connection)?.is_some());
+            assert!(AuthToken::read_valid(&new_id, connection)?.is_none());
+
+            assert!(AuthToken::rotate("unknown-session", None, connection).is_err());
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_mint_auth_token() {
+        with_test_db(|connection| {
+            let parent = AuthToken {
+                id: "mint-session".to_string(),
+                author: "someone".to_string(),
+                access_token: "github-token".to_string(),
+                previous_token_hash: None,
+                previous_token_expires: None,
+                scopes: default_scopes(),
+                expires_at: None,
+            };
+            AuthToken::create(&parent, connection)?;
+
+            let token = parent.mint(&[SCOPE_PUBLISH.to_string()], Some(Duration::from_secs(60)), connection)?;
+            assert_ne!(token.id, parent.id);
+            assert_eq!(token.author, "someone");
+            assert_eq!(token.scopes, vec![SCOPE_PUBLISH.to_string()]);
+            assert!(token.expires_at.is_some());
+            let token = AuthToken::read_valid(&token.id, connection)?.unwrap();
+
+            // a token can't grant more than it has
+            let err = token.mint(&[SCOPE_YANK.to_string()], None, connection).err().unwrap();
+            assert_eq!(err.downcast_ref::<ScopeRejected>(), Some(&ScopeRejected::NotGranted(SCOPE_YANK.to_string())));
+            let err = parent.mint(&["admin".to_string()], None, connection).err().unwrap();
+            assert_eq!(err.downcast_ref::<ScopeRejected>(), Some(&ScopeRejected::Unknown("admin".to_string())));
+
+            // and doesn't outlive its parent
+            let child = token.mint(&[], Some(Duration::from_secs(3600)), connection)?;
+            assert_eq!(child.scopes, token.scopes);
+            assert_eq!(child.expires_at, token.expires_at);
+            let child = token.mint(&[], None, connection)?;
+            assert_eq!(child.expires_at, token.expires_at);
+
+            AuthToken::revoke(&child.id, connection)?;
+            assert!(AuthToken::read_valid(&child.id, connection)?.is_none());
+            assert!(AuthToken::read_valid(&token.id, connection)?.is_some());
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_upload_session() {
+        with_test_db(|connection| {
+            let code = "-- Description: Chunked\n-- Version: 0.1.0\n-- License: MIT\n\nfunction run() end\n";
+            let session = UploadSession::create(&NewUploadSession {
+                author: "someone",
+                name: "chunked",
+                checksum: &checksum(code),
+                parts: 2,
+                allow_unchanged: false,
+                prerelease: false,
+                skip_syntax_check: false,
+            }, connection)?;
+
+            session.add_part(1, &code[20..], connection)?;
+            assert_eq!(session.missing(connection)?, vec![0]);
+            assert!(session.assemble(connection).is_err());
+
+            // a retried part replaces the previous upload
+            session.add_part(0, "garbage", connection)?;
+            session.add_part(0, &code[..20], connection)?;
+            assert!(session.add_part(2, "", connection).is_err());
+            assert_eq!(session.assemble(connection)?, code);
+
+            assert!(UploadSession::find_opt(session.id, "someone-else", "chunked", connection)?.is_none());
+            assert!(UploadSession::find_opt(session.id, "someone", "chunked", connection)?.is_some());
+
+            diesel::update(upload_sessions::table.find(session.id))
+                .set(upload_sessions::created.eq(SystemTime::now() - UPLOAD_SESSION_TIMEOUT))
+                .execute(connection)?;
+            assert!(UploadSession::find_opt(session.id, "someone", "chunked", connection)?.is_none());
+            assert!(UploadSession::expire(connection)? >= 1);
+            assert_eq!(upload_parts::table.filter(upload_parts::session_id.eq(session.id)).count().get_result::<i64>(connection)?, 0);
+            Ok(())
+        });
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_failed_webhook_deliveries() {
+        with_test_db(|connection| {
+            let delivery = |url, payload, delivered, replay_of| {
+                WebhookDelivery::create(&NewWebhookDelivery {
+                    url,
+                    payload,
+                    signature: Some("sha256=00"),
+                    attempt: 1,
+                    status: Some(if delivered { 200 } else { 500 }),
+                    response: None,
+                    delivered,
+                    replay_of,
+                }, connection)
+            };
+            let failed = || -> Result<Vec<i32>> {
+                let deliveries = WebhookDelivery::list(true, 100, connection)?;
+                Ok(deliveries.into_iter()
+                    .filter(|d| d.url.starts_with("https://hooks.example.com/"))
+                    .map(|d| d.id)
+                    .collect())
+            };
+
+            // retried until it succeeded
+            delivery("https://hooks.example.com/a", "{\"a\":1}", false, None)?;
+            delivery("https://hooks.example.com/a", "{\"a\":1}", true, None)?;
+            // given up, then replayed
+            let replayed = delivery("https://hooks.example.com/a", "{\"a\":2}", false, None)?;
+            // given up
+            let pending = delivery("https://hooks.example.com/b", "{\"a\":2}", false, None)?;
+
+            assert_eq!(failed()?, vec![pending.id, replayed.id]);
+
+            let replay = delivery("https://hooks.example.com/a", "{\"a\":2}", false, Some(replayed.id))?;
+            assert_eq!(failed()?, vec![replay.id, pending.id, replayed.id]);
+            delivery("https://hooks.example.com/a", "{\"a\":2}", true, Some(replayed.id))?;
+            assert_eq!(failed()?, vec![pending.id]);
+
+            let all = WebhookDelivery::list(false, 100, connection)?;
+            assert!(all.len() >= 6);
+            assert_eq!(WebhookDelivery::find_opt(pending.id, connection)?, Some(pending));
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_should_hide() {
+        let config = ModerationConfig::default();
+        assert!(!config.should_hide(DEFAULT_REPORT_THRESHOLD - 1));
+        assert!(config.should_hide(DEFAULT_REPORT_THRESHOLD));
+
+        let config = ModerationConfig {
+            hide_threshold: 0,
+        };
+        assert!(!config.should_hide(100));
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_hidden_modules() {
+        with_test_db(|connection| {
+            let code = "-- Description: Hide\n-- Version: 0.1.0\n-- License: MIT\n\nfunction run() end\n";
+            let module = Module::update_or_create("hide-author", "hide-test", "Query the quetzal api", connection)?;
+            module.add_version("0.1.0", code, &PublishOptions::default(), connection)?;
+            diesel::update(modules::table.find(module.id))
+                .set(modules::featured.eq(true))
+                .execute(connection)?;
+
+            let listed = || -> Result<(bool, bool, bool)> {
+                let search = Module::search("quetzal", None, false, false, connection)?
+                    .iter()
+                    .any(|(m, _)| m.id == module.id);
+                let quickstart = Module::quickstart(connection)?
+                    .iter()
+                    .any(|m| m.id == module.id);
+                let by_author = Module::by_author("hide-author", connection, 10, 0)?.1 > 0;
+                Ok((search, quickstart, by_author))
+            };
+            assert_eq!(listed()?, (true, true, true));
+
+            let config = ModerationConfig {
+                hide_threshold: 2,
+            };
+            assert!(!ModuleReport::submit(&module, "alice", "Looks like malware", &config, connection)?);
+            // reporting again doesn't count twice
+            assert!(!ModuleReport::submit(&module, "alice", "Sends my keys somewhere", &config, connection)?);
+            assert_eq!(ModuleReport::open(module.id, connection)?, 1);
+            assert_eq!(listed()?, (true, true, true));
+
+            assert!(ModuleReport::submit(&module, "bob", "Exfiltrates the keyring", &config, connection)?);
+            assert!(module.is_hidden(connection)?);
+            assert_eq!(listed()?, (false, false, false));
+
+            let reports = ModuleReport::list(100, connection)?
+                .into_iter()
+                .filter(|x| x.module.id == module.id)
+                .map(|x| (x.hidden, x.report.reporter, x.report.reason))
+                .collect::<Vec<_>>();
+            assert_eq!(reports, vec![
+                (true, "bob".to_string(), "Exfiltrates the keyring".to_string()),
+                (true, "alice".to_string(), "Sends my keys somewhere".to_string()),
+            ]);
+
+            // hidden modules can still be downloaded
+            let release = Release::find(module.id, "0.1.0", connection)?;
+            release.bump_downloads(connection)?;
+            assert_eq!(Release::find(module.id, "0.1.0", connection)?.downloads, 1);
+
+            module.unhide(connection)?;
+            assert_eq!(listed()?, (true, true, true));
+            assert_eq!(ModuleReport::open(module.id, connection)?, 0);
+
+            // a report after the review starts counting from zero
+            assert!(!ModuleReport::submit(&module, "alice", "Still suspicious", &config, connection)?);
+            assert!(!module.is_hidden(connection)?);
+            Ok(())
+        });
+    }
 }
@@ -0,0 +1,186 @@
+use crate::errors::*;
+use rocket::http::{Header, Status};
+use rocket::request::{self, FromRequest};
+use rocket::response::{self, Responder};
+use rocket::{Request, Outcome, State};
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+
+const DEFAULT_BURST: u32 = 30;
+const DEFAULT_PER_MINUTE: u32 = 60;
+// buckets are split across shards so concurrent searches from different
+// clients don't wait for each other
+const SHARDS: usize = 16;
+// forget about full buckets once a shard grows past this
+const MAX_ENTRIES_PER_SHARD: usize = 4096;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    shards: Vec<Mutex<HashMap<IpAddr, Bucket>>>,
+    burst: f64,
+    per_sec: f64,
+}
+
+impl RateLimiter {
+    pub fn new(burst: u32, per_minute: u32) -> RateLimiter {
+        let shards = (0..SHARDS)
+            .map(|_| Mutex::new(HashMap::new()))
+            .collect();
+
+        RateLimiter {
+            shards,
+            burst: f64::from(burst),
+            per_sec: f64::from(per_minute) / 60.0,
+        }
+    }
+
+    /// Read SEARCH_RATELIMIT_BURST and SEARCH_RATELIMIT_PER_MINUTE
+    pub fn from_env() -> Result<RateLimiter> {
+        let burst = match env::var("SEARCH_RATELIMIT_BURST") {
+            Ok(x) => x.parse::<u32>()
+                .context("SEARCH_RATELIMIT_BURST is invalid")?,
+            Err(_) => DEFAULT_BURST,
+        };
+        let per_minute = match env::var("SEARCH_RATELIMIT_PER_MINUTE") {
+            Ok(x) => x.parse::<u32>()
+                .context("SEARCH_RATELIMIT_PER_MINUTE is invalid")?,
+            Err(_) => DEFAULT_PER_MINUTE,
+        };
+
+        if burst == 0 || per_minute == 0 {
+            bail!("Search rate limits must be greater than zero");
+        }
+
+        Ok(RateLimiter::new(burst, per_minute))
+    }
+
+    fn shard(&self, ip: &IpAddr) -> &Mutex<HashMap<IpAddr, Bucket>> {
+        let mut h = DefaultHasher::new();
+        ip.hash(&mut h);
+        &self.shards[h.finish() as usize % self.shards.len()]
+    }
+
+    /// Take a token for this ip, or return how long the client has to wait
+    pub fn check(&self, ip: IpAddr, now: Instant) -> ::std::result::Result<(), Duration> {
+        let mut shard = self.shard(&ip).lock().unwrap();
+
+        if shard.len() >= MAX_ENTRIES_PER_SHARD && !shard.contains_key(&ip) {
+            let (burst, per_sec) = (self.burst, self.per_sec);
+            shard.retain(|_, bucket| {
+                let elapsed = now.duration_since(bucket.updated);
+                bucket.tokens + secs(elapsed) * per_sec < burst
+            });
+        }
+
+        let bucket = shard.entry(ip)
+            .or_insert_with(|| Bucket {
+                tokens: self.burst,
+                updated: now,
+            });
+
+        let elapsed = now.duration_since(bucket.updated);
+        bucket.tokens = (bucket.tokens + secs(elapsed) * self.per_sec).min(self.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / self.per_sec;
+            Err(Duration::from_secs(wait.ceil() as u64))
+        }
+    }
+}
+
+fn secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + f64::from(d.subsec_nanos()) / 1e9
+}
+
+/// Request guard that fails with 429 if the client is over its search quota
+pub struct SearchQuota;
+
+// stored in the request cache so the catcher can set Retry-After
+struct RetryAfter(Option<Duration>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for SearchQuota {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let limiter = request.guard::<State<RateLimiter>>()?;
+
+        let ip = match request.client_ip() {
+            Some(ip) => ip,
+            None => return Outcome::Success(SearchQuota),
+        };
+
+        match limiter.check(ip, Instant::now()) {
+            Ok(_) => Outcome::Success(SearchQuota),
+            Err(wait) => {
+                info!("Rate limiting search from {}", ip);
+                request.local_cache(|| RetryAfter(Some(wait)));
+                Outcome::Failure((Status::TooManyRequests, ()))
+            },
+        }
+    }
+}
+
+pub struct TooManyRequests;
+
+impl<'r> Responder<'r> for TooManyRequests {
+    fn respond_to(self, req: &Request) -> response::Result<'r> {
+        let wait = req.local_cache(|| RetryAfter(None)).0
+            .unwrap_or_else(|| Duration::from_secs(1));
+
+        let mut resp = err_msg(Status::TooManyRequests, "Too many requests, slow down")
+            .respond_to(req)?;
+        resp.set_header(Header::new("Retry-After", wait.as_secs().to_string()));
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst() {
+        let limiter = RateLimiter::new(3, 60);
+        let ip = "192.0.2.1".parse().unwrap();
+        let now = Instant::now();
+        assert!(limiter.check(ip, now).is_ok());
+        assert!(limiter.check(ip, now).is_ok());
+        assert!(limiter.check(ip, now).is_ok());
+        assert_eq!(limiter.check(ip, now), Err(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_refill() {
+        let limiter = RateLimiter::new(1, 6);
+        let ip = "192.0.2.1".parse().unwrap();
+        let now = Instant::now();
+        assert!(limiter.check(ip, now).is_ok());
+        assert_eq!(limiter.check(ip, now), Err(Duration::from_secs(10)));
+        assert!(limiter.check(ip, now + Duration::from_secs(10)).is_ok());
+    }
+
+    #[test]
+    fn test_separate_clients() {
+        let limiter = RateLimiter::new(1, 60);
+        let now = Instant::now();
+        assert!(limiter.check("192.0.2.1".parse().unwrap(), now).is_ok());
+        assert!(limiter.check("192.0.2.2".parse().unwrap(), now).is_ok());
+        assert!(limiter.check("192.0.2.1".parse().unwrap(), now).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+use crate::errors::*;
+use crate::auth2::{Admins, AuthHeader};
+use crate::db;
+use crate::models::*;
+use crate::webhook::Webhook;
+use rocket::State;
+use rocket::request::Form;
+
+
+fn verify_admin(session: &AuthHeader, admins: &Admins, connection: &db::Connection) -> ApiResult<String> {
+    let user = session.verify(connection)
+        .bad_request()
+        .public_context("Invalid auth token")?;
+
+    if !admins.contains(&user) {
+        bad_request!("Permission denied")
+    }
+
+    Ok(user)
+}
+
+#[derive(Debug, FromForm)]
+pub struct WebhookDeliveries {
+    failed: Option<bool>,
+    limit: Option<i64>,
+}
+
+#[get("/admin/webhooks?<q..>")]
+pub fn webhooks(q: Form<WebhookDeliveries>, session: AuthHeader, admins: State<Admins>, connection: db::Connection) -> ApiResult<ApiResponse<Vec<WebhookDelivery>>> {
+    verify_admin(&session, &admins, &connection)?;
+
+    let limit = q.limit.unwrap_or(25);
+    if limit < 1 || limit > 100 {
+        bad_request!("Limit must be between 1 and 100")
+    }
+
+    let deliveries = WebhookDelivery::list(q.failed.unwrap_or(false), limit, &connection)?;
+    Ok(ApiResponse::Success(deliveries))
+}
+
+#[post("/admin/webhooks/<id>/replay")]
+pub fn replay_webhook(id: i32, session: AuthHeader, admins: State<Admins>, webhook: State<Webhook>, connection: db::Connection) -> ApiResult<ApiResponse<WebhookDelivery>> {
+    let user = verify_admin(&session, &admins, &connection)?;
+
+    let delivery = match WebhookDelivery::find_opt(id, &connection)? {
+        Some(delivery) => delivery,
+        None => not_found!("Delivery does not exist"),
+    };
+
+    info!("Replaying webhook delivery {} for {:?}", id, user);
+    let replay = webhook.replay(&delivery, &connection)
+        .private_context("Failed to replay webhook")?;
+    Ok(ApiResponse::Success(replay))
+}
+
+#[derive(Debug, FromForm)]
+pub struct Reports {
+    limit: Option<i64>,
+}
+
+#[get("/admin/reports?<q..>")]
+pub fn reports(q: Form<Reports>, session: AuthHeader, admins: State<Admins>, connection: db::Connection) -> ApiResult<ApiResponse<Vec<OpenReport>>> {
+    verify_admin(&session, &admins, &connection)?;
+
+    let limit = q.limit.unwrap_or(25);
+    if limit < 1 || limit > 100 {
+        bad_request!("Limit must be between 1 and 100")
+    }
+
+    let reports = ModuleReport::list(limit, &connection)?;
+    Ok(ApiResponse::Success(reports))
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModerationResponse {
+    author: String,
+    name: String,
+    hidden: bool,
+    open_reports: i64,
+}
+
+fn moderate(author: &str, name: &str, hidden: bool, connection: &db::Connection) -> ApiResult<ModerationResponse> {
+    let module = Module::find(author, name, connection)
+        .not_found()
+        .public_context("Module does not exist")?;
+
+    if hidden {
+        module.hide(connection)?;
+    } else {
+        module.unhide(connection)?;
+    }
+
+    let open_reports = ModuleReport::open(module.id, connection)?;
+    Ok(ModerationResponse {
+        author: module.author,
+        name: module.name,
+        hidden,
+        open_reports,
+    })
+}
+
+fn set_verified(author: &str, verified: bool, connection: &db::Connection) -> ApiResult<Author> {
+    match Author::set_verified(author, verified, connection) {
+        Ok(author) => Ok(author),
+        Err(err) => {
+            if let Some(err) = err.downcast_ref::<InvalidName>() {
+                bad_request!("{}", err)
+            }
+            Err(err).private_context("Failed to update author")
+        },
+    }
+}
+
+/// Verification applies to every module of the author
+#[post("/admin/authors/<author>/verify")]
+pub fn verify_author(author: String, session: AuthHeader, admins: State<Admins>, connection: db::Connection) -> ApiResult<ApiResponse<Author>> {
+    let user = verify_admin(&session, &admins, &connection)?;
+    info!("Verifying {:?} for {:?}", author, user);
+    let author = set_verified(&author, true, &connection)?;
+    Ok(ApiResponse::Success(author))
+}
+
+#[post("/admin/authors/<author>/unverify")]
+pub fn unverify_author(author: String, session: AuthHeader, admins: State<Admins>, connection: db::Connection) -> ApiResult<ApiResponse<Author>> {
+    let user = verify_admin(&session, &admins, &connection)?;
+    info!("Unverifying {:?} for {:?}", author, user);
+    let author = set_verified(&author, false, &connection)?;
+    Ok(ApiResponse::Success(author))
+}
+
+#[post("/admin/modules/<author>/<name>/hide")]
+pub fn hide_module(author: String, name: String, session: AuthHeader, admins: State<Admins>, connection: db::Connection) -> ApiResult<ApiResponse<ModerationResponse>> {
+    let user = verify_admin(&session, &admins, &connection)?;
+    info!("Hiding {:?}/{:?} for {:?}", author, name, user);
+    let response = moderate(&author, &name, true, &connection)?;
+    Ok(ApiResponse::Success(response))
+}
+
+/// Unhiding a module marks its open reports as resolved
+#[post("/admin/modules/<author>/<name>/unhide")]
+pub fn unhide_module(author: String, name: String, session: AuthHeader, admins: State<Admins>, connection: db::Connection) -> ApiResult<ApiResponse<ModerationResponse>> {
+    let user = verify_admin(&session, &admins, &connection)?;
+    info!("Unhiding {:?}/{:?} for {:?}", author, name, user);
+    let response = moderate(&author, &name, false, &connection)?;
+    Ok(ApiResponse::Success(response))
+}
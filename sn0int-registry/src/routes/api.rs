@@ -1,15 +1,23 @@
 use crate::errors::*;
-use crate::auth2::AuthHeader;
+use crate::auth2::{AuthHeader, MissingScope};
 use crate::db;
+use crate::downloads::DownloadCounter;
 use crate::models::*;
+use crate::ratelimit::SearchQuota;
+use crate::stats::StatsCache;
+use crate::syntax::{self, SyntaxError};
+use crate::webhook::{ReleaseEvent, Webhook};
+use rocket::State;
+use rocket::http::Status;
 use diesel::Connection;
+use hex;
 use rocket::request::Form;
 use rocket_contrib::json::Json;
 use semver::Version;
 use sn0int_common::api::*;
-use sn0int_common::id;
+use sn0int_common::id::ModuleID;
 use sn0int_common::metadata::Metadata;
-use std::time::UNIX_EPOCH;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 
 #[get("/quickstart")]
@@ -20,36 +28,86 @@ pub fn quickstart(connection: db::Connection) -> ApiResult<ApiResponse<Vec<Modul
 
 #[get("/latest")]
 pub fn latest(connection: db::Connection) -> ApiResult<ApiResponse<LatestResponse>> {
-    let time = Release::latest(&connection)?
+    let published = Release::latest(&connection)?
+        .map(|x| x.published);
+    let time = published
         .map(|x| {
-            x.published.duration_since(UNIX_EPOCH)
+            x.duration_since(UNIX_EPOCH)
                 .expect("Time went backwards")
                 .as_secs()
         });
     let latest = LatestResponse {
         time,
+        published: published.as_ref().map(rfc3339),
     };
     Ok(ApiResponse::Success(latest))
 }
 
 #[derive(Debug, FromForm)]
 pub struct Search {
-    q: String,
+    q: Option<String>,
+    author: Option<String>,
+    featured: Option<bool>,
+    verified: Option<bool>,
 }
 
 #[get("/search?<q..>")]
-pub fn search(q: Form<Search>, connection: db::Connection) -> ApiResult<ApiResponse<Vec<SearchResponse>>> {
-    info!("Searching: {:?}", q.q);
+pub fn search(q: Form<Search>, _quota: SearchQuota, connection: db::Connection) -> ApiResult<ApiResponse<Vec<SearchResponse>>> {
+    let query = q.q.as_deref().unwrap_or("");
+    let featured_only = q.featured.unwrap_or(false);
+    let verified_only = q.verified.unwrap_or(false);
+    info!("Searching: {:?} (author={:?}, featured={:?}, verified={:?})", query, q.author, featured_only, verified_only);
 
-    let modules = Module::search(&q.q, &connection)?;
+    let modules = Module::search(query, q.author.as_deref(), featured_only, verified_only, &connection)?;
+    let authors = modules.iter()
+        .map(|(module, _)| module.author.as_str())
+        .collect::<Vec<_>>();
+    let verified = Author::verified_among(&authors, &connection)?;
+
+    let mut results = Vec::new();
+    for (module, downloads) in modules {
+        let latest = match &module.latest {
+            Some(latest) => latest.clone(),
+            None => continue,
+        };
+        let deprecated = deprecation(&module, &connection)?;
+        let is_verified = verified.contains(&module.author);
+
+        results.push(SearchResponse {
+            author: module.author,
+            name: module.name,
+            description: module.description,
+            latest,
+            featured: module.featured,
+            downloads,
+            deprecated,
+            verified: is_verified,
+        });
+    }
+
+    Ok(ApiResponse::Success(results))
+}
+
+#[derive(Debug, FromForm)]
+pub struct Leaderboard {
+    limit: Option<i64>,
+}
+
+#[get("/leaderboard?<q..>")]
+pub fn leaderboard(q: Form<Leaderboard>, connection: db::Connection) -> ApiResult<ApiResponse<Vec<TopModuleResponse>>> {
+    let limit = q.limit.unwrap_or(25);
+    if limit < 1 || limit > 100 {
+        bad_request!("Limit must be between 1 and 100")
+    }
+
+    let modules = Module::top_per_author(&connection, limit)?;
     let modules = modules.into_iter()
         .flat_map(|(module, downloads)| {
-            Ok::<_, ()>(SearchResponse {
+            Ok::<_, ()>(TopModuleResponse {
                 author: module.author,
                 name: module.name,
                 description: module.description,
                 latest: module.latest.ok_or(())?,
-                featured: module.featured,
                 downloads,
             })
         })
@@ -58,95 +116,799 @@ pub fn search(q: Form<Search>, connection: db::Connection) -> ApiResult<ApiRespo
     Ok(ApiResponse::Success(modules))
 }
 
+#[derive(Debug, FromForm)]
+pub struct Trending {
+    days: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[get("/trending?<q..>")]
+pub fn trending(q: Form<Trending>, connection: db::Connection) -> ApiResult<ApiResponse<Vec<TopModuleResponse>>> {
+    let days = q.days.unwrap_or(7);
+    if days < 1 || days > 90 {
+        bad_request!("Days must be between 1 and 90")
+    }
+    let limit = q.limit.unwrap_or(25);
+    if limit < 1 || limit > 100 {
+        bad_request!("Limit must be between 1 and 100")
+    }
+
+    let modules = Module::trending(days, limit, &connection)?;
+    let modules = modules.into_iter()
+        .flat_map(|(module, downloads)| {
+            Ok::<_, ()>(TopModuleResponse {
+                author: module.author,
+                name: module.name,
+                description: module.description,
+                latest: module.latest.ok_or(())?,
+                downloads,
+            })
+        })
+        .collect();
+
+    Ok(ApiResponse::Success(modules))
+}
+
+#[derive(Debug, FromForm)]
+pub struct AuthorPage {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[get("/author/<author>?<q..>")]
+pub fn author(author: String, q: Form<AuthorPage>, connection: db::Connection) -> ApiResult<ApiResponse<AuthorResponse>> {
+    let limit = q.limit.unwrap_or(25);
+    if limit < 1 || limit > 100 {
+        bad_request!("Limit must be between 1 and 100")
+    }
+    let offset = q.offset.unwrap_or(0);
+    if offset < 0 {
+        bad_request!("Offset can't be negative")
+    }
+
+    let (modules, total) = Module::by_author(&author, &connection, limit, offset)?;
+    let modules = modules.into_iter()
+        .flat_map(|module| {
+            Ok::<_, ()>(AuthorModuleResponse {
+                name: module.name,
+                description: module.description,
+                latest: module.latest.ok_or(())?,
+                featured: module.featured,
+            })
+        })
+        .collect();
+
+    Ok(ApiResponse::Success(AuthorResponse {
+        author,
+        modules,
+        total,
+    }))
+}
+
+#[derive(Debug, FromForm)]
+pub struct Recent {
+    limit: Option<i64>,
+}
+
+#[get("/recent?<q..>")]
+pub fn recent(q: Form<Recent>, connection: db::Connection) -> ApiResult<ApiResponse<Vec<RecentReleaseResponse>>> {
+    let limit = q.limit.unwrap_or(25);
+    if limit < 1 || limit > 100 {
+        bad_request!("Limit must be between 1 and 100")
+    }
+
+    let releases = Release::recent(&connection, limit)?;
+    let releases = releases.into_iter()
+        .map(|(module, release)| RecentReleaseResponse {
+            author: module.author,
+            name: module.name,
+            description: module.description,
+            version: release.version,
+            published: rfc3339(&release.published),
+            featured: module.featured,
+        })
+        .collect();
+
+    Ok(ApiResponse::Success(releases))
+}
+
+#[get("/stats")]
+pub fn stats(cache: State<StatsCache>, connection: db::Connection) -> ApiResult<ApiResponse<PlatformStatsResponse>> {
+    let stats = cache.get(Instant::now(), || Module::platform_stats(&connection))
+        .private_context("Failed to query platform stats")?;
+
+    Ok(ApiResponse::Success(PlatformStatsResponse {
+        modules: stats.modules,
+        releases: stats.releases,
+        downloads: stats.downloads,
+        active_authors: stats.active_authors,
+    }))
+}
+
+/// Modules that have been transferred to a different author point to their new location
+fn find_module(author: &str, name: &str, connection: &db::Connection) -> ApiResult<Module> {
+    if let Some(module) = Module::find_opt(author, name, connection)? {
+        if module.deleted_at.is_some() {
+            not_found!("Module has been deleted")
+        }
+        return Ok(module);
+    }
+
+    if let Some(module) = ModuleTransfer::moved(author, name, connection)? {
+        not_found!("Module has been moved to {}/{}", module.author, module.name)
+    }
+
+    not_found!("Module does not exist")
+}
+
+/// The deprecation notice that is sent to clients, if the module is deprecated
+fn deprecation(module: &Module, connection: &db::Connection) -> Result<Option<Deprecation>> {
+    if !module.deprecated {
+        return Ok(None);
+    }
+
+    let successor = match module.successor_id {
+        Some(id) => Module::id_opt(id, connection)?
+            .map(|successor| ModuleID {
+                author: successor.author,
+                name: successor.name,
+            }),
+        None => None,
+    };
+
+    Ok(Some(Deprecation {
+        message: module.deprecated_msg.clone(),
+        successor,
+    }))
+}
+
 #[get("/info/<author>/<name>", format="application/json")]
 pub fn info(author: String, name: String, connection: db::Connection) -> ApiResult<ApiResponse<ModuleInfoResponse>> {
     info!("Querying {:?}/{:?}", author, name);
-    let module = Module::find(&author, &name, &connection)
-        .not_found()
-        .public_context("Module does not exist")?;
+    let module = find_module(&author, &name, &connection)?;
+    let deprecated = deprecation(&module, &connection)?;
+    let verified = Author::is_verified(&module.author, &connection)?;
 
     Ok(ApiResponse::Success(ModuleInfoResponse {
         author: module.author,
         name: module.name,
         description: module.description,
         latest: module.latest,
+        deprecated,
+        verified,
+    }))
+}
+
+#[post("/info", format="application/json", data="<batch>")]
+pub fn info_batch(batch: Json<BatchInfoRequest>, connection: db::Connection) -> ApiResult<ApiResponse<BatchInfoResponse>> {
+    info!("Querying {} modules", batch.modules.len());
+    if batch.modules.len() > MAX_BATCH_SIZE {
+        bad_request!("Batch can't contain more than {} modules", MAX_BATCH_SIZE)
+    }
+
+    let modules = Module::find_many(&batch.modules, &connection)?;
+
+    let unknown = batch.into_inner().modules.into_iter()
+        .filter(|id| !modules.iter()
+            .any(|module| id.author == module.author && id.name == module.name))
+        .collect();
+    let authors = modules.iter()
+        .map(|module| module.author.as_str())
+        .collect::<Vec<_>>();
+    let verified = Author::verified_among(&authors, &connection)?;
+    let modules = modules.into_iter()
+        .map(|module| {
+            let deprecated = deprecation(&module, &connection)?;
+            let is_verified = verified.contains(&module.author);
+            Ok(ModuleInfoResponse {
+                author: module.author,
+                name: module.name,
+                description: module.description,
+                latest: module.latest,
+                deprecated,
+                verified: is_verified,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(ApiResponse::Success(BatchInfoResponse {
+        modules,
+        unknown,
+    }))
+}
+
+#[derive(Debug, FromForm)]
+pub struct VersionsPage {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[get("/versions/<author>/<name>?<q..>", format="application/json")]
+pub fn versions(author: String, name: String, q: Form<VersionsPage>, connection: db::Connection) -> ApiResult<ApiResponse<VersionsResponse>> {
+    let limit = q.limit.unwrap_or(25);
+    if limit < 1 || limit > MAX_RELEASE_PAGE {
+        bad_request!("Limit must be between 1 and {}", MAX_RELEASE_PAGE)
+    }
+    let offset = q.offset.unwrap_or(0);
+    if offset < 0 {
+        bad_request!("Offset can't be negative")
+    }
+
+    let module = find_module(&author, &name, &connection)?;
+    let releases = Release::list_for_module(module.id, offset, limit, &connection)?
+        .into_iter()
+        .map(|release| ReleaseResponse {
+            version: release.version,
+            downloads: i64::from(release.downloads),
+            published: rfc3339(&release.published),
+            prerelease: release.prerelease,
+            yanked: release.yanked,
+        })
+        .collect();
+
+    Ok(ApiResponse::Success(VersionsResponse {
+        author: module.author,
+        name: module.name,
+        releases,
+    }))
+}
+
+#[get("/module/<author>/<name>", format="application/json")]
+pub fn module_manifest(author: String, name: String, connection: db::Connection) -> ApiResult<ApiResponse<ModuleManifestResponse>> {
+    info!("Querying releases of {:?}/{:?}", author, name);
+    let manifest = match Module::manifest(&author, &name, &connection)? {
+        Some(manifest) => manifest,
+        // report deleted and moved modules like every other lookup
+        None => {
+            find_module(&author, &name, &connection)?;
+            not_found!("Module does not exist")
+        },
+    };
+    let module = manifest.module;
+    let deprecated = deprecation(&module, &connection)?;
+
+    let releases = manifest.releases.into_iter()
+        .map(|release| ManifestReleaseResponse {
+            version: release.version,
+            downloads: i64::from(release.downloads),
+            published: rfc3339(&release.published),
+            sha256: release.sha256,
+            prerelease: release.prerelease,
+        })
+        .collect();
+
+    Ok(ApiResponse::Success(ModuleManifestResponse {
+        author: module.author,
+        name: module.name,
+        description: module.description,
+        latest: module.latest,
+        deprecated,
+        releases,
+    }))
+}
+
+#[get("/manifest/<author>/<name>", format="application/json")]
+pub fn manifest(author: String, name: String, connection: db::Connection) -> ApiResult<ApiResponse<ManifestResponse>> {
+    info!("Querying manifest of {:?}/{:?}", author, name);
+    let module = find_module(&author, &name, &connection)?;
+    let latest = module.latest
+        .ok_or_else(|| format_err!("Module has no releases"))
+        .not_found()
+        .public_context("Module has no releases")?;
+    let manifest = Release::manifest(module.id, &latest, &connection)
+        .private_context("Failed to read manifest")?;
+    let readme = Release::readme(module.id, &latest, &connection)
+        .private_context("Failed to read readme")?;
+
+    Ok(ApiResponse::Success(ManifestResponse {
+        author: module.author,
+        name: module.name,
+        latest,
+        manifest,
+        readme,
+    }))
+}
+
+#[get("/readme/<author>/<name>", format="application/json")]
+pub fn readme(author: String, name: String, connection: db::Connection) -> ApiResult<ApiResponse<ReadmeResponse>> {
+    info!("Querying readme of {:?}/{:?}", author, name);
+    let module = find_module(&author, &name, &connection)?;
+    let version = module.latest
+        .ok_or_else(|| format_err!("Module has no releases"))
+        .not_found()
+        .public_context("Module has no releases")?;
+    let readme = Release::readme(module.id, &version, &connection)
+        .private_context("Failed to read readme")?;
+
+    Ok(ApiResponse::Success(ReadmeResponse {
+        author: module.author,
+        name: module.name,
+        version,
+        readme,
     }))
 }
 
 #[get("/dl/<author>/<name>/<version>", format="application/json")]
-pub fn download(author: String, name: String, version: String, connection: db::Connection) -> ApiResult<ApiResponse<DownloadResponse>> {
+pub fn download(author: String, name: String, version: String, downloads: State<DownloadCounter>, connection: db::Connection) -> ApiResult<ApiResponse<DownloadResponse>> {
     info!("Downloading {:?}/{:?} ({:?})", author, name, version);
-    let module = Module::find(&author, &name, &connection)
-        .not_found()
-        .public_context("Module does not exist")?;
+    let module = find_module(&author, &name, &connection)?;
     debug!("Module: {:?}", module);
     let release = Release::find(module.id, &version, &connection)
         .not_found()
         .public_context("Release does not exist")?;
     debug!("Release: {:?}", release);
 
-    release.bump_downloads(&connection)?;
+    downloads.record(release.id, &connection);
+    let deprecated = deprecation(&module, &connection)?;
+    let dependencies = release.dependencies(&connection)
+        .private_context("Failed to load dependencies")?;
 
     Ok(ApiResponse::Success(DownloadResponse {
         author,
         name,
         version,
         code: release.code,
+        sha256: Some(release.sha256),
+        deprecated,
+        dependencies,
     }))
 }
 
-#[post("/publish/<name>", format="application/json", data="<upload>")]
-pub fn publish(name: String, upload: Json<PublishRequest>, session: AuthHeader, connection: db::Connection) -> ApiResult<ApiResponse<PublishResponse>> {
-    let user = session.verify(&connection)
-        .bad_request()
-        .public_context("Invalid auth token")?;
-
-    id::valid_name(&user)
-        .bad_request()
-        .public_context("Username is invalid")?;
-    id::valid_name(&name)
-        .bad_request()
-        .public_context("Module name is invalid")?;
-
-    let metadata = upload.code.parse::<Metadata>()
+/// Add a release of `code`, shared by regular and chunked uploads
+#[allow(clippy::too_many_arguments)]
+fn publish_code(user: String, name: String, code: &str, prerelease: bool, allow_unchanged: bool, skip_syntax_check: bool, limits: &PublishLimits, webhook: &Webhook, connection: &db::Connection) -> ApiResult<PublishResponse> {
+    let metadata = code.parse::<Metadata>()
         .bad_request()
         .public_context("Failed to parse module metadata")?;
 
+    if !skip_syntax_check {
+        if let Err(err) = syntax::check(code) {
+            if let Some(err) = err.downcast_ref::<SyntaxError>() {
+                bad_request!("{}", err)
+            }
+            return Err(err).private_context("Failed to check syntax");
+        }
+    }
+
     let version = metadata.version.clone();
     Version::parse(&version)
         .bad_request()
         .public_context("Version is invalid")?;
 
-    connection.transaction::<_, WebError, _>(|| {
-        let module = Module::update_or_create(&user, &name, &metadata.description, &connection)
-            .private_context("Failed to write module metadata")?;
+    let added = connection.transaction::<_, WebError, _>(|| {
+        let module = match Module::update_or_create(&user, &name, &metadata.description, connection) {
+            Ok(module) => module,
+            Err(err) => {
+                if let Some(err) = err.downcast_ref::<NameReserved>() {
+                    bad_request!("{}", err)
+                }
+                if let Some(err) = err.downcast_ref::<ModuleDeleted>() {
+                    bad_request!("{}", err)
+                }
+                return Err(err).private_context("Failed to write module metadata");
+            },
+        };
 
-        match Release::try_find(module.id, &version, &connection)? {
+        match Release::try_find(module.id, &version, connection)? {
             Some(release) => {
                 // if the code is identical, pretend we published the version
-                if release.code != upload.code {
+                if release.code != code {
                     bad_request!("Version number already in use")
                 }
+                Ok(false)
             },
-            None => module.add_version(&version, &upload.code, &connection)
-                .private_context("Failed to add release")?,
-        }
+            None => {
+                let now = SystemTime::now();
+                if let Err(err) = PublishEvent::check(&user, now, limits.max_publishes, connection) {
+                    if let Some(err) = err.downcast_ref::<RateLimited>() {
+                        info!("Rate limiting publish of {:?}", user);
+                        return Err(err_msg(Status::TooManyRequests, err.to_string()));
+                    }
+                    return Err(err).private_context("Failed to check publish rate limit");
+                }
 
-        Ok(())
+                let options = PublishOptions {
+                    readme: metadata.readme.as_deref(),
+                    prerelease,
+                    allow_unchanged,
+                    limits: limits.clone(),
+                };
+                if let Err(err) = module.add_version(&version, code, &options, connection) {
+                    if let Some(err) = err.downcast_ref::<UnchangedRelease>() {
+                        bad_request!("{}, bump the version only if the code changed", err)
+                    }
+                    if let Some(err) = err.downcast_ref::<CodeTooLarge>() {
+                        bad_request!("{}", err)
+                    }
+                    if let Some(err) = err.downcast_ref::<VersionExists>() {
+                        bad_request!("{}", err)
+                    }
+                    if let Some(err) = err.downcast_ref::<InvalidVersion>() {
+                        bad_request!("{}", err)
+                    }
+                    if let Some(err) = err.downcast_ref::<InvalidDependency>() {
+                        bad_request!("{}", err)
+                    }
+                    return Err(err).private_context("Failed to add release");
+                }
+                PublishEvent::record(&user, now, connection)
+                    .private_context("Failed to record publish")?;
+                Ok(true)
+            },
+        }
     })?;
 
-    Ok(ApiResponse::Success(PublishResponse {
+    if added {
+        webhook.notify(ReleaseEvent {
+            author: user.clone(),
+            name: name.clone(),
+            version: version.clone(),
+        });
+    }
+
+    Ok(PublishResponse {
         author: user,
         name,
         version,
+    })
+}
+
+/// Verify the auth token, tokens without the scope are rejected
+fn verify_scope(session: &AuthHeader, scope: &str, connection: &db::Connection) -> ApiResult<String> {
+    match session.verify_scope(scope, connection) {
+        Ok(user) => Ok(user),
+        Err(err) => {
+            if let Some(err) = err.downcast_ref::<MissingScope>() {
+                bad_request!("{}", err)
+            }
+            Err(err)
+                .bad_request()
+                .public_context("Invalid auth token")
+        },
+    }
+}
+
+fn verify_publisher(name: &str, session: &AuthHeader, connection: &db::Connection) -> ApiResult<String> {
+    let user = verify_scope(session, SCOPE_PUBLISH, connection)?;
+
+    if let Err(err) = validate_name("author", &user).and_then(|_| validate_name("name", name)) {
+        bad_request!("{}", err)
+    }
+
+    Ok(user)
+}
+
+#[post("/publish/<name>", format="application/json", data="<upload>")]
+pub fn publish(name: String, upload: Json<PublishRequest>, session: AuthHeader, limits: State<PublishLimits>, webhook: State<Webhook>, connection: db::Connection) -> ApiResult<ApiResponse<PublishResponse>> {
+    let user = verify_publisher(&name, &session, &connection)?;
+    let reply = publish_code(user, name, &upload.code, upload.prerelease, upload.allow_unchanged, upload.skip_syntax_check, &limits, &webhook, &connection)?;
+    Ok(ApiResponse::Success(reply))
+}
+
+fn find_upload(id: i32, user: &str, name: &str, connection: &db::Connection) -> ApiResult<UploadSession> {
+    match UploadSession::find_opt(id, user, name, connection)? {
+        Some(session) => Ok(session),
+        None => not_found!("Upload session does not exist or has expired"),
+    }
+}
+
+fn upload_status(upload: &UploadSession, connection: &db::Connection) -> ApiResult<UploadStatusResponse> {
+    let missing = upload.missing(connection)?;
+    Ok(UploadStatusResponse {
+        session: upload.id,
+        parts: upload.parts as u32,
+        missing: missing.into_iter()
+            .map(|idx| idx as u32)
+            .collect(),
+    })
+}
+
+#[post("/upload/<name>", format="application/json", data="<start>")]
+pub fn upload_start(name: String, start: Json<UploadStartRequest>, session: AuthHeader, connection: db::Connection) -> ApiResult<ApiResponse<UploadStatusResponse>> {
+    let user = verify_publisher(&name, &session, &connection)?;
+
+    if start.parts < 1 || start.parts > MAX_UPLOAD_PARTS {
+        bad_request!("Number of parts must be between 1 and {}", MAX_UPLOAD_PARTS)
+    }
+    if start.checksum.len() != 64 || hex::decode(&start.checksum).is_err() {
+        bad_request!("Checksum must be a hex encoded sha256")
+    }
+
+    let expired = UploadSession::expire(&connection)
+        .private_context("Failed to expire upload sessions")?;
+    if expired > 0 {
+        info!("Expired {} upload sessions", expired);
+    }
+
+    info!("Starting upload of {:?}/{:?} ({} parts)", user, name, start.parts);
+    let upload = UploadSession::create(&NewUploadSession {
+        author: &user,
+        name: &name,
+        checksum: &start.checksum.to_lowercase(),
+        parts: start.parts as i32,
+        allow_unchanged: start.allow_unchanged,
+        prerelease: start.prerelease,
+        skip_syntax_check: start.skip_syntax_check,
+    }, &connection)
+        .private_context("Failed to create upload session")?;
+
+    Ok(ApiResponse::Success(upload_status(&upload, &connection)?))
+}
+
+#[get("/upload/<name>/<id>", format="application/json")]
+pub fn upload_info(name: String, id: i32, session: AuthHeader, connection: db::Connection) -> ApiResult<ApiResponse<UploadStatusResponse>> {
+    let user = verify_publisher(&name, &session, &connection)?;
+    let upload = find_upload(id, &user, &name, &connection)?;
+    Ok(ApiResponse::Success(upload_status(&upload, &connection)?))
+}
+
+#[put("/upload/<name>/<id>/<part>", format="application/json", data="<data>")]
+pub fn upload_part(name: String, id: i32, part: u32, data: Json<UploadPartRequest>, session: AuthHeader, connection: db::Connection) -> ApiResult<ApiResponse<UploadStatusResponse>> {
+    let user = verify_publisher(&name, &session, &connection)?;
+    let upload = find_upload(id, &user, &name, &connection)?;
+
+    if data.data.len() > UPLOAD_PART_SIZE {
+        bad_request!("Part can't be larger than {} bytes", UPLOAD_PART_SIZE)
+    }
+
+    upload.add_part(part as i32, &data.data, &connection)
+        .bad_request()
+        .public_context("Invalid part")?;
+
+    Ok(ApiResponse::Success(upload_status(&upload, &connection)?))
+}
+
+#[post("/upload/<name>/<id>/finalize", format="application/json")]
+pub fn upload_finalize(name: String, id: i32, session: AuthHeader, limits: State<PublishLimits>, webhook: State<Webhook>, connection: db::Connection) -> ApiResult<ApiResponse<PublishResponse>> {
+    let user = verify_publisher(&name, &session, &connection)?;
+    let upload = find_upload(id, &user, &name, &connection)?;
+
+    let code = match upload.assemble(&connection) {
+        Ok(code) => code,
+        Err(err) => {
+            if let Some(err) = err.downcast_ref::<UploadRejected>() {
+                bad_request!("{}", err)
+            }
+            return Err(err).private_context("Failed to assemble upload");
+        },
+    };
+
+    info!("Finalizing upload of {:?}/{:?}", user, name);
+    let reply = publish_code(user, name, &code, upload.prerelease, upload.allow_unchanged, upload.skip_syntax_check, &limits, &webhook, &connection)?;
+    upload.delete(&connection)
+        .private_context("Failed to delete upload session")?;
+
+    Ok(ApiResponse::Success(reply))
+}
+
+#[post("/transfer/<name>", format="application/json", data="<transfer>")]
+pub fn transfer(name: String, transfer: Json<TransferRequest>, session: AuthHeader, connection: db::Connection) -> ApiResult<ApiResponse<TransferResponse>> {
+    let user = verify_scope(&session, SCOPE_PUBLISH, &connection)?;
+
+    // only modules of the authenticated user can be transferred
+    let module = Module::find(&user, &name, &connection)
+        .not_found()
+        .public_context("Module does not exist")?;
+
+    info!("Transferring {:?}/{:?} to {:?}", user, name, transfer.new_author);
+    let module = match module.transfer_ownership(&transfer.new_author, &connection) {
+        Ok(module) => module,
+        Err(err) => {
+            if let Some(err) = err.downcast_ref::<TransferRejected>() {
+                bad_request!("{}", err)
+            }
+            if let Some(err) = err.downcast_ref::<InvalidName>() {
+                bad_request!("{}", err)
+            }
+            return Err(err).private_context("Failed to transfer module");
+        },
+    };
+
+    Ok(ApiResponse::Success(TransferResponse {
+        previous_author: user,
+        author: module.author,
+        name: module.name,
     }))
 }
 
-#[get("/whoami")]
-pub fn whoami(session: AuthHeader, connection: db::Connection) -> ApiResult<ApiResponse<WhoamiResponse>> {
+#[post("/yank/<name>/<version>", format="application/json", data="<yank>")]
+pub fn yank(name: String, version: String, yank: Json<YankRequest>, session: AuthHeader, connection: db::Connection) -> ApiResult<ApiResponse<YankResponse>> {
+    let user = verify_scope(&session, SCOPE_YANK, &connection)?;
+
+    // only releases of the authenticated user can be yanked
+    let module = Module::find(&user, &name, &connection)
+        .not_found()
+        .public_context("Module does not exist")?;
+    let release = Release::find(module.id, &version, &connection)
+        .not_found()
+        .public_context("Release does not exist")?;
+
+    info!("Setting yanked={:?} on {:?}/{:?} ({:?})", yank.yanked, user, name, version);
+    let latest = release.set_yanked(yank.yanked, &connection)
+        .private_context("Failed to yank release")?;
+
+    Ok(ApiResponse::Success(YankResponse {
+        author: module.author,
+        name: module.name,
+        version,
+        yanked: yank.yanked,
+        latest,
+    }))
+}
+
+#[post("/deprecate/<name>", format="application/json", data="<deprecate>")]
+pub fn deprecate(name: String, deprecate: Json<DeprecateRequest>, session: AuthHeader, connection: db::Connection) -> ApiResult<ApiResponse<DeprecateResponse>> {
+    let user = verify_scope(&session, SCOPE_PUBLISH, &connection)?;
+
+    // only modules of the authenticated user can be deprecated
+    let module = Module::find(&user, &name, &connection)
+        .not_found()
+        .public_context("Module does not exist")?;
+
+    let module = if deprecate.deprecated {
+        let successor = match &deprecate.successor {
+            Some(successor) => match Module::find(&successor.author, &successor.name, &connection) {
+                Ok(successor) => Some(successor.id),
+                Err(_) => bad_request!("Successor does not exist"),
+            },
+            None => None,
+        };
+        let msg = deprecate.message.as_deref()
+            .map(str::trim)
+            .filter(|msg| !msg.is_empty());
+
+        info!("Deprecating {:?}/{:?} (successor={:?})", user, name, deprecate.successor);
+        match module.deprecate(msg, successor, &connection) {
+            Ok(module) => module,
+            Err(err) => {
+                if let Some(err) = err.downcast_ref::<InvalidDeprecation>() {
+                    bad_request!("{}", err)
+                }
+                return Err(err).private_context("Failed to deprecate module");
+            },
+        }
+    } else {
+        info!("Undeprecating {:?}/{:?}", user, name);
+        module.undeprecate(&connection)
+            .private_context("Failed to undeprecate module")?
+    };
+
+    let deprecated = deprecation(&module, &connection)?;
+    Ok(ApiResponse::Success(DeprecateResponse {
+        author: module.author,
+        name: module.name,
+        deprecated,
+    }))
+}
+
+/// Soft delete a module, it can be restored until it's purged
+#[post("/delete/<name>")]
+pub fn delete_module(name: String, session: AuthHeader, connection: db::Connection) -> ApiResult<ApiResponse<DeleteResponse>> {
+    let user = verify_scope(&session, SCOPE_PUBLISH, &connection)?;
+
+    // only modules of the authenticated user can be deleted
+    let module = Module::find(&user, &name, &connection)
+        .not_found()
+        .public_context("Module does not exist")?;
+
+    info!("Deleting {:?}/{:?}", user, name);
+    let module = module.soft_delete(&connection)
+        .private_context("Failed to delete module")?;
+
+    Ok(ApiResponse::Success(DeleteResponse {
+        author: module.author,
+        name: module.name,
+        deleted: true,
+    }))
+}
+
+#[post("/restore/<name>")]
+pub fn restore_module(name: String, session: AuthHeader, connection: db::Connection) -> ApiResult<ApiResponse<DeleteResponse>> {
+    let user = verify_scope(&session, SCOPE_PUBLISH, &connection)?;
+
+    let module = match Module::find_opt(&user, &name, &connection)? {
+        Some(module) => module,
+        None => not_found!("Module does not exist"),
+    };
+    if module.deleted_at.is_none() {
+        bad_request!("Module is not deleted")
+    }
+
+    info!("Restoring {:?}/{:?}", user, name);
+    let module = module.restore(&connection)
+        .private_context("Failed to restore module")?;
+
+    Ok(ApiResponse::Success(DeleteResponse {
+        author: module.author,
+        name: module.name,
+        deleted: false,
+    }))
+}
+
+#[post("/report/<author>/<name>", format="application/json", data="<report>")]
+pub fn report(author: String, name: String, report: Json<ReportRequest>, session: AuthHeader, moderation: State<ModerationConfig>, connection: db::Connection) -> ApiResult<ApiResponse<ReportResponse>> {
+    let user = verify_scope(&session, SCOPE_READ, &connection)?;
+
+    let reason = report.reason.trim();
+    if reason.is_empty() {
+        bad_request!("Reason can not be empty")
+    }
+    if reason.len() > MAX_REPORT_REASON_LEN {
+        bad_request!("Reason can not be longer than {} bytes", MAX_REPORT_REASON_LEN)
+    }
+
+    let module = Module::find(&author, &name, &connection)
+        .not_found()
+        .public_context("Module does not exist")?;
+
+    info!("{:?} reported {:?}/{:?}", user, author, name);
+    ModuleReport::submit(&module, &user, reason, &moderation, &connection)
+        .private_context("Failed to report module")?;
+
+    Ok(ApiResponse::Success(ReportResponse {
+        author: module.author,
+        name: module.name,
+    }))
+}
+
+/// Replace the session id, the previous id keeps working for
+/// `TOKEN_ROTATION_GRACE` so running jobs aren't interrupted
+#[post("/rotate")]
+pub fn rotate(session: AuthHeader, connection: db::Connection) -> ApiResult<ApiResponse<RotateResponse>> {
+    let user = verify_scope(&session, SCOPE_READ, &connection)?;
+    let token = session.session(&connection)
+        .bad_request()
+        .public_context("Invalid auth token")?;
+
+    info!("Rotating auth token of {:?}", user);
+    let token = AuthToken::rotate(&token.id, Some(TOKEN_ROTATION_GRACE), &connection)
+        .private_context("Failed to rotate auth token")?;
+    Ok(ApiResponse::Success(RotateResponse {
+        session: token.id,
+    }))
+}
+
+/// Create an additional session for the same account, eg. for a CI job
+#[post("/token", format="application/json", data="<request>")]
+pub fn token(request: Json<TokenRequest>, session: AuthHeader, connection: db::Connection) -> ApiResult<ApiResponse<TokenResponse>> {
     let user = session.verify(&connection)
         .bad_request()
         .public_context("Invalid auth token")?;
+    let parent = session.session(&connection)
+        .bad_request()
+        .public_context("Invalid auth token")?;
+
+    info!("Creating auth token for {:?} with scopes {:?}", user, request.scopes);
+    let expires_in = request.expires_in.map(Duration::from_secs);
+    let token = match parent.mint(&request.scopes, expires_in, &connection) {
+        Ok(token) => token,
+        Err(err) => {
+            if let Some(err) = err.downcast_ref::<ScopeRejected>() {
+                bad_request!("{}", err)
+            }
+            return Err(err).private_context("Failed to create auth token");
+        },
+    };
+    Ok(ApiResponse::Success(TokenResponse {
+        session: token.id,
+    }))
+}
+
+/// Revoke the current session
+#[post("/logout")]
+pub fn logout(session: AuthHeader, connection: db::Connection) -> ApiResult<ApiResponse<()>> {
+    let token = session.session(&connection)
+        .bad_request()
+        .public_context("Invalid auth token")?;
+
+    AuthToken::revoke(&token.id, &connection)
+        .private_context("Failed to revoke auth token")?;
+    Ok(ApiResponse::Success(()))
+}
+
+#[get("/whoami")]
+pub fn whoami(session: AuthHeader, connection: db::Connection) -> ApiResult<ApiResponse<WhoamiResponse>> {
+    let user = verify_scope(&session, SCOPE_READ, &connection)?;
     Ok(ApiResponse::Success(WhoamiResponse {
         user,
     }))
@@ -1,10 +1,15 @@
 use crate::assets::{ASSET_REV, FAVICON, STYLE_SHEET};
+use crate::db;
+use crate::models::{Module, Release};
 use rocket::http::ContentType;
 use rocket::http::Status;
 use rocket::http::hyper::header::{CacheControl, CacheDirective};
 use rocket_contrib::templates::Template;
 
 
+/// Modules in the "you might also like" section of a module page
+const SIMILAR_LIMIT: i64 = 5;
+
 #[get("/")]
 pub fn index() -> Template {
     Template::render("index", hashmap!{
@@ -12,6 +17,60 @@ pub fn index() -> Template {
     })
 }
 
+#[derive(Serialize)]
+struct ModulePage<'a> {
+    #[serde(rename="ASSET_REV")]
+    asset_rev: &'a str,
+    author: String,
+    name: String,
+    description: String,
+    latest: Option<String>,
+    readme: Option<String>,
+    similar: Vec<SimilarModule>,
+}
+
+#[derive(Serialize)]
+struct SimilarModule {
+    author: String,
+    name: String,
+    description: String,
+}
+
+/// Show a module and the readme of its latest release
+#[get("/r/<author>/<name>")]
+pub fn module(author: String, name: String, connection: db::Connection) -> Result<Template, Status> {
+    let module = Module::find_opt(&author, &name, &connection)
+        .map_err(|_| Status::InternalServerError)?
+        .filter(|module| module.deleted_at.is_none())
+        .ok_or(Status::NotFound)?;
+
+    let readme = match &module.latest {
+        Some(latest) => Release::readme(module.id, latest, &connection)
+            .map_err(|_| Status::InternalServerError)?,
+        None => None,
+    };
+
+    let similar = Module::similar(module.id, &connection, SIMILAR_LIMIT)
+        .map_err(|_| Status::InternalServerError)?
+        .into_iter()
+        .map(|module| SimilarModule {
+            author: module.author,
+            name: module.name,
+            description: module.description,
+        })
+        .collect();
+
+    Ok(Template::render("module", ModulePage {
+        asset_rev: ASSET_REV.as_str(),
+        author: module.author,
+        name: module.name,
+        description: module.description,
+        latest: module.latest,
+        readme,
+        similar,
+    }))
+}
+
 #[derive(Responder)]
 pub struct CachableResponder {
     inner: Vec<u8>,
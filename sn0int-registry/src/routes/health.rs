@@ -1,8 +1,85 @@
 use crate::errors::*;
 use crate::db;
+use diesel::RunQueryDsl;
+use diesel::pg::PgConnection;
+use rocket::State;
+use rocket::http::Status;
+use rocket::response::status;
+use rocket_contrib::json::Json;
+use std::time::Duration;
 
 
-#[get("/health")]
-pub fn health(_connection: db::Connection) -> ApiResult<()> {
+/// Probes give up quickly, don't wait for the default pool timeout
+const DB_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct HealthResponse {
+    status: &'static str,
+    database: &'static str,
+    version: &'static str,
+}
+
+fn check_db(connection: &PgConnection) -> Result<()> {
+    diesel::sql_query("SELECT 1")
+        .execute(connection)?;
     Ok(())
 }
+
+/// The process is alive if it can answer at all, it's only ready to serve
+/// requests if the database is reachable
+fn readiness(db: &Result<()>) -> (Status, HealthResponse) {
+    let (code, status, database) = match db {
+        Ok(_) => (Status::Ok, "ready", "ok"),
+        Err(_) => (Status::ServiceUnavailable, "alive", "unavailable"),
+    };
+
+    (code, HealthResponse {
+        status,
+        database,
+        version: env!("CARGO_PKG_VERSION"),
+    })
+}
+
+/// Not rate limited and without auth so load balancers can probe it
+#[get("/health")]
+pub fn health(pool: State<db::Pool>) -> status::Custom<Json<HealthResponse>> {
+    let db = pool.get_timeout(DB_TIMEOUT)
+        .map_err(Error::from)
+        .and_then(|connection| check_db(&connection));
+
+    if let Err(err) = &db {
+        warn!("Health check failed: {}", err);
+    }
+
+    let (status, response) = readiness(&db);
+    status::Custom(status, Json(response))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::tests::with_test_db;
+
+    #[test]
+    fn test_readiness() {
+        let (status, response) = readiness(&Ok(()));
+        assert_eq!(status, Status::Ok);
+        assert_eq!(response, HealthResponse {
+            status: "ready",
+            database: "ok",
+            version: env!("CARGO_PKG_VERSION"),
+        });
+
+        let (status, response) = readiness(&Err(format_err!("connection refused")));
+        assert_eq!(status, Status::ServiceUnavailable);
+        assert_eq!(response.status, "alive");
+        assert_eq!(response.database, "unavailable");
+    }
+
+    /// Needs a postgres database, eg. `DATABASE_URL=postgres://localhost/sn0int cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn test_check_db() {
+        with_test_db(check_db);
+    }
+}
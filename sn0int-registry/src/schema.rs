@@ -6,6 +6,59 @@ table! {
         id -> Varchar,
         author -> Varchar,
         access_token -> Varchar,
+        previous_token_hash -> Nullable<Varchar>,
+        previous_token_expires -> Nullable<Timestamp>,
+        scopes -> Array<Varchar>,
+        expires_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::{TsVector as Tsvector};
+
+    authors (name) {
+        name -> Varchar,
+        verified -> Bool,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::{TsVector as Tsvector};
+
+    download_events (release_id, day) {
+        release_id -> Int4,
+        day -> Date,
+        count -> Int4,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::{TsVector as Tsvector};
+
+    module_reports (id) {
+        id -> Int4,
+        module_id -> Int4,
+        reporter -> Varchar,
+        reason -> Text,
+        created -> Timestamp,
+        resolved -> Bool,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::{TsVector as Tsvector};
+
+    module_transfers (id) {
+        id -> Int4,
+        module_id -> Int4,
+        old_author -> Varchar,
+        new_author -> Varchar,
+        name -> Varchar,
+        transferred -> Timestamp,
     }
 }
 
@@ -21,6 +74,35 @@ table! {
         latest -> Nullable<Varchar>,
         search_vector -> Tsvector,
         featured -> Bool,
+        hidden -> Bool,
+        deprecated -> Bool,
+        deprecated_msg -> Nullable<Text>,
+        successor_id -> Nullable<Int4>,
+        deleted_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::{TsVector as Tsvector};
+
+    publish_events (id) {
+        id -> Int4,
+        author -> Varchar,
+        created -> Timestamp,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::{TsVector as Tsvector};
+
+    release_dependencies (id) {
+        id -> Int4,
+        release_id -> Int4,
+        author -> Varchar,
+        name -> Varchar,
+        version_req -> Varchar,
     }
 }
 
@@ -35,13 +117,91 @@ table! {
         downloads -> Int4,
         code -> Text,
         published -> Timestamp,
+        sha256 -> Varchar,
+        manifest -> Nullable<Text>,
+        prerelease -> Bool,
+        readme -> Nullable<Text>,
+        yanked -> Bool,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::{TsVector as Tsvector};
+
+    reserved_names (id) {
+        id -> Int4,
+        name -> Varchar,
+        prefix -> Bool,
+        owners -> Array<Varchar>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::{TsVector as Tsvector};
+
+    upload_parts (session_id, idx) {
+        session_id -> Int4,
+        idx -> Int4,
+        data -> Text,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::{TsVector as Tsvector};
+
+    upload_sessions (id) {
+        id -> Int4,
+        author -> Varchar,
+        name -> Varchar,
+        checksum -> Varchar,
+        parts -> Int4,
+        allow_unchanged -> Bool,
+        created -> Timestamp,
+        prerelease -> Bool,
+        skip_syntax_check -> Bool,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_full_text_search::{TsVector as Tsvector};
+
+    webhook_deliveries (id) {
+        id -> Int4,
+        url -> Varchar,
+        payload -> Text,
+        signature -> Nullable<Varchar>,
+        attempt -> Int4,
+        status -> Nullable<Int4>,
+        response -> Nullable<Text>,
+        delivered -> Bool,
+        replay_of -> Nullable<Int4>,
+        created -> Timestamp,
     }
 }
 
+joinable!(download_events -> releases (release_id));
+joinable!(module_reports -> modules (module_id));
+joinable!(module_transfers -> modules (module_id));
+joinable!(release_dependencies -> releases (release_id));
 joinable!(releases -> modules (module_id));
+joinable!(upload_parts -> upload_sessions (session_id));
 
 allow_tables_to_appear_in_same_query!(
     auth_tokens,
+    authors,
+    download_events,
+    module_reports,
+    module_transfers,
     modules,
+    publish_events,
+    release_dependencies,
     releases,
+    reserved_names,
+    upload_parts,
+    upload_sessions,
+    webhook_deliveries,
 );
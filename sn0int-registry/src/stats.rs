@@ -0,0 +1,86 @@
+use crate::errors::*;
+use crate::models::PlatformStats;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+
+const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+/// Keeps the platform stats around for a while so the aggregates don't run
+/// on every request
+#[derive(Debug)]
+pub struct StatsCache {
+    ttl: Duration,
+    cached: Mutex<Option<(Instant, PlatformStats)>>,
+}
+
+impl Default for StatsCache {
+    fn default() -> StatsCache {
+        StatsCache::new(DEFAULT_TTL)
+    }
+}
+
+impl StatsCache {
+    pub fn new(ttl: Duration) -> StatsCache {
+        StatsCache {
+            ttl,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Return the cached stats, or compute them with `f` if they expired.
+    /// Failures are not cached.
+    pub fn get<F>(&self, now: Instant, f: F) -> Result<PlatformStats>
+        where F: FnOnce() -> Result<PlatformStats>
+    {
+        let mut cached = self.cached.lock().unwrap();
+
+        if let Some((updated, stats)) = &*cached {
+            if now.duration_since(*updated) < self.ttl {
+                return Ok(stats.clone());
+            }
+        }
+
+        let stats = f()?;
+        *cached = Some((now, stats.clone()));
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(modules: i64) -> PlatformStats {
+        PlatformStats {
+            modules,
+            releases: 0,
+            downloads: 0,
+            active_authors: 0,
+        }
+    }
+
+    #[test]
+    fn test_cached() {
+        let cache = StatsCache::new(Duration::from_secs(60));
+        let now = Instant::now();
+        assert_eq!(cache.get(now, || Ok(stats(1))).unwrap(), stats(1));
+        assert_eq!(cache.get(now + Duration::from_secs(59), || Ok(stats(2))).unwrap(), stats(1));
+    }
+
+    #[test]
+    fn test_expired() {
+        let cache = StatsCache::new(Duration::from_secs(60));
+        let now = Instant::now();
+        assert_eq!(cache.get(now, || Ok(stats(1))).unwrap(), stats(1));
+        assert_eq!(cache.get(now + Duration::from_secs(60), || Ok(stats(2))).unwrap(), stats(2));
+    }
+
+    #[test]
+    fn test_errors_not_cached() {
+        let cache = StatsCache::new(Duration::from_secs(60));
+        let now = Instant::now();
+        assert!(cache.get(now, || bail!("db is down")).is_err());
+        assert_eq!(cache.get(now, || Ok(stats(1))).unwrap(), stats(1));
+    }
+}
@@ -0,0 +1,117 @@
+use crate::errors::*;
+
+use crate::hlua::{Lua, LuaError, LuaFunction};
+
+
+#[derive(Debug, Fail)]
+pub struct SyntaxError {
+    pub line: Option<u32>,
+    pub message: String,
+}
+
+impl std::fmt::Display for SyntaxError {
+    fn fmt(&self, w: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(w, "Syntax error on line {}: {}", line, self.message),
+            None => write!(w, "Syntax error: {}", self.message),
+        }
+    }
+}
+
+impl SyntaxError {
+    /// Lua reports errors as `[string "chunk"]:3: 'end' expected near <eof>`
+    fn from_lua(err: &str) -> SyntaxError {
+        let located = err.find("]:")
+            .filter(|_| err.starts_with("[string "))
+            .and_then(|idx| {
+                let rest = &err[idx+2..];
+                let (line, message) = rest.split_at(rest.find(':')?);
+                let line = line.parse().ok()?;
+                Some((line, message[1..].trim()))
+            });
+
+        match located {
+            Some((line, message)) => SyntaxError {
+                line: Some(line),
+                message: message.to_string(),
+            },
+            None => SyntaxError {
+                line: None,
+                message: err.to_string(),
+            },
+        }
+    }
+}
+
+/// Compile the code without running it to make sure it parses
+pub fn check(code: &str) -> Result<()> {
+    // pass the state by value, hlua panics recovering a borrowed state after a failed load
+    match LuaFunction::load(Lua::new(), code) {
+        Ok(_) => Ok(()),
+        Err(LuaError::SyntaxError(err)) => Err(SyntaxError::from_lua(&err).into()),
+        Err(err) => bail!("Failed to load code: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn syntax_error(code: &str) -> SyntaxError {
+        let err = check(code).expect_err("code should not compile");
+        match err.downcast::<SyntaxError>() {
+            Ok(err) => err,
+            Err(err) => panic!("unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn test_valid_code() {
+        check(r#"-- Description: Valid module
+-- Version: 0.1.0
+-- License: GPL-3.0
+
+function run(arg)
+    x = http_request(nil, 'GET', 'https://example.com', {})
+    if last_err() then return end
+    -- this function doesn't exist, but that only fails at runtime
+    does_not_exist(x)
+end
+"#).unwrap();
+    }
+
+    #[test]
+    fn test_missing_end() {
+        let err = syntax_error(r#"-- Description: Broken module
+-- Version: 0.1.0
+
+function run(arg)
+    if arg then
+        info(arg)
+end
+"#);
+        assert_eq!(err.line, Some(8));
+        assert!(err.message.contains("'end' expected"), "{:?}", err);
+        assert!(err.to_string().starts_with("Syntax error on line 8: "));
+    }
+
+    #[test]
+    fn test_invalid_token() {
+        let err = syntax_error("function run()\n    x = = 1\nend\n");
+        assert_eq!(err.line, Some(2));
+    }
+
+    #[test]
+    fn test_unfinished_string() {
+        let err = syntax_error("function run()\n    info('hello)\nend\n");
+        assert_eq!(err.line, Some(2));
+        assert!(err.message.contains("unfinished string"), "{:?}", err);
+    }
+
+    #[test]
+    fn test_unlocated_error() {
+        let err = SyntaxError::from_lua("something went wrong");
+        assert_eq!(err.line, None);
+        assert_eq!(err.to_string(), "Syntax error: something went wrong");
+    }
+}
@@ -0,0 +1,315 @@
+use crate::errors::*;
+use crate::db::Pool;
+use crate::models::{NewWebhookDelivery, WebhookDelivery};
+use diesel::pg::PgConnection;
+use hmac::{Hmac, Mac};
+use reqwest;
+use sha2::Sha256;
+use std::cmp;
+use std::env;
+use std::io::Read;
+use std::thread;
+use std::time::Duration;
+
+
+const ATTEMPTS: u32 = 5;
+const TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Only the start of the response is kept in the delivery log
+const MAX_LOGGED_RESPONSE: usize = 1024;
+
+#[derive(Debug, Serialize)]
+pub struct ReleaseEvent {
+    pub author: String,
+    pub name: String,
+    pub version: String,
+}
+
+/// The request body and its signature, replays send both unchanged
+#[derive(Debug, Clone, PartialEq)]
+pub struct Payload {
+    pub body: String,
+    pub signature: Option<String>,
+}
+
+impl Payload {
+    pub fn new(event: &ReleaseEvent, secret: Option<&String>) -> Result<Payload> {
+        let body = serde_json::to_string(event)?;
+        let signature = secret.map(|secret| sign(secret, body.as_bytes()));
+        Ok(Payload {
+            body,
+            signature,
+        })
+    }
+}
+
+impl<'a> From<&'a WebhookDelivery> for Payload {
+    fn from(delivery: &'a WebhookDelivery) -> Payload {
+        Payload {
+            body: delivery.payload.clone(),
+            signature: delivery.signature.clone(),
+        }
+    }
+}
+
+/// The outcome of a single delivery attempt
+#[derive(Debug, PartialEq)]
+struct Attempt {
+    status: Option<i32>,
+    response: String,
+    delivered: bool,
+}
+
+/// Notifies an external url about new releases
+#[derive(Default)]
+pub struct Webhook {
+    url: Option<String>,
+    secret: Option<String>,
+    pool: Option<Pool>,
+}
+
+impl Webhook {
+    /// Read WEBHOOK_URL and WEBHOOK_SECRET, the webhook is disabled if no url
+    /// is set. Deliveries are logged to the database.
+    pub fn from_env(pool: Pool) -> Webhook {
+        Webhook {
+            url: env::var("WEBHOOK_URL").ok(),
+            secret: env::var("WEBHOOK_SECRET").ok(),
+            pool: Some(pool),
+        }
+    }
+
+    /// Deliver the event in the background, failed attempts are retried with
+    /// an increasing delay and every attempt is logged
+    pub fn notify(&self, event: ReleaseEvent) {
+        let url = match &self.url {
+            Some(url) => url.clone(),
+            None => return,
+        };
+        let payload = match Payload::new(&event, self.secret.as_ref()) {
+            Ok(payload) => payload,
+            Err(err) => {
+                error!("Failed to serialize webhook: {}", err);
+                return;
+            },
+        };
+        let pool = self.pool.clone();
+
+        thread::spawn(move || {
+            for attempt in 1..=ATTEMPTS {
+                let result = send(&url, &payload);
+
+                if let Some(pool) = &pool {
+                    match pool.get() {
+                        Ok(connection) => if let Err(err) = log(&connection, &url, &payload, attempt as i32, &result, None) {
+                            warn!("Failed to log webhook delivery: {}", err);
+                        },
+                        Err(err) => warn!("Failed to log webhook delivery: {}", err),
+                    }
+                }
+
+                if result.delivered {
+                    info!("Delivered webhook for {}/{} ({})", event.author, event.name, event.version);
+                    return;
+                }
+                warn!("Webhook delivery failed (attempt {}/{}): {}", attempt, ATTEMPTS, result.response);
+
+                if attempt < ATTEMPTS {
+                    thread::sleep(backoff(attempt));
+                }
+            }
+            error!("Giving up on webhook for {}/{} ({})", event.author, event.name, event.version);
+        });
+    }
+
+    /// Send a logged delivery again, to the same url and with the original
+    /// payload so the signature still verifies
+    pub fn replay(&self, delivery: &WebhookDelivery, connection: &PgConnection) -> Result<WebhookDelivery> {
+        let payload = Payload::from(delivery);
+        let result = send(&delivery.url, &payload);
+        log(connection, &delivery.url, &payload, 1, &result, Some(delivery.id))
+    }
+}
+
+/// Wait 1s, 2s, 4s, ... after the given attempt, but never longer than a minute
+fn backoff(attempt: u32) -> Duration {
+    let secs = 1u64 << cmp::min(attempt.saturating_sub(1), 6);
+    cmp::min(Duration::from_secs(secs), MAX_BACKOFF)
+}
+
+fn log(connection: &PgConnection, url: &str, payload: &Payload, attempt: i32, result: &Attempt, replay_of: Option<i32>) -> Result<WebhookDelivery> {
+    WebhookDelivery::create(&NewWebhookDelivery {
+        url,
+        payload: &payload.body,
+        signature: payload.signature.as_deref(),
+        attempt,
+        status: result.status,
+        response: Some(&result.response),
+        delivered: result.delivered,
+        replay_of,
+    }, connection)
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes())
+        .expect("hmac accepts keys of any size");
+    mac.input(body);
+    format!("sha256={}", hex::encode(mac.result().code()))
+}
+
+fn truncate(mut s: String) -> String {
+    if s.len() > MAX_LOGGED_RESPONSE {
+        let mut idx = MAX_LOGGED_RESPONSE;
+        while !s.is_char_boundary(idx) {
+            idx -= 1;
+        }
+        s.truncate(idx);
+    }
+    s
+}
+
+fn deliver(url: &str, payload: &Payload) -> Result<(u16, String)> {
+    let client = reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .build()?;
+    let mut req = client.post(url)
+        .header("Content-Type", "application/json");
+
+    if let Some(signature) = &payload.signature {
+        req = req.header("X-Sn0int-Signature", signature.as_str());
+    }
+
+    let resp = req.body(payload.body.clone()).send()?;
+    let status = resp.status().as_u16();
+
+    let mut body = Vec::new();
+    resp.take(MAX_LOGGED_RESPONSE as u64).read_to_end(&mut body)?;
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
+
+fn send(url: &str, payload: &Payload) -> Attempt {
+    match deliver(url, payload) {
+        Ok((status, response)) => Attempt {
+            status: Some(i32::from(status)),
+            response: truncate(response),
+            delivered: (200..300).contains(&status),
+        },
+        Err(err) => Attempt {
+            status: None,
+            response: truncate(err.to_string()),
+            delivered: false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_sign() {
+        let sig = sign("key", b"The quick brown fox jumps over the lazy dog");
+        assert_eq!(sig, "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8");
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1), Duration::from_secs(1));
+        assert_eq!(backoff(2), Duration::from_secs(2));
+        assert_eq!(backoff(4), Duration::from_secs(8));
+        assert_eq!(backoff(7), MAX_BACKOFF);
+        assert_eq!(backoff(100), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("ohai".to_string()), "ohai");
+        assert_eq!(truncate("a".repeat(2000)).len(), MAX_LOGGED_RESPONSE);
+        // don't split multi byte characters
+        assert_eq!(truncate("ä".repeat(1000)).len(), MAX_LOGGED_RESPONSE);
+        assert_eq!(truncate(format!("a{}", "ä".repeat(1000))).len(), MAX_LOGGED_RESPONSE - 1);
+    }
+
+    /// Answer one request with `status`, report the signature header and body
+    fn serve(status: u16) -> (String, mpsc::Receiver<(Option<String>, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut signature = None;
+            let mut len = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                let lower = line.to_lowercase();
+                if lower.starts_with("x-sn0int-signature:") {
+                    signature = Some(line[19..].trim().to_string());
+                } else if lower.starts_with("content-length:") {
+                    len = line[15..].trim().parse().unwrap();
+                }
+            }
+
+            let mut body = vec![0; len];
+            reader.read_exact(&mut body).unwrap();
+            tx.send((signature, String::from_utf8(body).unwrap())).unwrap();
+
+            let mut stream = stream;
+            write!(stream, "HTTP/1.1 {} X\r\nContent-Length: 4\r\nConnection: close\r\n\r\nohai", status).unwrap();
+        });
+
+        (url, rx)
+    }
+
+    #[test]
+    fn test_send() {
+        let payload = Payload::new(&ReleaseEvent {
+            author: "someone".into(),
+            name: "module".into(),
+            version: "0.1.0".into(),
+        }, Some(&"secret".to_string())).unwrap();
+
+        let (url, rx) = serve(500);
+        let attempt = send(&url, &payload);
+        assert_eq!(attempt, Attempt {
+            status: Some(500),
+            response: "ohai".into(),
+            delivered: false,
+        });
+
+        // the original signature is sent again and still matches the body
+        let (signature, body) = rx.recv().unwrap();
+        assert_eq!(body, payload.body);
+        assert_eq!(signature, Some(sign("secret", body.as_bytes())));
+
+        let (url, rx) = serve(204);
+        assert!(send(&url, &payload).delivered);
+        assert_eq!(rx.recv().unwrap(), (payload.signature.clone(), payload.body.clone()));
+    }
+
+    #[test]
+    fn test_send_unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        drop(listener);
+
+        let payload = Payload {
+            body: "{}".into(),
+            signature: None,
+        };
+        let attempt = send(&url, &payload);
+        assert_eq!(attempt.status, None);
+        assert!(!attempt.delivered);
+        assert!(!attempt.response.is_empty());
+    }
+}
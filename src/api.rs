@@ -9,11 +9,39 @@ use rand::distributions::Alphanumeric;
 use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
 use serde_json;
+use sha2::{Digest, Sha256};
 use sn0int_common::api::*;
 use sn0int_common::{ModuleID, ApiResponse};
 use crate::web;
 
 
+/// How often the upload of missing parts is attempted
+const MAX_UPLOAD_ATTEMPTS: usize = 3;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Split code into parts of at most `size` bytes without breaking characters
+fn split_parts(code: &str, size: usize) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = code;
+
+    while !rest.is_empty() {
+        let mut end = rest.len().min(size);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (part, remaining) = rest.split_at(end);
+        parts.push(part);
+        rest = remaining;
+    }
+
+    parts
+}
+
 pub struct Client {
     server: String,
     client: chrootable_https::Client<chrootable_https::Resolver>,
@@ -22,8 +50,11 @@ pub struct Client {
 
 impl Client {
     pub fn new(config: &Config) -> Result<Client> {
-        let client = match config.network.proxy {
-            Some(proxy) => chrootable_https::Client::with_socks5(proxy),
+        let client = match config.network.proxy()? {
+            Some(proxy) => match proxy.single_socks5() {
+                Some(proxy) => chrootable_https::Client::with_socks5(proxy),
+                None => bail!("The registry can only be reached through a single socks5 proxy"),
+            },
             _ => chrootable_https::Client::with_system_resolver()?,
         };
         Ok(Client {
@@ -93,23 +124,116 @@ impl Client {
         self.request(request, body.into())
     }
 
+    pub fn put<T, S>(&self, url: &str, body: &S) -> Result<T>
+        where T: DeserializeOwned + fmt::Debug,
+              S: Serialize + fmt::Debug,
+    {
+        let url = url.parse::<Uri>()?;
+
+        info!("requesting: {:?}", url);
+        let mut request = Request::put(url);
+        request.header(CONTENT_TYPE, "application/json; charset=utf-8");
+        let body = serde_json::to_string(body)?;
+        self.request(request, body.into())
+    }
+
     pub fn verify_session(&self) -> Result<String> {
         let url = format!("{}/api/v0/whoami", self.server);
         let resp = self.get::<WhoamiResponse>(&url)?;
         Ok(resp.user)
     }
 
-    pub fn publish_module(&self, name: &str, body: String) -> Result<PublishResponse> {
+    /// Replace the session token, returns the new one
+    pub fn rotate_session(&self) -> Result<String> {
+        let url = format!("{}/api/v0/rotate", self.server);
+        let resp = self.post::<RotateResponse, _>(&url, &())?;
+        Ok(resp.session)
+    }
+
+    /// Create an additional session token for the same account
+    pub fn create_token(&self, scopes: Vec<String>, expires_in: Option<u64>) -> Result<String> {
+        let url = format!("{}/api/v0/token", self.server);
+        let resp = self.post::<TokenResponse, _>(&url, &TokenRequest {
+            scopes,
+            expires_in,
+        })?;
+        Ok(resp.session)
+    }
+
+    /// Revoke the session token
+    pub fn logout(&self) -> Result<()> {
+        let url = format!("{}/api/v0/logout", self.server);
+        self.post::<(), _>(&url, &())
+    }
+
+    pub fn publish_module(&self, name: &str, body: String, prerelease: bool, allow_unchanged: bool, skip_syntax_check: bool) -> Result<PublishResponse> {
+        if body.len() > UPLOAD_PART_SIZE {
+            return self.publish_module_chunked(name, &body, prerelease, allow_unchanged, skip_syntax_check);
+        }
+
         let url = format!("{}/api/v0/publish/{}", self.server, name);
         let reply = self.post::<PublishResponse, _>(&url, &PublishRequest {
             code: body,
+            allow_unchanged,
+            prerelease,
+            skip_syntax_check,
         })?;
         Ok(reply)
     }
 
+    /// Upload large modules in parts, parts that failed are retried and
+    /// re-uploaded until the registry has all of them
+    fn publish_module_chunked(&self, name: &str, body: &str, prerelease: bool, allow_unchanged: bool, skip_syntax_check: bool) -> Result<PublishResponse> {
+        let parts = split_parts(body, UPLOAD_PART_SIZE);
+        if parts.len() > MAX_UPLOAD_PARTS as usize {
+            bail!("Module is too large, it can't be uploaded in more than {} parts", MAX_UPLOAD_PARTS);
+        }
+
+        let url = format!("{}/api/v0/upload/{}", self.server, name);
+        let mut status = self.post::<UploadStatusResponse, _>(&url, &UploadStartRequest {
+            checksum: hex(&Sha256::digest(body.as_bytes())),
+            parts: parts.len() as u32,
+            allow_unchanged,
+            prerelease,
+            skip_syntax_check,
+        })?;
+        let url = format!("{}/{}", url, status.session);
+
+        for _ in 0..MAX_UPLOAD_ATTEMPTS {
+            if status.missing.is_empty() {
+                break;
+            }
+
+            for idx in &status.missing {
+                let part = parts.get(*idx as usize)
+                    .ok_or_else(|| format_err!("Registry requested unknown part: {}", idx))?;
+                let result = self.put::<UploadStatusResponse, _>(&format!("{}/{}", url, idx), &UploadPartRequest {
+                    data: part.to_string(),
+                });
+                if let Err(err) = result {
+                    warn!("Failed to upload part {}: {}", idx, err);
+                }
+            }
+
+            status = self.get::<UploadStatusResponse>(&url)?;
+        }
+
+        if !status.missing.is_empty() {
+            bail!("Failed to upload parts {:?}", status.missing);
+        }
+
+        let reply = self.post::<PublishResponse, _>(&format!("{}/finalize", url), &())?;
+        Ok(reply)
+    }
+
     pub fn download_module(&self, module: &ModuleID, version: &str) -> Result<DownloadResponse> {
         let url = format!("{}/api/v0/dl/{}/{}/{}", self.server, module.author, module.name, version);
         let reply = self.get::<DownloadResponse>(&url)?;
+        if let Some(sha256) = &reply.sha256 {
+            if hex(&Sha256::digest(reply.code.as_bytes())) != *sha256 {
+                bail!("Downloaded module doesn't match its sha256, expected {}", sha256);
+            }
+        }
         Ok(reply)
     }
 
@@ -119,6 +243,14 @@ impl Client {
         Ok(reply)
     }
 
+    pub fn query_modules(&self, modules: Vec<ModuleID>) -> Result<BatchInfoResponse> {
+        let url = format!("{}/api/v0/info", self.server);
+        let reply = self.post::<BatchInfoResponse, _>(&url, &BatchInfoRequest {
+            modules,
+        })?;
+        Ok(reply)
+    }
+
     pub fn search(&self, query: &str) -> Result<Vec<SearchResponse>> {
         let url = format!("{}/api/v0/search", self.server);
         let reply = self.get_with::<Vec<SearchResponse>, _>(&url, &hashmap!{
@@ -139,3 +271,22 @@ impl Client {
         Ok(reply)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_parts() {
+        assert_eq!(split_parts("abcdefg", 3), vec!["abc", "def", "g"]);
+        assert_eq!(split_parts("abcdef", 3), vec!["abc", "def"]);
+        assert_eq!(split_parts("", 3), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_split_parts_utf8() {
+        let parts = split_parts("aöbü", 2);
+        assert_eq!(parts, vec!["a", "ö", "b", "ü"]);
+        assert_eq!(parts.concat(), "aöbü");
+    }
+}
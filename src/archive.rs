@@ -2,6 +2,7 @@ use crate::errors::*;
 use tar;
 use libflate::gzip;
 use std::io;
+use std::io::Read;
 use std::fs::File;
 use std::path::Path;
 
@@ -38,3 +39,39 @@ pub fn extract<R: io::Read, P: AsRef<Path>>(read: &mut R, filter: &str, target:
 
     bail!("Nothing in archive matched filter")
 }
+
+/// Decompress gzip data, fails if the output is larger than `limit` bytes
+pub fn gunzip(bytes: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let decoder = gzip::Decoder::new(bytes)
+        .context("Invalid gzip header")?;
+
+    let mut out = Vec::new();
+    decoder.take(limit as u64 + 1).read_to_end(&mut out)
+        .context("Failed to decompress gzip data")?;
+
+    if out.len() > limit {
+        bail!("Decompressed data exceeds {} bytes", limit);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = gzip::Encoder::new(Vec::new()).unwrap();
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().into_result().unwrap()
+    }
+
+    #[test]
+    fn test_gunzip() {
+        let data = gzip(b"hello world");
+        assert_eq!(gunzip(&data, 1024).unwrap(), b"hello world");
+        assert!(gunzip(&data, 5).is_err());
+        assert!(gunzip(b"hello world", 1024).is_err());
+    }
+}
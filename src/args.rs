@@ -3,6 +3,7 @@ use sn0int_common::ModuleID;
 use crate::cmd;
 use crate::options;
 use crate::workspaces::Workspace;
+use std::path::PathBuf;
 
 
 #[derive(Debug, StructOpt)]
@@ -37,6 +38,9 @@ pub enum SubCommand {
     #[structopt(author="", name="login")]
     /// Login to the registry for publishing
     Login(Login),
+    #[structopt(author="", name="logout")]
+    /// Revoke the session token and remove it from this machine
+    Logout(Logout),
     #[structopt(author="", name="new")]
     /// Create a new module
     New(New),
@@ -52,6 +56,15 @@ pub enum SubCommand {
     #[structopt(author="", name="select")]
     /// Select from the database
     Select(cmd::select_cmd::Args),
+    #[structopt(author="", name="watch")]
+    /// Print entities as they are added to the database
+    Watch(cmd::watch_cmd::Args),
+    #[structopt(author="", name="findings")]
+    /// List findings reported by modules
+    Findings(cmd::findings_cmd::Args),
+    #[structopt(author="", name="import")]
+    /// Import entities from scan results
+    Import(cmd::import_cmd::Args),
     #[structopt(author="", name="completions")]
     /// Generate shell completions
     Completions(Completions),
@@ -89,6 +102,20 @@ pub struct Run {
     #[structopt(short="o", long="option")]
     /// Set an option
     pub options: Vec<options::Opt>,
+    #[structopt(short="t", long="target")]
+    /// Run on these targets instead of the scope, supports
+    /// 10.0.0.0/24, host{1..100}, and @file
+    pub targets: Vec<String>,
+    #[structopt(long="fifo", parse(from_os_str))]
+    /// Stream inserts, updates and findings as json lines to this named pipe
+    pub fifo: Option<PathBuf>,
+    #[structopt(long="review-inserts")]
+    /// Ask before anything is added to the database, if stdin isn't a
+    /// terminal the inserts are only logged
+    pub review_inserts: bool,
+    #[structopt(long="resolver")]
+    /// Use these nameservers for dns, they are tried in order
+    pub resolvers: Vec<String>,
 }
 
 #[derive(Debug, StructOpt)]
@@ -99,6 +126,23 @@ pub struct Sandbox {
 
 #[derive(Debug, StructOpt)]
 pub struct Login {
+    /// Replace the session token of an existing login, the previous token
+    /// keeps working for an hour
+    #[structopt(long="rotate")]
+    pub rotate: bool,
+    /// Create an additional session token, eg. for a CI job, and print it
+    #[structopt(long="new-token")]
+    pub new_token: bool,
+    /// Only grant these scopes to the new token: publish, yank or read
+    #[structopt(long="scope")]
+    pub scopes: Vec<String>,
+    /// The new token expires after this many days
+    #[structopt(long="expires")]
+    pub expires: Option<u64>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Logout {
 }
 
 #[derive(Debug, StructOpt)]
@@ -112,13 +156,24 @@ pub struct Publish {
     /// The scripts to publish
     #[structopt(raw(required = "true"))]
     pub paths: Vec<String>,
+    #[structopt(long="allow-unchanged")]
+    /// Publish a new version even if the code didn't change
+    pub allow_unchanged: bool,
+    #[structopt(long="prerelease")]
+    /// Publish a beta that doesn't become the latest version
+    pub prerelease: bool,
+    #[structopt(long="skip-syntax-check")]
+    /// Publish even if the registry can't compile the code, eg. because it
+    /// uses syntax of a newer lua version
+    pub skip_syntax_check: bool,
 }
 
 #[derive(Debug, StructOpt)]
 pub struct Install {
     /// The script to install
     pub module: ModuleID,
-    /// Specify the version, defaults to the latest version
+    /// Specify the version, defaults to the latest version that isn't a
+    /// prerelease
     pub version: Option<String>,
 }
 
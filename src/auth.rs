@@ -5,6 +5,7 @@ use std::path::PathBuf;
 use std::thread;
 use std::time::Duration;
 use crate::api::Client;
+use crate::args::Login;
 use crate::config::Config;
 use crate::paths;
 use crate::term;
@@ -25,9 +26,26 @@ pub fn save_token(session: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn run_login(config: &Config) -> Result<()> {
+pub fn run_login(login: &Login, config: &Config) -> Result<()> {
     let mut client = Client::new(config)?;
 
+    if login.new_token {
+        client.authenticate(load_token().context("Not logged in")?);
+        let expires_in = login.expires.map(|days| days * 24 * 3600);
+        let session = client.create_token(login.scopes.clone(), expires_in)?;
+        term::success("Created session token");
+        println!("{}", session);
+        return Ok(());
+    }
+
+    if login.rotate {
+        client.authenticate(load_token().context("Not logged in")?);
+        let session = client.rotate_session()?;
+        save_token(&session)?;
+        term::success("Rotated session token");
+        return Ok(());
+    }
+
     if let Ok(session) = load_token() {
         client.authenticate(session);
         if let Ok(user) = client.verify_session() {
@@ -55,3 +73,24 @@ pub fn run_login(config: &Config) -> Result<()> {
 
     bail!("Authentication timed out")
 }
+
+pub fn run_logout(config: &Config) -> Result<()> {
+    let session = match load_token() {
+        Ok(session) => session,
+        Err(_) => {
+            term::info("Not logged in");
+            return Ok(());
+        },
+    };
+
+    let mut client = Client::new(config)?;
+    client.authenticate(session);
+    // the local token is removed even if the registry can't be reached
+    if let Err(err) = client.logout() {
+        term::warn(&format!("Failed to revoke session token: {}", err));
+    }
+
+    fs::remove_file(path()?)?;
+    term::success("Logged out");
+    Ok(())
+}
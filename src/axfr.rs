@@ -0,0 +1,382 @@
+use crate::errors::*;
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+use trust_dns_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use trust_dns_proto::rr::{self, Name, RecordType};
+use trust_dns_proto::serialize::binary::BinEncoder;
+
+
+/// Returned if the server doesn't allow the transfer
+#[derive(Debug, Fail, PartialEq)]
+pub enum AxfrError {
+    #[fail(display="Zone transfer refused ({})", _0)]
+    Refused(String),
+    #[fail(display="Zone transfer refused, connection closed without a response")]
+    Closed,
+}
+
+fn rcode_name(rcode: ResponseCode) -> String {
+    match rcode {
+        ResponseCode::FormErr => "FORMERR".to_string(),
+        ResponseCode::ServFail => "SERVFAIL".to_string(),
+        ResponseCode::NXDomain => "NXDOMAIN".to_string(),
+        ResponseCode::NotImp => "NOTIMP".to_string(),
+        ResponseCode::Refused => "REFUSED".to_string(),
+        ResponseCode::NotAuth => "NOTAUTH".to_string(),
+        _ => format!("RCODE{}", u16::from(rcode)),
+    }
+}
+
+fn type_name(rtype: RecordType) -> String {
+    match rtype {
+        RecordType::Unknown(code) => format!("TYPE{}", code),
+        _ => rtype.to_string(),
+    }
+}
+
+/// Names are returned without the trailing dot, like the answers of `dns`
+fn name_to_string(name: &Name) -> String {
+    if name.is_root() {
+        ".".to_string()
+    } else {
+        name.to_string().trim_end_matches('.').to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SOA {
+    pub mname: String,
+    pub rname: String,
+    pub serial: u32,
+    pub refresh: i32,
+    pub retry: i32,
+    pub expire: i32,
+    pub minimum: u32,
+}
+
+/// Record data, serialized like the answers of `dns`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum RData {
+    A(Ipv4Addr),
+    AAAA(Ipv6Addr),
+    CNAME(String),
+    MX((u16, String)),
+    NS(String),
+    PTR(String),
+    SOA(SOA),
+    SRV((String, u16)),
+    TXT(String),
+    /// Hex encoded data of record types that aren't decoded
+    Other(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Record {
+    pub name: String,
+    #[serde(rename="type")]
+    pub rtype: String,
+    pub ttl: u32,
+    pub data: RData,
+}
+
+impl<'a> From<&'a rr::RData> for RData {
+    fn from(rdata: &'a rr::RData) -> RData {
+        match rdata {
+            rr::RData::A(ip) => RData::A(*ip),
+            rr::RData::AAAA(ip) => RData::AAAA(*ip),
+            rr::RData::CNAME(name) => RData::CNAME(name_to_string(name)),
+            rr::RData::MX(mx) => RData::MX((mx.preference(), name_to_string(mx.exchange()))),
+            rr::RData::NS(name) => RData::NS(name_to_string(name)),
+            rr::RData::PTR(name) => RData::PTR(name_to_string(name)),
+            rr::RData::SOA(soa) => RData::SOA(SOA {
+                mname: name_to_string(soa.mname()),
+                rname: name_to_string(soa.rname()),
+                serial: soa.serial(),
+                refresh: soa.refresh(),
+                retry: soa.retry(),
+                expire: soa.expire(),
+                minimum: soa.minimum(),
+            }),
+            rr::RData::SRV(srv) => RData::SRV((name_to_string(srv.target()), srv.port())),
+            rr::RData::TXT(txt) => {
+                let txt = txt.iter()
+                    .flat_map(|x| x.iter().cloned())
+                    .collect::<Vec<_>>();
+                RData::TXT(String::from_utf8_lossy(&txt).to_string())
+            },
+            _ => RData::Other(hex(rdata)),
+        }
+    }
+}
+
+impl<'a> From<&'a rr::Record> for Record {
+    fn from(record: &'a rr::Record) -> Record {
+        Record {
+            name: name_to_string(record.name()),
+            rtype: type_name(record.rr_type()),
+            ttl: record.ttl(),
+            data: RData::from(record.rdata()),
+        }
+    }
+}
+
+/// Hex encode the data of record types that aren't decoded
+fn hex(rdata: &rr::RData) -> String {
+    let mut buf = Vec::new();
+    if rdata.emit(&mut BinEncoder::new(&mut buf)).is_err() {
+        return String::new();
+    }
+    buf.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Build an AXFR query for a zone, prefixed with its length for tcp
+pub fn query(zone: &str, id: u16) -> Result<Vec<u8>> {
+    let name = Name::from_ascii(zone)
+        .map_err(|err| format_err!("Invalid zone {:?}: {}", zone, err))?;
+
+    let mut msg = Message::new();
+    msg.set_id(id)
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .add_query(Query::query(name, RecordType::AXFR));
+    let msg = msg.to_vec()?;
+
+    let mut out = (msg.len() as u16).to_be_bytes().to_vec();
+    out.extend(msg);
+    Ok(out)
+}
+
+/// Collects the records of the messages of a zone transfer
+#[derive(Debug)]
+pub struct Transfer {
+    pub records: Vec<Record>,
+    /// Set once the closing SOA record has been received
+    pub complete: bool,
+    id: u16,
+    max_records: usize,
+}
+
+impl Transfer {
+    /// Start a transfer for the query with this message id
+    pub fn new(id: u16, max_records: usize) -> Transfer {
+        Transfer {
+            records: Vec::new(),
+            complete: false,
+            id,
+            max_records,
+        }
+    }
+
+    /// Add the records of a message, without its length prefix. Returns true
+    /// if the transfer is over, either because it's complete or because the
+    /// maximum number of records has been reached.
+    pub fn add(&mut self, msg: &[u8]) -> Result<bool> {
+        let msg = Message::from_vec(msg)?;
+        if msg.id() != self.id {
+            bail!("Response id doesn't match the query: {:#06x}", msg.id());
+        }
+
+        let rcode = msg.response_code();
+        if rcode != ResponseCode::NoError {
+            return Err(AxfrError::Refused(rcode_name(rcode)).into());
+        }
+
+        for record in msg.answers() {
+            let soa = record.rr_type() == RecordType::SOA;
+
+            if self.records.is_empty() && !soa {
+                bail!("Zone transfer didn't start with a SOA record");
+            }
+            let closing = soa && !self.records.is_empty();
+
+            self.records.push(Record::from(record));
+
+            if closing {
+                self.complete = true;
+                return Ok(true);
+            }
+            if self.records.len() >= self.max_records {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    const TYPE_A: u16 = 1;
+    const TYPE_CNAME: u16 = 5;
+    const TYPE_SOA: u16 = 6;
+    const TYPE_MX: u16 = 15;
+    const TYPE_TXT: u16 = 16;
+    const TYPE_AXFR: u16 = 252;
+    const CLASS_IN: u16 = 1;
+
+    fn encode_name(out: &mut Vec<u8>, name: &str) {
+        for label in name.split('.') {
+            out.push(label.len() as u8);
+            out.extend(label.as_bytes());
+        }
+        out.push(0);
+    }
+
+    fn record(msg: &mut Vec<u8>, name: &str, rtype: u16, rdata: &[u8]) {
+        encode_name(msg, name);
+        msg.extend(&rtype.to_be_bytes());
+        msg.extend(&CLASS_IN.to_be_bytes());
+        msg.extend(&3600u32.to_be_bytes());
+        msg.extend(&(rdata.len() as u16).to_be_bytes());
+        msg.extend(rdata);
+    }
+
+    pub fn soa() -> Vec<u8> {
+        let mut rdata = Vec::new();
+        encode_name(&mut rdata, "ns1.example.com");
+        encode_name(&mut rdata, "hostmaster.example.com");
+        for x in &[2019u32, 7200, 3600, 1_209_600, 300] {
+            rdata.extend(&x.to_be_bytes());
+        }
+        rdata
+    }
+
+    /// A response to an AXFR query, without the length prefix
+    pub fn response(rcode: u8, records: &[(&str, u16, Vec<u8>)]) -> Vec<u8> {
+        let mut msg = vec![0x13, 0x37, 0x84, rcode, 0, 1, 0, records.len() as u8, 0, 0, 0, 0];
+        encode_name(&mut msg, "example.com");
+        msg.extend(&TYPE_AXFR.to_be_bytes());
+        msg.extend(&CLASS_IN.to_be_bytes());
+        for (name, rtype, rdata) in records {
+            record(&mut msg, name, *rtype, rdata);
+        }
+        msg
+    }
+
+    #[test]
+    fn test_query() {
+        let q = query("example.com.", 0x1337).unwrap();
+        assert_eq!(q, b"\x00\x1d\x13\x37\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x07example\x03com\x00\x00\xfc\x00\x01".to_vec());
+
+        assert!(query("foo..example.com", 1).is_err());
+        assert!(query(&format!("{}.com", "a".repeat(64)), 1).is_err());
+    }
+
+    #[test]
+    fn test_transfer() {
+        let mut mx = vec![0, 10];
+        encode_name(&mut mx, "mail.example.com");
+
+        let mut transfer = Transfer::new(0x1337, 100);
+        let done = transfer.add(&response(0, &[
+            ("example.com", TYPE_SOA, soa()),
+            ("www.example.com", TYPE_A, vec![192, 0, 2, 1]),
+            ("example.com", TYPE_MX, mx),
+        ])).unwrap();
+        assert!(!done);
+
+        let done = transfer.add(&response(0, &[
+            ("example.com", TYPE_TXT, b"\x05hello\x06 world".to_vec()),
+            ("example.com", 99, vec![0xff]),
+            ("example.com", TYPE_SOA, soa()),
+        ])).unwrap();
+        assert!(done);
+        assert!(transfer.complete);
+
+        let records = &transfer.records;
+        assert_eq!(records.len(), 6);
+        assert_eq!(records[0].data, RData::SOA(SOA {
+            mname: "ns1.example.com".to_string(),
+            rname: "hostmaster.example.com".to_string(),
+            serial: 2019,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1_209_600,
+            minimum: 300,
+        }));
+        assert_eq!(records[1], Record {
+            name: "www.example.com".to_string(),
+            rtype: "A".to_string(),
+            ttl: 3600,
+            data: RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+        });
+        assert_eq!(records[2].data, RData::MX((10, "mail.example.com".to_string())));
+        assert_eq!(records[3].data, RData::TXT("hello world".to_string()));
+        assert_eq!(records[4].rtype, "TYPE99");
+        assert_eq!(records[4].data, RData::Other("ff".to_string()));
+    }
+
+    #[test]
+    fn test_transfer_max_records() {
+        let mut transfer = Transfer::new(0x1337, 2);
+        let done = transfer.add(&response(0, &[
+            ("example.com", TYPE_SOA, soa()),
+            ("a.example.com", TYPE_A, vec![192, 0, 2, 1]),
+            ("b.example.com", TYPE_A, vec![192, 0, 2, 2]),
+        ])).unwrap();
+        assert!(done);
+        assert!(!transfer.complete);
+        assert_eq!(transfer.records.len(), 2);
+    }
+
+    #[test]
+    fn test_transfer_refused() {
+        let err = Transfer::new(0x1337, 100).add(&response(5, &[])).unwrap_err();
+        assert_eq!(err.downcast_ref::<AxfrError>(), Some(&AxfrError::Refused("REFUSED".to_string())));
+
+        let err = Transfer::new(0x1337, 100).add(&response(0, &[
+            ("www.example.com", TYPE_A, vec![192, 0, 2, 1]),
+        ]));
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_transfer_wrong_id() {
+        let mut transfer = Transfer::new(0x1338, 100);
+        let err = transfer.add(&response(0, &[("example.com", TYPE_SOA, soa())]));
+        assert!(err.is_err());
+        assert!(transfer.records.is_empty());
+    }
+
+    #[test]
+    fn test_compressed_names() {
+        let mut msg = response(0, &[("example.com", TYPE_SOA, soa())]);
+        msg[7] = 2;
+        // www + pointer to example.com in the question at offset 12
+        msg.extend(b"\x03www\xc0\x0c");
+        msg.extend(&TYPE_CNAME.to_be_bytes());
+        msg.extend(&CLASS_IN.to_be_bytes());
+        msg.extend(&60u32.to_be_bytes());
+        msg.extend(&[0, 2, 0xc0, 0x0c]);
+
+        let mut transfer = Transfer::new(0x1337, 100);
+        transfer.add(&msg).unwrap();
+        assert_eq!(transfer.records[1].name, "www.example.com");
+        assert_eq!(transfer.records[1].data, RData::CNAME("example.com".to_string()));
+    }
+
+    #[test]
+    fn test_compression_loop() {
+        let mut msg = response(0, &[]);
+        msg[7] = 1;
+        // the name of the answer points to itself
+        let pos = msg.len() as u8;
+        msg.extend(&[0xc0, pos]);
+        msg.extend(&TYPE_SOA.to_be_bytes());
+        msg.extend(&CLASS_IN.to_be_bytes());
+        msg.extend(&60u32.to_be_bytes());
+        msg.extend(&[0, 0]);
+        assert!(Transfer::new(0x1337, 100).add(&msg).is_err());
+    }
+
+    #[test]
+    fn test_truncated_record() {
+        let mut msg = response(0, &[("example.com", TYPE_SOA, soa())]);
+        msg.truncate(msg.len() - 3);
+        assert!(Transfer::new(0x1337, 100).add(&msg).is_err());
+    }
+}
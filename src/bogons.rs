@@ -0,0 +1,157 @@
+use crate::errors::*;
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+
+/// Private networks, RFC 1918 and unique local addresses
+pub const PRIVATE: &[&str] = &[
+    "10.0.0.0/8",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "fc00::/7",
+];
+
+/// Reserved networks that are never routed on the internet, this includes
+/// the private networks
+pub const BOGONS: &[&str] = &[
+    // ipv4
+    "0.0.0.0/8",        // this network
+    "10.0.0.0/8",       // private
+    "100.64.0.0/10",    // carrier grade nat
+    "127.0.0.0/8",      // loopback
+    "169.254.0.0/16",   // link local
+    "172.16.0.0/12",    // private
+    "192.0.0.0/24",     // ietf protocol assignments
+    "192.0.2.0/24",     // documentation
+    "192.88.99.0/24",   // 6to4 relay anycast
+    "192.168.0.0/16",   // private
+    "198.18.0.0/15",    // benchmarking
+    "198.51.100.0/24",  // documentation
+    "203.0.113.0/24",   // documentation
+    "224.0.0.0/4",      // multicast
+    "240.0.0.0/4",      // reserved, includes broadcast
+    // ipv6
+    "::/128",           // unspecified
+    "::1/128",          // loopback
+    "::ffff:0:0/96",    // ipv4 mapped, checked against the ipv4 list instead
+    "64:ff9b:1::/48",   // local nat64
+    "100::/64",         // discard only
+    "2001:2::/48",      // benchmarking
+    "2001:db8::/32",    // documentation
+    "2001:10::/28",     // deprecated orchid
+    "2002::/16",        // 6to4
+    "3fff::/20",        // documentation
+    "fc00::/7",         // unique local
+    "fe80::/10",        // link local
+    "fec0::/10",        // deprecated site local
+    "ff00::/8",         // multicast
+];
+
+fn parse_network(net: &str) -> Result<(IpAddr, u8)> {
+    let idx = net.find('/')
+        .ok_or_else(|| format_err!("Network is missing a prefix: {:?}", net))?;
+    let addr = net[..idx].parse()?;
+    let prefix = net[idx+1..].parse()?;
+    Ok((addr, prefix))
+}
+
+fn parse_list(list: &[&str]) -> Vec<(IpAddr, u8)> {
+    list.iter()
+        .map(|net| parse_network(net).expect("Invalid network in list"))
+        .collect()
+}
+
+lazy_static! {
+    static ref PRIVATE_NETWORKS: Vec<(IpAddr, u8)> = parse_list(PRIVATE);
+    static ref BOGON_NETWORKS: Vec<(IpAddr, u8)> = parse_list(BOGONS);
+}
+
+fn v4_contains(net: Ipv4Addr, prefix: u8, ip: Ipv4Addr) -> bool {
+    let mask = if prefix == 0 { 0 } else { !0u32 << (32 - u32::from(prefix)) };
+    u32::from(net) & mask == u32::from(ip) & mask
+}
+
+fn v6_contains(net: Ipv6Addr, prefix: u8, ip: Ipv6Addr) -> bool {
+    let mask = if prefix == 0 { 0 } else { !0u128 << (128 - u32::from(prefix)) };
+    u128::from(net) & mask == u128::from(ip) & mask
+}
+
+fn ipv4_mapped(ip: &Ipv6Addr) -> Option<Ipv4Addr> {
+    match ip.segments() {
+        [0, 0, 0, 0, 0, 0xffff, a, b] => Some(Ipv4Addr::from((u32::from(a) << 16) | u32::from(b))),
+        _ => None,
+    }
+}
+
+fn matches(list: &[(IpAddr, u8)], ip: &IpAddr) -> bool {
+    // ipv4 mapped addresses are checked like the ipv4 address they contain
+    let ip = match ip {
+        IpAddr::V6(v6) => match ipv4_mapped(v6) {
+            Some(v4) => IpAddr::V4(v4),
+            None => *ip,
+        },
+        _ => *ip,
+    };
+
+    list.iter()
+        .any(|net| match (*net, ip) {
+            ((IpAddr::V4(net), prefix), IpAddr::V4(ip)) => v4_contains(net, prefix, ip),
+            ((IpAddr::V6(net), prefix), IpAddr::V6(ip)) => v6_contains(net, prefix, ip),
+            _ => false,
+        })
+}
+
+/// Returns true if the address is in a reserved range and can't be reached over the internet
+pub fn is_bogon(ip: &IpAddr) -> bool {
+    matches(&BOGON_NETWORKS, ip)
+}
+
+pub fn is_private(ip: &IpAddr) -> bool {
+    matches(&PRIVATE_NETWORKS, ip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_lists_are_valid() {
+        for net in BOGONS.iter().chain(PRIVATE) {
+            parse_network(net).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_bogon_v4() {
+        assert!(is_bogon(&ip("127.0.0.1")));
+        assert!(is_bogon(&ip("10.1.2.3")));
+        assert!(is_bogon(&ip("172.31.255.255")));
+        assert!(is_bogon(&ip("192.0.2.1")));
+        assert!(is_bogon(&ip("255.255.255.255")));
+        assert!(!is_bogon(&ip("172.32.0.1")));
+        assert!(!is_bogon(&ip("1.1.1.1")));
+    }
+
+    #[test]
+    fn test_bogon_v6() {
+        assert!(is_bogon(&ip("::1")));
+        assert!(is_bogon(&ip("fe80::1")));
+        assert!(is_bogon(&ip("2001:db8::1")));
+        assert!(is_bogon(&ip("::ffff:192.168.1.1")));
+        assert!(!is_bogon(&ip("::ffff:1.1.1.1")));
+        assert!(!is_bogon(&ip("2606:4700:4700::1111")));
+    }
+
+    #[test]
+    fn test_private() {
+        assert!(is_private(&ip("192.168.1.1")));
+        assert!(is_private(&ip("fd00::1")));
+        assert!(!is_private(&ip("127.0.0.1")));
+        assert!(!is_private(&ip("100.64.0.1")));
+        assert!(!is_private(&ip("8.8.8.8")));
+    }
+}
@@ -2,6 +2,8 @@ use crate::errors::*;
 
 use crate::models::*;
 use crate::shell::Readline;
+use sn0int_common::metadata::Source;
+use std::net;
 use structopt::StructOpt;
 use structopt::clap::AppSettings;
 use crate::utils;
@@ -259,6 +261,42 @@ impl IntoInsert for AddBreach {
     }
 }
 
+/// Turn a target passed to `run` into an entity of the type the module expects
+pub fn target_into_insert(rl: &Readline, source: &Source, target: String) -> Result<Insert> {
+    match source {
+        Source::Domains => AddDomain { domain: Some(target) }.into_insert(rl),
+        Source::Subdomains => AddSubdomain { subdomain: Some(target) }.into_insert(rl),
+        Source::IpAddrs => {
+            let family = match target.parse::<net::IpAddr>() {
+                Ok(net::IpAddr::V4(_)) => "4",
+                Ok(net::IpAddr::V6(_)) => "6",
+                Err(_) => bail!("This is not a valid ip address: {:?}", target),
+            };
+
+            Ok(Insert::IpAddr(NewIpAddr {
+                family: family.to_string(),
+                value: target,
+                continent: None,
+                continent_code: None,
+                country: None,
+                country_code: None,
+                city: None,
+                latitude: None,
+                longitude: None,
+                asn: None,
+                as_org: None,
+                description: None,
+                reverse_dns: None,
+            }))
+        },
+        Source::Emails => AddEmail { email: Some(target) }.into_insert(rl),
+        Source::PhoneNumbers => AddPhoneNumber { phonenumber: Some(target), name: None }.into_insert(rl),
+        Source::Networks => AddNetwork { network: Some(target), latitude: None, longitude: None }.into_insert(rl),
+        Source::Devices => AddDevice { mac: Some(target), name: None }.into_insert(rl),
+        _ => bail!("Targets can't be generated for this module source"),
+    }
+}
+
 pub fn run(rl: &mut Readline, args: &[String]) -> Result<()> {
     let args = Args::from_iter_safe(args)?;
     let insert = match args {
@@ -28,6 +28,10 @@ pub enum Args {
     Devices(Filter),
     #[structopt(name="networks")]
     Networks(Filter),
+    #[structopt(name="ports")]
+    Ports(Filter),
+    #[structopt(name="cryptoaddrs")]
+    CryptoAddrs(Filter),
 }
 
 #[derive(Debug, StructOpt)]
@@ -52,6 +56,8 @@ pub fn run(rl: &mut Readline, args: &[String]) -> Result<()> {
         Args::PhoneNumbers(filter) => delete::<PhoneNumber>(rl, &filter),
         Args::Devices(filter) => delete::<Device>(rl, &filter),
         Args::Networks(filter) => delete::<Network>(rl, &filter),
+        Args::Ports(filter) => delete::<Port>(rl, &filter),
+        Args::CryptoAddrs(filter) => delete::<CryptoAddr>(rl, &filter),
     }?;
     term::info(&format!("Deleted {} rows", rows));
     Ok(())
@@ -0,0 +1,119 @@
+use crate::errors::*;
+
+use crate::cmd::Cmd;
+use crate::db::findings::{Finding, Severity};
+use crate::sarif;
+use crate::shell::Readline;
+use crate::term::{Level, TERM_SETTINGS};
+use serde_json;
+use std::str::FromStr;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(author = "",
+            raw(global_settings = "&[AppSettings::ColoredHelp]"))]
+pub struct Args {
+    #[structopt(short="s", long="severity", default_value="info")]
+    /// Only show findings with at least this severity
+    severity: Severity,
+    #[structopt(long="json")]
+    /// Json output
+    json: bool,
+    #[structopt(subcommand)]
+    subcommand: Option<Subcommand>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Subcommand {
+    #[structopt(name="export")]
+    /// Export findings for other tools
+    Export(ExportArgs),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ExportArgs {
+    #[structopt(short="s", long="severity", default_value="info")]
+    /// Only export findings with at least this severity
+    severity: Severity,
+    #[structopt(long="format")]
+    /// The output format, currently only sarif
+    format: Format,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Sarif,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Format> {
+        match s {
+            "sarif" => Ok(Format::Sarif),
+            _ => bail!("Unknown export format: {:?}", s),
+        }
+    }
+}
+
+fn export(rl: &mut Readline, args: &ExportArgs) -> Result<()> {
+    let db = rl.db();
+    let findings = Finding::list(db, args.severity)?;
+
+    match args.format {
+        Format::Sarif => {
+            let log = sarif::export(db, findings)?;
+            println!("{}", serde_json::to_string_pretty(&log)?);
+        },
+    }
+
+    Ok(())
+}
+
+fn colored_severity(severity: &str) -> String {
+    let label = format!("[{}]", severity);
+    let level = match severity {
+        "critical" | "high" => Level::Error,
+        "medium" => Level::Warn,
+        _ => Level::Info,
+    };
+    TERM_SETTINGS.paint(level, &label)
+}
+
+impl Cmd for Args {
+    fn run(&self, rl: &mut Readline) -> Result<()> {
+        if let Some(Subcommand::Export(args)) = &self.subcommand {
+            return export(rl, args);
+        }
+
+        let db = rl.db();
+
+        for finding in Finding::list(db, self.severity)? {
+            if self.json {
+                println!("{}", serde_json::to_string(&finding)?);
+                continue;
+            }
+
+            println!("#{}, {} {}", finding.id, colored_severity(&finding.severity), finding.title);
+            match finding.entity(db) {
+                Some(Ok(label)) => println!("\t{}: {:?}", finding.family.as_ref().unwrap(), label),
+                Some(Err(_)) => println!("\t{}: (deleted)", finding.family.as_ref().unwrap()),
+                None => (),
+            }
+            if let Some(details) = &finding.details {
+                for line in details.lines() {
+                    println!("\t{}", line);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[inline]
+pub fn run(rl: &mut Readline, args: &[String]) -> Result<()> {
+    Args::run_str(rl, args)
+}
@@ -1,18 +1,20 @@
 use crate::errors::*;
 
 use crate::shell::Readline;
+use crate::term::{Level, TERM_SETTINGS};
 
 
 #[inline]
 fn help(name: &str, descr: &str) {
-    println!("    \x1b[32m{:13}\x1b[0m {}", name, descr);
+    println!("    {} {}", TERM_SETTINGS.paint(Level::Info, &format!("{:13}", name)), descr);
 }
 
 pub fn run(_rl: &mut Readline, _args: &[String]) -> Result<()> {
 
-    println!("\n\x1b[33mCOMMANDS:\x1b[0m");
+    println!("\n{}", TERM_SETTINGS.paint(Level::Warn, "COMMANDS:"));
     help("add",        "Add new entities to the database");
     help("delete",     "Delete entities from the database");
+    help("findings",   "List findings reported by modules");
     help("keyring",    "Manage saved credentials");
     help("mod",        "Manage installed modules");
     help("noscope",    "Exclude entities from scope");
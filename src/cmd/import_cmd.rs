@@ -0,0 +1,89 @@
+use crate::errors::*;
+
+use crate::cmd::Cmd;
+use crate::import;
+use crate::import::csv::Mapping;
+use crate::shell::Readline;
+use crate::term;
+use sn0int_common::metadata::Source;
+use std::fs;
+use std::path::PathBuf;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(author = "",
+            raw(global_settings = "&[AppSettings::ColoredHelp]"))]
+pub enum Args {
+    #[structopt(author="", name="nmap")]
+    /// Import hosts, open ports and hostnames from nmap xml output (-oX)
+    Nmap(ImportFile),
+    #[structopt(author="", name="masscan")]
+    /// Import hosts, open ports and banners from masscan json output (-oJ)
+    Masscan(ImportFile),
+    #[structopt(author="", name="csv")]
+    /// Import entities from a csv file, columns are mapped to fields
+    Csv(ImportCsv),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ImportFile {
+    #[structopt(parse(from_os_str))]
+    /// Path to the file that is imported
+    path: PathBuf,
+}
+
+impl ImportFile {
+    fn read(&self) -> Result<String> {
+        fs::read_to_string(&self.path)
+            .context(format!("Failed to read {:?}", self.path))
+            .map_err(Error::from)
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ImportCsv {
+    #[structopt(flatten)]
+    file: ImportFile,
+    #[structopt(long="type")]
+    /// The type of entities, like domains or emails
+    source: Source,
+    #[structopt(long="map", raw(required="true", number_of_values="1"))]
+    /// Read a field from a column, the column is either a number starting
+    /// at 1 or the name in the header, eg. value=1 or value=domain
+    map: Vec<Mapping>,
+    #[structopt(long="header")]
+    /// Skip the first row, this is implied if a column is referenced by name
+    header: bool,
+}
+
+impl ImportCsv {
+    fn run(&self, rl: &mut Readline) -> Result<()> {
+        let stats = import::import_csv(rl, &self.source, &self.file.read()?, &self.map, self.header)?;
+        term::info(&format!("Imported entities ({}), skipped {} rows",
+            stats.entities, stats.skipped));
+        Ok(())
+    }
+}
+
+impl Cmd for Args {
+    fn run(&self, rl: &mut Readline) -> Result<()> {
+        let scan = match self {
+            Args::Nmap(file) => import::nmap::parse(&file.read()?)?,
+            Args::Masscan(file) => import::masscan::parse(&file.read()?)?,
+            Args::Csv(csv) => return csv.run(rl),
+        };
+
+        let stats = import::import(rl, scan)?;
+        term::info(&format!("Imported ipaddrs ({}), ports ({}) and subdomains ({})",
+            stats.ipaddrs, stats.ports, stats.subdomains));
+
+        Ok(())
+    }
+}
+
+#[inline]
+pub fn run(rl: &mut Readline, args: &[String]) -> Result<()> {
+    Args::run_str(rl, args)
+}
@@ -13,7 +13,9 @@ pub trait Cmd: structopt::StructOpt + Sized {
 
 pub mod add_cmd;
 pub mod delete_cmd;
+pub mod findings_cmd;
 pub mod help_cmd;
+pub mod import_cmd;
 pub mod run_cmd;
 pub mod use_cmd;
 pub mod select_cmd;
@@ -24,4 +26,5 @@ pub mod set_cmd;
 pub mod scope_cmd;
 pub mod target_cmd;
 pub mod quickstart_cmd;
+pub mod watch_cmd;
 pub mod workspace_cmd;
@@ -2,18 +2,23 @@ use crate::errors::*;
 
 use crate::args;
 use crate::db::{Database, Filter};
+use crate::db::state::ModuleState;
 use crate::db::ttl;
+use crate::cmd::add_cmd;
 use crate::engine::Module;
+use crate::fifo::Fifo;
 use crate::models::*;
 use crate::shell::Readline;
 use crate::keyring::KeyRing;
+use crate::targets;
 use crate::term;
 use crate::utils;
-use crate::worker;
+use crate::worker::{self, Review};
 use serde::Serialize;
 use serde_json;
 use sn0int_common::metadata::Source;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use structopt::StructOpt;
 use structopt::clap::AppSettings;
 
@@ -26,6 +31,14 @@ pub struct Args {
     threads: usize,
     #[structopt(short="v", long="verbose", parse(from_occurrences))]
     verbose: u64,
+    #[structopt(short="t", long="target")]
+    targets: Vec<String>,
+    #[structopt(long="fifo", parse(from_os_str))]
+    fifo: Option<PathBuf>,
+    #[structopt(long="review-inserts")]
+    review_inserts: bool,
+    #[structopt(long="resolver")]
+    resolvers: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -37,6 +50,21 @@ pub struct Params<'a> {
     pub grant_full_keyring: bool,
     pub deny_keyring: bool,
     pub exit_on_error: bool,
+    pub targets: Vec<String>,
+    pub fifo: Option<PathBuf>,
+    pub review: Option<Review>,
+    pub resolvers: Vec<String>,
+}
+
+/// Ask for every insert if we can, otherwise only log them
+fn review_mode(enabled: bool, stdin: bool) -> Option<Review> {
+    if !enabled {
+        None
+    } else if !stdin && atty::is(atty::Stream::Stdin) {
+        Some(Review::Confirm)
+    } else {
+        Some(Review::Log)
+    }
 }
 
 impl<'a> From<&'a args::Run> for Params<'a> {
@@ -49,6 +77,10 @@ impl<'a> From<&'a args::Run> for Params<'a> {
             grant_full_keyring: args.grant_full_keyring,
             deny_keyring: args.deny_keyring,
             exit_on_error: args.exit_on_error,
+            targets: args.targets.clone(),
+            fifo: args.fifo.clone(),
+            review: review_mode(args.review_inserts, args.stdin),
+            resolvers: args.resolvers.clone(),
         }
     }
 }
@@ -63,6 +95,10 @@ impl From<Args> for Params<'static> {
             grant_full_keyring: false,
             deny_keyring: false,
             exit_on_error: false,
+            targets: args.targets,
+            fifo: args.fifo,
+            review: review_mode(args.review_inserts, false),
+            resolvers: args.resolvers,
         }
     }
 }
@@ -80,6 +116,60 @@ fn prepare_args<T: Scopable + Serialize + Model>(db: &Database, filter: &Filter,
         .collect()
 }
 
+fn prepare_target<T: Serialize + Model>(db: &Database, insert: Insert) -> Result<Option<(serde_json::Value, Option<String>)>> {
+    match db.insert_generic(insert)? {
+        Some((_, id)) => prepare_arg(T::by_id(db, id)?).map(Some),
+        // entity exists but has been removed from scope
+        None => Ok(None),
+    }
+}
+
+/// Expand the target generators and add every target to the database before
+/// the module runs on it
+fn prepare_targets<'a>(rl: &'a Readline, module: &'a Module, exprs: &[String]) -> Result<impl Iterator<Item=Result<(serde_json::Value, Option<String>)>> + 'a> {
+    let source = match module.source() {
+        Some(source) => source,
+        None => bail!("Module doesn't have a source, targets can't be used"),
+    };
+
+    // networks are entities themselves, don't expand them into addresses
+    let cidr = source != &Source::Networks;
+    let generators = targets::parse(exprs, cidr)?;
+    let size = targets::count(&generators, cidr)?;
+    if size > targets::CONFIRM_THRESHOLD {
+        let msg = format!("Targets expand to {} entities, continue?", size);
+        if !utils::no_else_yes(&msg)? {
+            bail!("Aborted");
+        }
+    }
+
+    let iter = targets::chain(generators, cidr)
+        .filter_map(move |target| {
+            let arg = target.and_then(|target| {
+                let insert = add_cmd::target_into_insert(rl, source, target)?;
+                let db = rl.db();
+                match source {
+                    Source::Domains => prepare_target::<Domain>(db, insert),
+                    Source::Subdomains => prepare_target::<Subdomain>(db, insert),
+                    Source::IpAddrs => prepare_target::<IpAddr>(db, insert),
+                    Source::Emails => prepare_target::<Email>(db, insert),
+                    Source::PhoneNumbers => prepare_target::<PhoneNumber>(db, insert),
+                    Source::Networks => prepare_target::<Network>(db, insert),
+                    Source::Devices => prepare_target::<Device>(db, insert),
+                    _ => unreachable!(),
+                }
+            });
+
+            match arg {
+                Ok(Some(arg)) => Some(Ok(arg)),
+                Ok(None) => None,
+                Err(err) => Some(Err(err)),
+            }
+        });
+
+    Ok(iter)
+}
+
 fn prepare_keyring(keyring: &mut KeyRing, module: &Module, params: &Params) -> Result<()> {
     for namespace in keyring.unauthorized_namespaces(&module) {
         let grant_access = if params.deny_keyring {
@@ -103,13 +193,8 @@ fn prepare_keyring(keyring: &mut KeyRing, module: &Module, params: &Params) -> R
     Ok(())
 }
 
-pub fn execute(rl: &mut Readline, params: Params, options: HashMap<String, String>) -> Result<()> {
-    let module = rl.module()
-        .map(|m| m.to_owned())
-        .ok_or_else(|| format_err!("No module selected"))?;
-
-    prepare_keyring(rl.keyring_mut(), &module, &params)?;
-
+/// Every entity in scope that matches the module source
+fn prepare_scoped_args(rl: &Readline, module: &Module) -> Result<Vec<(serde_json::Value, Option<String>)>> {
     let filter = rl.scoped_targets();
 
     let args = match module.source() {
@@ -124,7 +209,7 @@ pub fn execute(rl: &mut Readline, params: Params, options: HashMap<String, Strin
         Some(Source::Accounts(service)) => prepare_args::<Account>(rl.db(), &filter, service.as_ref()),
         Some(Source::KeyRing(namespace)) => {
             let keyring = rl.keyring();
-            if keyring.is_access_granted(&module, &namespace) {
+            if keyring.is_access_granted(module, &namespace) {
                 keyring.get_all_for(&namespace).into_iter()
                     .map(|key| {
                         let pretty = format!("{}:{}", key.namespace, key.access_key);
@@ -139,9 +224,65 @@ pub fn execute(rl: &mut Readline, params: Params, options: HashMap<String, Strin
         None => Ok(vec![(serde_json::Value::Null, None)]),
     }?;
 
+    Ok(args)
+}
+
+fn spawn<I>(rl: &Readline, module: &Module, args: I, params: &Params, options: HashMap<String, String>, fifo: Option<&Fifo>) -> usize
+    where I: IntoIterator<Item=Result<(serde_json::Value, Option<String>)>>
+{
+    let proxy = match rl.config().network.proxy() {
+        Ok(proxy) => proxy,
+        Err(err) => {
+            term::error(&format!("Invalid proxy config: {}", err));
+            return 1;
+        },
+    };
+
     rl.signal_register().catch_ctrl();
-    let errors = worker::spawn(rl, &module, args, &params, rl.config().network.proxy.clone(), options);
+    let errors = worker::spawn(rl, module, args, params, proxy, options, fifo);
     rl.signal_register().reset_ctrlc();
+    errors
+}
+
+pub fn execute(rl: &mut Readline, params: Params, options: HashMap<String, String>) -> Result<()> {
+    let module = rl.module()
+        .map(|m| m.to_owned())
+        .ok_or_else(|| format_err!("No module selected"))?;
+
+    if module.capabilities().is_none() {
+        term::warn(&format!("{} doesn't declare its capabilities, allowing everything", module.canonical()));
+    }
+    if module.produces().is_none() {
+        term::warn(&format!("{} doesn't declare the entities it produces, allowing all inserts", module.canonical()));
+    }
+
+    let options = module.validate_options(options)?;
+
+    prepare_keyring(rl.keyring_mut(), &module, &params)?;
+
+    if !module.keep_state() {
+        ModuleState::clear_outdated(rl.db(), &module.canonical(), module.version())?;
+    }
+
+    let fifo = match &params.fifo {
+        Some(path) => Some(Fifo::open(path)?),
+        None => None,
+    };
+
+    let errors = if !params.targets.is_empty() {
+        let args = prepare_targets(rl, &module, &params.targets)?;
+        spawn(rl, &module, args, &params, options, fifo.as_ref())
+    } else {
+        let args = prepare_scoped_args(rl, &module)?;
+        spawn(rl, &module, args.into_iter().map(Ok), &params, options, fifo.as_ref())
+    };
+
+    if let Some(fifo) = fifo {
+        let dropped = fifo.finish();
+        if dropped > 0 {
+            term::warn(&format!("Dropped {} events, nobody was reading from the fifo fast enough", dropped));
+        }
+    }
 
     if errors > 0 {
         term::info(&format!("Finished {} ({} errors)", module.canonical(), errors));
@@ -1,6 +1,8 @@
 use crate::errors::*;
 
+use crate::cmd::noscope_cmd;
 use crate::db;
+use crate::scope_templates::{RuleAction, ScopeRule, ScopeTemplates, TemplateParam};
 use crate::shell::Readline;
 use structopt::StructOpt;
 use structopt::clap::AppSettings;
@@ -24,6 +26,48 @@ pub enum Args {
     Emails(Filter),
     #[structopt(name="phonenumbers")]
     PhoneNumbers(Filter),
+    #[structopt(name="apply")]
+    /// Apply a scope template to the current workspace
+    Apply(ScopeApply),
+    #[structopt(name="template")]
+    /// Manage scope templates
+    Template(TemplateArgs),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ScopeApply {
+    name: String,
+    /// Template parameters as key=value
+    params: Vec<TemplateParam>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum TemplateArgs {
+    #[structopt(name="add")]
+    /// Append a rule to a template, creating it if needed
+    Add(TemplateAdd),
+    #[structopt(name="delete")]
+    /// Delete a template
+    Delete(TemplateDelete),
+    #[structopt(name="list")]
+    /// List all templates and their rules
+    List,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct TemplateAdd {
+    name: String,
+    /// Either scope or noscope
+    action: RuleAction,
+    /// The entity family, eg. domains
+    family: String,
+    /// The filter, placeholders are written as {{key}}
+    args: Vec<String>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct TemplateDelete {
+    name: String,
 }
 
 #[derive(Debug, StructOpt)]
@@ -46,6 +90,8 @@ pub fn run(rl: &mut Readline, args: &[String]) -> Result<()> {
         Args::Urls(filter) => scope::<Url>(rl, &filter),
         Args::Emails(filter) => scope::<Email>(rl, &filter),
         Args::PhoneNumbers(filter) => scope::<PhoneNumber>(rl, &filter),
+        Args::Apply(apply) => return apply_template(rl, &apply),
+        Args::Template(args) => return template(args),
     }?;
     term::info(&format!("Updated {} rows", rows));
     Ok(())
@@ -54,3 +100,45 @@ pub fn run(rl: &mut Readline, args: &[String]) -> Result<()> {
 fn scope<T: Model + Detailed>(rl: &mut Readline, filter: &Filter) -> Result<usize> {
     rl.db().scope::<T>(&filter.parse()?)
 }
+
+fn apply_template(rl: &mut Readline, apply: &ScopeApply) -> Result<()> {
+    let templates = ScopeTemplates::init()?;
+    let rules = templates.get(&apply.name)
+        .ok_or_else(|| format_err!("Template doesn't exist: {:?}", apply.name))?;
+
+    // instantiate everything first so we don't apply half of a template
+    let rules = rules.iter()
+        .map(|rule| Ok((rule.action, rule.instantiate(&apply.params)?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    for (action, args) in rules {
+        match action {
+            RuleAction::Scope => run(rl, &args)?,
+            RuleAction::Noscope => noscope_cmd::run(rl, &args)?,
+        }
+    }
+
+    Ok(())
+}
+
+fn template(args: TemplateArgs) -> Result<()> {
+    let mut templates = ScopeTemplates::init()?;
+
+    match args {
+        TemplateArgs::Add(add) => {
+            let rule = ScopeRule::new(add.action, add.family, add.args)?;
+            templates.insert(add.name, rule)?;
+        },
+        TemplateArgs::Delete(delete) => templates.delete(&delete.name)?,
+        TemplateArgs::List => {
+            for (name, rules) in templates.list() {
+                println!("{}:", name);
+                for rule in rules {
+                    println!("    {}", rule);
+                }
+            }
+        },
+    }
+
+    Ok(())
+}
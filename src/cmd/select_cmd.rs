@@ -2,10 +2,12 @@ use crate::errors::*;
 
 use crate::cmd::Cmd;
 use crate::db;
+use crate::db::confidence;
 use crate::db::ttl;
 use crate::shell::Readline;
 use serde::Serialize;
 use serde_json;
+use std::str::FromStr;
 use structopt::StructOpt;
 use structopt::clap::AppSettings;
 use crate::models::*;
@@ -20,6 +22,12 @@ pub struct Args {
     #[structopt(long="json")]
     /// Json output
     json: bool,
+    #[structopt(long="format")]
+    /// Print every entity with a template like `{value}`, or the name of a builtin template
+    format: Option<Template>,
+    #[structopt(long="min-confidence")]
+    /// Only select entities with at least this confidence, 0-100 or low, medium, high
+    min_confidence: Option<String>,
 }
 
 #[derive(Debug, StructOpt)]
@@ -54,6 +62,12 @@ pub enum Target {
     #[structopt(name="breaches")]
     /// Select breaches
     Breaches(Filter),
+    #[structopt(name="ports")]
+    /// Select ports
+    Ports(Filter),
+    #[structopt(name="cryptoaddrs")]
+    /// Select cryptocurrency addresses
+    CryptoAddrs(Filter),
 }
 
 #[derive(Debug, StructOpt)]
@@ -63,28 +77,163 @@ pub struct Filter {
 
 impl Filter {
     pub fn parse(&self) -> Result<db::Filter> {
-        db::Filter::parse_optional(&self.args)
+        db::Filter::parse_ordered(&self.args)
+    }
+}
+
+/// Templates that can be used with `--format` by name
+pub const BUILTIN_TEMPLATES: &[(&str, &str)] = &[
+    ("value", "{value}"),
+    ("id", "{id}\t{value}"),
+    ("markdown", "- {value}"),
+    ("hostport", "{ip_addr}:{port}"),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Column(String),
+}
+
+/// A format string like `{ip_addr}:{port}`, `{{` and `}}` are literal braces
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    fn parse(s: &str) -> Result<Template> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                },
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                },
+                '{' => {
+                    let mut column = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => column.push(c),
+                            None => bail!("Template has an unclosed `{{`"),
+                        }
+                    }
+
+                    let column = column.trim();
+                    if column.is_empty() {
+                        bail!("Template has an empty placeholder");
+                    }
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(literal.split_off(0)));
+                    }
+                    segments.push(Segment::Column(column.to_string()));
+                },
+                '}' => bail!("Template has a `}}` without `{{`, use `}}}}` for a literal brace"),
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Template {
+            segments,
+        })
+    }
+
+    fn columns(&self) -> impl Iterator<Item=&str> {
+        self.segments.iter()
+            .filter_map(|segment| match segment {
+                Segment::Column(column) => Some(column.as_str()),
+                Segment::Literal(_) => None,
+            })
+    }
+
+    pub fn validate(&self, columns: &[String]) -> Result<()> {
+        for column in self.columns() {
+            if !columns.iter().any(|c| c == column) {
+                bail!("Unknown column: {:?}", column);
+            }
+        }
+        Ok(())
+    }
+
+    /// Values are inserted as they are, nothing is escaped
+    pub fn render<T: Serialize>(&self, obj: &T) -> Result<String> {
+        let obj = serde_json::to_value(obj)?;
+        let mut out = String::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => out.push_str(literal),
+                Segment::Column(column) => match &obj[column] {
+                    serde_json::Value::Null => (),
+                    serde_json::Value::String(value) => out.push_str(value),
+                    value => out.push_str(&value.to_string()),
+                },
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+impl FromStr for Template {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Template> {
+        let template = BUILTIN_TEMPLATES.iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, template)| *template)
+            .unwrap_or(s);
+        Template::parse(template)
     }
 }
 
 pub struct Printer<'a, 'b> {
     rl: &'a mut Readline<'b>,
     json: bool,
+    format: Option<Template>,
+    min_confidence: Option<i32>,
 }
 
 impl<'a, 'b> Printer<'a, 'b> {
-    pub fn new(rl: &'a mut Readline<'b>, json: bool) -> Printer<'a, 'b> {
+    pub fn new(rl: &'a mut Readline<'b>, json: bool, format: Option<Template>, min_confidence: Option<i32>) -> Printer<'a, 'b> {
         Printer {
             rl,
             json,
+            format,
+            min_confidence,
         }
     }
 
     pub fn select<T: Model + Detailed + Serialize>(&self, filter: &Filter) -> Result<()> {
-        for obj in self.rl.db().filter::<T>(&filter.parse()?)? {
+        let mut filter = filter.parse()?;
+        let columns = self.rl.db().columns::<T>()?;
+        filter.validate_order(&columns)?;
+        if let Some(format) = &self.format {
+            format.validate(&columns)?;
+        }
+        if let Some(min) = self.min_confidence {
+            filter = filter.and_min_confidence(T::table(), min);
+        }
+
+        for obj in self.rl.db().filter::<T>(&filter)? {
             if self.json {
                 let v = serde_json::to_string(&obj)?;
                 println!("{}", v);
+            } else if let Some(format) = &self.format {
+                println!("{}", format.render(&obj)?);
             } else {
                 println!("{}", obj.detailed(self.rl.db())?);
             }
@@ -96,7 +245,14 @@ impl<'a, 'b> Printer<'a, 'b> {
 
 impl Cmd for Args {
     fn run(&self, rl: &mut Readline) -> Result<()> {
-        let printer = Printer::new(rl, self.json);
+        let min_confidence = match &self.min_confidence {
+            Some(min) => Some(confidence::parse(min)?),
+            None => None,
+        };
+        if self.json && self.format.is_some() {
+            bail!("--json and --format can't be used together");
+        }
+        let printer = Printer::new(rl, self.json, self.format.clone(), min_confidence);
 
         match &self.subcommand {
             Target::Domains(filter) => printer.select::<Domain>(&filter),
@@ -109,6 +265,8 @@ impl Cmd for Args {
             Target::Networks(filter) => printer.select::<Network>(&filter),
             Target::Accounts(filter) => printer.select::<Account>(&filter),
             Target::Breaches(filter) => printer.select::<Breach>(&filter),
+            Target::Ports(filter) => printer.select::<Port>(&filter),
+            Target::CryptoAddrs(filter) => printer.select::<CryptoAddr>(&filter),
         }
     }
 }
@@ -118,3 +276,52 @@ pub fn run(rl: &mut Readline, args: &[String]) -> Result<()> {
     ttl::reap_expired(rl.db())?;
     Args::run_str(rl, args)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns(x: &[&str]) -> Vec<String> {
+        x.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_render_template() {
+        let template = "{ip_addr}:{port} {{{status}}}".parse::<Template>().unwrap();
+        let port = serde_json::json!({
+            "ip_addr": "192.0.2.1",
+            "port": 22,
+            "status": "open",
+            "banner": null,
+        });
+        assert_eq!(template.render(&port).unwrap(), "192.0.2.1:22 {open}");
+
+        // nothing is escaped and null is empty
+        let template = "{ip_addr},{banner}".parse::<Template>().unwrap();
+        assert_eq!(template.render(&port).unwrap(), "192.0.2.1,");
+    }
+
+    #[test]
+    fn test_builtin_template() {
+        let template = "id".parse::<Template>().unwrap();
+        let domain = serde_json::json!({"id": 3, "value": "example.com"});
+        assert_eq!(template.render(&domain).unwrap(), "3\texample.com");
+
+        assert_eq!("hostport".parse::<Template>().unwrap(), "{ip_addr}:{port}".parse::<Template>().unwrap());
+    }
+
+    #[test]
+    fn test_invalid_template() {
+        assert!("{value".parse::<Template>().is_err());
+        assert!("value}".parse::<Template>().is_err());
+        assert!("{ }".parse::<Template>().is_err());
+    }
+
+    #[test]
+    fn test_validate_template() {
+        let template = "hostport".parse::<Template>().unwrap();
+        template.validate(&columns(&["id", "ip_addr", "port"])).unwrap();
+        let err = template.validate(&columns(&["id", "value"])).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown column: \"ip_addr\"");
+    }
+}
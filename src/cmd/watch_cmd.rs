@@ -0,0 +1,89 @@
+use crate::errors::*;
+
+use crate::cmd::Cmd;
+use crate::cmd::select_cmd::{Filter, Target};
+use crate::shell::Readline;
+use serde::Serialize;
+use serde_json;
+use std::thread;
+use std::time::Duration;
+use structopt::StructOpt;
+use structopt::clap::AppSettings;
+use crate::models::*;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(author = "",
+            raw(global_settings = "&[AppSettings::ColoredHelp]"))]
+pub struct Args {
+    #[structopt(subcommand)]
+    subcommand: Target,
+    #[structopt(long="json")]
+    /// Json output
+    json: bool,
+    #[structopt(long="scoped")]
+    /// Only show entities that are in scope
+    scoped: bool,
+    #[structopt(long="interval", default_value="250")]
+    /// Check for new entities every this many milliseconds
+    interval: u64,
+}
+
+impl Args {
+    /// Print entities that are added after the watch started until ctrl-c
+    /// is pressed. Another process, like `sn0int run`, writes to the
+    /// workspace, so the database is polled.
+    fn watch<T: Model + Detailed + Serialize>(&self, rl: &mut Readline, filter: &Filter) -> Result<()> {
+        let filter = filter.parse()?;
+        filter.validate_order(&rl.db().columns::<T>()?)?;
+        let filter = if self.scoped {
+            filter.and_scoped()
+        } else {
+            filter
+        };
+
+        let mut last = rl.db().max_id::<T>()?.unwrap_or(0);
+        let interval = Duration::from_millis(self.interval);
+
+        let signal_register = rl.signal_register().clone();
+        rl.set_signal_handler()?;
+        signal_register.catch_ctrl();
+
+        while !signal_register.ctrlc_received() {
+            for obj in rl.db().filter::<T>(&filter.and_id_above(last))? {
+                last = last.max(obj.id());
+
+                if self.json {
+                    let v = serde_json::to_string(&obj)?;
+                    println!("{}", v);
+                } else {
+                    println!("{}", obj.detailed(rl.db())?);
+                }
+            }
+
+            thread::sleep(interval);
+        }
+
+        signal_register.reset_ctrlc();
+        Ok(())
+    }
+}
+
+impl Cmd for Args {
+    fn run(&self, rl: &mut Readline) -> Result<()> {
+        match &self.subcommand {
+            Target::Domains(filter) => self.watch::<Domain>(rl, filter),
+            Target::Subdomains(filter) => self.watch::<Subdomain>(rl, filter),
+            Target::IpAddrs(filter) => self.watch::<IpAddr>(rl, filter),
+            Target::Urls(filter) => self.watch::<Url>(rl, filter),
+            Target::Emails(filter) => self.watch::<Email>(rl, filter),
+            Target::PhoneNumbers(filter) => self.watch::<PhoneNumber>(rl, filter),
+            Target::Devices(filter) => self.watch::<Device>(rl, filter),
+            Target::Networks(filter) => self.watch::<Network>(rl, filter),
+            Target::Accounts(filter) => self.watch::<Account>(rl, filter),
+            Target::Breaches(filter) => self.watch::<Breach>(rl, filter),
+            Target::Ports(filter) => self.watch::<Port>(rl, filter),
+            Target::CryptoAddrs(filter) => self.watch::<CryptoAddr>(rl, filter),
+        }
+    }
+}
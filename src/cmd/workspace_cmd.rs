@@ -1,6 +1,6 @@
 use crate::errors::*;
 
-use crate::db::Database;
+use crate::db::{encryption, Database};
 use crate::shell::Readline;
 use structopt::StructOpt;
 use structopt::clap::AppSettings;
@@ -18,7 +18,8 @@ pub fn run(rl: &mut Readline, args: &[String]) -> Result<()> {
     let args = Args::from_iter_safe(args)?;
 
     if let Some(workspace) = args.workspace {
-        let db = Database::establish(workspace)?;
+        let passphrase = encryption::passphrase(rl.config(), rl.keyring(), &workspace)?;
+        let db = Database::establish(workspace, passphrase)?;
         rl.set_db(db);
     } else {
         for x in workspaces::list()? {
@@ -10,6 +10,7 @@ use std::str::FromStr;
 use std::io::stdout;
 use structopt::StructOpt;
 use crate::shell::Command;
+use crate::term::TERM_SETTINGS;
 use crate::workspaces;
 
 
@@ -35,7 +36,9 @@ impl CmdCompleter {
                             "devices",
                             "networks",
                             "accounts",
-                            "breaches"];
+                            "breaches",
+                            "ports",
+                            "cryptoaddrs"];
 
             let results: Vec<String> = options.iter()
                 .filter(|x| x.starts_with(arg))
@@ -214,7 +217,11 @@ impl Highlighter for CmdCompleter {
 
     #[inline]
     fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
-        Owned("\x1b[90m".to_owned() + hint + "\x1b[m")
+        if TERM_SETTINGS.colors() {
+            Owned("\x1b[90m".to_owned() + hint + "\x1b[m")
+        } else {
+            Borrowed(hint)
+        }
     }
 }
 
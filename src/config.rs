@@ -1,9 +1,13 @@
 use dirs;
 use crate::errors::*;
+use chrootable_https::dns::Resolver;
+use crate::connector::{TlsConfig, TlsProfile};
+use crate::proxy::{Proxy, ProxyChain};
 use std::collections::HashMap;
 use std::fs;
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
-use std::net::SocketAddr;
+use std::time::Duration;
 use toml;
 
 
@@ -15,6 +19,10 @@ pub struct Config {
     pub namespaces: HashMap<String, PathBuf>,
     #[serde(default)]
     pub network: NetworkConfig,
+    #[serde(default)]
+    pub filesystem: FilesystemConfig,
+    #[serde(default)]
+    pub workspaces: HashMap<String, WorkspaceConfig>,
 }
 
 impl Config {
@@ -47,6 +55,58 @@ impl Config {
 
         Ok(config)
     }
+
+    /// Read the insert hook of a workspace, this happens outside of the sandbox
+    pub fn insert_hook(&self, workspace: &str) -> Result<Option<String>> {
+        match self.workspaces.get(workspace) {
+            Some(config) => config.insert_hook(),
+            None => Ok(None),
+        }
+    }
+
+    /// Workspaces that are encrypted with sqlcipher
+    pub fn is_encrypted(&self, workspace: &str) -> bool {
+        self.workspaces.get(workspace)
+            .map(|config| config.encrypt)
+            .unwrap_or(false)
+    }
+
+    /// The nameservers of the dns function, they are tried in order. The
+    /// resolvers of a run take precedence over the workspace and the network
+    /// section, without any configured resolvers the system resolvers are used.
+    pub fn resolver(&self, workspace: &str, run: &[String]) -> Result<Resolver> {
+        let resolvers = if !run.is_empty() {
+            run
+        } else {
+            match self.workspaces.get(workspace) {
+                Some(config) if !config.resolvers.is_empty() => &config.resolvers,
+                _ => &self.network.resolvers,
+            }
+        };
+
+        if resolvers.is_empty() {
+            return Resolver::from_system();
+        }
+
+        let ns = resolvers.iter()
+            .map(|x| parse_resolver(x))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Resolver {
+            ns,
+            tcp: false,
+            timeout: Some(Duration::from_secs(3)),
+        })
+    }
+}
+
+/// Parse `ip` or `ip:port`, the port defaults to 53
+fn parse_resolver(resolver: &str) -> Result<SocketAddr> {
+    if let Ok(addr) = resolver.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    let ip = resolver.parse::<IpAddr>()
+        .map_err(|_| format_err!("Invalid resolver, expected ip or ip:port: {:?}", resolver))?;
+    Ok(SocketAddr::new(ip, 53))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -55,6 +115,8 @@ pub struct CoreConfig {
     pub registry: String,
     #[serde(default, rename="no-autoupdate")]
     pub no_autoupdate: bool,
+    #[serde(rename="insert-batch-size")]
+    pub insert_batch_size: Option<usize>,
 }
 
 impl Default for CoreConfig {
@@ -62,10 +124,18 @@ impl Default for CoreConfig {
         CoreConfig {
             registry: default_registry(),
             no_autoupdate: false,
+            insert_batch_size: None,
         }
     }
 }
 
+impl CoreConfig {
+    /// How many inserts of an import or db_add_many share a transaction
+    pub fn insert_batch_size(&self) -> usize {
+        self.insert_batch_size.unwrap_or(500).max(1)
+    }
+}
+
 #[inline]
 fn default_registry() -> String {
     String::from("https://sn0int.com")
@@ -73,5 +143,104 @@ fn default_registry() -> String {
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct NetworkConfig {
-    pub proxy: Option<SocketAddr>,
+    pub proxy: Option<Proxy>,
+    #[serde(default, rename="proxy-chain")]
+    pub proxy_chain: Vec<Proxy>,
+    #[serde(rename="ca-bundle")]
+    pub ca_bundle: Option<PathBuf>,
+    #[serde(default, rename="ca-bundle-only")]
+    pub ca_bundle_only: bool,
+    #[serde(rename="tls-profile")]
+    pub tls_profile: Option<TlsProfile>,
+    #[serde(default)]
+    pub resolvers: Vec<String>,
+}
+
+impl NetworkConfig {
+    /// A single proxy is a chain with one hop
+    pub fn proxy(&self) -> Result<Option<ProxyChain>> {
+        match (&self.proxy, self.proxy_chain.is_empty()) {
+            (Some(_), false) => bail!("proxy and proxy-chain can't be used at the same time"),
+            (Some(proxy), true) => Ok(Some(ProxyChain::new(vec![proxy.clone()])?)),
+            (None, false) => Ok(Some(ProxyChain::new(self.proxy_chain.clone())?)),
+            (None, true) => Ok(None),
+        }
+    }
+
+    /// Read the ca bundle, this happens outside of the sandbox
+    pub fn tls(&self) -> Result<TlsConfig> {
+        let ca_bundle = match &self.ca_bundle {
+            Some(path) => {
+                let path = expand_home(path)?;
+                let pem = fs::read_to_string(&path)
+                    .context(format!("Failed to read ca bundle: {:?}", path))?;
+                Some(pem)
+            },
+            None => None,
+        };
+
+        let tls = TlsConfig {
+            ca_bundle,
+            ca_bundle_only: self.ca_bundle_only,
+            profile: self.tls_profile,
+        };
+        // fail early instead of in every worker
+        tls.client_config()?;
+        Ok(tls)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FilesystemConfig {
+    pub sandbox: Option<PathBuf>,
+}
+
+impl FilesystemConfig {
+    /// The directory modules are allowed to read from, relative paths start in the home folder
+    pub fn sandbox(&self) -> Result<Option<PathBuf>> {
+        let folder = match &self.sandbox {
+            Some(folder) => folder,
+            None => return Ok(None),
+        };
+
+        expand_home(folder).map(Some)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WorkspaceConfig {
+    #[serde(rename="insert-hook")]
+    pub insert_hook: Option<PathBuf>,
+    #[serde(default)]
+    pub encrypt: bool,
+    #[serde(default)]
+    pub resolvers: Vec<String>,
+}
+
+impl WorkspaceConfig {
+    pub fn insert_hook(&self) -> Result<Option<String>> {
+        let path = match &self.insert_hook {
+            Some(path) => expand_home(path)?,
+            None => return Ok(None),
+        };
+
+        let code = fs::read_to_string(&path)
+            .context(format!("Failed to read insert hook: {:?}", path))?;
+        Ok(Some(code))
+    }
+}
+
+/// Relative paths start in the home folder
+fn expand_home(path: &Path) -> Result<PathBuf> {
+    if path.is_absolute() {
+        return Ok(path.to_owned());
+    }
+
+    let path = path.strip_prefix("~/")
+        .unwrap_or(path);
+
+    let path = dirs::home_dir()
+        .ok_or_else(|| format_err!("Failed to find home folder"))?
+        .join(path);
+    Ok(path)
 }
@@ -0,0 +1,386 @@
+use crate::errors::*;
+
+use crate::proxy::ProxyChain;
+use chrootable_https::dns::{DnsResolver, RecordType};
+use chrootable_https::Resolver;
+use futures::{future, Future};
+use hyper::client::connect::{Connect, Connected, Destination, HttpConnector};
+use hyper_rustls::HttpsConnector;
+use rand::prelude::*;
+use rustls::{BulkAlgorithm, ClientConfig, ProtocolVersion, SupportedCipherSuite, ALL_CIPHERSUITES};
+use tokio::net::TcpStream;
+use std::io::{self, BufReader};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+
+/// hyper client that resolves through our own dns config and gives us access
+/// to the response body as a stream, so we can stop reading at any point.
+pub type WebClient = hyper::Client<HttpsConnector<Connector>>;
+
+/// Connections are closed after every request unless a session opts into
+/// pooling, reusing a connection links all requests sent over it.
+pub fn client(resolver: Resolver, proxy: Option<ProxyChain>, tls: &TlsConfig) -> Result<WebClient> {
+    let mut builder = hyper::Client::builder();
+    builder.keep_alive(false);
+    build(builder, resolver, proxy, tls, None)
+}
+
+/// Client for urls that point to the sni instead of the server. The name in
+/// the url is presented in the client hello and used to verify the
+/// certificate, but the connection goes to `connect_to`.
+pub fn sni_client(resolver: Resolver, proxy: Option<ProxyChain>, tls: &TlsConfig, connect_to: &str) -> Result<WebClient> {
+    let mut builder = hyper::Client::builder();
+    builder.keep_alive(false);
+    build(builder, resolver, proxy, tls, Some(connect_to.to_string()))
+}
+
+/// The sni needs to be a dns name, rustls doesn't send ip addresses
+pub fn validate_sni(sni: &str) -> Result<webpki::DNSNameRef<'_>> {
+    webpki::DNSNameRef::try_from_ascii_str(sni)
+        .map_err(|_| format_err!("Invalid sni, expected a dns name: {:?}", sni))
+}
+
+/// Presets for the parameters of the client hello that rustls lets us
+/// change, so not all connections share the same tls fingerprint. The
+/// extensions, curves and signature algorithms are fixed by rustls. Without
+/// a profile the rustls defaults are used.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all="lowercase")]
+pub enum TlsProfile {
+    /// Prefer aes over chacha20, like clients with hardware aes support
+    Aes,
+    /// Only offer tls 1.2, without session tickets
+    Tls12,
+    /// Shuffle the cipher suites and pick the versions and session tickets
+    /// at random, every time a client config is built
+    Random,
+}
+
+fn is_tls13(suite: &SupportedCipherSuite) -> bool {
+    suite.usable_for_version(ProtocolVersion::TLSv1_3)
+}
+
+fn is_chacha20(suite: &SupportedCipherSuite) -> bool {
+    suite.bulk == BulkAlgorithm::CHACHA20_POLY1305
+}
+
+impl TlsProfile {
+    pub fn apply(self, config: &mut ClientConfig) {
+        match self {
+            TlsProfile::Aes => {
+                // the sort is stable, so tls 1.3 suites stay in front
+                config.ciphersuites.sort_by_key(|suite| (!is_tls13(suite), is_chacha20(suite)));
+            },
+            TlsProfile::Tls12 => {
+                config.versions = vec![ProtocolVersion::TLSv1_2];
+                config.ciphersuites.retain(|suite| !is_tls13(suite));
+                config.enable_tickets = false;
+            },
+            TlsProfile::Random => {
+                let mut rng = thread_rng();
+
+                let (mut tls13, mut tls12): (Vec<_>, Vec<_>) = ALL_CIPHERSUITES.iter()
+                    .partition(|suite| is_tls13(suite));
+                tls13.shuffle(&mut rng);
+                tls12.shuffle(&mut rng);
+
+                if rng.gen() {
+                    config.versions = vec![ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2];
+                    config.ciphersuites = tls13.into_iter().chain(tls12).cloned().collect();
+                } else {
+                    config.versions = vec![ProtocolVersion::TLSv1_2];
+                    config.ciphersuites = tls12.into_iter().cloned().collect();
+                }
+                config.enable_tickets = rng.gen();
+            },
+        }
+    }
+}
+
+/// Certificate authorities that are trusted for tls connections. The bundle
+/// is passed around as pem text because the sandbox can't read it from disk.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TlsConfig {
+    pub ca_bundle: Option<String>,
+    pub ca_bundle_only: bool,
+    #[serde(default)]
+    pub profile: Option<TlsProfile>,
+}
+
+impl TlsConfig {
+    /// Per-request settings take precedence over the global config
+    pub fn with_overrides(&self, ca_bundle: &Option<String>, ca_bundle_only: Option<bool>) -> TlsConfig {
+        TlsConfig {
+            ca_bundle: ca_bundle.clone().or_else(|| self.ca_bundle.clone()),
+            ca_bundle_only: ca_bundle_only.unwrap_or(self.ca_bundle_only),
+            profile: self.profile,
+        }
+    }
+
+    pub fn with_profile(&self, profile: Option<TlsProfile>) -> TlsConfig {
+        TlsConfig {
+            profile: profile.or(self.profile),
+            ..self.clone()
+        }
+    }
+
+    /// Trust the bundle in addition to the builtin roots, or instead of them
+    /// if `ca_bundle_only` is set
+    pub fn client_config(&self) -> Result<ClientConfig> {
+        let mut config = ClientConfig::new();
+
+        if !self.ca_bundle_only {
+            config
+                .root_store
+                .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        }
+
+        if let Some(pem) = &self.ca_bundle {
+            let mut reader = BufReader::new(pem.as_bytes());
+            let (valid, _) = config.root_store.add_pem_file(&mut reader)
+                .map_err(|_| format_err!("Failed to parse ca bundle"))?;
+            if valid == 0 {
+                bail!("ca bundle doesn't contain any valid certificates");
+            }
+        } else if self.ca_bundle_only {
+            bail!("ca-bundle-only is set but no ca bundle is configured");
+        }
+
+        config.ct_logs = Some(&ct_logs::LOGS);
+        if let Some(profile) = self.profile {
+            profile.apply(&mut config);
+        }
+        Ok(config)
+    }
+}
+
+pub const DEFAULT_MAX_IDLE_PER_HOST: usize = 2;
+pub const DEFAULT_IDLE_TIMEOUT: u64 = 30_000;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PoolOptions {
+    max_idle_per_host: Option<usize>,
+    idle_timeout: Option<u64>,
+    pub tls_profile: Option<TlsProfile>,
+}
+
+impl PoolOptions {
+    pub fn max_idle_per_host(&self) -> usize {
+        self.max_idle_per_host.unwrap_or(DEFAULT_MAX_IDLE_PER_HOST)
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_millis(self.idle_timeout.unwrap_or(DEFAULT_IDLE_TIMEOUT))
+    }
+}
+
+/// Client that keeps idle connections around. The connections are driven by
+/// the runtime, so it needs to outlive the individual requests.
+#[derive(Debug)]
+pub struct HttpPool {
+    client: WebClient,
+    rt: Mutex<Runtime>,
+}
+
+impl HttpPool {
+    pub fn new(resolver: Resolver, proxy: Option<ProxyChain>, tls: &TlsConfig, options: &PoolOptions) -> Result<HttpPool> {
+        let mut builder = hyper::Client::builder();
+        builder.keep_alive(true)
+            .keep_alive_timeout(options.idle_timeout())
+            .max_idle_per_host(options.max_idle_per_host());
+
+        Ok(HttpPool {
+            client: build(builder, resolver, proxy, tls, None)?,
+            rt: Mutex::new(Runtime::new()?),
+        })
+    }
+
+    pub fn client(&self) -> &WebClient {
+        &self.client
+    }
+
+    pub fn block_on<F>(&self, fut: F) -> Result<F::Item>
+        where
+            F: Future<Error = Error> + Send + 'static,
+            F::Item: Send + 'static,
+    {
+        let mut rt = self.rt.lock().unwrap();
+        rt.block_on(fut)
+    }
+}
+
+fn build(builder: hyper::client::Builder, resolver: Resolver, proxy: Option<ProxyChain>, tls: &TlsConfig, connect_to: Option<String>) -> Result<WebClient> {
+    let mut http = HttpConnector::new(4);
+    http.enforce_http(false);
+
+    let connector = Connector {
+        http,
+        proxy,
+        resolver,
+        connect_to,
+    };
+
+    let config = tls.client_config()?;
+    let https = HttpsConnector::from((connector, config));
+    Ok(builder.build::<_, hyper::Body>(https))
+}
+
+#[derive(Debug, Clone)]
+pub struct Connector {
+    http: HttpConnector,
+    proxy: Option<ProxyChain>,
+    resolver: Resolver,
+    /// Connect to this host instead of the one in the url
+    connect_to: Option<String>,
+}
+
+fn is_ipaddr(host: &str) -> bool {
+    (host.starts_with('[') && host.ends_with(']')) || host.parse::<Ipv4Addr>().is_ok()
+}
+
+fn io_err(err: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err.to_string())
+}
+
+/// Ipv4 is preferred, AAAA is only queried if there's no A record
+fn resolve_host(resolver: &Resolver, host: &str) -> impl Future<Item=IpAddr, Error=Error> {
+    let fallback = resolver.clone();
+    let name = host.to_string();
+    resolver.resolve(host, RecordType::A)
+        .and_then(|reply| reply.success())
+        .then(move |ips| {
+            match ips.ok().and_then(|ips| ips.into_iter().next()) {
+                Some(ip) => future::Either::A(future::ok(ip)),
+                None => future::Either::B(fallback.resolve(&name, RecordType::AAAA)
+                    .and_then(|reply| {
+                        reply.success()?.into_iter().next()
+                            .ok_or_else(|| format_err!("no record found"))
+                    })),
+            }
+        })
+}
+
+impl Connect for Connector {
+    type Transport = TcpStream;
+    type Error = io::Error;
+    type Future = Box<Future<Item = (TcpStream, Connected), Error = io::Error> + Send>;
+
+    fn connect(&self, mut dest: Destination) -> Self::Future {
+        // the tls layer already took the name from the url
+        if let Some(host) = &self.connect_to {
+            if let Err(err) = dest.set_host(host) {
+                return Box::new(future::err(io::Error::new(io::ErrorKind::Other, err)));
+            }
+        }
+
+        if let Some(proxy) = &self.proxy {
+            let port = match (dest.scheme(), dest.port()) {
+                (_, Some(port)) => port,
+                ("http", None) => 80,
+                (_, None) => 443,
+            };
+            let fut = proxy.connect(dest.host(), port)
+                .map(|stream| (stream, Connected::new()))
+                .map_err(io_err);
+            return Box::new(fut);
+        }
+
+        let http = self.http.clone();
+
+        if is_ipaddr(dest.host()) {
+            return Box::new(http.connect(dest));
+        }
+
+        let fut = resolve_host(&self.resolver, dest.host())
+            .and_then(move |ip| {
+                let ip = match ip {
+                    IpAddr::V4(ip) => ip.to_string(),
+                    IpAddr::V6(ip) => format!("[{}]", ip),
+                };
+                dest.set_host(&ip)?;
+                Ok(dest)
+            })
+            .map_err(io_err)
+            .and_then(move |dest| http.connect(dest));
+
+        Box::new(fut)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::CipherSuite;
+
+    fn suites(config: &ClientConfig) -> Vec<CipherSuite> {
+        config.ciphersuites.iter()
+            .map(|suite| suite.suite)
+            .collect()
+    }
+
+    fn profile(profile: TlsProfile) -> ClientConfig {
+        TlsConfig::default()
+            .with_profile(Some(profile))
+            .client_config()
+            .unwrap()
+    }
+
+    #[test]
+    fn verify_default_profile() {
+        let config = TlsConfig::default().client_config().unwrap();
+        assert_eq!(suites(&config), suites(&ClientConfig::new()));
+        assert_eq!(config.versions, vec![ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2]);
+        assert_eq!(config.ciphersuites.len(), ALL_CIPHERSUITES.len());
+    }
+
+    #[test]
+    fn verify_aes_profile() {
+        let suites = suites(&profile(TlsProfile::Aes));
+        assert_eq!(&suites[..3], &[
+            CipherSuite::TLS13_AES_256_GCM_SHA384,
+            CipherSuite::TLS13_AES_128_GCM_SHA256,
+            CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
+        ]);
+        assert_eq!(suites.len(), ALL_CIPHERSUITES.len());
+        assert_eq!(suites.last(), Some(&CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256));
+    }
+
+    #[test]
+    fn verify_tls12_profile() {
+        let config = profile(TlsProfile::Tls12);
+        assert_eq!(config.versions, vec![ProtocolVersion::TLSv1_2]);
+        assert!(!config.enable_tickets);
+        assert_eq!(config.ciphersuites.len(), 6);
+        assert!(config.ciphersuites.iter().all(|suite| !is_tls13(suite)));
+    }
+
+    #[test]
+    fn verify_random_profile() {
+        for _ in 0..16 {
+            let config = profile(TlsProfile::Random);
+            let tls13 = config.versions.contains(&ProtocolVersion::TLSv1_3);
+            assert!(config.versions.contains(&ProtocolVersion::TLSv1_2));
+            assert_eq!(config.ciphersuites.len(), if tls13 { 9 } else { 6 });
+            // tls 1.3 suites are always offered first
+            let first_tls12 = config.ciphersuites.iter()
+                .position(|suite| !is_tls13(suite))
+                .unwrap();
+            assert!(config.ciphersuites[first_tls12..].iter().all(|suite| !is_tls13(suite)));
+        }
+    }
+
+    #[test]
+    fn verify_profile_override() {
+        let tls = TlsConfig {
+            profile: Some(TlsProfile::Tls12),
+            ..Default::default()
+        };
+        assert_eq!(tls.with_profile(None).profile, Some(TlsProfile::Tls12));
+        assert_eq!(tls.with_overrides(&None, Some(false)).profile, Some(TlsProfile::Tls12));
+        assert_eq!(tls.with_profile(Some(TlsProfile::Aes)).profile, Some(TlsProfile::Aes));
+    }
+}
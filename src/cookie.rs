@@ -0,0 +1,179 @@
+use crate::errors::*;
+
+use cookie::{Cookie as RawCookie, SameSite};
+use time::Duration;
+
+
+/// A cookie as sent in a Set-Cookie header
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub domain: Option<String>,
+    pub path: Option<String>,
+    pub expires: Option<String>,
+    pub max_age: Option<i64>,
+    pub secure: bool,
+    pub httponly: bool,
+    pub samesite: Option<String>,
+}
+
+fn samesite(value: &str) -> Option<SameSite> {
+    match value.to_lowercase().as_str() {
+        "strict" => Some(SameSite::Strict),
+        "lax" => Some(SameSite::Lax),
+        _ => None,
+    }
+}
+
+/// Parse a single Set-Cookie header. Unknown or malformed attributes are
+/// ignored, None is returned if the header doesn't contain a `name=value`
+/// pair.
+pub fn parse(header: &str) -> Option<Cookie> {
+    let cookie = RawCookie::parse(header).ok()?;
+
+    Some(Cookie {
+        name: cookie.name().to_string(),
+        value: cookie.value().to_string(),
+        domain: cookie.domain().map(String::from),
+        path: cookie.path().map(String::from),
+        expires: cookie.expires().map(|tm| tm.rfc822().to_string()),
+        max_age: cookie.max_age().map(|x| x.num_seconds()),
+        secure: cookie.secure().unwrap_or(false),
+        httponly: cookie.http_only().unwrap_or(false),
+        samesite: cookie.same_site()
+            .filter(|x| !x.is_none())
+            .map(|x| x.to_string()),
+    })
+}
+
+/// Parse multiple Set-Cookie headers, one per line. Lines that don't contain
+/// a cookie are skipped.
+pub fn parse_all(headers: &str) -> Vec<Cookie> {
+    headers.lines()
+        .filter_map(parse)
+        .collect()
+}
+
+fn ensure_valid(field: &str, value: &str) -> Result<()> {
+    if let Some(c) = value.chars().find(|c| *c == ';' || c.is_control()) {
+        bail!("Cookie {} contains invalid character: {:?}", field, c);
+    }
+    Ok(())
+}
+
+/// Format a cookie for a Set-Cookie header
+pub fn serialize(cookie: &Cookie) -> Result<String> {
+    if cookie.name.is_empty() {
+        bail!("Cookie name can't be empty");
+    }
+    if let Some(c) = cookie.name.chars().find(|c| *c == '=' || c.is_whitespace()) {
+        bail!("Cookie name contains invalid character: {:?}", c);
+    }
+    ensure_valid("name", &cookie.name)?;
+    ensure_valid("value", &cookie.value)?;
+
+    let mut out = RawCookie::new(cookie.name.clone(), cookie.value.clone());
+
+    if let Some(domain) = &cookie.domain {
+        ensure_valid("domain", domain)?;
+        out.set_domain(domain.clone());
+    }
+    if let Some(path) = &cookie.path {
+        ensure_valid("path", path)?;
+        out.set_path(path.clone());
+    }
+    if let Some(expires) = &cookie.expires {
+        let tm = time::strptime(expires, "%a, %d %b %Y %H:%M:%S %Z")
+            .map_err(|_| format_err!("Invalid expires date: {:?}", expires))?;
+        out.set_expires(tm);
+    }
+    if let Some(max_age) = cookie.max_age {
+        out.set_max_age(Duration::seconds(max_age));
+    }
+    if cookie.secure {
+        out.set_secure(true);
+    }
+    if cookie.httponly {
+        out.set_http_only(true);
+    }
+    if let Some(value) = &cookie.samesite {
+        let value = samesite(value)
+            .ok_or_else(|| format_err!("Invalid samesite value: {:?}", value))?;
+        out.set_same_site(value);
+    }
+
+    Ok(out.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let cookie = parse("SID=31d4d96e407aad42; Path=/; Domain=.example.com; Expires=Wed, 09 Jun 2021 10:18:14 GMT; Max-Age=3600; Secure; HttpOnly; SameSite=lax").unwrap();
+        assert_eq!(cookie, Cookie {
+            name: "SID".to_string(),
+            value: "31d4d96e407aad42".to_string(),
+            domain: Some("example.com".to_string()),
+            path: Some("/".to_string()),
+            expires: Some("Wed, 09 Jun 2021 10:18:14 GMT".to_string()),
+            max_age: Some(3600),
+            secure: true,
+            httponly: true,
+            samesite: Some("Lax".to_string()),
+        });
+    }
+
+    #[test]
+    fn test_parse_lenient() {
+        let cookie = parse("lang=en-US;; Max-Age=soon; Expires=tomorrow; SameSite=sometimes; Foo=bar; secure").unwrap();
+        assert_eq!(cookie, Cookie {
+            name: "lang".to_string(),
+            value: "en-US".to_string(),
+            secure: true,
+            ..Default::default()
+        });
+
+        assert_eq!(parse("novalue"), None);
+        assert_eq!(parse("=foo"), None);
+        assert_eq!(parse(""), None);
+    }
+
+    #[test]
+    fn test_parse_all() {
+        let cookies = parse_all("a=1; Path=/\ninvalid\nb=2; HttpOnly\n");
+        assert_eq!(cookies.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), vec!["a", "b"]);
+        assert!(cookies[1].httponly);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let header = "SID=31d4d96e407aad42; HttpOnly; Secure; SameSite=Strict; Path=/; Domain=example.com; Max-Age=3600; Expires=Wed, 09 Jun 2021 10:18:14 GMT";
+        let cookie = parse(header).unwrap();
+        assert_eq!(serialize(&cookie).unwrap(), header);
+    }
+
+    #[test]
+    fn test_serialize_invalid() {
+        let cookie = |name: &str, value: &str| Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            ..Default::default()
+        };
+        assert!(serialize(&cookie("", "foo")).is_err());
+        assert!(serialize(&cookie("a b", "foo")).is_err());
+        assert!(serialize(&cookie("a", "foo; Secure")).is_err());
+        assert!(serialize(&cookie("a", "foo\r\nX-Injected: 1")).is_err());
+        assert!(serialize(&Cookie {
+            samesite: Some("sometimes".to_string()),
+            ..cookie("a", "b")
+        }).is_err());
+        assert!(serialize(&Cookie {
+            expires: Some("tomorrow".to_string()),
+            ..cookie("a", "b")
+        }).is_err());
+    }
+}
@@ -0,0 +1,342 @@
+use crate::errors::*;
+
+use sha2::{Digest, Sha256};
+
+
+const BASE58: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BECH32: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+const BECH32_CONST: u32 = 1;
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+
+/// Normalize the name of a currency, like `BTC` to `btc`
+pub fn currency(currency: &str) -> Result<String> {
+    let currency = currency.trim().to_lowercase();
+    if currency.is_empty() || !currency.chars().all(|c| c.is_ascii_alphanumeric()) {
+        bail!("Invalid currency: {:?}", currency);
+    }
+    Ok(currency)
+}
+
+/// Validate an address and bring it into a form that can be used for
+/// deduplication. Addresses of currencies we don't know are only trimmed.
+pub fn normalize(currency: &str, address: &str) -> Result<String> {
+    let address = address.trim();
+    if address.is_empty() {
+        bail!("Address is empty");
+    }
+
+    match currency {
+        "btc" => btc(address),
+        "eth" => eth(address),
+        _ => Ok(address.to_string()),
+    }
+}
+
+fn btc(address: &str) -> Result<String> {
+    let lower = address.to_lowercase();
+    if lower.starts_with("bc1") || lower.starts_with("tb1") {
+        segwit(address)
+    } else {
+        base58check(address)?;
+        Ok(address.to_string())
+    }
+}
+
+fn base58_decode(s: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::<u8>::new();
+    for c in s.bytes() {
+        let mut carry = match BASE58.iter().position(|x| *x == c) {
+            Some(idx) => idx as u32,
+            None => bail!("Invalid base58 character: {:?}", c as char),
+        };
+        for byte in out.iter_mut().rev() {
+            carry += u32::from(*byte) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            out.insert(0, carry as u8);
+            carry >>= 8;
+        }
+    }
+
+    // leading ones are leading zero bytes
+    let zeros = s.bytes().take_while(|c| *c == b'1').count();
+    let mut buf = vec![0; zeros];
+    buf.extend(out);
+    Ok(buf)
+}
+
+fn base58check(address: &str) -> Result<()> {
+    let buf = base58_decode(address)?;
+    if buf.len() != 25 {
+        bail!("Invalid length for a bitcoin address");
+    }
+
+    let (payload, checksum) = buf.split_at(21);
+    let hash = Sha256::digest(&Sha256::digest(payload));
+    if &hash[..4] != checksum {
+        bail!("Invalid checksum for bitcoin address");
+    }
+
+    match payload[0] {
+        // p2pkh and p2sh, mainnet and testnet
+        0x00 | 0x05 | 0x6f | 0xc4 => Ok(()),
+        version => bail!("Unknown bitcoin address version: {}", version),
+    }
+}
+
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GEN: [u32; 5] = [0x3b6a_57b2, 0x2650_8e6d, 0x1ea1_19fa, 0x3d42_33dd, 0x2a14_62b3];
+    let mut chk = 1u32;
+    for v in values {
+        let b = chk >> 25;
+        chk = (chk & 0x1ff_ffff) << 5 ^ u32::from(*v);
+        for (i, g) in GEN.iter().enumerate() {
+            if (b >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+    }
+    chk
+}
+
+fn segwit(address: &str) -> Result<String> {
+    if address.to_lowercase() != address && address.to_uppercase() != address {
+        bail!("Bech32 address must not be mixed case");
+    }
+    let address = address.to_lowercase();
+
+    let idx = match address.rfind('1') {
+        Some(idx) => idx,
+        None => bail!("Missing bech32 separator"),
+    };
+    let (hrp, data) = (&address[..idx], &address[idx + 1..]);
+    if data.len() < 6 + 1 || address.len() > 90 {
+        bail!("Invalid length for a bech32 address");
+    }
+
+    let data = data.bytes()
+        .map(|c| match BECH32.iter().position(|x| *x == c) {
+            Some(idx) => Ok(idx as u8),
+            None => bail!("Invalid bech32 character: {:?}", c as char),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut values = hrp.bytes().map(|c| c >> 5).collect::<Vec<_>>();
+    values.push(0);
+    values.extend(hrp.bytes().map(|c| c & 31));
+    values.extend(&data);
+
+    let version = data[0];
+    let expected = match version {
+        0 => BECH32_CONST,
+        1..=16 => BECH32M_CONST,
+        _ => bail!("Invalid witness version: {}", version),
+    };
+    if bech32_polymod(&values) != expected {
+        bail!("Invalid checksum for bitcoin address");
+    }
+
+    // witness program without the version and the checksum
+    let program = &data[1..data.len() - 6];
+    let len = program.len() * 5 / 8;
+    let valid = match (version, len) {
+        (0, 20) | (0, 32) => true,
+        (0, _) => false,
+        (_, 2..=40) => true,
+        _ => false,
+    };
+    if !valid {
+        bail!("Invalid witness program length: {}", len);
+    }
+
+    Ok(address)
+}
+
+const KECCAK_RC: [u64; 24] = [
+    0x0000_0000_0000_0001, 0x0000_0000_0000_8082, 0x8000_0000_0000_808a, 0x8000_0000_8000_8000,
+    0x0000_0000_0000_808b, 0x0000_0000_8000_0001, 0x8000_0000_8000_8081, 0x8000_0000_0000_8009,
+    0x0000_0000_0000_008a, 0x0000_0000_0000_0088, 0x0000_0000_8000_8009, 0x0000_0000_8000_000a,
+    0x0000_0000_8000_808b, 0x8000_0000_0000_008b, 0x8000_0000_0000_8089, 0x8000_0000_0000_8003,
+    0x8000_0000_0000_8002, 0x8000_0000_0000_0080, 0x0000_0000_0000_800a, 0x8000_0000_8000_000a,
+    0x8000_0000_8000_8081, 0x8000_0000_0000_8080, 0x0000_0000_8000_0001, 0x8000_0000_8000_8008,
+];
+const KECCAK_ROTC: [u32; 24] = [1, 3, 6, 10, 15, 21, 28, 36, 45, 55, 2, 14, 27, 41, 56, 8, 25, 43, 62, 18, 39, 61, 20, 44];
+const KECCAK_PILN: [usize; 24] = [10, 7, 11, 17, 18, 3, 5, 16, 8, 21, 24, 4, 15, 23, 19, 13, 12, 2, 20, 14, 22, 9, 6, 1];
+
+fn keccak_f(st: &mut [u64; 25]) {
+    for rc in &KECCAK_RC {
+        // theta
+        let mut bc = [0u64; 5];
+        for i in 0..5 {
+            bc[i] = st[i] ^ st[i + 5] ^ st[i + 10] ^ st[i + 15] ^ st[i + 20];
+        }
+        for i in 0..5 {
+            let t = bc[(i + 4) % 5] ^ bc[(i + 1) % 5].rotate_left(1);
+            for j in (0..25).step_by(5) {
+                st[j + i] ^= t;
+            }
+        }
+
+        // rho and pi
+        let mut t = st[1];
+        for (piln, rotc) in KECCAK_PILN.iter().zip(KECCAK_ROTC.iter()) {
+            let tmp = st[*piln];
+            st[*piln] = t.rotate_left(*rotc);
+            t = tmp;
+        }
+
+        // chi
+        for j in (0..25).step_by(5) {
+            let mut row = [0u64; 5];
+            row.copy_from_slice(&st[j..j + 5]);
+            for i in 0..5 {
+                st[j + i] ^= !row[(i + 1) % 5] & row[(i + 2) % 5];
+            }
+        }
+
+        // iota
+        st[0] ^= rc;
+    }
+}
+
+/// The original keccak padding that ethereum uses, this is not sha3-256
+fn keccak256(data: &[u8]) -> [u8; 32] {
+    const RATE: usize = 136;
+
+    // there's always at least one byte of padding
+    let pad = RATE - data.len() % RATE;
+    let mut padded = data.to_vec();
+    padded.resize(data.len() + pad, 0x00);
+    padded[data.len()] |= 0x01;
+    *padded.last_mut().unwrap() |= 0x80;
+
+    let mut st = [0u64; 25];
+    for block in padded.chunks(RATE) {
+        for (i, lane) in block.chunks(8).enumerate() {
+            let lane = lane.iter().rev().fold(0u64, |acc, b| acc << 8 | u64::from(*b));
+            st[i] ^= lane;
+        }
+        keccak_f(&mut st);
+    }
+
+    let mut out = [0u8; 32];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = (st[i / 8] >> (8 * (i % 8))) as u8;
+    }
+    out
+}
+
+/// Mixed case addresses carry an EIP-55 checksum, addresses in a single case
+/// don't and are accepted as is. Either way the checksummed form is returned.
+fn eth(address: &str) -> Result<String> {
+    let hex = if address.starts_with("0x") || address.starts_with("0X") {
+        &address[2..]
+    } else {
+        bail!("Ethereum address must start with 0x");
+    };
+
+    if hex.len() != 40 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("Ethereum address must be 40 hex characters");
+    }
+
+    let lower = hex.to_lowercase();
+    let hash = keccak256(lower.as_bytes());
+
+    let checksummed = lower.chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = (hash[i / 2] >> (if i % 2 == 0 { 4 } else { 0 })) & 0x0f;
+            if nibble >= 8 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect::<String>();
+
+    let mixed = hex != lower && hex != hex.to_uppercase();
+    if mixed && hex != checksummed {
+        bail!("Invalid checksum for ethereum address");
+    }
+
+    Ok(format!("0x{}", checksummed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_currency() {
+        assert_eq!(currency(" BTC ").unwrap(), "btc");
+        assert!(currency("").is_err());
+        assert!(currency("b tc").is_err());
+    }
+
+    #[test]
+    fn test_btc_base58() {
+        assert_eq!(normalize("btc", "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2").unwrap(), "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2");
+        assert_eq!(normalize("btc", " 3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy\n").unwrap(), "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy");
+    }
+
+    #[test]
+    fn test_btc_base58_invalid() {
+        // last character changed
+        assert!(normalize("btc", "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN3").is_err());
+        // 0 isn't part of the base58 alphabet
+        assert!(normalize("btc", "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN0").is_err());
+        assert!(normalize("btc", "1BvBMSEYst").is_err());
+    }
+
+    #[test]
+    fn test_btc_bech32() {
+        assert_eq!(normalize("btc", "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4").unwrap(), "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4");
+        assert_eq!(normalize("btc", "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0").unwrap(), "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0");
+    }
+
+    #[test]
+    fn test_btc_bech32_invalid() {
+        // last character changed
+        assert!(normalize("btc", "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5").is_err());
+        // mixed case
+        assert!(normalize("btc", "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3T4").is_err());
+        // v1 program with a bech32 instead of a bech32m checksum
+        assert!(normalize("btc", "bc1pw508d6qejxtdg4y5r3zarvary0c5xw7kw508d6qejxtdg4y5r3zarvary0c5xw7k7grplx").is_err());
+    }
+
+    #[test]
+    fn test_keccak256() {
+        let hash = keccak256(b"");
+        assert_eq!(&hash[..4], &[0xc5, 0xd2, 0x46, 0x01]);
+        assert_eq!(&hash[28..], &[0x5d, 0x85, 0xa4, 0x70]);
+    }
+
+    #[test]
+    fn test_eth() {
+        for address in &["0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+                         "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+                         "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+                         "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb"] {
+            assert_eq!(&normalize("eth", address).unwrap(), address);
+            assert_eq!(&normalize("eth", &address.to_lowercase()).unwrap(), address);
+        }
+    }
+
+    #[test]
+    fn test_eth_invalid() {
+        // checksum broken by changing the case of a single letter
+        assert!(normalize("eth", "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD").is_err());
+        assert!(normalize("eth", "5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").is_err());
+        assert!(normalize("eth", "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beae").is_err());
+        assert!(normalize("eth", "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaeg").is_err());
+    }
+
+    #[test]
+    fn test_unknown_currency() {
+        assert_eq!(normalize("xmr", " 44AFFq5kSiGBoZ \n").unwrap(), "44AFFq5kSiGBoZ");
+        assert!(normalize("xmr", "  ").is_err());
+    }
+}
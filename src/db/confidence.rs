@@ -0,0 +1,201 @@
+use crate::errors::*;
+use crate::db::Database;
+use crate::schema::*;
+use crate::models::*;
+use diesel;
+use diesel::prelude::*;
+use serde::{Deserialize, Deserializer};
+
+
+/// The confidence of entities that don't have one
+pub const DEFAULT_CONFIDENCE: i32 = 50;
+
+#[derive(Identifiable, Queryable, AsChangeset, PartialEq, Debug)]
+#[table_name="confidences"]
+pub struct Confidence {
+    pub id: i32,
+    pub family: String,
+    pub key: i32,
+    pub confidence: i32,
+}
+
+#[derive(Insertable)]
+#[table_name="confidences"]
+pub struct NewConfidence<'a> {
+    pub family: &'a str,
+    pub key: i32,
+    pub confidence: i32,
+}
+
+/// Parse a confidence from 0 to 100, or one of `low`, `medium` and `high`
+pub fn parse(s: &str) -> Result<i32> {
+    match s.to_lowercase().as_str() {
+        "low" => Ok(25),
+        "medium" => Ok(DEFAULT_CONFIDENCE),
+        "high" => Ok(75),
+        num => {
+            let num = num.parse::<i64>()
+                .map_err(|_| format_err!("Invalid confidence: {:?}", s))?;
+            validate(num)
+        },
+    }
+}
+
+fn validate(num: i64) -> Result<i32> {
+    if !(0..=100).contains(&num) {
+        bail!("Confidence must be between 0 and 100: {}", num);
+    }
+    Ok(num as i32)
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ConfidenceValue {
+    Number(i64),
+    Level(String),
+}
+
+/// Accept a number or a level, see `parse`
+pub fn deserialize_opt<'de, D>(deserializer: D) -> ::std::result::Result<Option<i32>, D::Error>
+    where D: Deserializer<'de>
+{
+    use serde::de::Error;
+
+    let value = match Option::<ConfidenceValue>::deserialize(deserializer)? {
+        Some(ConfidenceValue::Number(num)) => validate(num),
+        Some(ConfidenceValue::Level(level)) => parse(&level),
+        None => return Ok(None),
+    };
+    value.map(Some)
+        .map_err(D::Error::custom)
+}
+
+impl Confidence {
+    pub fn find(obj: &Insert, my_key: i32, db: &Database) -> Result<Option<Confidence>> {
+        use crate::schema::confidences::dsl::*;
+
+        confidences
+            .filter(family.eq(obj.table()))
+            .filter(key.eq(my_key))
+            .first::<Self>(db.db())
+            .optional()
+            .map_err(Error::from)
+    }
+
+    /// Read the confidence of an entity, the default is used if it doesn't have one
+    pub fn get(obj: &Insert, key: i32, db: &Database) -> Result<i32> {
+        let confidence = Self::find(obj, key, db)?
+            .map(|c| c.confidence)
+            .unwrap_or(DEFAULT_CONFIDENCE);
+        Ok(confidence)
+    }
+
+    /// Set the confidence of an entity. Unless `force` is set, a lower
+    /// confidence than the current one is ignored.
+    pub fn set(obj: &Insert, my_key: i32, value: i32, force: bool, db: &Database) -> Result<()> {
+        use crate::schema::confidences::dsl::*;
+
+        match Self::find(obj, my_key, db)? {
+            Some(mut old) => {
+                if force || old.confidence < value {
+                    debug!("Updating confidence on record");
+                    old.confidence = value;
+
+                    diesel::update(confidences.filter(id.eq(old.id)))
+                        .set(old)
+                        .execute(db.db())?;
+                }
+            },
+            None => {
+                if force || DEFAULT_CONFIDENCE < value {
+                    debug!("Setting confidence on record");
+                    diesel::insert_into(confidences)
+                        .values(NewConfidence {
+                            family: obj.table(),
+                            key: my_key,
+                            confidence: value,
+                        })
+                        .execute(db.db())?;
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Set the confidence of a new entity
+    pub fn create(obj: &Insert, key: i32, value: i32, db: &Database) -> Result<()> {
+        Self::set(obj, key, value, true, db)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn domain(value: &str) -> Insert {
+        Insert::Domain(NewDomain {
+            value: value.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("0").unwrap(), 0);
+        assert_eq!(parse("100").unwrap(), 100);
+        assert_eq!(parse("low").unwrap(), 25);
+        assert_eq!(parse("Medium").unwrap(), DEFAULT_CONFIDENCE);
+        assert_eq!(parse("high").unwrap(), 75);
+        assert!(parse("101").is_err());
+        assert!(parse("-1").is_err());
+        assert!(parse("certain").is_err());
+    }
+
+    #[test]
+    fn test_deserialize() {
+        #[derive(Deserialize)]
+        struct Options {
+            #[serde(default, deserialize_with="deserialize_opt")]
+            confidence: Option<i32>,
+        }
+
+        let opts = serde_json::from_str::<Options>(r#"{"confidence":80}"#).unwrap();
+        assert_eq!(opts.confidence, Some(80));
+        let opts = serde_json::from_str::<Options>(r#"{"confidence":"low"}"#).unwrap();
+        assert_eq!(opts.confidence, Some(25));
+        let opts = serde_json::from_str::<Options>(r#"{}"#).unwrap();
+        assert_eq!(opts.confidence, None);
+        assert!(serde_json::from_str::<Options>(r#"{"confidence":200}"#).is_err());
+        assert!(serde_json::from_str::<Options>(r#"{"confidence":"certain"}"#).is_err());
+    }
+
+    #[test]
+    fn test_set_keeps_higher() {
+        let db = Database::in_memory().unwrap();
+        let obj = domain("example.com");
+        let (_, id) = db.insert_generic(obj.clone()).unwrap().unwrap();
+
+        assert_eq!(Confidence::get(&obj, id, &db).unwrap(), DEFAULT_CONFIDENCE);
+        // lower than the default
+        Confidence::set(&obj, id, 30, false, &db).unwrap();
+        assert_eq!(Confidence::get(&obj, id, &db).unwrap(), DEFAULT_CONFIDENCE);
+
+        Confidence::set(&obj, id, 90, false, &db).unwrap();
+        assert_eq!(Confidence::get(&obj, id, &db).unwrap(), 90);
+        Confidence::set(&obj, id, 60, false, &db).unwrap();
+        assert_eq!(Confidence::get(&obj, id, &db).unwrap(), 90);
+
+        Confidence::set(&obj, id, 60, true, &db).unwrap();
+        assert_eq!(Confidence::get(&obj, id, &db).unwrap(), 60);
+    }
+
+    #[test]
+    fn test_create_below_default() {
+        let db = Database::in_memory().unwrap();
+        let obj = domain("example.com");
+        let (_, id) = db.insert_generic(obj.clone()).unwrap().unwrap();
+
+        Confidence::create(&obj, id, 10, &db).unwrap();
+        assert_eq!(Confidence::get(&obj, id, &db).unwrap(), 10);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::errors::*;
+
+use crate::config::Config;
+use crate::keyring::{KeyName, KeyRing};
+use crate::utils;
+use crate::workspaces::Workspace;
+use diesel;
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+
+
+/// Keyring namespace of workspace passphrases, modules can't request it
+pub const KEYRING_NAMESPACE: &str = "sn0int-workspace";
+
+#[derive(QueryableByName)]
+struct CipherVersion {
+    #[sql_type="Text"]
+    #[allow(dead_code)]
+    cipher_version: String,
+}
+
+/// The passphrase of an encrypted workspace, None if the workspace isn't
+/// encrypted. The keyring is checked first, if there's no passphrase stored
+/// we ask for it in interactive mode.
+pub fn passphrase(config: &Config, keyring: &KeyRing, workspace: &Workspace) -> Result<Option<String>> {
+    if !config.is_encrypted(workspace) {
+        return Ok(None);
+    }
+
+    let key = KeyName::new(KEYRING_NAMESPACE, workspace.as_str());
+    if let Some(secret) = keyring.get(&key).and_then(|entry| entry.secret_key) {
+        return Ok(Some(secret));
+    }
+
+    if !atty::is(atty::Stream::Stdin) {
+        bail!("Workspace {:?} is encrypted, add the passphrase to the keyring with `keyring add {}:{}`",
+              workspace.as_str(), KEYRING_NAMESPACE, workspace.as_str());
+    }
+
+    let passphrase = utils::question_secret(&format!("Passphrase for workspace {:?}", workspace.as_str()))?;
+    if passphrase.is_empty() {
+        bail!("Passphrase can not be empty");
+    }
+    Ok(Some(passphrase))
+}
+
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// Set the key of a freshly opened connection, this has to happen before the
+/// first query. Fails if sqlite isn't sqlcipher or if the key is wrong.
+pub fn unlock(db: &SqliteConnection, passphrase: &str) -> Result<()> {
+    db.execute(&format!("PRAGMA key = {}", quote(passphrase)))
+        .context("Failed to set encryption key")?;
+
+    // regular sqlite silently ignores the key, don't keep writing plaintext
+    let version = diesel::sql_query("PRAGMA cipher_version")
+        .load::<CipherVersion>(db)?;
+    if version.is_empty() {
+        bail!("Workspace encryption needs sqlcipher, build sn0int with `--features sqlcipher`");
+    }
+
+    db.execute("SELECT count(*) FROM sqlite_master")
+        .map_err(|_| format_err!("Failed to decrypt workspace, wrong passphrase or the database isn't encrypted"))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("hunter2"), "'hunter2'");
+        assert_eq!(quote("it's"), "'it''s'");
+        assert_eq!(quote("'; DROP TABLE domains; --"), "'''; DROP TABLE domains; --'");
+    }
+
+    #[test]
+    fn test_not_encrypted() {
+        let config = Config::default();
+        let keyring = serde_json::from_str::<KeyRing>(r#"{"keys":{},"grants":{}}"#).unwrap();
+        let workspace = "default".parse().unwrap();
+        assert_eq!(passphrase(&config, &keyring, &workspace).unwrap(), None);
+    }
+
+    #[test]
+    fn test_passphrase_from_keyring() {
+        let config = toml::from_str::<Config>(r#"
+        [workspaces.secret]
+        encrypt = true
+        "#).unwrap();
+        let keyring = serde_json::from_str::<KeyRing>(r#"{"keys":{"sn0int-workspace":{"secret":"hunter2"}},"grants":{}}"#).unwrap();
+
+        let workspace = "secret".parse().unwrap();
+        assert_eq!(passphrase(&config, &keyring, &workspace).unwrap(), Some("hunter2".to_string()));
+    }
+
+    #[test]
+    #[cfg(not(feature="sqlcipher"))]
+    fn test_unlock_without_sqlcipher() {
+        let db = SqliteConnection::establish(":memory:").unwrap();
+        let err = unlock(&db, "hunter2").unwrap_err();
+        assert_eq!(err.to_string(), "Workspace encryption needs sqlcipher, build sn0int with `--features sqlcipher`");
+    }
+}
@@ -0,0 +1,219 @@
+use crate::errors::*;
+use crate::db::{Database, Family};
+use crate::schema::*;
+use crate::models::*;
+use chrono::{NaiveDateTime, Utc};
+use diesel;
+use diesel::prelude::*;
+use std::fmt;
+use std::str::FromStr;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Eq, Ord, Serialize, Deserialize)]
+#[serde(rename_all="lowercase")]
+pub enum Severity {
+    Info,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    pub const ALL: &'static [Severity] = &[
+        Severity::Info,
+        Severity::Low,
+        Severity::Medium,
+        Severity::High,
+        Severity::Critical,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+impl FromStr for Severity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Severity> {
+        match s {
+            "info" => Ok(Severity::Info),
+            "low" => Ok(Severity::Low),
+            "medium" => Ok(Severity::Medium),
+            "high" => Ok(Severity::High),
+            "critical" => Ok(Severity::Critical),
+            _ => bail!("Unknown severity: {:?}", s),
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "{}", self.as_str())
+    }
+}
+
+#[derive(Identifiable, Queryable, Serialize, PartialEq, Debug)]
+#[table_name="findings"]
+pub struct Finding {
+    pub id: i32,
+    pub severity: String,
+    pub title: String,
+    pub family: Option<String>,
+    pub key: Option<i32>,
+    pub details: Option<String>,
+    pub created: NaiveDateTime,
+    pub module: Option<String>,
+}
+
+/// A finding reported by a module, sent from the sandbox to the main process
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewFinding {
+    pub severity: Severity,
+    pub title: String,
+    pub family: Option<String>,
+    pub id: Option<i32>,
+    pub details: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name="findings"]
+struct InsertFinding {
+    severity: &'static str,
+    title: String,
+    family: Option<String>,
+    key: Option<i32>,
+    details: Option<String>,
+    created: NaiveDateTime,
+    module: String,
+}
+
+impl NewFinding {
+    /// Make sure the entity reference is complete and points to something we can display
+    pub fn validate(&self) -> Result<()> {
+        match (&self.family, self.id) {
+            (Some(family), Some(_)) => match Family::from_str(family)? {
+                Family::SubdomainIpAddr |
+                Family::NetworkDevice |
+                Family::BreachEmail => bail!("Findings can't reference {:?}", family),
+                _ => (),
+            },
+            (None, None) => (),
+            _ => bail!("Entity reference needs both family and id"),
+        }
+        Ok(())
+    }
+}
+
+/// Lookup the value of the entity a finding points to
+pub fn entity_label(db: &Database, family: &str, id: i32) -> Result<String> {
+    let label = match Family::from_str(family)? {
+        Family::Domain => Domain::by_id(db, id)?.to_string(),
+        Family::Subdomain => Subdomain::by_id(db, id)?.to_string(),
+        Family::IpAddr => IpAddr::by_id(db, id)?.to_string(),
+        Family::Url => Url::by_id(db, id)?.to_string(),
+        Family::Email => Email::by_id(db, id)?.to_string(),
+        Family::PhoneNumber => PhoneNumber::by_id(db, id)?.to_string(),
+        Family::Device => Device::by_id(db, id)?.to_string(),
+        Family::Network => Network::by_id(db, id)?.to_string(),
+        Family::Account => Account::by_id(db, id)?.to_string(),
+        Family::Breach => Breach::by_id(db, id)?.to_string(),
+        Family::Port => Port::by_id(db, id)?.to_string(),
+        Family::CryptoAddr => CryptoAddr::by_id(db, id)?.to_string(),
+        family => bail!("Findings can't reference {:?}", family),
+    };
+    Ok(label)
+}
+
+impl Finding {
+    pub fn create(obj: &NewFinding, module_name: &str, db: &Database) -> Result<i32> {
+        use crate::schema::findings::dsl::*;
+
+        obj.validate()?;
+        if let (Some(f), Some(k)) = (&obj.family, obj.id) {
+            entity_label(db, f, k)
+                .context("Entity doesn't exist")?;
+        }
+
+        diesel::insert_into(findings)
+            .values(InsertFinding {
+                severity: obj.severity.as_str(),
+                title: obj.title.clone(),
+                family: obj.family.clone(),
+                key: obj.id,
+                details: obj.details.clone(),
+                created: Utc::now().naive_utc(),
+                module: module_name.to_string(),
+            })
+            .execute(db.db())?;
+
+        let my_id = findings.select(id)
+            .order(id.desc())
+            .first::<i32>(db.db())?;
+
+        Ok(my_id)
+    }
+
+    /// List all findings with at least this severity, oldest first
+    pub fn list(db: &Database, min: Severity) -> Result<Vec<Finding>> {
+        use crate::schema::findings::dsl::*;
+
+        let severities = Severity::ALL.iter()
+            .filter(|x| **x >= min)
+            .map(|x| x.as_str())
+            .collect::<Vec<_>>();
+
+        findings
+            .filter(severity.eq_any(severities))
+            .order(id.asc())
+            .load::<Self>(db.db())
+            .map_err(Error::from)
+    }
+
+    pub fn entity(&self, db: &Database) -> Option<Result<String>> {
+        match (&self.family, self.key) {
+            (Some(family), Some(key)) => Some(entity_label(db, family, key)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finding(family: Option<&str>, id: Option<i32>) -> NewFinding {
+        NewFinding {
+            severity: Severity::High,
+            title: "Exposed admin panel".into(),
+            family: family.map(String::from),
+            id,
+            details: None,
+        }
+    }
+
+    #[test]
+    fn test_severity_order() {
+        assert!(Severity::Critical > Severity::High);
+        assert!(Severity::Info < Severity::Low);
+        assert_eq!(Severity::from_str("medium").unwrap(), Severity::Medium);
+        assert!(Severity::from_str("urgent").is_err());
+    }
+
+    #[test]
+    fn test_validate_entity_ref() {
+        assert!(finding(None, None).validate().is_ok());
+        assert!(finding(Some("subdomain"), Some(1)).validate().is_ok());
+        assert!(finding(Some("subdomain"), None).validate().is_err());
+        assert!(finding(None, Some(1)).validate().is_err());
+        assert!(finding(Some("subdomain-ipaddr"), Some(1)).validate().is_err());
+        assert!(finding(Some("foo"), Some(1)).validate().is_err());
+    }
+}
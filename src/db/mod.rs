@@ -3,16 +3,24 @@ use crate::errors::*;
 use diesel;
 use diesel::expression::SqlLiteral;
 use diesel::expression::sql_literal::sql;
-use diesel::sql_types::Bool;
+use diesel::sql_types::{Bool, Integer, Nullable, Text};
 use diesel::prelude::*;
 use crate::models::*;
 use crate::schema::*;
 use std::str::FromStr;
 use crate::paths;
+use crate::url_canonical;
 use crate::migrations;
 use crate::worker;
+use serde::Serialize;
+use serde_json;
+use std::collections::HashMap;
 use crate::workspaces::Workspace;
 
+pub mod confidence;
+pub mod encryption;
+pub mod findings;
+pub mod state;
 pub mod ttl;
 
 
@@ -32,7 +40,42 @@ impl DbChange {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The outcome of a single row of `Database::insert_many`
+pub type InsertResult = Result<Option<(DbChange, i32)>>;
+
+/// What to do if an inserted entity already exists
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all="lowercase")]
+pub enum Conflict {
+    /// Update the fields that changed, this is what db_add does
+    Update,
+    /// Keep the existing entity as it is
+    Skip,
+    /// Fail the insert
+    Error,
+}
+
+/// Options of an insert that are set with db_add_opts
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct InsertOptions {
+    pub conflict: Conflict,
+    /// Raise the confidence of the entity to this value
+    pub confidence: Option<i32>,
+    /// Also lower the confidence if the entity already has a higher one
+    pub override_confidence: bool,
+}
+
+impl Default for InsertOptions {
+    fn default() -> InsertOptions {
+        InsertOptions {
+            conflict: Conflict::Update,
+            confidence: None,
+            override_confidence: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum Family {
     Domain,
     Subdomain,
@@ -47,6 +90,30 @@ pub enum Family {
     Account,
     Breach,
     BreachEmail,
+    Port,
+    CryptoAddr,
+}
+
+impl Family {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Family::Domain => "domain",
+            Family::Subdomain => "subdomain",
+            Family::IpAddr => "ipaddr",
+            Family::SubdomainIpAddr => "subdomain-ipaddr",
+            Family::Url => "url",
+            Family::Email => "email",
+            Family::PhoneNumber => "phonenumber",
+            Family::Device => "device",
+            Family::Network => "network",
+            Family::NetworkDevice => "network-device",
+            Family::Account => "account",
+            Family::Breach => "breach",
+            Family::BreachEmail => "breach-email",
+            Family::Port => "port",
+            Family::CryptoAddr => "cryptoaddr",
+        }
+    }
 }
 
 impl FromStr for Family {
@@ -67,6 +134,8 @@ impl FromStr for Family {
             "account" => Family::Account,
             "breach" => Family::Breach,
             "breach-email" => Family::BreachEmail,
+            "port" => Family::Port,
+            "cryptoaddr" => Family::CryptoAddr,
             _ => bail!("Unknown object family"),
         })
     }
@@ -78,21 +147,33 @@ pub struct Database {
 }
 
 impl Database {
-    pub fn establish(name: Workspace) -> Result<Database> {
+    /// Open a workspace, `passphrase` is set if the workspace is encrypted
+    pub fn establish(name: Workspace, passphrase: Option<String>) -> Result<Database> {
         let db = worker::spawn_fn("Connecting to database", || {
-            Database::establish_quiet(name)
+            Database::establish_quiet(name, passphrase)
         }, false)?;
 
         Ok(db)
     }
 
-    pub fn establish_quiet(name: Workspace) -> Result<Database> {
+    pub fn establish_quiet(name: Workspace, passphrase: Option<String>) -> Result<Database> {
         let path = paths::data_dir()?.join(name.to_string() + ".db");
         let path = path.into_os_string().into_string()
             .map_err(|_| format_err!("Failed to convert db path to utf-8"))?;
+        Database::connect(name, &path, passphrase.as_deref())
+    }
 
-        let db = SqliteConnection::establish(&path)
+    #[cfg(test)]
+    pub fn in_memory() -> Result<Database> {
+        Database::connect("test".parse()?, ":memory:", None)
+    }
+
+    fn connect(name: Workspace, path: &str, passphrase: Option<&str>) -> Result<Database> {
+        let db = SqliteConnection::establish(path)
             .context("Failed to connect to database")?;
+        if let Some(passphrase) = passphrase {
+            encryption::unlock(&db, passphrase)?;
+        }
         migrations::run(&db)
             .context("Failed to run migrations")?;
         db.execute("PRAGMA journal_mode = WAL")
@@ -116,31 +197,78 @@ impl Database {
 
     /// Returns true if we didn't have this value yet
     pub fn insert_generic(&self, object: Insert) -> Result<Option<(DbChange, i32)>> {
+        self.insert_generic_with(object, Conflict::Update)
+    }
+
+    pub fn insert_generic_with(&self, object: Insert, conflict: Conflict) -> Result<Option<(DbChange, i32)>> {
         match object {
-            Insert::Domain(object) => self.insert_struct(object),
-            Insert::Subdomain(object) => self.insert_struct(object),
-            Insert::IpAddr(object) => self.insert_struct(object),
-            Insert::SubdomainIpAddr(object) => self.insert_subdomain_ipaddr_struct(&object),
-            Insert::Url(object) => self.insert_struct(object),
-            Insert::Email(object) => self.insert_struct(object),
-            Insert::PhoneNumber(object) => self.insert_struct(object),
-            Insert::Device(object) => self.insert_struct(object),
-            Insert::Network(object) => self.insert_struct(object),
-            Insert::NetworkDevice(object) => self.insert_network_device_struct(&object),
-            Insert::Account(object) => self.insert_struct(object),
-            Insert::Breach(object) => self.insert_struct(object),
-            Insert::BreachEmail(object) => self.insert_breach_email_struct(object),
+            Insert::Domain(object) => self.insert_struct_with(object, conflict),
+            Insert::Subdomain(object) => self.insert_struct_with(object, conflict),
+            Insert::IpAddr(object) => self.insert_struct_with(object, conflict),
+            Insert::SubdomainIpAddr(object) => self.insert_subdomain_ipaddr_struct(&object, conflict),
+            Insert::Url(object) => self.insert_struct_with(object, conflict),
+            Insert::Email(object) => self.insert_struct_with(object, conflict),
+            Insert::PhoneNumber(object) => self.insert_struct_with(object, conflict),
+            Insert::Device(object) => self.insert_struct_with(object, conflict),
+            Insert::Network(object) => self.insert_struct_with(object, conflict),
+            Insert::NetworkDevice(object) => self.insert_network_device_struct(&object, conflict),
+            Insert::Account(object) => self.insert_struct_with(object, conflict),
+            Insert::Breach(object) => self.insert_struct_with(object, conflict),
+            Insert::BreachEmail(object) => self.insert_breach_email_struct(object, conflict),
+            Insert::Port(object) => self.insert_struct_with(object, conflict),
+            Insert::CryptoAddr(object) => self.insert_cryptoaddr_struct(object, conflict),
         }
     }
 
+    /// Call `f` for every object, every `batch_size` calls share a transaction
+    /// which is a lot faster than committing every insert on its own. An error
+    /// rolls back the current batch and stops, earlier batches are kept.
+    pub fn batched<I, T, F>(&self, objects: Vec<I>, batch_size: usize, mut f: F) -> Result<Vec<T>>
+        where F: FnMut(I) -> Result<T>
+    {
+        let batch_size = batch_size.max(1);
+        let mut results = Vec::with_capacity(objects.len());
+        let mut objects = objects.into_iter().peekable();
+
+        while objects.peek().is_some() {
+            self.db.transaction::<_, Error, _>(|| {
+                for object in objects.by_ref().take(batch_size) {
+                    results.push(f(object)?);
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok(results)
+    }
+
+    /// Insert many entities in batches, duplicates are handled like in
+    /// `insert_generic_with`. Every row has its own result, a row that fails
+    /// doesn't affect the others.
+    pub fn insert_many(&self, objects: Vec<Insert>, conflict: Conflict, batch_size: usize) -> Result<Vec<InsertResult>> {
+        self.batched(objects, batch_size, |object| {
+            Ok(self.insert_generic_with(object, conflict))
+        })
+    }
+
     /// Returns true if we didn't have this value yet
     pub fn insert_struct<T: InsertableStruct<M>, M: Model + Scopable>(&self, obj: T) -> Result<Option<(DbChange, i32)>> {
+        self.insert_struct_with(obj, Conflict::Update)
+    }
+
+    pub fn insert_struct_with<T: InsertableStruct<M>, M: Model + Scopable>(&self, obj: T, conflict: Conflict) -> Result<Option<(DbChange, i32)>> {
         if let Some(existing) = M::get_opt(self, obj.value())? {
             // entity is out of scope
             if !existing.scoped() {
                 return Ok(None);
             }
 
+            match conflict {
+                Conflict::Update => (),
+                Conflict::Skip => return Ok(Some((DbChange::None, existing.id()))),
+                Conflict::Error => bail!("Entity already exists: #{}", existing.id()),
+            }
+
             let update = obj.upsert(&existing);
             if update.is_dirty() {
                 update.apply(&self)?;
@@ -155,8 +283,20 @@ impl Database {
         }
     }
 
-    pub fn insert_subdomain_ipaddr_struct(&self, subdomain_ipaddr: &NewSubdomainIpAddr) -> Result<Option<(DbChange, i32)>> {
+    fn ensure_exists<T: Model>(&self, id: i32, name: &str) -> Result<()> {
+        T::by_id(self, id)
+            .map(|_| ())
+            .map_err(|_| format_err!("{} doesn't exist: #{}", name, id))
+    }
+
+    pub fn insert_subdomain_ipaddr_struct(&self, subdomain_ipaddr: &NewSubdomainIpAddr, conflict: Conflict) -> Result<Option<(DbChange, i32)>> {
+        self.ensure_exists::<Subdomain>(subdomain_ipaddr.subdomain_id, "Subdomain")?;
+        self.ensure_exists::<IpAddr>(subdomain_ipaddr.ip_addr_id, "IpAddr")?;
+
         if let Some(subdomain_ipaddr_id) = SubdomainIpAddr::get_id_opt(self, &(subdomain_ipaddr.subdomain_id, subdomain_ipaddr.ip_addr_id))? {
+            if conflict == Conflict::Error {
+                bail!("Link already exists: #{}", subdomain_ipaddr_id);
+            }
             Ok(Some((DbChange::None, subdomain_ipaddr_id)))
         } else {
             diesel::insert_into(subdomain_ipaddrs::table)
@@ -167,8 +307,14 @@ impl Database {
         }
     }
 
-    pub fn insert_network_device_struct(&self, network_device: &NewNetworkDevice) -> Result<Option<(DbChange, i32)>> {
+    pub fn insert_network_device_struct(&self, network_device: &NewNetworkDevice, conflict: Conflict) -> Result<Option<(DbChange, i32)>> {
+        self.ensure_exists::<Network>(network_device.network_id, "Network")?;
+        self.ensure_exists::<Device>(network_device.device_id, "Device")?;
+
         if let Some(network_device_id) = NetworkDevice::get_id_opt(self, &(network_device.network_id, network_device.device_id))? {
+            if conflict == Conflict::Error {
+                bail!("Link already exists: #{}", network_device_id);
+            }
             Ok(Some((DbChange::None, network_device_id)))
         } else {
             diesel::insert_into(network_devices::table)
@@ -179,12 +325,21 @@ impl Database {
         }
     }
 
-    pub fn insert_breach_email_struct(&self, obj: NewBreachEmail) -> Result<Option<(DbChange, i32)>> {
+    pub fn insert_breach_email_struct(&self, obj: NewBreachEmail, conflict: Conflict) -> Result<Option<(DbChange, i32)>> {
+        self.ensure_exists::<Breach>(obj.breach_id, "Breach")?;
+        self.ensure_exists::<Email>(obj.email_id, "Email")?;
+
         let value = &(obj.breach_id, obj.email_id, obj.password.clone());
 
         if let Some(existing) = BreachEmail::get_opt(self, value)? {
             let id = <BreachEmail as Model>::id(&existing);
 
+            match conflict {
+                Conflict::Update => (),
+                Conflict::Skip => return Ok(Some((DbChange::None, id))),
+                Conflict::Error => bail!("Link already exists: #{}", id),
+            }
+
             let update = obj.upsert(&existing);
             if update.is_dirty() {
                 update.apply(&self)?;
@@ -201,8 +356,56 @@ impl Database {
         }
     }
 
+    /// The same address may be valid for multiple currencies, so it's only a
+    /// duplicate if the currency matches as well
+    pub fn insert_cryptoaddr_struct(&self, obj: NewCryptoAddr, conflict: Conflict) -> Result<Option<(DbChange, i32)>> {
+        if let Some(existing) = CryptoAddr::get_with_currency_opt(self, &obj.currency, &obj.value)? {
+            // entity is out of scope
+            if !existing.scoped() {
+                return Ok(None);
+            }
+
+            match conflict {
+                Conflict::Update => (),
+                Conflict::Skip => return Ok(Some((DbChange::None, existing.id))),
+                Conflict::Error => bail!("Entity already exists: #{}", existing.id),
+            }
+
+            let update = obj.upsert(&existing);
+            if update.is_dirty() {
+                update.apply(self)?;
+                Ok(Some((DbChange::Update(update.generic()), existing.id)))
+            } else {
+                Ok(Some((DbChange::None, existing.id)))
+            }
+        } else {
+            obj.insert(self)?;
+            let id = CryptoAddr::get_with_currency(self, &obj.currency, &obj.value)?.id;
+            Ok(Some((DbChange::Insert, id)))
+        }
+    }
+
     //
 
+    /// Returns false if the entity targeted by the update doesn't exist
+    pub fn update_target_exists(&self, object: &Update) -> bool {
+        let id = object.id();
+        match object {
+            Update::Subdomain(_) => Subdomain::by_id(self, id).is_ok(),
+            Update::IpAddr(_) => IpAddr::by_id(self, id).is_ok(),
+            Update::Url(_) => Url::by_id(self, id).is_ok(),
+            Update::Email(_) => Email::by_id(self, id).is_ok(),
+            Update::PhoneNumber(_) => PhoneNumber::by_id(self, id).is_ok(),
+            Update::Device(_) => Device::by_id(self, id).is_ok(),
+            Update::Network(_) => Network::by_id(self, id).is_ok(),
+            Update::NetworkDevice(_) => NetworkDevice::by_id(self, id).is_ok(),
+            Update::Account(_) => Account::by_id(self, id).is_ok(),
+            Update::BreachEmail(_) => BreachEmail::by_id(self, id).is_ok(),
+            Update::Port(_) => Port::by_id(self, id).is_ok(),
+            Update::CryptoAddr(_) => CryptoAddr::by_id(self, id).is_ok(),
+        }
+    }
+
     pub fn update_generic(&self, object: &Update) -> Result<i32> {
         match object {
             Update::Subdomain(object) => self.update_subdomain(object),
@@ -215,6 +418,8 @@ impl Database {
             Update::NetworkDevice(object) => self.update_network_device(object),
             Update::Account(object) => self.update_account(object),
             Update::BreachEmail(object) => self.update_breach_email(object),
+            Update::Port(object) => self.update_port(object),
+            Update::CryptoAddr(object) => self.update_cryptoaddr(object),
         }
     }
 
@@ -298,6 +503,22 @@ impl Database {
         Ok(breach_email.id)
     }
 
+    pub fn update_port(&self, update: &PortUpdate) -> Result<i32> {
+        use crate::schema::ports::columns::*;
+        diesel::update(ports::table.filter(id.eq(update.id)))
+            .set(update)
+            .execute(&self.db)?;
+        Ok(update.id)
+    }
+
+    pub fn update_cryptoaddr(&self, update: &CryptoAddrUpdate) -> Result<i32> {
+        use crate::schema::cryptoaddrs::columns::*;
+        diesel::update(cryptoaddrs::table.filter(id.eq(update.id)))
+            .set(update)
+            .execute(&self.db)?;
+        Ok(update.id)
+    }
+
     fn get_opt_typed<T: Model + Scopable>(&self, value: &T::ID) -> Result<Option<i32>> {
         match T::get_opt(self, &value)? {
             Some(ref obj) if obj.scoped() => Ok(Some(obj.id())),
@@ -311,7 +532,10 @@ impl Database {
             Family::Subdomain => self.get_opt_typed::<Subdomain>(&value),
             Family::IpAddr => self.get_opt_typed::<IpAddr>(&value),
             Family::SubdomainIpAddr => bail!("Unsupported operation"),
-            Family::Url => self.get_opt_typed::<Url>(&value),
+            Family::Url => match url_canonical::canonical(value, false) {
+                Ok(value) => self.get_opt_typed::<Url>(&value),
+                Err(_) => self.get_opt_typed::<Url>(&value),
+            },
             Family::Email => self.get_opt_typed::<Email>(&value),
             Family::PhoneNumber => self.get_opt_typed::<PhoneNumber>(&value),
             Family::Device => self.get_opt_typed::<Device>(&value),
@@ -320,6 +544,47 @@ impl Database {
             Family::Account => self.get_opt_typed::<Account>(&value),
             Family::Breach => self.get_opt_typed::<Breach>(&value),
             Family::BreachEmail => bail!("Unsupported operation"),
+            Family::Port => self.get_opt_typed::<Port>(&value),
+            Family::CryptoAddr => self.get_opt_typed::<CryptoAddr>(&value),
+        }
+    }
+
+    fn query_typed<T: Model + Serialize>(&self, filter: &Filter, limit: Option<usize>, after: Option<i32>) -> Result<Vec<serde_json::Value>> {
+        let mut filter = filter.and_scoped();
+        if let Some(after) = after {
+            filter = filter.and_id_above(after);
+        }
+        // sqlite applies the limit, so only the requested rows are loaded
+        if let Some(limit) = limit {
+            filter = filter.limited(T::table(), limit);
+        }
+
+        T::filter(self, &filter)?
+            .iter()
+            .map(|x| serde_json::to_value(x).map_err(Error::from))
+            .collect()
+    }
+
+    /// Select scoped entities of a family that match the filter, sorted by
+    /// id. With `after` only entities with a higher id are returned, so a
+    /// large table can be read in batches.
+    pub fn query(&self, family: &Family, filter: &Filter, limit: Option<usize>, after: Option<i32>) -> Result<Vec<serde_json::Value>> {
+        match family {
+            Family::Domain => self.query_typed::<Domain>(filter, limit, after),
+            Family::Subdomain => self.query_typed::<Subdomain>(filter, limit, after),
+            Family::IpAddr => self.query_typed::<IpAddr>(filter, limit, after),
+            Family::SubdomainIpAddr => bail!("Unsupported operation"),
+            Family::Url => self.query_typed::<Url>(filter, limit, after),
+            Family::Email => self.query_typed::<Email>(filter, limit, after),
+            Family::PhoneNumber => self.query_typed::<PhoneNumber>(filter, limit, after),
+            Family::Device => self.query_typed::<Device>(filter, limit, after),
+            Family::Network => self.query_typed::<Network>(filter, limit, after),
+            Family::NetworkDevice => bail!("Unsupported operation"),
+            Family::Account => self.query_typed::<Account>(filter, limit, after),
+            Family::Breach => self.query_typed::<Breach>(filter, limit, after),
+            Family::BreachEmail => bail!("Unsupported operation"),
+            Family::Port => self.query_typed::<Port>(filter, limit, after),
+            Family::CryptoAddr => self.query_typed::<CryptoAddr>(filter, limit, after),
         }
     }
 
@@ -329,10 +594,26 @@ impl Database {
         T::list(self)
     }
 
+    /// List the columns of the table that stores `T`
+    pub fn columns<T: Model>(&self) -> Result<Vec<String>> {
+        let columns = diesel::sql_query(format!("PRAGMA table_info({})", T::table()))
+            .load::<TableColumn>(self.db())?;
+        Ok(columns.into_iter()
+            .map(|c| c.name)
+            .collect())
+    }
+
     pub fn filter<T: Model>(&self, filter: &Filter) -> Result<Vec<T>> {
         T::filter(self, filter)
     }
 
+    /// The highest id of the table that stores `T`, None if it's empty
+    pub fn max_id<T: Model>(&self) -> Result<Option<i32>> {
+        let row = diesel::sql_query(format!("SELECT max(id) AS id FROM {}", T::table()))
+            .get_result::<MaxId>(self.db())?;
+        Ok(row.id)
+    }
+
     pub fn filter_with_param<T: Model>(&self, filter: &Filter, param: Option<&String>) -> Result<Vec<T>> {
         match param {
             Some(param) => T::filter_with_param(self, filter, param),
@@ -351,17 +632,60 @@ impl Database {
     pub fn delete<T: Model>(&self, filter: &Filter) -> Result<usize> {
         T::delete(self, filter)
     }
+
+    /// Like `delete`, entities that depend on this one are removed by the
+    /// foreign keys. Returns false if the entity didn't exist
+    pub fn delete_id(&self, family: &Family, id: i32) -> Result<bool> {
+        let rows = match family {
+            Family::Domain => Domain::delete_id(self, id)?,
+            Family::Subdomain => Subdomain::delete_id(self, id)?,
+            Family::IpAddr => IpAddr::delete_id(self, id)?,
+            Family::SubdomainIpAddr => SubdomainIpAddr::delete_id(self, id)?,
+            Family::Url => Url::delete_id(self, id)?,
+            Family::Email => Email::delete_id(self, id)?,
+            Family::PhoneNumber => PhoneNumber::delete_id(self, id)?,
+            Family::Device => Device::delete_id(self, id)?,
+            Family::Network => Network::delete_id(self, id)?,
+            Family::NetworkDevice => NetworkDevice::delete_id(self, id)?,
+            Family::Account => Account::delete_id(self, id)?,
+            Family::Breach => Breach::delete_id(self, id)?,
+            Family::BreachEmail => BreachEmail::delete_id(self, id)?,
+            Family::Port => Port::delete_id(self, id)?,
+            Family::CryptoAddr => CryptoAddr::delete_id(self, id)?,
+        };
+        Ok(rows > 0)
+    }
+}
+
+#[derive(QueryableByName)]
+struct TableColumn {
+    #[sql_type="Text"]
+    name: String,
+}
+
+#[derive(QueryableByName)]
+struct MaxId {
+    #[sql_type="Nullable<Integer>"]
+    id: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Order {
+    column: String,
+    desc: bool,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Filter {
     query: String,
+    order: Vec<Order>,
 }
 
 impl Filter {
     pub fn new<I: Into<String>>(query: I) -> Filter {
         Filter {
             query: query.into(),
+            order: Vec::new(),
         }
     }
 
@@ -422,6 +746,35 @@ impl Filter {
         Ok(Filter::new(query))
     }
 
+    /// Build a filter that requires every column to equal the given value
+    pub fn from_fields(fields: &HashMap<String, serde_json::Value>) -> Result<Filter> {
+        use serde_json::Value;
+
+        if fields.is_empty() {
+            return Ok(Filter::new("1"));
+        }
+
+        let mut keys = fields.keys().collect::<Vec<_>>();
+        keys.sort();
+
+        let mut query = Vec::new();
+        for key in keys {
+            if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                bail!("Invalid column name: {:?}", key);
+            }
+
+            query.push(match &fields[key] {
+                Value::Null => format!("{} IS NULL", key),
+                Value::Bool(v) => format!("{} = {}", key, if *v { 1 } else { 0 }),
+                Value::Number(v) => format!("{} = {}", key, v),
+                Value::String(v) => format!("{} = {}", key, Self::escape(v)),
+                _ => bail!("Unsupported filter value for {:?}", key),
+            });
+        }
+
+        Ok(Filter::new(query.join(" AND ")))
+    }
+
     pub fn parse_optional(args: &[String]) -> Result<Filter> {
         debug!("Parsing optional query: {:?}", args);
 
@@ -433,24 +786,536 @@ impl Filter {
         Self::parse(args)
     }
 
+    /// Parse an optional filter followed by an optional `order by` clause
+    pub fn parse_ordered(args: &[String]) -> Result<Filter> {
+        let pos = args.windows(2)
+            .position(|w| w[0].to_lowercase() == "order" && w[1].to_lowercase() == "by");
+
+        let (args, order) = match pos {
+            Some(pos) => (&args[..pos], Self::parse_order(&args[pos+2..])?),
+            None => (args, Vec::new()),
+        };
+
+        let mut filter = Self::parse_optional(args)?;
+        filter.order = order;
+        Ok(filter)
+    }
+
+    fn parse_order(args: &[String]) -> Result<Vec<Order>> {
+        debug!("Parsing order: {:?}", args);
+
+        let clause = args.join(" ");
+        let mut order = Vec::new();
+
+        for key in clause.split(',') {
+            let key = key.split_whitespace().collect::<Vec<_>>();
+            let (column, desc) = match key.as_slice() {
+                [column] => (column, false),
+                [column, direction] => match direction.to_lowercase().as_str() {
+                    "asc" => (column, false),
+                    "desc" => (column, true),
+                    _ => bail!("Invalid sort direction: {:?}", direction),
+                },
+                [] => bail!("Sort key is missing"),
+                _ => bail!("Invalid sort key: {:?}", key.join(" ")),
+            };
+
+            if !column.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                bail!("Invalid column name: {:?}", column);
+            }
+
+            order.push(Order {
+                column: column.to_string(),
+                desc,
+            });
+        }
+
+        Ok(order)
+    }
+
+    /// Make sure every sort key refers to an existing column
+    pub fn validate_order(&self, columns: &[String]) -> Result<()> {
+        for key in &self.order {
+            if !columns.contains(&key.column) {
+                bail!("Unknown column: {:?}", key.column);
+            }
+        }
+        Ok(())
+    }
+
     pub fn query(&self) -> &str {
         &self.query
     }
 
     pub fn and_scoped(&self) -> Filter {
         let query = format!("({}) AND unscoped=0", self.query);
-        Filter::new(query)
+        Filter {
+            query,
+            order: self.order.clone(),
+        }
+    }
+
+    /// Only match rows that have been added after `id`
+    pub fn and_id_above(&self, id: i32) -> Filter {
+        let query = format!("({}) AND id > {}", self.query, id);
+        Filter {
+            query,
+            order: self.order.clone(),
+        }
+    }
+
+    /// Only match the first `limit` rows of `table` by id, the order of the
+    /// filter isn't used to pick them
+    pub fn limited(&self, table: &str, limit: usize) -> Filter {
+        let query = format!("id IN (SELECT id FROM {} WHERE {} ORDER BY id ASC LIMIT {})", table, self.query, limit);
+        Filter {
+            query,
+            order: self.order.clone(),
+        }
+    }
+
+    /// Only match rows with at least this confidence, rows without one have
+    /// the default confidence
+    pub fn and_min_confidence(&self, table: &str, min: i32) -> Filter {
+        let query = format!("({}) AND COALESCE((SELECT confidence FROM confidences WHERE family = {} AND key = {}.id), {}) >= {}",
+            self.query, Self::escape(table), table, confidence::DEFAULT_CONFIDENCE, min);
+        Filter {
+            query,
+            order: self.order.clone(),
+        }
     }
 
     pub fn sql(&self) -> SqlLiteral<Bool> {
         sql::<Bool>(&self.query)
     }
+
+    /// Sort keys of this filter, ties and unordered filters are sorted by id
+    pub fn order_sql(&self) -> SqlLiteral<Text> {
+        let mut order = self.order.iter()
+            .map(|key| format!("{} {}", key.column, if key.desc { "DESC" } else { "ASC" }))
+            .collect::<Vec<_>>();
+        order.push("id ASC".to_string());
+        sql::<Text>(&order.join(", "))
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::json;
+
+    fn insert(db: &Database, object: serde_json::Value) -> i32 {
+        let object: Insert = serde_json::from_value(object).unwrap();
+        db.insert_generic(object).unwrap().unwrap().1
+    }
+
+    #[test]
+    fn test_delete_id_cascades() {
+        let db = Database::in_memory().unwrap();
+        let domain = insert(&db, json!({"Domain": {"value": "example.com"}}));
+        let www = insert(&db, json!({"Subdomain": {"domain_id": domain, "value": "www.example.com"}}));
+        let mail = insert(&db, json!({"Subdomain": {"domain_id": domain, "value": "mail.example.com"}}));
+        let ipaddr = insert(&db, json!({"IpAddr": {"family": "4", "value": "192.0.2.1"}}));
+        let link = insert(&db, json!({"SubdomainIpAddr": {"subdomain_id": www, "ip_addr_id": ipaddr}}));
+        let url = insert(&db, json!({"Url": {"subdomain_id": www, "value": "https://www.example.com/", "path": "/", "body": null}}));
+
+        assert!(db.delete_id(&Family::Subdomain, www).unwrap());
+        assert!(!db.delete_id(&Family::Subdomain, www).unwrap());
+
+        // children are removed, parents and unrelated entities are kept
+        assert!(Url::by_id(&db, url).is_err());
+        assert!(SubdomainIpAddr::by_id(&db, link).is_err());
+        assert!(Subdomain::by_id(&db, mail).is_ok());
+        assert!(Domain::by_id(&db, domain).is_ok());
+        assert!(IpAddr::by_id(&db, ipaddr).is_ok());
+
+        assert!(db.delete_id(&Family::Domain, domain).unwrap());
+        assert!(Subdomain::by_id(&db, mail).is_err());
+        assert!(IpAddr::by_id(&db, ipaddr).is_ok());
+    }
+
+    #[test]
+    fn test_delete_removes_confidence() {
+        use crate::db::confidence::Confidence;
+
+        let db = Database::in_memory().unwrap();
+        let domain: Insert = serde_json::from_value(json!({"Domain": {"value": "example.com"}})).unwrap();
+        let subdomain: Insert = serde_json::from_value(json!({"Subdomain": {"domain_id": 1, "value": "www.example.com"}})).unwrap();
+        let domain_id = db.insert_generic(domain.clone()).unwrap().unwrap().1;
+        let subdomain_id = db.insert_generic(subdomain.clone()).unwrap().unwrap().1;
+        Confidence::create(&domain, domain_id, 90, &db).unwrap();
+        Confidence::create(&subdomain, subdomain_id, 90, &db).unwrap();
+
+        // the subdomain is removed by the foreign key
+        assert!(db.delete_id(&Family::Domain, domain_id).unwrap());
+        assert!(Confidence::find(&domain, domain_id, &db).unwrap().is_none());
+        assert!(Confidence::find(&subdomain, subdomain_id, &db).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_filter_min_confidence() {
+        use crate::db::confidence::Confidence;
+
+        let db = Database::in_memory().unwrap();
+        let objects = domains(&["a.com", "b.com", "c.com"]);
+        let mut ids = Vec::new();
+        for object in &objects {
+            ids.push(db.insert_generic(object.clone()).unwrap().unwrap().1);
+        }
+        Confidence::create(&objects[0], ids[0], 90, &db).unwrap();
+        Confidence::create(&objects[1], ids[1], 10, &db).unwrap();
+
+        let values = |min| {
+            let filter = Filter::new("1").and_min_confidence("domains", min);
+            db.filter::<Domain>(&filter).unwrap().into_iter()
+                .map(|d| d.value)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(values(0), vec!["a.com", "b.com", "c.com"]);
+        // c.com has the default confidence
+        assert_eq!(values(confidence::DEFAULT_CONFIDENCE), vec!["a.com", "c.com"]);
+        assert_eq!(values(75), vec!["a.com"]);
+    }
+
+    fn domains(values: &[&str]) -> Vec<Insert> {
+        values.iter()
+            .map(|value| Insert::Domain(NewDomain {
+                value: value.to_string(),
+            }))
+            .collect()
+    }
+
+    #[test]
+    fn test_insert_many() {
+        let db = Database::in_memory().unwrap();
+        let b = insert(&db, json!({"Domain": {"value": "b.com"}}));
+
+        let mut objects = domains(&["a.com", "b.com", "a.com"]);
+        objects.push(Insert::Subdomain(NewSubdomain {
+            domain_id: 1337,
+            value: "www.c.com".to_string(),
+            resolvable: None,
+        }));
+        objects.extend(domains(&["c.com"]));
+
+        let results = db.insert_many(objects, Conflict::Update, 2).unwrap();
+        assert_eq!(results.len(), 5);
+
+        let a = match &results[0] {
+            Ok(Some((DbChange::Insert, id))) => *id,
+            x => panic!("unexpected result: {:?}", x),
+        };
+        match &results[1] {
+            Ok(Some((DbChange::None, id))) => assert_eq!(*id, b),
+            x => panic!("unexpected result: {:?}", x),
+        }
+        match &results[2] {
+            Ok(Some((DbChange::None, id))) => assert_eq!(*id, a),
+            x => panic!("unexpected result: {:?}", x),
+        }
+        assert!(results[3].is_err());
+        match &results[4] {
+            Ok(Some((DbChange::Insert, _))) => (),
+            x => panic!("unexpected result: {:?}", x),
+        }
+
+        let results = db.insert_many(domains(&["a.com", "d.com"]), Conflict::Error, 10).unwrap();
+        assert!(results[0].is_err());
+        assert!(results[1].is_ok());
+        assert_eq!(db.list::<Domain>().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_batched_rollback() {
+        let db = Database::in_memory().unwrap();
+        let result = db.batched(domains(&["a.com", "b.com", "c.com", "d.com"]), 2, |object| {
+            if let Insert::Domain(domain) = &object {
+                if domain.value == "d.com" {
+                    bail!("stop");
+                }
+            }
+            db.insert_generic(object)
+        });
+        assert!(result.is_err());
+
+        // the first batch was committed, the second one was rolled back
+        let values = db.list::<Domain>().unwrap()
+            .into_iter()
+            .map(|d| d.value)
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec!["a.com", "b.com"]);
+    }
+
+    /// Compare the batched path with single inserts, run with
+    /// `cargo test --release bench_insert_many -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_insert_many() {
+        use std::time::Instant;
+
+        let path = std::env::temp_dir().join(format!("sn0int-bench-{}.db", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let db = Database::connect("bench".parse().unwrap(), &path, None).unwrap();
+
+        let values = |prefix: &str| (0..2000)
+            .map(|i| format!("{}{}.com", prefix, i))
+            .collect::<Vec<_>>();
+
+        let single = values("single");
+        let start = Instant::now();
+        for value in &single {
+            db.insert_generic(domains(&[value])[0].clone()).unwrap();
+        }
+        let single_time = start.elapsed();
+
+        let batched = values("batched");
+        let batched = batched.iter().map(String::as_str).collect::<Vec<_>>();
+        let start = Instant::now();
+        for result in db.insert_many(domains(&batched), Conflict::Update, 500).unwrap() {
+            result.unwrap();
+        }
+        let batched_time = start.elapsed();
+
+        drop(db);
+        for suffix in &["", "-wal", "-shm"] {
+            std::fs::remove_file(format!("{}{}", path, suffix)).ok();
+        }
+
+        println!("single inserts: {:?}, batched inserts: {:?}", single_time, batched_time);
+        assert!(batched_time < single_time);
+    }
+
+    #[test]
+    fn test_query_batches() {
+        let db = Database::in_memory().unwrap();
+        for i in 0..25 {
+            insert(&db, json!({"Domain": {"value": format!("{}.com", i)}}));
+        }
+        db.noscope::<Domain>(&Filter::new("id % 5 = 0")).unwrap();
+
+        let mut ids = Vec::new();
+        let mut after = 0;
+        loop {
+            let rows = db.query(&Family::Domain, &Filter::new("1"), Some(7), Some(after)).unwrap();
+            if rows.is_empty() {
+                break;
+            }
+            assert!(rows.len() <= 7);
+            after = rows.last().unwrap()["id"].as_i64().unwrap() as i32;
+            ids.extend(rows.iter().map(|row| row["id"].as_i64().unwrap()));
+        }
+
+        let expected = (1..=25).filter(|id| id % 5 != 0).collect::<Vec<_>>();
+        assert_eq!(ids, expected);
+    }
+
+    #[test]
+    fn test_filter_order_by() {
+        let db = Database::in_memory().unwrap();
+        insert(&db, json!({"Domain": {"value": "b.com"}}));
+        insert(&db, json!({"Domain": {"value": "c.com"}}));
+        insert(&db, json!({"Domain": {"value": "a.com"}}));
+
+        let values = |args: &[&str]| {
+            let args = args.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            let filter = Filter::parse_ordered(&args).unwrap();
+            filter.validate_order(&db.columns::<Domain>().unwrap()).unwrap();
+            db.filter::<Domain>(&filter).unwrap()
+                .into_iter()
+                .map(|d| d.value)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(values(&[]), vec!["b.com", "c.com", "a.com"]);
+        assert_eq!(values(&["order", "by", "value"]), vec!["a.com", "b.com", "c.com"]);
+        assert_eq!(values(&["where", "value", "!=", "b.com", "ORDER", "BY", "value", "DESC"]), vec!["c.com", "a.com"]);
+        assert_eq!(values(&["order", "by", "unscoped,", "id", "desc"]), vec!["a.com", "c.com", "b.com"]);
+    }
+
+    #[test]
+    fn test_filter_id_above() {
+        let db = Database::in_memory().unwrap();
+        assert_eq!(db.max_id::<Domain>().unwrap(), None);
+
+        insert(&db, json!({"Domain": {"value": "a.com"}}));
+        let last = db.max_id::<Domain>().unwrap().unwrap();
+        insert(&db, json!({"Domain": {"value": "b.com"}}));
+        insert(&db, json!({"Domain": {"value": "c.com"}}));
+        assert_eq!(db.max_id::<Domain>().unwrap(), Some(last + 2));
+
+        let filter = Filter::parse_optional(&["where".to_string(), "value".to_string(), "!=".to_string(), "c.com".to_string()]).unwrap();
+        let values = db.filter::<Domain>(&filter.and_id_above(last)).unwrap()
+            .into_iter()
+            .map(|d| d.value)
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec!["b.com"]);
+    }
+
+    #[test]
+    fn test_cryptoaddrs() {
+        let db = Database::in_memory().unwrap();
+        let add = |currency: &str, value: &str, label: Option<&str>| {
+            let object = InsertCryptoAddr {
+                value: value.to_string(),
+                currency: currency.to_string(),
+                label: label.map(String::from),
+            }.try_into_new().unwrap();
+            db.insert_generic(Insert::CryptoAddr(object)).unwrap().unwrap()
+        };
+
+        let (_, btc) = add("BTC", "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2", None);
+        let (_, eth) = add("eth", "0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed", None);
+        add("btc", "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4", Some("cold storage"));
+
+        // the same address in its checksummed form is deduplicated
+        let (change, id) = add("eth", "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed", Some("donations"));
+        assert_eq!(id, eth);
+        match change {
+            DbChange::Update(_) => (),
+            change => panic!("Expected an update: {:?}", change),
+        }
+
+        let select = |args: &[&str]| {
+            let args = args.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            let filter = Filter::parse_optional(&args).unwrap();
+            db.filter::<CryptoAddr>(&filter).unwrap()
+        };
+
+        let btcs = select(&["where", "currency", "=", "btc"]);
+        assert_eq!(btcs.iter().map(|x| x.value.as_str()).collect::<Vec<_>>(),
+            vec!["1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2", "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"]);
+        assert_eq!(btcs[0].id, btc);
+
+        let eths = select(&["where", "value", "=", "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"]);
+        assert_eq!(eths.len(), 1);
+        assert_eq!(eths[0].currency, "eth");
+        assert_eq!(eths[0].label, Some("donations".to_string()));
+
+        assert_eq!(select(&[]).len(), 3);
+
+        // the same address on a different chain is a different entity
+        let (change, etc) = add("etc", "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed", None);
+        match change {
+            DbChange::Insert => (),
+            change => panic!("Expected an insert: {:?}", change),
+        }
+        assert_ne!(etc, eth);
+        assert_eq!(select(&[]).len(), 4);
+    }
+
+    #[test]
+    fn test_cryptoaddrs_invalid() {
+        let object = InsertCryptoAddr {
+            value: "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN3".to_string(),
+            currency: "btc".to_string(),
+            label: None,
+        };
+        assert!(object.try_into_new().is_err());
+    }
+
+    #[test]
+    fn test_filter_order_by_invalid() {
+        let parse = |args: &[&str]| {
+            let args = args.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            Filter::parse_ordered(&args)
+        };
+
+        assert!(parse(&["order", "by"]).is_err());
+        assert!(parse(&["order", "by", "value", "up"]).is_err());
+        assert!(parse(&["order", "by", "value,", ",id"]).is_err());
+        assert!(parse(&["order", "by", "value;", "drop"]).is_err());
+
+        let db = Database::in_memory().unwrap();
+        let filter = parse(&["order", "by", "path"]).unwrap();
+        assert!(filter.validate_order(&db.columns::<Domain>().unwrap()).is_err());
+        assert!(filter.validate_order(&db.columns::<Url>().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_conflict_deserialize() {
+        let conflict: Conflict = serde_json::from_str(r#""skip""#).unwrap();
+        assert_eq!(conflict, Conflict::Skip);
+        assert!(serde_json::from_str::<Conflict>(r#""replace""#).is_err());
+    }
+
+    #[test]
+    fn test_conflict_duplicates() {
+        let db = Database::in_memory().unwrap();
+        let domain = insert(&db, json!({"Domain": {"value": "example.com"}}));
+        let www = insert(&db, json!({"Subdomain": {"domain_id": domain, "value": "www.example.com", "resolvable": false}}));
+
+        let add = |conflict| {
+            let object = serde_json::from_value(json!({"Subdomain": {"domain_id": domain, "value": "www.example.com", "resolvable": true}})).unwrap();
+            db.insert_generic_with(object, conflict)
+        };
+        let resolvable = || Subdomain::by_id(&db, www).unwrap().resolvable;
+
+        match add(Conflict::Skip).unwrap() {
+            Some((DbChange::None, id)) => assert_eq!(id, www),
+            change => panic!("Expected no change: {:?}", change),
+        }
+        assert_eq!(resolvable(), Some(false));
+
+        let err = add(Conflict::Error).unwrap_err();
+        assert_eq!(err.to_string(), format!("Entity already exists: #{}", www));
+        assert_eq!(resolvable(), Some(false));
+
+        match add(Conflict::Update).unwrap() {
+            Some((DbChange::Update(_), id)) => assert_eq!(id, www),
+            change => panic!("Expected an update: {:?}", change),
+        }
+        assert_eq!(resolvable(), Some(true));
+    }
+
+    #[test]
+    fn test_filter_from_fields() {
+        let mut fields = HashMap::new();
+        fields.insert("value".to_string(), serde_json::Value::from("a'b"));
+        fields.insert("domain_id".to_string(), serde_json::Value::from(1));
+        fields.insert("resolvable".to_string(), serde_json::Value::Bool(true));
+        let filter = Filter::from_fields(&fields).unwrap();
+        assert_eq!(filter, Filter::new("domain_id = 1 AND resolvable = 1 AND value = 'a''b'"));
+    }
+
+    #[test]
+    fn test_filter_from_fields_invalid_column() {
+        let mut fields = HashMap::new();
+        fields.insert("1=1 OR value".to_string(), serde_json::Value::from("x"));
+        assert!(Filter::from_fields(&fields).is_err());
+    }
+
+    #[test]
+    fn test_query_fields_limit() {
+        let db = Database::in_memory().unwrap();
+        let a = insert(&db, json!({"Domain": {"value": "a.com"}}));
+        let b = insert(&db, json!({"Domain": {"value": "b.com"}}));
+        for value in &["www.a.com", "mail.a.com", "dev.a.com"] {
+            insert(&db, json!({"Subdomain": {"domain_id": a, "value": value}}));
+        }
+        insert(&db, json!({"Subdomain": {"domain_id": b, "value": "www.b.com"}}));
+
+        let query = |fields: serde_json::Value, limit, after| {
+            let fields = serde_json::from_value::<HashMap<String, serde_json::Value>>(fields).unwrap();
+            let filter = Filter::from_fields(&fields).unwrap();
+            db.query(&Family::Subdomain, &filter, limit, after).unwrap()
+                .into_iter()
+                .map(|row| row["value"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(query(json!({}), None, None), vec!["www.a.com", "mail.a.com", "dev.a.com", "www.b.com"]);
+        assert_eq!(query(json!({"domain_id": a}), None, None), vec!["www.a.com", "mail.a.com", "dev.a.com"]);
+        assert_eq!(query(json!({"domain_id": a}), Some(2), None), vec!["www.a.com", "mail.a.com"]);
+        assert_eq!(query(json!({"domain_id": b, "value": "www.b.com"}), Some(2), None), vec!["www.b.com"]);
+        assert!(query(json!({"value": "www.c.com"}), None, None).is_empty());
+
+        // rows added after the first query show up in the next one
+        insert(&db, json!({"Subdomain": {"domain_id": a, "value": "api.a.com"}}));
+        assert_eq!(query(json!({"domain_id": a}), None, None), vec!["www.a.com", "mail.a.com", "dev.a.com", "api.a.com"]);
+
+        assert!(db.query(&Family::SubdomainIpAddr, &Filter::new("1"), None, None).is_err());
+    }
 
     #[test]
     fn test_filter_simple() {
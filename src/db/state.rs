@@ -0,0 +1,169 @@
+use crate::errors::*;
+use crate::db::Database;
+use crate::schema::*;
+use diesel;
+use diesel::prelude::*;
+use serde_json;
+
+
+pub const MAX_KEY_SIZE: usize = 256;
+pub const MAX_VALUE_SIZE: usize = 64 * 1024;
+
+#[derive(Identifiable, Queryable, PartialEq, Debug)]
+#[table_name="module_state"]
+pub struct ModuleState {
+    pub id: i32,
+    pub module: String,
+    pub key: String,
+    pub value: String,
+    pub version: String,
+}
+
+#[derive(Insertable)]
+#[table_name="module_state"]
+struct NewModuleState<'a> {
+    module: &'a str,
+    key: &'a str,
+    value: &'a str,
+    version: &'a str,
+}
+
+/// Serialize a value and make sure it stays within the limits
+pub fn encode(key: &str, value: &serde_json::Value) -> Result<String> {
+    if key.is_empty() || key.len() > MAX_KEY_SIZE {
+        bail!("State keys need to be between 1 and {} bytes", MAX_KEY_SIZE);
+    }
+
+    let value = serde_json::to_string(value)?;
+    if value.len() > MAX_VALUE_SIZE {
+        bail!("State value is too large ({} bytes, max is {})", value.len(), MAX_VALUE_SIZE);
+    }
+
+    Ok(value)
+}
+
+impl ModuleState {
+    pub fn get(db: &Database, my_module: &str, my_key: &str) -> Result<Option<serde_json::Value>> {
+        use crate::schema::module_state::dsl::*;
+
+        let state = module_state
+            .filter(module.eq(my_module))
+            .filter(key.eq(my_key))
+            .first::<Self>(db.db())
+            .optional()?;
+
+        match state {
+            Some(state) => Ok(Some(serde_json::from_str(&state.value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Store a value for this module, None deletes the key
+    pub fn set(db: &Database, my_module: &str, my_version: &str, my_key: &str, my_value: Option<&serde_json::Value>) -> Result<()> {
+        use crate::schema::module_state::dsl::*;
+
+        let my_value = match my_value {
+            Some(my_value) => encode(my_key, my_value)?,
+            None => {
+                diesel::delete(module_state
+                    .filter(module.eq(my_module))
+                    .filter(key.eq(my_key)))
+                    .execute(db.db())?;
+                return Ok(());
+            },
+        };
+
+        diesel::replace_into(module_state)
+            .values(NewModuleState {
+                module: my_module,
+                key: my_key,
+                value: &my_value,
+                version: my_version,
+            })
+            .execute(db.db())?;
+
+        Ok(())
+    }
+
+    /// Remove state that has been written by a different version of this
+    /// module, its format might have changed since then
+    pub fn clear_outdated(db: &Database, my_module: &str, my_version: &str) -> Result<usize> {
+        use crate::schema::module_state::dsl::*;
+
+        diesel::delete(module_state
+            .filter(module.eq(my_module))
+            .filter(version.ne(my_version)))
+            .execute(db.db())
+            .map_err(Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_encode_limits() {
+        let value = json!({"cursor": 1234});
+        assert_eq!(encode("cursor", &value).unwrap(), r#"{"cursor":1234}"#);
+        assert!(encode("", &value).is_err());
+        assert!(encode(&"a".repeat(MAX_KEY_SIZE + 1), &value).is_err());
+
+        let large = serde_json::Value::String("a".repeat(MAX_VALUE_SIZE));
+        assert!(encode("large", &large).is_err());
+    }
+
+    #[test]
+    fn test_set_get() {
+        let db = Database::in_memory().unwrap();
+        assert_eq!(ModuleState::get(&db, "kpcyrd/foo", "cursor").unwrap(), None);
+
+        ModuleState::set(&db, "kpcyrd/foo", "0.1.0", "cursor", Some(&json!({"page": 1}))).unwrap();
+        assert_eq!(ModuleState::get(&db, "kpcyrd/foo", "cursor").unwrap(), Some(json!({"page": 1})));
+
+        // keys are separate for each module
+        assert_eq!(ModuleState::get(&db, "kpcyrd/bar", "cursor").unwrap(), None);
+    }
+
+    #[test]
+    fn test_set_overwrite() {
+        let db = Database::in_memory().unwrap();
+        ModuleState::set(&db, "kpcyrd/foo", "0.1.0", "cursor", Some(&json!(1))).unwrap();
+        ModuleState::set(&db, "kpcyrd/foo", "0.1.0", "cursor", Some(&json!(2))).unwrap();
+        assert_eq!(ModuleState::get(&db, "kpcyrd/foo", "cursor").unwrap(), Some(json!(2)));
+    }
+
+    #[test]
+    fn test_set_none_deletes() {
+        let db = Database::in_memory().unwrap();
+        ModuleState::set(&db, "kpcyrd/foo", "0.1.0", "cursor", Some(&json!("abc"))).unwrap();
+        ModuleState::set(&db, "kpcyrd/foo", "0.1.0", "other", Some(&json!(true))).unwrap();
+
+        ModuleState::set(&db, "kpcyrd/foo", "0.1.0", "cursor", None).unwrap();
+        assert_eq!(ModuleState::get(&db, "kpcyrd/foo", "cursor").unwrap(), None);
+        assert_eq!(ModuleState::get(&db, "kpcyrd/foo", "other").unwrap(), Some(json!(true)));
+
+        // deleting a missing key is not an error
+        ModuleState::set(&db, "kpcyrd/foo", "0.1.0", "cursor", None).unwrap();
+    }
+
+    #[test]
+    fn test_clear_outdated() {
+        let db = Database::in_memory().unwrap();
+        ModuleState::set(&db, "kpcyrd/foo", "0.1.0", "cursor", Some(&json!(1))).unwrap();
+        ModuleState::set(&db, "kpcyrd/bar", "0.1.0", "cursor", Some(&json!(2))).unwrap();
+
+        assert_eq!(ModuleState::clear_outdated(&db, "kpcyrd/foo", "0.1.0").unwrap(), 0);
+        assert_eq!(ModuleState::get(&db, "kpcyrd/foo", "cursor").unwrap(), Some(json!(1)));
+
+        assert_eq!(ModuleState::clear_outdated(&db, "kpcyrd/foo", "0.2.0").unwrap(), 1);
+        assert_eq!(ModuleState::get(&db, "kpcyrd/foo", "cursor").unwrap(), None);
+        assert_eq!(ModuleState::get(&db, "kpcyrd/bar", "cursor").unwrap(), Some(json!(2)));
+
+        // state written by the new version is kept
+        ModuleState::set(&db, "kpcyrd/foo", "0.2.0", "cursor", Some(&json!(3))).unwrap();
+        assert_eq!(ModuleState::clear_outdated(&db, "kpcyrd/foo", "0.2.0").unwrap(), 0);
+        assert_eq!(ModuleState::get(&db, "kpcyrd/foo", "cursor").unwrap(), Some(json!(3)));
+    }
+}
@@ -110,6 +110,8 @@ impl Ttl {
             "devices" => Device::delete_id(db, self.key)?,
             "networks" => Network::delete_id(db, self.key)?,
             "network_devices" => NetworkDevice::delete_id(db, self.key)?,
+            "ports" => Port::delete_id(db, self.key)?,
+            "cryptoaddrs" => CryptoAddr::delete_id(db, self.key)?,
             _ => bail!("Unknown table"),
         };
 
@@ -0,0 +1,256 @@
+use crate::errors::*;
+
+use std::fmt;
+use std::net;
+
+
+/// The longest address that can be used in a smtp `MAIL FROM` or `RCPT TO`
+const MAX_ADDRESS_LEN: usize = 254;
+const MAX_LOCAL_LEN: usize = 64;
+const MAX_LABEL_LEN: usize = 63;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EmailAddress {
+    /// The local part as written, including the quotes of quoted local parts
+    pub local: String,
+    /// The lowercase domain, or the address literal like `[192.0.2.1]`
+    pub domain: String,
+    /// The local part without the plus-addressing tag
+    pub user: String,
+    /// The part after the `+` in `user+tag@example.com`
+    pub tag: Option<String>,
+}
+
+impl fmt::Display for EmailAddress {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "{}@{}", self.local, self.domain)
+    }
+}
+
+fn is_atext(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c) || !c.is_ascii()
+}
+
+fn validate_local(local: &str) -> Result<()> {
+    if local.is_empty() {
+        bail!("Local part is empty");
+    }
+    if local.len() > MAX_LOCAL_LEN {
+        bail!("Local part is longer than {} bytes", MAX_LOCAL_LEN);
+    }
+
+    if local.starts_with('"') {
+        if local.len() < 2 || !local.ends_with('"') {
+            bail!("Quoted local part isn't terminated");
+        }
+
+        let mut chars = local[1..local.len()-1].chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(c) if c == ' ' || c.is_ascii_graphic() => (),
+                    _ => bail!("Invalid escape sequence in quoted local part"),
+                },
+                '"' => bail!("Unescaped quote in quoted local part"),
+                c if c == ' ' || c.is_ascii_graphic() || !c.is_ascii() => (),
+                c => bail!("Invalid character in quoted local part: {:?}", c),
+            }
+        }
+    } else {
+        for atom in local.split('.') {
+            if atom.is_empty() {
+                bail!("Local part has a leading, trailing or repeated dot");
+            }
+            if let Some(c) = atom.chars().find(|c| !is_atext(*c)) {
+                bail!("Invalid character in local part: {:?}", c);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn normalize_domain(domain: &str) -> Result<String> {
+    if domain.starts_with('[') {
+        if !domain.ends_with(']') {
+            bail!("Address literal isn't terminated");
+        }
+        let literal = &domain[1..domain.len()-1];
+        let valid = if literal.len() > 5 && literal[..5].eq_ignore_ascii_case("ipv6:") {
+            literal[5..].parse::<net::Ipv6Addr>().is_ok()
+        } else {
+            literal.parse::<net::Ipv4Addr>().is_ok()
+        };
+        if !valid {
+            bail!("Invalid address literal: {:?}", domain);
+        }
+        return Ok(domain.to_lowercase());
+    }
+
+    let domain = domain.to_lowercase();
+    let labels = domain.split('.').collect::<Vec<_>>();
+    if labels.len() < 2 {
+        bail!("Domain needs at least two labels");
+    }
+
+    for label in labels {
+        if label.is_empty() {
+            bail!("Domain has an empty label");
+        }
+        if label.len() > MAX_LABEL_LEN {
+            bail!("Domain label is longer than {} bytes", MAX_LABEL_LEN);
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            bail!("Domain label can't start or end with a hyphen");
+        }
+        if let Some(c) = label.chars().find(|c| !(c.is_alphanumeric() || *c == '-')) {
+            bail!("Invalid character in domain: {:?}", c);
+        }
+    }
+
+    Ok(domain)
+}
+
+/// Parse and validate an address, the domain is lowercased. This accepts
+/// what's used in practice, comments and folding whitespace are rejected.
+pub fn parse(addr: &str) -> Result<EmailAddress> {
+    let addr = addr.trim();
+    if addr.len() > MAX_ADDRESS_LEN {
+        bail!("Address is longer than {} bytes", MAX_ADDRESS_LEN);
+    }
+
+    // quoted local parts may contain an @, the domain never does
+    let idx = match addr.rfind('@') {
+        Some(idx) => idx,
+        None => bail!("Address has no @"),
+    };
+    let (local, domain) = (&addr[..idx], &addr[idx+1..]);
+
+    validate_local(local)
+        .context("Invalid local part")?;
+    let domain = normalize_domain(domain)
+        .context("Invalid domain")?;
+
+    let (user, tag) = match local.find('+') {
+        Some(idx) if !local.starts_with('"') && idx > 0 => {
+            (local[..idx].to_string(), Some(local[idx+1..].to_string()))
+        },
+        _ => (local.to_string(), None),
+    };
+
+    Ok(EmailAddress {
+        local: local.to_string(),
+        domain,
+        user,
+        tag,
+    })
+}
+
+/// Validate an address and bring it into the form that's stored in the database
+pub fn normalize(addr: &str) -> Result<String> {
+    parse(addr).map(|addr| addr.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_simple() {
+        let addr = parse("john.doe@example.com").unwrap();
+        assert_eq!(addr, EmailAddress {
+            local: "john.doe".into(),
+            domain: "example.com".into(),
+            user: "john.doe".into(),
+            tag: None,
+        });
+    }
+
+    #[test]
+    fn test_normalize_domain_case() {
+        assert_eq!(normalize(" John.Doe@Mail.EXAMPLE.com\n").unwrap(), "John.Doe@mail.example.com");
+    }
+
+    #[test]
+    fn test_plus_addressing() {
+        let addr = parse("john+newsletter@example.com").unwrap();
+        assert_eq!(addr.user, "john");
+        assert_eq!(addr.tag, Some("newsletter".into()));
+        assert_eq!(addr.to_string(), "john+newsletter@example.com");
+
+        let addr = parse("john+@example.com").unwrap();
+        assert_eq!(addr.tag, Some("".into()));
+
+        // a leading + isn't a tag
+        let addr = parse("+1234@example.com").unwrap();
+        assert_eq!(addr.user, "+1234");
+        assert_eq!(addr.tag, None);
+    }
+
+    #[test]
+    fn test_quoted_local() {
+        let addr = parse(r#""john doe"@example.com"#).unwrap();
+        assert_eq!(addr.local, r#""john doe""#);
+        assert_eq!(addr.tag, None);
+
+        let addr = parse(r#""john@home+x"@example.com"#).unwrap();
+        assert_eq!(addr.local, r#""john@home+x""#);
+        assert_eq!(addr.domain, "example.com");
+        assert_eq!(addr.tag, None);
+
+        assert!(parse(r#""escaped \" quote"@example.com"#).is_ok());
+        assert!(parse(r#""unescaped " quote"@example.com"#).is_err());
+        assert!(parse(r#""unterminated@example.com"#).is_err());
+    }
+
+    #[test]
+    fn test_address_literal() {
+        assert_eq!(parse("root@[192.0.2.1]").unwrap().domain, "[192.0.2.1]");
+        assert_eq!(parse("root@[IPv6:2001:DB8::1]").unwrap().domain, "[ipv6:2001:db8::1]");
+        assert!(parse("root@[192.0.2.256]").is_err());
+        assert!(parse("root@[192.0.2.1").is_err());
+    }
+
+    #[test]
+    fn test_international() {
+        let addr = parse("jöhn@EXÄMPLE.com").unwrap();
+        assert_eq!(addr.to_string(), "jöhn@exämple.com");
+    }
+
+    #[test]
+    fn test_invalid() {
+        for addr in &[
+            "",
+            "example.com",
+            "@example.com",
+            "john@",
+            "john@localhost",
+            "john@example..com",
+            "john@-example.com",
+            "john@example.com.",
+            "john@exa_mple.com",
+            ".john@example.com",
+            "john.@example.com",
+            "jo..hn@example.com",
+            "john doe@example.com",
+            "john(comment)@example.com",
+            "john@example.com@example.com",
+            "<john@example.com>",
+        ] {
+            assert!(parse(addr).is_err(), "{:?} should be invalid", addr);
+        }
+    }
+
+    #[test]
+    fn test_length_limits() {
+        let local = "a".repeat(64);
+        assert!(parse(&format!("{}@example.com", local)).is_ok());
+        assert!(parse(&format!("a{}@example.com", local)).is_err());
+
+        let label = "a".repeat(64);
+        assert!(parse(&format!("john@{}.com", label)).is_err());
+
+        let domain = format!("{}.com", vec!["a".repeat(60); 5].join("."));
+        assert!(parse(&format!("john@{}", domain)).is_err());
+    }
+}
@@ -1,22 +1,33 @@
 use crate::errors::*;
 
-use crate::db::Family;
+use crate::db::{Family, InsertOptions};
+use crate::db::findings::NewFinding;
+use crate::connector::{self, HttpPool, PoolOptions, TlsConfig, WebClient};
 use crate::engine::{Environment, Reporter};
+use crate::frontier::Frontier;
 use crate::geoip::{GeoIP, AsnDB};
-use crate::hlua::{self, AnyLuaValue};
+use crate::hlua::{self, ffi, AnyLuaValue, AsMutLua, LuaRead, Push, PushGuard};
+use crate::json::LuaJsonValue;
 use crate::keyring::KeyRingEntry;
 use crate::models::{Insert, Update};
+use crate::proxy::ProxyChain;
+use crate::permute::Permutations;
 use crate::psl::Psl;
 use crate::runtime;
-use crate::sockets::Socket;
+use crate::smtp::SmtpClient;
+use crate::sockets::{Socket, SocketOptions};
 use crate::web::{HttpSession, HttpRequest, RequestOptions};
-use crate::worker::{Event, LogEvent, DatabaseEvent, StdioEvent};
+use crate::filesystem::Entry;
+use crate::worker::{Event, LogEvent, DatabaseEvent, InsertBatchEvent, QueryEvent, StateEvent, CounterEvent, StdioEvent, FsEvent, ModuleError, ModuleErrorKind};
 use chrootable_https::{self, Resolver};
+use sn0int_common::metadata::Capability;
 use serde_json;
 use std::collections::HashMap;
 use std::result;
-use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+use std::fmt;
+use std::sync::{Arc, Mutex, TryLockError};
 use rand::prelude::*;
 use rand::distributions::Alphanumeric;
 
@@ -53,6 +64,7 @@ pub trait State {
     }
 
     fn db_insert(&self, object: Insert) -> Result<Option<i32>> {
+        self.require_produces(object.family())?;
         self.send(&Event::Database(DatabaseEvent::Insert(object)));
         let reply = self.recv()?;
         let reply: result::Result<Option<i32>, String> = serde_json::from_value(reply)?;
@@ -61,6 +73,7 @@ pub trait State {
     }
 
     fn db_insert_ttl(&self, object: Insert, ttl: i32) -> Result<Option<i32>> {
+        self.require_produces(object.family())?;
         self.send(&Event::Database(DatabaseEvent::InsertTtl((object, ttl))));
         let reply = self.recv()?;
         let reply: result::Result<Option<i32>, String> = serde_json::from_value(reply)?;
@@ -68,6 +81,29 @@ pub trait State {
         reply.map_err(|err| format_err!("Failed to add to database: {:?}", err))
     }
 
+    fn db_insert_opts(&self, object: Insert, options: InsertOptions) -> Result<Option<i32>> {
+        self.require_produces(object.family())?;
+        self.send(&Event::Database(DatabaseEvent::InsertOpts((object, options))));
+        let reply = self.recv()?;
+        let reply: result::Result<Option<i32>, String> = serde_json::from_value(reply)?;
+
+        reply.map_err(|err| format_err!("Failed to add to database: {:?}", err))
+    }
+
+    fn db_insert_many(&self, objects: Vec<Insert>, options: InsertOptions) -> Result<Vec<result::Result<Option<i32>, String>>> {
+        for object in &objects {
+            self.require_produces(object.family())?;
+        }
+        self.send(&Event::InsertBatch(InsertBatchEvent {
+            objects,
+            options,
+        }));
+        let reply = self.recv()?;
+        let reply: result::Result<Vec<result::Result<Option<i32>, String>>, String> = serde_json::from_value(reply)?;
+
+        reply.map_err(|err| format_err!("Failed to add to database: {:?}", err))
+    }
+
     fn db_select(&self, family: Family, value: String) -> Result<Option<i32>> {
         self.send(&Event::Database(DatabaseEvent::Select((family, value))));
         let reply = self.recv()?;
@@ -76,6 +112,14 @@ pub trait State {
         reply.map_err(|err| format_err!("Failed to query database: {:?}", err))
     }
 
+    fn db_query(&self, query: QueryEvent) -> Result<Vec<serde_json::Value>> {
+        self.send(&Event::Query(query));
+        let reply = self.recv()?;
+        let reply: result::Result<Vec<serde_json::Value>, String> = serde_json::from_value(reply)?;
+
+        reply.map_err(|err| format_err!("Failed to query database: {:?}", err))
+    }
+
     fn db_update(&self, object: String, update: Update) -> Result<Option<i32>> {
         self.send(&Event::Database(DatabaseEvent::Update((object, update))));
         let reply = self.recv()?;
@@ -84,6 +128,54 @@ pub trait State {
         reply.map_err(|err| format_err!("Failed to update database: {:?}", err))
     }
 
+    fn db_delete(&self, family: Family, id: i32) -> Result<bool> {
+        self.send(&Event::Database(DatabaseEvent::Delete((family, id))));
+        let reply = self.recv()?;
+        let reply: result::Result<Option<i32>, String> = serde_json::from_value(reply)?;
+
+        reply.map(|id| id.is_some())
+            .map_err(|err| format_err!("Failed to delete from database: {:?}", err))
+    }
+
+    fn db_report(&self, finding: NewFinding) -> Result<Option<i32>> {
+        self.send(&Event::Database(DatabaseEvent::Report(finding)));
+        let reply = self.recv()?;
+        let reply: result::Result<Option<i32>, String> = serde_json::from_value(reply)?;
+
+        reply.map_err(|err| format_err!("Failed to report finding: {:?}", err))
+    }
+
+    fn state_get(&self, key: String) -> Result<Option<serde_json::Value>> {
+        self.send(&Event::State(StateEvent::Get(key)));
+        let reply = self.recv()?;
+        let reply: result::Result<Option<serde_json::Value>, String> = serde_json::from_value(reply)?;
+
+        reply.map_err(|err| format_err!("Failed to read state: {:?}", err))
+    }
+
+    fn state_set(&self, key: String, value: Option<serde_json::Value>) -> Result<()> {
+        self.send(&Event::State(StateEvent::Set((key, value))));
+        let reply = self.recv()?;
+        let reply: result::Result<Option<serde_json::Value>, String> = serde_json::from_value(reply)?;
+
+        reply.map(|_| ())
+            .map_err(|err| format_err!("Failed to write state: {:?}", err))
+    }
+
+    fn counter_inc(&self, name: String, by: i32) -> Result<i32> {
+        self.send(&Event::Counter(CounterEvent::Inc((name, by))));
+        let reply = self.recv()?;
+        let reply: result::Result<i32, String> = serde_json::from_value(reply)?;
+        reply.map_err(|err| format_err!("Failed to update counter: {}", err))
+    }
+
+    fn counter_get(&self, name: String) -> Result<i32> {
+        self.send(&Event::Counter(CounterEvent::Get(name)));
+        let reply = self.recv()?;
+        let reply: result::Result<i32, String> = serde_json::from_value(reply)?;
+        reply.map_err(|err| format_err!("Failed to read counter: {}", err))
+    }
+
     fn stdin_readline(&self) -> Result<Option<String>> {
         self.send(&Event::Stdio(StdioEvent {}));
         let reply = self.recv()?;
@@ -91,15 +183,69 @@ pub trait State {
         reply.map_err(|err| format_err!("Failed to read stdin: {:?}", err))
     }
 
+    fn fs_read(&self, path: String) -> Result<String> {
+        self.send(&Event::Fs(FsEvent::Read(path)));
+        let reply = self.recv()?;
+        let reply: result::Result<String, String> = serde_json::from_value(reply)?;
+        reply.map_err(|err| format_err!("Failed to read file: {}", err))
+    }
+
+    fn fs_list(&self, path: String) -> Result<Vec<Entry>> {
+        self.send(&Event::Fs(FsEvent::List(path)));
+        let reply = self.recv()?;
+        let reply: result::Result<Vec<Entry>, String> = serde_json::from_value(reply)?;
+        reply.map_err(|err| format_err!("Failed to list directory: {}", err))
+    }
+
+    fn fs_lines(&self, path: String) -> Result<String> {
+        self.send(&Event::Fs(FsEvent::Lines(path)));
+        let reply = self.recv()?;
+        let reply: result::Result<String, String> = serde_json::from_value(reply)?;
+        reply.map_err(|err| format_err!("Failed to open file: {}", err))
+    }
+
+    fn fs_readline(&self, id: String) -> Result<Option<String>> {
+        self.send(&Event::Fs(FsEvent::ReadLine(id)));
+        let reply = self.recv()?;
+        let reply: result::Result<Option<String>, String> = serde_json::from_value(reply)?;
+        reply.map_err(|err| format_err!("Failed to read line: {}", err))
+    }
+
     fn random_id(&self) -> String {
         thread_rng().sample_iter(&Alphanumeric).take(16).collect()
     }
 
+    fn capabilities(&self) -> Option<&[Capability]>;
+
+    /// Fail unless the module declared this capability, legacy modules are allowed everything
+    fn require(&self, capability: Capability) -> Result<()> {
+        match self.capabilities() {
+            Some(capabilities) if !capabilities.contains(&capability) => {
+                bail!("Module needs to declare the {:?} capability", capability.as_str())
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn produces(&self) -> Option<&[Family]>;
+
+    /// Fail unless the module declared that it adds this entity type, legacy modules may add everything
+    fn require_produces(&self, family: Family) -> Result<()> {
+        match self.produces() {
+            Some(produces) if !produces.contains(&family) => {
+                bail!("Module needs to declare that it produces {:?} entities", family.as_str())
+            },
+            _ => Ok(()),
+        }
+    }
+
     fn keyring(&self, namespace: &str) -> Vec<&KeyRingEntry>;
 
     fn dns_config(&self) -> &Resolver;
 
-    fn proxy(&self) -> Option<&SocketAddr>;
+    fn proxy(&self) -> Option<&ProxyChain>;
+
+    fn tls(&self) -> &TlsConfig;
 
     fn getopt(&self, key: &str) -> Option<&String>;
 
@@ -109,17 +255,89 @@ pub trait State {
 
     fn asn(&self) -> &AsnDB;
 
-    fn sock_connect(&self, host: &str, port: u16) -> Result<String>;
+    fn asn_prefixes(&self) -> &Mutex<HashMap<u32, Vec<String>>>;
+
+    fn sock_open(&self, host: &str, port: u16, options: &SocketOptions) -> Result<Socket>;
+
+    fn sock_connect(&self, host: &str, port: u16, options: &SocketOptions) -> Result<String>;
 
     fn get_sock(&self, id: &str)-> Arc<Mutex<Socket>>;
 
-    fn http(&self) -> &chrootable_https::Client<Resolver>;
+    fn smtp_register(&self, client: SmtpClient) -> String;
+
+    fn get_smtp(&self, id: &str) -> Result<Arc<Mutex<SmtpClient>>>;
+
+    fn cursor_register(&self, cursor: runtime::DbCursor) -> String;
+
+    fn get_cursor(&self, id: &str) -> Result<Arc<Mutex<runtime::DbCursor>>>;
+
+    fn crawler_register(&self, crawler: runtime::Crawler) -> String;
+
+    fn get_crawler(&self, id: &str) -> Result<Arc<Mutex<runtime::Crawler>>>;
+
+    fn permutations_register(&self, permutations: Permutations) -> String;
+
+    fn get_permutations(&self, id: &str) -> Result<Arc<Mutex<Permutations>>>;
+
+    fn frontier(&self) -> &Mutex<Frontier>;
+
+    fn http(&self) -> &WebClient;
 
     fn http_mksession(&self) -> String;
 
+    fn http_set_pool(&self, session_id: &str, options: &PoolOptions) -> Result<()>;
+
+    fn http_pool(&self, session_id: &str) -> Option<Arc<HttpPool>>;
+
     fn http_request(&self, session_id: &str, method: String, url: String, options: RequestOptions) -> HttpRequest;
 
     fn register_in_jar(&self, session: &str, key: String, value: String);
+
+    fn run_insert_hook(&self, family: Family, object: LuaJsonValue) -> Result<Option<LuaJsonValue>>;
+}
+
+/// A separate lua environment for the insert hook of a workspace, it has the
+/// same functions and permissions as the module that is running
+pub struct InsertHook {
+    lua: hlua::Lua<'static>,
+}
+
+impl fmt::Debug for InsertHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("InsertHook").finish()
+    }
+}
+
+impl InsertHook {
+    fn load(code: &str, state: &Arc<LuaState>) -> Result<InsertHook> {
+        let mut lua = lua_with_functions(state);
+
+        lua.execute::<()>(code)
+            .map_err(|err| ModuleError::new(ModuleErrorKind::Load, format!("Failed to load insert hook: {}", err)))?;
+
+        {
+            let insert: Option<hlua::LuaFunction<_>> = lua.get("insert");
+            if insert.is_none() {
+                return Err(ModuleError::new(ModuleErrorKind::Load, "insert hook doesn't define insert".to_string()).into());
+            }
+        }
+
+        Ok(InsertHook {
+            lua,
+        })
+    }
+
+    /// Returns the entity that should be added, or None if it should be dropped
+    fn call(&mut self, family: Family, object: LuaJsonValue) -> Result<Option<LuaJsonValue>> {
+        let args = vec![AnyLuaValue::LuaString(family.as_str().to_string()), object.into()];
+        let ret = call_function(&mut self.lua, b"insert\0", args)
+            .map_err(|err| format_err!("Insert hook failed: {}", err))?;
+
+        match ret {
+            AnyLuaValue::LuaNil | AnyLuaValue::LuaBoolean(false) => Ok(None),
+            ret => Ok(Some(LuaJsonValue::from(ret))),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -127,16 +345,26 @@ pub struct LuaState {
     error: Mutex<Option<Error>>,
     logger: Arc<Mutex<Box<Reporter>>>,
     socket_sessions: Mutex<HashMap<String, Arc<Mutex<Socket>>>>,
+    smtp_sessions: Mutex<HashMap<String, Arc<Mutex<SmtpClient>>>>,
+    db_cursors: Mutex<HashMap<String, Arc<Mutex<runtime::DbCursor>>>>,
+    crawlers: Mutex<HashMap<String, Arc<Mutex<runtime::Crawler>>>>,
+    permutations: Mutex<HashMap<String, Arc<Mutex<Permutations>>>>,
     http_sessions: Mutex<HashMap<String, HttpSession>>,
-    http: chrootable_https::Client<Resolver>,
+    http: WebClient,
+    frontier: Mutex<Frontier>,
     verbose: u64,
     keyring: Vec<KeyRingEntry>, // TODO: maybe hashmap
     dns_config: Resolver,
     psl: Psl,
     geoip: GeoIP,
     asn: AsnDB,
-    proxy: Option<SocketAddr>,
+    asn_prefixes: Mutex<HashMap<u32, Vec<String>>>,
+    proxy: Option<ProxyChain>,
+    tls: TlsConfig,
     options: HashMap<String, String>,
+    capabilities: Option<Vec<Capability>>,
+    produces: Option<Vec<Family>>,
+    insert_hook: Mutex<Option<InsertHook>>,
 }
 
 impl State for LuaState {
@@ -171,6 +399,14 @@ impl State for LuaState {
         self.verbose
     }
 
+    fn capabilities(&self) -> Option<&[Capability]> {
+        self.capabilities.as_ref().map(|x| &x[..])
+    }
+
+    fn produces(&self) -> Option<&[Family]> {
+        self.produces.as_ref().map(|x| &x[..])
+    }
+
     fn keyring(&self, namespace: &str) -> Vec<&KeyRingEntry> {
         self.keyring.iter()
             .filter(|x| x.namespace == namespace)
@@ -181,10 +417,14 @@ impl State for LuaState {
         &self.dns_config
     }
 
-    fn proxy(&self) -> Option<&SocketAddr> {
+    fn proxy(&self) -> Option<&ProxyChain> {
         self.proxy.as_ref()
     }
 
+    fn tls(&self) -> &TlsConfig {
+        &self.tls
+    }
+
     fn getopt(&self, key: &str) -> Option<&String> {
         self.options.get(key)
     }
@@ -201,14 +441,19 @@ impl State for LuaState {
         &self.asn
     }
 
-    fn sock_connect(&self, host: &str, port: u16) -> Result<String> {
+    fn asn_prefixes(&self) -> &Mutex<HashMap<u32, Vec<String>>> {
+        &self.asn_prefixes
+    }
+
+    fn sock_open(&self, host: &str, port: u16, options: &SocketOptions) -> Result<Socket> {
+        Socket::open(self.proxy.as_ref(), &self.dns_config, host, port, options)
+    }
+
+    fn sock_connect(&self, host: &str, port: u16, options: &SocketOptions) -> Result<String> {
         let mut mtx = self.socket_sessions.lock().unwrap();
         let id = self.random_id();
 
-        let sock = match &self.proxy {
-            Some(proxy) => Socket::connect_socks5(proxy, host, port)?,
-            _ => Socket::connect(&self.dns_config, host, port)?,
-        };
+        let sock = self.sock_open(host, port, options)?;
 
         mtx.insert(id.clone(), Arc::new(Mutex::new(sock)));
 
@@ -221,7 +466,67 @@ impl State for LuaState {
         sock.clone()
     }
 
-    fn http(&self) -> &chrootable_https::Client<Resolver> {
+    fn smtp_register(&self, client: SmtpClient) -> String {
+        let mut mtx = self.smtp_sessions.lock().unwrap();
+        let id = self.random_id();
+        mtx.insert(id.clone(), Arc::new(Mutex::new(client)));
+        id
+    }
+
+    fn get_smtp(&self, id: &str) -> Result<Arc<Mutex<SmtpClient>>> {
+        let mtx = self.smtp_sessions.lock().unwrap();
+        mtx.get(id)
+            .cloned()
+            .ok_or_else(|| format_err!("Invalid smtp session: {:?}", id))
+    }
+
+    fn cursor_register(&self, cursor: runtime::DbCursor) -> String {
+        let mut mtx = self.db_cursors.lock().unwrap();
+        let id = self.random_id();
+        mtx.insert(id.clone(), Arc::new(Mutex::new(cursor)));
+        id
+    }
+
+    fn get_cursor(&self, id: &str) -> Result<Arc<Mutex<runtime::DbCursor>>> {
+        let mtx = self.db_cursors.lock().unwrap();
+        mtx.get(id)
+            .cloned()
+            .ok_or_else(|| format_err!("Invalid db cursor: {:?}", id))
+    }
+
+    fn crawler_register(&self, crawler: runtime::Crawler) -> String {
+        let mut mtx = self.crawlers.lock().unwrap();
+        let id = self.random_id();
+        mtx.insert(id.clone(), Arc::new(Mutex::new(crawler)));
+        id
+    }
+
+    fn get_crawler(&self, id: &str) -> Result<Arc<Mutex<runtime::Crawler>>> {
+        let mtx = self.crawlers.lock().unwrap();
+        mtx.get(id)
+            .cloned()
+            .ok_or_else(|| format_err!("Invalid crawler: {:?}", id))
+    }
+
+    fn permutations_register(&self, permutations: Permutations) -> String {
+        let mut mtx = self.permutations.lock().unwrap();
+        let id = self.random_id();
+        mtx.insert(id.clone(), Arc::new(Mutex::new(permutations)));
+        id
+    }
+
+    fn get_permutations(&self, id: &str) -> Result<Arc<Mutex<Permutations>>> {
+        let mtx = self.permutations.lock().unwrap();
+        mtx.get(id)
+            .cloned()
+            .ok_or_else(|| format_err!("Invalid permute cursor: {:?}", id))
+    }
+
+    fn frontier(&self) -> &Mutex<Frontier> {
+        &self.frontier
+    }
+
+    fn http(&self) -> &WebClient {
         &self.http
     }
 
@@ -232,6 +537,23 @@ impl State for LuaState {
         id
     }
 
+    fn http_set_pool(&self, session_id: &str, options: &PoolOptions) -> Result<()> {
+        let tls = self.tls.with_profile(options.tls_profile);
+        let pool = HttpPool::new(self.dns_config.clone(), self.proxy.clone(), &tls, options)?;
+
+        let mut mtx = self.http_sessions.lock().unwrap();
+        let session = mtx.get_mut(session_id)
+            .ok_or_else(|| format_err!("Invalid session reference"))?;
+        session.pool = Some(Arc::new(pool));
+        Ok(())
+    }
+
+    fn http_pool(&self, session_id: &str) -> Option<Arc<HttpPool>> {
+        let mtx = self.http_sessions.lock().unwrap();
+        mtx.get(session_id)
+            .and_then(|session| session.pool.clone())
+    }
+
     fn http_request(&self, session_id: &str, method: String, url: String, options: RequestOptions) -> HttpRequest {
         let mtx = self.http_sessions.lock().unwrap();
         let session = mtx.get(session_id).expect("invalid session reference"); // TODO
@@ -245,6 +567,73 @@ impl State for LuaState {
             session.cookies.register_in_jar(key, value);
         }
     }
+
+    fn run_insert_hook(&self, family: Family, object: LuaJsonValue) -> Result<Option<LuaJsonValue>> {
+        let mut hook = match self.insert_hook.try_lock() {
+            Ok(hook) => hook,
+            // entities that are added by the hook itself don't run the hook again
+            Err(TryLockError::WouldBlock) => return Ok(Some(object)),
+            Err(TryLockError::Poisoned(_)) => bail!("Insert hook has crashed"),
+        };
+
+        match hook.as_mut() {
+            Some(hook) => hook.call(family, object),
+            None => Ok(Some(object)),
+        }
+    }
+}
+
+// not exposed by lua52-sys, lua_State is passed as void since it's opaque
+extern "C" {
+    fn luaL_traceback(lua: *mut c_void, lua1: *mut c_void, msg: *const c_char, level: c_int);
+}
+
+/// Message handler for lua_pcall that appends a traceback to the error
+extern "C" fn traceback_handler(lua: *mut ffi::lua_State) -> c_int {
+    unsafe {
+        let msg = ffi::lua_tolstring(lua, 1, ptr::null_mut());
+        luaL_traceback(lua as *mut c_void, lua as *mut c_void, msg, 1);
+    }
+    1
+}
+
+/// Call a global function like `LuaFunction::call_with_args` does, but with
+/// a message handler so the traceback is still available on errors. The name
+/// needs to be nul terminated.
+fn call_function(lua: &mut hlua::Lua, name: &[u8], args: Vec<AnyLuaValue>) -> result::Result<AnyLuaValue, String> {
+    unsafe {
+        let raw = lua.as_mut_lua().state_ptr();
+        ffi::lua_pushcfunction(raw, traceback_handler);
+        ffi::lua_getglobal(raw, name.as_ptr() as *const c_char);
+        let mut pushed = 0;
+        for arg in args {
+            pushed += match arg.push_to_lua(&mut *lua) {
+                Ok(guard) => guard.forget(),
+                Err(_) => unreachable!("Pushing a lua value can't fail"),
+            };
+        }
+        let ret = ffi::lua_pcall(raw, pushed, 1, -(pushed + 2));
+
+        // pops the return value and the message handler
+        let mut guard = PushGuard::new(&mut *lua, 2);
+        if ret == 0 {
+            Ok(AnyLuaValue::lua_read(&mut guard).unwrap_or(AnyLuaValue::LuaNil))
+        } else {
+            let err: Option<String> = LuaRead::lua_read(&mut guard).ok();
+            Err(err.unwrap_or_else(|| format!("lua_pcall failed with {}", ret)))
+        }
+    }
+}
+
+fn split_traceback(err: &str) -> (String, Option<String>) {
+    if err.starts_with("stack traceback:\n") {
+        return (String::new(), Some(err.to_string()));
+    }
+
+    match err.find("\nstack traceback:\n") {
+        Some(idx) => (err[..idx].to_string(), Some(err[idx+1..].to_string())),
+        None => (err.to_string(), None),
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -252,25 +641,21 @@ pub struct Script {
     code: String,
 }
 
-fn ctx<'a>(env: Environment, logger: Arc<Mutex<Box<Reporter>>>) -> (hlua::Lua<'a>, Arc<LuaState>) {
+fn ctx<'a>(env: Environment, logger: Arc<Mutex<Box<Reporter>>>) -> Result<(hlua::Lua<'a>, Arc<LuaState>)> {
     debug!("Creating lua context");
-    let mut lua = hlua::Lua::new();
-    lua.open_string();
-
-    let http = match env.proxy {
-        Some(proxy) => chrootable_https::Client::with_socks5(proxy),
-        _ => {
-            let resolver = env.dns_config.clone();
-            chrootable_https::Client::new(resolver)
-        },
-    };
+    let http = connector::client(env.dns_config.clone(), env.proxy.clone(), &env.tls)?;
 
     let state = Arc::new(LuaState {
         error: Mutex::new(None),
         logger,
         socket_sessions: Mutex::new(HashMap::new()),
+        smtp_sessions: Mutex::new(HashMap::new()),
+        db_cursors: Mutex::new(HashMap::new()),
+        crawlers: Mutex::new(HashMap::new()),
+        permutations: Mutex::new(HashMap::new()),
         http_sessions: Mutex::new(HashMap::new()),
         http,
+        frontier: Mutex::new(Frontier::default()),
 
         verbose: env.verbose,
         keyring: env.keyring,
@@ -278,42 +663,124 @@ fn ctx<'a>(env: Environment, logger: Arc<Mutex<Box<Reporter>>>) -> (hlua::Lua<'a
         psl: env.psl,
         geoip: env.geoip,
         asn: env.asn,
+        asn_prefixes: Mutex::new(HashMap::new()),
         proxy: env.proxy,
+        tls: env.tls,
         options: env.options,
+        capabilities: env.capabilities,
+        produces: env.produces,
+        insert_hook: Mutex::new(None),
     });
 
+    let lua = lua_with_functions(&state);
+
+    if let Some(code) = &env.insert_hook {
+        debug!("Loading insert hook");
+        let hook = InsertHook::load(code, &state)?;
+        *state.insert_hook.lock().unwrap() = Some(hook);
+    }
+
+    debug!("Created lua context");
+
+    Ok((lua, state))
+}
+
+/// Create a lua environment with all runtime functions
+fn lua_with_functions<'a>(state: &Arc<LuaState>) -> hlua::Lua<'a> {
+    let mut lua = hlua::Lua::new();
+    lua.open_string();
+
+    runtime::bytes_to_hex(&mut lua, state.clone());
     runtime::clear_err(&mut lua, state.clone());
+    runtime::cookie_parse(&mut lua, state.clone());
+    runtime::cookie_serialize(&mut lua, state.clone());
+    runtime::counter_get(&mut lua, state.clone());
+    runtime::counter_inc(&mut lua, state.clone());
+    runtime::crawl(&mut lua, state.clone());
+    runtime::crawl_next(&mut lua, state.clone());
     runtime::datetime(&mut lua, state.clone());
     runtime::db_add(&mut lua, state.clone());
+    runtime::db_add_many(&mut lua, state.clone());
+    runtime::db_add_opts(&mut lua, state.clone());
+    runtime::db_cursor(&mut lua, state.clone());
+    runtime::db_cursor_next(&mut lua, state.clone());
     runtime::db_add_ttl(&mut lua, state.clone());
+    runtime::db_delete(&mut lua, state.clone());
+    runtime::db_link(&mut lua, state.clone());
     runtime::db_select(&mut lua, state.clone());
     runtime::db_update(&mut lua, state.clone());
+    runtime::report(&mut lua, state.clone());
     runtime::debug(&mut lua, state.clone());
     runtime::dns(&mut lua, state.clone());
+    runtime::dns_axfr(&mut lua, state.clone());
+    runtime::dns_reverse_batch(&mut lua, state.clone());
+    runtime::email_parse(&mut lua, state.clone());
+    runtime::enrich_ips(&mut lua, state.clone());
     runtime::error(&mut lua, state.clone());
     runtime::asn_lookup(&mut lua, state.clone());
+    runtime::asn_prefixes(&mut lua, state.clone());
+    runtime::form_decode(&mut lua, state.clone());
+    runtime::frontier_pop(&mut lua, state.clone());
+    runtime::frontier_push(&mut lua, state.clone());
+    runtime::fs_lines(&mut lua, state.clone());
+    runtime::fs_list(&mut lua, state.clone());
+    runtime::fs_read(&mut lua, state.clone());
+    runtime::fs_readline(&mut lua, state.clone());
     runtime::geoip_lookup(&mut lua, state.clone());
     runtime::getopt(&mut lua, state.clone());
+    runtime::grab_banner(&mut lua, state.clone());
+    runtime::gunzip(&mut lua, state.clone());
     runtime::hex(&mut lua, state.clone());
+    runtime::hex_to_bytes(&mut lua, state.clone());
+    runtime::hexdump(&mut lua, state.clone());
     runtime::html_select(&mut lua, state.clone());
     runtime::html_select_list(&mut lua, state.clone());
+    runtime::html_text(&mut lua, state.clone());
+    runtime::http_head(&mut lua, state.clone());
     runtime::http_mksession(&mut lua, state.clone());
+    runtime::http_range(&mut lua, state.clone());
     runtime::http_request(&mut lua, state.clone());
     runtime::http_send(&mut lua, state.clone());
+    runtime::http_session_pool(&mut lua, state.clone());
     runtime::info(&mut lua, state.clone());
+    runtime::ip_is_bogon(&mut lua, state.clone());
+    runtime::ip_is_private(&mut lua, state.clone());
+    runtime::json_canonical(&mut lua, state.clone());
     runtime::json_decode(&mut lua, state.clone());
     runtime::json_decode_stream(&mut lua, state.clone());
     runtime::json_encode(&mut lua, state.clone());
+    runtime::json_merge(&mut lua, state.clone());
+    runtime::json_patch(&mut lua, state.clone());
+    runtime::json_transform(&mut lua, state.clone());
+    runtime::jwt_decode(&mut lua, state.clone());
+    runtime::jwt_verify(&mut lua, state.clone());
     runtime::keyring(&mut lua, state.clone());
     runtime::last_err(&mut lua, state.clone());
+    runtime::lines(&mut lua, state.clone());
+    runtime::link_header_parse(&mut lua, state.clone());
+    runtime::mime_from_ext(&mut lua, state.clone());
+    runtime::mime_sniff(&mut lua, state.clone());
+    runtime::multipart_parse(&mut lua, state.clone());
+    runtime::permute(&mut lua, state.clone());
+    runtime::permute_cursor(&mut lua, state.clone());
+    runtime::permute_next(&mut lua, state.clone());
     runtime::pgp_pubkey(&mut lua, state.clone());
     runtime::pgp_pubkey_armored(&mut lua, state.clone());
     runtime::print(&mut lua, state.clone());
+    runtime::protobuf_decode(&mut lua, state.clone());
     runtime::psl_domain_from_dns_name(&mut lua, state.clone());
+    runtime::query_decode(&mut lua, state.clone());
     runtime::regex_find(&mut lua, state.clone());
     runtime::regex_find_all(&mut lua, state.clone());
+    runtime::robots_parse(&mut lua, state.clone());
+    runtime::scan(&mut lua, state.clone());
+    runtime::scan_ports(&mut lua, state.clone());
     runtime::semver_match(&mut lua, state.clone());
+    runtime::sitemap_parse(&mut lua, state.clone());
     runtime::sleep(&mut lua, state.clone());
+    runtime::smtp_cmd(&mut lua, state.clone());
+    runtime::smtp_connect(&mut lua, state.clone());
+    runtime::snapshot(&mut lua, state.clone());
     runtime::sn0int_version(&mut lua, state.clone());
     runtime::sock_connect(&mut lua, state.clone());
     runtime::sock_send(&mut lua, state.clone());
@@ -327,8 +794,16 @@ fn ctx<'a>(env: Environment, logger: Arc<Mutex<Box<Reporter>>>) -> (hlua::Lua<'a
     runtime::sock_recvuntil(&mut lua, state.clone());
     runtime::sock_sendafter(&mut lua, state.clone());
     runtime::sock_newline(&mut lua, state.clone());
+    runtime::split(&mut lua, state.clone());
+    runtime::state_get(&mut lua, state.clone());
+    runtime::state_set(&mut lua, state.clone());
     runtime::status(&mut lua, state.clone());
     runtime::stdin_readline(&mut lua, state.clone());
+    runtime::table_diff(&mut lua, state.clone());
+    runtime::text_diff(&mut lua, state.clone());
+    runtime::text_patch(&mut lua, state.clone());
+    runtime::totp(&mut lua, state.clone());
+    runtime::url_canonical(&mut lua, state.clone());
     runtime::url_decode(&mut lua, state.clone());
     runtime::url_encode(&mut lua, state.clone());
     runtime::url_escape(&mut lua, state.clone());
@@ -336,11 +811,13 @@ fn ctx<'a>(env: Environment, logger: Arc<Mutex<Box<Reporter>>>) -> (hlua::Lua<'a
     runtime::url_parse(&mut lua, state.clone());
     runtime::url_unescape(&mut lua, state.clone());
     runtime::utf8_decode(&mut lua, state.clone());
+    runtime::uuid_v4(&mut lua, state.clone());
+    runtime::uuid_v7(&mut lua, state.clone());
     runtime::x509_parse_pem(&mut lua, state.clone());
+    runtime::yaml_decode(&mut lua, state.clone());
+    runtime::yaml_encode(&mut lua, state.clone());
 
-    debug!("Created lua context");
-
-    (lua, state)
+    lua
 }
 
 impl Script {
@@ -363,43 +840,95 @@ impl Script {
         })
     }
 
+    /// Every run gets a fresh lua environment, globals that have been set by
+    /// a previous run are never visible
     pub fn run(&self, env: Environment,
                       tx: Arc<Mutex<Box<Reporter>>>,
                       arg: AnyLuaValue
     ) -> Result<()> {
-        let (mut lua, state) = ctx(env, tx);
+        let (mut lua, state) = ctx(env, tx)?;
 
         debug!("Initializing lua module");
-        lua.execute::<()>(&self.code)?;
-
-        let run: Result<_> = lua.get("run")
-            .ok_or_else(|| format_err!( "run undefined"));
-        let mut run: hlua::LuaFunction<_> = run?;
+        lua.execute::<()>(&self.code)
+            .map_err(|err| ModuleError::new(ModuleErrorKind::Load, err.to_string()))?;
+
+        {
+            let run: Option<hlua::LuaFunction<_>> = lua.get("run");
+            if run.is_none() {
+                return Err(ModuleError::new(ModuleErrorKind::Load, "run undefined").into());
+            }
+        }
 
         debug!("Starting lua script");
-        let result: hlua::AnyLuaValue = run.call_with_args(arg)
-            .map_err(|err| format_err!("execution failed: {:?}", err))?;
+        let result = call_function(&mut lua, b"run\0", vec![arg]);
 
+        // the hook holds a reference to the state
+        state.insert_hook.lock().unwrap().take();
         debug!("Lua script terminated");
 
-        if let Some(err) = state.error.lock().unwrap().take() {
-            return Err(err);
-        }
+        // an error that was set by a function is more accurate than the lua error
+        let function_err = state.error.lock().unwrap().take()
+            .map(|err| ModuleError::new(ModuleErrorKind::Function, err.to_string()));
 
         use crate::hlua::AnyLuaValue::*;
-        match result {
-            LuaString(x) => bail!("Script returned error: {:?}", x),
-            _ => Ok(())
-        }
+        let err = match (result, function_err) {
+            (Err(err), function_err) => {
+                let (message, traceback) = split_traceback(&err);
+                let mut err = function_err
+                    .unwrap_or_else(|| ModuleError::new(ModuleErrorKind::Runtime, message));
+                err.traceback = traceback;
+                err
+            },
+            (Ok(_), Some(err)) => err,
+            (Ok(LuaString(x)), None) => ModuleError::new(ModuleErrorKind::Returned, x),
+            (Ok(_), None) => return Ok(()),
+        };
+        Err(err.into())
     }
 
     #[cfg(test)]
     pub fn test(&self) -> Result<()> {
+        self.test_with_capabilities(None)
+    }
+
+    #[cfg(test)]
+    pub fn test_with_capabilities(&self, capabilities: Option<Vec<Capability>>) -> Result<()> {
+        self.test_with(capabilities, None)
+    }
+
+    #[cfg(test)]
+    pub fn test_with_produces(&self, produces: Option<Vec<Family>>) -> Result<()> {
+        self.test_with(None, produces)
+    }
+
+    /// Run with an insert hook, the reporter needs to reply to inserts
+    #[cfg(test)]
+    pub fn test_with_insert_hook(&self, insert_hook: &str, reporter: Arc<Mutex<Box<Reporter>>>) -> Result<()> {
+        let env = Script::test_env(None, None, Some(insert_hook.to_string()))?;
+        self.run(env, reporter, AnyLuaValue::LuaNil)
+    }
+
+    /// Run with a custom reporter, eg. to reply to state events
+    #[cfg(test)]
+    pub fn test_with_reporter(&self, reporter: Arc<Mutex<Box<Reporter>>>) -> Result<()> {
+        let env = Script::test_env(None, None, None)?;
+        self.run(env, reporter, AnyLuaValue::LuaNil)
+    }
+
+    #[cfg(test)]
+    fn test_with(&self, capabilities: Option<Vec<Capability>>, produces: Option<Vec<Family>>) -> Result<()> {
         use crate::engine::tests::DummyReporter;
+        let env = Script::test_env(capabilities, produces, None)?;
+        self.run(env, DummyReporter::new(), AnyLuaValue::LuaNil)
+    }
+
+    #[cfg(test)]
+    fn test_env(capabilities: Option<Vec<Capability>>, produces: Option<Vec<Family>>, insert_hook: Option<String>) -> Result<Environment> {
         use crate::geoip::Maxmind;
         let keyring = Vec::new();
         let dns_config = Resolver::from_system()?;
         let proxy = None;
+        let tls = TlsConfig::default();
         let psl = r#"
 // ===BEGIN ICANN DOMAINS===
 com
@@ -413,11 +942,118 @@ com
             keyring,
             dns_config,
             proxy,
+            tls,
             options: HashMap::new(),
+            insert_hook,
             psl,
             geoip,
             asn,
+            capabilities,
+            produces,
         };
-        self.run(env, DummyReporter::new(), AnyLuaValue::LuaNil)
+        Ok(env)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module_error(script: &str) -> ModuleError {
+        let script = Script::load_unchecked(script).expect("Failed to load script");
+        let err = script.test().expect_err("Script didn't fail");
+        err.downcast::<ModuleError>().expect("Not a module error")
+    }
+
+    #[test]
+    fn verify_runtime_error_traceback() {
+        let err = module_error(r#"
+        function helper()
+            local x = nil
+            return x['y']
+        end
+
+        function run()
+            helper()
+        end
+        "#);
+        assert_eq!(err.kind, ModuleErrorKind::Runtime);
+        assert!(err.message.contains("attempt to index"), "{:?}", err.message);
+        let traceback = err.traceback.expect("Traceback is missing");
+        assert!(traceback.starts_with("stack traceback:"), "{:?}", traceback);
+        assert!(traceback.contains("in function 'helper'"), "{:?}", traceback);
+    }
+
+    #[test]
+    fn verify_function_error_traceback() {
+        let err = module_error(r#"
+        function run()
+            x = json_decode('{')
+            return x['a']
+        end
+        "#);
+        assert_eq!(err.kind, ModuleErrorKind::Function);
+        assert!(!err.message.contains("attempt to index"), "{:?}", err.message);
+        assert!(err.traceback.is_some());
+
+        let err = module_error(r#"
+        function run()
+            json_decode('{')
+        end
+        "#);
+        assert_eq!(err.kind, ModuleErrorKind::Function);
+        assert!(err.traceback.is_none());
+    }
+
+    #[test]
+    fn verify_returned_error() {
+        let err = module_error(r#"
+        function run()
+            return 'oops'
+        end
+        "#);
+        assert_eq!(err, ModuleError::new(ModuleErrorKind::Returned, "oops"));
+        assert_eq!(err.to_string(), "Script returned error: \"oops\"");
+    }
+
+    #[test]
+    fn verify_missing_run() {
+        let err = module_error("function main() end");
+        assert_eq!(err.kind, ModuleErrorKind::Load);
+    }
+
+    #[test]
+    fn verify_no_globals_between_modules() {
+        let a = Script::load_unchecked(r#"
+        leaked_toplevel = 'a'
+        function helper() end
+        function run()
+            leaked_global = 'a'
+        end
+        "#).expect("Failed to load script");
+        a.test().expect("Script failed");
+
+        let b = Script::load_unchecked(r#"
+        function run()
+            if leaked_toplevel ~= nil or leaked_global ~= nil or helper ~= nil then
+                return 'globals leaked from previous module'
+            end
+        end
+        "#).expect("Failed to load script");
+        b.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_no_globals_between_runs() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            if runs ~= nil then
+                return 'globals leaked from previous run'
+            end
+            runs = 1
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("First run failed");
+        script.test().expect("Second run failed");
     }
 }
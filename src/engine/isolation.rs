@@ -1,17 +1,20 @@
 use crate::errors::*;
 use chrootable_https::dns::Resolver;
+use crate::connector::TlsConfig;
 use crate::engine::{Environment, Module, Reporter};
+use crate::filesystem::LineReaders;
 use crate::geoip::{GeoIP, AsnDB, Maxmind};
 use crate::keyring::KeyRingEntry;
+use crate::proxy::ProxyChain;
 use crate::psl::Psl;
 use serde_json;
-use crate::worker::{Event, Event2, LogEvent, ExitEvent, EventSender, EventWithCallback};
+use crate::worker::{Event, Event2, LogEvent, ExitEvent, EventSender, EventWithCallback, ModuleError, ModuleErrorKind};
 
 use std::collections::HashMap;
 use std::env;
 use std::io::prelude::*;
 use std::io::{self, BufReader, BufRead, stdin, Stdin, Stdout};
-use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::{mpsc, Arc, Mutex};
 use std::process::{Command, Child, Stdio, ChildStdin, ChildStdout};
 
@@ -21,31 +24,28 @@ pub struct StartCommand {
     verbose: u64,
     keyring: Vec<KeyRingEntry>,
     dns_config: Resolver,
-    proxy: Option<SocketAddr>,
+    proxy: Option<ProxyChain>,
+    tls: TlsConfig,
     options: HashMap<String, String>,
+    insert_hook: Option<String>,
     module: Module,
     arg: serde_json::Value,
 }
 
-impl StartCommand {
-    pub fn new(verbose: u64,
-               keyring: Vec<KeyRingEntry>,
-               dns_config: Resolver,
-               proxy: Option<SocketAddr>,
-               options: HashMap<String, String>,
-               module: Module,
-               arg: serde_json::Value,
-    ) -> StartCommand {
-        StartCommand {
-            verbose,
-            keyring,
-            dns_config,
-            proxy,
-            options,
-            module,
-            arg,
-        }
-    }
+/// Settings of a single module run, everything except `has_stdin` and
+/// `sandbox` is sent to the child in the `StartCommand`
+#[derive(Debug)]
+pub struct SpawnOptions {
+    pub arg: serde_json::Value,
+    pub keyring: Vec<KeyRingEntry>,
+    pub verbose: u64,
+    pub has_stdin: bool,
+    pub dns_config: Resolver,
+    pub proxy: Option<ProxyChain>,
+    pub tls: TlsConfig,
+    pub options: HashMap<String, String>,
+    pub insert_hook: Option<String>,
+    pub sandbox: Option<PathBuf>,
 }
 
 pub struct Supervisor {
@@ -173,16 +173,19 @@ impl Reporter for StdioReporter {
     }
 }
 
-pub fn spawn_module(module: Module,
-                    tx: &EventSender,
-                    arg: serde_json::Value,
-                    keyring: Vec<KeyRingEntry>,
-                    verbose: u64,
-                    has_stdin: bool,
-                    proxy: Option<SocketAddr>,
-                    options: HashMap<String, String>,
-) -> Result<ExitEvent> {
-    let dns_config = Resolver::from_system()?;
+pub fn spawn_module(module: Module, tx: &EventSender, options: SpawnOptions) -> Result<ExitEvent> {
+    let SpawnOptions {
+        arg,
+        keyring,
+        verbose,
+        has_stdin,
+        dns_config,
+        proxy,
+        tls,
+        options,
+        insert_hook,
+        sandbox,
+    } = options;
 
     let mut reader = if has_stdin {
         Some(BufReader::new(stdin()))
@@ -190,17 +193,37 @@ pub fn spawn_module(module: Module,
         None
     };
 
+    let mut readers = LineReaders::default();
+
     let mut supervisor = Supervisor::setup(&module)?;
-    supervisor.send_start(&StartCommand::new(verbose, keyring, dns_config, proxy, options, module, arg))?;
+    supervisor.send_start(&StartCommand {
+        verbose,
+        keyring,
+        dns_config,
+        proxy,
+        tls,
+        options,
+        insert_hook,
+        module,
+        arg,
+    })?;
 
     let exit = loop {
         match supervisor.recv()? {
             Event::Log(event) => tx.send(Event2::Log(event)),
-            Event::Database(object) => supervisor.send_event_callback(object, &tx),
+            Event::Database(object) => supervisor.send_event_callback(object, tx),
+            Event::InsertBatch(object) => supervisor.send_event_callback(object, tx),
+            Event::Query(object) => supervisor.send_event_callback(object, tx),
+            Event::State(object) => supervisor.send_event_callback(object, tx),
+            Event::Counter(object) => supervisor.send_event_callback(object, tx),
             Event::Stdio(object) => object.apply(&mut supervisor, tx, &mut reader),
+            Event::Fs(object) => object.apply(&mut supervisor, tx, &sandbox, &mut readers),
             Event::Exit(event) => {
                 if let ExitEvent::Err(err) = &event {
-                    tx.send(Event2::Log(LogEvent::Error(err.clone())));
+                    tx.send(Event2::Log(LogEvent::Error(err.to_string())));
+                    if let (Some(traceback), true) = (&err.traceback, verbose >= 1) {
+                        tx.send(Event2::Log(LogEvent::Error(traceback.clone())));
+                    }
                 }
                 break event;
             },
@@ -226,10 +249,14 @@ pub fn run_worker(geoip: Vec<u8>, asn: Vec<u8>, psl: &str) -> Result<()> {
         keyring: start.keyring,
         dns_config: start.dns_config,
         proxy: start.proxy,
+        tls: start.tls,
         options: start.options,
+        insert_hook: start.insert_hook,
         psl,
         geoip,
         asn,
+        capabilities: start.module.capabilities().map(|x| x.to_vec()),
+        produces: start.module.produces().map(|x| x.to_vec()),
     };
 
     let mtx: Arc<Mutex<Box<Reporter>>> = Arc::new(Mutex::new(Box::new(reporter)));
@@ -241,7 +268,10 @@ pub fn run_worker(geoip: Vec<u8>, asn: Vec<u8>, psl: &str) -> Result<()> {
 
     let event = match result {
         Ok(_) => ExitEvent::Ok,
-        Err(err) => ExitEvent::Err(err.to_string()),
+        Err(err) => match err.downcast::<ModuleError>() {
+            Ok(err) => ExitEvent::Err(err),
+            Err(err) => ExitEvent::Err(ModuleError::new(ModuleErrorKind::Load, err.to_string())),
+        },
     };
     reporter.send(&Event::Exit(event))?;
 
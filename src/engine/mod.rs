@@ -1,19 +1,21 @@
 use crate::errors::*;
 
 use crate::config::Config;
+use crate::connector::TlsConfig;
+use crate::db::Family;
 use crate::geoip::{GeoIP, AsnDB};
 use crate::json::LuaJsonValue;
 use crate::keyring::KeyRingEntry;
+use crate::proxy::ProxyChain;
 use serde_json;
 use std::fs;
 use std::fmt::Debug;
 use std::path::PathBuf;
 use std::collections::HashMap;
-use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
 use crate::engine::ctx::Script;
 use sn0int_common::ModuleID;
-use sn0int_common::metadata::{Metadata, Source};
+use sn0int_common::metadata::{Capability, Metadata, OptionDecl, Source};
 use chrootable_https::dns::Resolver;
 use crate::psl::Psl;
 use crate::paths;
@@ -33,11 +35,15 @@ pub struct Environment {
     pub verbose: u64,
     pub keyring: Vec<KeyRingEntry>,
     pub dns_config: Resolver,
-    pub proxy: Option<SocketAddr>,
+    pub proxy: Option<ProxyChain>,
+    pub tls: TlsConfig,
     pub options: HashMap<String, String>,
+    pub insert_hook: Option<String>,
     pub psl: Psl,
     pub geoip: GeoIP,
     pub asn: AsnDB,
+    pub capabilities: Option<Vec<Capability>>,
+    pub produces: Option<Vec<Family>>,
 }
 
 #[derive(Debug)]
@@ -215,10 +221,26 @@ pub struct Module {
     version: String,
     source: Option<Source>,
     keyring_access: Vec<String>,
+    keep_state: bool,
+    capabilities: Option<Vec<Capability>>,
+    produces: Option<Vec<Family>>,
+    options: Vec<OptionDecl>,
     private_module: bool,
     script: Script,
 }
 
+/// Check the options against their declarations and normalize them,
+/// options that aren't declared are passed through as they are
+fn validate_options(decls: &[OptionDecl], mut options: HashMap<String, String>) -> Result<HashMap<String, String>> {
+    for decl in decls {
+        if let Some(value) = options.get_mut(&decl.name) {
+            *value = decl.kind.coerce(value)
+                .context(format!("Invalid option {:?}", decl.name))?;
+        }
+    }
+    Ok(options)
+}
+
 impl Module {
     pub fn load(path: &Path, author: &str, name: &str, private_module: bool) -> Result<Module> {
         debug!("Loading lua module {}/{} from {:?}", author, name, path);
@@ -228,6 +250,19 @@ impl Module {
         let metadata = code.parse::<Metadata>()
             .context("Failed to parse module metadata")?;
 
+        let produces = match metadata.produces {
+            Some(names) => {
+                let mut produces = Vec::new();
+                for name in names {
+                    let family = name.parse::<Family>()
+                        .context(format!("Invalid entity type in Produces: {:?}", name))?;
+                    produces.push(family);
+                }
+                Some(produces)
+            },
+            None => None,
+        };
+
         let script = Script::load_unchecked(code)?;
 
         Ok(Module {
@@ -237,11 +272,20 @@ impl Module {
             version: metadata.version,
             source: metadata.source,
             keyring_access: metadata.keyring_access,
+            keep_state: metadata.keep_state,
+            capabilities: metadata.capabilities,
+            produces,
+            options: metadata.options,
             private_module,
             script,
         })
     }
 
+    #[inline]
+    pub fn author(&self) -> &str {
+        &self.author
+    }
+
     #[inline]
     pub fn name(&self) -> &str {
         &self.name
@@ -279,6 +323,32 @@ impl Module {
         &self.keyring_access
     }
 
+    #[inline]
+    pub fn keep_state(&self) -> bool {
+        self.keep_state
+    }
+
+    /// None if the module doesn't declare capabilities, it's allowed to use everything
+    #[inline]
+    pub fn capabilities(&self) -> Option<&[Capability]> {
+        self.capabilities.as_ref().map(|x| &x[..])
+    }
+
+    /// None if the module doesn't declare what it adds, it's allowed to add everything
+    #[inline]
+    pub fn produces(&self) -> Option<&[Family]> {
+        self.produces.as_ref().map(|x| &x[..])
+    }
+
+    #[inline]
+    pub fn options(&self) -> &[OptionDecl] {
+        &self.options
+    }
+
+    pub fn validate_options(&self, options: HashMap<String, String>) -> Result<HashMap<String, String>> {
+        validate_options(&self.options, options)
+    }
+
     #[inline]
     pub fn is_private(&self) -> bool {
         self.private_module
@@ -314,10 +384,14 @@ pub mod tests {
 
     impl DummyReporter {
         pub fn new() -> Arc<Mutex<Box<Reporter>>> {
-            Arc::new(Mutex::new(Box::new(DummyReporter)))
+            shared(Box::new(DummyReporter))
         }
     }
 
+    pub fn shared(reporter: Box<Reporter>) -> Arc<Mutex<Box<Reporter>>> {
+        Arc::new(Mutex::new(reporter))
+    }
+
     impl Reporter for DummyReporter {
         fn send(&mut self, _event: &Event) -> Result<()> {
             Ok(())
@@ -327,4 +401,85 @@ pub mod tests {
             unimplemented!("DummyReporter::recv doesn't exist")
         }
     }
+
+    /// Keeps every event that has been sent and replies to inserts like they
+    /// all got the id 1
+    #[derive(Debug, Default)]
+    pub struct RecordingReporter {
+        events: Arc<Mutex<Vec<serde_json::Value>>>,
+    }
+
+    impl RecordingReporter {
+        pub fn events(&self) -> Arc<Mutex<Vec<serde_json::Value>>> {
+            self.events.clone()
+        }
+    }
+
+    impl Reporter for RecordingReporter {
+        fn send(&mut self, event: &Event) -> Result<()> {
+            let event = serde_json::to_value(event)?;
+            self.events.lock().unwrap().push(event);
+            Ok(())
+        }
+
+        fn recv(&mut self) -> Result<serde_json::Value> {
+            let events = self.events.lock().unwrap();
+            let reply = match events.last().and_then(|e| e["InsertBatch"]["objects"].as_array()) {
+                Some(objects) => serde_json::json!({"Ok": vec![serde_json::json!({"Ok": 1}); objects.len()]}),
+                None => serde_json::json!({"Ok": 1}),
+            };
+            Ok(reply)
+        }
+    }
+
+    /// Keeps the module state in memory and replies to reads and writes
+    #[derive(Debug, Default)]
+    pub struct StateReporter {
+        state: HashMap<String, serde_json::Value>,
+        reply: serde_json::Value,
+    }
+
+    impl Reporter for StateReporter {
+        fn send(&mut self, event: &Event) -> Result<()> {
+            self.reply = match event {
+                Event::State(worker::StateEvent::Get(key)) => serde_json::json!({"Ok": self.state.get(key)}),
+                Event::State(worker::StateEvent::Set((key, value))) => {
+                    match value {
+                        Some(value) => self.state.insert(key.clone(), value.clone()),
+                        None => self.state.remove(key),
+                    };
+                    serde_json::json!({"Ok": null})
+                },
+                _ => serde_json::json!({"Ok": 1}),
+            };
+            Ok(())
+        }
+
+        fn recv(&mut self) -> Result<serde_json::Value> {
+            Ok(self.reply.take())
+        }
+    }
+
+    #[test]
+    fn test_validate_options() {
+        let decls = vec![
+            "port int".parse::<OptionDecl>().unwrap(),
+            "target domain".parse::<OptionDecl>().unwrap(),
+        ];
+
+        let options = validate_options(&decls, hashmap!{
+            "port".to_string() => "0443".to_string(),
+            "other".to_string() => "0443".to_string(),
+        }).unwrap();
+        assert_eq!(options, hashmap!{
+            "port".to_string() => "443".to_string(),
+            "other".to_string() => "0443".to_string(),
+        });
+
+        let err = validate_options(&decls, hashmap!{
+            "port".to_string() => "https".to_string(),
+        }).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid option \"port\"");
+        assert_eq!(err.iter_chain().nth(1).map(|x| x.to_string()), Some("Expected an integer, got \"https\"".to_string()));
+    }
 }
@@ -0,0 +1,304 @@
+use crate::errors::*;
+
+use nix::libc;
+use nix::sys::stat::Mode;
+use nix::unistd;
+use serde::Serialize;
+use serde_json;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+
+/// Number of lines that are kept while nobody is reading from the fifo
+pub const BUFFER_SIZE: usize = 1024;
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long buffered lines are retried after the run finished
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Open the fifo for writing, returns None if there's no reader yet
+fn open_pipe(path: &Path) -> Result<Option<File>> {
+    // a blocking open would wait for a reader to show up. The fd stays non
+    // blocking so a reader that stops reading can't stall the writer thread
+    match OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path) {
+        Ok(file) => Ok(Some(file)),
+        Err(ref err) if err.raw_os_error() == Some(libc::ENXIO) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Write as much as the pipe takes right now, the rest stays in the buffer.
+/// `offset` is the number of bytes of the first line that have been written.
+fn write_lines(pipe: &mut File, buf: &mut VecDeque<Vec<u8>>, offset: &mut usize) -> io::Result<()> {
+    while let Some(line) = buf.front() {
+        match pipe.write(&line[*offset..]) {
+            Ok(0) => break,
+            Ok(n) => {
+                *offset += n;
+                if *offset == line.len() {
+                    buf.pop_front();
+                    *offset = 0;
+                }
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+fn writer(path: PathBuf, rx: mpsc::Receiver<String>, dropped: Arc<AtomicUsize>) {
+    let mut buf = VecDeque::new();
+    let mut offset = 0;
+    let mut pipe = None;
+    let mut deadline = None;
+
+    loop {
+        if deadline.is_none() {
+            match rx.recv_timeout(POLL_INTERVAL) {
+                Ok(line) => buf.push_back(line.into_bytes()),
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => deadline = Some(Instant::now() + FLUSH_TIMEOUT),
+            }
+            buf.extend(rx.try_iter().map(String::into_bytes));
+        } else {
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        // keep the most recent lines if nobody is reading, a line that has
+        // been written partially has to be finished first
+        while buf.len() > BUFFER_SIZE {
+            buf.remove(if offset > 0 { 1 } else { 0 });
+            dropped.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if pipe.is_none() {
+            pipe = match open_pipe(&path) {
+                Ok(pipe) => pipe,
+                Err(err) => {
+                    error!("Failed to open fifo {:?}: {}", path, err);
+                    break;
+                },
+            };
+        }
+
+        if let Some(mut file) = pipe.take() {
+            match write_lines(&mut file, &mut buf, &mut offset) {
+                Ok(_) => pipe = Some(file),
+                // the reader went away, wait for the next one
+                Err(err) => {
+                    debug!("Failed to write to fifo: {}", err);
+                    if offset > 0 {
+                        buf.pop_front();
+                        dropped.fetch_add(1, Ordering::Relaxed);
+                        offset = 0;
+                    }
+                },
+            }
+        }
+
+        if let Some(deadline) = deadline {
+            // don't wait for a reader that isn't there or doesn't keep up
+            if buf.is_empty() || pipe.is_none() || Instant::now() >= deadline {
+                break;
+            }
+        }
+    }
+
+    dropped.fetch_add(buf.len(), Ordering::Relaxed);
+}
+
+#[derive(Serialize)]
+struct Line<'a, T: Serialize> {
+    target: &'a str,
+    #[serde(flatten)]
+    event: T,
+}
+
+/// Write events as json lines to a named pipe in the background. Writes never
+/// block, events are dropped if the buffer is full or if the reader doesn't
+/// keep up.
+pub struct Fifo {
+    tx: mpsc::SyncSender<String>,
+    dropped: Arc<AtomicUsize>,
+    thread: JoinHandle<()>,
+}
+
+impl Fifo {
+    /// Open a fifo, it's created if it doesn't exist yet
+    pub fn open(path: &Path) -> Result<Fifo> {
+        match fs::metadata(path) {
+            Ok(metadata) => {
+                if !metadata.file_type().is_fifo() {
+                    bail!("File exists but isn't a fifo: {:?}", path);
+                }
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                unistd::mkfifo(path, Mode::S_IRUSR | Mode::S_IWUSR)
+                    .context(format!("Failed to create fifo: {:?}", path))?;
+            },
+            Err(err) => return Err(err.into()),
+        }
+
+        let (tx, rx) = mpsc::sync_channel(BUFFER_SIZE);
+        let dropped = Arc::new(AtomicUsize::new(0));
+
+        let path = path.to_owned();
+        let dropped2 = dropped.clone();
+        let thread = thread::spawn(move || writer(path, rx, dropped2));
+
+        Ok(Fifo {
+            tx,
+            dropped,
+            thread,
+        })
+    }
+
+    pub fn send<T: Serialize>(&self, target: &str, event: T) {
+        let mut line = match serde_json::to_string(&Line { target, event }) {
+            Ok(line) => line,
+            Err(err) => {
+                error!("Failed to serialize fifo event: {}", err);
+                return;
+            },
+        };
+        line.push('\n');
+
+        match self.tx.try_send(line) {
+            Ok(_) => (),
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            },
+        }
+    }
+
+    /// Wait until the buffered events are written, at most `FLUSH_TIMEOUT`.
+    /// Returns the number of events that have been dropped
+    pub fn finish(self) -> usize {
+        drop(self.tx);
+        if self.thread.join().is_err() {
+            error!("Fifo writer thread panicked");
+        }
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// A fifo together with the name of the target that is currently running, so
+/// it can be passed around like a prefixed logger
+pub struct PrefixedFifo<'a> {
+    fifo: &'a Fifo,
+    target: &'a str,
+}
+
+impl<'a> PrefixedFifo<'a> {
+    pub fn new(fifo: &'a Fifo, target: &'a str) -> PrefixedFifo<'a> {
+        PrefixedFifo {
+            fifo,
+            target,
+        }
+    }
+
+    pub fn send<T: Serialize>(&self, event: T) {
+        self.fifo.send(self.target, event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::env;
+    use std::io::{BufRead, BufReader};
+
+    fn path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("sn0int-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_no_reader() {
+        let path = path("fifo-no-reader");
+        let fifo = Fifo::open(&path).unwrap();
+        for i in 0..BUFFER_SIZE * 3 {
+            fifo.send("x", json!({"i": i}));
+        }
+        assert!(fifo.finish() >= BUFFER_SIZE);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_reader() {
+        let path = path("fifo-reader");
+        let fifo = Fifo::open(&path).unwrap();
+
+        let path2 = path.clone();
+        let reader = thread::spawn(move || {
+            let file = File::open(path2).unwrap();
+            BufReader::new(file).lines()
+                .collect::<io::Result<Vec<_>>>()
+                .unwrap()
+        });
+
+        // wait until the reader is connected
+        thread::sleep(POLL_INTERVAL * 3);
+        fifo.send("example.com", json!({"event": "insert", "id": 1}));
+        fifo.send("example.com", json!({"event": "insert", "id": 2}));
+        assert_eq!(fifo.finish(), 0);
+
+        assert_eq!(reader.join().unwrap(), vec![
+            r#"{"target":"example.com","event":"insert","id":1}"#,
+            r#"{"target":"example.com","event":"insert","id":2}"#,
+        ]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_stalled_reader() {
+        let path = path("fifo-stalled");
+        let fifo = Fifo::open(&path).unwrap();
+
+        let (done, wait) = mpsc::channel::<()>();
+        let path2 = path.clone();
+        let reader = thread::spawn(move || {
+            // keep the fifo open without reading from it
+            let _file = File::open(path2).unwrap();
+            let _ = wait.recv();
+        });
+
+        thread::sleep(POLL_INTERVAL * 3);
+        let padding = "A".repeat(1024);
+        for i in 0..BUFFER_SIZE {
+            fifo.send("x", json!({"i": i, "padding": padding}));
+            if i % 64 == 0 {
+                thread::sleep(POLL_INTERVAL / 10);
+            }
+        }
+
+        let start = Instant::now();
+        assert!(fifo.finish() > 0);
+        assert!(start.elapsed() < FLUSH_TIMEOUT * 3);
+
+        done.send(()).unwrap();
+        reader.join().unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_not_a_fifo() {
+        let path = path("fifo-file");
+        fs::write(&path, "").unwrap();
+        assert!(Fifo::open(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,208 @@
+use crate::errors::*;
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Component, Path, PathBuf};
+
+
+/// A directory entry returned by `fs_list`
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub name: String,
+    pub dir: bool,
+}
+
+/// Resolve a path that has been requested by a module. The path has to stay
+/// inside the sandbox directory, this includes symlinks that point outside.
+pub fn resolve(root: &Path, path: &str) -> Result<PathBuf> {
+    // check the path before touching the filesystem so we don't leak
+    // anything about files outside of the sandbox
+    let mut depth = 0usize;
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => (),
+            Component::ParentDir if depth > 0 => depth -= 1,
+            _ => bail!("Path is outside of the sandbox directory: {:?}", path),
+        }
+    }
+
+    let root = fs::canonicalize(root)
+        .context("Failed to open sandbox directory")?;
+
+    let resolved = match fs::canonicalize(root.join(path)) {
+        Ok(resolved) => resolved,
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => bail!("No such file or directory: {:?}", path),
+        Err(err) => bail!("Failed to resolve {:?}: {}", path, err),
+    };
+
+    if !resolved.starts_with(&root) {
+        bail!("Path is outside of the sandbox directory: {:?}", path);
+    }
+
+    Ok(resolved)
+}
+
+pub fn read(root: &Path, path: &str) -> Result<String> {
+    let resolved = resolve(root, path)?;
+    if resolved.is_dir() {
+        bail!("Path is a directory: {:?}", path);
+    }
+
+    let bytes = fs::read(&resolved)
+        .map_err(|err| format_err!("Failed to read {:?}: {}", path, err))?;
+    String::from_utf8(bytes)
+        .map_err(|_| format_err!("File is not valid utf8: {:?}", path))
+}
+
+/// List a directory, sorted by name
+pub fn list(root: &Path, path: &str) -> Result<Vec<Entry>> {
+    let resolved = resolve(root, path)?;
+    if !resolved.is_dir() {
+        bail!("Path is not a directory: {:?}", path);
+    }
+
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(&resolved)? {
+        let entry = entry?;
+        let name = entry.file_name()
+            .into_string()
+            .map_err(|_| format_err!("Failed to decode filename"))?;
+        entries.push(Entry {
+            name,
+            dir: entry.file_type()?.is_dir(),
+        });
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(entries)
+}
+
+/// Reads a file one line at a time, so huge wordlists don't need to fit into memory
+#[derive(Debug)]
+pub struct LineReader {
+    reader: BufReader<File>,
+}
+
+impl LineReader {
+    pub fn open(root: &Path, path: &str) -> Result<LineReader> {
+        let resolved = resolve(root, path)?;
+        if resolved.is_dir() {
+            bail!("Path is a directory: {:?}", path);
+        }
+
+        let file = File::open(&resolved)
+            .map_err(|err| format_err!("Failed to open {:?}: {}", path, err))?;
+        Ok(LineReader {
+            reader: BufReader::new(file),
+        })
+    }
+
+    /// Returns the next line without the line ending, None at the end of the file
+    pub fn next_line(&mut self) -> Result<Option<String>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+
+        Ok(Some(line))
+    }
+}
+
+/// Open line readers of a module, files are closed once they are read
+/// completely or the module exits
+#[derive(Debug, Default)]
+pub struct LineReaders {
+    next_id: usize,
+    readers: HashMap<String, LineReader>,
+}
+
+impl LineReaders {
+    pub fn open(&mut self, root: &Path, path: &str) -> Result<String> {
+        let reader = LineReader::open(root, path)?;
+        let id = self.next_id.to_string();
+        self.next_id += 1;
+        self.readers.insert(id.clone(), reader);
+        Ok(id)
+    }
+
+    pub fn next_line(&mut self, id: &str) -> Result<Option<String>> {
+        let line = match self.readers.get_mut(id) {
+            Some(reader) => reader.next_line()?,
+            None => bail!("Invalid line reader reference: {:?}", id),
+        };
+
+        if line.is_none() {
+            self.readers.remove(id);
+        }
+
+        Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_sandbox() {
+        let root = env::temp_dir().join("sn0int-test-sandbox");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("lists")).unwrap();
+        fs::write(root.join("lists/words.txt"), "foo\nbar\n").unwrap();
+        fs::write(root.join("hosts.txt"), "example.com\n").unwrap();
+
+        assert_eq!(read(&root, "lists/words.txt").unwrap(), "foo\nbar\n");
+        assert_eq!(read(&root, "./lists/../hosts.txt").unwrap(), "example.com\n");
+        assert_eq!(list(&root, ".").unwrap(), vec![
+            Entry { name: "hosts.txt".into(), dir: false },
+            Entry { name: "lists".into(), dir: true },
+        ]);
+
+        assert!(read(&root, "../sn0int-test-sandbox/hosts.txt").is_err());
+        assert!(read(&root, "/etc/passwd").is_err());
+        assert!(read(&root, "lists").is_err());
+        assert!(list(&root, "hosts.txt").is_err());
+
+        let err = read(&root, "missing.txt").unwrap_err();
+        assert!(err.to_string().contains("No such file"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_line_readers() {
+        let root = env::temp_dir().join("sn0int-test-lines");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("words.txt"), "foo\r\nbar\n\nbaz").unwrap();
+
+        let mut readers = LineReaders::default();
+        let id = readers.open(&root, "words.txt").unwrap();
+        let mut lines = Vec::new();
+        while let Some(line) = readers.next_line(&id).unwrap() {
+            lines.push(line);
+        }
+        assert_eq!(lines, vec!["foo", "bar", "", "baz"]);
+        assert!(readers.next_line(&id).is_err());
+
+        // stop early, the rest of the file is never read
+        let id = readers.open(&root, "words.txt").unwrap();
+        assert_eq!(readers.next_line(&id).unwrap(), Some("foo".to_string()));
+        assert_eq!(readers.readers.len(), 1);
+
+        assert!(readers.open(&root, "../words.txt").is_err());
+        assert!(readers.open(&root, ".").is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
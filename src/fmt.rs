@@ -1,3 +1,4 @@
+use crate::term::TERM_SETTINGS;
 use std::fmt;
 pub use std::fmt::Write;
 pub use std::fmt::{Result, Formatter, Display, Debug};
@@ -75,6 +76,7 @@ use self::colors::*;
 pub struct DetailFormatter<'a, 'b> {
     w: &'a mut fmt::Formatter<'b>,
     scoped: bool,
+    colors: bool,
     in_group: bool,
     fresh_group: bool,
 }
@@ -84,6 +86,7 @@ impl<'a, 'b> DetailFormatter<'a, 'b> {
         DetailFormatter {
             w,
             scoped,
+            colors: TERM_SETTINGS.colors(),
             in_group: false,
             fresh_group: true,
         }
@@ -106,7 +109,7 @@ impl<'a, 'b> DetailFormatter<'a, 'b> {
 
     #[inline]
     pub fn start(&mut self) -> fmt::Result {
-        if !self.scoped {
+        if !self.scoped && self.colors {
             write!(self, "\x1b[90m")
         } else {
             Ok(())
@@ -115,7 +118,7 @@ impl<'a, 'b> DetailFormatter<'a, 'b> {
 
     #[inline]
     pub fn end(&mut self) -> fmt::Result {
-        if !self.scoped {
+        if !self.scoped && self.colors {
             write!(self, "\x1b[0m")
         } else {
             Ok(())
@@ -124,7 +127,7 @@ impl<'a, 'b> DetailFormatter<'a, 'b> {
 
     #[inline]
     pub fn id<D: fmt::Display>(&mut self, v: D) -> fmt::Result {
-        if self.scoped {
+        if self.scoped && self.colors {
             write!(self, "\x1b[32m#{}\x1b[0m, ", v)
         } else {
             write!(self, "#{}, ", v)
@@ -133,7 +136,7 @@ impl<'a, 'b> DetailFormatter<'a, 'b> {
 
     #[inline]
     pub fn color<C: Color>(&mut self) -> fmt::Result {
-        if self.scoped {
+        if self.scoped && self.colors {
             C::color(self)
         } else {
             Ok(())
@@ -143,7 +146,7 @@ impl<'a, 'b> DetailFormatter<'a, 'b> {
     #[inline]
     pub fn display<C: Color, D: fmt::Display>(&mut self, v: D) -> fmt::Result {
         self.push_into_group()?;
-        if self.scoped {
+        if self.scoped && self.colors {
             C::display(self, v)
         } else {
             write!(self, "{}", v)
@@ -153,7 +156,7 @@ impl<'a, 'b> DetailFormatter<'a, 'b> {
     #[inline]
     pub fn debug<C: Color, D: fmt::Debug>(&mut self, v: D) -> fmt::Result {
         self.push_into_group()?;
-        if self.scoped {
+        if self.scoped && self.colors {
             C::debug(self, v)
         } else {
             write!(self, "{:?}", v)
@@ -169,7 +172,7 @@ impl<'a, 'b> DetailFormatter<'a, 'b> {
 
     #[inline]
     pub fn clear(&mut self) -> fmt::Result {
-        if self.scoped {
+        if self.scoped && self.colors {
             write!(self, "\x1b[0m")
         } else {
             Ok(())
@@ -190,7 +193,9 @@ impl<'a, 'b> DetailFormatter<'a, 'b> {
 
     #[inline]
     pub fn child<D: fmt::Display>(&mut self, c: D) -> fmt::Result {
-        if self.scoped {
+        if !self.colors {
+            write!(self, "\n\t{}", c)
+        } else if self.scoped {
             // if child is unscoped, draw as grey as well
             write!(self, "\n\t\x1b[33m{}\x1b[0m", c)
         } else {
@@ -0,0 +1,112 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+
+/// Upper bound of urls we keep track of, both queued and visited
+pub const DEFAULT_LIMIT: usize = 100_000;
+
+/// Url queue for crawlers that never returns the same url twice. Urls are
+/// handed out round-robin by host so a single site isn't hammered while
+/// others are waiting.
+#[derive(Debug)]
+pub struct Frontier {
+    seen: HashSet<String>,
+    queues: HashMap<String, VecDeque<String>>,
+    hosts: VecDeque<String>,
+    limit: usize,
+}
+
+impl Default for Frontier {
+    fn default() -> Frontier {
+        Frontier::new(DEFAULT_LIMIT)
+    }
+}
+
+impl Frontier {
+    pub fn new(limit: usize) -> Frontier {
+        Frontier {
+            seen: HashSet::new(),
+            queues: HashMap::new(),
+            hosts: VecDeque::new(),
+            limit,
+        }
+    }
+
+    /// Returns true if the url has been queued, false if we've seen it before
+    /// or the frontier is full
+    pub fn push(&mut self, host: &str, url: String) -> bool {
+        if self.seen.len() >= self.limit || self.seen.contains(&url) {
+            return false;
+        }
+
+        self.seen.insert(url.clone());
+
+        let queue = self.queues.entry(host.to_string())
+            .or_default();
+        if queue.is_empty() {
+            self.hosts.push_back(host.to_string());
+        }
+        queue.push_back(url);
+
+        true
+    }
+
+    pub fn pop(&mut self) -> Option<String> {
+        let host = self.hosts.pop_front()?;
+        let queue = self.queues.get_mut(&host)?;
+        let url = queue.pop_front();
+
+        if queue.is_empty() {
+            self.queues.remove(&host);
+        } else {
+            self.hosts.push_back(host);
+        }
+
+        url
+    }
+
+    pub fn len(&self) -> usize {
+        self.queues.values()
+            .map(|x| x.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedup() {
+        let mut f = Frontier::default();
+        assert!(f.push("a", "https://a/".into()));
+        assert!(!f.push("a", "https://a/".into()));
+        assert_eq!(f.pop(), Some("https://a/".into()));
+        assert!(!f.push("a", "https://a/".into()));
+        assert_eq!(f.pop(), None);
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let mut f = Frontier::default();
+        f.push("a", "https://a/1".into());
+        f.push("a", "https://a/2".into());
+        f.push("b", "https://b/1".into());
+        assert_eq!(f.len(), 3);
+        assert_eq!(f.pop(), Some("https://a/1".into()));
+        assert_eq!(f.pop(), Some("https://b/1".into()));
+        assert_eq!(f.pop(), Some("https://a/2".into()));
+        assert_eq!(f.pop(), None);
+        assert!(f.is_empty());
+    }
+
+    #[test]
+    fn test_limit() {
+        let mut f = Frontier::new(1);
+        assert!(f.push("a", "https://a/1".into()));
+        assert!(!f.push("a", "https://a/2".into()));
+    }
+}
@@ -13,6 +13,7 @@ pub static GEOIP_CITY_URL: &str = "https://geolite.maxmind.com/download/geoip/da
 pub static GEOIP_ASN_URL: &str = "https://geolite.maxmind.com/download/geoip/database/GeoLite2-ASN.tar.gz";
 
 pub mod models;
+pub mod prefixes;
 use self::models::GeoLookup;
 use self::models::AsnLookup;
 
@@ -145,8 +145,8 @@ impl Location {
 
 #[derive(Debug, Serialize)]
 pub struct AsnLookup {
-    asn: u32,
-    as_org: String,
+    pub asn: u32,
+    pub as_org: String,
 }
 
 impl AsnLookup {
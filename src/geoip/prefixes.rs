@@ -0,0 +1,161 @@
+use crate::errors::*;
+use std::net::IpAddr;
+
+pub static ASN_PREFIXES_URL: &str = "https://stat.ripe.net/data/announced-prefixes/data.json";
+
+
+#[derive(Debug, Deserialize)]
+struct AnnouncedPrefixes {
+    status: String,
+    data: AnnouncedPrefixesData,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnnouncedPrefixesData {
+    #[serde(default)]
+    prefixes: Vec<AnnouncedPrefix>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnnouncedPrefix {
+    prefix: String,
+}
+
+/// Accept `13335`, `"13335"` and `"AS13335"`
+pub fn parse_asn(asn: &str) -> Result<u32> {
+    let num = if asn.len() > 2 && asn[..2].eq_ignore_ascii_case("as") {
+        &asn[2..]
+    } else {
+        asn
+    };
+
+    num.parse::<u32>()
+        .map_err(|_| format_err!("Invalid asn: {:?}", asn))
+}
+
+pub fn url(asn: u32) -> String {
+    format!("{}?resource=AS{}", ASN_PREFIXES_URL, asn)
+}
+
+/// Split a cidr into the network and the prefix length
+fn parse_prefix(prefix: &str) -> Result<(IpAddr, u8)> {
+    let idx = prefix.find('/')
+        .ok_or_else(|| format_err!("Prefix has no length: {:?}", prefix))?;
+
+    let network = prefix[..idx].parse::<IpAddr>()
+        .context("Invalid network")?;
+    let len = prefix[idx+1..].parse::<u8>()
+        .context("Invalid prefix length")?;
+
+    let max = if network.is_ipv4() { 32 } else { 128 };
+    if len > max {
+        bail!("Prefix length is out of range: {:?}", prefix);
+    }
+
+    Ok((network, len))
+}
+
+fn contains(network: IpAddr, len: u8, ip: IpAddr) -> bool {
+    match (network, ip) {
+        (IpAddr::V4(network), IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(len)).unwrap_or(0);
+            u32::from(network) & mask == u32::from(ip) & mask
+        },
+        (IpAddr::V6(network), IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(len)).unwrap_or(0);
+            u128::from(network) & mask == u128::from(ip) & mask
+        },
+        _ => false,
+    }
+}
+
+/// Find the most specific prefix that contains the ip
+pub fn find_network(prefixes: &[String], ip: IpAddr) -> Option<&str> {
+    prefixes.iter()
+        .filter_map(|prefix| {
+            let (network, len) = parse_prefix(prefix).ok()?;
+            if contains(network, len, ip) {
+                Some((len, prefix.as_str()))
+            } else {
+                None
+            }
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, prefix)| prefix)
+}
+
+/// Read the announced prefixes from the api response, ipv4 prefixes are
+/// returned before ipv6 prefixes. ASNs that aren't known don't have any
+/// prefixes.
+pub fn parse_response(body: &[u8]) -> Result<Vec<String>> {
+    let resp = serde_json::from_slice::<AnnouncedPrefixes>(body)
+        .context("Failed to parse announced prefixes")?;
+
+    if resp.status != "ok" {
+        bail!("Failed to fetch announced prefixes: {:?}", resp.status);
+    }
+
+    let mut prefixes = resp.data.prefixes.into_iter()
+        .map(|p| parse_prefix(&p.prefix).map(|x| (x, p.prefix)))
+        .collect::<Result<Vec<_>>>()?;
+    prefixes.sort_by_key(|((network, len), _)| (network.is_ipv6(), *network, *len));
+    prefixes.dedup_by(|a, b| a.0 == b.0);
+
+    Ok(prefixes.into_iter()
+        .map(|(_, prefix)| prefix)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_asn() {
+        assert_eq!(parse_asn("13335").unwrap(), 13335);
+        assert_eq!(parse_asn("AS13335").unwrap(), 13335);
+        assert_eq!(parse_asn("as13335").unwrap(), 13335);
+        assert!(parse_asn("AS").is_err());
+        assert!(parse_asn("ASx").is_err());
+        assert!(parse_asn("-1").is_err());
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let prefixes = parse_response(br#"{"status":"ok","data":{"resource":"13335","prefixes":[
+            {"prefix":"2606:4700::/32","timelines":[]},
+            {"prefix":"104.16.0.0/13","timelines":[]},
+            {"prefix":"1.1.1.0/24","timelines":[]},
+            {"prefix":"1.1.1.0/24","timelines":[]}
+        ]}}"#).unwrap();
+        assert_eq!(prefixes, vec!["1.1.1.0/24", "104.16.0.0/13", "2606:4700::/32"]);
+    }
+
+    #[test]
+    fn test_parse_response_unknown() {
+        let prefixes = parse_response(br#"{"status":"ok","data":{"resource":"4294967295","prefixes":[]}}"#).unwrap();
+        assert!(prefixes.is_empty());
+    }
+
+    #[test]
+    fn test_find_network() {
+        let prefixes = vec![
+            "1.0.0.0/8".to_string(),
+            "1.1.1.0/24".to_string(),
+            "0.0.0.0/0".to_string(),
+            "2606:4700::/32".to_string(),
+        ];
+        assert_eq!(find_network(&prefixes, "1.1.1.1".parse().unwrap()), Some("1.1.1.0/24"));
+        assert_eq!(find_network(&prefixes, "1.2.3.4".parse().unwrap()), Some("1.0.0.0/8"));
+        assert_eq!(find_network(&prefixes, "192.0.2.1".parse().unwrap()), Some("0.0.0.0/0"));
+        assert_eq!(find_network(&prefixes, "2606:4700::1111".parse().unwrap()), Some("2606:4700::/32"));
+        assert_eq!(find_network(&prefixes, "2001:db8::1".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn test_parse_response_invalid() {
+        assert!(parse_response(br#"{"status":"error","data":{}}"#).is_err());
+        assert!(parse_response(br#"{"status":"ok","data":{"prefixes":[{"prefix":"1.1.1.0/33"}]}}"#).is_err());
+        assert!(parse_response(b"<html>").is_err());
+    }
+}
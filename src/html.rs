@@ -1,10 +1,13 @@
 use crate::errors::Result;
 
 use kuchiki;
+use kuchiki::{NodeData, NodeRef};
 use kuchiki::traits::TendrilSink;
+use serde_json;
 use std::collections::HashMap;
 use crate::hlua::AnyLuaValue;
 use crate::engine::structs::LuaMap;
+use crate::json::LuaJsonValue;
 
 
 #[derive(Debug, PartialEq)]
@@ -98,6 +101,200 @@ pub fn html_form(html: &str) -> Result<HashMap<String, String>> {
     Ok(form)
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct HtmlTextOptions {
+    /// Append the target of links after the link text
+    #[serde(default)]
+    pub links: bool,
+}
+
+impl HtmlTextOptions {
+    pub fn try_from(x: AnyLuaValue) -> Result<HtmlTextOptions> {
+        match LuaJsonValue::from(x) {
+            LuaJsonValue::Null => Ok(HtmlTextOptions::default()),
+            x => {
+                let x = serde_json::from_value(x.into())?;
+                Ok(x)
+            },
+        }
+    }
+}
+
+/// Elements that are never rendered
+const HIDDEN: &[&str] = &["head", "script", "style", "noscript", "template", "iframe", "object", "svg", "select", "textarea"];
+/// Elements that are separated from the surrounding text by a blank line
+const PARAGRAPHS: &[&str] = &["p", "h1", "h2", "h3", "h4", "h5", "h6", "blockquote", "pre", "ul", "ol", "dl", "table", "hr", "figure"];
+/// Elements that start on a new line
+const BLOCKS: &[&str] = &["address", "article", "aside", "body", "dd", "div", "dt", "fieldset", "figcaption", "footer", "form", "header", "li", "main", "nav", "section", "tr", "caption"];
+
+#[derive(Debug, Default)]
+struct TextWriter {
+    out: String,
+    /// Number of newlines that are written before the next text, if any
+    newlines: usize,
+    space: bool,
+}
+
+impl TextWriter {
+    fn flush(&mut self) {
+        if self.out.is_empty() {
+            // the text starts here, drop leading whitespace
+        } else if self.newlines > 0 {
+            let trimmed = self.out.trim_end_matches(' ').len();
+            self.out.truncate(trimmed);
+            let existing = self.out.len() - self.out.trim_end_matches('\n').len();
+            for _ in existing..self.newlines {
+                self.out.push('\n');
+            }
+        } else if self.space && !self.out.ends_with(char::is_whitespace) {
+            self.out.push(' ');
+        }
+        self.newlines = 0;
+        self.space = false;
+    }
+
+    /// Whitespace is collapsed into a single space
+    fn text(&mut self, text: &str) {
+        for c in text.chars() {
+            if c.is_whitespace() {
+                self.space = true;
+            } else {
+                self.flush();
+                self.out.push(c);
+            }
+        }
+    }
+
+    /// Whitespace is kept as it is
+    fn preformatted(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        self.flush();
+        self.out.push_str(text);
+    }
+
+    fn block(&mut self, newlines: usize) {
+        self.newlines = self.newlines.max(newlines);
+    }
+
+    fn line_break(&mut self) {
+        let trimmed = self.out.trim_end_matches(' ').len();
+        self.out.truncate(trimmed);
+        self.out.push('\n');
+        self.newlines = 0;
+        self.space = false;
+    }
+
+    fn finish(self) -> String {
+        self.out.trim_end().to_string()
+    }
+}
+
+fn link_target(node: &NodeRef) -> Option<String> {
+    let element = node.as_element()?;
+    let attrs = element.attributes.borrow();
+    let href = attrs.get("href")?.trim();
+
+    if href.is_empty() || href.starts_with('#') || href.starts_with("javascript:") {
+        None
+    } else {
+        Some(href.to_string())
+    }
+}
+
+fn write_text(w: &mut TextWriter, node: &NodeRef, options: &HtmlTextOptions, pre: bool) {
+    match node.data() {
+        NodeData::Text(text) => {
+            if pre {
+                w.preformatted(&text.borrow());
+            } else {
+                w.text(&text.borrow());
+            }
+        },
+        NodeData::Element(element) => {
+            let name = &*element.name.local;
+            if HIDDEN.contains(&name) {
+                return;
+            }
+
+            if name == "br" {
+                w.line_break();
+                return;
+            }
+
+            let newlines = if PARAGRAPHS.contains(&name) {
+                2
+            } else if BLOCKS.contains(&name) {
+                1
+            } else {
+                0
+            };
+            w.block(newlines);
+
+            if name == "td" || name == "th" {
+                w.space = true;
+            }
+
+            let start = w.out.len();
+            let pre = pre || name == "pre";
+            for child in node.children() {
+                write_text(w, &child, options, pre);
+            }
+
+            if name == "a" && options.links {
+                if let Some(href) = link_target(node) {
+                    // skip links that show their target already
+                    let label = w.out.get(start..).unwrap_or("").trim();
+                    if label != href {
+                        w.text(" ");
+                        w.preformatted(&format!("({})", href));
+                    }
+                }
+            }
+
+            if name == "td" || name == "th" {
+                w.space = true;
+            }
+
+            w.block(newlines);
+        },
+        NodeData::Document(_) | NodeData::DocumentFragment => {
+            for child in node.children() {
+                write_text(w, &child, options, pre);
+            }
+        },
+        _ => (),
+    }
+}
+
+/// Extract the visible text of a document, block elements start on a new line
+pub fn html_text(html: &str, options: &HtmlTextOptions) -> String {
+    let doc = kuchiki::parse_html().one(html);
+    let mut w = TextWriter::default();
+    write_text(&mut w, &doc, options, false);
+    w.finish()
+}
+
+/// Like html_text, but elements that match one of the selectors are removed first
+pub fn html_text_without(html: &str, selectors: &[String], options: &HtmlTextOptions) -> Result<String> {
+    let doc = kuchiki::parse_html().one(html);
+    for selector in selectors {
+        // collect first, detaching while iterating skips siblings
+        let nodes = match doc.select(selector) {
+            Ok(x) => x.collect::<Vec<_>>(),
+            Err(_) => bail!("css selector failed: {:?}", selector),
+        };
+        for node in nodes {
+            node.as_node().detach();
+        }
+    }
+
+    let mut w = TextWriter::default();
+    write_text(&mut w, &doc, options, false);
+    Ok(w.finish())
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -130,4 +327,66 @@ mod tests {
             }
         ]);
     }
+
+    #[test]
+    fn test_html_text() {
+        let html = r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>Example Domain</title>
+    <style>body { color: red; }</style>
+</head>
+<body>
+<div>
+    <h1>Example   Domain</h1>
+    <p>This domain is for use in illustrative examples in documents. You may use this
+    domain in literature without prior coordination or asking for permission.</p>
+    <p><a href="https://www.iana.org/domains/example">More information...</a></p>
+    <ul>
+        <li>Tom &amp; Jerry</li>
+        <li><b>bold</b> and <i>italic</i></li>
+    </ul>
+    <p>first line<br>second line</p>
+    <script>alert(1)</script>
+</div>
+</body>
+</html>"#;
+
+        assert_eq!(html_text(html, &HtmlTextOptions::default()), "Example Domain
+
+This domain is for use in illustrative examples in documents. You may use this domain in literature without prior coordination or asking for permission.
+
+More information...
+
+Tom & Jerry
+bold and italic
+
+first line
+second line");
+
+        let text = html_text(html, &HtmlTextOptions { links: true });
+        assert!(text.contains("\nMore information... (https://www.iana.org/domains/example)\n"));
+    }
+
+    #[test]
+    fn test_html_text_inline() {
+        let opts = HtmlTextOptions { links: true };
+        assert_eq!(html_text("foo<span>bar</span> <b>baz</b>", &opts), "foobar baz");
+        assert_eq!(html_text("<a href=\"#top\">top</a> <a href=\"https://example.com\">https://example.com</a>", &opts), "top https://example.com");
+        assert_eq!(html_text("<table><tr><td>a</td><td>b</td></tr><tr><td>c</td><td>d</td></tr></table>", &opts), "a b\nc d");
+    }
+
+    #[test]
+    fn test_html_text_pre() {
+        let html = "<p>code:</p><pre>fn main() {\n    run();\n}</pre>";
+        assert_eq!(html_text(html, &HtmlTextOptions::default()), "code:\n\nfn main() {\n    run();\n}");
+    }
+
+    #[test]
+    fn test_html_text_without() {
+        let html = r#"<p>Price: 5</p><p class="ts">Generated 12:03:11</p><div id="ads"><p>Buy now</p><p>Sale</p></div>"#;
+        let selectors = vec![".ts".to_string(), "#ads p".to_string()];
+        assert_eq!(html_text_without(html, &selectors, &HtmlTextOptions::default()).unwrap(), "Price: 5");
+        assert!(html_text_without(html, &["<".to_string()], &HtmlTextOptions::default()).is_err());
+    }
 }
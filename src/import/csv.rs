@@ -0,0 +1,332 @@
+use crate::errors::*;
+
+use crate::models::Insert;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::str::FromStr;
+
+
+/// A record and the line it started on
+#[derive(Debug, PartialEq)]
+pub struct Row {
+    pub line: usize,
+    pub fields: Vec<String>,
+}
+
+/// Parse csv as described in rfc 4180. Fields may be quoted, quoted fields
+/// can contain commas, newlines and quotes written as `""`. Empty lines are
+/// skipped.
+pub fn parse(input: &str) -> Result<Vec<Row>> {
+    let mut rows = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+
+    let mut line = 1;
+    let mut start = 1;
+    let mut quoted = false;
+    // the current field has been quoted and the quote was closed
+    let mut closed = false;
+
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                },
+                '"' => {
+                    quoted = false;
+                    closed = true;
+                },
+                '\n' => {
+                    line += 1;
+                    field.push(c);
+                },
+                _ => field.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.is_empty() && !closed => quoted = true,
+            '"' => bail!("Unexpected quote in unquoted field on line {}", line),
+            ',' => {
+                fields.push(field.clone());
+                field.clear();
+                closed = false;
+            },
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\n' => {
+                if !fields.is_empty() || !field.is_empty() || closed {
+                    fields.push(field.clone());
+                    rows.push(Row {
+                        line: start,
+                        fields: fields.clone(),
+                    });
+                }
+                fields.clear();
+                field.clear();
+                closed = false;
+                line += 1;
+                start = line;
+            },
+            _ if closed => bail!("Unexpected data after closing quote on line {}", line),
+            _ => field.push(c),
+        }
+    }
+
+    if quoted {
+        bail!("Quoted field starting on line {} is never closed", start);
+    }
+    if !fields.is_empty() || !field.is_empty() || closed {
+        fields.push(field);
+        rows.push(Row {
+            line: start,
+            fields,
+        });
+    }
+
+    Ok(rows)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Column {
+    /// Counting from 1
+    Index(usize),
+    Name(String),
+}
+
+impl FromStr for Column {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Column> {
+        if s.is_empty() {
+            bail!("Column can't be empty");
+        }
+
+        if s.chars().all(|c| c.is_ascii_digit()) {
+            match s.parse::<usize>()? {
+                0 => bail!("Column numbers start at 1"),
+                idx => Ok(Column::Index(idx)),
+            }
+        } else {
+            Ok(Column::Name(s.to_string()))
+        }
+    }
+}
+
+/// Read an entity field from a column, like `value=1` or `value=domain`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    pub field: String,
+    pub column: Column,
+}
+
+impl FromStr for Mapping {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Mapping> {
+        let idx = s.find('=')
+            .ok_or_else(|| format_err!("Mapping must look like field=column: {:?}", s))?;
+        let (field, column) = (&s[..idx], &s[idx+1..]);
+
+        if field.is_empty() {
+            bail!("Field can't be empty: {:?}", s);
+        }
+
+        Ok(Mapping {
+            field: field.to_string(),
+            column: column.parse()?,
+        })
+    }
+}
+
+impl Mapping {
+    pub fn needs_header(&self) -> bool {
+        match self.column {
+            Column::Name(_) => true,
+            Column::Index(_) => false,
+        }
+    }
+}
+
+/// Resolve the mappings to zero based indexes, the header is empty if the
+/// file doesn't have one
+pub fn resolve(mappings: &[Mapping], header: &[String]) -> Result<Vec<(String, usize)>> {
+    let mut resolved = Vec::new();
+
+    for mapping in mappings {
+        if resolved.iter().any(|(field, _)| *field == mapping.field) {
+            bail!("Field is mapped twice: {:?}", mapping.field);
+        }
+
+        let idx = match &mapping.column {
+            Column::Index(idx) => idx - 1,
+            Column::Name(_) if header.is_empty() => {
+                bail!("Columns can only be referenced by name if there's a header")
+            },
+            Column::Name(name) => header.iter()
+                .position(|x| x.trim() == name)
+                .ok_or_else(|| format_err!("Header has no column named {:?}", name))?,
+        };
+
+        resolved.push((mapping.field.clone(), idx));
+    }
+
+    if !resolved.iter().any(|(field, _)| field == "value") {
+        bail!("A column needs to be mapped to value, eg --map value=1");
+    }
+
+    Ok(resolved)
+}
+
+/// Overwrite fields of an entity. Cells are used as strings unless the field
+/// needs a number or a boolean.
+fn merge_fields<T: Serialize + DeserializeOwned>(obj: T, fields: &[(String, String)]) -> Result<T> {
+    let mut object = match serde_json::to_value(obj)? {
+        Value::Object(object) => object,
+        _ => bail!("Entity isn't an object"),
+    };
+
+    for (key, cell) in fields {
+        if key.ends_with("_id") || key == "family" {
+            bail!("Field can't be imported: {:?}", key);
+        }
+        if !object.contains_key(key) {
+            bail!("Unknown field: {:?}", key);
+        }
+
+        // fields that are empty don't tell us their type, try both
+        let mut candidates = vec![Value::String(cell.to_string())];
+        if let Ok(parsed) = serde_json::from_str::<Value>(cell) {
+            candidates.push(parsed);
+        }
+
+        let mut valid = false;
+        for candidate in candidates {
+            let mut attempt = object.clone();
+            attempt.insert(key.to_string(), candidate);
+            if serde_json::from_value::<T>(Value::Object(attempt.clone())).is_ok() {
+                object = attempt;
+                valid = true;
+                break;
+            }
+        }
+
+        if !valid {
+            bail!("Invalid value for {:?}: {:?}", key, cell);
+        }
+    }
+
+    serde_json::from_value(Value::Object(object))
+        .map_err(Error::from)
+}
+
+/// Set additional fields on an entity that was created from the value column
+pub fn set_fields(insert: Insert, fields: &[(String, String)]) -> Result<Insert> {
+    if fields.is_empty() {
+        return Ok(insert);
+    }
+
+    let insert = match insert {
+        Insert::Domain(x) => Insert::Domain(merge_fields(x, fields)?),
+        Insert::Subdomain(x) => Insert::Subdomain(merge_fields(x, fields)?),
+        Insert::IpAddr(x) => Insert::IpAddr(merge_fields(x, fields)?),
+        Insert::Email(x) => Insert::Email(merge_fields(x, fields)?),
+        Insert::PhoneNumber(x) => Insert::PhoneNumber(merge_fields(x, fields)?),
+        Insert::Device(x) => Insert::Device(merge_fields(x, fields)?),
+        Insert::Network(x) => Insert::Network(merge_fields(x, fields)?),
+        _ => bail!("Fields can't be imported for this entity"),
+    };
+    Ok(insert)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{NewEmail, NewIpAddr};
+
+    fn row(line: usize, fields: &[&str]) -> Row {
+        Row {
+            line,
+            fields: fields.iter().map(|x| x.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let rows = parse("value,comment\nexample.com,plain\r\n\n\"example.org\",\"with \"\"quotes\"\", commas\nand newlines\"\n,\n").unwrap();
+        assert_eq!(rows, vec![
+            row(1, &["value", "comment"]),
+            row(2, &["example.com", "plain"]),
+            row(4, &["example.org", "with \"quotes\", commas\nand newlines"]),
+            row(6, &["", ""]),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_no_trailing_newline() {
+        assert_eq!(parse("a,\"b\"").unwrap(), vec![row(1, &["a", "b"])]);
+        assert_eq!(parse("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(parse("\"never closed\n").is_err());
+        assert!(parse("foo\"bar\n").is_err());
+        assert!(parse("\"foo\"bar\n").is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let header = vec!["email".to_string(), " valid ".to_string()];
+        let mappings = vec!["value=email".parse().unwrap(), "valid=2".parse().unwrap()];
+        assert_eq!(resolve(&mappings, &header).unwrap(), vec![
+            ("value".to_string(), 0),
+            ("valid".to_string(), 1),
+        ]);
+
+        assert!(resolve(&mappings, &[]).is_err());
+        assert!(resolve(&["valid=2".parse().unwrap()], &[]).is_err());
+        assert!(resolve(&["value=1".parse().unwrap(), "value=2".parse().unwrap()], &[]).is_err());
+        assert!("value=0".parse::<Mapping>().is_err());
+        assert!("value".parse::<Mapping>().is_err());
+    }
+
+    #[test]
+    fn test_merge_fields() {
+        let email = merge_fields(NewEmail {
+            value: "root@example.com".to_string(),
+            valid: None,
+        }, &[("valid".to_string(), "true".to_string())]).unwrap();
+        assert_eq!(email.valid, Some(true));
+
+        let ipaddr = NewIpAddr {
+            family: "4".to_string(),
+            value: "192.0.2.1".to_string(),
+            continent: None,
+            continent_code: None,
+            country: None,
+            country_code: None,
+            city: None,
+            latitude: None,
+            longitude: None,
+            asn: None,
+            as_org: None,
+            description: None,
+            reverse_dns: None,
+        };
+        let ipaddr = merge_fields(ipaddr, &[
+            ("description".to_string(), "1234".to_string()),
+            ("asn".to_string(), "64496".to_string()),
+        ]).unwrap();
+        assert_eq!(ipaddr.description, Some("1234".to_string()));
+        assert_eq!(ipaddr.asn, Some(64496));
+
+        assert!(merge_fields(ipaddr.clone(), &[("asn".to_string(), "foo".to_string())]).is_err());
+        assert!(merge_fields(ipaddr.clone(), &[("family".to_string(), "6".to_string())]).is_err());
+        assert!(merge_fields(ipaddr, &[("nope".to_string(), "1".to_string())]).is_err());
+    }
+}
@@ -0,0 +1,237 @@
+use crate::errors::*;
+
+use crate::import::{Host, HostPort, Scan};
+use std::net;
+
+
+#[derive(Debug, Deserialize)]
+struct Record {
+    ip: net::IpAddr,
+    #[serde(default)]
+    ports: Vec<RecordPort>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordPort {
+    port: u16,
+    proto: String,
+    status: Option<String>,
+    service: Option<RecordService>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecordService {
+    name: Option<String>,
+    banner: Option<String>,
+}
+
+/// masscan writes one record per line while it's running. The records are
+/// wrapped in a list, but depending on the version there's a trailing comma
+/// and the list isn't closed if the scan has been interrupted.
+fn records(input: &str) -> Result<(Vec<Record>, bool)> {
+    let mut records = Vec::new();
+    let mut complete = false;
+
+    let mut rest = input.trim_start();
+    if !rest.starts_with('[') {
+        bail!("File doesn't look like masscan json output");
+    }
+    rest = &rest[1..];
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        if rest.is_empty() {
+            break;
+        }
+        // masscan 1.0.3 ends the list with {finished: 1}, which isn't valid json
+        if rest.starts_with(']') || rest.starts_with("{finished:") {
+            complete = true;
+            break;
+        }
+
+        let mut stream = serde_json::Deserializer::from_str(rest)
+            .into_iter::<serde_json::Value>();
+        let value = match stream.next() {
+            Some(Ok(value)) => value,
+            // the file ends in the middle of a record
+            Some(Err(ref err)) if err.is_eof() => break,
+            Some(Err(err)) => bail!("Failed to parse masscan record: {}", err),
+            None => break,
+        };
+        rest = &rest[stream.byte_offset()..];
+
+        if value.get("finished").is_some() {
+            continue;
+        }
+
+        let record = serde_json::from_value(value)
+            .context("Invalid masscan record")?;
+        records.push(record);
+    }
+
+    Ok((records, complete))
+}
+
+/// Parse masscan json output (`-oJ`). Every open port and every banner is a
+/// separate record, they are merged by ip address.
+pub fn parse(input: &str) -> Result<Scan> {
+    let (records, complete) = records(input)?;
+
+    let mut hosts: Vec<Host> = Vec::new();
+    for record in records {
+        let idx = match hosts.iter().position(|x| x.addr == record.ip) {
+            Some(idx) => idx,
+            None => {
+                hosts.push(Host {
+                    addr: record.ip,
+                    hostnames: Vec::new(),
+                    ports: Vec::new(),
+                });
+                hosts.len() - 1
+            },
+        };
+        let host = &mut hosts[idx];
+
+        for port in record.ports {
+            // ping scans report icmp as port 0
+            match port.proto.as_str() {
+                "tcp" | "udp" | "sctp" => (),
+                _ => continue,
+            }
+
+            let status = port.status.as_ref().map(|x| &x[..]);
+            if port.service.is_none() && status != Some("open") {
+                continue;
+            }
+
+            let existing = host.ports.iter()
+                .position(|x| x.port == port.port && x.protocol == port.proto);
+            let existing = match existing {
+                Some(idx) => &mut host.ports[idx],
+                None => {
+                    host.ports.push(HostPort {
+                        protocol: port.proto,
+                        port: port.port,
+                        // a banner implies the port is open
+                        status: Some("open".to_string()),
+                        banner: None,
+                        service: None,
+                        version: None,
+                    });
+                    host.ports.last_mut().unwrap()
+                },
+            };
+
+            // masscan reports multiple banners per port, eg. the http
+            // response and the html title, keep the first one
+            if let Some(service) = port.service {
+                if existing.service.is_none() {
+                    existing.service = service.name;
+                }
+                if existing.banner.is_none() {
+                    existing.banner = service.banner
+                        .filter(|x| !x.is_empty());
+                }
+            }
+        }
+    }
+
+    Ok(Scan {
+        hosts,
+        complete,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCAN: &str = r#"[
+{   "ip": "192.0.2.1",   "timestamp": "1552000000", "ports": [ {"port": 80, "proto": "tcp", "status": "open", "reason": "syn-ack", "ttl": 54} ] },
+{   "ip": "192.0.2.1",   "timestamp": "1552000001", "ports": [ {"port": 22, "proto": "tcp", "status": "open", "reason": "syn-ack", "ttl": 54} ] },
+{   "ip": "192.0.2.1",   "timestamp": "1552000002", "ports": [ {"port": 22, "proto": "tcp", "service": {"name": "ssh", "banner": "SSH-2.0-OpenSSH_7.9"} } ] },
+{   "ip": "192.0.2.1",   "timestamp": "1552000003", "ports": [ {"port": 80, "proto": "tcp", "service": {"name": "http", "banner": "HTTP/1.1 200 OK\r\nServer: nginx"} } ] },
+{   "ip": "192.0.2.1",   "timestamp": "1552000003", "ports": [ {"port": 80, "proto": "tcp", "service": {"name": "title", "banner": "Welcome"} } ] },
+{   "ip": "2001:db8::1",   "timestamp": "1552000004", "ports": [ {"port": 53, "proto": "udp", "status": "open", "reason": "none", "ttl": 54} ] },
+{   "ip": "192.0.2.7",   "timestamp": "1552000005", "ports": [ {"port": 0, "proto": "icmp", "status": "open", "reason": "none", "ttl": 54} ] },
+{   "ip": "192.0.2.8",   "timestamp": "1552000006", "ports": [ {"port": 443, "proto": "tcp", "status": "closed", "reason": "rst", "ttl": 54} ] },
+{finished: 1}
+]
+"#;
+
+    fn port(protocol: &str, port: u16, service: Option<&str>, banner: Option<&str>) -> HostPort {
+        HostPort {
+            protocol: protocol.to_string(),
+            port,
+            status: Some("open".to_string()),
+            banner: banner.map(String::from),
+            service: service.map(String::from),
+            version: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_scan() {
+        let scan = parse(SCAN).unwrap();
+        assert!(scan.complete);
+        assert_eq!(scan.hosts, vec![
+            Host {
+                addr: "192.0.2.1".parse().unwrap(),
+                hostnames: vec![],
+                ports: vec![
+                    port("tcp", 80, Some("http"), Some("HTTP/1.1 200 OK\r\nServer: nginx")),
+                    port("tcp", 22, Some("ssh"), Some("SSH-2.0-OpenSSH_7.9")),
+                ],
+            },
+            Host {
+                addr: "2001:db8::1".parse().unwrap(),
+                hostnames: vec![],
+                ports: vec![port("udp", 53, None, None)],
+            },
+            Host {
+                addr: "192.0.2.7".parse().unwrap(),
+                hostnames: vec![],
+                ports: vec![],
+            },
+            Host {
+                addr: "192.0.2.8".parse().unwrap(),
+                hostnames: vec![],
+                ports: vec![],
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_parse_finished() {
+        let scan = parse(&SCAN.replace("{finished: 1}", "{\"finished\": 1}")).unwrap();
+        assert!(scan.complete);
+        assert_eq!(scan.hosts.len(), 4);
+
+        let scan = parse(&SCAN.replace("{finished: 1}\n", "")).unwrap();
+        assert!(scan.complete);
+        assert_eq!(scan.hosts.len(), 4);
+    }
+
+    #[test]
+    fn test_parse_interrupted() {
+        // trailing comma, no closing bracket and cut off in the middle of a record
+        let idx = SCAN.find("{   \"ip\": \"2001:db8::1\"").unwrap() + 30;
+        let scan = parse(&SCAN[..idx]).unwrap();
+        assert!(!scan.complete);
+        assert_eq!(scan.hosts.len(), 1);
+        assert_eq!(scan.hosts[0].ports.len(), 2);
+
+        let idx = SCAN.find("{   \"ip\": \"2001:db8::1\"").unwrap();
+        let scan = parse(&SCAN[..idx]).unwrap();
+        assert!(!scan.complete);
+        assert_eq!(scan.hosts.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(parse("").is_err());
+        assert!(parse("<nmaprun>").is_err());
+        assert!(parse("[{\"ip\": \"example.com\"}]").is_err());
+        assert!(parse("[{\"ip\": \"192.0.2.1\"} {]").is_err());
+    }
+}
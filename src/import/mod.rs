@@ -0,0 +1,224 @@
+use crate::errors::*;
+
+use crate::cmd::add_cmd;
+use crate::db::DbChange;
+use crate::models::*;
+use crate::shell::Readline;
+use crate::term;
+use self::csv::Mapping;
+use sn0int_common::metadata::Source;
+use std::fmt;
+use std::net;
+
+pub mod csv;
+pub mod masscan;
+pub mod nmap;
+
+
+/// A host found by a scanner, only open ports are kept
+#[derive(Debug, PartialEq)]
+pub struct Host {
+    pub addr: net::IpAddr,
+    pub hostnames: Vec<String>,
+    pub ports: Vec<HostPort>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct HostPort {
+    pub protocol: String,
+    pub port: u16,
+    pub status: Option<String>,
+    pub banner: Option<String>,
+    pub service: Option<String>,
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Scan {
+    pub hosts: Vec<Host>,
+    /// false if the scan was interrupted before it finished writing the file
+    pub complete: bool,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Count {
+    pub new: usize,
+    pub existing: usize,
+}
+
+impl Count {
+    fn add(&mut self, change: &DbChange) {
+        match change {
+            DbChange::Insert => self.new += 1,
+            _ => self.existing += 1,
+        }
+    }
+}
+
+impl fmt::Display for Count {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "{} new, {} existing", self.new, self.existing)
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Stats {
+    pub ipaddrs: Count,
+    pub ports: Count,
+    pub subdomains: Count,
+}
+
+/// Insert an entity and return its id, None if it's out of scope
+fn insert(rl: &Readline, object: Insert, count: Option<&mut Count>) -> Result<Option<i32>> {
+    let id = match rl.db().insert_generic(object)? {
+        Some((change, id)) => {
+            if let Some(count) = count {
+                count.add(&change);
+            }
+            Some(id)
+        },
+        None => None,
+    };
+    Ok(id)
+}
+
+fn import_host(rl: &Readline, host: Host, stats: &mut Stats) -> Result<()> {
+    let ip_addr = host.addr.to_string();
+    let object = add_cmd::target_into_insert(rl, &Source::IpAddrs, ip_addr.clone())?;
+    let ip_addr_id = match insert(rl, object, Some(&mut stats.ipaddrs))? {
+        Some(id) => id,
+        None => {
+            debug!("Skipping {}, ip address is out of scope", ip_addr);
+            return Ok(());
+        },
+    };
+
+    for port in host.ports {
+        let object = InsertPort {
+            ip_addr_id,
+            ip_addr: ip_addr.clone(),
+            port: port.port,
+            protocol: port.protocol,
+            status: port.status,
+            banner: port.banner,
+            service: port.service,
+            version: port.version,
+        }.try_into_new()?;
+
+        insert(rl, Insert::Port(object), Some(&mut stats.ports))?;
+    }
+
+    for hostname in host.hostnames {
+        // hostnames may be out of scope or not be valid dns names at all
+        let object = match add_cmd::target_into_insert(rl, &Source::Subdomains, hostname.clone()) {
+            Ok(object) => object,
+            Err(err) => {
+                term::warn(&format!("Skipping hostname {:?}: {}", hostname, err));
+                continue;
+            },
+        };
+        let subdomain_id = match insert(rl, object, Some(&mut stats.subdomains))? {
+            Some(id) => id,
+            None => continue,
+        };
+
+        insert(rl, Insert::SubdomainIpAddr(NewSubdomainIpAddr {
+            subdomain_id,
+            ip_addr_id,
+        }), None)?;
+    }
+
+    Ok(())
+}
+
+/// Add the hosts of a scan to the database, including their ports and
+/// hostnames. The regular insert path is used, so existing entities are
+/// updated and entities that are out of scope are skipped.
+pub fn import(rl: &Readline, scan: Scan) -> Result<Stats> {
+    if !scan.complete {
+        term::warn("Scan seems to be incomplete, importing the hosts that have been found so far");
+    }
+
+    let mut stats = Stats::default();
+    let batch_size = rl.config().core.insert_batch_size();
+    rl.db().batched(scan.hosts, batch_size, |host| {
+        let label = host.addr;
+        import_host(rl, host, &mut stats)
+            .context(format!("Failed to import {}", label))?;
+        Ok(())
+    })?;
+
+    Ok(stats)
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct CsvStats {
+    pub entities: Count,
+    /// Rows that are invalid or out of scope
+    pub skipped: usize,
+}
+
+fn import_row(rl: &Readline, source: &Source, columns: &[(String, usize)], row: &csv::Row, count: &mut Count) -> Result<bool> {
+    let mut value = None;
+    let mut fields = Vec::new();
+
+    for (field, idx) in columns {
+        let cell = row.fields.get(*idx)
+            .ok_or_else(|| format_err!("Row has no column {}", idx + 1))?
+            .trim();
+
+        if field == "value" {
+            value = Some(cell);
+        } else if !cell.is_empty() {
+            fields.push((field.to_string(), cell.to_string()));
+        }
+    }
+
+    let value = match value {
+        Some(value) if !value.is_empty() => value,
+        _ => bail!("Value is empty"),
+    };
+
+    let object = add_cmd::target_into_insert(rl, source, value.to_string())?;
+    let object = csv::set_fields(object, &fields)?;
+
+    match insert(rl, object, Some(count))? {
+        Some(_) => Ok(true),
+        None => {
+            debug!("Skipping {:?}, entity is out of scope", value);
+            Ok(false)
+        },
+    }
+}
+
+/// Add an entity for every row of a csv file, columns are mapped to fields
+/// of the entity. Rows that can't be imported are skipped with a warning.
+pub fn import_csv(rl: &Readline, source: &Source, input: &str, mappings: &[Mapping], header: bool) -> Result<CsvStats> {
+    let mut rows = csv::parse(input)?.into_iter();
+
+    let header = header || mappings.iter().any(Mapping::needs_header);
+    let header = if header {
+        let row = rows.next()
+            .ok_or_else(|| format_err!("File is empty, expected a header"))?;
+        row.fields
+    } else {
+        Vec::new()
+    };
+    let columns = csv::resolve(mappings, &header)?;
+
+    let mut stats = CsvStats::default();
+    let batch_size = rl.config().core.insert_batch_size();
+    rl.db().batched(rows.collect(), batch_size, |row| {
+        match import_row(rl, source, &columns, &row, &mut stats.entities) {
+            Ok(true) => (),
+            Ok(false) => stats.skipped += 1,
+            Err(err) => {
+                term::warn(&format!("Skipping line {}: {}", row.line, err));
+                stats.skipped += 1;
+            },
+        }
+        Ok(())
+    })?;
+
+    Ok(stats)
+}
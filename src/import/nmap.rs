@@ -0,0 +1,237 @@
+use crate::errors::*;
+
+use crate::import::{Host, HostPort, Scan};
+use crate::xml::next_tag;
+use std::net;
+
+
+#[derive(Debug, Default)]
+struct HostBuilder {
+    addr: Option<net::IpAddr>,
+    up: Option<bool>,
+    hostnames: Vec<String>,
+    ports: Vec<HostPort>,
+}
+
+impl HostBuilder {
+    fn build(self) -> Option<Host> {
+        let addr = self.addr?;
+
+        // hosts that are down are only listed with -v
+        if self.up == Some(false) && self.ports.is_empty() {
+            return None;
+        }
+
+        Some(Host {
+            addr,
+            hostnames: self.hostnames,
+            ports: self.ports,
+        })
+    }
+}
+
+/// Parse nmap xml output (`-oX`). Scans that have been interrupted are
+/// parsed up to the point the file ends.
+pub fn parse(input: &str) -> Result<Scan> {
+    let mut scan = Scan::default();
+    let mut seen_root = false;
+
+    let mut host: Option<HostBuilder> = None;
+    let mut port: Option<HostPort> = None;
+
+    let mut input = input;
+    while let Some((tag, rest)) = next_tag(input) {
+        input = rest;
+
+        let tag = match tag {
+            Some(tag) => tag,
+            None => continue,
+        };
+
+        match (tag.name.as_str(), tag.closing) {
+            ("nmaprun", false) => seen_root = true,
+            ("nmaprun", true) => scan.complete = true,
+            ("host", false) => host = Some(HostBuilder::default()),
+            ("host", true) => {
+                if let Some(h) = host.take().and_then(|h| h.build()) {
+                    scan.hosts.push(h);
+                }
+            },
+            ("status", false) => if let Some(host) = &mut host {
+                host.up = tag.attr("state").map(|x| x == "up");
+            },
+            ("address", false) => if let Some(host) = &mut host {
+                match tag.attr("addrtype") {
+                    Some("ipv4") | Some("ipv6") => {
+                        let addr = tag.attr("addr")
+                            .ok_or_else(|| format_err!("Address is missing addr attribute"))?;
+                        host.addr = Some(addr.parse::<net::IpAddr>()
+                            .context("Invalid ip address")?);
+                    },
+                    // mac addresses
+                    _ => (),
+                }
+            },
+            ("hostname", false) => if let Some(host) = &mut host {
+                if let Some(name) = tag.attr("name") {
+                    let name = name.to_lowercase();
+                    if !host.hostnames.contains(&name) {
+                        host.hostnames.push(name);
+                    }
+                }
+            },
+            ("port", false) if host.is_some() => {
+                let protocol = tag.attr("protocol")
+                    .ok_or_else(|| format_err!("Port is missing protocol attribute"))?;
+                let portid = tag.attr("portid")
+                    .ok_or_else(|| format_err!("Port is missing portid attribute"))?
+                    .parse::<u16>()
+                    .context("Invalid port")?;
+
+                port = Some(HostPort {
+                    protocol: protocol.to_string(),
+                    port: portid,
+                    status: None,
+                    banner: None,
+                    service: None,
+                    version: None,
+                });
+            },
+            ("port", true) => {
+                if let (Some(host), Some(port)) = (&mut host, port.take()) {
+                    if port.status.as_ref().map(|x| &x[..]) == Some("open") {
+                        host.ports.push(port);
+                    }
+                }
+            },
+            ("state", false) => if let Some(port) = &mut port {
+                port.status = tag.attr("state").map(String::from);
+            },
+            ("service", false) => if let Some(port) = &mut port {
+                port.service = tag.attr("name").map(String::from);
+
+                let version = match (tag.attr("product"), tag.attr("version")) {
+                    (Some(product), Some(version)) => Some(format!("{} {}", product, version)),
+                    (Some(product), None) => Some(product.to_string()),
+                    (None, Some(version)) => Some(version.to_string()),
+                    (None, None) => None,
+                };
+                port.version = version;
+            },
+            _ => (),
+        }
+    }
+
+    if !seen_root {
+        bail!("File doesn't look like nmap xml output");
+    }
+
+    // keep what we know about the host the scan stopped at
+    if let Some(h) = host.and_then(|h| h.build()) {
+        scan.hosts.push(h);
+    }
+
+    Ok(scan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCAN: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE nmaprun>
+<?xml-stylesheet href="file:///usr/bin/../share/nmap/nmap.xsl" type="text/xsl"?>
+<!-- Nmap 7.70 scan initiated as: nmap -sV -oX scan.xml 192.0.2.0/30 -->
+<nmaprun scanner="nmap" args="nmap -sV -oX scan.xml 192.0.2.0/30" start="1552000000" version="7.70" xmloutputversion="1.04">
+<host starttime="1552000000" endtime="1552000010"><status state="up" reason="echo-reply" reason_ttl="54"/>
+<address addr="192.0.2.1" addrtype="ipv4"/>
+<address addr="00:11:22:33:44:55" addrtype="mac" vendor="Example"/>
+<hostnames>
+<hostname name="www.example.com" type="user"/>
+<hostname name="WWW.example.com" type="PTR"/>
+</hostnames>
+<ports><extraports state="closed" count="997">
+<extrareasons reason="resets" count="997"/>
+</extraports>
+<port protocol="tcp" portid="22"><state state="open" reason="syn-ack" reason_ttl="54"/><service name="ssh" product="OpenSSH" version="7.9" extrainfo="protocol 2.0" method="probed" conf="10"/></port>
+<port protocol="tcp" portid="80"><state state="open" reason="syn-ack" reason_ttl="54"/><service name="http" product="nginx &amp; &quot;friends&quot; &gt; 1" method="probed" conf="10"/></port>
+<port protocol="tcp" portid="443"><state state="filtered" reason="no-response" reason_ttl="0"/><service name="https" method="table" conf="3"/></port>
+</ports>
+</host>
+<host><status state="down" reason="no-response" reason_ttl="0"/>
+<address addr="192.0.2.2" addrtype="ipv4"/>
+</host>
+<host starttime="1552000000" endtime="1552000010"><status state="up" reason="echo-reply" reason_ttl="54"/>
+<address addr="2001:db8::1" addrtype="ipv6"/>
+<hostnames>
+</hostnames>
+<ports><port protocol="udp" portid="53"><state state="open" reason="udp-response" reason_ttl="54"/></port>
+</ports>
+</host>
+<runstats><finished time="1552000020" elapsed="20.00" exit="success"/><hosts up="2" down="1" total="3"/>
+</runstats>
+</nmaprun>
+"#;
+
+    #[test]
+    fn test_parse_scan() {
+        let scan = parse(SCAN).unwrap();
+        assert!(scan.complete);
+        assert_eq!(scan.hosts, vec![
+            Host {
+                addr: "192.0.2.1".parse().unwrap(),
+                hostnames: vec!["www.example.com".into()],
+                ports: vec![
+                    HostPort {
+                        protocol: "tcp".into(),
+                        port: 22,
+                        status: Some("open".into()),
+                        banner: None,
+                        service: Some("ssh".into()),
+                        version: Some("OpenSSH 7.9".into()),
+                    },
+                    HostPort {
+                        protocol: "tcp".into(),
+                        port: 80,
+                        status: Some("open".into()),
+                        banner: None,
+                        service: Some("http".into()),
+                        version: Some("nginx & \"friends\" > 1".into()),
+                    },
+                ],
+            },
+            Host {
+                addr: "2001:db8::1".parse().unwrap(),
+                hostnames: vec![],
+                ports: vec![
+                    HostPort {
+                        protocol: "udp".into(),
+                        port: 53,
+                        status: Some("open".into()),
+                        banner: None,
+                        service: None,
+                        version: None,
+                    },
+                ],
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_parse_interrupted() {
+        // cut off in the middle of the third host
+        let idx = SCAN.find("<ports><port protocol=\"udp\"").unwrap() + 20;
+        let scan = parse(&SCAN[..idx]).unwrap();
+        assert!(!scan.complete);
+        assert_eq!(scan.hosts.len(), 2);
+        assert_eq!(scan.hosts[0].ports.len(), 2);
+        assert_eq!(scan.hosts[1].addr, "2001:db8::1".parse::<net::IpAddr>().unwrap());
+        assert!(scan.hosts[1].ports.is_empty());
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(parse("").is_err());
+        assert!(parse("{\"ip\": \"192.0.2.1\"}").is_err());
+    }
+}
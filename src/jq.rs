@@ -0,0 +1,482 @@
+//! A small subset of jq to reshape json values. Supported are:
+//!
+//! - `.` returns the input
+//! - `.foo`, `."foo"` and `.[0]` access a key or an index
+//! - `.[]` iterates over all values of an array or object
+//! - `a | b` passes every output of `a` into `b`
+//! - `[a]` collects all outputs of `a` into an array
+//! - `map(a)` is the same as `[.[] | a]`
+//! - `select(a)` passes the input through if `a` is truthy
+//! - `==`, `!=`, `<`, `<=`, `>` and `>=` compare two values
+//! - string, number, `true`, `false` and `null` literals
+//!
+//! Everything else is rejected with an error.
+use crate::errors::*;
+
+use serde_json::{self, Value};
+use std::cmp::Ordering;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Dot,
+    Pipe,
+    LBracket,
+    RBracket,
+    LParen,
+    RParen,
+    Op(Op),
+    Ident(String),
+    Str(String),
+    Num(f64),
+}
+
+fn tokenize(program: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = program.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let token = match c {
+            _ if c.is_whitespace() => continue,
+            '.' => Token::Dot,
+            '|' => Token::Pipe,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '=' | '!' | '<' | '>' => {
+                let eq = match chars.peek() {
+                    Some((_, '=')) => {
+                        chars.next();
+                        true
+                    },
+                    _ => false,
+                };
+                match (c, eq) {
+                    ('=', true) => Token::Op(Op::Eq),
+                    ('!', true) => Token::Op(Op::Ne),
+                    ('<', false) => Token::Op(Op::Lt),
+                    ('<', true) => Token::Op(Op::Le),
+                    ('>', false) => Token::Op(Op::Gt),
+                    ('>', true) => Token::Op(Op::Ge),
+                    _ => bail!("Unsupported operator at position {}", i),
+                }
+            },
+            '"' => {
+                let mut end = None;
+                let mut escaped = false;
+                for (j, c) in chars.by_ref() {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '"' => {
+                            end = Some(j);
+                            break;
+                        },
+                        _ => (),
+                    }
+                }
+                let end = end.ok_or_else(|| format_err!("Unterminated string at position {}", i))?;
+                let s = serde_json::from_str(&program[i..=end])
+                    .context(format!("Invalid string at position {}", i))?;
+                Token::Str(s)
+            },
+            '-' | '0'..='9' => {
+                let mut end = i + 1;
+                while let Some(&(j, c)) = chars.peek() {
+                    if !(c.is_ascii_digit() || c == '.') {
+                        break;
+                    }
+                    chars.next();
+                    end = j + 1;
+                }
+                let n = program[i..end].parse()
+                    .map_err(|_| format_err!("Invalid number at position {}", i))?;
+                Token::Num(n)
+            },
+            'a'..='z' | 'A'..='Z' | '_' => {
+                let mut end = i + 1;
+                while let Some(&(j, c)) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    chars.next();
+                    end = j + 1;
+                }
+                Token::Ident(program[i..end].to_string())
+            },
+            _ => bail!("Unsupported character {:?} at position {}", c, i),
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, PartialEq)]
+enum Filter {
+    Identity,
+    Field(String),
+    Index(f64),
+    Iterate,
+    Literal(Value),
+    Pipe(Box<Filter>, Box<Filter>),
+    Collect(Box<Filter>),
+    Map(Box<Filter>),
+    Select(Box<Filter>),
+    Compare(Box<Filter>, Op, Box<Filter>),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        let token = self.tokens.get(self.pos);
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: &Token) -> Result<()> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => bail!("Expected {:?}, found {:?}", expected, token),
+            None => bail!("Expected {:?}, found end of program", expected),
+        }
+    }
+
+    fn pipe(&mut self) -> Result<Filter> {
+        let mut filter = self.compare()?;
+        while self.peek() == Some(&Token::Pipe) {
+            self.next();
+            let rhs = self.compare()?;
+            filter = Filter::Pipe(Box::new(filter), Box::new(rhs));
+        }
+        Ok(filter)
+    }
+
+    fn compare(&mut self) -> Result<Filter> {
+        let lhs = self.postfix()?;
+        let op = match self.peek() {
+            Some(Token::Op(op)) => *op,
+            _ => return Ok(lhs),
+        };
+        self.next();
+        let rhs = self.postfix()?;
+        Ok(Filter::Compare(Box::new(lhs), op, Box::new(rhs)))
+    }
+
+    fn postfix(&mut self) -> Result<Filter> {
+        let mut filter = self.term()?;
+        while let Some(suffix) = self.suffix()? {
+            filter = Filter::Pipe(Box::new(filter), Box::new(suffix));
+        }
+        Ok(filter)
+    }
+
+    /// `.foo`, `."foo"`, `[]` and `[0]` after a term
+    fn suffix(&mut self) -> Result<Option<Filter>> {
+        match self.peek() {
+            Some(Token::Dot) => {
+                self.next();
+                match self.next() {
+                    Some(Token::Ident(name)) | Some(Token::Str(name)) => Ok(Some(Filter::Field(name.to_string()))),
+                    Some(token) => bail!("Expected a key after '.', found {:?}", token),
+                    None => bail!("Expected a key after '.', found end of program"),
+                }
+            },
+            Some(Token::LBracket) => {
+                self.next();
+                self.brackets().map(Some)
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// The inside of `.[]`, `.[0]` and `.["foo"]`
+    fn brackets(&mut self) -> Result<Filter> {
+        let filter = match self.next() {
+            Some(Token::RBracket) => return Ok(Filter::Iterate),
+            Some(Token::Num(n)) => Filter::Index(*n),
+            Some(Token::Str(s)) => Filter::Field(s.to_string()),
+            Some(token) => bail!("Unsupported index: {:?}", token),
+            None => bail!("Expected an index, found end of program"),
+        };
+        self.expect(&Token::RBracket)?;
+        Ok(filter)
+    }
+
+    fn term(&mut self) -> Result<Filter> {
+        let token = match self.next() {
+            Some(token) => token,
+            None => bail!("Unexpected end of program"),
+        };
+
+        match token {
+            Token::Dot => match self.peek() {
+                Some(Token::Ident(_)) | Some(Token::Str(_)) => {
+                    match self.next() {
+                        Some(Token::Ident(name)) | Some(Token::Str(name)) => Ok(Filter::Field(name.to_string())),
+                        _ => unreachable!(),
+                    }
+                },
+                Some(Token::LBracket) => {
+                    self.next();
+                    self.brackets()
+                },
+                _ => Ok(Filter::Identity),
+            },
+            Token::LBracket => {
+                let inner = self.pipe()?;
+                self.expect(&Token::RBracket)?;
+                Ok(Filter::Collect(Box::new(inner)))
+            },
+            Token::LParen => {
+                let inner = self.pipe()?;
+                self.expect(&Token::RParen)?;
+                Ok(inner)
+            },
+            Token::Str(s) => Ok(Filter::Literal(Value::String(s.to_string()))),
+            Token::Num(n) => {
+                let n = serde_json::Number::from_f64(*n)
+                    .ok_or_else(|| format_err!("Invalid number: {}", n))?;
+                Ok(Filter::Literal(Value::Number(n)))
+            },
+            Token::Ident(name) => match name.as_str() {
+                "true" => Ok(Filter::Literal(Value::Bool(true))),
+                "false" => Ok(Filter::Literal(Value::Bool(false))),
+                "null" => Ok(Filter::Literal(Value::Null)),
+                "map" | "select" => {
+                    let select = name == "select";
+                    self.expect(&Token::LParen)?;
+                    let inner = Box::new(self.pipe()?);
+                    self.expect(&Token::RParen)?;
+                    if select {
+                        Ok(Filter::Select(inner))
+                    } else {
+                        Ok(Filter::Map(inner))
+                    }
+                },
+                _ => bail!("Unsupported function: {:?}", name),
+            },
+            token => bail!("Unexpected {:?}", token),
+        }
+    }
+}
+
+fn parse(program: &str) -> Result<Filter> {
+    let mut parser = Parser {
+        tokens: tokenize(program)?,
+        pos: 0,
+    };
+    let filter = parser.pipe()?;
+    if let Some(token) = parser.peek() {
+        bail!("Unexpected {:?}", token);
+    }
+    Ok(filter)
+}
+
+fn type_name(v: &Value) -> &'static str {
+    match v {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn is_truthy(v: &Value) -> bool {
+    match v {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        _ => true,
+    }
+}
+
+fn compare(a: &Value, op: Op, b: &Value) -> Result<bool> {
+    let ordering = match (a, b) {
+        // lua doesn't distinguish between 1 and 1.0
+        (Value::Number(a), Value::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => match op {
+            Op::Eq => return Ok(a == b),
+            Op::Ne => return Ok(a != b),
+            _ => bail!("Can't compare {} with {}", type_name(a), type_name(b)),
+        },
+    };
+
+    Ok(match (op, ordering) {
+        (Op::Eq, Some(Ordering::Equal)) => true,
+        (Op::Eq, _) => false,
+        (Op::Ne, Some(Ordering::Equal)) => false,
+        (Op::Ne, _) => true,
+        (Op::Lt, Some(Ordering::Less)) => true,
+        (Op::Le, Some(Ordering::Less)) | (Op::Le, Some(Ordering::Equal)) => true,
+        (Op::Gt, Some(Ordering::Greater)) => true,
+        (Op::Ge, Some(Ordering::Greater)) | (Op::Ge, Some(Ordering::Equal)) => true,
+        _ => false,
+    })
+}
+
+fn iterate(input: &Value) -> Result<Vec<Value>> {
+    match input {
+        Value::Array(v) => Ok(v.clone()),
+        Value::Object(v) => Ok(v.values().cloned().collect()),
+        _ => bail!("Can't iterate over {}", type_name(input)),
+    }
+}
+
+fn eval(filter: &Filter, input: &Value) -> Result<Vec<Value>> {
+    match filter {
+        Filter::Identity => Ok(vec![input.clone()]),
+        Filter::Field(name) => match input {
+            Value::Object(v) => Ok(vec![v.get(name).cloned().unwrap_or(Value::Null)]),
+            Value::Null => Ok(vec![Value::Null]),
+            _ => bail!("Can't index {} with {:?}", type_name(input), name),
+        },
+        Filter::Index(n) => match input {
+            Value::Array(v) => {
+                let n = *n as i64;
+                let i = if n < 0 { v.len() as i64 + n } else { n };
+                if i < 0 {
+                    Ok(vec![Value::Null])
+                } else {
+                    Ok(vec![v.get(i as usize).cloned().unwrap_or(Value::Null)])
+                }
+            },
+            Value::Null => Ok(vec![Value::Null]),
+            _ => bail!("Can't index {} with a number", type_name(input)),
+        },
+        Filter::Iterate => iterate(input),
+        Filter::Literal(v) => Ok(vec![v.clone()]),
+        Filter::Pipe(lhs, rhs) => {
+            let mut out = Vec::new();
+            for v in eval(lhs, input)? {
+                out.extend(eval(rhs, &v)?);
+            }
+            Ok(out)
+        },
+        Filter::Collect(inner) => Ok(vec![Value::Array(eval(inner, input)?)]),
+        Filter::Map(inner) => {
+            let mut out = Vec::new();
+            for v in iterate(input)? {
+                out.extend(eval(inner, &v)?);
+            }
+            Ok(vec![Value::Array(out)])
+        },
+        Filter::Select(cond) => {
+            let mut out = Vec::new();
+            for v in eval(cond, input)? {
+                if is_truthy(&v) {
+                    out.push(input.clone());
+                }
+            }
+            Ok(out)
+        },
+        Filter::Compare(lhs, op, rhs) => {
+            let mut out = Vec::new();
+            for b in eval(rhs, input)? {
+                for a in eval(lhs, input)? {
+                    out.push(Value::Bool(compare(&a, *op, &b)?));
+                }
+            }
+            Ok(out)
+        },
+    }
+}
+
+/// Run a program and return all outputs
+pub fn transform(input: &Value, program: &str) -> Result<Vec<Value>> {
+    let filter = parse(program)
+        .context(format!("Failed to parse program: {:?}", program))?;
+    eval(&filter, input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn users() -> Value {
+        json!([
+            {"name": "alice", "active": true, "age": 31, "tags": ["admin"]},
+            {"name": "bob", "active": false, "age": 17, "tags": []},
+            {"name": "carol", "active": true, "age": 18},
+        ])
+    }
+
+    #[test]
+    fn test_identity() {
+        assert_eq!(transform(&users(), ".").unwrap(), vec![users()]);
+    }
+
+    #[test]
+    fn test_fields() {
+        let x = json!({"a": {"b": [1, 2, 3]}, "c d": 4});
+        assert_eq!(transform(&x, ".a.b[0]").unwrap(), vec![json!(1)]);
+        assert_eq!(transform(&x, ".a.b[-1]").unwrap(), vec![json!(3)]);
+        assert_eq!(transform(&x, ".a.b[9]").unwrap(), vec![Value::Null]);
+        assert_eq!(transform(&x, ".\"c d\"").unwrap(), vec![json!(4)]);
+        assert_eq!(transform(&x, ".[\"c d\"]").unwrap(), vec![json!(4)]);
+        assert_eq!(transform(&x, ".missing.a").unwrap(), vec![Value::Null]);
+        assert_eq!(transform(&x, ".a.b[]").unwrap(), vec![json!(1), json!(2), json!(3)]);
+    }
+
+    #[test]
+    fn test_map_select() {
+        let x = users();
+        assert_eq!(transform(&x, "map(select(.active)) | map(.name)").unwrap(),
+                   vec![json!(["alice", "carol"])]);
+        assert_eq!(transform(&x, "[.[] | select(.active) | .name]").unwrap(),
+                   vec![json!(["alice", "carol"])]);
+        assert_eq!(transform(&x, "map(select(.age >= 18).name)").unwrap(),
+                   vec![json!(["alice", "carol"])]);
+        assert_eq!(transform(&x, ".[] | select(.name == \"bob\") | .age").unwrap(),
+                   vec![json!(17)]);
+        assert_eq!(transform(&x, "map(.tags[0])").unwrap(),
+                   vec![json!(["admin", null, null])]);
+    }
+
+    #[test]
+    fn test_compare() {
+        let x = json!({"a": 1, "b": "x"});
+        assert_eq!(transform(&x, ".a == 1.0").unwrap(), vec![json!(true)]);
+        assert_eq!(transform(&x, ".a != null").unwrap(), vec![json!(true)]);
+        assert_eq!(transform(&x, ".b < \"y\"").unwrap(), vec![json!(true)]);
+        assert_eq!(transform(&x, ".a > -1").unwrap(), vec![json!(true)]);
+        assert!(transform(&x, ".a < .b").is_err());
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        assert!(transform(&json!(1), ".[]").is_err());
+        assert!(transform(&json!("x"), ".a").is_err());
+        assert!(transform(&json!({}), ".[0]").is_err());
+    }
+
+    #[test]
+    fn test_unsupported_syntax() {
+        let x = users();
+        for program in &["", ".a,.b", "keys", ".a +.b", "map(.a", ".[", ". | | .", "..", ".a = 1", "\"abc"] {
+            assert!(transform(&x, program).is_err(), "{:?}", program);
+        }
+    }
+}
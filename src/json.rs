@@ -32,6 +32,224 @@ pub fn decode_stream(x: &str) -> Result<Vec<AnyLuaValue>> {
     Ok(list)
 }
 
+/// How arrays are combined if both sides of a merge contain one
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all="lowercase")]
+pub enum ArrayMerge {
+    Replace,
+    Concat,
+}
+
+/// Deep merge two values, `b` wins on conflicts
+pub fn merge(a: Value, b: Value, arrays: ArrayMerge) -> Value {
+    match (a, b) {
+        (Value::Object(mut a), Value::Object(b)) => {
+            for (k, v) in b {
+                let v = match a.remove(&k) {
+                    Some(old) => merge(old, v, arrays),
+                    None => v,
+                };
+                a.insert(k, v);
+            }
+            Value::Object(a)
+        },
+        (Value::Array(mut a), Value::Array(b)) if arrays == ArrayMerge::Concat => {
+            a.extend(b);
+            Value::Array(a)
+        },
+        (_, b) => b,
+    }
+}
+
+/// Apply a json merge-patch as defined in RFC 7386
+pub fn merge_patch(doc: Value, patch: Value) -> Value {
+    match patch {
+        Value::Object(patch) => {
+            let mut doc = match doc {
+                Value::Object(doc) => doc,
+                _ => Map::new(),
+            };
+
+            for (k, v) in patch {
+                if v.is_null() {
+                    doc.remove(&k);
+                } else {
+                    let old = doc.remove(&k).unwrap_or(Value::Null);
+                    doc.insert(k, merge_patch(old, v));
+                }
+            }
+
+            Value::Object(doc)
+        },
+        patch => patch,
+    }
+}
+
+/// How arrays are compared by `diff`
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all="lowercase")]
+pub enum ArrayDiff {
+    Ordered,
+    Unordered,
+}
+
+/// Keys that differ between two values, nested keys are joined with `.` and
+/// array indexes start at 1
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Diff {
+    pub added: Map<String, Value>,
+    pub removed: Map<String, Value>,
+    pub changed: Map<String, Value>,
+}
+
+fn diff_path(parent: &str, key: &str) -> String {
+    if parent.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", parent, key)
+    }
+}
+
+fn diff_into(diff: &mut Diff, path: &str, old: Value, new: Value, arrays: ArrayDiff) {
+    match (old, new) {
+        (Value::Object(mut old), Value::Object(new)) => {
+            for (k, new) in new {
+                let path = diff_path(path, &k);
+                match old.remove(&k) {
+                    Some(old) => diff_into(diff, &path, old, new, arrays),
+                    None => { diff.added.insert(path, new); },
+                }
+            }
+            for (k, old) in old {
+                diff.removed.insert(diff_path(path, &k), old);
+            }
+        },
+        (Value::Array(old), Value::Array(new)) if arrays == ArrayDiff::Unordered => {
+            let mut old = old.into_iter()
+                .enumerate()
+                .map(|(i, x)| (i + 1, x))
+                .collect::<Vec<_>>();
+
+            for (i, new) in new.into_iter().enumerate() {
+                match old.iter().position(|(_, old)| *old == new) {
+                    Some(idx) => { old.remove(idx); },
+                    None => { diff.added.insert(diff_path(path, &(i + 1).to_string()), new); },
+                }
+            }
+            for (i, old) in old {
+                diff.removed.insert(diff_path(path, &i.to_string()), old);
+            }
+        },
+        (Value::Array(old), Value::Array(new)) => {
+            let mut old = old.into_iter();
+            let mut new = new.into_iter();
+            let mut i = 1;
+            loop {
+                let path = diff_path(path, &i.to_string());
+                match (old.next(), new.next()) {
+                    (Some(old), Some(new)) => diff_into(diff, &path, old, new, arrays),
+                    (None, Some(new)) => { diff.added.insert(path, new); },
+                    (Some(old), None) => { diff.removed.insert(path, old); },
+                    (None, None) => break,
+                }
+                i += 1;
+            }
+        },
+        (old, new) => {
+            if old != new {
+                diff.changed.insert(path.to_string(), serde_json::json!({
+                    "old": old,
+                    "new": new,
+                }));
+            }
+        },
+    }
+}
+
+/// Compare two values recursively
+pub fn diff(old: Value, new: Value, arrays: ArrayDiff) -> Diff {
+    let mut diff = Diff::default();
+    diff_into(&mut diff, "", old, new, arrays);
+    diff
+}
+
+/// Format a float like javascript does, as required by RFC 8785
+fn canonical_number(f: f64) -> Result<String> {
+    if !f.is_finite() {
+        bail!("Can't serialize {} as canonical json", f);
+    }
+
+    if f == 0f64 {
+        return Ok("0".to_string());
+    }
+
+    // very small and very large numbers use the exponential notation
+    let small = f.abs() < 1e-6;
+    let large = f.abs() >= 1e21;
+    if small || large {
+        // rust omits the sign of positive exponents
+        let s = format!("{:e}", f);
+        match s.find('e') {
+            Some(i) if !s[i+1..].starts_with('-') => Ok(format!("{}e+{}", &s[..i], &s[i+1..])),
+            _ => Ok(s),
+        }
+    } else {
+        Ok(format!("{}", f))
+    }
+}
+
+fn write_canonical(out: &mut String, v: &Value) -> Result<()> {
+    match v {
+        Value::Null => out.push_str("null"),
+        Value::Bool(v) => out.push_str(if *v { "true" } else { "false" }),
+        Value::Number(v) => {
+            if v.is_u64() || v.is_i64() {
+                out.push_str(&v.to_string());
+            } else {
+                let f = v.as_f64()
+                    .ok_or_else(|| format_err!("Invalid number: {}", v))?;
+                out.push_str(&canonical_number(f)?);
+            }
+        },
+        Value::String(v) => out.push_str(&serde_json::to_string(v)?),
+        Value::Array(v) => {
+            out.push('[');
+            for (i, x) in v.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(out, x)?;
+            }
+            out.push(']');
+        },
+        Value::Object(v) => {
+            // keys are sorted by their utf-16 code units
+            let mut keys = v.keys().collect::<Vec<_>>();
+            keys.sort_by_key(|k| k.encode_utf16().collect::<Vec<_>>());
+
+            out.push('{');
+            for (i, k) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::to_string(k)?);
+                out.push(':');
+                write_canonical(out, &v[k])?;
+            }
+            out.push('}');
+        },
+    }
+    Ok(())
+}
+
+/// Serialize into canonical json as described in RFC 8785, the output
+/// doesn't depend on key order and is suitable for signatures
+pub fn canonical(v: &Value) -> Result<String> {
+    let mut out = String::new();
+    write_canonical(&mut out, v)?;
+    Ok(out)
+}
+
 pub fn lua_array_is_list(array: &[(AnyLuaValue, AnyLuaValue)]) -> bool {
     if !array.is_empty() {
         let first = &array[0];
@@ -89,7 +307,11 @@ impl From<AnyLuaValue> for LuaJsonValue {
             AnyLuaValue::LuaNumber(v) => {
                 // this is needed or every number is detected as float
                 LuaJsonValue::Number(if v % 1f64 == 0f64 {
-                    (v as u64).into()
+                    if v < 0f64 {
+                        (v as i64).into()
+                    } else {
+                        (v as u64).into()
+                    }
                 } else {
                     Number::from_f64(v).expect("invalid LuaJson::Number")
                 })
@@ -152,3 +374,105 @@ impl From<serde_json::Value> for LuaJsonValue {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(x: &str) -> Value {
+        serde_json::from_str(x).unwrap()
+    }
+
+    #[test]
+    fn test_merge_nested() {
+        let x = merge(json(r#"{"a":{"b":1,"c":[1]},"d":1}"#),
+                      json(r#"{"a":{"c":[2],"e":3}}"#),
+                      ArrayMerge::Replace);
+        assert_eq!(x, json(r#"{"a":{"b":1,"c":[2],"e":3},"d":1}"#));
+    }
+
+    #[test]
+    fn test_merge_concat() {
+        let x = merge(json(r#"{"a":{"c":[1]}}"#),
+                      json(r#"{"a":{"c":[2]}}"#),
+                      ArrayMerge::Concat);
+        assert_eq!(x, json(r#"{"a":{"c":[1,2]}}"#));
+    }
+
+    #[test]
+    fn test_diff_nested() {
+        let old = json(r#"{"a":1,"b":{"c":[1,2],"d":"x","e":true},"f":null}"#);
+        let new = json(r#"{"a":1,"b":{"c":[1,3,4],"d":"y"},"g":2}"#);
+        let diff = diff(old, new, ArrayDiff::Ordered);
+
+        assert_eq!(Value::Object(diff.added), json(r#"{"b.c.3":4,"g":2}"#));
+        assert_eq!(Value::Object(diff.removed), json(r#"{"b.e":true,"f":null}"#));
+        assert_eq!(Value::Object(diff.changed), json(r#"{"b.c.2":{"old":2,"new":3},"b.d":{"old":"x","new":"y"}}"#));
+    }
+
+    #[test]
+    fn test_diff_unordered() {
+        let old = json(r#"{"ports":[80,443,8080]}"#);
+        let new = json(r#"{"ports":[443,22,80]}"#);
+        let diff = diff(old, new, ArrayDiff::Unordered);
+
+        assert_eq!(Value::Object(diff.added), json(r#"{"ports.2":22}"#));
+        assert_eq!(Value::Object(diff.removed), json(r#"{"ports.3":8080}"#));
+        assert!(diff.changed.is_empty());
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        // examples from RFC 7386, appendix A
+        let x = merge_patch(json(r#"{"a":"b","c":{"d":"e","f":"g"}}"#),
+                            json(r#"{"a":"z","c":{"f":null}}"#));
+        assert_eq!(x, json(r#"{"a":"z","c":{"d":"e"}}"#));
+
+        let x = merge_patch(json(r#"{"a":[{"b":"c"}]}"#),
+                            json(r#"{"a":[1]}"#));
+        assert_eq!(x, json(r#"{"a":[1]}"#));
+
+        let x = merge_patch(json(r#"["a","b"]"#),
+                            json(r#"{"a":"b","c":null}"#));
+        assert_eq!(x, json(r#"{"a":"b"}"#));
+
+        let x = merge_patch(json(r#"{}"#),
+                            json(r#"{"a":{"bb":{"ccc":null}}}"#));
+        assert_eq!(x, json(r#"{"a":{"bb":{}}}"#));
+    }
+
+    #[test]
+    fn test_canonical_key_order() {
+        let a = json(r#"{"b":1,"a":{"d":[3,{"z":1,"y":2}],"c":null}}"#);
+        let b = json(r#"{ "a": { "c": null, "d": [3, {"y": 2, "z": 1}] }, "b": 1 }"#);
+        assert_eq!(canonical(&a).unwrap(), r#"{"a":{"c":null,"d":[3,{"y":2,"z":1}]},"b":1}"#);
+        assert_eq!(canonical(&a).unwrap(), canonical(&b).unwrap());
+    }
+
+    #[test]
+    fn test_canonical_numbers() {
+        // examples from RFC 8785, appendix B
+        for (f, expected) in &[
+            (0f64, "0"),
+            (-0f64, "0"),
+            (1e21, "1e+21"),
+            (1e20, "100000000000000000000"),
+            (9007199254740992f64, "9007199254740992"),
+            (0.000001, "0.000001"),
+            (1e-7, "1e-7"),
+            (-1.5e-7, "-1.5e-7"),
+            (4.5, "4.5"),
+            (333333333.3333333, "333333333.3333333"),
+        ] {
+            assert_eq!(canonical_number(*f).unwrap(), *expected, "{:?}", f);
+        }
+        assert!(canonical_number(1e308 * 10f64).is_err());
+        assert_eq!(canonical(&json("[1,-2,1.5]")).unwrap(), "[1,-2,1.5]");
+    }
+
+    #[test]
+    fn test_canonical_strings() {
+        let x = json(r#"{"\u20ac":"Euro Sign","\r":"Carriage Return","1":"One","\ud83d\ude00":"Emoji","\u0080":"Control","\u00f6":"Latin Small Letter O With Diaeresis","a\u000fb":"\u001f"}"#);
+        assert_eq!(canonical(&x).unwrap(), "{\"\\r\":\"Carriage Return\",\"1\":\"One\",\"a\\u000fb\":\"\\u001f\",\"\u{80}\":\"Control\",\"ö\":\"Latin Small Letter O With Diaeresis\",\"€\":\"Euro Sign\",\"😀\":\"Emoji\"}");
+    }
+}
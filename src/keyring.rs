@@ -1,5 +1,6 @@
 use crate::errors::*;
 
+use crate::db::encryption;
 use crate::engine::Module;
 use crate::hlua::AnyLuaValue;
 use crate::json::LuaJsonValue;
@@ -59,6 +60,11 @@ impl FromStr for KeyName {
     }
 }
 
+/// Namespaces that are used by sn0int itself and never handed to modules
+fn is_reserved(namespace: &str) -> bool {
+    namespace == encryption::KEYRING_NAMESPACE
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct KeyRing {
     keys: HashMap<String, HashMap<String, Option<String>>>,
@@ -160,6 +166,7 @@ impl KeyRing {
 
     pub fn unauthorized_namespaces<'a>(&self, module: &'a Module) -> Vec<&'a String> {
         module.keyring_access().iter()
+            .filter(|namespace| !is_reserved(namespace))
             .filter(|namespace| !self.is_access_granted(&module, &namespace))
             .collect()
     }
@@ -182,6 +189,7 @@ impl KeyRing {
     pub fn request_keys(&self, module: &Module) -> Vec<KeyRingEntry> {
         // TODO: we probably want to randomize the order
         module.keyring_access().iter()
+            .filter(|namespace| !is_reserved(namespace))
             .filter(|namespace| self.is_access_granted(&module, &namespace))
             .flat_map(|namespace| self.list_for(namespace))
             .flat_map(|x| self.get(&x))
@@ -18,33 +18,64 @@ pub mod api;
 pub mod archive;
 pub mod args;
 pub mod auth;
+pub mod axfr;
+pub mod bogons;
 pub mod cmd;
 pub mod complete;
 pub mod config;
+pub mod connector;
+pub mod cookie;
 pub mod crt;
+pub mod cryptoaddr;
 pub mod db;
+pub mod email;
 pub mod errors;
 pub mod engine;
+#[cfg(unix)]
+pub mod fifo;
+#[cfg(not(unix))]
+#[path="fifo_unsupported.rs"]
+pub mod fifo;
+pub mod filesystem;
 pub mod fmt;
+pub mod frontier;
 pub mod geoip;
 pub mod html;
+pub mod import;
+pub mod jq;
 pub mod json;
+pub mod jwt;
 pub mod keyring;
+pub mod link_header;
 pub mod migrations;
 pub mod models;
+pub mod multipart;
 pub mod paths;
+pub mod permute;
+pub mod proxy;
 pub mod psl;
 pub mod options;
 pub mod registry;
+pub mod robots;
 pub mod runtime;
+pub mod scan;
 pub mod sandbox;
+pub mod sarif;
 pub mod schema;
+pub mod scope_templates;
 pub mod ser;
 pub mod shell;
+pub mod sigv4;
+pub mod sitemap;
+pub mod smtp;
 pub mod sockets;
+pub mod targets;
 pub mod term;
 pub mod update;
+pub mod url_canonical;
 pub mod utils;
 pub mod web;
 pub mod worker;
 pub mod workspaces;
+pub mod xml;
+pub mod yaml;
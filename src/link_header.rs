@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+
+
+/// A link from an RFC 5988 Link header
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Link {
+    pub url: String,
+    pub params: BTreeMap<String, String>,
+}
+
+/// Split on `sep`, but not inside of `<...>` or quoted strings
+fn split(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut in_url = false;
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if in_quotes => escaped = true,
+            '"' if !in_url => in_quotes = !in_quotes,
+            '<' if !in_quotes => in_url = true,
+            '>' if !in_quotes => in_url = false,
+            c if c == sep && !in_url && !in_quotes => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            },
+            _ => (),
+        }
+    }
+    parts.push(&s[start..]);
+
+    parts
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        let mut out = String::new();
+        let mut chars = value[1..value.len()-1].chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => out.extend(chars.next()),
+                c => out.push(c),
+            }
+        }
+        out
+    } else {
+        value.to_string()
+    }
+}
+
+/// Parse a single link, eg. `<https://example.com/?page=2>; rel="next"`.
+/// Returns None if the link is malformed.
+fn parse_link(link: &str) -> Option<(Vec<String>, Link)> {
+    let link = link.trim();
+    if !link.starts_with('<') {
+        return None;
+    }
+    let end = link.find('>')?;
+
+    let mut rels = Vec::new();
+    let mut params = BTreeMap::new();
+
+    let url = link[1..end].trim().to_string();
+    let mut attrs = split(&link[end+1..], ';').into_iter();
+
+    // only whitespace is allowed between the url and the first param
+    if !attrs.next()?.trim().is_empty() {
+        return None;
+    }
+
+    for attr in attrs {
+        let (key, value) = match attr.find('=') {
+            Some(idx) => (attr[..idx].trim(), unquote(&attr[idx+1..])),
+            None => (attr.trim(), String::new()),
+        };
+        if key.is_empty() {
+            continue;
+        }
+        let key = key.to_lowercase();
+
+        if key == "rel" {
+            // only the first rel counts if it's repeated
+            if rels.is_empty() {
+                rels = value.split_whitespace()
+                    .map(|rel| rel.to_lowercase())
+                    .collect();
+            }
+        } else {
+            params.entry(key).or_insert(value);
+        }
+    }
+
+    Some((rels, Link {
+        url,
+        params,
+    }))
+}
+
+/// Parse Link headers into a map of links by their relation type. A header may
+/// contain multiple links, a link with multiple relation types is added for
+/// each of them. Links that are malformed or don't have a relation type are
+/// ignored, if a relation type is used more than once the first link is kept.
+pub fn parse<I: AsRef<str>>(headers: &[I]) -> BTreeMap<String, Link> {
+    let mut links = BTreeMap::new();
+
+    for header in headers {
+        for line in header.as_ref().lines() {
+            for link in split(line, ',') {
+                if let Some((rels, link)) = parse_link(link) {
+                    for rel in rels {
+                        links.entry(rel).or_insert_with(|| link.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(url: &str, params: &[(&str, &str)]) -> Link {
+        Link {
+            url: url.to_string(),
+            params: params.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse_github() {
+        let links = parse(&[r#"<https://api.github.com/user/repos?page=3&per_page=100>; rel="next", <https://api.github.com/user/repos?page=50&per_page=100>; rel="last""#]);
+        assert_eq!(links.len(), 2);
+        assert_eq!(links["next"], link("https://api.github.com/user/repos?page=3&per_page=100", &[]));
+        assert_eq!(links["last"], link("https://api.github.com/user/repos?page=50&per_page=100", &[]));
+    }
+
+    #[test]
+    fn test_parse_params() {
+        let links = parse(&[r#"<https://example.com/a,b;c>; REL=Prev; title="Page 1, \"intro\""; type=text/html"#]);
+        assert_eq!(links["prev"], link("https://example.com/a,b;c", &[
+            ("title", "Page 1, \"intro\""),
+            ("type", "text/html"),
+        ]));
+    }
+
+    #[test]
+    fn test_parse_multiple_rels() {
+        let links = parse(&[r#"<https://example.com/2>; rel="next last""#]);
+        assert_eq!(links["next"], links["last"]);
+    }
+
+    #[test]
+    fn test_parse_multiple_headers() {
+        let links = parse(&[
+            "<https://example.com/1>; rel=first",
+            "<https://example.com/3>; rel=next\n<https://example.com/9>; rel=last",
+            "<https://example.com/4>; rel=next",
+        ]);
+        assert_eq!(links.keys().collect::<Vec<_>>(), vec!["first", "last", "next"]);
+        assert_eq!(links["next"].url, "https://example.com/3");
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        assert!(parse::<&str>(&[]).is_empty());
+        assert!(parse(&[""]).is_empty());
+        assert!(parse(&["https://example.com/; rel=next"]).is_empty());
+        assert!(parse(&["<https://example.com/; rel=next"]).is_empty());
+        assert!(parse(&["<https://example.com/> garbage; rel=next"]).is_empty());
+        assert!(parse(&["<https://example.com/>; title=nothing"]).is_empty());
+
+        let links = parse(&["garbage, <https://example.com/2>; rel=next"]);
+        assert_eq!(links.keys().collect::<Vec<_>>(), vec!["next"]);
+    }
+}
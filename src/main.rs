@@ -16,6 +16,7 @@ use sn0int::psl::Psl;
 use sn0int::registry;
 use sn0int::sandbox;
 use sn0int::shell;
+use sn0int::term;
 use structopt::StructOpt;
 use std::fs::OpenOptions;
 use std::io::Write;
@@ -85,6 +86,8 @@ fn run() -> Result<()> {
         sandbox::fasten_seatbelt()?;
     }
 
+    term::init();
+
     let config = Config::load_or_default()
         .context("Failed to load config")?;
 
@@ -93,12 +96,16 @@ fn run() -> Result<()> {
     match args.subcommand {
         Some(SubCommand::Run(ref run)) => run_run(&args, run, &config),
         Some(SubCommand::Sandbox(_)) => run_sandbox(),
-        Some(SubCommand::Login(_)) => auth::run_login(&config),
+        Some(SubCommand::Login(ref login)) => auth::run_login(login, &config),
+        Some(SubCommand::Logout(_)) => auth::run_logout(&config),
         Some(SubCommand::New(ref new)) => run_new(&args, new),
         Some(SubCommand::Publish(ref publish)) => registry::run_publish(&args, publish, &config),
         Some(SubCommand::Install(ref install)) => registry::run_install(install, &config),
         Some(SubCommand::Search(ref search)) => registry::run_search(search, &config),
         Some(SubCommand::Select(ref select)) => run_cmd(&args, select, &config),
+        Some(SubCommand::Watch(ref watch)) => run_cmd(&args, watch, &config),
+        Some(SubCommand::Findings(ref findings)) => run_cmd(&args, findings, &config),
+        Some(SubCommand::Import(ref import)) => run_cmd(&args, import, &config),
         Some(SubCommand::Completions(ref completions)) => complete::run_generate(completions),
         None => shell::run(&args, &config),
     }
@@ -1,11 +1,127 @@
 #![allow(unused_imports)]
 use crate::errors::*;
 
+use crate::schema::urls;
+use crate::url_canonical;
+use diesel::prelude::*;
+use diesel::sql_types::Text;
 use diesel::sqlite::*;
 
 embed_migrations!();
 
 pub fn run(conn: &SqliteConnection) -> Result<()> {
     embedded_migrations::run(conn)?;
+    run_data_migrations(conn)?;
     Ok(())
 }
+
+#[derive(QueryableByName)]
+struct DataMigration {
+    #[sql_type="Text"]
+    name: String,
+}
+
+/// Run the data migrations that have been queued by an sql migration
+fn run_data_migrations(conn: &SqliteConnection) -> Result<()> {
+    let pending = diesel::sql_query("SELECT name FROM data_migrations")
+        .load::<DataMigration>(conn)?;
+
+    for migration in pending {
+        conn.transaction::<_, Error, _>(|| {
+            match migration.name.as_str() {
+                "canonical_urls" => canonical_urls(conn)?,
+                name => bail!("Unknown data migration: {:?}", name),
+            }
+            diesel::sql_query("DELETE FROM data_migrations WHERE name = ?")
+                .bind::<Text, _>(&migration.name)
+                .execute(conn)?;
+            Ok(())
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Canonicalize urls that have been added before urls got canonicalized
+/// on insert. If the canonical url is already known the duplicate is removed.
+fn canonical_urls(conn: &SqliteConnection) -> Result<()> {
+    let rows = urls::table
+        .select((urls::id, urls::value))
+        .load::<(i32, String)>(conn)?;
+
+    for (id, value) in rows {
+        let canonical = match url_canonical::canonical(&value, false) {
+            Ok(canonical) => canonical,
+            // keep urls we can't parse as they are
+            Err(_) => continue,
+        };
+        if canonical == value {
+            continue;
+        }
+
+        let existing = urls::table
+            .filter(urls::value.eq(&canonical))
+            .select(urls::id)
+            .first::<i32>(conn)
+            .optional()?;
+
+        if existing.is_some() {
+            diesel::delete(urls::table.find(id))
+                .execute(conn)?;
+        } else {
+            let path = url::Url::parse(&canonical)?.path().to_string();
+            diesel::update(urls::table.find(id))
+                .set((urls::value.eq(&canonical), urls::path.eq(path)))
+                .execute(conn)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+
+    fn insert_raw(conn: &SqliteConnection, value: &str, path: &str) {
+        diesel::sql_query("INSERT INTO urls (subdomain_id, value, path, unscoped) VALUES (1, ?, ?, 0)")
+            .bind::<Text, _>(value)
+            .bind::<Text, _>(path)
+            .execute(conn)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_canonical_urls() {
+        let db = Database::in_memory().unwrap();
+        let conn = db.db();
+        conn.execute("INSERT INTO domains (value, unscoped) VALUES ('example.com', 0)").unwrap();
+        conn.execute("INSERT INTO subdomains (domain_id, value, unscoped) VALUES (1, 'www.example.com', 0)").unwrap();
+
+        insert_raw(conn, "https://www.example.com/a", "/a");
+        insert_raw(conn, "HTTPS://WWW.example.com:443/a", "/a");
+        insert_raw(conn, "HTTPS://www.example.com/x/../b", "/x/../b");
+
+        canonical_urls(conn).unwrap();
+
+        let urls = urls::table
+            .select((urls::value, urls::path))
+            .order_by(urls::value)
+            .load::<(String, String)>(conn)
+            .unwrap();
+        assert_eq!(urls, vec![
+            ("https://www.example.com/a".to_string(), "/a".to_string()),
+            ("https://www.example.com/b".to_string(), "/b".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_data_migrations_run_once() {
+        let db = Database::in_memory().unwrap();
+        let pending = diesel::sql_query("SELECT name FROM data_migrations")
+            .load::<DataMigration>(db.db())
+            .unwrap();
+        assert!(pending.is_empty());
+    }
+}
@@ -23,6 +23,10 @@ pub struct Account {
 impl Model for Account {
     type ID = str;
 
+    fn table() -> &'static str {
+        "accounts"
+    }
+
     fn to_string(&self) -> String {
         self.value.to_owned()
     }
@@ -38,7 +42,8 @@ impl Model for Account {
     fn filter(db: &Database, filter: &Filter) -> Result<Vec<Self>> {
         use crate::schema::accounts::dsl::*;
 
-        let query = accounts.filter(filter.sql());
+        let query = accounts.filter(filter.sql())
+            .order(filter.order_sql());
         let results = query.load::<Self>(db.db())?;
 
         Ok(results)
@@ -49,7 +54,8 @@ impl Model for Account {
 
         let query = accounts
             .filter(service.eq(param))
-            .filter(filter.sql());
+            .filter(filter.sql())
+            .order(filter.order_sql());
         let results = query.load::<Self>(db.db())?;
 
         Ok(results)
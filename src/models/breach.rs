@@ -17,6 +17,10 @@ pub struct Breach {
 impl Model for Breach {
     type ID = str;
 
+    fn table() -> &'static str {
+        "breaches"
+    }
+
     fn to_string(&self) -> String {
         self.value.to_owned()
     }
@@ -32,7 +36,8 @@ impl Model for Breach {
     fn filter(db: &Database, filter: &Filter) -> Result<Vec<Self>> {
         use crate::schema::breaches::dsl::*;
 
-        let query = breaches.filter(filter.sql());
+        let query = breaches.filter(filter.sql())
+            .order(filter.order_sql());
         let results = query.load::<Self>(db.db())?;
 
         Ok(results)
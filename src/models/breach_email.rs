@@ -18,6 +18,10 @@ pub struct BreachEmail {
 impl Model for BreachEmail {
     type ID = (i32, i32, Option<String>);
 
+    fn table() -> &'static str {
+        "breach_emails"
+    }
+
     fn to_string(&self) -> String {
         unimplemented!("BreachEmail can not be printed")
     }
@@ -33,7 +37,8 @@ impl Model for BreachEmail {
     fn filter(db: &Database, filter: &Filter) -> Result<Vec<Self>> {
         use crate::schema::breach_emails::dsl::*;
 
-        let query = breach_emails.filter(filter.sql());
+        let query = breach_emails.filter(filter.sql())
+            .order(filter.order_sql());
         let results = query.load::<Self>(db.db())?;
 
         Ok(results)
@@ -0,0 +1,305 @@
+use crate::errors::*;
+use crate::cryptoaddr;
+use crate::fmt::colors::*;
+use crate::models::*;
+use diesel;
+use diesel::prelude::*;
+
+
+#[derive(Identifiable, Queryable, Serialize, Deserialize, PartialEq, Debug)]
+#[table_name="cryptoaddrs"]
+pub struct CryptoAddr {
+    pub id: i32,
+    pub value: String,
+    pub currency: String,
+    pub label: Option<String>,
+    pub unscoped: bool,
+}
+
+impl Model for CryptoAddr {
+    type ID = str;
+
+    fn table() -> &'static str {
+        "cryptoaddrs"
+    }
+
+    fn to_string(&self) -> String {
+        self.value.to_owned()
+    }
+
+    fn list(db: &Database) -> Result<Vec<Self>> {
+        use crate::schema::cryptoaddrs::dsl::*;
+
+        let results = cryptoaddrs.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
+    fn filter(db: &Database, filter: &Filter) -> Result<Vec<Self>> {
+        use crate::schema::cryptoaddrs::dsl::*;
+
+        let query = cryptoaddrs.filter(filter.sql())
+            .order(filter.order_sql());
+        let results = query.load::<Self>(db.db())?;
+
+        Ok(results)
+    }
+
+    fn delete(db: &Database, filter: &Filter) -> Result<usize> {
+        use crate::schema::cryptoaddrs::dsl::*;
+
+        diesel::delete(cryptoaddrs.filter(filter.sql()))
+            .execute(db.db())
+            .map_err(Error::from)
+    }
+
+    fn delete_id(db: &Database, my_id: i32) -> Result<usize> {
+        use crate::schema::cryptoaddrs::dsl::*;
+
+        diesel::delete(cryptoaddrs.filter(id.eq(my_id)))
+            .execute(db.db())
+            .map_err(Error::from)
+    }
+
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn value(&self) -> &Self::ID {
+        &self.value
+    }
+
+    fn by_id(db: &Database, my_id: i32) -> Result<Self> {
+        use crate::schema::cryptoaddrs::dsl::*;
+
+        let cryptoaddr = cryptoaddrs.filter(id.eq(my_id))
+            .first::<Self>(db.db())?;
+
+        Ok(cryptoaddr)
+    }
+
+    fn get(db: &Database, query: &Self::ID) -> Result<Self> {
+        use crate::schema::cryptoaddrs::dsl::*;
+
+        let cryptoaddr = cryptoaddrs.filter(value.eq(query))
+            .first::<Self>(db.db())?;
+
+        Ok(cryptoaddr)
+    }
+
+    fn get_opt(db: &Database, query: &Self::ID) -> Result<Option<Self>> {
+        use crate::schema::cryptoaddrs::dsl::*;
+
+        let cryptoaddr = cryptoaddrs.filter(value.eq(query))
+            .first::<Self>(db.db())
+            .optional()?;
+
+        Ok(cryptoaddr)
+    }
+}
+
+impl CryptoAddr {
+    pub fn get_with_currency(db: &Database, my_currency: &str, query: &str) -> Result<Self> {
+        use crate::schema::cryptoaddrs::dsl::*;
+
+        let cryptoaddr = cryptoaddrs.filter(currency.eq(my_currency))
+            .filter(value.eq(query))
+            .first::<Self>(db.db())?;
+
+        Ok(cryptoaddr)
+    }
+
+    pub fn get_with_currency_opt(db: &Database, my_currency: &str, query: &str) -> Result<Option<Self>> {
+        use crate::schema::cryptoaddrs::dsl::*;
+
+        let cryptoaddr = cryptoaddrs.filter(currency.eq(my_currency))
+            .filter(value.eq(query))
+            .first::<Self>(db.db())
+            .optional()?;
+
+        Ok(cryptoaddr)
+    }
+}
+
+impl Scopable for CryptoAddr {
+    fn scoped(&self) -> bool {
+        !self.unscoped
+    }
+
+    fn scope(db: &Database, filter: &Filter) -> Result<usize> {
+        use crate::schema::cryptoaddrs::dsl::*;
+
+        diesel::update(cryptoaddrs.filter(filter.sql()))
+            .set(unscoped.eq(false))
+            .execute(db.db())
+            .map_err(Error::from)
+    }
+
+    fn noscope(db: &Database, filter: &Filter) -> Result<usize> {
+        use crate::schema::cryptoaddrs::dsl::*;
+
+        diesel::update(cryptoaddrs.filter(filter.sql()))
+            .set(unscoped.eq(true))
+            .execute(db.db())
+            .map_err(Error::from)
+    }
+}
+
+pub struct PrintableCryptoAddr {
+    value: String,
+    currency: String,
+}
+
+impl fmt::Display for PrintableCryptoAddr {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "{:?} ({})", self.value, self.currency)
+    }
+}
+
+impl Printable<PrintableCryptoAddr> for CryptoAddr {
+    fn printable(&self, _db: &Database) -> Result<PrintableCryptoAddr> {
+        Ok(PrintableCryptoAddr {
+            value: self.value.to_string(),
+            currency: self.currency.to_string(),
+        })
+    }
+}
+
+pub struct DetailedCryptoAddr {
+    id: i32,
+    value: String,
+    currency: String,
+    label: Option<String>,
+    unscoped: bool,
+}
+
+impl DisplayableDetailed for DetailedCryptoAddr {
+    #[inline]
+    fn scoped(&self) -> bool {
+        !self.unscoped
+    }
+
+    #[inline]
+    fn print(&self, w: &mut fmt::DetailFormatter) -> fmt::Result {
+        w.id(self.id)?;
+        w.debug::<Green, _>(&self.value)?;
+
+        w.start_group();
+        w.display::<Yellow, _>(&self.currency)?;
+        w.opt_debug::<Yellow, _>(&self.label)?;
+        w.end_group()?;
+
+        Ok(())
+    }
+
+    #[inline]
+    fn children(&self, _w: &mut fmt::DetailFormatter) -> fmt::Result {
+        Ok(())
+    }
+}
+
+display_detailed!(DetailedCryptoAddr);
+
+impl Detailed for CryptoAddr {
+    type T = DetailedCryptoAddr;
+
+    fn detailed(&self, _db: &Database) -> Result<Self::T> {
+        Ok(DetailedCryptoAddr {
+            id: self.id,
+            value: self.value.to_string(),
+            currency: self.currency.to_string(),
+            label: self.label.clone(),
+            unscoped: self.unscoped,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
+#[table_name="cryptoaddrs"]
+pub struct NewCryptoAddr {
+    pub value: String,
+    pub currency: String,
+    pub label: Option<String>,
+}
+
+impl InsertableStruct<CryptoAddr> for NewCryptoAddr {
+    fn value(&self) -> &str {
+        &self.value
+    }
+
+    fn insert(&self, db: &Database) -> Result<()> {
+        diesel::insert_into(cryptoaddrs::table)
+            .values(self)
+            .execute(db.db())?;
+        Ok(())
+    }
+}
+
+impl Upsertable<CryptoAddr> for NewCryptoAddr {
+    type Update = CryptoAddrUpdate;
+
+    fn upsert(self, existing: &CryptoAddr) -> Self::Update {
+        Self::Update {
+            id: existing.id,
+            label: Self::upsert_opt(self.label, &existing.label),
+        }
+    }
+}
+
+impl Printable<PrintableCryptoAddr> for NewCryptoAddr {
+    fn printable(&self, _db: &Database) -> Result<PrintableCryptoAddr> {
+        Ok(PrintableCryptoAddr {
+            value: self.value.to_string(),
+            currency: self.currency.to_string(),
+        })
+    }
+}
+
+pub type InsertCryptoAddr = NewCryptoAddr;
+
+/// Addresses are normalized, so the same address is only added once
+impl LuaInsertToNew for InsertCryptoAddr {
+    type Target = NewCryptoAddr;
+
+    fn try_into_new(self) -> Result<NewCryptoAddr> {
+        let currency = cryptoaddr::currency(&self.currency)?;
+        let value = cryptoaddr::normalize(&currency, &self.value)?;
+
+        Ok(NewCryptoAddr {
+            value,
+            currency,
+            label: self.label,
+        })
+    }
+}
+
+#[derive(Identifiable, AsChangeset, Serialize, Deserialize, Debug)]
+#[table_name="cryptoaddrs"]
+pub struct CryptoAddrUpdate {
+    pub id: i32,
+    pub label: Option<String>,
+}
+
+impl Upsert for CryptoAddrUpdate {
+    fn is_dirty(&self) -> bool {
+        self.label.is_some()
+    }
+
+    fn generic(self) -> Update {
+        Update::CryptoAddr(self)
+    }
+
+    fn apply(&self, db: &Database) -> Result<i32> {
+        db.update_cryptoaddr(self)
+    }
+}
+
+impl Updateable<CryptoAddr> for CryptoAddrUpdate {
+    fn changeset(&mut self, existing: &CryptoAddr) {
+        Self::clear_if_equal(&mut self.label, &existing.label);
+    }
+
+    fn fmt(&self, updates: &mut Vec<String>) {
+        Self::push_value(updates, "label", &self.label);
+    }
+}
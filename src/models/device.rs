@@ -21,6 +21,10 @@ pub struct Device {
 impl Model for Device {
     type ID = str;
 
+    fn table() -> &'static str {
+        "devices"
+    }
+
     fn to_string(&self) -> String {
         self.value.to_owned()
     }
@@ -36,7 +40,8 @@ impl Model for Device {
     fn filter(db: &Database, filter: &Filter) -> Result<Vec<Self>> {
         use crate::schema::devices::dsl::*;
 
-        let query = devices.filter(filter.sql());
+        let query = devices.filter(filter.sql())
+            .order(filter.order_sql());
         let results = query.load::<Self>(db.db())?;
 
         Ok(results)
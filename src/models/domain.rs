@@ -16,6 +16,10 @@ pub struct Domain {
 impl Model for Domain {
     type ID = str;
 
+    fn table() -> &'static str {
+        "domains"
+    }
+
     fn to_string(&self) -> String {
         self.value.to_owned()
     }
@@ -31,7 +35,8 @@ impl Model for Domain {
     fn filter(db: &Database, filter: &Filter) -> Result<Vec<Self>> {
         use crate::schema::domains::dsl::*;
 
-        let query = domains.filter(filter.sql());
+        let query = domains.filter(filter.sql())
+            .order(filter.order_sql());
         let results = query.load::<Self>(db.db())?;
 
         Ok(results)
@@ -1,4 +1,5 @@
 use crate::errors::*;
+use crate::email;
 use crate::fmt::Write;
 use crate::fmt::colors::*;
 use diesel;
@@ -18,6 +19,10 @@ pub struct Email {
 impl Model for Email {
     type ID = str;
 
+    fn table() -> &'static str {
+        "emails"
+    }
+
     fn to_string(&self) -> String {
         self.value.to_owned()
     }
@@ -33,7 +38,8 @@ impl Model for Email {
     fn filter(db: &Database, filter: &Filter) -> Result<Vec<Self>> {
         use crate::schema::emails::dsl::*;
 
-        let query = emails.filter(filter.sql());
+        let query = emails.filter(filter.sql())
+            .order(filter.order_sql());
         let results = query.load::<Self>(db.db())?;
 
         Ok(results)
@@ -278,7 +284,12 @@ impl LuaInsertToNew for InsertEmail {
     type Target = NewEmail;
 
     fn try_into_new(self) -> Result<NewEmail> {
-        Ok(self)
+        let value = email::normalize(&self.value)?;
+
+        Ok(NewEmail {
+            value,
+            valid: self.valid,
+        })
     }
 }
 
@@ -31,6 +31,10 @@ pub struct IpAddr {
 impl Model for IpAddr {
     type ID = str;
 
+    fn table() -> &'static str {
+        "ipaddrs"
+    }
+
     fn to_string(&self) -> String {
         self.value.to_owned()
     }
@@ -46,7 +50,8 @@ impl Model for IpAddr {
     fn filter(db: &Database, filter: &Filter) -> Result<Vec<Self>> {
         use crate::schema::ipaddrs::dsl::*;
 
-        let query = ipaddrs.filter(filter.sql());
+        let query = ipaddrs.filter(filter.sql())
+            .order(filter.order_sql());
         let results = query.load::<Self>(db.db())?;
 
         Ok(results)
@@ -143,6 +148,12 @@ impl IpAddr {
             .collect::<result::Result<_, _>>()
             .map_err(Error::from)
     }
+
+    fn ports(&self, db: &Database) -> Result<Vec<Port>> {
+        Port::belonging_to(self)
+            .load::<Port>(db.db())
+            .map_err(Error::from)
+    }
 }
 
 pub struct PrintableIpAddr {
@@ -167,6 +178,7 @@ pub struct DetailedIpAddr {
     id: i32,
     value: net::IpAddr,
     subdomains: Vec<PrintableSubdomain>,
+    ports: Vec<PrintablePort>,
     unscoped: bool,
     continent: Option<String>,
     country: Option<String>,
@@ -225,6 +237,9 @@ impl DisplayableDetailed for DetailedIpAddr {
         for subdomain in &self.subdomains {
             w.child(subdomain)?;
         }
+        for port in &self.ports {
+            w.child(port)?;
+        }
         Ok(())
     }
 }
@@ -239,10 +254,15 @@ impl Detailed for IpAddr {
             .map(|x| x.printable(db))
             .collect::<Result<_>>()?;
 
+        let ports = self.ports(db)?.into_iter()
+            .map(|x| x.printable(db))
+            .collect::<Result<_>>()?;
+
         Ok(DetailedIpAddr {
             id: self.id,
             value: self.value.parse()?,
             subdomains,
+            ports,
             unscoped: self.unscoped,
             continent: self.continent.clone(),
             country: self.country.clone(),
@@ -1,7 +1,8 @@
 use crate::errors::*;
-use crate::db::{Database, Filter};
+use crate::db::{Database, Family, Filter};
 use crate::fmt;
 use crate::schema::*;
+use crate::term::{Level, TERM_SETTINGS};
 
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,6 +20,8 @@ pub enum Insert {
     Account(NewAccount),
     Breach(NewBreach),
     BreachEmail(NewBreachEmail),
+    Port(NewPort),
+    CryptoAddr(NewCryptoAddr),
 }
 
 impl Insert {
@@ -48,11 +51,33 @@ impl Insert {
                 let breach = Breach::by_id(db, x.breach_id)?;
                 let email = Email::by_id(db, x.email_id)?;
                 format!("{:?}+{:?}", breach.value, email.value)
-            }
+            },
+            Insert::Port(x) => format!("{:?}", x.value),
+            Insert::CryptoAddr(x) => format!("{:?}", x.value),
         };
         Ok(label)
     }
 
+    pub fn family(&self) -> Family {
+        match self {
+            Insert::Domain(_) => Family::Domain,
+            Insert::Subdomain(_) => Family::Subdomain,
+            Insert::IpAddr(_) => Family::IpAddr,
+            Insert::SubdomainIpAddr(_) => Family::SubdomainIpAddr,
+            Insert::Url(_) => Family::Url,
+            Insert::Email(_) => Family::Email,
+            Insert::PhoneNumber(_) => Family::PhoneNumber,
+            Insert::Device(_) => Family::Device,
+            Insert::Network(_) => Family::Network,
+            Insert::NetworkDevice(_) => Family::NetworkDevice,
+            Insert::Account(_) => Family::Account,
+            Insert::Breach(_) => Family::Breach,
+            Insert::BreachEmail(_) => Family::BreachEmail,
+            Insert::Port(_) => Family::Port,
+            Insert::CryptoAddr(_) => Family::CryptoAddr,
+        }
+    }
+
     pub fn table(&self) -> &str {
         match self {
             Insert::Domain(_) => "domains",
@@ -68,6 +93,8 @@ impl Insert {
             Insert::Account(_) => "accounts",
             Insert::Breach(_) => "breaches",
             Insert::BreachEmail(_) => "breach_emails",
+            Insert::Port(_) => "ports",
+            Insert::CryptoAddr(_) => "cryptoaddrs",
         }
     }
 
@@ -86,6 +113,8 @@ impl Insert {
             Insert::Account(x) => format!("Account: {}", x.printable(db)?),
             Insert::Breach(x) => format!("Breach: {}", x.printable(db)?),
             Insert::BreachEmail(x) => x.printable(db)?.to_string(),
+            Insert::Port(x) => format!("Port: {}", x.printable(db)?),
+            Insert::CryptoAddr(x) => format!("CryptoAddr: {}", x.printable(db)?),
         })
     }
 }
@@ -102,9 +131,28 @@ pub enum Update {
     NetworkDevice(NetworkDeviceUpdate),
     Account(AccountUpdate),
     BreachEmail(BreachEmailUpdate),
+    Port(PortUpdate),
+    CryptoAddr(CryptoAddrUpdate),
 }
 
 impl Update {
+    pub fn id(&self) -> i32 {
+        match self {
+            Update::Subdomain(update)     => update.id,
+            Update::IpAddr(update)        => update.id,
+            Update::Url(update)           => update.id,
+            Update::Email(update)         => update.id,
+            Update::PhoneNumber(update)   => update.id,
+            Update::Device(update)        => update.id,
+            Update::Network(update)       => update.id,
+            Update::NetworkDevice(update) => update.id,
+            Update::Account(update)       => update.id,
+            Update::BreachEmail(update)   => update.id,
+            Update::Port(update)          => update.id,
+            Update::CryptoAddr(update)    => update.id,
+        }
+    }
+
     pub fn is_dirty(&self) -> bool {
         match self {
             Update::Subdomain(update)     => update.is_dirty(),
@@ -117,6 +165,8 @@ impl Update {
             Update::NetworkDevice(update) => update.is_dirty(),
             Update::Account(update)       => update.is_dirty(),
             Update::BreachEmail(update)   => update.is_dirty(),
+            Update::Port(update)          => update.is_dirty(),
+            Update::CryptoAddr(update)    => update.is_dirty(),
         }
     }
 }
@@ -134,6 +184,8 @@ impl fmt::Display for Update {
             Update::NetworkDevice(update) => write!(w, "{}", update.to_string()),
             Update::Account(update)       => write!(w, "{}", update.to_string()),
             Update::BreachEmail(update)   => write!(w, "{}", update.to_string()),
+            Update::Port(update)          => write!(w, "{}", update.to_string()),
+            Update::CryptoAddr(update)    => write!(w, "{}", update.to_string()),
         }
     }
 }
@@ -141,6 +193,8 @@ impl fmt::Display for Update {
 pub trait Model: Sized {
     type ID: ?Sized;
 
+    fn table() -> &'static str;
+
     fn to_string(&self) -> String;
 
     fn list(db: &Database) -> Result<Vec<Self>>;
@@ -247,14 +301,14 @@ pub trait Updateable<M> {
     #[inline]
     fn push_value<D: fmt::Debug>(updates: &mut Vec<String>, name: &str, value: &Option<D>) {
         if let Some(v) = value {
-            updates.push(format!("{} => \x1b[33m{:?}\x1b[0m", name, v));
+            updates.push(format!("{} => {}", name, TERM_SETTINGS.paint(Level::Warn, &format!("{:?}", v))));
         }
     }
 
     #[inline]
     fn push_raw<T: AsRef<str>>(updates: &mut Vec<String>, name: &str, value: Option<T>) {
         if let Some(v) = value {
-            updates.push(format!("{} => \x1b[33m{}\x1b[0m", name, v.as_ref()));
+            updates.push(format!("{} => {}", name, TERM_SETTINGS.paint(Level::Warn, v.as_ref())));
         }
     }
 
@@ -338,3 +392,9 @@ pub use self::breach::*;
 
 mod breach_email;
 pub use self::breach_email::*;
+
+mod port;
+pub use self::port::*;
+
+mod cryptoaddr;
+pub use self::cryptoaddr::*;
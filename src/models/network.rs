@@ -18,6 +18,10 @@ pub struct Network {
 impl Model for Network {
     type ID = str;
 
+    fn table() -> &'static str {
+        "networks"
+    }
+
     fn to_string(&self) -> String {
         self.value.to_owned()
     }
@@ -33,7 +37,8 @@ impl Model for Network {
     fn filter(db: &Database, filter: &Filter) -> Result<Vec<Self>> {
         use crate::schema::networks::dsl::*;
 
-        let query = networks.filter(filter.sql());
+        let query = networks.filter(filter.sql())
+            .order(filter.order_sql());
         let results = query.load::<Self>(db.db())?;
 
         Ok(results)
@@ -20,6 +20,10 @@ pub struct NetworkDevice {
 impl Model for NetworkDevice {
     type ID = (i32, i32);
 
+    fn table() -> &'static str {
+        "network_devices"
+    }
+
     fn to_string(&self) -> String {
         unimplemented!("NetworkDevice can not be printed")
     }
@@ -35,7 +39,8 @@ impl Model for NetworkDevice {
     fn filter(db: &Database, filter: &Filter) -> Result<Vec<Self>> {
         use crate::schema::network_devices::dsl::*;
 
-        let query = network_devices.filter(filter.sql());
+        let query = network_devices.filter(filter.sql())
+            .order(filter.order_sql());
         let results = query.load::<Self>(db.db())?;
 
         Ok(results)
@@ -27,6 +27,10 @@ pub struct PhoneNumber {
 impl Model for PhoneNumber {
     type ID = str;
 
+    fn table() -> &'static str {
+        "phonenumbers"
+    }
+
     fn to_string(&self) -> String {
         self.value.to_owned()
     }
@@ -42,7 +46,8 @@ impl Model for PhoneNumber {
     fn filter(db: &Database, filter: &Filter) -> Result<Vec<Self>> {
         use crate::schema::phonenumbers::dsl::*;
 
-        let query = phonenumbers.filter(filter.sql());
+        let query = phonenumbers.filter(filter.sql())
+            .order(filter.order_sql());
         let results = query.load::<Self>(db.db())?;
 
         Ok(results)
@@ -20,6 +20,10 @@ pub struct Subdomain {
 impl Model for Subdomain {
     type ID = str;
 
+    fn table() -> &'static str {
+        "subdomains"
+    }
+
     fn to_string(&self) -> String {
         self.value.to_owned()
     }
@@ -35,7 +39,8 @@ impl Model for Subdomain {
     fn filter(db: &Database, filter: &Filter) -> Result<Vec<Self>> {
         use crate::schema::subdomains::dsl::*;
 
-        let query = subdomains.filter(filter.sql());
+        let query = subdomains.filter(filter.sql())
+            .order(filter.order_sql());
         let results = query.load::<Self>(db.db())?;
 
         Ok(results)
@@ -18,6 +18,10 @@ pub struct SubdomainIpAddr {
 impl Model for SubdomainIpAddr {
     type ID = (i32, i32);
 
+    fn table() -> &'static str {
+        "subdomain_ipaddrs"
+    }
+
     fn to_string(&self) -> String {
         unimplemented!("SubdomainIpAddr can not be printed")
     }
@@ -33,7 +37,8 @@ impl Model for SubdomainIpAddr {
     fn filter(db: &Database, filter: &Filter) -> Result<Vec<Self>> {
         use crate::schema::subdomain_ipaddrs::dsl::*;
 
-        let query = subdomain_ipaddrs.filter(filter.sql());
+        let query = subdomain_ipaddrs.filter(filter.sql())
+            .order(filter.order_sql());
         let results = query.load::<Self>(db.db())?;
 
         Ok(results)
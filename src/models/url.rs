@@ -6,6 +6,7 @@ use diesel;
 use diesel::prelude::*;
 use crate::ser;
 use crate::url;
+use crate::url_canonical;
 
 
 #[derive(Identifiable, Queryable, Associations, Serialize, Deserialize, PartialEq, Debug)]
@@ -27,6 +28,10 @@ pub struct Url {
 impl Model for Url {
     type ID = str;
 
+    fn table() -> &'static str {
+        "urls"
+    }
+
     fn to_string(&self) -> String {
         self.value.to_owned()
     }
@@ -42,7 +47,8 @@ impl Model for Url {
     fn filter(db: &Database, filter: &Filter) -> Result<Vec<Self>> {
         use crate::schema::urls::dsl::*;
 
-        let query = urls.filter(filter.sql());
+        let query = urls.filter(filter.sql())
+            .order(filter.order_sql());
         let results = query.load::<Self>(db.db())?;
 
         Ok(results)
@@ -289,12 +295,14 @@ impl LuaInsertToNew for InsertUrl {
     type Target = NewUrl;
 
     fn try_into_new(self) -> Result<NewUrl> {
-        let url = url::Url::parse(&self.value)?;
+        // equivalent urls are stored as the same entity
+        let value = url_canonical::canonical(&self.value, false)?;
+        let url = url::Url::parse(&value)?;
         let path = url.path().to_string();
 
         Ok(NewUrl {
             subdomain_id: self.subdomain_id,
-            value: self.value,
+            value,
             path,
             status: self.status,
             body: self.body,
@@ -0,0 +1,275 @@
+use crate::errors::*;
+
+use std::collections::BTreeMap;
+
+
+/// Nested multiparts deeper than this are returned as regular parts
+const MAX_DEPTH: usize = 8;
+
+/// A single part of a multipart body
+#[derive(Debug, Default, PartialEq)]
+pub struct Part {
+    /// Header names are lowercase, repeated headers are joined with `, `
+    pub headers: BTreeMap<String, String>,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+    /// The parts of a nested multipart
+    pub parts: Vec<Part>,
+}
+
+/// Read the boundary parameter of a `multipart/*` content type
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let mime = params.next()?.trim().to_lowercase();
+    if !mime.starts_with("multipart/") {
+        return None;
+    }
+
+    params
+        .filter_map(|param| {
+            let idx = param.find('=')?;
+            let (key, value) = (param[..idx].trim(), param[idx+1..].trim());
+            if key.eq_ignore_ascii_case("boundary") {
+                Some(value.trim_matches('"').to_string())
+            } else {
+                None
+            }
+        })
+        .find(|boundary| !boundary.is_empty())
+}
+
+/// Guess the boundary from the first delimiter line, for bodies without a content type
+pub fn boundary_from_body(body: &[u8]) -> Option<String> {
+    body.split(|b| *b == b'\n')
+        .find(|line| line.starts_with(b"--"))
+        .map(|line| String::from_utf8_lossy(&line[2..]).trim_end().to_string())
+        .filter(|boundary| !boundary.is_empty())
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if from > haystack.len() {
+        return None;
+    }
+    haystack[from..].windows(needle.len())
+        .position(|window| window == needle)
+        .map(|idx| idx + from)
+}
+
+fn at_line_start(body: &[u8], idx: usize) -> bool {
+    idx == 0 || body[idx - 1] == b'\n'
+}
+
+/// A delimiter is followed by `--`, or by optional whitespace and the end of the line
+fn is_delimiter_end(rest: &[u8]) -> bool {
+    if rest.starts_with(b"--") {
+        return true;
+    }
+    rest.iter()
+        .find(|b| **b != b' ' && **b != b'\t')
+        .map(|b| *b == b'\r' || *b == b'\n')
+        .unwrap_or(true)
+}
+
+/// Find the next delimiter line
+fn find_delimiter(body: &[u8], delimiter: &[u8], from: usize) -> Option<usize> {
+    let mut from = from;
+    while let Some(idx) = find(body, delimiter, from) {
+        if at_line_start(body, idx) && is_delimiter_end(&body[idx + delimiter.len()..]) {
+            return Some(idx);
+        }
+        from = idx + 1;
+    }
+    None
+}
+
+/// Strip the line break in front of a delimiter, it belongs to the delimiter
+fn strip_line_break(body: &[u8]) -> &[u8] {
+    if body.ends_with(b"\r\n") {
+        &body[..body.len() - 2]
+    } else if body.ends_with(b"\n") {
+        &body[..body.len() - 1]
+    } else {
+        body
+    }
+}
+
+fn parse_headers(raw: &[u8]) -> BTreeMap<String, String> {
+    let raw = String::from_utf8_lossy(raw);
+    let mut headers = BTreeMap::<String, String>::new();
+    let mut last: Option<String> = None;
+
+    for line in raw.lines() {
+        // folded header, continues the previous line
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(value) = last.as_ref().and_then(|key| headers.get_mut(key)) {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+
+        let idx = match line.find(':') {
+            Some(idx) => idx,
+            None => continue,
+        };
+        let key = line[..idx].trim().to_lowercase();
+        let value = line[idx+1..].trim();
+
+        headers.entry(key.clone())
+            .and_modify(|x| {
+                x.push_str(", ");
+                x.push_str(value);
+            })
+            .or_insert_with(|| value.to_string());
+        last = Some(key);
+    }
+
+    headers
+}
+
+fn parse_part(raw: &[u8], depth: usize) -> Part {
+    // a part that starts with an empty line doesn't have headers
+    let (headers, body) = if raw.starts_with(b"\r\n") {
+        (BTreeMap::new(), &raw[2..])
+    } else if raw.starts_with(b"\n") {
+        (BTreeMap::new(), &raw[1..])
+    } else {
+        match (find(raw, b"\r\n\r\n", 0), find(raw, b"\n\n", 0)) {
+            (Some(a), Some(b)) if b < a => (parse_headers(&raw[..b]), &raw[b+2..]),
+            (Some(a), _) => (parse_headers(&raw[..a]), &raw[a+4..]),
+            (None, Some(b)) => (parse_headers(&raw[..b]), &raw[b+2..]),
+            // only headers, the body is empty
+            (None, None) => (parse_headers(raw), &raw[raw.len()..]),
+        }
+    };
+
+    let content_type = headers.get("content-type").cloned();
+    let parts = match content_type.as_ref().and_then(|x| boundary(x)) {
+        Some(ref boundary) if depth < MAX_DEPTH => parse_depth(body, boundary, depth + 1)
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    };
+
+    Part {
+        headers,
+        content_type,
+        body: body.to_vec(),
+        parts,
+    }
+}
+
+fn parse_depth(body: &[u8], boundary: &str, depth: usize) -> Result<Vec<Part>> {
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+
+    // everything before the first delimiter is preamble
+    let mut cursor = find_delimiter(body, delimiter, 0)
+        .ok_or_else(|| format_err!("Boundary not found in body"))?;
+
+    let mut parts = Vec::new();
+    loop {
+        cursor += delimiter.len();
+
+        // the close delimiter ends the body, the epilogue is ignored
+        if body[cursor..].starts_with(b"--") {
+            break;
+        }
+
+        // skip transport padding and the line break after the delimiter
+        let start = match body[cursor..].iter().position(|b| *b == b'\n') {
+            Some(idx) => cursor + idx + 1,
+            // a delimiter at the very end of the body
+            None => break,
+        };
+
+        match find_delimiter(body, delimiter, start) {
+            Some(end) => {
+                parts.push(parse_part(strip_line_break(&body[start..end]), depth));
+                cursor = end;
+            },
+            // the close delimiter is missing, the rest of the body is the last part
+            None => {
+                parts.push(parse_part(strip_line_break(&body[start..]), depth));
+                break;
+            },
+        }
+    }
+
+    Ok(parts)
+}
+
+/// Split a multipart body into its parts. The preamble and epilogue are
+/// ignored, a missing close delimiter is tolerated.
+pub fn parse(body: &[u8], boundary: &str) -> Result<Vec<Part>> {
+    if boundary.is_empty() {
+        bail!("Boundary can not be empty");
+    }
+    parse_depth(body, boundary, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boundary() {
+        assert_eq!(boundary("multipart/mixed; boundary=abc"), Some("abc".to_string()));
+        assert_eq!(boundary("multipart/related; type=\"text/html\"; Boundary=\"ab\""), Some("ab".to_string()));
+        assert_eq!(boundary("multipart/mixed"), None);
+        assert_eq!(boundary("text/plain; boundary=abc"), None);
+    }
+
+    #[test]
+    fn test_boundary_from_body() {
+        assert_eq!(boundary_from_body(b"--abc\r\nfoo"), Some("abc".to_string()));
+        assert_eq!(boundary_from_body(b"preamble\n--abc  \nfoo"), Some("abc".to_string()));
+        assert_eq!(boundary_from_body(b"foo--abc\n"), None);
+        assert_eq!(boundary_from_body(b"--\r\n"), None);
+    }
+
+    #[test]
+    fn test_parse_mixed() {
+        let body = b"This is the preamble.\r\n--frontier\r\nContent-Type: text/plain\r\nX-Folded: a\r\n b\r\n\r\nThis is the body of the message.\r\n--frontier\r\nContent-Type: application/octet-stream\r\nContent-Transfer-Encoding: base64\r\n\r\nPGh0bWw+\r\n--frontier--\r\nepilogue";
+        let parts = parse(body, "frontier").unwrap();
+        assert_eq!(parts.len(), 2);
+
+        assert_eq!(parts[0].content_type.as_deref(), Some("text/plain"));
+        assert_eq!(parts[0].headers["x-folded"], "a b");
+        assert_eq!(parts[0].body, b"This is the body of the message.");
+
+        assert_eq!(parts[1].content_type.as_deref(), Some("application/octet-stream"));
+        assert_eq!(parts[1].headers["content-transfer-encoding"], "base64");
+        assert_eq!(parts[1].body, b"PGh0bWw+");
+    }
+
+    #[test]
+    fn test_parse_lenient() {
+        // lf line breaks, no headers and no close delimiter
+        let body = b"--x\n\nfirst\n--x\nContent-Type: text/plain\n\nsecond\nline\n";
+        let parts = parse(body, "x").unwrap();
+        assert_eq!(parts.len(), 2);
+        assert!(parts[0].headers.is_empty());
+        assert_eq!(parts[0].content_type, None);
+        assert_eq!(parts[0].body, b"first");
+        assert_eq!(parts[1].body, b"second\nline");
+    }
+
+    #[test]
+    fn test_parse_nested() {
+        let body = b"--outer\r\nContent-Type: multipart/alternative; boundary=\"inner\"\r\n\r\n--inner\r\nContent-Type: text/plain\r\n\r\nplain\r\n--inner\r\nContent-Type: text/html\r\n\r\n<b>html</b>\r\n--inner--\r\n--outer\r\nContent-Type: text/plain\r\n\r\n--outer is not a delimiter in here -- outer\r\n--outer--";
+        let parts = parse(body, "outer").unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].parts.len(), 2);
+        assert_eq!(parts[0].parts[0].body, b"plain");
+        assert_eq!(parts[0].parts[1].content_type.as_deref(), Some("text/html"));
+        assert_eq!(parts[0].parts[1].body, b"<b>html</b>");
+        assert!(parts[1].parts.is_empty());
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(parse(b"no delimiter", "x").is_err());
+        assert!(parse(b"--x\r\n\r\nfoo", "").is_err());
+        assert_eq!(parse(b"--x--", "x").unwrap(), Vec::new());
+    }
+}
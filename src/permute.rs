@@ -0,0 +1,247 @@
+use crate::errors::*;
+
+use std::str::FromStr;
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(String),
+    Word,
+    /// Numbers from start to end, inclusive, padded with zeros to width
+    Range {
+        start: u64,
+        end: u64,
+        width: usize,
+    },
+}
+
+/// A template like `dev-{word}` or `{word}{00-99}`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    tokens: Vec<Token>,
+}
+
+impl Pattern {
+    fn ranges(&self) -> impl Iterator<Item=(u64, u64)> + '_ {
+        self.tokens.iter()
+            .filter_map(|t| match t {
+                Token::Range { start, end, .. } => Some((*start, *end)),
+                _ => None,
+            })
+    }
+
+    fn render(&self, word: &str, counters: &[u64]) -> String {
+        let mut out = String::new();
+        let mut counters = counters.iter();
+
+        for token in &self.tokens {
+            match token {
+                Token::Literal(x) => out.push_str(x),
+                Token::Word => out.push_str(word),
+                Token::Range { width, .. } => {
+                    let n = counters.next().expect("counter for every range");
+                    out.push_str(&format!("{:0width$}", n, width=width));
+                },
+            }
+        }
+
+        out
+    }
+
+    /// Advance the counters like an odometer, returns false once all
+    /// combinations have been used
+    fn increment(&self, counters: &mut [u64]) -> bool {
+        let ranges = self.ranges().collect::<Vec<_>>();
+
+        for (counter, (start, end)) in counters.iter_mut().zip(ranges).rev() {
+            if *counter < end {
+                *counter += 1;
+                return true;
+            }
+            *counter = start;
+        }
+
+        false
+    }
+}
+
+fn parse_range(x: &str) -> Result<Token> {
+    let idx = x.find('-')
+        .ok_or_else(|| format_err!("Unknown placeholder: {{{}}}", x))?;
+    let (start, end) = (&x[..idx], &x[idx+1..]);
+
+    let start_num = start.parse::<u64>()
+        .map_err(|_| format_err!("Invalid start of range: {:?}", start))?;
+    let end_num = end.parse::<u64>()
+        .map_err(|_| format_err!("Invalid end of range: {:?}", end))?;
+    if start_num > end_num {
+        bail!("Range is empty: {{{}}}", x);
+    }
+
+    // a leading zero enables padding, eg. {00-99}
+    let width = if start.len() > 1 && start.starts_with('0') {
+        start.len()
+    } else {
+        0
+    };
+
+    Ok(Token::Range {
+        start: start_num,
+        end: end_num,
+        width,
+    })
+}
+
+impl FromStr for Pattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Pattern> {
+        let mut tokens = Vec::new();
+        let mut literal = String::new();
+        let mut rest = s;
+
+        while let Some(idx) = rest.find('{') {
+            literal.push_str(&rest[..idx]);
+            let end = rest[idx..].find('}')
+                .ok_or_else(|| format_err!("Unclosed placeholder in pattern: {:?}", s))?;
+            let placeholder = &rest[idx+1..idx+end];
+
+            if !literal.is_empty() {
+                tokens.push(Token::Literal(literal.clone()));
+                literal.clear();
+            }
+
+            if placeholder == "word" {
+                tokens.push(Token::Word);
+            } else {
+                tokens.push(parse_range(placeholder)?);
+            }
+
+            rest = &rest[idx+end+1..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            tokens.push(Token::Literal(literal));
+        }
+
+        if !tokens.contains(&Token::Word) {
+            bail!("Pattern needs a {{word}} placeholder: {:?}", s);
+        }
+
+        Ok(Pattern {
+            tokens,
+        })
+    }
+}
+
+/// Lazily generates every pattern for every word, in order
+#[derive(Debug)]
+pub struct Permutations {
+    words: Vec<String>,
+    patterns: Vec<Pattern>,
+    word: usize,
+    pattern: usize,
+    counters: Option<Vec<u64>>,
+}
+
+impl Permutations {
+    pub fn new(words: Vec<String>, patterns: Vec<Pattern>) -> Permutations {
+        Permutations {
+            words,
+            patterns,
+            word: 0,
+            pattern: 0,
+            counters: None,
+        }
+    }
+}
+
+impl Iterator for Permutations {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        if self.word >= self.words.len() || self.patterns.is_empty() {
+            return None;
+        }
+
+        let pattern = &self.patterns[self.pattern];
+        let counters = self.counters.get_or_insert_with(|| {
+            pattern.ranges()
+                .map(|(start, _)| start)
+                .collect()
+        });
+
+        let out = pattern.render(&self.words[self.word], counters);
+
+        if !pattern.increment(counters) {
+            self.counters = None;
+            self.pattern += 1;
+            if self.pattern >= self.patterns.len() {
+                self.pattern = 0;
+                self.word += 1;
+            }
+        }
+
+        Some(out)
+    }
+}
+
+/// Apply all patterns to all words, `{word}` is replaced with each word and
+/// ranges like `{1-9}` are replaced with every number in that range
+pub fn permute(words: Vec<String>, patterns: &[String]) -> Result<Permutations> {
+    let patterns = patterns.iter()
+        .map(|x| x.parse::<Pattern>())
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Permutations::new(words, patterns))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(x: &[&str]) -> Vec<String> {
+        x.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_permute() {
+        let words = strings(&["admin", "mail"]);
+        let patterns = strings(&["{word}", "dev-{word}", "{word}{1-2}", "{word}-{0-1}{08-09}"]);
+        let list = permute(words, &patterns).unwrap().collect::<Vec<_>>();
+        assert_eq!(list, strings(&[
+            "admin",
+            "dev-admin",
+            "admin1",
+            "admin2",
+            "admin-008",
+            "admin-009",
+            "admin-108",
+            "admin-109",
+            "mail",
+            "dev-mail",
+            "mail1",
+            "mail2",
+            "mail-008",
+            "mail-009",
+            "mail-108",
+            "mail-109",
+        ]));
+    }
+
+    #[test]
+    fn test_permute_lazy() {
+        let words = strings(&["www"]);
+        let patterns = strings(&["{word}{0-18446744073709551615}"]);
+        let list = permute(words, &patterns).unwrap().take(3).collect::<Vec<_>>();
+        assert_eq!(list, strings(&["www0", "www1", "www2"]));
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        assert!("dev-".parse::<Pattern>().is_err());
+        assert!("{word".parse::<Pattern>().is_err());
+        assert!("{word}{9-1}".parse::<Pattern>().is_err());
+        assert!("{word}{a-z}".parse::<Pattern>().is_err());
+        assert!("{word}{foo}".parse::<Pattern>().is_err());
+    }
+}
@@ -0,0 +1,491 @@
+use crate::errors::*;
+
+use futures::future::{self, Loop};
+use futures::stream;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use tokio::net::TcpStream;
+use tokio::prelude::*;
+
+
+/// Response headers of an http proxy are read byte by byte, give up if they
+/// never end
+const MAX_CONNECT_RESPONSE: usize = 8192;
+
+pub type ConnectFuture = Box<Future<Item = TcpStream, Error = Error> + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProxyKind {
+    Socks5,
+    Http,
+}
+
+impl ProxyKind {
+    fn scheme(self) -> &'static str {
+        match self {
+            ProxyKind::Socks5 => "socks5",
+            ProxyKind::Http => "http",
+        }
+    }
+}
+
+/// A single proxy, written as `socks5://127.0.0.1:9050` or
+/// `http://127.0.0.1:8080`. An address without a scheme is a socks5 proxy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Proxy {
+    pub kind: ProxyKind,
+    pub addr: SocketAddr,
+}
+
+impl FromStr for Proxy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Proxy> {
+        let (kind, addr) = match s.find("://") {
+            Some(idx) => {
+                let kind = match &s[..idx] {
+                    "socks5" => ProxyKind::Socks5,
+                    "http" => ProxyKind::Http,
+                    scheme => bail!("Unsupported proxy scheme: {:?}", scheme),
+                };
+                (kind, &s[idx + 3..])
+            },
+            None => (ProxyKind::Socks5, s),
+        };
+
+        let addr = addr.trim_end_matches('/')
+            .parse::<SocketAddr>()
+            .context(format!("Invalid proxy address: {:?}", s))?;
+
+        Ok(Proxy {
+            kind,
+            addr,
+        })
+    }
+}
+
+impl fmt::Display for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}://{}", self.kind.scheme(), self.addr)
+    }
+}
+
+impl Serialize for Proxy {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Proxy {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Proxy, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|err: Error| de::Error::custom(err.to_string()))
+    }
+}
+
+/// Proxies that are traversed in order, the first one is connected to
+/// directly and the last one connects to the destination.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProxyChain {
+    hops: Vec<Proxy>,
+}
+
+impl ProxyChain {
+    pub fn new(hops: Vec<Proxy>) -> Result<ProxyChain> {
+        if hops.is_empty() {
+            bail!("Proxy chain is empty");
+        }
+        Ok(ProxyChain {
+            hops,
+        })
+    }
+
+    pub fn hops(&self) -> &[Proxy] {
+        &self.hops
+    }
+
+    /// The address of the proxy if this chain is a single socks5 proxy
+    pub fn single_socks5(&self) -> Option<SocketAddr> {
+        match self.hops.first() {
+            Some(proxy) if self.hops.len() == 1 && proxy.kind == ProxyKind::Socks5 => Some(proxy.addr),
+            _ => None,
+        }
+    }
+
+    pub fn connect(&self, host: &str, port: u16) -> ConnectFuture {
+        let first = self.hops[0].clone();
+
+        // every hop is asked to connect to the next one, the last hop
+        // connects to the destination
+        let mut steps = Vec::new();
+        for (i, hop) in self.hops.iter().enumerate() {
+            let dest = match self.hops.get(i + 1) {
+                Some(next) => (next.addr.ip().to_string(), next.addr.port()),
+                None => (host.to_string(), port),
+            };
+            steps.push((i + 1, hop.clone(), dest));
+        }
+
+        debug!("connecting to {:?}:{:?} through {:?}", host, port, self.hops);
+        let fut = TcpStream::connect(&first.addr)
+            .map_err(move |err| format_err!("Failed to connect to proxy hop 1 ({}): {}", first, err))
+            .and_then(|stream| {
+                stream::iter_ok(steps)
+                    .fold(stream, |stream, (i, hop, (host, port))| {
+                        let dest = authority(&host, port);
+                        handshake(stream, hop.kind, &host, port)
+                            .map_err(move |err| format_err!("Proxy hop {} ({}) failed to connect to {}: {}", i, hop, dest, err))
+                    })
+            });
+        Box::new(fut)
+    }
+}
+
+fn strip_brackets(host: &str) -> &str {
+    if host.starts_with('[') && host.ends_with(']') {
+        &host[1..host.len() - 1]
+    } else {
+        host
+    }
+}
+
+fn authority(host: &str, port: u16) -> String {
+    let host = strip_brackets(host);
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{}]:{}", ip, port),
+        _ => format!("{}:{}", host, port),
+    }
+}
+
+fn handshake(stream: TcpStream, kind: ProxyKind, host: &str, port: u16) -> ConnectFuture {
+    match kind {
+        ProxyKind::Socks5 => socks5(stream, host, port),
+        ProxyKind::Http => http_connect(stream, host, port),
+    }
+}
+
+fn socks5_request(host: &str, port: u16) -> Result<Vec<u8>> {
+    let mut buf = vec![
+        0x05, // version
+        0x01, // tcp connect
+        0x00, // reserved
+    ];
+
+    let host = strip_brackets(host);
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            buf.push(0x01);
+            buf.extend(&ip.octets());
+        },
+        Ok(IpAddr::V6(ip)) => {
+            buf.push(0x04);
+            buf.extend(&ip.octets());
+        },
+        Err(_) => {
+            if host.len() > 255 {
+                bail!("Domain is too long for socks5: {:?}", host);
+            }
+            buf.push(0x03);
+            buf.push(host.len() as u8);
+            buf.extend(host.bytes());
+        },
+    }
+
+    buf.push((port >> 8) as u8);
+    buf.push(port as u8);
+    Ok(buf)
+}
+
+fn socks5_reply(code: u8) -> &'static str {
+    match code {
+        0x01 => "general failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "ttl expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn socks5(stream: TcpStream, host: &str, port: u16) -> ConnectFuture {
+    let request = match socks5_request(host, port) {
+        Ok(request) => request,
+        Err(err) => return Box::new(future::err(err)),
+    };
+
+    let fut = tokio::io::write_all(stream, [
+            0x05, // version
+            0x01, // number of supported auths
+            0x00, // unauthenticated
+        ])
+        .and_then(|(stream, _)| tokio::io::read_exact(stream, [0; 2]))
+        .map_err(Error::from)
+        .and_then(|(stream, buf)| {
+            if buf[0] != 0x05 {
+                bail!("Proxy is not a socks5 server");
+            }
+            if buf[1] != 0x00 {
+                bail!("Socks5 proxy requires authentication");
+            }
+            Ok(stream)
+        })
+        .and_then(move |stream| tokio::io::write_all(stream, request).map_err(Error::from))
+        .and_then(|(stream, _)| tokio::io::read_exact(stream, [0; 4]).map_err(Error::from))
+        .and_then(|(stream, buf)| -> Box<Future<Item = (TcpStream, usize), Error = Error> + Send> {
+            if buf[0] != 0x05 {
+                return Box::new(future::err(format_err!("Proxy is not a socks5 server")));
+            }
+            if buf[1] != 0x00 {
+                return Box::new(future::err(format_err!("Socks5 connect failed: {}", socks5_reply(buf[1]))));
+            }
+
+            // skip the bound address
+            match buf[3] {
+                0x01 => Box::new(future::ok((stream, 4))),
+                0x04 => Box::new(future::ok((stream, 16))),
+                0x03 => Box::new(tokio::io::read_exact(stream, [0; 1])
+                    .map(|(stream, len)| (stream, len[0] as usize))
+                    .map_err(Error::from)),
+                _ => Box::new(future::err(format_err!("Invalid socks5 address type"))),
+            }
+        })
+        .and_then(|(stream, len)| tokio::io::read_exact(stream, vec![0; len + 2]).map_err(Error::from))
+        .map(|(stream, _)| stream);
+    Box::new(fut)
+}
+
+/// Only the status line is checked, a successful response can't have a body
+fn parse_connect_response(buf: &[u8]) -> Result<()> {
+    let response = String::from_utf8_lossy(buf);
+    let status = response.lines().next().unwrap_or("");
+
+    let mut parts = status.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some(version), Some(code)) if version.starts_with("HTTP/") => {
+            if code.starts_with('2') {
+                Ok(())
+            } else {
+                bail!("Http proxy refused to connect: {:?}", status)
+            }
+        },
+        _ => bail!("Proxy is not a http proxy: {:?}", status),
+    }
+}
+
+fn http_connect(stream: TcpStream, host: &str, port: u16) -> ConnectFuture {
+    let authority = authority(host, port);
+    let request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", authority);
+
+    let fut = tokio::io::write_all(stream, request.into_bytes())
+        .map_err(Error::from)
+        .and_then(|(stream, _)| {
+            future::loop_fn((stream, Vec::new()), |(stream, mut buf)| {
+                tokio::io::read_exact(stream, [0; 1])
+                    .map_err(Error::from)
+                    .and_then(move |(stream, byte)| {
+                        buf.push(byte[0]);
+                        if buf.ends_with(b"\r\n\r\n") {
+                            Ok(Loop::Break((stream, buf)))
+                        } else if buf.len() > MAX_CONNECT_RESPONSE {
+                            bail!("Http proxy response is too large")
+                        } else {
+                            Ok(Loop::Continue((stream, buf)))
+                        }
+                    })
+            })
+        })
+        .and_then(|(stream, buf)| {
+            parse_connect_response(&buf)?;
+            Ok(stream)
+        });
+    Box::new(fut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::{self, Shutdown, TcpListener};
+    use std::thread;
+    use tokio::runtime::Runtime;
+
+    fn relay(client: net::TcpStream, upstream: net::TcpStream) {
+        let mut client2 = client.try_clone().unwrap();
+        let mut upstream2 = upstream.try_clone().unwrap();
+        let (mut client, mut upstream) = (client, upstream);
+        thread::spawn(move || {
+            std::io::copy(&mut upstream2, &mut client2).ok();
+            client2.shutdown(Shutdown::Write).ok();
+        });
+        std::io::copy(&mut client, &mut upstream).ok();
+        upstream.shutdown(Shutdown::Write).ok();
+    }
+
+    fn socks5_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut client, _) = listener.accept().unwrap();
+            let mut buf = [0; 3];
+            client.read_exact(&mut buf).unwrap();
+            client.write_all(&[0x05, 0x00]).unwrap();
+
+            let mut buf = [0; 4];
+            client.read_exact(&mut buf).unwrap();
+            assert_eq!(buf[3], 0x01);
+            let mut dest = [0; 6];
+            client.read_exact(&mut dest).unwrap();
+            let ip = net::Ipv4Addr::new(dest[0], dest[1], dest[2], dest[3]);
+            let port = u16::from(dest[4]) << 8 | u16::from(dest[5]);
+
+            let upstream = net::TcpStream::connect((ip, port)).unwrap();
+            client.write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).unwrap();
+            relay(client, upstream);
+        });
+        addr
+    }
+
+    fn http_server(allowed: bool) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (client, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(client.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let dest = line.split_whitespace().nth(1).unwrap().to_string();
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header == "\r\n" {
+                    break;
+                }
+            }
+
+            let mut client = client;
+            if !allowed {
+                client.write_all(b"HTTP/1.1 403 Forbidden\r\n\r\n").unwrap();
+                return;
+            }
+            let upstream = net::TcpStream::connect(dest.as_str()).unwrap();
+            client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n").unwrap();
+            relay(client, upstream);
+        });
+        addr
+    }
+
+    fn echo_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (client, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(client.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let mut client = client;
+            client.write_all(format!("echo: {}", line).as_bytes()).unwrap();
+        });
+        addr
+    }
+
+    fn roundtrip(chain: &ProxyChain, dest: SocketAddr) -> Result<String> {
+        let fut = chain.connect(&dest.ip().to_string(), dest.port())
+            .and_then(|stream| tokio::io::write_all(stream, b"ohai\n").map_err(Error::from))
+            .and_then(|(stream, _)| tokio::io::read_to_end(stream, Vec::new()).map_err(Error::from))
+            .map(|(_, buf)| String::from_utf8(buf).unwrap());
+        let mut rt = Runtime::new()?;
+        rt.block_on(fut)
+    }
+
+    #[test]
+    fn test_parse_proxy() {
+        let proxy = "socks5://127.0.0.1:9050".parse::<Proxy>().unwrap();
+        assert_eq!(proxy, Proxy { kind: ProxyKind::Socks5, addr: "127.0.0.1:9050".parse().unwrap() });
+        let proxy = "http://[::1]:8080".parse::<Proxy>().unwrap();
+        assert_eq!(proxy, Proxy { kind: ProxyKind::Http, addr: "[::1]:8080".parse().unwrap() });
+        assert_eq!(proxy.to_string(), "http://[::1]:8080");
+    }
+
+    #[test]
+    fn test_parse_proxy_without_scheme() {
+        let proxy = "127.0.0.1:9050".parse::<Proxy>().unwrap();
+        assert_eq!(proxy.kind, ProxyKind::Socks5);
+    }
+
+    #[test]
+    fn test_parse_proxy_invalid() {
+        assert!("https://127.0.0.1:8080".parse::<Proxy>().is_err());
+        assert!("socks4://127.0.0.1:1080".parse::<Proxy>().is_err());
+        assert!("socks5://localhost:1080".parse::<Proxy>().is_err());
+        assert!("http://127.0.0.1".parse::<Proxy>().is_err());
+    }
+
+    #[test]
+    fn test_empty_chain() {
+        assert!(ProxyChain::new(vec![]).is_err());
+    }
+
+    #[test]
+    fn test_socks5_request() {
+        let buf = socks5_request("example.com", 443).unwrap();
+        assert_eq!(buf, b"\x05\x01\x00\x03\x0bexample.com\x01\xbb".to_vec());
+        let buf = socks5_request("[::1]", 80).unwrap();
+        assert_eq!(buf[3], 0x04);
+        assert_eq!(buf.len(), 4 + 16 + 2);
+    }
+
+    #[test]
+    fn test_parse_connect_response() {
+        assert!(parse_connect_response(b"HTTP/1.1 200 Connection established\r\n\r\n").is_ok());
+        assert!(parse_connect_response(b"HTTP/1.0 407 Proxy Authentication Required\r\n\r\n").is_err());
+        assert!(parse_connect_response(b"SSH-2.0-OpenSSH_7.9\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_chain_socks5_over_http() {
+        let echo = echo_server();
+        let chain = ProxyChain::new(vec![
+            Proxy { kind: ProxyKind::Http, addr: http_server(true) },
+            Proxy { kind: ProxyKind::Socks5, addr: socks5_server() },
+        ]).unwrap();
+        assert_eq!(roundtrip(&chain, echo).unwrap(), "echo: ohai\n");
+    }
+
+    #[test]
+    fn test_chain_socks5_over_socks5() {
+        let echo = echo_server();
+        let chain = ProxyChain::new(vec![
+            Proxy { kind: ProxyKind::Socks5, addr: socks5_server() },
+            Proxy { kind: ProxyKind::Socks5, addr: socks5_server() },
+        ]).unwrap();
+        assert_eq!(roundtrip(&chain, echo).unwrap(), "echo: ohai\n");
+    }
+
+    #[test]
+    fn test_chain_refused_hop() {
+        let echo = echo_server();
+        let chain = ProxyChain::new(vec![
+            Proxy { kind: ProxyKind::Socks5, addr: socks5_server() },
+            Proxy { kind: ProxyKind::Http, addr: http_server(false) },
+        ]).unwrap();
+        let err = roundtrip(&chain, echo).unwrap_err();
+        assert!(err.to_string().starts_with("Proxy hop 2 "));
+    }
+
+    #[test]
+    fn test_chain_unreachable_hop() {
+        // bind and drop a listener to find a port that is closed
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let chain = ProxyChain::new(vec![
+            Proxy { kind: ProxyKind::Socks5, addr: closed },
+        ]).unwrap();
+        let err = roundtrip(&chain, closed).unwrap_err();
+        assert!(err.to_string().starts_with("Failed to connect to proxy hop 1 "));
+    }
+}
@@ -5,6 +5,8 @@ use crate::auth;
 use crate::config::Config;
 use colored::Colorize;
 use separator::Separatable;
+use sn0int_common::api::Deprecation;
+use sn0int_common::id::ModuleID;
 use sn0int_common::metadata::Metadata;
 use std::fs;
 use std::path::Path;
@@ -38,7 +40,7 @@ pub fn run_publish(_args: &Args, publish: &Publish, config: &Config) -> Result<(
 
         let label = format!("Uploading {} {} ({:?})", name, metadata.version, path);
         match worker::spawn_fn(&label, || {
-            client.publish_module(&name, code.to_string())
+            client.publish_module(&name, code.to_string(), publish.prerelease, publish.allow_unchanged, publish.skip_syntax_check)
         }, true) {
             Ok(result) => term::info(&format!("Published {}/{} {} ({:?})",
                                               result.author,
@@ -56,11 +58,22 @@ pub fn run_publish(_args: &Args, publish: &Publish, config: &Config) -> Result<(
     Ok(())
 }
 
+fn warn_deprecated(module: &ModuleID, deprecated: &Deprecation) {
+    let mut msg = format!("{} is deprecated", module);
+    if let Some(message) = &deprecated.message {
+        msg.push_str(&format!(": {}", message));
+    }
+    if let Some(successor) = &deprecated.successor {
+        msg.push_str(&format!(" (use {} instead)", successor));
+    }
+    term::warn(&msg);
+}
+
 pub fn run_install(install: &Install, config: &Config) -> Result<()> {
     let client = Client::new(&config)?;
 
     let label = format!("Installing {}", install.module);
-    worker::spawn_fn(&label, || {
+    let deprecated = worker::spawn_fn(&label, || {
         let version = match install.version {
             Some(ref version) => version.to_string(),
             None => client.query_module(&install.module)
@@ -82,8 +95,14 @@ pub fn run_install(install: &Install, config: &Config) -> Result<()> {
         fs::write(&path, module.code)
             .context(format_err!("Failed to write to {:?}", path))?;
 
-        Ok(())
-    }, false)
+        Ok(module.deprecated)
+    }, false)?;
+
+    if let Some(deprecated) = deprecated {
+        warn_deprecated(&install.module, &deprecated);
+    }
+
+    Ok(())
 }
 
 pub fn run_search(search: &Search, config: &Config) -> Result<()> {
@@ -95,10 +114,12 @@ pub fn run_search(search: &Search, config: &Config) -> Result<()> {
     }, true)?;
 
     for module in &modules {
-        println!("{} ({}) - {} downloads {}", module.canonical().green(),
+        println!("{} ({}) - {} downloads {}{}{}", module.canonical().green(),
                             module.latest.yellow(),
                             module.downloads.separated_string(),
-                            (if module.featured { "[featured]" } else { "" }).cyan());
+                            (if module.verified { "[verified]" } else { "" }).green(),
+                            (if module.featured { "[featured]" } else { "" }).cyan(),
+                            (if module.deprecated.is_some() { "[deprecated]" } else { "" }).red());
         println!("\t{}", module.description);
     }
 
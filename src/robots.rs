@@ -0,0 +1,235 @@
+/// Rules that apply to a set of user agents
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Group {
+    pub user_agents: Vec<String>,
+    pub allow: Vec<String>,
+    pub disallow: Vec<String>,
+    pub crawl_delay: Option<f64>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Robots {
+    pub groups: Vec<Group>,
+    pub sitemaps: Vec<String>,
+}
+
+/// Parse a robots.txt file. Lines that can't be parsed and rules that don't
+/// follow a `User-agent` line are ignored, like crawlers do.
+pub fn parse(text: &str) -> Robots {
+    let mut robots = Robots::default();
+    let mut group: Option<Group> = None;
+    // consecutive user-agent lines share the rules that follow them
+    let mut collecting_agents = false;
+
+    for line in text.lines() {
+        let line = match line.find('#') {
+            Some(idx) => &line[..idx],
+            None => line,
+        };
+
+        let idx = match line.find(':') {
+            Some(idx) => idx,
+            None => continue,
+        };
+        let key = line[..idx].trim().to_lowercase();
+        let value = line[idx+1..].trim();
+
+        if key == "user-agent" {
+            if !collecting_agents {
+                if let Some(group) = group.take() {
+                    robots.groups.push(group);
+                }
+            }
+            group.get_or_insert_with(Group::default)
+                .user_agents.push(value.to_string());
+            collecting_agents = true;
+            continue;
+        }
+
+        if key == "sitemap" {
+            if !value.is_empty() {
+                robots.sitemaps.push(value.to_string());
+            }
+            continue;
+        }
+
+        collecting_agents = false;
+        let group = match &mut group {
+            Some(group) => group,
+            None => continue,
+        };
+
+        match key.as_str() {
+            // an empty disallow rule allows everything
+            "allow" | "disallow" if value.is_empty() => (),
+            "allow" => group.allow.push(value.to_string()),
+            "disallow" => group.disallow.push(value.to_string()),
+            "crawl-delay" => group.crawl_delay = value.parse().ok(),
+            _ => (),
+        }
+    }
+
+    if let Some(group) = group {
+        robots.groups.push(group);
+    }
+
+    robots
+}
+
+impl Robots {
+    /// The group of a user agent, only the product token is compared, eg.
+    /// `sn0int` of `sn0int/0.1`. A group that names the agent takes
+    /// precedence over `*`.
+    pub fn group(&self, user_agent: &str) -> Option<&Group> {
+        let token = user_agent.split('/')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_lowercase();
+
+        self.groups.iter()
+            .find(|group| group.user_agents.iter().any(|agent| agent.to_lowercase() == token))
+            .or_else(|| self.groups.iter()
+                .find(|group| group.user_agents.iter().any(|agent| agent == "*")))
+    }
+
+    /// Check if a path, including the query, may be crawled. The longest
+    /// matching rule wins, allow wins if both are equally long.
+    pub fn is_allowed(&self, user_agent: &str, path: &str) -> bool {
+        let group = match self.group(user_agent) {
+            Some(group) => group,
+            None => return true,
+        };
+
+        match (longest_match(&group.allow, path), longest_match(&group.disallow, path)) {
+            (_, None) => true,
+            (None, Some(_)) => false,
+            (Some(allow), Some(disallow)) => allow >= disallow,
+        }
+    }
+}
+
+fn longest_match(rules: &[String], path: &str) -> Option<usize> {
+    rules.iter()
+        .filter(|rule| rule_matches(rule, path))
+        .map(|rule| rule.len())
+        .max()
+}
+
+/// `*` matches any sequence of characters, a trailing `$` means the rule has
+/// to match until the end of the path
+fn rule_matches(rule: &str, path: &str) -> bool {
+    let (rule, anchored) = match rule.strip_suffix('$') {
+        Some(rule) => (rule, true),
+        None => (rule, false),
+    };
+
+    let parts = rule.split('*').collect::<Vec<_>>();
+    let (last, parts) = parts.split_last().expect("split always returns one part");
+
+    if parts.is_empty() {
+        return if anchored { path == *last } else { path.starts_with(last) };
+    }
+
+    if !path.starts_with(parts[0]) {
+        return false;
+    }
+    let mut pos = parts[0].len();
+    for part in &parts[1..] {
+        match path[pos..].find(part) {
+            Some(idx) => pos += idx + part.len(),
+            None => return false,
+        }
+    }
+
+    if anchored {
+        path[pos..].ends_with(last)
+    } else {
+        path[pos..].contains(last)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(x: &[&str]) -> Vec<String> {
+        x.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let robots = parse(r#"# robots.txt for example.com
+Allow: /ignored
+User-agent: googlebot
+user-agent: bingbot
+Disallow: /admin/ # private
+Allow: /admin/public
+Crawl-delay: 2.5
+
+User-agent: *
+Disallow:
+Disallow: /search
+Sitemap: https://example.com/sitemap.xml
+invalid line
+User-Agent: badbot
+Disallow: /
+"#);
+        assert_eq!(robots, Robots {
+            groups: vec![
+                Group {
+                    user_agents: strings(&["googlebot", "bingbot"]),
+                    allow: strings(&["/admin/public"]),
+                    disallow: strings(&["/admin/"]),
+                    crawl_delay: Some(2.5),
+                },
+                Group {
+                    user_agents: strings(&["*"]),
+                    allow: vec![],
+                    disallow: strings(&["/search"]),
+                    crawl_delay: None,
+                },
+                Group {
+                    user_agents: strings(&["badbot"]),
+                    allow: vec![],
+                    disallow: strings(&["/"]),
+                    crawl_delay: None,
+                },
+            ],
+            sitemaps: strings(&["https://example.com/sitemap.xml"]),
+        });
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        assert_eq!(parse(""), Robots::default());
+        assert_eq!(parse("<html>not found</html>"), Robots::default());
+    }
+
+    #[test]
+    fn test_is_allowed() {
+        let robots = parse(r#"
+User-agent: *
+Disallow: /private/
+Allow: /private/public
+Disallow: /*.pdf$
+Disallow: /search*q=
+
+User-agent: sn0int
+Disallow: /admin
+"#);
+        assert!(robots.is_allowed("googlebot", "/"));
+        assert!(!robots.is_allowed("googlebot", "/private/x"));
+        assert!(robots.is_allowed("googlebot", "/private/public/x"));
+        assert!(!robots.is_allowed("googlebot", "/files/a.pdf"));
+        assert!(robots.is_allowed("googlebot", "/files/a.pdf?x=1"));
+        assert!(!robots.is_allowed("googlebot", "/search?a=1&q=x"));
+        assert!(robots.is_allowed("googlebot", "/search?a=1"));
+
+        // a specific group replaces the rules of `*`
+        assert!(!robots.is_allowed("sn0int/0.11", "/admin/x"));
+        assert!(robots.is_allowed("sn0int/0.11", "/private/x"));
+
+        assert!(Robots::default().is_allowed("sn0int", "/anything"));
+    }
+}
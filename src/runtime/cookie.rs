@@ -0,0 +1,101 @@
+use crate::errors::*;
+
+use crate::cookie::{self, Cookie};
+use crate::engine::ctx::State;
+use crate::engine::structs;
+use crate::hlua::{self, AnyLuaValue};
+use crate::json::LuaJsonValue;
+use std::sync::Arc;
+
+
+/// Accept a single string with one header per line or a list of headers
+fn headers(x: AnyLuaValue) -> Result<Vec<String>> {
+    match x {
+        AnyLuaValue::LuaString(x) => Ok(vec![x]),
+        x => match LuaJsonValue::from(x) {
+            LuaJsonValue::Object(ref x) if x.is_empty() => Ok(Vec::new()),
+            x => structs::from_lua(x),
+        },
+    }
+}
+
+pub fn cookie_parse(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("cookie_parse", hlua::function1(move |x: AnyLuaValue| -> Result<AnyLuaValue> {
+        let headers = headers(x)
+            .context("Set-Cookie headers must be a string or a list of strings")
+            .map_err(|err| state.set_error(err.into()))?;
+
+        let cookies = headers.iter()
+            .flat_map(|x| cookie::parse_all(x))
+            .collect::<Vec<_>>();
+
+        let cookies = serde_json::to_value(cookies)
+            .map_err(|err| state.set_error(err.into()))?;
+        Ok(LuaJsonValue::from(cookies).into())
+    }))
+}
+
+pub fn cookie_serialize(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("cookie_serialize", hlua::function1(move |x: AnyLuaValue| -> Result<String> {
+        let cookie = structs::from_lua::<Cookie>(x.into())
+            .context("invalid cookie")
+            .map_err(|err| state.set_error(err.into()))?;
+        cookie::serialize(&cookie)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::ctx::Script;
+
+    #[test]
+    fn verify_cookie_parse() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = cookie_parse({'a=1; Path=/; Secure', 'invalid', 'b=2; HttpOnly; SameSite=strict'})
+            if last_err() then return end
+            if #x ~= 2 then
+                return 'unexpected number of cookies: ' .. #x
+            end
+            if x[1]['name'] ~= 'a' or x[1]['value'] ~= '1' or x[1]['path'] ~= '/' or not x[1]['secure'] then
+                return 'unexpected first cookie'
+            end
+            if x[2]['name'] ~= 'b' or not x[2]['httponly'] or x[2]['samesite'] ~= 'Strict' then
+                return 'unexpected second cookie'
+            end
+
+            x = cookie_parse('c=3; Domain=.example.com')
+            if last_err() then return end
+            if #x ~= 1 or x[1]['domain'] ~= 'example.com' then
+                return 'unexpected cookie from string'
+            end
+        end
+        "#).unwrap();
+        script.test().expect("Failed to run script");
+    }
+
+    #[test]
+    fn verify_cookie_serialize() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = cookie_serialize({name='SID', value='abc', path='/', secure=true, samesite='lax'})
+            if last_err() then return end
+            if x ~= 'SID=abc; Secure; SameSite=Lax; Path=/' then
+                return 'unexpected cookie: ' .. x
+            end
+        end
+        "#).unwrap();
+        script.test().expect("Failed to run script");
+    }
+
+    #[test]
+    fn verify_cookie_serialize_invalid() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            cookie_serialize({name='SID', value='abc; Secure'})
+        end
+        "#).unwrap();
+        assert!(script.test().is_err());
+    }
+}
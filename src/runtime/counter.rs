@@ -0,0 +1,19 @@
+use crate::errors::*;
+use crate::engine::ctx::State;
+use crate::hlua;
+use std::sync::Arc;
+
+
+pub fn counter_inc(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("counter_inc", hlua::function2(move |name: String, by: i32| -> Result<i32> {
+        state.counter_inc(name, by)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn counter_get(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("counter_get", hlua::function1(move |name: String| -> Result<i32> {
+        state.counter_get(name)
+            .map_err(|err| state.set_error(err))
+    }))
+}
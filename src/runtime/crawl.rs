@@ -0,0 +1,454 @@
+use crate::errors::*;
+
+use crate::engine::ctx::State;
+use crate::engine::structs::{LuaList, LuaMap};
+use crate::frontier;
+use crate::hlua::{self, AnyLuaValue};
+use crate::html;
+use crate::json::LuaJsonValue;
+use crate::robots::{self, Robots};
+use crate::web::RequestOptions;
+use serde_json;
+use sn0int_common::metadata::Capability;
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use url::Url;
+
+
+const DEFAULT_MAX_DEPTH: usize = 2;
+const DEFAULT_MAX_PAGES: usize = 100;
+/// A crawl-delay from robots.txt can't stall the crawl longer than this
+const MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// Options of crawl, the remaining keys are regular request options that are
+/// used for every page
+#[derive(Debug, Default, Deserialize)]
+pub struct CrawlOptions {
+    /// Links of pages at this depth aren't followed, the seeds are depth 0
+    max_depth: Option<usize>,
+    max_pages: Option<usize>,
+    /// Only follow links to the origins of the seeds
+    same_origin: Option<bool>,
+    /// Only follow links to hosts that are in scope
+    #[serde(default)]
+    scope: bool,
+    /// Respect robots.txt
+    robots: Option<bool>,
+    /// Minimum delay between two requests to the same host in milliseconds
+    #[serde(default)]
+    delay: u64,
+    session: Option<String>,
+    #[serde(flatten)]
+    request: RequestOptions,
+}
+
+impl CrawlOptions {
+    pub fn try_from(x: AnyLuaValue) -> Result<CrawlOptions> {
+        match LuaJsonValue::from(x) {
+            LuaJsonValue::Null => Ok(CrawlOptions::default()),
+            x => {
+                let x = serde_json::from_value(x.into())?;
+                Ok(x)
+            },
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Page {
+    url: String,
+    depth: usize,
+    status: Option<u16>,
+    text: Option<String>,
+    links: Vec<String>,
+    error: Option<String>,
+}
+
+impl From<Page> for AnyLuaValue {
+    fn from(page: Page) -> AnyLuaValue {
+        let mut map = LuaMap::new();
+        map.insert_str("url", page.url);
+        map.insert_num("depth", page.depth as f64);
+        if let Some(status) = page.status {
+            map.insert_num("status", f64::from(status));
+        }
+        if let Some(text) = page.text {
+            map.insert_str("text", text);
+        }
+        let mut links = LuaList::new();
+        for link in page.links {
+            links.push_str(link);
+        }
+        map.insert("links", links);
+        if let Some(error) = page.error {
+            map.insert_str("error", error);
+        }
+        map.into()
+    }
+}
+
+/// A breadth first crawl, pages are only fetched by crawl_next
+#[derive(Debug)]
+pub struct Crawler {
+    queue: VecDeque<(Url, usize)>,
+    seen: HashSet<String>,
+    origins: HashSet<String>,
+    /// Parsed robots.txt of every origin we've visited
+    robots: HashMap<String, Robots>,
+    last_fetch: HashMap<String, Instant>,
+    pages: usize,
+    max_depth: usize,
+    max_pages: usize,
+    same_origin: bool,
+    scope: bool,
+    respect_robots: bool,
+    delay: Duration,
+    session: String,
+    request: RequestOptions,
+}
+
+impl Crawler {
+    fn new(state: &Arc<State>, seeds: Vec<String>, options: CrawlOptions) -> Result<Crawler> {
+        let session = match options.session {
+            Some(session) => session,
+            None => state.http_mksession(),
+        };
+
+        let mut crawler = Crawler {
+            queue: VecDeque::new(),
+            seen: HashSet::new(),
+            origins: HashSet::new(),
+            robots: HashMap::new(),
+            last_fetch: HashMap::new(),
+            pages: 0,
+            max_depth: options.max_depth.unwrap_or(DEFAULT_MAX_DEPTH),
+            max_pages: options.max_pages.unwrap_or(DEFAULT_MAX_PAGES),
+            same_origin: options.same_origin.unwrap_or(true),
+            scope: options.scope,
+            respect_robots: options.robots.unwrap_or(true),
+            delay: Duration::from_millis(options.delay),
+            session,
+            request: options.request,
+        };
+
+        let seeds = seeds.iter()
+            .map(|seed| Url::parse(seed)
+                .with_context(|_| format!("Invalid seed url: {:?}", seed)))
+            .collect::<::std::result::Result<Vec<_>, _>>()?;
+
+        for seed in &seeds {
+            crawler.origins.insert(seed.origin().ascii_serialization());
+        }
+        for seed in seeds {
+            crawler.push(state, seed, 0)?;
+        }
+
+        Ok(crawler)
+    }
+
+    /// Queue a url unless it was seen before or it's outside of the crawl
+    fn push(&mut self, state: &Arc<State>, mut url: Url, depth: usize) -> Result<()> {
+        url.set_fragment(None);
+
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Ok(());
+        }
+
+        if self.same_origin && !self.origins.contains(&url.origin().ascii_serialization()) {
+            return Ok(());
+        }
+
+        if self.seen.contains(url.as_str()) || self.seen.len() >= frontier::DEFAULT_LIMIT {
+            return Ok(());
+        }
+
+        if self.scope {
+            let host = match url.host_str() {
+                Some(host) => host.to_string(),
+                None => return Ok(()),
+            };
+            if !super::frontier::in_scope(state, &host)? {
+                debug!("Dropping out of scope url: {:?}", url.as_str());
+                return Ok(());
+            }
+        }
+
+        self.seen.insert(url.to_string());
+        self.queue.push_back((url, depth));
+        Ok(())
+    }
+
+    /// Wait until the host may be requested again
+    fn wait(&mut self, url: &Url, delay: Duration) {
+        let host = url.host_str().unwrap_or("").to_string();
+        if let Some(last) = self.last_fetch.get(&host) {
+            let elapsed = last.elapsed();
+            if elapsed < delay {
+                thread::sleep(delay - elapsed);
+            }
+        }
+        self.last_fetch.insert(host, Instant::now());
+    }
+
+    fn get(&self, state: &State, url: &Url) -> Result<chrootable_https::Response> {
+        let req = state.http_request(&self.session, "GET".to_string(), url.to_string(), self.request.clone());
+        req.send_raw(state)
+    }
+
+    /// Fetch robots.txt once per origin, if it's not available everything is allowed
+    fn robots(&mut self, state: &State, url: &Url) -> &Robots {
+        let origin = url.origin().ascii_serialization();
+        if !self.robots.contains_key(&origin) {
+            let robots = match url.join("/robots.txt") {
+                Ok(robots_url) => {
+                    self.wait(&robots_url, self.delay);
+                    match self.get(state, &robots_url) {
+                        Ok(res) if res.status >= 200 && res.status < 300 => robots::parse(&String::from_utf8_lossy(&res.body)),
+                        Ok(res) => {
+                            debug!("No robots.txt for {:?}: http status {}", origin, res.status);
+                            Robots::default()
+                        },
+                        Err(err) => {
+                            debug!("Failed to fetch robots.txt for {:?}: {}", origin, err);
+                            Robots::default()
+                        },
+                    }
+                },
+                Err(_) => Robots::default(),
+            };
+            self.robots.insert(origin.clone(), robots);
+        }
+        &self.robots[&origin]
+    }
+
+    fn fetch(&mut self, state: &Arc<State>, url: Url, depth: usize) -> Result<Page> {
+        let user_agent = self.request.user_agent();
+
+        let mut delay = self.delay;
+        if self.respect_robots {
+            let mut path = url.path().to_string();
+            if let Some(query) = url.query() {
+                path.push('?');
+                path.push_str(query);
+            }
+
+            let robots = self.robots(state.as_ref(), &url);
+            if !robots.is_allowed(&user_agent, &path) {
+                debug!("Skipping url disallowed by robots.txt: {:?}", url.as_str());
+                return Ok(Page {
+                    url: url.into_string(),
+                    depth,
+                    error: Some("Disallowed by robots.txt".to_string()),
+                    ..Page::default()
+                });
+            }
+
+            let crawl_delay = robots.group(&user_agent)
+                .and_then(|group| group.crawl_delay)
+                .filter(|secs| *secs > 0.0)
+                .map(|secs| Duration::from_millis((secs * 1000.0) as u64));
+            if let Some(crawl_delay) = crawl_delay {
+                delay = cmp::max(delay, cmp::min(crawl_delay, MAX_DELAY));
+            }
+        }
+
+        self.wait(&url, delay);
+        self.pages += 1;
+
+        let res = match self.get(state.as_ref(), &url) {
+            Ok(res) => res,
+            Err(err) => return Ok(Page {
+                url: url.into_string(),
+                depth,
+                error: Some(err.to_string()),
+                ..Page::default()
+            }),
+        };
+
+        let header = |name: &str| res.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone());
+
+        let mut links = Vec::new();
+        // redirects are followed like links, but at the same depth
+        if res.status >= 300 && res.status < 400 {
+            if let Some(location) = header("location").and_then(|x| url.join(&x).ok()) {
+                links.push(location.to_string());
+                self.push(state, location, depth)?;
+            }
+        }
+
+        let text = String::from_utf8_lossy(&res.body).into_owned();
+        let is_html = header("content-type")
+            .map(|x| x.to_lowercase().contains("html"))
+            .unwrap_or(false);
+
+        if is_html {
+            for link in html::html_select_list(&text, "a[href]")? {
+                let link = match url.join(&link.attrs["href"]) {
+                    Ok(mut link) => {
+                        link.set_fragment(None);
+                        link
+                    },
+                    Err(_) => continue,
+                };
+                links.push(link.to_string());
+                if depth < self.max_depth {
+                    self.push(state, link, depth + 1)?;
+                }
+            }
+        }
+
+        Ok(Page {
+            url: url.into_string(),
+            depth,
+            status: Some(res.status),
+            text: Some(text),
+            links,
+            error: None,
+        })
+    }
+
+    /// Fetch the next page, None if the crawl is done
+    fn next(&mut self, state: &Arc<State>) -> Result<Option<Page>> {
+        if self.pages >= self.max_pages {
+            self.queue.clear();
+            return Ok(None);
+        }
+
+        match self.queue.pop_front() {
+            Some((url, depth)) => self.fetch(state, url, depth).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+pub fn crawl(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("crawl", hlua::function2(move |seeds: AnyLuaValue, options: AnyLuaValue| -> Result<String> {
+        state.require(Capability::Network)
+            .map_err(|err| state.set_error(err))?;
+
+        let seeds = match seeds {
+            AnyLuaValue::LuaString(seed) => vec![seed],
+            seeds => crate::engine::structs::from_lua::<Vec<String>>(seeds.into())
+                .map_err(|err| state.set_error(err))?,
+        };
+
+        let options = CrawlOptions::try_from(options)
+            .context("invalid crawl options")
+            .map_err(|err| state.set_error(Error::from(err)))?;
+
+        let crawler = Crawler::new(&state, seeds, options)
+            .map_err(|err| state.set_error(err))?;
+        Ok(state.crawler_register(crawler))
+    }))
+}
+
+pub fn crawl_next(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("crawl_next", hlua::function1(move |crawler: String| -> Result<AnyLuaValue> {
+        let crawler = state.get_crawler(&crawler)
+            .map_err(|err| state.set_error(err))?;
+        let mut crawler = crawler.lock().unwrap();
+
+        crawler.next(&state)
+            .map_err(|err| state.set_error(err))
+            .map(|page| match page {
+                Some(page) => page.into(),
+                None => AnyLuaValue::LuaNil,
+            })
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::ctx::Script;
+
+    /// Serve a small site, every page links to the next level
+    fn serve_site() -> u16 {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use std::thread;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 4096];
+                let n = stream.read(&mut buf).unwrap_or(0);
+                let req = String::from_utf8_lossy(&buf[..n]);
+                let path = req.split(' ').nth(1).unwrap_or("/").to_string();
+
+                let (status, content_type, body) = match path.as_str() {
+                    "/robots.txt" => ("200 OK", "text/plain", "User-agent: *\nDisallow: /private\n"),
+                    "/" => ("200 OK", "text/html", "<a href=\"/a#top\">a</a><a href=\"b\">b</a><a href=\"/private/x\">x</a><a href=\"http://example.com/\">ext</a><a href=\"mailto:a@example.com\">mail</a>"),
+                    "/a" => ("200 OK", "text/html", "<a href=\"/\">home</a><a href=\"/c\">c</a>"),
+                    "/b" => ("200 OK", "text/html", "<a href=\"/a\">a</a>"),
+                    "/c" => ("200 OK", "text/html", "<a href=\"/d\">d</a>"),
+                    _ => ("404 Not Found", "text/plain", "not found"),
+                };
+                let _ = stream.write_all(format!("HTTP/1.1 {}\r\nConnection: close\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}", status, content_type, body.len(), body).as_bytes());
+            }
+        });
+
+        port
+    }
+
+    fn crawl_script(port: u16, options: &str, expected: &str) -> Script {
+        Script::load_unchecked(format!(r#"
+        function run()
+            c = crawl('http://127.0.0.1:{}/', {{{}}})
+            if last_err() then return end
+
+            urls = ''
+            while true do
+                page = crawl_next(c)
+                if last_err() then return end
+                if page == nil then break end
+
+                if page['error'] then
+                    urls = urls .. '!'
+                end
+                -- strip the origin of the test server
+                urls = urls .. page['url']:sub({} + 1) .. ':' .. page['depth'] .. ' '
+            end
+
+            if urls ~= '{}' then
+                return 'unexpected crawl: ' .. urls
+            end
+        end
+        "#, port, options, format!("http://127.0.0.1:{}", port).len(), expected)).expect("Failed to load script")
+    }
+
+    #[test]
+    fn verify_crawl() {
+        let port = serve_site();
+        crawl_script(port, "", "/:0 /a:1 /b:1 !/private/x:1 /c:2 ")
+            .test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_crawl_limits() {
+        let port = serve_site();
+        crawl_script(port, "max_depth=1", "/:0 /a:1 /b:1 !/private/x:1 ")
+            .test().expect("Script failed");
+        crawl_script(port, "max_pages=2", "/:0 /a:1 ")
+            .test().expect("Script failed");
+        crawl_script(port, "robots=false, max_depth=1", "/:0 /a:1 /b:1 /private/x:1 ")
+            .test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_crawl_invalid_seed() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            crawl('not a url', nil)
+        end
+        "#).expect("Failed to load script");
+        assert!(script.test().is_err());
+    }
+}
@@ -1,14 +1,20 @@
 use crate::errors::*;
 
-use serde;
-use crate::db::Family;
+use serde::{self, Serialize};
+use crate::db::{confidence, Conflict, Family, InsertOptions};
+use crate::db::findings::NewFinding;
 use crate::engine::ctx::State;
-use crate::engine::structs;
+use crate::engine::structs::{self, LuaList, LuaMap};
 use crate::hlua::{self, AnyLuaValue};
 use std::str::FromStr;
 use std::sync::Arc;
 use crate::models::*;
 use crate::json::LuaJsonValue;
+use crate::worker::QueryEvent;
+use sn0int_common::metadata::Capability;
+use serde_json;
+use std::collections::HashMap;
+use std::convert::TryFrom;
 
 
 pub fn try_into_new<T: LuaInsertToNew>(x: LuaJsonValue) -> Result<T::Target>
@@ -59,6 +65,12 @@ fn into_insert(family: Family, object: LuaJsonValue) -> Result<Insert> {
         Family::BreachEmail => {
             Insert::BreachEmail(try_into_new::<InsertBreachEmail>(object)?)
         },
+        Family::Port => {
+            Insert::Port(try_into_new::<InsertPort>(object)?)
+        },
+        Family::CryptoAddr => {
+            Insert::CryptoAddr(try_into_new::<InsertCryptoAddr>(object)?)
+        },
     };
     Ok(obj)
 }
@@ -67,10 +79,13 @@ pub fn db_add(lua: &mut hlua::Lua, state: Arc<State>) {
     lua.set("db_add", hlua::function2(move |family: String, object: AnyLuaValue| -> Result<Option<i32>> {
         let family = Family::from_str(&family)
             .map_err(|e| state.set_error(e))?;
-        let object = LuaJsonValue::from(object);
 
-        let object = into_insert(family, object)
-            .map_err(|e| state.set_error(e))?;
+        let object = match insert_hook(&state, family, LuaJsonValue::from(object))
+            .map_err(|e| state.set_error(e))?
+        {
+            Some(object) => object,
+            None => return Ok(None),
+        };
 
         state.db_insert(object)
             .map_err(|e| state.set_error(e))
@@ -81,39 +96,318 @@ pub fn db_add_ttl(lua: &mut hlua::Lua, state: Arc<State>) {
     lua.set("db_add_ttl", hlua::function3(move |family: String, object: AnyLuaValue, ttl: i32| -> Result<Option<i32>> {
         let family = Family::from_str(&family)
             .map_err(|e| state.set_error(e))?;
-        let object = LuaJsonValue::from(object);
 
-        let object = into_insert(family, object)
-            .map_err(|e| state.set_error(e))?;
+        let object = match insert_hook(&state, family, LuaJsonValue::from(object))
+            .map_err(|e| state.set_error(e))?
+        {
+            Some(object) => object,
+            None => return Ok(None),
+        };
 
         state.db_insert_ttl(object, ttl)
             .map_err(|e| state.set_error(e))
     }))
 }
 
-pub fn db_select(lua: &mut hlua::Lua, state: Arc<State>) {
-    lua.set("db_select", hlua::function2(move |family: String, value: String| -> Result<Option<i32>> {
+#[derive(Debug, Default, Deserialize)]
+struct AddOptions {
+    conflict: Option<Conflict>,
+    #[serde(default, deserialize_with="confidence::deserialize_opt")]
+    confidence: Option<i32>,
+    #[serde(default)]
+    override_confidence: bool,
+}
+
+impl From<AddOptions> for InsertOptions {
+    fn from(options: AddOptions) -> InsertOptions {
+        InsertOptions {
+            conflict: options.conflict.unwrap_or(Conflict::Update),
+            confidence: options.confidence,
+            override_confidence: options.override_confidence,
+        }
+    }
+}
+
+pub fn db_add_opts(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("db_add_opts", hlua::function3(move |family: String, object: AnyLuaValue, options: AnyLuaValue| -> Result<Option<i32>> {
         let family = Family::from_str(&family)
             .map_err(|e| state.set_error(e))?;
 
-        state.db_select(family, value)
+        let options = structs::from_lua::<AddOptions>(options.into())
+            .context("invalid insert options")
+            .map_err(|e| state.set_error(e.into()))?;
+
+        let object = match insert_hook(&state, family, LuaJsonValue::from(object))
+            .map_err(|e| state.set_error(e))?
+        {
+            Some(object) => object,
+            None => return Ok(None),
+        };
+
+        state.db_insert_opts(object, options.into())
             .map_err(|e| state.set_error(e))
     }))
 }
 
-fn gen_changeset<T: Model, U: Updateable<T>>(object: LuaJsonValue, mut update: LuaJsonValue) -> Result<(i32, String, U)>
+/// Pass an entity through the insert hook of the workspace, returns None if
+/// the hook dropped it
+pub(super) fn insert_hook(state: &Arc<State>, family: Family, object: LuaJsonValue) -> Result<Option<Insert>> {
+    match state.run_insert_hook(family, object)? {
+        Some(object) => into_insert(family, object).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Refuse to send more than this in a single message to the parent
+const MAX_BATCH_SIZE: usize = 10_000;
+
+/// Objects that have been dropped by the insert hook are None
+fn into_inserts<F>(family: Family, objects: LuaJsonValue, hook: F) -> Result<Vec<Option<Insert>>>
+    where F: Fn(LuaJsonValue) -> Result<Option<LuaJsonValue>>
+{
+    let objects = match objects {
+        LuaJsonValue::Array(objects) => objects,
+        // empty lua tables are objects
+        LuaJsonValue::Object(ref x) if x.is_empty() => Vec::new(),
+        _ => bail!("objects must be a list"),
+    };
+
+    if objects.len() > MAX_BATCH_SIZE {
+        bail!("Can't add more than {} objects at once", MAX_BATCH_SIZE);
+    }
+
+    objects.into_iter()
+        .enumerate()
+        .map(|(i, object)| {
+            let object = match hook(object)? {
+                Some(object) => object,
+                None => return Ok(None),
+            };
+            into_insert(family, object)
+                .map(Some)
+                .context(format!("Invalid object #{}", i + 1))
+                .map_err(Error::from)
+        })
+        .collect()
+}
+
+pub fn db_add_many(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("db_add_many", hlua::function3(move |family: String, objects: AnyLuaValue, options: AnyLuaValue| -> Result<AnyLuaValue> {
+        let family = Family::from_str(&family)
+            .map_err(|e| state.set_error(e))?;
+
+        let options = structs::from_lua::<AddOptions>(options.into())
+            .context("invalid insert options")
+            .map_err(|e| state.set_error(e.into()))?;
+
+        let objects = into_inserts(family, LuaJsonValue::from(objects), |object| state.run_insert_hook(family, object))
+            .map_err(|e| state.set_error(e))?;
+        let kept = objects.iter()
+            .map(Option::is_some)
+            .collect::<Vec<_>>();
+
+        let results = state.db_insert_many(objects.into_iter().flatten().collect(), options.into())
+            .map_err(|e| state.set_error(e))?;
+
+        // dropped objects don't have an id, like skipped ones
+        let mut results = results.into_iter();
+        let results = kept.into_iter()
+            .map(|kept| if kept {
+                results.next().unwrap_or(Ok(None))
+            } else {
+                Ok(None)
+            });
+
+        let mut list = LuaList::new();
+        for result in results {
+            let mut row = LuaMap::new();
+            match result {
+                Ok(Some(id)) => row.insert_num("id", f64::from(id)),
+                Ok(None) => (),
+                Err(err) => row.insert_str("error", err),
+            }
+            list.push(row);
+        }
+        Ok(list.into())
+    }))
+}
+
+fn link_insert(from: &str, from_id: i32, to: &str, to_id: i32) -> Result<Insert> {
+    let obj = match (from, to) {
+        ("subdomain", "ipaddr") => Insert::SubdomainIpAddr(NewSubdomainIpAddr {
+            subdomain_id: from_id,
+            ip_addr_id: to_id,
+        }),
+        ("ipaddr", "subdomain") => return link_insert(to, to_id, from, from_id),
+        ("network", "device") => Insert::NetworkDevice(NewNetworkDevice {
+            network_id: from_id,
+            device_id: to_id,
+            ipaddr: None,
+            last_seen: None,
+        }),
+        ("device", "network") => return link_insert(to, to_id, from, from_id),
+        ("breach", "email") => Insert::BreachEmail(NewBreachEmail {
+            breach_id: from_id,
+            email_id: to_id,
+            password: None,
+        }),
+        ("email", "breach") => return link_insert(to, to_id, from, from_id),
+        _ => bail!("Entities can't be linked: {:?} -> {:?}", from, to),
+    };
+    Ok(obj)
+}
+
+pub fn db_link(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("db_link", hlua::function4(move |from: String, from_id: i32, to: String, to_id: i32| -> Result<Option<i32>> {
+        let object = link_insert(&from, from_id, &to, to_id)
+            .map_err(|e| state.set_error(e))?;
+
+        state.db_insert(object)
+            .map_err(|e| state.set_error(e))
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct SelectFilter {
+    #[serde(rename="where", default)]
+    fields: HashMap<String, serde_json::Value>,
+    limit: Option<usize>,
+}
+
+pub fn db_select(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("db_select", hlua::function2(move |family: String, filter: AnyLuaValue| -> Result<AnyLuaValue> {
+        let family = Family::from_str(&family)
+            .map_err(|e| state.set_error(e))?;
+
+        // a plain value returns the id of that entity, a table returns all matching entities
+        match filter {
+            AnyLuaValue::LuaString(value) => {
+                let id = state.db_select(family, value)
+                    .map_err(|e| state.set_error(e))?;
+                Ok(id.map(|id| AnyLuaValue::LuaNumber(f64::from(id)))
+                    .unwrap_or(AnyLuaValue::LuaNil))
+            },
+            filter => {
+                let filter = structs::from_lua::<SelectFilter>(filter.into())
+                    .map_err(|e| state.set_error(e))?;
+
+                let rows = state.db_query(QueryEvent {
+                    family,
+                    fields: filter.fields,
+                    limit: filter.limit,
+                    after: None,
+                }).map_err(|e| state.set_error(e))?;
+
+                Ok(lua_rows(rows))
+            },
+        }
+    }))
+}
+
+fn lua_rows(rows: Vec<serde_json::Value>) -> AnyLuaValue {
+    AnyLuaValue::LuaArray(rows.into_iter()
+        .enumerate()
+        .map(|(i, x)| (AnyLuaValue::LuaNumber((i + 1) as f64), LuaJsonValue::from(x).into()))
+        .collect())
+}
+
+/// Batches of db_cursor_next are capped so a single reply stays small
+const MAX_CURSOR_BATCH: usize = 10_000;
+
+/// Position of a db_cursor, entities are returned in the order of their id
+/// so entities that are added while iterating show up at the end
+#[derive(Debug)]
+pub struct DbCursor {
+    family: Family,
+    fields: HashMap<String, serde_json::Value>,
+    after: i32,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CursorFilter {
+    #[serde(rename="where", default)]
+    fields: HashMap<String, serde_json::Value>,
+    /// Resume after this id
+    #[serde(default)]
+    after: i32,
+}
+
+pub fn db_cursor(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("db_cursor", hlua::function2(move |family: String, filter: AnyLuaValue| -> Result<String> {
+        let family = Family::from_str(&family)
+            .map_err(|e| state.set_error(e))?;
+
+        let filter = match filter {
+            AnyLuaValue::LuaNil => CursorFilter::default(),
+            filter => structs::from_lua::<CursorFilter>(filter.into())
+                .map_err(|e| state.set_error(e))?,
+        };
+
+        Ok(state.cursor_register(DbCursor {
+            family,
+            fields: filter.fields,
+            after: filter.after,
+        }))
+    }))
+}
+
+pub fn db_cursor_next(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("db_cursor_next", hlua::function2(move |cursor: String, batch_size: i32| -> Result<AnyLuaValue> {
+        if batch_size < 1 || batch_size as usize > MAX_CURSOR_BATCH {
+            let e = format_err!("Batch size must be between 1 and {}", MAX_CURSOR_BATCH);
+            return Err(state.set_error(e));
+        }
+
+        let cursor = state.get_cursor(&cursor)
+            .map_err(|e| state.set_error(e))?;
+        let mut cursor = cursor.lock().unwrap();
+
+        let rows = state.db_query(QueryEvent {
+            family: cursor.family,
+            fields: cursor.fields.clone(),
+            limit: Some(batch_size as usize),
+            after: Some(cursor.after),
+        }).map_err(|e| state.set_error(e))?;
+
+        if let Some(id) = rows.last().and_then(|row| row["id"].as_i64()) {
+            cursor.after = id as i32;
+        }
+
+        Ok(lua_rows(rows))
+    }))
+}
+
+/// Keys of the lua table that didn't end up in the update struct
+fn unknown_fields<U: Serialize>(keys: Vec<String>, update: &U) -> Result<Vec<String>> {
+    // every field of the update struct is serialized, including unset ones
+    let fields = serde_json::to_value(update)?;
+    Ok(keys.into_iter()
+        .filter(|key| fields.get(key).is_none())
+        .collect())
+}
+
+/// Unknown fields are passed to `warn` and ignored, existing modules rely on this
+fn gen_changeset<T: Model, U: Updateable<T>>(object: LuaJsonValue, update: LuaJsonValue, warn: &dyn Fn(&str)) -> Result<(i32, String, U)>
     where
         for<'de> T: serde::Deserialize<'de>,
-        for<'de> U: serde::Deserialize<'de>,
+        for<'de> U: Serialize + serde::Deserialize<'de>,
 {
     let existing = structs::from_lua::<T>(object)?;
 
+    let mut update = match update {
+        LuaJsonValue::Object(update) => update,
+        _ => bail!("Update needs to be a table"),
+    };
     // copy the id over to the update struct so we can identify the row
-    if let LuaJsonValue::Object(ref mut update) = update {
-        update.insert("id".into(), LuaJsonValue::Number(existing.id().into()));
-    }
+    update.insert("id".into(), LuaJsonValue::Number(existing.id().into()));
 
-    let mut update = structs::from_lua::<U>(update)?;
+    let keys = update.keys()
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut update = structs::from_lua::<U>(LuaJsonValue::Object(update))?;
+    for key in unknown_fields(keys, &update)? {
+        warn(&key);
+    }
 
     let value = existing.to_string();
     update.changeset(&existing);
@@ -121,37 +415,80 @@ fn gen_changeset<T: Model, U: Updateable<T>>(object: LuaJsonValue, mut update: L
     Ok((existing.id(), value, update))
 }
 
+fn gen_update<U>(id: i32, update: LuaJsonValue) -> Result<(i32, String, U)>
+    where
+        for<'de> U: Serialize + serde::Deserialize<'de>,
+{
+    let mut update = match update {
+        LuaJsonValue::Object(update) => update,
+        _ => bail!("Update needs to be a table"),
+    };
+    update.insert("id".into(), LuaJsonValue::Number(id.into()));
+
+    let keys = update.keys()
+        .cloned()
+        .collect::<Vec<_>>();
+    let update = structs::from_lua::<U>(LuaJsonValue::Object(update))?;
+    if let Some(key) = unknown_fields(keys, &update)?.first() {
+        bail!("Unknown field: {:?}", key);
+    }
+
+    Ok((id, format!("#{}", id), update))
+}
+
+/// Accepts either an existing entity or an entity id as `object`
+fn changeset<T, U>(object: LuaJsonValue, update: LuaJsonValue, warn: &dyn Fn(&str)) -> Result<(i32, String, U)>
+    where
+        for<'de> T: Model + serde::Deserialize<'de>,
+        for<'de> U: Updateable<T> + Serialize + serde::Deserialize<'de>,
+{
+    match object {
+        LuaJsonValue::Number(id) => {
+            let id = id.as_i64()
+                .and_then(|x| i32::try_from(x).ok())
+                .ok_or_else(|| format_err!("Invalid id: {}", id))?;
+            gen_update::<U>(id, update)
+        },
+        object => gen_changeset::<T, U>(object, update, warn),
+    }
+}
+
 pub fn db_update(lua: &mut hlua::Lua, state: Arc<State>) {
     lua.set("db_update", hlua::function3(move |family: String, object: AnyLuaValue, update: AnyLuaValue| -> Result<Option<i32>> {
         let family = Family::from_str(&family)
             .map_err(|e| state.set_error(e))?;
         let object = LuaJsonValue::from(object);
         let update = LuaJsonValue::from(update);
+        let warn = |key: &str| state.error(format!("db_update: ignoring unknown field {:?}", key));
 
         let update = match family {
             Family::Domain => bail!("Domain doesn't have mutable fields"),
-            Family::Subdomain => gen_changeset::<Subdomain, SubdomainUpdate>(object, update)
+            Family::Subdomain => changeset::<Subdomain, SubdomainUpdate>(object, update, &warn)
                 .map(|(id, v, u)| (id, v, Update::Subdomain(u))),
-            Family::IpAddr => gen_changeset::<IpAddr, IpAddrUpdate>(object, update)
+            Family::IpAddr => changeset::<IpAddr, IpAddrUpdate>(object, update, &warn)
                 .map(|(id, v, u)| (id, v, Update::IpAddr(u))),
             Family::SubdomainIpAddr => bail!("Subdomain-IpAddr doesn't have mutable fields"),
-            Family::Url => gen_changeset::<Url, UrlUpdate>(object, update)
+            Family::Url => changeset::<Url, UrlUpdate>(object, update, &warn)
                 .map(|(id, v, u)| (id, v, Update::Url(u))),
-            Family::Email => gen_changeset::<Email, EmailUpdate>(object, update)
+            Family::Email => changeset::<Email, EmailUpdate>(object, update, &warn)
                 .map(|(id, v, u)| (id, v, Update::Email(u))),
-            Family::PhoneNumber => gen_changeset::<PhoneNumber, PhoneNumberUpdate>(object, update)
+            Family::PhoneNumber => changeset::<PhoneNumber, PhoneNumberUpdate>(object, update, &warn)
                 .map(|(id, v, u)| (id, v, Update::PhoneNumber(u))),
-            Family::Device => gen_changeset::<Device, DeviceUpdate>(object, update)
+            Family::Device => changeset::<Device, DeviceUpdate>(object, update, &warn)
                 .map(|(id, v, u)| (id, v, Update::Device(u))),
-            Family::Network => gen_changeset::<Network, NetworkUpdate>(object, update)
+            Family::Network => changeset::<Network, NetworkUpdate>(object, update, &warn)
                 .map(|(id, v, u)| (id, v, Update::Network(u))),
-            Family::NetworkDevice => gen_changeset::<NetworkDevice, NetworkDeviceUpdate>(object, update)
+            Family::NetworkDevice => changeset::<NetworkDevice, NetworkDeviceUpdate>(object, update, &warn)
                 .map(|(id, v, u)| (id, v, Update::NetworkDevice(u))),
-            Family::Account => gen_changeset::<Account, AccountUpdate>(object, update)
+            Family::Account => changeset::<Account, AccountUpdate>(object, update, &warn)
                 .map(|(id, v, u)| (id, v, Update::Account(u))),
             Family::Breach => bail!("Breach doesn't have mutable fields"),
-            Family::BreachEmail => gen_changeset::<BreachEmail, BreachEmailUpdate>(object, update)
+            Family::BreachEmail => changeset::<BreachEmail, BreachEmailUpdate>(object, update, &warn)
                 .map(|(id, v, u)| (id, v, Update::BreachEmail(u))),
+            Family::Port => changeset::<Port, PortUpdate>(object, update, &warn)
+                .map(|(id, v, u)| (id, v, Update::Port(u))),
+            Family::CryptoAddr => changeset::<CryptoAddr, CryptoAddrUpdate>(object, update, &warn)
+                .map(|(id, v, u)| (id, v, Update::CryptoAddr(u))),
         };
 
         let (id, value, update) = update
@@ -165,3 +502,414 @@ pub fn db_update(lua: &mut hlua::Lua, state: Arc<State>) {
         }
     }))
 }
+
+pub fn db_delete(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("db_delete", hlua::function2(move |family: String, id: i32| -> Result<bool> {
+        // unlike other capabilities this is also required if the module
+        // doesn't declare any
+        match state.capabilities() {
+            Some(capabilities) if capabilities.contains(&Capability::Delete) => (),
+            _ => return Err(state.set_error(format_err!("Module needs to declare the \"delete\" capability"))),
+        }
+
+        let family = Family::from_str(&family)
+            .map_err(|e| state.set_error(e))?;
+
+        state.db_delete(family, id)
+            .map_err(|e| state.set_error(e))
+    }))
+}
+
+pub fn report(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("report", hlua::function1(move |finding: AnyLuaValue| -> Result<Option<i32>> {
+        let finding = structs::from_lua::<NewFinding>(finding.into())
+            .map_err(|e| state.set_error(e))?;
+        finding.validate()
+            .map_err(|e| state.set_error(e))?;
+
+        state.db_report(finding)
+            .map_err(|e| state.set_error(e))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_url_insert_is_canonical() {
+        let object = LuaJsonValue::from(serde_json::json!({
+            "subdomain_id": 1,
+            "value": "HTTPS://WWW.Example.com:443/a/./b/../c?",
+            "body": null,
+        }));
+        match into_insert(Family::Url, object).unwrap() {
+            Insert::Url(url) => {
+                assert_eq!(url.value, "https://www.example.com/a/c");
+                assert_eq!(url.path, "/a/c");
+            },
+            x => panic!("unexpected insert: {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_link_either_direction() {
+        let a = link_insert("subdomain", 1, "ipaddr", 2).unwrap();
+        let b = link_insert("ipaddr", 2, "subdomain", 1).unwrap();
+        match (a, b) {
+            (Insert::SubdomainIpAddr(a), Insert::SubdomainIpAddr(b)) => {
+                assert_eq!((a.subdomain_id, a.ip_addr_id), (1, 2));
+                assert_eq!((b.subdomain_id, b.ip_addr_id), (1, 2));
+            },
+            _ => panic!("unexpected link type"),
+        }
+    }
+
+    #[test]
+    fn test_into_inserts() {
+        let objects = LuaJsonValue::Array(vec![
+            LuaJsonValue::from(serde_json::json!({"value": "example.com"})),
+            LuaJsonValue::from(serde_json::json!({"value": "example.org"})),
+        ]);
+        let inserts = into_inserts(Family::Domain, objects, |x| Ok(Some(x))).unwrap();
+        assert_eq!(inserts.len(), 2);
+
+        let empty = LuaJsonValue::Object(HashMap::new());
+        assert!(into_inserts(Family::Domain, empty, |x| Ok(Some(x))).unwrap().is_empty());
+
+        let invalid = LuaJsonValue::Array(vec![
+            LuaJsonValue::from(serde_json::json!({"value": "example.com"})),
+            LuaJsonValue::from(serde_json::json!({"nope": true})),
+        ]);
+        let err = into_inserts(Family::Domain, invalid, |x| Ok(Some(x))).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid object #2");
+    }
+
+    #[test]
+    fn test_into_inserts_dropped() {
+        let objects = LuaJsonValue::Array(vec![
+            LuaJsonValue::from(serde_json::json!({"value": "example.com"})),
+            LuaJsonValue::from(serde_json::json!({"nope": true})),
+        ]);
+        // dropped objects aren't validated
+        let inserts = into_inserts(Family::Domain, objects, |x| {
+            match x {
+                LuaJsonValue::Object(ref o) if o.contains_key("nope") => Ok(None),
+                x => Ok(Some(x)),
+            }
+        }).unwrap();
+        assert_eq!(inserts.len(), 2);
+        assert!(inserts[0].is_some());
+        assert!(inserts[1].is_none());
+    }
+
+    #[test]
+    fn test_update_by_id() {
+        let mut update = HashMap::new();
+        update.insert("asn".to_string(), LuaJsonValue::Number(1337.into()));
+        let (id, _, update) = gen_update::<IpAddrUpdate>(1, LuaJsonValue::Object(update)).unwrap();
+        assert_eq!(id, 1);
+        assert_eq!(update.id, 1);
+        assert_eq!(update.asn, Some(1337));
+    }
+
+    #[test]
+    fn test_update_unknown_field() {
+        let mut update = HashMap::new();
+        update.insert("nope".to_string(), LuaJsonValue::Bool(true));
+        assert!(gen_update::<IpAddrUpdate>(1, LuaJsonValue::Object(update)).is_err());
+    }
+
+    #[test]
+    fn test_changeset_unknown_field() {
+        let subdomain = || {
+            let mut object = HashMap::new();
+            object.insert("id".to_string(), LuaJsonValue::Number(1.into()));
+            object.insert("domain_id".to_string(), LuaJsonValue::Number(1.into()));
+            object.insert("value".to_string(), LuaJsonValue::String("www.example.com".into()));
+            object.insert("unscoped".to_string(), LuaJsonValue::Bool(false));
+            LuaJsonValue::Object(object)
+        };
+
+        let mut update = HashMap::new();
+        update.insert("resolvable".to_string(), LuaJsonValue::Bool(true));
+        let (id, _, update) = gen_changeset::<Subdomain, SubdomainUpdate>(subdomain(), LuaJsonValue::Object(update), &|key| panic!("unexpected warning: {}", key)).unwrap();
+        assert_eq!(id, 1);
+        assert_eq!(update.resolvable, Some(true));
+
+        // unknown fields are ignored with a warning
+        let mut update = HashMap::new();
+        update.insert("resolvable".to_string(), LuaJsonValue::Bool(true));
+        update.insert("nope".to_string(), LuaJsonValue::Bool(true));
+        let warnings = RefCell::new(Vec::new());
+        let (_, _, update) = gen_changeset::<Subdomain, SubdomainUpdate>(subdomain(), LuaJsonValue::Object(update), &|key| warnings.borrow_mut().push(key.to_string())).unwrap();
+        assert_eq!(update.resolvable, Some(true));
+        assert_eq!(warnings.into_inner(), vec!["nope"]);
+    }
+
+    #[test]
+    fn test_update_id_out_of_range() {
+        let id = LuaJsonValue::Number((i64::from(i32::MAX) + 1).into());
+        assert!(changeset::<IpAddr, IpAddrUpdate>(id, LuaJsonValue::Object(HashMap::new()), &|_| ()).is_err());
+    }
+
+    #[test]
+    fn test_link_invalid() {
+        assert!(link_insert("domain", 1, "ipaddr", 2).is_err());
+        assert!(link_insert("subdomain", 1, "subdomain", 2).is_err());
+    }
+
+    #[test]
+    fn verify_delete_capability() {
+        use crate::engine::ctx::Script;
+
+        let script = Script::load_unchecked(r#"
+        function run()
+            db_delete('subdomain', 1)
+        end
+        "#).expect("Failed to load script");
+
+        for capabilities in &[None, Some(vec![Capability::Network])] {
+            let err = script.test_with_capabilities(capabilities.clone()).unwrap_err();
+            assert!(err.to_string().contains("delete"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn verify_undeclared_insert_blocked() {
+        use crate::engine::ctx::Script;
+
+        for code in &[
+            "db_add('domain', {value='example.com'})",
+            "db_add_ttl('domain', {value='example.com'}, 60)",
+            "db_add_many('domain', {{value='example.com'}}, {})",
+            "db_link('subdomain', 1, 'ipaddr', 2)",
+        ] {
+            let script = Script::load_unchecked(format!(r#"
+            function run()
+                {}
+                if last_err() then
+                    error(last_err())
+                end
+            end
+            "#, code)).expect("Failed to load script");
+
+            let err = script.test_with_produces(Some(vec![Family::Subdomain])).unwrap_err();
+            assert!(err.to_string().contains("Module needs to declare that it produces"), "{}: {}", code, err);
+        }
+    }
+
+    /// The values of every entity that has been sent to the database
+    fn inserted(events: &[serde_json::Value]) -> Vec<String> {
+        events.iter()
+            .filter_map(|event| {
+                let insert = &event["Database"]["Insert"];
+                insert.as_object()?
+                    .values()
+                    .next()?["value"]
+                    .as_str()
+                    .map(String::from)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn verify_insert_hook_drops() {
+        use crate::engine::ctx::Script;
+        use crate::engine::tests::{shared, RecordingReporter};
+
+        let hook = r#"
+        function insert(family, obj)
+            if family == 'subdomain' and regex_find('^staging\\.', obj['value']) then
+                return nil
+            end
+            obj['value'] = obj['value']:lower()
+            return obj
+        end
+        "#;
+
+        let script = Script::load_unchecked(r#"
+        function run()
+            values = {'staging.example.com', 'WWW.example.com'}
+            for i=1, #values do
+                value = values[i]
+                id = db_add('subdomain', {domain_id=1, value=value})
+                if last_err() then return end
+                if value == 'staging.example.com' and id ~= nil then
+                    return 'dropped entity has an id'
+                end
+            end
+
+            x = db_add_many('subdomain', {
+                {domain_id=1, value='staging.example.org'},
+                {domain_id=1, value='API.example.org'},
+            }, {})
+            if last_err() then return end
+            if x[1]['id'] ~= nil or x[2]['id'] ~= 1 then
+                return 'unexpected db_add_many results'
+            end
+        end
+        "#).unwrap();
+
+        let reporter = RecordingReporter::default();
+        let events = reporter.events();
+        script.test_with_insert_hook(hook, shared(Box::new(reporter))).expect("Failed to run script");
+
+        let events = events.lock().unwrap();
+        assert_eq!(inserted(&events), vec!["www.example.com"]);
+        let batch = events.iter()
+            .find(|e| e.get("InsertBatch").is_some())
+            .expect("no batch insert");
+        let batch = batch["InsertBatch"]["objects"].as_array().unwrap();
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0]["Subdomain"]["value"], "api.example.org");
+    }
+
+    #[test]
+    fn verify_insert_hook_no_recursion() {
+        use crate::engine::ctx::Script;
+        use crate::engine::tests::{shared, RecordingReporter};
+
+        // the hook adding entities itself doesn't run the hook again
+        let hook = r#"
+        function insert(family, obj)
+            if family == 'subdomain' then
+                db_add('subdomain', {domain_id=obj['domain_id'], value='www.' .. obj['value']})
+            end
+            return obj
+        end
+        "#;
+
+        let script = Script::load_unchecked(r#"
+        function run()
+            db_add('subdomain', {domain_id=1, value='example.com'})
+            if last_err() then return end
+        end
+        "#).unwrap();
+
+        let reporter = RecordingReporter::default();
+        let events = reporter.events();
+        script.test_with_insert_hook(hook, shared(Box::new(reporter))).expect("Failed to run script");
+
+        let events = events.lock().unwrap();
+        assert_eq!(inserted(&events), vec!["www.example.com", "example.com"]);
+    }
+
+    #[test]
+    fn verify_insert_hook_invalid() {
+        use crate::engine::ctx::Script;
+        use crate::engine::tests::{shared, RecordingReporter};
+
+        let script = Script::load_unchecked("function run() end").unwrap();
+        let reporter = shared(Box::new(RecordingReporter::default()));
+        let err = script.test_with_insert_hook("function other() end", reporter).unwrap_err();
+        assert!(err.to_string().contains("insert hook doesn't define insert"), "{}", err);
+
+        let script = Script::load_unchecked(r#"
+        function run()
+            db_add('domain', {value='example.com'})
+            if last_err() then
+                error(last_err())
+            end
+        end
+        "#).unwrap();
+        let reporter = shared(Box::new(RecordingReporter::default()));
+        let err = script.test_with_insert_hook("function insert(family, obj) return obj['x']['y'] end", reporter).unwrap_err();
+        assert!(err.to_string().contains("Insert hook failed"), "{}", err);
+    }
+
+    /// Answers queries from an in-memory database
+    struct DbReporter {
+        db: crate::db::Database,
+        reply: serde_json::Value,
+    }
+
+    impl std::fmt::Debug for DbReporter {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.debug_struct("DbReporter").finish()
+        }
+    }
+
+    impl crate::engine::Reporter for DbReporter {
+        fn send(&mut self, event: &crate::worker::Event) -> Result<()> {
+            if let crate::worker::Event::Query(query) = event {
+                let result = crate::db::Filter::from_fields(&query.fields)
+                    .and_then(|filter| self.db.query(&query.family, &filter, query.limit, query.after))
+                    .map_err(|e| e.to_string());
+                self.reply = serde_json::to_value(result)?;
+            }
+            Ok(())
+        }
+
+        fn recv(&mut self) -> Result<serde_json::Value> {
+            Ok(self.reply.take())
+        }
+    }
+
+    #[test]
+    fn verify_db_cursor() {
+        use crate::db::{Database, Filter};
+        use crate::engine::ctx::Script;
+        use crate::engine::tests::shared;
+
+        let db = Database::in_memory().unwrap();
+        for i in 1..=2500 {
+            db.insert_generic(Insert::Domain(NewDomain {
+                value: format!("{}.example.com", i),
+            })).unwrap();
+        }
+        // every tenth domain is out of scope
+        db.noscope::<Domain>(&Filter::new("id % 10 = 0")).unwrap();
+
+        let script = Script::load_unchecked(r#"
+        function run()
+            cursor = db_cursor('domain', {})
+            count = 0
+            last = 0
+            while true do
+                batch = db_cursor_next(cursor, 300)
+                if last_err() then return end
+                if #batch == 0 then break end
+                if #batch > 300 then return 'batch is too large' end
+
+                for i=1,#batch do
+                    id = batch[i]['id']
+                    if id <= last then return 'ids are out of order' end
+                    if id % 10 == 0 then return 'unscoped domain returned' end
+                    if batch[i]['value'] ~= id .. '.example.com' then return 'unexpected value' end
+                    last = id
+                end
+                count = count + #batch
+            end
+            if count ~= 2250 then return 'expected 2250 domains, got ' .. count end
+
+            -- resume from a known id and filter by column
+            cursor = db_cursor('domain', {after=2495})
+            batch = db_cursor_next(cursor, 100)
+            if #batch ~= 4 or batch[1]['id'] ~= 2496 then return 'failed to resume' end
+
+            cursor = db_cursor('domain', {where={value='42.example.com'}})
+            batch = db_cursor_next(cursor, 100)
+            if #batch ~= 1 or batch[1]['id'] ~= 42 then return 'filter not applied' end
+        end
+        "#).unwrap();
+
+        let reporter = DbReporter {
+            db,
+            reply: serde_json::Value::Null,
+        };
+        script.test_with_reporter(shared(Box::new(reporter))).expect("Script failed");
+    }
+
+    #[test]
+    fn verify_db_cursor_invalid() {
+        use crate::engine::ctx::Script;
+
+        for code in &["db_cursor_next('invalid', 10)", "db_cursor_next(db_cursor('domain', {}), 0)", "db_cursor('invalid', {})"] {
+            let script = Script::load_unchecked(format!("function run() {} end", code)).unwrap();
+            assert!(script.test().is_err(), "{}", code);
+        }
+    }
+}
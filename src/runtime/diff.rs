@@ -0,0 +1,507 @@
+use crate::errors::*;
+
+use crate::engine::ctx::State;
+use crate::hlua;
+use std::cmp;
+use std::sync::Arc;
+
+
+/// Lines of context around each change
+const CONTEXT: usize = 3;
+/// Give up on finding the shortest diff after this many edits, the rest is
+/// replaced as a whole. This keeps memory bounded for unrelated inputs.
+const MAX_EDITS: usize = 1000;
+const NO_NEWLINE: &str = "\\ No newline at end of file";
+
+/// Split after every newline, the last line might not have one
+fn lines(x: &str) -> Vec<&str> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for (i, c) in x.char_indices() {
+        if c == '\n' {
+            lines.push(&x[start..=i]);
+            start = i + 1;
+        }
+    }
+    if start < x.len() {
+        lines.push(&x[start..]);
+    }
+    lines
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+impl Edit {
+    fn old_line(self) -> Option<usize> {
+        match self {
+            Edit::Equal(i, _) | Edit::Delete(i) => Some(i),
+            Edit::Insert(_) => None,
+        }
+    }
+
+    fn new_line(self) -> Option<usize> {
+        match self {
+            Edit::Equal(_, j) | Edit::Insert(j) => Some(j),
+            Edit::Delete(_) => None,
+        }
+    }
+
+    fn is_change(self) -> bool {
+        self.old_line().is_none() || self.new_line().is_none()
+    }
+}
+
+/// Myers' diff algorithm, `a` and `b` are expected to have no common prefix
+/// or suffix. Returns None if the diff would need more than MAX_EDITS edits.
+fn myers(a: &[&str], b: &[&str]) -> Option<Vec<Edit>> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max = cmp::min((n + m) as usize, MAX_EDITS) as isize;
+
+    // v[k] is the furthest x on diagonal k, offset by max so it can be indexed
+    let idx = |k: isize| (k + max + 1) as usize;
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    let mut trace = Vec::new();
+
+    let mut found = None;
+    'outer: for d in 0..=max {
+        trace.push(v.clone());
+        let mut k = -d;
+        while k <= d {
+            let mut x = if k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+                v[idx(k + 1)]
+            } else {
+                v[idx(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx(k)] = x;
+            if x >= n && y >= m {
+                found = Some(d);
+                break 'outer;
+            }
+            k += 2;
+        }
+    }
+    let found = found?;
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (0..=found).rev() {
+        let v = &trace[d as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[idx(prev_k)];
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Equal(x as usize, y as usize));
+        }
+        if d > 0 {
+            if x == prev_x {
+                edits.push(Edit::Insert(prev_y as usize));
+            } else {
+                edits.push(Edit::Delete(prev_x as usize));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+
+    edits.reverse();
+    Some(edits)
+}
+
+fn edits(a: &[&str], b: &[&str]) -> Vec<Edit> {
+    let prefix = a.iter().zip(b)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let a_mid = &a[prefix..a.len() - suffix];
+    let b_mid = &b[prefix..b.len() - suffix];
+
+    let mut edits = (0..prefix)
+        .map(|i| Edit::Equal(i, i))
+        .collect::<Vec<_>>();
+
+    match myers(a_mid, b_mid) {
+        Some(mid) => edits.extend(mid.into_iter().map(|e| match e {
+            Edit::Equal(i, j) => Edit::Equal(i + prefix, j + prefix),
+            Edit::Delete(i) => Edit::Delete(i + prefix),
+            Edit::Insert(j) => Edit::Insert(j + prefix),
+        })),
+        None => {
+            edits.extend((0..a_mid.len()).map(|i| Edit::Delete(i + prefix)));
+            edits.extend((0..b_mid.len()).map(|j| Edit::Insert(j + prefix)));
+        },
+    }
+
+    let a_end = a.len() - suffix;
+    let b_end = b.len() - suffix;
+    edits.extend((0..suffix).map(|i| Edit::Equal(a_end + i, b_end + i)));
+    edits
+}
+
+fn push_line(out: &mut String, prefix: char, line: &str) {
+    out.push(prefix);
+    out.push_str(line);
+    if !line.ends_with('\n') {
+        out.push('\n');
+        out.push_str(NO_NEWLINE);
+        out.push('\n');
+    }
+}
+
+/// Line numbers in hunk headers start at 1, empty ranges point at the line before
+fn range(start: usize, len: usize) -> String {
+    if len == 0 {
+        format!("{},0", start)
+    } else {
+        format!("{},{}", start + 1, len)
+    }
+}
+
+/// Create a unified diff, returns an empty string if both are identical
+pub fn diff(a: &str, b: &str) -> String {
+    let a = lines(a);
+    let b = lines(b);
+    let edits = edits(&a, &b);
+
+    let mut out = String::new();
+    let mut i = 0;
+    while i < edits.len() {
+        // find the next change
+        match edits[i..].iter().position(|e| e.is_change()) {
+            Some(n) => i += n,
+            None => break,
+        }
+
+        // extend the hunk until there's enough unchanged lines in between
+        let start = i.saturating_sub(CONTEXT);
+        let mut end = i;
+        let mut equal = 0;
+        while end < edits.len() {
+            if edits[end].is_change() {
+                equal = 0;
+            } else {
+                equal += 1;
+            }
+            end += 1;
+            if equal > 2 * CONTEXT {
+                break;
+            }
+        }
+        let end = end - equal.saturating_sub(CONTEXT);
+        let hunk = &edits[start..end];
+
+        let a_start = edits[..start].iter().filter(|e| e.old_line().is_some()).count();
+        let b_start = edits[..start].iter().filter(|e| e.new_line().is_some()).count();
+        let a_len = hunk.iter().filter(|e| e.old_line().is_some()).count();
+        let b_len = hunk.iter().filter(|e| e.new_line().is_some()).count();
+
+        if out.is_empty() {
+            out.push_str("--- a\n+++ b\n");
+        }
+        out.push_str(&format!("@@ -{} +{} @@\n", range(a_start, a_len), range(b_start, b_len)));
+        for e in hunk {
+            match *e {
+                Edit::Equal(i, _) => push_line(&mut out, ' ', a[i]),
+                Edit::Delete(i) => push_line(&mut out, '-', a[i]),
+                Edit::Insert(j) => push_line(&mut out, '+', b[j]),
+            }
+        }
+
+        i = end;
+    }
+
+    out
+}
+
+#[derive(Debug, PartialEq)]
+struct Hunk {
+    /// index of the first line in the old text
+    start: usize,
+    old: Vec<String>,
+    new: Vec<String>,
+}
+
+fn parse_range(x: &str) -> Result<(usize, usize)> {
+    let mut parts = x.splitn(2, ',');
+    let start = parts.next().unwrap_or("").parse::<usize>()?;
+    let len = match parts.next() {
+        Some(len) => len.parse::<usize>()?,
+        None => 1,
+    };
+    let start = if len == 0 { start } else { start.saturating_sub(1) };
+    Ok((start, len))
+}
+
+fn parse_header(line: &str) -> Result<(usize, usize, usize)> {
+    let mut parts = line.split_whitespace();
+    let (at1, old, new, at2) = (parts.next(), parts.next(), parts.next(), parts.next());
+    let (old, new) = match (at1, old, new, at2) {
+        (Some("@@"), Some(old), Some(new), Some("@@")) if old.starts_with('-') && new.starts_with('+') => (old, new),
+        _ => bail!("Invalid hunk header: {:?}", line.trim_end()),
+    };
+    let (start, old_len) = parse_range(&old[1..])
+        .context(format!("Invalid hunk header: {:?}", line.trim_end()))?;
+    let (_, new_len) = parse_range(&new[1..])
+        .context(format!("Invalid hunk header: {:?}", line.trim_end()))?;
+    Ok((start, old_len, new_len))
+}
+
+fn parse_patch(patch: &str) -> Result<Vec<Hunk>> {
+    let lines = lines(patch);
+    let mut hunks = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i];
+        i += 1;
+        if !line.starts_with("@@") {
+            // file headers and anything else in between hunks
+            continue;
+        }
+
+        let (start, old_len, new_len) = parse_header(line)?;
+        let mut hunk = Hunk {
+            start,
+            old: Vec::new(),
+            new: Vec::new(),
+        };
+
+        while hunk.old.len() < old_len || hunk.new.len() < new_len {
+            let line = match lines.get(i) {
+                Some(line) => *line,
+                None => bail!("Hunk #{} is truncated", hunks.len() + 1),
+            };
+            i += 1;
+
+            let (prefix, content) = match line.chars().next() {
+                Some(c) => (c, &line[c.len_utf8()..]),
+                None => bail!("Hunk #{} is truncated", hunks.len() + 1),
+            };
+            match prefix {
+                ' ' => {
+                    hunk.old.push(content.to_string());
+                    hunk.new.push(content.to_string());
+                },
+                '-' => hunk.old.push(content.to_string()),
+                '+' => hunk.new.push(content.to_string()),
+                // some tools strip the space of empty context lines
+                '\n' => {
+                    hunk.old.push(line.to_string());
+                    hunk.new.push(line.to_string());
+                },
+                _ => bail!("Invalid line in hunk #{}: {:?}", hunks.len() + 1, line.trim_end()),
+            }
+
+            if lines.get(i).map(|l| l.starts_with('\\')).unwrap_or(false) {
+                i += 1;
+                let last = match prefix {
+                    '-' => hunk.old.last_mut(),
+                    '+' => hunk.new.last_mut(),
+                    _ => {
+                        if let Some(old) = hunk.old.last_mut() {
+                            old.pop();
+                        }
+                        hunk.new.last_mut()
+                    },
+                };
+                if let Some(last) = last {
+                    last.pop();
+                }
+            }
+        }
+
+        if hunk.old.len() != old_len || hunk.new.len() != new_len {
+            bail!("Hunk #{} doesn't match its header", hunks.len() + 1);
+        }
+        hunks.push(hunk);
+    }
+
+    Ok(hunks)
+}
+
+/// Apply a unified diff, every hunk needs to match exactly
+fn patch(a: &str, patch: &str) -> Result<String> {
+    let a = lines(a);
+    let hunks = parse_patch(patch)?;
+
+    let mut out = String::new();
+    let mut pos = 0;
+    for (n, hunk) in hunks.iter().enumerate() {
+        if hunk.start < pos || hunk.start + hunk.old.len() > a.len() {
+            bail!("Hunk #{} doesn't apply: line {} is out of range", n + 1, hunk.start + 1);
+        }
+
+        for (i, expected) in hunk.old.iter().enumerate() {
+            let found = a[hunk.start + i];
+            if found != expected {
+                bail!("Hunk #{} doesn't apply at line {}: expected {:?}, found {:?}",
+                      n + 1, hunk.start + i + 1, expected, found);
+            }
+        }
+
+        for line in &a[pos..hunk.start] {
+            out.push_str(line);
+        }
+        for line in &hunk.new {
+            out.push_str(line);
+        }
+        pos = hunk.start + hunk.old.len();
+    }
+
+    for line in &a[pos..] {
+        out.push_str(line);
+    }
+
+    Ok(out)
+}
+
+pub fn text_diff(lua: &mut hlua::Lua, _: Arc<State>) {
+    lua.set("text_diff", hlua::function2(move |a: String, b: String| -> String {
+        diff(&a, &b)
+    }))
+}
+
+pub fn text_patch(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("text_patch", hlua::function2(move |a: String, p: String| -> Result<String> {
+        patch(&a, &p)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ctx::Script;
+
+    fn roundtrip(a: &str, b: &str) {
+        let d = diff(a, b);
+        assert_eq!(patch(a, &d).unwrap(), b, "{}", d);
+    }
+
+    #[test]
+    fn test_diff() {
+        let d = diff("a\nb\nc\n", "a\nx\nc\n");
+        assert_eq!(d, "--- a\n+++ b\n@@ -1,3 +1,3 @@\n a\n-b\n+x\n c\n");
+    }
+
+    #[test]
+    fn test_diff_identical() {
+        assert_eq!(diff("a\nb\n", "a\nb\n"), "");
+        assert_eq!(diff("", ""), "");
+    }
+
+    #[test]
+    fn test_diff_context() {
+        let a = (1..=20).map(|i| format!("{}\n", i)).collect::<String>();
+        let b = (1..=20).map(|i| match i {
+            2 => "two\n".to_string(),
+            18 => "eighteen\n".to_string(),
+            _ => format!("{}\n", i),
+        }).collect::<String>();
+        let d = diff(&a, &b);
+        assert_eq!(d, "--- a\n+++ b\n\
+                       @@ -1,5 +1,5 @@\n 1\n-2\n+two\n 3\n 4\n 5\n\
+                       @@ -15,6 +15,6 @@\n 15\n 16\n 17\n-18\n+eighteen\n 19\n 20\n");
+        roundtrip(&a, &b);
+    }
+
+    #[test]
+    fn test_no_newline() {
+        let d = diff("a\nb", "a\nb\n");
+        assert_eq!(d, "--- a\n+++ b\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+b\n");
+        roundtrip("a\nb", "a\nb\n");
+        roundtrip("a\nb\n", "a\nc");
+        roundtrip("a", "b");
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        roundtrip("", "a\nb\n");
+        roundtrip("a\nb\n", "");
+        roundtrip("a\nb\nc\nd\ne\n", "b\nc\nx\ne\nf\n");
+        roundtrip("x\ny\n", "a\nb\nx\ny\n");
+
+        let a = (0..500).map(|i| format!("line {}\n", i)).collect::<String>();
+        let b = (0..500).map(|i| if i % 7 == 0 {
+            format!("changed {}\n", i)
+        } else {
+            format!("line {}\n", i)
+        }).collect::<String>();
+        roundtrip(&a, &b);
+    }
+
+    #[test]
+    fn test_unrelated_inputs() {
+        let a = (0..2000).map(|i| format!("a {}\n", i)).collect::<String>();
+        let b = (0..2000).map(|i| format!("b {}\n", i)).collect::<String>();
+        roundtrip(&a, &b);
+    }
+
+    #[test]
+    fn test_patch_gnu_format() {
+        // produced by `diff -u`, single lines don't have a count
+        let p = "--- a.txt\t2019-03-01\n+++ b.txt\t2019-03-01\n@@ -2 +2 @@\n-b\n+x\n";
+        assert_eq!(patch("a\nb\nc\n", p).unwrap(), "a\nx\nc\n");
+    }
+
+    #[test]
+    fn test_patch_fails() {
+        let d = diff("a\nb\nc\n", "a\nx\nc\n");
+        let err = patch("a\ny\nc\n", &d).unwrap_err();
+        assert_eq!(err.to_string(), "Hunk #1 doesn't apply at line 2: expected \"b\\n\", found \"y\\n\"");
+        assert!(patch("a\n", &d).is_err());
+        assert!(patch("a\nb\nc\n", "@@ -1,3 +1,3 @@\n a\n").is_err());
+        assert!(patch("a\nb\nc\n", "@@ foo @@\n").is_err());
+    }
+
+    #[test]
+    fn verify_text_diff_patch() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            old = "a\nb\nc\n"
+            new = "a\nx\nc\nd\n"
+            d = text_diff(old, new)
+            x = text_patch(old, d)
+            if x ~= new then
+                return 'roundtrip failed: ' .. x
+            end
+            if text_diff(old, old) ~= '' then
+                return 'expected empty diff'
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_text_patch_fails() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            d = text_diff("a\nb\n", "a\nc\n")
+            text_patch("x\ny\n", d)
+        end
+        "#).expect("Failed to load script");
+        assert!(script.test().is_err());
+    }
+}
@@ -1,14 +1,31 @@
 use crate::errors::*;
 
-use chrootable_https::dns::{Resolver, DnsResolver, RecordType};
+use chrootable_https::dns::{Resolver, DnsResolver, DnsError, DnsReply, RData, RecordType};
+use crate::axfr::{self, AxfrError, Transfer};
 use crate::engine;
 use crate::engine::ctx::State;
+use crate::engine::structs::{self, LuaMap};
 use crate::json::LuaJsonValue;
 use crate::hlua::{self, AnyLuaValue};
+use crate::sockets::{Socket, SocketOptions};
+use futures::future::{self, Loop};
+use futures::{stream, Future, Stream};
+use rand::random;
 use serde_json;
+use sn0int_common::metadata::Capability;
 use std::sync::Arc;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::time::Duration;
+use tokio::runtime::Runtime;
+
+
+/// Connect and read timeout of zone transfers, in milliseconds
+const AXFR_TIMEOUT: u64 = 10_000;
+/// Transfers are stopped after this many records
+const AXFR_MAX_RECORDS: usize = 50_000;
+/// Reverse lookups of dns_reverse_batch that are running at the same time
+const REVERSE_CONCURRENCY: usize = 16;
+const REVERSE_MAX_CONCURRENCY: usize = 64;
 
 
 #[derive(Debug, Deserialize)]
@@ -50,8 +67,68 @@ impl ResolveOptions {
     }
 }
 
+fn resolver(state: &State, nameserver: Option<SocketAddr>, tcp: bool, timeout: Option<u64>) -> Resolver {
+    let ns = match nameserver {
+        Some(ns) => vec![ns],
+        None => state.dns_config().ns.clone(),
+    };
+
+    Resolver {
+        ns,
+        tcp,
+        timeout: timeout.map(Duration::from_millis)
+            .or(state.dns_config().timeout),
+    }
+}
+
+/// Try the nameservers in order, the next one is only asked if the query
+/// fails or the server couldn't answer it. NXDOMAIN is an answer.
+fn resolving_with_fallback(resolver: &Resolver, name: &str, record: RecordType) -> Box<dyn Future<Item=DnsReply, Error=Error> + Send> {
+    if resolver.ns.is_empty() {
+        return Box::new(future::err(format_err!("No nameserver configured")));
+    }
+
+    let resolver = resolver.clone();
+    let name = name.to_string();
+    Box::new(future::loop_fn(0, move |i| {
+        let ns = resolver.ns[i];
+        let single = Resolver {
+            ns: vec![ns],
+            ..resolver.clone()
+        };
+        // if every server fails the last result is returned
+        let last = i + 1 >= resolver.ns.len();
+
+        single.resolve(&name, record)
+            .then(move |result| {
+                match &result {
+                    Ok(DnsReply { error: None, .. }) => (),
+                    Ok(DnsReply { error: Some(DnsError::NXDomain), .. }) => (),
+                    _ if last => (),
+                    Ok(DnsReply { error: Some(err), .. }) => {
+                        debug!("Nameserver {} failed with {:?}, trying next", ns, err);
+                        return Ok(Loop::Continue(i + 1));
+                    },
+                    Err(err) => {
+                        debug!("Nameserver {} failed: {}, trying next", ns, err);
+                        return Ok(Loop::Continue(i + 1));
+                    },
+                }
+                result.map(Loop::Break)
+            })
+    }))
+}
+
+fn resolve_with_fallback(resolver: &Resolver, name: &str, record: RecordType) -> Result<DnsReply> {
+    let mut rt = Runtime::new()?;
+    rt.block_on(resolving_with_fallback(resolver, name, record))
+}
+
 pub fn dns(lua: &mut hlua::Lua, state: Arc<State>) {
     lua.set("dns", hlua::function2(move |name: String, options: AnyLuaValue| -> Result<AnyLuaValue> {
+        state.require(Capability::Network)
+            .map_err(|e| state.set_error(e))?;
+
         if state.proxy().is_some() {
             let e = format_err!("dns is disabled if a proxy is active");
             return Err(state.set_error(e));
@@ -60,34 +137,211 @@ pub fn dns(lua: &mut hlua::Lua, state: Arc<State>) {
         let options = ResolveOptions::from_lua(options)
             .map_err(|e| state.set_error(e))?;
 
-        let ns = match options.nameserver {
-            Some(ns) => vec![ns],
-            None => state.dns_config().ns.clone(),
-        };
+        let resolver = resolver(state.as_ref(), options.nameserver, options.tcp, options.timeout);
 
-        let timeout = options.timeout
-            .map(Duration::from_millis);
+        let record = options.record_type()
+            .map_err(|e| state.set_error(e))?;
+        let reply = resolve_with_fallback(&resolver, &name, record)
+            .map_err(|e| state.set_error(e))?;
 
-        let resolver = Resolver {
-            ns,
-            tcp: options.tcp,
-            timeout,
+        let reply = serde_json::to_value(reply)
+            .map_err(|e| state.set_error(e.into()))?;
+
+        Ok(LuaJsonValue::from(reply).into())
+    }))
+}
+
+/// Split `host:port`, the port defaults to 53
+fn nameserver(server: &str) -> Result<(String, u16)> {
+    if let Ok(addr) = server.parse::<SocketAddr>() {
+        return Ok((addr.ip().to_string(), addr.port()));
+    }
+
+    match server.rfind(':') {
+        Some(idx) if !server[..idx].contains(':') => {
+            let port = server[idx+1..].parse::<u16>()
+                .context("Invalid nameserver port")?;
+            Ok((server[..idx].to_string(), port))
+        },
+        _ => Ok((server.to_string(), 53)),
+    }
+}
+
+/// Read the next message from the connection, None if the connection
+/// was closed or timed out
+fn recv_message(sock: &mut Socket) -> Option<Vec<u8>> {
+    let len = sock.recvn(2).ok()?;
+    let len = u32::from(len[0]) << 8 | u32::from(len[1]);
+    sock.recvn(len).ok()
+}
+
+fn zone_transfer(state: &State, server: &str, zone: &str) -> Result<Transfer> {
+    let (host, port) = nameserver(server)?;
+    let options = SocketOptions {
+        timeout: Some(AXFR_TIMEOUT),
+        ..Default::default()
+    };
+
+    let mut sock = state.sock_open(&host, port, &options)?;
+    let id = random();
+    sock.send(&axfr::query(zone, id)?)?;
+
+    let mut transfer = Transfer::new(id, AXFR_MAX_RECORDS);
+    loop {
+        let msg = match recv_message(&mut sock) {
+            Some(msg) => msg,
+            // keep the records of a transfer that got interrupted
+            None if !transfer.records.is_empty() => break,
+            None => return Err(AxfrError::Closed.into()),
         };
 
-        let reply = resolver.resolve(&name, options.record_type()?)
-            .wait_for_response()
+        if transfer.add(&msg)? {
+            break;
+        }
+    }
+
+    Ok(transfer)
+}
+
+pub fn dns_axfr(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("dns_axfr", hlua::function2(move |server: String, zone: String| -> Result<AnyLuaValue> {
+        state.require(Capability::Network)
             .map_err(|e| state.set_error(e))?;
 
-        let reply = serde_json::to_value(reply)
+        let transfer = zone_transfer(state.as_ref(), &server, &zone)
+            .map_err(|e| state.set_error(e))?;
+
+        let records = serde_json::to_value(transfer.records)
             .map_err(|e| state.set_error(e.into()))?;
 
-        Ok(LuaJsonValue::from(reply).into())
+        let mut reply = LuaMap::new();
+        reply.insert("records", LuaJsonValue::from(records));
+        reply.insert("complete", AnyLuaValue::LuaBoolean(transfer.complete));
+        Ok(reply.into())
     }))
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct ReverseOptions {
+    nameserver: Option<SocketAddr>,
+    #[serde(default)]
+    tcp: bool,
+    timeout: Option<u64>,
+    concurrency: Option<usize>,
+}
+
+impl ReverseOptions {
+    fn concurrency(&self) -> Result<usize> {
+        match self.concurrency {
+            Some(0) => bail!("concurrency must be at least 1"),
+            Some(n) => Ok(n.min(REVERSE_MAX_CONCURRENCY)),
+            None => Ok(REVERSE_CONCURRENCY),
+        }
+    }
+}
+
+/// The name of the PTR record of an ip address
+fn ptr_name(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let o = ip.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", o[3], o[2], o[1], o[0])
+        },
+        IpAddr::V6(ip) => {
+            let mut name = String::new();
+            for b in ip.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", b & 0xf, b >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        },
+    }
+}
+
+/// Resolve the PTR records of all ip addresses, at most `concurrency` queries
+/// are in flight at the same time. Every lookup falls back to the next
+/// nameserver like `dns` does.
+fn reverse_batch(resolver: Resolver, ips: Vec<IpAddr>, concurrency: usize) -> Result<Vec<(IpAddr, Result<DnsReply>)>> {
+    let lookups = stream::iter_ok::<_, Error>(ips)
+        .map(move |ip| {
+            resolving_with_fallback(&resolver, &ptr_name(&ip), RecordType::PTR)
+                .then(move |reply| Ok((ip, reply)))
+        })
+        .buffer_unordered(concurrency)
+        .collect();
+
+    let mut rt = Runtime::new()?;
+    rt.block_on(lookups)
+}
+
+/// The PTR names of a reply, NXDOMAIN is an empty result
+fn ptr_names(reply: DnsReply) -> Result<Vec<String>> {
+    match reply.error {
+        None | Some(DnsError::NXDomain) => (),
+        Some(err) => bail!("{:?}", err),
+    }
+
+    Ok(reply.answers.into_iter()
+        .filter_map(|(_, rdata, _)| match rdata {
+            RData::PTR(name) => Some(name),
+            _ => None,
+        })
+        .collect())
+}
+
+pub fn dns_reverse_batch(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("dns_reverse_batch", hlua::function2(move |ips: AnyLuaValue, options: AnyLuaValue| -> Result<AnyLuaValue> {
+        state.require(Capability::Network)
+            .map_err(|e| state.set_error(e))?;
+
+        if state.proxy().is_some() {
+            let e = format_err!("dns is disabled if a proxy is active");
+            return Err(state.set_error(e));
+        }
+
+        let ips: Vec<String> = match LuaJsonValue::from(ips) {
+            LuaJsonValue::Object(ref x) if x.is_empty() => Vec::new(),
+            x => structs::from_lua(x)
+                .context("ips must be a list of ip addresses")
+                .map_err(|e| state.set_error(e.into()))?,
+        };
+        let ips = ips.iter()
+            .map(|ip| ip.parse::<IpAddr>()
+                .context(format!("Invalid ip address: {:?}", ip))
+                .map_err(Error::from))
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| state.set_error(e))?;
+
+        let options = match options {
+            AnyLuaValue::LuaNil => ReverseOptions::default(),
+            x => structs::from_lua(x.into())
+                .map_err(|e| state.set_error(e))?,
+        };
+        let concurrency = options.concurrency()
+            .map_err(|e| state.set_error(e))?;
+
+        let resolver = resolver(state.as_ref(), options.nameserver, options.tcp, options.timeout);
+        let replies = reverse_batch(resolver, ips, concurrency)
+            .map_err(|e| state.set_error(e))?;
+
+        let mut results = LuaMap::new();
+        for (ip, reply) in replies {
+            // a single failing lookup shouldn't throw away the whole batch
+            match reply.and_then(ptr_names) {
+                Ok(names) => results.insert(ip.to_string(), LuaJsonValue::from(serde_json::Value::from(names))),
+                Err(err) => state.error(format!("Failed to resolve PTR of {}: {}", ip, err)),
+            }
+        }
+
+        Ok(results.into())
+    }))
+}
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::axfr::tests::{response, soa};
+    use crate::config::Config;
     use crate::engine::ctx::Script;
 
     #[test]
@@ -303,4 +557,305 @@ mod tests {
         "#).expect("Failed to load script");
         script.test().expect("Script failed");
     }
+
+    /// Reply to the first query with these messages, then close the connection.
+    /// The id of the messages is set to the id of the query, or to a different one if `wrong_id` is set
+    fn serve_axfr_with(messages: Vec<Vec<u8>>, wrong_id: bool) -> u16 {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use std::thread;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 512];
+            let _ = stream.read_exact(&mut buf[..4]);
+            for mut msg in messages {
+                msg[0] = if wrong_id { !buf[2] } else { buf[2] };
+                msg[1] = buf[3];
+                let _ = stream.write_all(&(msg.len() as u16).to_be_bytes());
+                let _ = stream.write_all(&msg);
+            }
+        });
+
+        port
+    }
+
+    fn serve_axfr(messages: Vec<Vec<u8>>) -> u16 {
+        serve_axfr_with(messages, false)
+    }
+
+    fn axfr_script(port: u16, complete: bool) -> Script {
+        Script::load_unchecked(format!(r#"
+        function run()
+            x = dns_axfr('127.0.0.1:{}', 'example.com')
+            if last_err() then return end
+            records = x['records']
+            if #records < 2 or records[2]['name'] ~= 'www.example.com' or records[2]['data']['A'] ~= '192.0.2.1' then
+                return 'unexpected records'
+            end
+            if records[1]['type'] ~= 'SOA' or records[1]['data']['SOA']['serial'] ~= 2019 then
+                return 'unexpected soa'
+            end
+            if x['complete'] ~= {} then
+                return 'unexpected complete flag'
+            end
+        end
+        "#, port, complete)).expect("Failed to load script")
+    }
+
+    #[test]
+    fn verify_axfr() {
+        let port = serve_axfr(vec![
+            response(0, &[("example.com", 6, soa()), ("www.example.com", 1, vec![192, 0, 2, 1])]),
+            response(0, &[("example.com", 6, soa())]),
+        ]);
+        axfr_script(port, true).test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_axfr_interrupted() {
+        let port = serve_axfr(vec![
+            response(0, &[("example.com", 6, soa()), ("www.example.com", 1, vec![192, 0, 2, 1])]),
+        ]);
+        axfr_script(port, false).test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_axfr_refused() {
+        let port = serve_axfr(vec![response(5, &[])]);
+        let err = axfr_script(port, true).test().unwrap_err();
+        assert!(err.to_string().contains("REFUSED"));
+
+        let port = serve_axfr(vec![]);
+        assert!(axfr_script(port, true).test().is_err());
+    }
+
+    #[test]
+    fn verify_axfr_wrong_id() {
+        let port = serve_axfr_with(vec![
+            response(0, &[("example.com", 6, soa()), ("www.example.com", 1, vec![192, 0, 2, 1])]),
+            response(0, &[("example.com", 6, soa())]),
+        ], true);
+        let err = axfr_script(port, true).test().unwrap_err();
+        assert!(err.to_string().contains("doesn't match"));
+    }
+
+    #[test]
+    fn test_ptr_name() {
+        assert_eq!(super::ptr_name(&"192.0.2.1".parse().unwrap()), "1.2.0.192.in-addr.arpa");
+        assert_eq!(super::ptr_name(&"2001:db8::567:89ab".parse().unwrap()),
+                   "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa");
+    }
+
+    /// Answer every query with the rcode and rdata returned for its labels,
+    /// the answer has the type of the question
+    fn serve_udp<F>(answer: F) -> u16
+        where F: Fn(&[String]) -> (u8, Vec<u8>) + Send + 'static
+    {
+        use std::net::UdpSocket;
+        use std::thread;
+        use std::time::Duration;
+
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let port = sock.local_addr().unwrap().port();
+
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            while let Ok((n, peer)) = sock.recv_from(&mut buf) {
+                let query = &buf[..n];
+
+                // the question starts after the header and ends after qtype and qclass
+                let mut end = 12;
+                let mut labels = Vec::new();
+                while query[end] != 0 {
+                    let len = query[end] as usize;
+                    labels.push(String::from_utf8_lossy(&query[end+1..end+1+len]).to_string());
+                    end += 1 + len;
+                }
+                end += 5;
+                let qtype = &query[end-4..end-2];
+
+                let (rcode, rdata) = answer(&labels);
+                let mut msg = vec![query[0], query[1], 0x81, 0x80 | rcode, 0, 1, 0, (rcode == 0) as u8, 0, 0, 0, 0];
+                msg.extend(&query[12..end]);
+                if rcode == 0 {
+                    msg.extend(&[0xc0, 12, qtype[0], qtype[1], 0, 1, 0, 0, 0x0e, 0x10]);
+                    msg.extend(&(rdata.len() as u16).to_be_bytes());
+                    msg.extend(&rdata);
+                }
+                sock.send_to(&msg, peer).unwrap();
+            }
+        });
+
+        port
+    }
+
+    /// Answer PTR queries for 192.0.2.x with host-x.example.com, 192.0.2.99 is
+    /// NXDOMAIN and 192.0.2.98 is SERVFAIL
+    fn serve_ptr() -> u16 {
+        serve_udp(|labels| {
+            match labels[0].as_str() {
+                "99" => (3, vec![]),
+                "98" => (2, vec![]),
+                host => {
+                    let mut rdata = Vec::new();
+                    for label in &[format!("host-{}", host), "example".to_string(), "com".to_string()] {
+                        rdata.push(label.len() as u8);
+                        rdata.extend(label.as_bytes());
+                    }
+                    rdata.push(0);
+                    (0, rdata)
+                },
+            }
+        })
+    }
+
+    #[test]
+    fn verify_reverse_batch() {
+        let port = serve_ptr();
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            ips = {{}}
+            for i=1,40 do
+                ips[i] = '192.0.2.' .. i
+            end
+            ips[41] = '192.0.2.99'
+            ips[42] = '192.0.2.98'
+
+            x = dns_reverse_batch(ips, {{
+                nameserver='127.0.0.1:{}',
+                timeout=5000,
+                concurrency=8,
+            }})
+            if last_err() then return end
+
+            for i=1,40 do
+                names = x['192.0.2.' .. i]
+                if names == nil or names[1] ~= 'host-' .. i .. '.example.com.' then
+                    return 'unexpected ptr of 192.0.2.' .. i
+                end
+            end
+            if x['192.0.2.99'] == nil or #x['192.0.2.99'] ~= 0 then
+                return 'nxdomain should be empty'
+            end
+            if x['192.0.2.98'] ~= nil then
+                return 'servfail should be missing'
+            end
+        end
+        "#, port)).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_reverse_batch_fallback() {
+        let servfail = serve_udp(|_| (2, vec![]));
+        let resolver = Resolver {
+            ns: vec![nameserver(servfail), nameserver(serve_ptr())],
+            tcp: false,
+            timeout: Some(Duration::from_millis(5000)),
+        };
+
+        let ips = vec!["192.0.2.1".parse().unwrap(), "192.0.2.98".parse().unwrap()];
+        let mut replies = reverse_batch(resolver, ips, 2).unwrap();
+        replies.sort_by_key(|(ip, _)| *ip);
+        let mut replies = replies.into_iter().map(|(_, reply)| reply.and_then(ptr_names));
+
+        assert_eq!(replies.next().unwrap().unwrap(), vec!["host-1.example.com."]);
+        // both servers failed
+        assert!(replies.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn verify_reverse_batch_invalid() {
+        for (ips, options) in &[("{'example.com'}", "nil"), ("'192.0.2.1'", "nil"), ("{'192.0.2.1'}", "{concurrency=0}")] {
+            let script = Script::load_unchecked(format!(r#"
+            function run()
+                dns_reverse_batch({}, {})
+            end
+            "#, ips, options)).expect("Failed to load script");
+            assert!(script.test().is_err(), "{} {}", ips, options);
+        }
+    }
+
+    fn nameserver(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn a_records(reply: &DnsReply) -> Vec<String> {
+        reply.answers.iter()
+            .filter_map(|(_, rdata, _)| match rdata {
+                RData::A(ip) => Some(ip.to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_resolver_config() {
+        let config = toml::from_str::<Config>(r#"
+        [network]
+        resolvers = ["192.0.2.1", "192.0.2.2:5353"]
+
+        [workspaces.acme]
+        resolvers = ["2001:db8::1", "[2001:db8::2]:5353"]
+        "#).unwrap();
+
+        let resolver = config.resolver("default", &[]).unwrap();
+        assert_eq!(resolver.ns, vec!["192.0.2.1:53".parse::<SocketAddr>().unwrap(), "192.0.2.2:5353".parse().unwrap()]);
+        let resolver = config.resolver("acme", &[]).unwrap();
+        assert_eq!(resolver.ns, vec!["[2001:db8::1]:53".parse::<SocketAddr>().unwrap(), "[2001:db8::2]:5353".parse().unwrap()]);
+        let resolver = config.resolver("acme", &["192.0.2.3".to_string()]).unwrap();
+        assert_eq!(resolver.ns, vec!["192.0.2.3:53".parse::<SocketAddr>().unwrap()]);
+
+        assert!(config.resolver("acme", &["example.com".to_string()]).is_err());
+    }
+
+    #[test]
+    fn verify_configured_resolver() {
+        let port = serve_udp(|_| (0, vec![192, 0, 2, 1]));
+        let config = toml::from_str::<Config>(&format!(r#"
+        [workspaces.acme]
+        resolvers = ["127.0.0.1:{}"]
+        "#, port)).unwrap();
+
+        let resolver = config.resolver("acme", &[]).unwrap();
+        let reply = resolve_with_fallback(&resolver, "example.com", RecordType::A).unwrap();
+        assert_eq!(reply.error, None);
+        assert_eq!(a_records(&reply), vec!["192.0.2.1"]);
+    }
+
+    #[test]
+    fn verify_resolver_fallback() {
+        use std::net::UdpSocket;
+
+        // never answers, the query times out
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let silent = silent.local_addr().unwrap().port();
+        let servfail = serve_udp(|_| (2, vec![]));
+        let nxdomain = serve_udp(|_| (3, vec![]));
+        let working = serve_udp(|_| (0, vec![192, 0, 2, 1]));
+
+        let resolver = |ports: &[u16]| Resolver {
+            ns: ports.iter().map(|port| nameserver(*port)).collect(),
+            tcp: false,
+            timeout: Some(Duration::from_millis(500)),
+        };
+
+        let reply = resolve_with_fallback(&resolver(&[silent, servfail, working]), "example.com", RecordType::A).unwrap();
+        assert_eq!(a_records(&reply), vec!["192.0.2.1"]);
+
+        // nxdomain is an answer, don't ask the next server
+        let reply = resolve_with_fallback(&resolver(&[nxdomain, working]), "example.com", RecordType::A).unwrap();
+        assert_eq!(reply.error, Some(DnsError::NXDomain));
+
+        // if every server fails the last reply is returned
+        let reply = resolve_with_fallback(&resolver(&[silent, servfail]), "example.com", RecordType::A).unwrap();
+        assert_eq!(reply.error, Some(DnsError::ServFail));
+        assert!(resolve_with_fallback(&resolver(&[servfail, silent]), "example.com", RecordType::A).is_err());
+        assert!(resolve_with_fallback(&resolver(&[]), "example.com", RecordType::A).is_err());
+    }
 }
@@ -0,0 +1,66 @@
+use crate::errors::*;
+
+use crate::email;
+use crate::engine::ctx::State;
+use crate::hlua::{self, AnyLuaValue};
+use crate::json::LuaJsonValue;
+use std::sync::Arc;
+
+
+pub fn email_parse(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("email_parse", hlua::function1(move |addr: String| -> Result<AnyLuaValue> {
+        let addr = match email::parse(&addr) {
+            Ok(addr) => addr,
+            Err(_) => return Ok(AnyLuaValue::LuaNil),
+        };
+
+        let mut v = serde_json::to_value(&addr)
+            .map_err(|err| state.set_error(err.into()))?;
+        v["normalized"] = addr.to_string().into();
+        Ok(LuaJsonValue::from(v).into())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::ctx::Script;
+
+    #[test]
+    fn verify_email_parse() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = email_parse('John+osint@Example.COM')
+            if x['local'] ~= 'John+osint' or x['domain'] ~= 'example.com' then
+                return 'unexpected address'
+            end
+            if x['user'] ~= 'John' or x['tag'] ~= 'osint' then
+                return 'unexpected plus-addressing'
+            end
+            if x['normalized'] ~= 'John+osint@example.com' then
+                return 'unexpected normalized address: ' .. x['normalized']
+            end
+
+            x = email_parse('"john doe"@example.com')
+            if x['local'] ~= '"john doe"' or x['tag'] ~= nil then
+                return 'unexpected quoted address'
+            end
+        end
+        "#).unwrap();
+        script.test().expect("Failed to run script");
+    }
+
+    #[test]
+    fn verify_email_parse_invalid() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            if email_parse('john@@example.com') ~= nil then
+                return 'expected nil'
+            end
+            if email_parse('not an email') ~= nil then
+                return 'expected nil'
+            end
+        end
+        "#).unwrap();
+        script.test().expect("Failed to run script");
+    }
+}
@@ -0,0 +1,49 @@
+use crate::errors::*;
+
+use crate::db::Family;
+use crate::engine::ctx::State;
+use crate::hlua;
+use std::net::IpAddr;
+use std::sync::Arc;
+use url::Url;
+
+
+pub(super) fn in_scope(state: &Arc<State>, host: &str) -> Result<bool> {
+    if host.parse::<IpAddr>().is_ok() {
+        return Ok(state.db_select(Family::IpAddr, host.to_string())?.is_some());
+    }
+
+    if state.db_select(Family::Subdomain, host.to_string())?.is_some() {
+        return Ok(true);
+    }
+
+    Ok(state.db_select(Family::Domain, host.to_string())?.is_some())
+}
+
+pub fn frontier_push(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("frontier_push", hlua::function1(move |url: String| -> Result<bool> {
+        let mut url = Url::parse(&url)
+            .map_err(|err| state.set_error(Error::from(err)))?;
+        url.set_fragment(None);
+
+        let host = match url.host_str() {
+            Some(host) => host.to_string(),
+            None => return Err(state.set_error(format_err!("Url has no host: {:?}", url.as_str()))),
+        };
+
+        if !in_scope(&state, &host).map_err(|err| state.set_error(err))? {
+            debug!("Dropping out of scope url: {:?}", url.as_str());
+            return Ok(false);
+        }
+
+        let mut frontier = state.frontier().lock().unwrap();
+        Ok(frontier.push(&host, url.into_string()))
+    }))
+}
+
+pub fn frontier_pop(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("frontier_pop", hlua::function0(move || -> Option<String> {
+        let mut frontier = state.frontier().lock().unwrap();
+        frontier.pop()
+    }))
+}
@@ -0,0 +1,76 @@
+use crate::errors::*;
+use crate::engine::ctx::State;
+use crate::hlua::{self, AnyLuaValue};
+use crate::json::LuaJsonValue;
+use sn0int_common::metadata::Capability;
+use serde_json;
+use std::sync::Arc;
+
+
+pub fn fs_read(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("fs_read", hlua::function1(move |path: String| -> Result<String> {
+        state.require(Capability::Filesystem)
+            .map_err(|err| state.set_error(err))?;
+
+        state.fs_read(path)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn fs_list(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("fs_list", hlua::function1(move |path: String| -> Result<AnyLuaValue> {
+        state.require(Capability::Filesystem)
+            .map_err(|err| state.set_error(err))?;
+
+        let entries = state.fs_list(path)
+            .map_err(|err| state.set_error(err))?;
+        let entries = serde_json::to_value(entries)
+            .map_err(|err| state.set_error(err.into()))?;
+        Ok(LuaJsonValue::from(entries).into())
+    }))
+}
+
+pub fn fs_lines(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("fs_lines", hlua::function1(move |path: String| -> Result<String> {
+        state.require(Capability::Filesystem)
+            .map_err(|err| state.set_error(err))?;
+
+        state.fs_lines(path)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn fs_readline(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("fs_readline", hlua::function1(move |reader: String| -> Result<Option<String>> {
+        state.fs_readline(reader)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::ctx::Script;
+
+    #[test]
+    fn verify_fs_requires_capability() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            fs_read('wordlist.txt')
+            if last_err() then return end
+        end
+        "#).expect("Failed to load script");
+
+        let err = script.test_with_capabilities(Some(vec![])).unwrap_err();
+        assert!(err.to_string().contains("filesystem"));
+
+        let script = Script::load_unchecked(r#"
+        function run()
+            fs_lines('wordlist.txt')
+            if last_err() then return end
+        end
+        "#).expect("Failed to load script");
+
+        let err = script.test_with_capabilities(Some(vec![])).unwrap_err();
+        assert!(err.to_string().contains("filesystem"));
+    }
+}
@@ -1,11 +1,28 @@
 use crate::errors::*;
 
+use crate::connector::WebClient;
 use crate::engine::ctx::State;
+use crate::engine::structs::LuaList;
+use crate::geoip::prefixes;
 use crate::json::LuaJsonValue;
 use crate::hlua::{self, AnyLuaValue};
+use crate::web::{self, RequestOptions};
+use futures::{future, stream, Future, Stream};
+use hyper::{Body, Request};
+use hyper::header::USER_AGENT;
 use serde_json;
+use sn0int_common::metadata::Capability;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::net::IpAddr;
+use std::time::Duration;
+use tokio::prelude::FutureExt;
+use tokio::runtime::Runtime;
+
+
+const DEFAULT_CONCURRENCY: usize = 4;
+const MAX_CONCURRENCY: usize = 16;
+const PREFIXES_TIMEOUT: Duration = Duration::from_secs(30);
 
 
 pub fn asn_lookup(lua: &mut hlua::Lua, state: Arc<State>) {
@@ -24,6 +41,267 @@ pub fn asn_lookup(lua: &mut hlua::Lua, state: Arc<State>) {
     }))
 }
 
+fn fetch_asn_prefixes(state: &State, asn: u32) -> Result<Vec<String>> {
+    if let Some(cached) = state.asn_prefixes().lock().unwrap().get(&asn) {
+        return Ok(cached.clone());
+    }
+
+    let session = state.http_mksession();
+    let req = state.http_request(&session, "GET".to_string(), prefixes::url(asn), RequestOptions::default());
+    let resp = req.send_raw(state)?;
+    if resp.status != 200 {
+        bail!("Failed to fetch announced prefixes: http status {}", resp.status);
+    }
+
+    let list = prefixes::parse_response(&resp.body)?;
+    debug!("AS{} announces {} prefixes", asn, list.len());
+
+    let mut cache = state.asn_prefixes().lock().unwrap();
+    cache.insert(asn, list.clone());
+    Ok(list)
+}
+
+pub fn asn_prefixes(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("asn_prefixes", hlua::function1(move |asn: AnyLuaValue| -> Result<AnyLuaValue> {
+        let asn = match asn {
+            AnyLuaValue::LuaNumber(x) if x >= 0.0 && x % 1.0 == 0.0 && x < 4_294_967_296.0 => Ok(x as u32),
+            AnyLuaValue::LuaString(x) => prefixes::parse_asn(&x),
+            x => Err(format_err!("Invalid asn: {:?}", x)),
+        }.map_err(|err| state.set_error(err))?;
+
+        state.require(Capability::Network)
+            .map_err(|err| state.set_error(err))?;
+
+        let list = fetch_asn_prefixes(state.as_ref(), asn)
+            .map_err(|err| state.set_error(err))?;
+
+        let mut prefixes = LuaList::new();
+        for prefix in list {
+            prefixes.push_str(prefix);
+        }
+        Ok(prefixes.into())
+    }))
+}
+
+/// Options of enrich_ips
+#[derive(Debug, Default, Deserialize)]
+pub struct EnrichOptions {
+    /// Find the network with the announced prefixes of the asn, needs the network capability
+    network: Option<bool>,
+    /// Number of asns that are fetched at the same time
+    concurrency: Option<usize>,
+}
+
+impl EnrichOptions {
+    pub fn try_from(x: AnyLuaValue) -> Result<EnrichOptions> {
+        match LuaJsonValue::from(x) {
+            LuaJsonValue::Null => Ok(EnrichOptions::default()),
+            x => {
+                let x = serde_json::from_value(x.into())?;
+                Ok(x)
+            },
+        }
+    }
+}
+
+/// Everything we know about an ip, lookups that failed are listed in errors
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct Enriched {
+    ip: String,
+    country: Option<String>,
+    country_code: Option<String>,
+    asn: Option<u32>,
+    org: Option<String>,
+    network: Option<String>,
+    errors: Vec<String>,
+}
+
+fn request_asn_prefixes(client: WebClient, user_agent: &str, asn: u32) -> impl Future<Item=Vec<String>, Error=Error> {
+    let req = Request::get(prefixes::url(asn))
+        .header(USER_AGENT, user_agent)
+        .body(Body::empty());
+
+    future::result(req)
+        .map_err(Error::from)
+        .and_then(move |req| client.request(req).map_err(Error::from))
+        .and_then(|res| {
+            let status = res.status().as_u16();
+            web::read_body_limited(res.into_body().map_err(Error::from), web::DEFAULT_MAX_BODY_BYTES)
+                .and_then(move |body| {
+                    if status != 200 {
+                        bail!("Failed to fetch announced prefixes: http status {}", status);
+                    }
+                    prefixes::parse_response(&body)
+                })
+        })
+        .timeout(PREFIXES_TIMEOUT)
+        .map_err(|err| match err.into_inner() {
+            Some(err) => err,
+            _ => format_err!("Request timed out"),
+        })
+}
+
+/// Fetch the announced prefixes of every asn that isn't cached yet, at most
+/// `concurrency` requests are sent at the same time
+fn fetch_many_asn_prefixes(state: &State, asns: HashSet<u32>, concurrency: usize) -> HashMap<u32, ::std::result::Result<Vec<String>, String>> {
+    let mut results = HashMap::new();
+    let mut missing = Vec::new();
+    {
+        let cache = state.asn_prefixes().lock().unwrap();
+        for asn in asns {
+            match cache.get(&asn) {
+                Some(cached) => {
+                    results.insert(asn, Ok(cached.clone()));
+                },
+                None => missing.push(asn),
+            }
+        }
+    }
+
+    if missing.is_empty() {
+        return results;
+    }
+
+    let mut rt = match Runtime::new() {
+        Ok(rt) => rt,
+        Err(err) => {
+            for asn in missing {
+                results.insert(asn, Err(err.to_string()));
+            }
+            return results;
+        },
+    };
+
+    let client = state.http().clone();
+    let user_agent = web::default_user_agent();
+    let fut = stream::iter_ok::<_, ()>(missing)
+        .map(move |asn| {
+            request_asn_prefixes(client.clone(), &user_agent, asn)
+                .then(move |res| Ok((asn, res)))
+        })
+        .buffer_unordered(concurrency)
+        .collect();
+    let fetched = rt.block_on(fut).unwrap_or_default();
+
+    let mut cache = state.asn_prefixes().lock().unwrap();
+    for (asn, res) in fetched {
+        match res {
+            Ok(list) => {
+                debug!("AS{} announces {} prefixes", asn, list.len());
+                cache.insert(asn, list.clone());
+                results.insert(asn, Ok(list));
+            },
+            Err(err) => {
+                results.insert(asn, Err(err.to_string()));
+            },
+        }
+    }
+
+    results
+}
+
+fn enrich(state: &State, ips: Vec<String>, options: &EnrichOptions) -> Vec<Enriched> {
+    let mut seen = HashSet::new();
+    let mut results = Vec::new();
+    let mut parsed = Vec::new();
+
+    for ip in ips {
+        if !seen.insert(ip.clone()) {
+            continue;
+        }
+
+        let mut enriched = Enriched {
+            ip,
+            ..Default::default()
+        };
+
+        match enriched.ip.parse::<IpAddr>() {
+            Ok(addr) => {
+                match state.geoip().lookup(addr) {
+                    Ok(lookup) => {
+                        enriched.country = lookup.country;
+                        enriched.country_code = lookup.country_code;
+                    },
+                    Err(err) => enriched.errors.push(format!("GeoIP lookup failed: {}", err)),
+                }
+
+                match state.asn().lookup(addr) {
+                    Ok(lookup) => {
+                        enriched.asn = Some(lookup.asn);
+                        enriched.org = Some(lookup.as_org);
+                    },
+                    Err(err) => enriched.errors.push(format!("ASN lookup failed: {}", err)),
+                }
+
+                parsed.push((results.len(), addr));
+            },
+            Err(_) => enriched.errors.push("Failed to parse IP".to_string()),
+        }
+
+        results.push(enriched);
+    }
+
+    if options.network.unwrap_or(true) {
+        // ips in the same network share the asn, so every asn is only fetched once
+        let asns = results.iter()
+            .filter_map(|x| x.asn)
+            .collect::<HashSet<_>>();
+        let concurrency = options.concurrency.unwrap_or(DEFAULT_CONCURRENCY);
+        let prefixes = fetch_many_asn_prefixes(state, asns, concurrency);
+
+        for (idx, addr) in parsed {
+            let enriched = &mut results[idx];
+            let asn = match enriched.asn {
+                Some(asn) => asn,
+                None => continue,
+            };
+            match &prefixes[&asn] {
+                Ok(list) => enriched.network = prefixes::find_network(list, addr).map(String::from),
+                Err(err) => enriched.errors.push(err.to_string()),
+            }
+        }
+    }
+
+    results
+}
+
+pub fn enrich_ips(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("enrich_ips", hlua::function2(move |ips: AnyLuaValue, options: AnyLuaValue| -> Result<AnyLuaValue> {
+        let ips = match LuaJsonValue::from(ips) {
+            LuaJsonValue::Array(ips) => ips.into_iter()
+                .map(|ip| match ip {
+                    LuaJsonValue::String(ip) => Ok(ip),
+                    ip => Err(format_err!("Expected ip as string, got {:?}", ip)),
+                })
+                .collect::<Result<Vec<_>>>(),
+            // an empty table has no items
+            LuaJsonValue::Object(ref x) if x.is_empty() => Ok(Vec::new()),
+            x => Err(format_err!("Expected list of ips, got {:?}", x)),
+        }.map_err(|err| state.set_error(err))?;
+
+        let options = EnrichOptions::try_from(options)
+            .context("invalid enrich options")
+            .map_err(|err| state.set_error(Error::from(err)))?;
+
+        if let Some(concurrency) = options.concurrency {
+            if !(1..=MAX_CONCURRENCY).contains(&concurrency) {
+                return Err(state.set_error(format_err!("concurrency needs to be within 1-{}", MAX_CONCURRENCY)));
+            }
+        }
+
+        if options.network.unwrap_or(true) {
+            state.require(Capability::Network)
+                .map_err(|err| state.set_error(err))?;
+        }
+
+        let results = enrich(state.as_ref(), ips, &options);
+        let results = serde_json::to_value(results)
+            .map_err(|e| state.set_error(e.into()))?;
+
+        Ok(LuaJsonValue::from(results).into())
+    }))
+}
+
 pub fn geoip_lookup(lua: &mut hlua::Lua, state: Arc<State>) {
     lua.set("geoip_lookup", hlua::function1(move |ip: String| -> Result<AnyLuaValue> {
         let ip = ip.parse::<IpAddr>()
@@ -45,6 +323,66 @@ pub fn geoip_lookup(lua: &mut hlua::Lua, state: Arc<State>) {
 mod tests {
     use crate::engine::ctx::Script;
 
+    #[test]
+    fn verify_enrich_ips_partial() {
+        // lookups that fail are reported per ip instead of failing the call
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = enrich_ips({'1.1.1.1', 'foo', '1.1.1.1'}, {network=false})
+            if last_err() then return end
+            if #x ~= 2 then
+                return 'expected 2 results, got ' .. #x
+            end
+            if x[1]['ip'] ~= '1.1.1.1' or x[1]['network'] ~= nil then
+                return 'unexpected first result'
+            end
+            if x[1]['asn'] == nil and #x[1]['errors'] == 0 then
+                return 'missing asn without error'
+            end
+            if x[2]['ip'] ~= 'foo' or x[2]['errors'][1] ~= 'Failed to parse IP' then
+                return 'unexpected second result'
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_enrich_ips_invalid() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            enrich_ips({'1.1.1.1'}, {concurrency=0})
+        end
+        "#).expect("Failed to load script");
+        let err = script.test().expect_err("Script didn't fail");
+        assert!(err.to_string().contains("concurrency"), "{}", err);
+
+        let script = Script::load_unchecked(r#"
+        function run()
+            enrich_ips({1}, {network=false})
+        end
+        "#).expect("Failed to load script");
+        assert!(script.test().is_err());
+    }
+
+    #[test]
+    #[ignore]
+    fn verify_enrich_ips() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = enrich_ips({'1.1.1.1', '1.0.0.1'})
+            if last_err() then return end
+            if x[1]['asn'] ~= 13335 or x[1]['network'] == nil then
+                return 'unexpected result'
+            end
+            if x[2]['asn'] ~= 13335 then
+                return 'unexpected asn'
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
     #[test]
     fn verify_asn_lookup() {
         let script = Script::load_unchecked(r#"
@@ -56,6 +394,45 @@ mod tests {
         script.test().expect("Script failed");
     }
 
+    #[test]
+    fn verify_asn_prefixes_invalid() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            asn_prefixes('ASx')
+        end
+        "#).expect("Failed to load script");
+        let err = script.test().expect_err("Script didn't fail");
+        assert!(err.to_string().contains("Invalid asn"), "{}", err);
+    }
+
+    #[test]
+    #[ignore]
+    fn verify_asn_prefixes() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = asn_prefixes('AS13335')
+            if last_err() then return end
+            if #x == 0 then
+                return 'expected prefixes'
+            end
+
+            -- the second lookup is cached
+            y = asn_prefixes(13335)
+            if #x ~= #y then
+                return 'unexpected cached result'
+            end
+
+            -- this asn is reserved and doesn't announce anything
+            x = asn_prefixes(4294967295)
+            if last_err() then return end
+            if #x ~= 0 then
+                return 'expected no prefixes'
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
     #[test]
     fn verify_geoip_lookup() {
         let script = Script::load_unchecked(r#"
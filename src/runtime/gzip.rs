@@ -0,0 +1,50 @@
+use crate::errors::*;
+
+use crate::archive;
+use crate::engine::ctx::State;
+use crate::engine::structs::{byte_array, lua_bytes};
+use crate::hlua::{self, AnyLuaValue};
+use std::sync::Arc;
+
+
+/// Refuse to decompress more than this, to protect against gzip bombs
+const MAX_GUNZIP_SIZE: usize = 64 * 1024 * 1024;
+
+pub fn gunzip(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("gunzip", hlua::function1(move |bytes: AnyLuaValue| -> Result<AnyLuaValue> {
+        let bytes = byte_array(bytes)
+            .map_err(|err| state.set_error(err))?;
+        archive::gunzip(&bytes, MAX_GUNZIP_SIZE)
+            .map(|bytes| lua_bytes(&bytes))
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::ctx::Script;
+
+    #[test]
+    fn verify_gunzip() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = gunzip(hex_to_bytes('1f8b0800000000000003cb48cdc9c95728cf2fca49010085114a0d0b000000'))
+            if last_err() then return end
+            if x ~= 'hello world' then
+                return 'unexpected output: ' .. x
+            end
+        end
+        "#).unwrap();
+        script.test().expect("Failed to run script");
+    }
+
+    #[test]
+    fn verify_gunzip_invalid() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            gunzip('hello world')
+        end
+        "#).unwrap();
+        assert!(script.test().is_err());
+    }
+}
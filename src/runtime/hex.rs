@@ -1,23 +1,143 @@
 use crate::errors::*;
 
 use crate::engine::ctx::State;
-use crate::engine::structs::byte_array;
+use crate::engine::structs::{byte_array, lua_bytes};
 use crate::hlua::{self, AnyLuaValue};
 use std::sync::Arc;
 
 
+fn to_hex(bytes: &[u8]) -> String {
+    let mut out = String::new();
+
+    for b in bytes {
+        out += &format!("{:02x}", b);
+    }
+
+    out
+}
+
+fn from_hex(x: &str) -> Result<Vec<u8>> {
+    let digits = x.chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16)
+            .map(|d| d as u8)
+            .ok_or_else(|| format_err!("Invalid hex character: {:?}", c)))
+        .collect::<Result<Vec<_>>>()?;
+
+    if digits.len() % 2 != 0 {
+        bail!("Hex string has an odd number of digits");
+    }
+
+    Ok(digits.chunks(2)
+        .map(|x| x[0] << 4 | x[1])
+        .collect())
+}
+
+/// Format bytes like `hexdump -C`
+fn to_hexdump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+
+    for (i, chunk) in bytes.chunks(16).enumerate() {
+        out += &format!("{:08x} ", i * 16);
+
+        for j in 0..16 {
+            if j == 8 {
+                out.push(' ');
+            }
+            match chunk.get(j) {
+                Some(b) => out += &format!(" {:02x}", b),
+                None => out += "   ",
+            }
+        }
+
+        out += "  |";
+        for b in chunk {
+            if b.is_ascii_graphic() || *b == b' ' {
+                out.push(*b as char);
+            } else {
+                out.push('.');
+            }
+        }
+        out += "|\n";
+    }
+
+    out
+}
+
 pub fn hex(lua: &mut hlua::Lua, state: Arc<State>) {
     lua.set("hex", hlua::function1(move |bytes: AnyLuaValue| -> Result<String> {
         byte_array(bytes)
             .map_err(|err| state.set_error(err))
-            .map(|bytes| {
-                let mut out = String::new();
+            .map(|bytes| to_hex(&bytes))
+    }))
+}
+
+pub fn bytes_to_hex(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("bytes_to_hex", hlua::function1(move |bytes: AnyLuaValue| -> Result<String> {
+        byte_array(bytes)
+            .map_err(|err| state.set_error(err))
+            .map(|bytes| to_hex(&bytes))
+    }))
+}
 
-                for b in bytes {
-                    out += &format!("{:02x}", b);
-                }
+pub fn hex_to_bytes(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("hex_to_bytes", hlua::function1(move |x: String| -> Result<AnyLuaValue> {
+        from_hex(&x)
+            .map_err(|err| state.set_error(err))
+            .map(|bytes| lua_bytes(&bytes))
+    }))
+}
 
-                out
-            })
+pub fn hexdump(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("hexdump", hlua::function1(move |bytes: AnyLuaValue| -> Result<String> {
+        byte_array(bytes)
+            .map_err(|err| state.set_error(err))
+            .map(|bytes| to_hexdump(&bytes))
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::ctx::Script;
+
+    #[test]
+    fn verify_hex_roundtrip() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = bytes_to_hex({0x00, 0xff, 0x13, 0x37})
+            if x ~= '00ff1337' then
+                return 'unexpected hex: ' .. x
+            end
+
+            y = hex_to_bytes('00FF 1337')
+            if bytes_to_hex(y) ~= x then
+                return 'roundtrip failed'
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_hex_to_bytes_invalid() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            hex_to_bytes('abc')
+        end
+        "#).expect("Failed to load script");
+        assert!(script.test().is_err());
+    }
+
+    #[test]
+    fn verify_hexdump() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = hexdump('hello world\x00\xff\n')
+            if x ~= '00000000  68 65 6c 6c 6f 20 77 6f  72 6c 64 00 ff 0a        |hello world...|\n' then
+                return 'unexpected hexdump: ' .. x
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+}
@@ -21,3 +21,36 @@ pub fn html_select_list(lua: &mut hlua::Lua, state: Arc<State>) {
             .map(|x| x.into_iter().map(|x| x.into()).collect())
     }))
 }
+
+pub fn html_text(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("html_text", hlua::function2(move |html: String, options: AnyLuaValue| -> Result<String> {
+        let options = html::HtmlTextOptions::try_from(options)
+            .map_err(|err| state.set_error(err))?;
+        Ok(html::html_text(&html, &options))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::ctx::Script;
+
+    #[test]
+    fn verify_html_text() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            html = '<p>Read the <a href="/docs">docs</a></p><p>Bye</p>'
+
+            x = html_text(html, {})
+            if x ~= 'Read the docs\n\nBye' then
+                return 'unexpected text: ' .. x
+            end
+
+            x = html_text(html, {links=true})
+            if x ~= 'Read the docs (/docs)\n\nBye' then
+                return 'unexpected text: ' .. x
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+}
@@ -1,10 +1,13 @@
 use crate::errors::*;
 
+use crate::connector::PoolOptions;
 use crate::engine::ctx::State;
+use crate::engine::structs;
 use crate::hlua::{self, AnyLuaValue, AnyHashableLuaValue};
+use sn0int_common::metadata::Capability;
 use std::sync::Arc;
 use std::collections::HashMap;
-use crate::web::{RequestOptions, HttpRequest};
+use crate::web::{self, HeadOptions, RangeOptions, RequestOptions, HttpRequest};
 
 
 pub fn http_mksession(lua: &mut hlua::Lua, state: Arc<State>) {
@@ -24,8 +27,22 @@ pub fn http_request(lua: &mut hlua::Lua, state: Arc<State>) {
     }))
 }
 
+pub fn http_session_pool(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("http_session_pool", hlua::function2(move |session: String, options: AnyLuaValue| -> Result<()> {
+        let options = structs::from_lua::<PoolOptions>(options.into())
+            .context("invalid pool options")
+            .map_err(|err| state.set_error(Error::from(err)))?;
+
+        state.http_set_pool(&session, &options)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
 pub fn http_send(lua: &mut hlua::Lua, state: Arc<State>) {
     lua.set("http_send", hlua::function1(move |request: AnyLuaValue| -> Result<HashMap<AnyHashableLuaValue, AnyLuaValue>> {
+        state.require(Capability::Network)
+            .map_err(|err| state.set_error(err))?;
+
         let req = match HttpRequest::try_from(request)
                                 .context("invalid http request object") {
             Ok(req) => req,
@@ -38,10 +55,68 @@ pub fn http_send(lua: &mut hlua::Lua, state: Arc<State>) {
     }))
 }
 
+pub fn http_head(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("http_head", hlua::function2(move |url: String, options: AnyLuaValue| -> Result<HashMap<AnyHashableLuaValue, AnyLuaValue>> {
+        state.require(Capability::Network)
+            .map_err(|err| state.set_error(err))?;
+
+        let options = HeadOptions::try_from(options)
+            .context("invalid request options")
+            .map_err(|err| state.set_error(Error::from(err)))?;
+
+        let session = match options.session {
+            Some(session) => session,
+            None => state.http_mksession(),
+        };
+        let max_redirects = options.max_redirects.unwrap_or(web::DEFAULT_MAX_REDIRECTS);
+
+        let req = state.http_request(&session, "HEAD".to_string(), url, options.request);
+        req.head(state.as_ref(), max_redirects)
+            .map_err(|err| state.set_error(err))
+            .map(|resp| resp.into())
+    }))
+}
+
+/// Offsets are read as numbers, they need to be integers that are exact in a double
+fn range_offset(name: &str, x: f64) -> Result<u64> {
+    if x < 0.0 || x.fract() != 0.0 || x > 9_007_199_254_740_992.0 {
+        bail!("Range {} must be a positive integer: {}", name, x);
+    }
+    Ok(x as u64)
+}
+
+pub fn http_range(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("http_range", hlua::function4(move |url: String, start: f64, end: f64, options: AnyLuaValue| -> Result<HashMap<AnyHashableLuaValue, AnyLuaValue>> {
+        state.require(Capability::Network)
+            .map_err(|err| state.set_error(err))?;
+
+        let start = range_offset("start", start)
+            .map_err(|err| state.set_error(err))?;
+        let end = range_offset("end", end)
+            .map_err(|err| state.set_error(err))?;
+
+        let options = RangeOptions::try_from(options)
+            .context("invalid request options")
+            .map_err(|err| state.set_error(Error::from(err)))?;
+
+        let session = match options.session {
+            Some(session) => session,
+            None => state.http_mksession(),
+        };
+
+        let req = state.http_request(&session, "GET".to_string(), url, options.request);
+        req.range(state.as_ref(), start, end)
+            .map_err(|err| state.set_error(err))
+            .map(|resp| resp.into())
+    }))
+}
 
 #[cfg(test)]
 mod tests {
     use crate::engine::ctx::Script;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
     use std::time::{Instant, Duration};
 
     #[test]
@@ -146,4 +221,395 @@ mod tests {
         "#).expect("failed to load script");
         script.test().expect("Script failed");
     }
+
+    fn serve_body(len: usize) -> u16 {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use std::thread;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 4096];
+                let _ = stream.read(&mut buf);
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n");
+                let _ = stream.write_all(&vec![b'A'; len]);
+            }
+        });
+
+        port
+    }
+
+    #[test]
+    fn verify_max_body_bytes() {
+        let port = serve_body(4096);
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            session = http_mksession()
+            req = http_request(session, "GET", "http://127.0.0.1:{}/", {{
+                max_body_bytes=1024
+            }})
+            http_send(req)
+        end
+        "#, port)).expect("failed to load script");
+        assert!(script.test().is_err());
+    }
+
+    #[test]
+    fn verify_body_within_limit() {
+        let port = serve_body(512);
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            session = http_mksession()
+            req = http_request(session, "GET", "http://127.0.0.1:{}/", {{
+                max_body_bytes=1024
+            }})
+            x = http_send(req)
+            if last_err() then return end
+            if #x['text'] ~= 512 then
+                return 'unexpected body length'
+            end
+        end
+        "#, port)).expect("failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_read_timeout() {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use std::thread;
+
+        // sends the headers and then stalls in the middle of the body
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 4096];
+            let _ = stream.read(&mut buf);
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 1024\r\n\r\nAAAA");
+            thread::sleep(Duration::from_secs(5));
+        });
+
+        let start = Instant::now();
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            session = http_mksession()
+            req = http_request(session, "GET", "http://127.0.0.1:{}/", {{
+                connect_timeout=5000,
+                read_timeout=250,
+            }})
+            http_send(req)
+        end
+        "#, port)).expect("failed to load script");
+        assert!(script.test().is_err());
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    fn serve_keepalive() -> (u16, Arc<AtomicUsize>) {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use std::thread;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        let ctr = connections.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                ctr.fetch_add(1, Ordering::SeqCst);
+                let mut stream = stream.unwrap();
+                thread::spawn(move || {
+                    let mut buf = [0; 4096];
+                    while let Ok(n) = stream.read(&mut buf) {
+                        if n == 0 {
+                            break;
+                        }
+                        let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+                    }
+                });
+            }
+        });
+
+        (port, connections)
+    }
+
+    fn connections_for_three_requests(setup: &str) -> usize {
+        let (port, connections) = serve_keepalive();
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            session = http_mksession()
+            {}
+            for i=1,3 do
+                req = http_request(session, "GET", "http://127.0.0.1:{}/", {{}})
+                x = http_send(req)
+                if last_err() then return end
+            end
+        end
+        "#, setup, port)).expect("failed to load script");
+        script.test().expect("Script failed");
+        connections.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn verify_no_pool_by_default() {
+        assert_eq!(connections_for_three_requests(""), 3);
+    }
+
+    #[test]
+    fn verify_pool_reuses_connections() {
+        let n = connections_for_three_requests("http_session_pool(session, {max_idle_per_host=1})");
+        assert_eq!(n, 1);
+    }
+
+    fn serve_head() -> u16 {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use std::thread;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 4096];
+                let n = stream.read(&mut buf).unwrap_or(0);
+                let req = String::from_utf8_lossy(&buf[..n]).to_string();
+
+                let reply: &[u8] = if req.starts_with("HEAD /old ") {
+                    b"HTTP/1.1 301 Moved Permanently\r\nLocation: /new\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                } else if req.starts_with("HEAD ") {
+                    b"HTTP/1.1 405 Method Not Allowed\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                } else if req.starts_with("GET /new ") && req.to_lowercase().contains("range: bytes=0-0") {
+                    b"HTTP/1.1 206 Partial Content\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-0/1234\r\nContent-Length: 1\r\nConnection: close\r\n\r\nA"
+                } else {
+                    b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                };
+                let _ = stream.write_all(reply);
+            }
+        });
+
+        port
+    }
+
+    #[test]
+    fn verify_head_fallback() {
+        let port = serve_head();
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            x = http_head("http://127.0.0.1:{0}/old", {{}})
+            if last_err() then return end
+            if x['status'] ~= 206 or x['method'] ~= 'GET' then
+                return 'unexpected status: ' .. x['status']
+            end
+            if x['url'] ~= 'http://127.0.0.1:{0}/new' then
+                return 'unexpected url: ' .. x['url']
+            end
+            if x['content_length'] ~= 1234 or x['content_type'] ~= 'text/plain' then
+                return 'unexpected metadata'
+            end
+            if #x['redirects'] ~= 1 or x['redirects'][1]['status'] ~= 301 then
+                return 'unexpected redirects'
+            end
+        end
+        "#, port)).expect("failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_head_max_redirects() {
+        let port = serve_head();
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            x = http_head("http://127.0.0.1:{}/old", {{max_redirects=0}})
+            if last_err() then return end
+            if x['status'] ~= 301 or #x['redirects'] ~= 0 then
+                return 'redirect should not be followed'
+            end
+        end
+        "#, port)).expect("failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    /// Reply over tls and report the sni and host header of the request
+    fn serve_tls() -> (u16, mpsc::Receiver<(Option<String>, String)>) {
+        use crate::sockets::tests::{TEST_CERT, TEST_KEY};
+        use rustls::internal::pemfile;
+        use rustls::{NoClientAuth, ServerConfig, ServerSession, Session, StreamOwned};
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use std::thread;
+
+        let certs = pemfile::certs(&mut TEST_CERT.as_bytes()).unwrap();
+        let mut keys = pemfile::pkcs8_private_keys(&mut TEST_KEY.as_bytes()).unwrap();
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config.set_single_cert(certs, keys.remove(0)).unwrap();
+        let config = Arc::new(config);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let session = ServerSession::new(&config);
+                let mut stream = StreamOwned::new(session, stream.unwrap());
+                let mut buf = [0; 4096];
+                let n = stream.read(&mut buf).unwrap_or(0);
+                let req = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+                let host = req.lines()
+                    .find(|line| line.starts_with("host: "))
+                    .map(|line| line[6..].to_string())
+                    .unwrap_or_default();
+                let _ = tx.send((stream.sess.get_sni_hostname().map(String::from), host));
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok")
+                    .and_then(|_| stream.flush());
+                stream.sess.send_close_notify();
+                let _ = stream.flush();
+            }
+        });
+
+        (port, rx)
+    }
+
+    #[test]
+    fn verify_sni_override() {
+        use crate::sockets::tests::TEST_CA;
+
+        let (port, rx) = serve_tls();
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            session = http_mksession()
+            req = http_request(session, "GET", "https://127.0.0.1:{}/", {{
+                sni="localhost",
+                ca_bundle=[[{}]],
+                ca_bundle_only=true,
+                timeout=5000,
+            }})
+            x = http_send(req)
+            if last_err() then return end
+            if x['status'] ~= 200 or x['text'] ~= 'ok' then
+                return 'unexpected response'
+            end
+        end
+        "#, port, TEST_CA)).expect("failed to load script");
+        script.test().expect("Script failed");
+
+        let (sni, host) = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(sni.as_deref(), Some("localhost"));
+        assert_ne!(sni.as_deref(), Some("127.0.0.1"));
+        assert_eq!(host, format!("127.0.0.1:{}", port));
+    }
+
+    #[test]
+    fn verify_invalid_sni() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            session = http_mksession()
+            req = http_request(session, "GET", "https://127.0.0.1:1/", {
+                sni="127.0.0.1",
+            })
+            http_send(req)
+        end
+        "#).expect("failed to load script");
+        let err = script.test().unwrap_err();
+        assert!(err.to_string().contains("Invalid sni"), "{}", err);
+    }
+
+    /// `/ranged` honors the Range header, `/full` ignores it
+    fn serve_range() -> u16 {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use std::thread;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 4096];
+                let n = stream.read(&mut buf).unwrap_or(0);
+                let req = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+
+                let reply: &[u8] = if req.starts_with("get /ranged ") && req.contains("range: bytes=2-5") {
+                    b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 2-5/10\r\nContent-Length: 4\r\nConnection: close\r\n\r\n2345"
+                } else if req.starts_with("get /ranged ") && req.contains("range: bytes=20-30") {
+                    b"HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */10\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                } else if req.starts_with("get /full ") {
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\nConnection: close\r\n\r\n0123456789"
+                } else {
+                    b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                };
+                let _ = stream.write_all(reply);
+            }
+        });
+
+        port
+    }
+
+    #[test]
+    fn verify_range() {
+        let port = serve_range();
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            x = http_range("http://127.0.0.1:{0}/ranged", 2, 5, {{}})
+            if last_err() then return end
+            if x['status'] ~= 206 or not x['honored'] then
+                return 'range should be honored: ' .. x['status']
+            end
+            if x['text'] ~= '2345' or x['content_length'] ~= 10 then
+                return 'unexpected partial body: ' .. x['text']
+            end
+
+            x = http_range("http://127.0.0.1:{0}/ranged", 20, 30, {{}})
+            if last_err() then return end
+            if x['status'] ~= 416 or x['honored'] or x['text'] ~= '' or x['content_length'] ~= 10 then
+                return 'unexpected unsatisfiable range'
+            end
+        end
+        "#, port)).expect("failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_range_fallback() {
+        let port = serve_range();
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            x = http_range("http://127.0.0.1:{0}/full", 2, 5, {{}})
+            if last_err() then return end
+            if x['status'] ~= 200 or x['honored'] then
+                return 'range should be ignored'
+            end
+            if x['text'] ~= '2345' or x['content_length'] ~= 10 then
+                return 'unexpected truncated body: ' .. x['text']
+            end
+
+            x = http_range("http://127.0.0.1:{0}/full", 8, 100, {{}})
+            if last_err() then return end
+            if x['text'] ~= '89' then
+                return 'unexpected end of body: ' .. x['text']
+            end
+        end
+        "#, port)).expect("failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_range_invalid() {
+        for (start, end) in &[("5", "2"), ("-1", "2"), ("0.5", "2"), ("0", "1e12")] {
+            let script = Script::load_unchecked(format!(r#"
+            function run()
+                http_range("http://127.0.0.1:1/", {}, {}, {{}})
+            end
+            "#, start, end)).expect("failed to load script");
+            assert!(script.test().is_err(), "{}-{}", start, end);
+        }
+    }
 }
@@ -0,0 +1,62 @@
+use crate::errors::*;
+
+use crate::bogons;
+use crate::engine::ctx::State;
+use crate::hlua;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+
+fn parse_ip(ip: &str) -> Result<IpAddr> {
+    ip.parse()
+        .map_err(|_| format_err!("Invalid ip address: {:?}", ip))
+}
+
+pub fn ip_is_bogon(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("ip_is_bogon", hlua::function1(move |ip: String| -> Result<bool> {
+        parse_ip(&ip)
+            .map(|ip| bogons::is_bogon(&ip))
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+pub fn ip_is_private(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("ip_is_private", hlua::function1(move |ip: String| -> Result<bool> {
+        parse_ip(&ip)
+            .map(|ip| bogons::is_private(&ip))
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::ctx::Script;
+
+    #[test]
+    fn verify_ip_is_bogon() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            if not ip_is_bogon('192.168.1.1') or not ip_is_bogon('fe80::1') then
+                return 'reserved address not detected'
+            end
+            if ip_is_bogon('1.1.1.1') then
+                return 'public address is a bogon'
+            end
+            if not ip_is_private('10.0.0.1') or ip_is_private('127.0.0.1') then
+                return 'private check failed'
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_ip_is_bogon_invalid() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            ip_is_bogon('example.com')
+        end
+        "#).expect("Failed to load script");
+        assert!(script.test().is_err());
+    }
+}
@@ -2,7 +2,9 @@ use crate::errors::*;
 use crate::engine::ctx::State;
 use crate::hlua::{self, AnyLuaValue};
 use std::sync::Arc;
-use crate::json;
+use crate::jq;
+use crate::json::{self, ArrayDiff, ArrayMerge, LuaJsonValue};
+use serde_json::Value;
 
 
 pub fn json_decode(lua: &mut hlua::Lua, state: Arc<State>) {
@@ -19,6 +21,13 @@ pub fn json_encode(lua: &mut hlua::Lua, state: Arc<State>) {
     }))
 }
 
+pub fn json_canonical(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("json_canonical", hlua::function1(move |x: AnyLuaValue| -> Result<String> {
+        json::canonical(&to_json(x))
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
 pub fn json_decode_stream(lua: &mut hlua::Lua, state: Arc<State>) {
     lua.set("json_decode_stream", hlua::function1(move |x: String| -> Result<Vec<AnyLuaValue>> {
         json::decode_stream(&x)
@@ -26,6 +35,73 @@ pub fn json_decode_stream(lua: &mut hlua::Lua, state: Arc<State>) {
     }))
 }
 
+#[derive(Debug, Default, Deserialize)]
+struct MergeOptions {
+    arrays: Option<ArrayMerge>,
+}
+
+fn to_json(x: AnyLuaValue) -> Value {
+    LuaJsonValue::from(x).into()
+}
+
+pub fn json_merge(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("json_merge", hlua::function3(move |a: AnyLuaValue, b: AnyLuaValue, options: AnyLuaValue| -> Result<AnyLuaValue> {
+        let options: MergeOptions = serde_json::from_value(to_json(options))
+            .context("invalid merge options")
+            .map_err(|err| state.set_error(err.into()))?;
+        let arrays = options.arrays.unwrap_or(ArrayMerge::Replace);
+
+        let v = json::merge(to_json(a), to_json(b), arrays);
+        Ok(LuaJsonValue::from(v).into())
+    }))
+}
+
+pub fn json_patch(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("json_patch", hlua::function2(move |doc: AnyLuaValue, patch: AnyLuaValue| -> Result<AnyLuaValue> {
+        // lua tables can't hold nil, patches that delete keys need to be passed as json
+        let patch = match patch {
+            AnyLuaValue::LuaString(patch) => serde_json::from_str(&patch)
+                .context("Failed to parse patch")
+                .map_err(|err| state.set_error(err.into()))?,
+            patch => to_json(patch),
+        };
+
+        let v = json::merge_patch(to_json(doc), patch);
+        Ok(LuaJsonValue::from(v).into())
+    }))
+}
+
+pub fn json_transform(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("json_transform", hlua::function2(move |x: AnyLuaValue, program: String| -> Result<AnyLuaValue> {
+        let mut out = jq::transform(&to_json(x), &program)
+            .map_err(|err| state.set_error(err))?;
+
+        match out.len() {
+            0 => Ok(AnyLuaValue::LuaNil),
+            1 => Ok(LuaJsonValue::from(out.remove(0)).into()),
+            n => Err(state.set_error(format_err!("Program returned {} values, use [...] to collect them into a list", n))),
+        }
+    }))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DiffOptions {
+    arrays: Option<ArrayDiff>,
+}
+
+pub fn table_diff(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("table_diff", hlua::function3(move |old: AnyLuaValue, new: AnyLuaValue, options: AnyLuaValue| -> Result<AnyLuaValue> {
+        let options: DiffOptions = serde_json::from_value(to_json(options))
+            .context("invalid diff options")
+            .map_err(|err| state.set_error(err.into()))?;
+        let arrays = options.arrays.unwrap_or(ArrayDiff::Ordered);
+
+        let diff = json::diff(to_json(old), to_json(new), arrays);
+        let v = serde_json::to_value(diff)
+            .map_err(|err| state.set_error(err.into()))?;
+        Ok(LuaJsonValue::from(v).into())
+    }))
+}
 
 #[cfg(test)]
 mod tests {
@@ -44,6 +120,29 @@ mod tests {
         script.test().expect("Script failed");
     }
 
+    #[test]
+    fn verify_json_canonical() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            a = {}
+            a['b'] = {z=1, y=-2}
+            a['a'] = {1.5, 'x'}
+            b = {}
+            b['a'] = {1.5, 'x'}
+            b['b'] = {y=-2, z=1}
+
+            x = json_canonical(a)
+            if x ~= '{"a":[1.5,"x"],"b":{"y":-2,"z":1}}' then
+                return 'unexpected canonical json: ' .. x
+            end
+            if json_canonical(b) ~= x then
+                return 'key order changed the output: ' .. json_canonical(b)
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
     #[test]
     fn verify_json_decode() {
         let script = Script::load_unchecked(r#"
@@ -69,4 +168,106 @@ mod tests {
         "#).expect("Failed to load script");
         script.test().expect("Script failed");
     }
+
+    #[test]
+    fn verify_json_merge() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = json_merge({a={b=1, c={1}}}, {a={c={2}, d=3}}, {})
+            if json_encode(x['a']['c']) ~= '[2]' or x['a']['b'] ~= 1 or x['a']['d'] ~= 3 then
+                return 'merge failed: ' .. json_encode(x)
+            end
+
+            x = json_merge({a={1}}, {a={2}}, {arrays='concat'})
+            if json_encode(x) ~= '{"a":[1,2]}' then
+                return 'concat failed: ' .. json_encode(x)
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_json_patch() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            doc = {a='b', c={d='e', f='g'}}
+            x = json_patch(doc, '{"a":"z","c":{"f":null}}')
+            if json_encode(x) ~= '{"a":"z","c":{"d":"e"}}' then
+                return 'patch failed: ' .. json_encode(x)
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_json_transform() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            users = json_decode('[{"name":"alice","active":true},{"name":"bob","active":false},{"name":"carol","active":true}]')
+            x = json_transform(users, 'map(select(.active)) | map(.name)')
+            if json_encode(x) ~= '["alice","carol"]' then
+                return 'transform failed: ' .. json_encode(x)
+            end
+
+            x = json_transform({a={b='c'}}, '.a.b')
+            if x ~= 'c' then
+                return 'field failed: ' .. json_encode(x)
+            end
+
+            x = json_transform(users, '.[] | select(.name == "dave")')
+            if x ~= nil then
+                return 'expected nil: ' .. json_encode(x)
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_json_transform_multiple() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            json_transform({1, 2}, '.[]')
+        end
+        "#).expect("Failed to load script");
+        assert!(script.test().is_err());
+    }
+
+    #[test]
+    fn verify_json_transform_unsupported() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            json_transform({1, 2}, 'length')
+        end
+        "#).expect("Failed to load script");
+        assert!(script.test().is_err());
+    }
+
+    #[test]
+    fn verify_table_diff() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            old = {a=1, b={c={1,2}, d='x'}, e=true}
+            new = {a=1, b={c={1,3,4}, d='y'}, f=2}
+            x = table_diff(old, new, {})
+            if x['added']['b.c.3'] ~= 4 or x['added']['f'] ~= 2 then
+                return 'added failed: ' .. json_encode(x)
+            end
+            if x['removed']['e'] ~= true then
+                return 'removed failed: ' .. json_encode(x)
+            end
+            if x['changed']['b.c.2']['new'] ~= 3 or x['changed']['b.d']['old'] ~= 'x' then
+                return 'changed failed: ' .. json_encode(x)
+            end
+
+            x = table_diff({ports={80,443}}, {ports={443,80}}, {arrays='unordered'})
+            if json_encode(x) ~= '{"added":{},"changed":{},"removed":{}}' then
+                return 'unordered failed: ' .. json_encode(x)
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
 }
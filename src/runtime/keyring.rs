@@ -1,12 +1,43 @@
+use crate::errors::*;
+
 use crate::engine::ctx::State;
 use crate::hlua::{self, AnyLuaValue};
+use sn0int_common::metadata::Capability;
 use std::sync::Arc;
 
 
 pub fn keyring(lua: &mut hlua::Lua, state: Arc<State>) {
-    lua.set("keyring", hlua::function1(move |namespace: String| -> Vec<AnyLuaValue> {
-        state.keyring(&namespace).into_iter()
+    lua.set("keyring", hlua::function1(move |namespace: String| -> Result<Vec<AnyLuaValue>> {
+        state.require(Capability::Keyring)
+            .map_err(|err| state.set_error(err))?;
+
+        let keys = state.keyring(&namespace).into_iter()
             .map(|x| x.to_lua().unwrap())
-            .collect()
+            .collect();
+        Ok(keys)
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::ctx::Script;
+    use sn0int_common::metadata::Capability;
+
+    #[test]
+    fn verify_keyring_capability() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            keys = keyring('aws')
+            if last_err() then return end
+            if #keys ~= 0 then
+                return 'unexpected keys'
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test_with_capabilities(None).expect("Script failed");
+        script.test_with_capabilities(Some(vec![Capability::Keyring])).expect("Script failed");
+
+        let err = script.test_with_capabilities(Some(vec![Capability::Network])).unwrap_err();
+        assert!(err.to_string().contains("keyring"));
+    }
+}
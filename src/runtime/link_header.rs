@@ -0,0 +1,83 @@
+use crate::errors::*;
+
+use crate::engine::ctx::State;
+use crate::engine::structs;
+use crate::hlua::{self, AnyLuaValue};
+use crate::json::LuaJsonValue;
+use crate::link_header;
+use std::sync::Arc;
+
+
+/// Accept nil, a single string with one header per line or a list of headers
+fn headers(x: AnyLuaValue) -> Result<Vec<String>> {
+    match x {
+        AnyLuaValue::LuaNil => Ok(Vec::new()),
+        AnyLuaValue::LuaString(x) => Ok(vec![x]),
+        x => match LuaJsonValue::from(x) {
+            LuaJsonValue::Object(ref x) if x.is_empty() => Ok(Vec::new()),
+            x => structs::from_lua(x),
+        },
+    }
+}
+
+pub fn link_header_parse(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("link_header_parse", hlua::function1(move |x: AnyLuaValue| -> Result<AnyLuaValue> {
+        let headers = headers(x)
+            .context("Link headers must be a string or a list of strings")
+            .map_err(|err| state.set_error(err.into()))?;
+
+        let links = link_header::parse(&headers);
+
+        let links = serde_json::to_value(links)
+            .map_err(|err| state.set_error(err.into()))?;
+        Ok(LuaJsonValue::from(links).into())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::ctx::Script;
+
+    #[test]
+    fn verify_link_header_parse() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = link_header_parse('<https://example.com/?page=2>; rel="next", <https://example.com/?page=9>; rel="last"; title="End"')
+            if last_err() then return end
+            if x['next']['url'] ~= 'https://example.com/?page=2' then
+                return 'unexpected next link'
+            end
+            if x['last']['url'] ~= 'https://example.com/?page=9' or x['last']['params']['title'] ~= 'End' then
+                return 'unexpected last link'
+            end
+            if x['prev'] then
+                return 'unexpected prev link'
+            end
+
+            x = link_header_parse({'<https://example.com/?page=1>; rel=prev', '<https://example.com/?page=3>; rel=next'})
+            if last_err() then return end
+            if x['prev']['url'] ~= 'https://example.com/?page=1' or x['next']['url'] ~= 'https://example.com/?page=3' then
+                return 'unexpected links from list'
+            end
+        end
+        "#).unwrap();
+        script.test().expect("Failed to run script");
+    }
+
+    #[test]
+    fn verify_link_header_parse_empty() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            headers = {'', 'garbage', '<https://example.com/>; rel=', nil}
+            for i=1, 4 do
+                x = link_header_parse(headers[i])
+                if last_err() then return end
+                if x['next'] or x['prev'] or x['last'] or x[''] then
+                    return 'expected no links for header ' .. i
+                end
+            end
+        end
+        "#).unwrap();
+        script.test().expect("Failed to run script");
+    }
+}
@@ -0,0 +1,72 @@
+use crate::errors::*;
+
+use crate::engine::ctx::State;
+use crate::engine::structs::byte_array;
+use crate::hlua::{self, AnyLuaValue};
+use std::sync::Arc;
+
+
+pub fn mime_sniff(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("mime_sniff", hlua::function1(move |bytes: AnyLuaValue| -> Result<Option<String>> {
+        let bytes = byte_array(bytes)
+            .map_err(|err| state.set_error(err))?;
+
+        let mime = infer::Infer::new().get(&bytes)
+            .map(|x| x.mime);
+        Ok(mime)
+    }))
+}
+
+pub fn mime_from_ext(lua: &mut hlua::Lua, _: Arc<State>) {
+    lua.set("mime_from_ext", hlua::function1(move |name: String| -> Option<String> {
+        mime_guess::from_path(&name)
+            .first()
+            .map(|x| x.to_string())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::ctx::Script;
+
+    #[test]
+    fn verify_mime_sniff_png() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = mime_sniff({0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00})
+            if x ~= 'image/png' then
+                return 'unexpected mime type'
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_mime_sniff_unknown() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = mime_sniff('ohai')
+            if x ~= nil then
+                return 'unexpected mime type'
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_mime_from_ext() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            if mime_from_ext('foo.jpg') ~= 'image/jpeg' then
+                return 'unexpected mime type for jpg'
+            end
+            if mime_from_ext('foo') ~= nil then
+                return 'unexpected mime type for unknown extension'
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+}
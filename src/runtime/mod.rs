@@ -5,25 +5,51 @@ macro_rules! import_fns {
     };
 }
 
+import_fns!(cookie);
+import_fns!(counter);
+import_fns!(crawl);
 import_fns!(datetime);
 import_fns!(db);
+import_fns!(diff);
 import_fns!(dns);
+import_fns!(email);
 import_fns!(error);
+import_fns!(frontier);
+import_fns!(fs);
 import_fns!(geoip);
+import_fns!(gzip);
 import_fns!(hex);
 import_fns!(http);
 import_fns!(html);
+import_fns!(ip);
 import_fns!(json);
+import_fns!(jwt);
 import_fns!(keyring);
+import_fns!(link_header);
 import_fns!(logger);
+import_fns!(mime);
+import_fns!(multipart);
 import_fns!(options);
+import_fns!(permute);
 import_fns!(pgp);
+import_fns!(portscan);
+import_fns!(protobuf);
 import_fns!(psl);
 import_fns!(regex);
+import_fns!(robots);
+import_fns!(scan);
 import_fns!(semver);
+import_fns!(sitemap);
 import_fns!(sleep);
+import_fns!(smtp);
+import_fns!(snapshot);
 import_fns!(sock);
+import_fns!(split);
+import_fns!(state);
 import_fns!(stdio);
+import_fns!(totp);
 import_fns!(url);
 import_fns!(utf8);
+import_fns!(uuid);
 import_fns!(x509);
+import_fns!(yaml);
@@ -0,0 +1,102 @@
+use crate::errors::*;
+
+use crate::engine::ctx::State;
+use crate::engine::structs::{byte_array, lua_bytes, LuaList, LuaMap};
+use crate::hlua::{self, AnyLuaValue};
+use crate::multipart::{self, Part};
+use std::sync::Arc;
+
+
+/// The boundary can be passed on its own or as the Content-Type header, if
+/// it's missing it's read from the first delimiter of the body
+fn boundary(body: &[u8], x: AnyLuaValue) -> Result<String> {
+    match x {
+        AnyLuaValue::LuaNil => multipart::boundary_from_body(body)
+            .ok_or_else(|| format_err!("Failed to detect multipart boundary")),
+        AnyLuaValue::LuaString(x) => {
+            if x.trim().to_lowercase().starts_with("multipart/") {
+                multipart::boundary(&x)
+                    .ok_or_else(|| format_err!("Content-Type has no boundary: {:?}", x))
+            } else {
+                Ok(x)
+            }
+        },
+        x => bail!("Boundary must be a string or nil: {:?}", x),
+    }
+}
+
+fn part_to_lua(part: Part) -> AnyLuaValue {
+    let mut headers = LuaMap::new();
+    for (key, value) in part.headers {
+        headers.insert_str(key, value);
+    }
+
+    let mut parts = LuaList::new();
+    for part in part.parts {
+        parts.push(part_to_lua(part));
+    }
+
+    let mut map = LuaMap::new();
+    map.insert("headers", headers);
+    if let Some(content_type) = part.content_type {
+        map.insert_str("content_type", content_type);
+    }
+    map.insert_str("text", String::from_utf8_lossy(&part.body));
+    map.insert("binary", lua_bytes(&part.body));
+    map.insert("parts", parts);
+    map.into()
+}
+
+pub fn multipart_parse(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("multipart_parse", hlua::function2(move |body: AnyLuaValue, x: AnyLuaValue| -> Result<AnyLuaValue> {
+        let body = byte_array(body)
+            .map_err(|err| state.set_error(err))?;
+        let boundary = boundary(&body, x)
+            .map_err(|err| state.set_error(err))?;
+
+        let parts = multipart::parse(&body, &boundary)
+            .map_err(|err| state.set_error(err))?;
+
+        let mut list = LuaList::new();
+        for part in parts {
+            list.push(part_to_lua(part));
+        }
+        Ok(list.into())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::ctx::Script;
+
+    #[test]
+    fn verify_multipart_parse() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            body = '--batch\r\nContent-Type: application/json\r\n\r\n{"id":1}\r\n--batch\r\nContent-Type: multipart/alternative; boundary=alt\r\n\r\n--alt\r\nContent-Type: text/plain\r\n\r\nhello\r\n--alt--\r\n--batch--\r\n'
+
+            parts = multipart_parse(body, 'multipart/mixed; boundary="batch"')
+            if last_err() then return end
+            if #parts ~= 2 then return 'expected 2 parts' end
+            if parts[1]['content_type'] ~= 'application/json' then return 'content type' end
+            if json_decode(parts[1]['text'])['id'] ~= 1 then return 'json body' end
+            if parts[2]['parts'][1]['text'] ~= 'hello' then return 'nested part' end
+
+            -- the boundary on its own and detected from the body
+            if #multipart_parse(body, 'batch') ~= 2 then return 'boundary' end
+            if #multipart_parse(body, nil) ~= 2 then return 'detected boundary' end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_multipart_parse_invalid() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            multipart_parse('no delimiter', 'multipart/mixed')
+        end
+        "#).expect("Failed to load script");
+        assert!(script.test().is_err());
+    }
+}
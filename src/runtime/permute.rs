@@ -0,0 +1,167 @@
+use crate::errors::*;
+
+use crate::engine::ctx::State;
+use crate::engine::structs::{self, LuaList};
+use crate::hlua::{self, AnyLuaValue};
+use crate::json::LuaJsonValue;
+use crate::permute;
+use std::sync::Arc;
+
+
+/// Generating more candidates needs an explicit limit
+const DEFAULT_LIMIT: usize = 10_000;
+/// Batches of permute_next are capped like db_cursor_next
+const MAX_PERMUTE_BATCH: usize = 10_000;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PermuteOptions {
+    limit: Option<usize>,
+}
+
+/// Empty lua tables are objects, accept them as an empty list
+fn string_list(x: AnyLuaValue) -> Result<Vec<String>> {
+    match LuaJsonValue::from(x) {
+        LuaJsonValue::Object(ref x) if x.is_empty() => Ok(Vec::new()),
+        x => structs::from_lua(x),
+    }
+}
+
+pub fn permute(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("permute", hlua::function3(move |words: AnyLuaValue, patterns: AnyLuaValue, options: AnyLuaValue| -> Result<AnyLuaValue> {
+        let words = string_list(words)
+            .context("words must be a list of strings")
+            .map_err(|err| state.set_error(err.into()))?;
+        let patterns = string_list(patterns)
+            .context("patterns must be a list of strings")
+            .map_err(|err| state.set_error(err.into()))?;
+        let options = structs::from_lua::<PermuteOptions>(options.into())
+            .context("invalid permute options")
+            .map_err(|err| state.set_error(err.into()))?;
+
+        let permutations = permute::permute(words, &patterns)
+            .map_err(|err| state.set_error(err))?;
+
+        // fail instead of silently dropping candidates
+        let limit = options.limit.unwrap_or(DEFAULT_LIMIT);
+        let candidates = permutations.take(limit.saturating_add(1)).collect::<Vec<_>>();
+        if candidates.len() > limit {
+            let err = format_err!("Patterns generate more than {} candidates, raise the limit or use permute_cursor", limit);
+            return Err(state.set_error(err));
+        }
+
+        let mut list = LuaList::new();
+        for x in candidates {
+            list.push_str(x);
+        }
+        Ok(list.into())
+    }))
+}
+
+pub fn permute_cursor(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("permute_cursor", hlua::function2(move |words: AnyLuaValue, patterns: AnyLuaValue| -> Result<String> {
+        let words = string_list(words)
+            .context("words must be a list of strings")
+            .map_err(|err| state.set_error(err.into()))?;
+        let patterns = string_list(patterns)
+            .context("patterns must be a list of strings")
+            .map_err(|err| state.set_error(err.into()))?;
+
+        let permutations = permute::permute(words, &patterns)
+            .map_err(|err| state.set_error(err))?;
+
+        Ok(state.permutations_register(permutations))
+    }))
+}
+
+pub fn permute_next(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("permute_next", hlua::function2(move |cursor: String, batch_size: i32| -> Result<AnyLuaValue> {
+        if batch_size < 1 || batch_size as usize > MAX_PERMUTE_BATCH {
+            let e = format_err!("Batch size must be between 1 and {}", MAX_PERMUTE_BATCH);
+            return Err(state.set_error(e));
+        }
+
+        let permutations = state.get_permutations(&cursor)
+            .map_err(|e| state.set_error(e))?;
+        let mut permutations = permutations.lock().unwrap();
+
+        let mut list = LuaList::new();
+        for x in permutations.by_ref().take(batch_size as usize) {
+            list.push_str(x);
+        }
+        Ok(list.into())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::ctx::Script;
+
+    #[test]
+    fn verify_permute() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = permute({'admin', 'mail'}, {'{word}', 'dev-{word}', '{word}{1-2}'}, {limit=8})
+            if last_err() then return end
+            expected = {'admin', 'dev-admin', 'admin1', 'admin2', 'mail', 'dev-mail', 'mail1', 'mail2'}
+            if #x ~= #expected then
+                return 'unexpected number of results: ' .. #x
+            end
+            for i=1, #x do
+                if x[i] ~= expected[i] then
+                    return 'unexpected result: ' .. x[i]
+                end
+            end
+        end
+        "#).unwrap();
+        script.test().expect("Failed to run script");
+    }
+
+    #[test]
+    fn verify_permute_over_limit() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            permute({'admin', 'mail'}, {'{word}', 'dev-{word}', '{word}{1-2}'}, {limit=7})
+        end
+        "#).unwrap();
+        assert!(script.test().is_err());
+    }
+
+    #[test]
+    fn verify_permute_cursor() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            cursor = permute_cursor({'admin', 'mail'}, {'{word}', '{word}{1-2}'})
+            if last_err() then return end
+            x = {}
+            while true do
+                batch = permute_next(cursor, 4)
+                if last_err() then return end
+                if #batch == 0 then break end
+                for i=1, #batch do
+                    x[#x+1] = batch[i]
+                end
+            end
+            expected = {'admin', 'admin1', 'admin2', 'mail', 'mail1', 'mail2'}
+            if #x ~= #expected then
+                return 'unexpected number of results: ' .. #x
+            end
+            for i=1, #x do
+                if x[i] ~= expected[i] then
+                    return 'unexpected result: ' .. x[i]
+                end
+            end
+        end
+        "#).unwrap();
+        script.test().expect("Failed to run script");
+    }
+
+    #[test]
+    fn verify_permute_invalid_pattern() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            permute({'admin'}, {'dev-'}, {})
+        end
+        "#).unwrap();
+        assert!(script.test().is_err());
+    }
+}
@@ -0,0 +1,412 @@
+use crate::errors::*;
+
+use chrootable_https::dns::{DnsResolver, RecordType};
+use crate::db::Family;
+use crate::engine::ctx::State;
+use crate::engine::structs::{self, lua_bytes, LuaList, LuaMap};
+use crate::hlua::{self, AnyLuaValue};
+use crate::json::LuaJsonValue;
+use crate::sockets::{self, BannerOptions, Socket, SocketOptions};
+use sn0int_common::metadata::Capability;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+
+const DEFAULT_CONCURRENCY: usize = 32;
+/// Every connect holds a file descriptor
+const MAX_CONCURRENCY: usize = 256;
+/// Closed ports answer right away, filtered ports would block a worker until
+/// the timeout
+const DEFAULT_CONNECT_TIMEOUT: u64 = 1000;
+
+/// Options of scan_ports, the remaining keys are the options of grab_banner
+#[derive(Debug, Default, Deserialize)]
+pub struct ScanOptions {
+    concurrency: Option<usize>,
+    /// Read a banner from every open port
+    #[serde(default)]
+    banner: bool,
+    /// Add every open port to the database as a port of this ip address
+    ip_addr_id: Option<i32>,
+    #[serde(flatten)]
+    banner_options: BannerOptions,
+}
+
+impl ScanOptions {
+    pub fn try_from(x: AnyLuaValue) -> Result<ScanOptions> {
+        match LuaJsonValue::from(x) {
+            LuaJsonValue::Null => Ok(ScanOptions::default()),
+            x => {
+                let x = serde_json::from_value(x.into())?;
+                Ok(x)
+            },
+        }
+    }
+
+    fn concurrency(&self) -> Result<usize> {
+        let concurrency = self.concurrency.unwrap_or(DEFAULT_CONCURRENCY);
+        if !(1..=MAX_CONCURRENCY).contains(&concurrency) {
+            bail!("Concurrency must be between 1 and {}", MAX_CONCURRENCY);
+        }
+        Ok(concurrency)
+    }
+
+    fn socket_options(&self) -> SocketOptions {
+        let mut options = self.banner_options.socket_options();
+        if options.connect_timeout.or(options.timeout).is_none() {
+            options.connect_timeout = Some(DEFAULT_CONNECT_TIMEOUT);
+        }
+        options
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct OpenPort {
+    port: u16,
+    banner: Option<Vec<u8>>,
+}
+
+/// Parse a port list like `22,80,8000-8100`
+fn parse_ports(spec: &str) -> Result<Vec<u16>> {
+    let mut ports = Vec::new();
+
+    for part in spec.split(',') {
+        let part = part.trim();
+        if part.is_empty() {
+            continue;
+        }
+
+        let (start, end) = match part.find('-') {
+            Some(idx) => (part[..idx].trim(), part[idx+1..].trim()),
+            None => (part, part),
+        };
+        let start = start.parse::<u16>()
+            .with_context(|_| format!("Invalid port: {:?}", part))?;
+        let end = end.parse::<u16>()
+            .with_context(|_| format!("Invalid port: {:?}", part))?;
+
+        if start == 0 || start > end {
+            bail!("Invalid port range: {:?}", part);
+        }
+        ports.extend(start..=end);
+    }
+
+    Ok(ports)
+}
+
+fn read_ports(ports: AnyLuaValue) -> Result<Vec<u16>> {
+    let ports = match ports {
+        AnyLuaValue::LuaString(spec) => parse_ports(&spec)?,
+        AnyLuaValue::LuaNumber(port) => structs::from_lua::<Vec<u16>>(LuaJsonValue::Array(vec![
+            LuaJsonValue::from(AnyLuaValue::LuaNumber(port)),
+        ]))?,
+        ports => structs::from_lua::<Vec<u16>>(ports.into())?,
+    };
+
+    if ports.contains(&0) {
+        bail!("Port 0 can't be scanned");
+    }
+
+    let mut seen = HashSet::new();
+    Ok(ports.into_iter()
+        .filter(|port| seen.insert(*port))
+        .collect())
+}
+
+/// The address we connect to, hosts are resolved once instead of once per
+/// port. With a proxy the hostname is passed on so the proxy resolves it.
+fn resolve_target(state: &State, host: &str) -> Result<String> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(addr) = host.parse::<IpAddr>() {
+        return Ok(addr.to_string());
+    }
+
+    if state.proxy().is_some() {
+        return Ok(host.to_string());
+    }
+
+    let resolver = state.dns_config();
+    for record in &[RecordType::A, RecordType::AAAA] {
+        let addrs = resolver.resolve(host, *record)
+            .wait_for_response()?
+            .success()?;
+        if let Some(addr) = addrs.first() {
+            return Ok(addr.to_string());
+        }
+    }
+
+    bail!("no dns records found")
+}
+
+/// Connect to every port with a bounded number of workers, closed and
+/// filtered ports are dropped
+fn scan(state: &State, addr: &str, ports: Vec<u16>, options: ScanOptions) -> Result<Vec<OpenPort>> {
+    let concurrency = options.concurrency()?;
+    let socket_options = Arc::new(options.socket_options());
+    let banner_options = Arc::new(options.banner_options);
+    let grab_banner = options.banner;
+
+    let queue = Arc::new(Mutex::new(ports.into_iter()));
+    let (tx, rx) = channel::unbounded();
+
+    let workers = (0..concurrency)
+        .map(|_| {
+            let queue = queue.clone();
+            let tx = tx.clone();
+            let addr = addr.to_string();
+            let proxy = state.proxy().cloned();
+            let resolver = state.dns_config().clone();
+            let tls = banner_options.tls(state.tls());
+            let socket_options = socket_options.clone();
+            let banner_options = banner_options.clone();
+
+            thread::spawn(move || loop {
+                let port = match queue.lock().unwrap().next() {
+                    Some(port) => port,
+                    None => break,
+                };
+
+                let sock = match Socket::open(proxy.as_ref(), &resolver, &addr, port, &socket_options) {
+                    Ok(sock) => sock,
+                    Err(err) => {
+                        debug!("Port {} is not open: {}", port, err);
+                        continue;
+                    },
+                };
+
+                // an open port without a banner is still an open port
+                let banner = if grab_banner {
+                    match sockets::grab_banner(sock, &addr, &banner_options, &tls) {
+                        Ok(banner) => Some(banner),
+                        Err(err) => {
+                            debug!("Failed to read banner of port {}: {}", port, err);
+                            None
+                        },
+                    }
+                } else {
+                    None
+                };
+
+                if tx.send(OpenPort { port, banner }).is_err() {
+                    break;
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(tx);
+
+    for worker in workers {
+        if worker.join().is_err() {
+            bail!("Port scan worker crashed");
+        }
+    }
+
+    let mut open = rx.iter().collect::<Vec<_>>();
+    open.sort_by_key(|x| x.port);
+    Ok(open)
+}
+
+fn store(state: &Arc<State>, ip_addr_id: i32, ip_addr: &str, open: &OpenPort) -> Result<Option<i32>> {
+    let banner = open.banner.as_ref()
+        .map(|banner| String::from_utf8_lossy(banner).trim_end().to_string())
+        .filter(|banner| !banner.is_empty());
+
+    let object = LuaJsonValue::from(serde_json::json!({
+        "ip_addr_id": ip_addr_id,
+        "ip_addr": ip_addr,
+        "port": open.port,
+        "protocol": "tcp",
+        "status": "open",
+        "banner": banner,
+    }));
+
+    match super::db::insert_hook(state, Family::Port, object)? {
+        Some(object) => state.db_insert(object),
+        None => Ok(None),
+    }
+}
+
+pub fn scan_ports(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("scan_ports", hlua::function3(move |host: String, ports: AnyLuaValue, options: AnyLuaValue| -> Result<AnyLuaValue> {
+        state.require(Capability::Network)
+            .map_err(|err| state.set_error(err))?;
+
+        let ports = read_ports(ports)
+            .context("invalid ports")
+            .map_err(|err| state.set_error(Error::from(err)))?;
+
+        let options = ScanOptions::try_from(options)
+            .context("invalid scan options")
+            .map_err(|err| state.set_error(Error::from(err)))?;
+        let ip_addr_id = options.ip_addr_id;
+
+        let addr = resolve_target(state.as_ref(), &host)
+            .map_err(|err| state.set_error(err))?;
+
+        if ip_addr_id.is_some() && addr.parse::<IpAddr>().is_err() {
+            let err = format_err!("Adding ports to the database needs an ip address, not {:?}", host);
+            return Err(state.set_error(err));
+        }
+
+        let open = scan(state.as_ref(), &addr, ports, options)
+            .map_err(|err| state.set_error(err))?;
+
+        let mut list = LuaList::new();
+        for open in open {
+            let mut map = LuaMap::new();
+
+            if let Some(ip_addr_id) = ip_addr_id {
+                let id = store(&state, ip_addr_id, &addr, &open)
+                    .map_err(|err| state.set_error(err))?;
+                if let Some(id) = id {
+                    map.insert_num("id", f64::from(id));
+                }
+            }
+
+            map.insert_num("port", f64::from(open.port));
+            if let Some(banner) = open.banner {
+                map.insert("banner", lua_bytes(&banner));
+            }
+            list.push(map);
+        }
+
+        Ok(list.into())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ctx::Script;
+    use crate::engine::tests::{shared, RecordingReporter};
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_parse_ports() {
+        assert_eq!(parse_ports("22, 80,8000-8003,").unwrap(), vec![22, 80, 8000, 8001, 8002, 8003]);
+        assert_eq!(parse_ports("65535").unwrap(), vec![65535]);
+        assert!(parse_ports("0").is_err());
+        assert!(parse_ports("80-22").is_err());
+        assert!(parse_ports("65536").is_err());
+        assert!(parse_ports("http").is_err());
+    }
+
+    #[test]
+    fn test_read_ports() {
+        assert_eq!(read_ports(AnyLuaValue::LuaString("443,22,443".into())).unwrap(), vec![443, 22]);
+        assert_eq!(read_ports(AnyLuaValue::LuaNumber(8080.0)).unwrap(), vec![8080]);
+        assert!(read_ports(AnyLuaValue::LuaNumber(0.0)).is_err());
+    }
+
+    /// Accept connections on a random port, optionally send a banner
+    fn listen(addr: &str, banner: Option<&'static str>) -> Option<u16> {
+        let listener = TcpListener::bind(addr).ok()?;
+        let port = listener.local_addr().unwrap().port();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                if let Some(banner) = banner {
+                    let _ = stream.write_all(banner.as_bytes());
+                }
+            }
+        });
+
+        Some(port)
+    }
+
+    /// A port that nothing is listening on
+    fn closed_port() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    #[test]
+    fn verify_scan_ports() {
+        let ssh = listen("127.0.0.1:0", Some("SSH-2.0-OpenSSH_7.9\r\n")).unwrap();
+        let silent = listen("127.0.0.1:0", None).unwrap();
+        let closed = closed_port();
+
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            x = scan_ports('127.0.0.1', {{{}, {}, {}}}, {{
+                banner=true,
+                read_timeout=200,
+                concurrency=2,
+            }})
+            if last_err() then return end
+            if #x ~= 2 then return 'expected 2 open ports, got ' .. #x end
+
+            for i=1,#x do
+                if x[i]['port'] == {} and utf8_decode(x[i]['banner']) ~= 'SSH-2.0-OpenSSH_7.9\r\n' then
+                    return 'unexpected banner'
+                end
+                if x[i]['port'] == {} and x[i]['banner'] and #x[i]['banner'] ~= 0 then
+                    return 'unexpected banner on silent port'
+                end
+            end
+        end
+        "#, closed, ssh, silent, ssh, silent)).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_scan_ports_ipv6() {
+        let port = match listen("[::1]:0", None) {
+            Some(port) => port,
+            // no ipv6 in this environment
+            None => return,
+        };
+
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            x = scan_ports('[::1]', '{}', {{}})
+            if last_err() then return end
+            if #x ~= 1 or x[1]['port'] ~= {} then return 'port not found' end
+        end
+        "#, port, port)).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_scan_ports_store() {
+        let port = listen("127.0.0.1:0", Some("220 smtp ready\r\n")).unwrap();
+
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            x = scan_ports('127.0.0.1', {}, {{
+                banner=true,
+                read_timeout=200,
+                ip_addr_id=7,
+            }})
+            if last_err() then return end
+            if #x ~= 1 or x[1]['id'] ~= 1 then return 'port not added' end
+        end
+        "#, port)).expect("Failed to load script");
+
+        let reporter = RecordingReporter::default();
+        let events = reporter.events();
+        script.test_with_reporter(shared(Box::new(reporter))).expect("Script failed");
+
+        let events = events.lock().unwrap();
+        let insert = events.iter()
+            .find_map(|e| e["Database"]["Insert"].get("Port"))
+            .expect("No port inserted");
+        assert_eq!(insert["ip_addr_id"], 7);
+        assert_eq!(insert["value"], format!("tcp/127.0.0.1:{}", port));
+        assert_eq!(insert["status"], "open");
+        assert_eq!(insert["banner"], "220 smtp ready");
+    }
+
+    #[test]
+    fn verify_scan_ports_invalid() {
+        for code in &["scan_ports('127.0.0.1', '80-22', {})", "scan_ports('127.0.0.1', 80, {concurrency=0})"] {
+            let script = Script::load_unchecked(format!("function run() {} end", code))
+                .expect("Failed to load script");
+            assert!(script.test().is_err());
+        }
+    }
+}
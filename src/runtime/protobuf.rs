@@ -0,0 +1,355 @@
+use crate::errors::*;
+
+use crate::engine::ctx::State;
+use crate::engine::structs::{self, byte_array, lua_bytes, LuaList};
+use crate::hlua::{self, AnyLuaValue};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Field {
+    number: u32,
+    #[serde(rename="type")]
+    kind: String,
+    #[serde(default)]
+    repeated: bool,
+    #[serde(default)]
+    fields: HashMap<String, Field>,
+}
+
+#[derive(Debug)]
+enum Wire<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+impl<'a> Wire<'a> {
+    fn name(&self) -> &'static str {
+        match self {
+            Wire::Varint(_) => "varint",
+            Wire::Fixed64(_) => "fixed64",
+            Wire::Bytes(_) => "length-delimited",
+            Wire::Fixed32(_) => "fixed32",
+        }
+    }
+
+    fn raw(&self) -> AnyLuaValue {
+        match self {
+            Wire::Varint(v) => AnyLuaValue::LuaNumber(*v as f64),
+            Wire::Fixed64(v) => AnyLuaValue::LuaNumber(*v as f64),
+            Wire::Bytes(v) => lua_bytes(v),
+            Wire::Fixed32(v) => AnyLuaValue::LuaNumber(f64::from(*v)),
+        }
+    }
+}
+
+/// The wire type a field of this type is encoded with
+fn wire_type(kind: &str) -> Option<u64> {
+    match kind {
+        "int32" | "int64" | "uint32" | "uint64" | "sint32" | "sint64" | "bool" | "enum" => Some(0),
+        "fixed64" | "sfixed64" | "double" => Some(1),
+        "string" | "bytes" | "message" => Some(2),
+        "fixed32" | "sfixed32" | "float" => Some(5),
+        _ => None,
+    }
+}
+
+fn validate(schema: &HashMap<String, Field>) -> Result<()> {
+    let mut numbers = HashMap::new();
+
+    for (name, field) in schema {
+        if field.number == 0 || field.number >= 1 << 29 {
+            bail!("Field number of {:?} is out of range: {}", name, field.number);
+        }
+        if let Some(other) = numbers.insert(field.number, name) {
+            bail!("Field number {} is used by {:?} and {:?}", field.number, other, name);
+        }
+        if wire_type(&field.kind).is_none() {
+            bail!("Unknown type for {:?}: {:?}", name, field.kind);
+        }
+
+        if field.kind == "message" {
+            validate(&field.fields)?;
+        } else if !field.fields.is_empty() {
+            bail!("Only messages can have fields: {:?}", name);
+        }
+    }
+
+    Ok(())
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Reader<'a> {
+        Reader {
+            buf,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if n > self.buf.len() {
+            bail!("Unexpected end of message");
+        }
+        let (head, tail) = self.buf.split_at(n);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for i in 0..10 {
+            let b = self.take(1)?[0];
+            value |= u64::from(b & 0x7f) << (i * 7);
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("Varint is too long")
+    }
+
+    fn fixed64(&mut self) -> Result<u64> {
+        let b = self.take(8)?;
+        Ok(b.iter().rev().fold(0, |acc, b| acc << 8 | u64::from(*b)))
+    }
+
+    fn fixed32(&mut self) -> Result<u32> {
+        let b = self.take(4)?;
+        Ok(b.iter().rev().fold(0, |acc, b| acc << 8 | u32::from(*b)))
+    }
+
+    fn wire(&mut self, wire_type: u64) -> Result<Wire<'a>> {
+        match wire_type {
+            0 => Ok(Wire::Varint(self.varint()?)),
+            1 => Ok(Wire::Fixed64(self.fixed64()?)),
+            2 => {
+                let len = self.varint()?;
+                if len > self.buf.len() as u64 {
+                    bail!("Unexpected end of message");
+                }
+                Ok(Wire::Bytes(self.take(len as usize)?))
+            },
+            5 => Ok(Wire::Fixed32(self.fixed32()?)),
+            3 | 4 => bail!("Groups are not supported"),
+            _ => bail!("Invalid wire type: {}", wire_type),
+        }
+    }
+}
+
+fn zigzag(v: u64) -> i64 {
+    ((v >> 1) as i64) ^ -((v & 1) as i64)
+}
+
+fn decode_scalar(kind: &str, wire: &Wire) -> Result<AnyLuaValue> {
+    let num = match (kind, wire) {
+        ("int32", Wire::Varint(v)) => f64::from(*v as i32),
+        ("int64", Wire::Varint(v)) => *v as i64 as f64,
+        ("uint32", Wire::Varint(v)) => f64::from(*v as u32),
+        ("uint64", Wire::Varint(v)) => *v as f64,
+        ("sint32", Wire::Varint(v)) => f64::from(zigzag(*v) as i32),
+        ("sint64", Wire::Varint(v)) => zigzag(*v) as f64,
+        ("bool", Wire::Varint(v)) => return Ok(AnyLuaValue::LuaBoolean(*v != 0)),
+        ("enum", Wire::Varint(v)) => f64::from(*v as i32),
+        ("fixed64", Wire::Fixed64(v)) => *v as f64,
+        ("sfixed64", Wire::Fixed64(v)) => *v as i64 as f64,
+        ("double", Wire::Fixed64(v)) => f64::from_bits(*v),
+        ("fixed32", Wire::Fixed32(v)) => f64::from(*v),
+        ("sfixed32", Wire::Fixed32(v)) => f64::from(*v as i32),
+        ("float", Wire::Fixed32(v)) => f64::from(f32::from_bits(*v)),
+        ("string", Wire::Bytes(v)) => {
+            let s = String::from_utf8(v.to_vec())
+                .context("String field is not valid utf8")?;
+            return Ok(AnyLuaValue::LuaString(s));
+        },
+        ("bytes", Wire::Bytes(v)) => return Ok(lua_bytes(v)),
+        _ => bail!("Expected {} for {:?}, found {}", wire_name(kind), kind, wire.name()),
+    };
+    Ok(AnyLuaValue::LuaNumber(num))
+}
+
+fn wire_name(kind: &str) -> &'static str {
+    match wire_type(kind) {
+        Some(0) => "varint",
+        Some(1) => "fixed64",
+        Some(2) => "length-delimited",
+        _ => "fixed32",
+    }
+}
+
+fn decode_field(field: &Field, wire: Wire) -> Result<Vec<AnyLuaValue>> {
+    let expected = wire_type(&field.kind);
+
+    match wire {
+        Wire::Bytes(buf) if field.kind == "message" => Ok(vec![decode_message(buf, &field.fields)?]),
+        // repeated scalars are usually packed into a single length-delimited field
+        Wire::Bytes(buf) if field.repeated && expected != Some(2) => {
+            let mut reader = Reader::new(buf);
+            let mut values = Vec::new();
+            while !reader.is_empty() {
+                let wire = reader.wire(expected.unwrap_or(2))?;
+                values.push(decode_scalar(&field.kind, &wire)?);
+            }
+            Ok(values)
+        },
+        wire => Ok(vec![decode_scalar(&field.kind, &wire)?]),
+    }
+}
+
+fn decode_message(buf: &[u8], schema: &HashMap<String, Field>) -> Result<AnyLuaValue> {
+    let by_number = schema.iter()
+        .map(|(name, field)| (field.number, (name, field)))
+        .collect::<HashMap<_, _>>();
+
+    let mut known = HashMap::<&String, Vec<AnyLuaValue>>::new();
+    let mut unknown = BTreeMap::<u32, LuaList>::new();
+
+    let mut reader = Reader::new(buf);
+    while !reader.is_empty() {
+        let key = reader.varint()?;
+        let number = key >> 3;
+        if number == 0 || number >= 1 << 29 {
+            bail!("Invalid field number: {}", number);
+        }
+        let number = number as u32;
+        let wire = reader.wire(key & 7)?;
+
+        match by_number.get(&number) {
+            Some((name, field)) => {
+                let values = decode_field(field, wire)
+                    .context(format!("Failed to decode {:?}", name))?;
+                known.entry(name)
+                    .or_default()
+                    .extend(values);
+            },
+            None => unknown.entry(number)
+                .or_default()
+                .push(wire.raw()),
+        }
+    }
+
+    let mut out = Vec::new();
+    for (name, mut values) in known {
+        let value = if schema[name].repeated {
+            let mut list = LuaList::new();
+            for value in values {
+                list.push(value);
+            }
+            list.into()
+        } else {
+            // the last value wins if a singular field is sent multiple times
+            values.pop().expect("Field was decoded without a value")
+        };
+        out.push((AnyLuaValue::LuaString(name.to_string()), value));
+    }
+    for (number, values) in unknown {
+        out.push((AnyLuaValue::LuaNumber(f64::from(number)), values.into()));
+    }
+
+    Ok(AnyLuaValue::LuaArray(out))
+}
+
+fn decode(bytes: AnyLuaValue, schema: AnyLuaValue) -> Result<AnyLuaValue> {
+    let bytes = byte_array(bytes)?;
+    let schema = structs::from_lua::<HashMap<String, Field>>(schema.into())
+        .context("Invalid protobuf schema")?;
+    validate(&schema)
+        .context("Invalid protobuf schema")?;
+    let msg = decode_message(&bytes, &schema)
+        .context("Failed to decode protobuf")?;
+    Ok(msg)
+}
+
+pub fn protobuf_decode(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("protobuf_decode", hlua::function2(move |bytes: AnyLuaValue, schema: AnyLuaValue| -> Result<AnyLuaValue> {
+        decode(bytes, schema)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::ctx::Script;
+
+    #[test]
+    fn verify_protobuf_decode() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            schema = {
+                id={number=1, type='uint64'},
+                name={number=2, type='string'},
+                child={number=3, type='message', fields={
+                    delta={number=1, type='sint32'},
+                }},
+                values={number=4, type='uint32', repeated=true},
+                ratio={number=6, type='double'},
+                enabled={number=7, type='bool'},
+            }
+            msg = '\x08\x96\x01' ..
+                  '\x12\x07testing' ..
+                  '\x1a\x02\x08\x03' ..
+                  '\x22\x03\x03\x8e\x02' ..
+                  '\x2d\x01\x00\x00\x00' ..
+                  '\x31\x00\x00\x00\x00\x00\x00\xf8\x3f' ..
+                  '\x38\x01' ..
+                  '\x2d\x02\x00\x00\x00'
+
+            x = protobuf_decode(msg, schema)
+            if last_err() then return end
+
+            if x['id'] ~= 150 or x['name'] ~= 'testing' or x['child']['delta'] ~= -2 then
+                return 'unexpected scalars: ' .. json_encode(x)
+            end
+            if x['values'][1] ~= 3 or x['values'][2] ~= 270 or x['values'][3] ~= nil then
+                return 'unexpected repeated field'
+            end
+            if x['ratio'] ~= 1.5 or x['enabled'] ~= true then
+                return 'unexpected fixed64 and bool fields'
+            end
+            if x[5][1] ~= 1 or x[5][2] ~= 2 then
+                return 'unknown field was not preserved'
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_protobuf_decode_truncated() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            protobuf_decode('\x12\x07test', {name={number=2, type='string'}})
+        end
+        "#).expect("Failed to load script");
+        assert!(script.test().is_err());
+    }
+
+    #[test]
+    fn verify_protobuf_decode_wire_type_mismatch() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            protobuf_decode('\x08\x01', {name={number=1, type='string'}})
+        end
+        "#).expect("Failed to load script");
+        assert!(script.test().is_err());
+    }
+
+    #[test]
+    fn verify_protobuf_decode_invalid_schema() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            protobuf_decode('', {name={number=1, type='varchar'}})
+        end
+        "#).expect("Failed to load script");
+        assert!(script.test().is_err());
+    }
+}
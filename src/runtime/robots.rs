@@ -0,0 +1,46 @@
+use crate::errors::*;
+
+use crate::engine::ctx::State;
+use crate::hlua::{self, AnyLuaValue};
+use crate::json::LuaJsonValue;
+use crate::robots;
+use serde_json;
+use std::sync::Arc;
+
+
+pub fn robots_parse(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("robots_parse", hlua::function1(move |text: String| -> Result<AnyLuaValue> {
+        let robots = robots::parse(&text);
+        let robots = serde_json::to_value(robots)
+            .map_err(|e| state.set_error(e.into()))?;
+        Ok(LuaJsonValue::from(robots).into())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::ctx::Script;
+
+    #[test]
+    fn verify_robots_parse() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = robots_parse("User-agent: *\nDisallow: /admin/\nAllow: /admin/public\nSitemap: https://example.com/sitemap.xml\n")
+            if last_err() then return end
+            if x['groups'][1]['user_agents'][1] ~= '*' then
+                return 'unexpected user agent'
+            end
+            if x['groups'][1]['disallow'][1] ~= '/admin/' then
+                return 'unexpected disallow rule'
+            end
+            if x['groups'][1]['allow'][1] ~= '/admin/public' then
+                return 'unexpected allow rule'
+            end
+            if x['sitemaps'][1] ~= 'https://example.com/sitemap.xml' then
+                return 'unexpected sitemap'
+            end
+        end
+        "#).unwrap();
+        script.test().expect("Failed to run script");
+    }
+}
@@ -0,0 +1,64 @@
+use crate::errors::*;
+
+use crate::engine::ctx::State;
+use crate::engine::structs::LuaList;
+use crate::hlua::{self, AnyLuaValue};
+use crate::scan::{self, Value};
+use std::sync::Arc;
+
+
+pub fn scan(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("scan", hlua::function2(move |input: String, pattern: String| -> Result<AnyLuaValue> {
+        let values = scan::scan(&input, &pattern)
+            .map_err(|err| state.set_error(err))?;
+
+        let values = match values {
+            Some(values) => values,
+            None => return Ok(AnyLuaValue::LuaNil),
+        };
+
+        let mut list = LuaList::new();
+        for value in values {
+            match value {
+                Value::Num(x) => list.push(AnyLuaValue::LuaNumber(x as f64)),
+                Value::Str(x) => list.push_str(x),
+            }
+        }
+        Ok(list.into())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::ctx::Script;
+
+    #[test]
+    fn verify_scan() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = scan('SSH-2.0-OpenSSH_7.9p1 Debian-10', 'SSH-%d.%d-%s')
+            if last_err() then return end
+            if x[1] ~= 2 or x[2] ~= 0 or x[3] ~= 'OpenSSH_7.9p1' then
+                return 'unexpected captures'
+            end
+
+            x = scan('HTTP/1.1 200 OK', 'SSH-%d.%d-%s')
+            if last_err() then return end
+            if x ~= nil then
+                return 'expected no match'
+            end
+        end
+        "#).unwrap();
+        script.test().expect("Failed to run script");
+    }
+
+    #[test]
+    fn verify_scan_invalid_pattern() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            scan('foo', '%q')
+        end
+        "#).unwrap();
+        assert!(script.test().is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use crate::errors::*;
+
+use crate::engine::ctx::State;
+use crate::engine::structs::byte_array;
+use crate::hlua::{self, AnyLuaValue};
+use crate::json::LuaJsonValue;
+use crate::sitemap;
+use serde_json;
+use std::sync::Arc;
+
+
+pub fn sitemap_parse(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("sitemap_parse", hlua::function1(move |xml: AnyLuaValue| -> Result<AnyLuaValue> {
+        let xml = byte_array(xml)
+            .map_err(|err| state.set_error(err))?;
+        let sitemap = sitemap::parse(&xml)
+            .map_err(|err| state.set_error(err))?;
+        let sitemap = serde_json::to_value(sitemap)
+            .map_err(|e| state.set_error(e.into()))?;
+        Ok(LuaJsonValue::from(sitemap).into())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::ctx::Script;
+
+    #[test]
+    fn verify_sitemap_parse() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = sitemap_parse([[<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url>
+    <loc>https://example.com/</loc>
+    <priority>0.5</priority>
+  </url>
+</urlset>
+]])
+            if last_err() then return end
+            if x['urls'][1]['loc'] ~= 'https://example.com/' then
+                return 'unexpected url'
+            end
+            if x['urls'][1]['priority'] ~= 0.5 then
+                return 'unexpected priority'
+            end
+            if #x['sitemaps'] ~= 0 then
+                return 'unexpected sitemaps'
+            end
+        end
+        "#).unwrap();
+        script.test().expect("Failed to run script");
+    }
+
+    #[test]
+    fn verify_sitemap_parse_invalid() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            sitemap_parse('<html></html>')
+        end
+        "#).unwrap();
+        assert!(script.test().is_err());
+    }
+}
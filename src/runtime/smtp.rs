@@ -0,0 +1,42 @@
+use crate::errors::*;
+
+use crate::engine::ctx::State;
+use crate::engine::structs;
+use crate::hlua::{self, AnyLuaValue};
+use crate::smtp::{SmtpClient, SmtpOptions};
+use sn0int_common::metadata::Capability;
+use std::sync::Arc;
+
+
+pub fn smtp_connect(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("smtp_connect", hlua::function3(move |host: String, port: u16, options: AnyLuaValue| -> Result<String> {
+        state.require(Capability::Network)
+            .map_err(|err| state.set_error(err))?;
+
+        let options = structs::from_lua::<SmtpOptions>(options.into())
+            .context("invalid smtp options")
+            .map_err(|err| state.set_error(err.into()))?;
+
+        let sock = state.sock_open(&host, port, &options.socket_options())
+            .map_err(|err| state.set_error(err))?;
+
+        let tls = options.tls(state.tls());
+        let client = SmtpClient::connect(sock, &host, &options, &tls)
+            .map_err(|err| state.set_error(err))?;
+
+        Ok(state.smtp_register(client))
+    }))
+}
+
+pub fn smtp_cmd(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("smtp_cmd", hlua::function2(move |client: String, cmd: String| -> Result<AnyLuaValue> {
+        let client = state.get_smtp(&client)
+            .map_err(|err| state.set_error(err))?;
+        let mut client = client.lock().unwrap();
+
+        let reply = client.cmd(&cmd)
+            .map_err(|err| state.set_error(err))?;
+
+        Ok(reply.into())
+    }))
+}
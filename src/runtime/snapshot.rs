@@ -0,0 +1,267 @@
+use crate::errors::*;
+
+use crate::engine::ctx::State;
+use crate::engine::structs::LuaMap;
+use crate::hlua::{self, AnyLuaValue};
+use crate::html::{self, HtmlTextOptions};
+use crate::json::LuaJsonValue;
+use crate::web::RequestOptions;
+use serde_json;
+use sha2::{Digest, Sha256};
+use sn0int_common::metadata::Capability;
+use std::sync::Arc;
+
+
+/// Options of snapshot, the remaining keys are regular request options
+#[derive(Debug, Default, Deserialize)]
+pub struct SnapshotOptions {
+    /// Key of the snapshot in the module state, defaults to `snapshot:` followed by the url
+    key: Option<String>,
+    /// Remove elements that change on every request, like timestamps or csrf tokens
+    #[serde(default)]
+    ignore: Vec<String>,
+    session: Option<String>,
+    #[serde(flatten)]
+    request: RequestOptions,
+}
+
+impl SnapshotOptions {
+    pub fn try_from(x: AnyLuaValue) -> Result<SnapshotOptions> {
+        match LuaJsonValue::from(x) {
+            LuaJsonValue::Null => Ok(SnapshotOptions::default()),
+            x => {
+                let x = serde_json::from_value(x.into())?;
+                Ok(x)
+            },
+        }
+    }
+}
+
+/// What is kept in the module state between runs
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Stored {
+    hash: String,
+    text: String,
+}
+
+#[derive(Debug, PartialEq)]
+struct Snapshot {
+    status: u16,
+    hash: String,
+    /// The hash of the last snapshot, None on the first run
+    previous: Option<String>,
+    text: String,
+    /// Unified diff against the last snapshot, empty if nothing changed
+    diff: String,
+}
+
+impl Snapshot {
+    fn changed(&self) -> bool {
+        match &self.previous {
+            Some(previous) => *previous != self.hash,
+            None => false,
+        }
+    }
+}
+
+impl From<Snapshot> for AnyLuaValue {
+    fn from(snapshot: Snapshot) -> AnyLuaValue {
+        let mut map = LuaMap::new();
+        map.insert_num("status", f64::from(snapshot.status));
+        map.insert("changed", AnyLuaValue::LuaBoolean(snapshot.changed()));
+        map.insert_str("hash", snapshot.hash);
+        if let Some(previous) = snapshot.previous {
+            map.insert_str("previous", previous);
+        }
+        map.insert_str("text", snapshot.text);
+        map.insert_str("diff", snapshot.diff);
+        map.into()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Html is reduced to its visible text so markup changes don't count as
+/// changes, everything else is compared as is
+fn normalize(body: &[u8], content_type: Option<&str>, ignore: &[String]) -> Result<String> {
+    let body = String::from_utf8_lossy(body);
+    let is_html = content_type
+        .map(|x| x.to_lowercase().contains("html"))
+        .unwrap_or(false);
+
+    if is_html || !ignore.is_empty() {
+        html::html_text_without(&body, ignore, &HtmlTextOptions::default())
+    } else {
+        Ok(body.into_owned())
+    }
+}
+
+/// Compare with the last snapshot and replace it
+fn compare(state: &State, key: String, text: String) -> Result<(String, Option<String>, String)> {
+    let hash = hex(&Sha256::digest(text.as_bytes()));
+
+    let previous = match state.state_get(key.clone())? {
+        Some(previous) => Some(serde_json::from_value::<Stored>(previous)
+            .context("Snapshot in state is invalid")?),
+        None => None,
+    };
+
+    let diff = match &previous {
+        Some(previous) if previous.hash != hash => super::diff::diff(&previous.text, &text),
+        _ => String::new(),
+    };
+
+    let stored = serde_json::to_value(Stored {
+        hash: hash.clone(),
+        text,
+    })?;
+    state.state_set(key, Some(stored))?;
+
+    Ok((hash, previous.map(|x| x.hash), diff))
+}
+
+fn snapshot_url(state: &State, url: String, options: SnapshotOptions) -> Result<Snapshot> {
+    let session = match options.session {
+        Some(session) => session,
+        None => state.http_mksession(),
+    };
+    let key = options.key.unwrap_or_else(|| format!("snapshot:{}", url));
+
+    let req = state.http_request(&session, "GET".to_string(), url, options.request);
+    let res = req.send_raw(state)?;
+
+    // error pages shouldn't replace the snapshot
+    if res.status < 200 || res.status >= 300 {
+        bail!("Unexpected http status: {}", res.status);
+    }
+
+    let content_type = res.headers.iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("content-type"))
+        .map(|(_, value)| value.as_str());
+    let text = normalize(&res.body, content_type, &options.ignore)?;
+
+    let (hash, previous, diff) = compare(state, key, text.clone())?;
+    Ok(Snapshot {
+        status: res.status,
+        hash,
+        previous,
+        text,
+        diff,
+    })
+}
+
+pub fn snapshot(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("snapshot", hlua::function2(move |url: String, options: AnyLuaValue| -> Result<AnyLuaValue> {
+        state.require(Capability::Network)
+            .map_err(|err| state.set_error(err))?;
+
+        let options = SnapshotOptions::try_from(options)
+            .context("invalid snapshot options")
+            .map_err(|err| state.set_error(Error::from(err)))?;
+
+        snapshot_url(state.as_ref(), url, options)
+            .map_err(|err| state.set_error(err))
+            .map(|snapshot| snapshot.into())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ctx::Script;
+    use crate::engine::tests::{shared, StateReporter};
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_normalize() {
+        let ignore = vec![".ts".to_string()];
+        let body = b"<p>hello</p><p class=\"ts\">12:03</p>";
+        assert_eq!(normalize(body, Some("text/html; charset=utf-8"), &ignore).unwrap(), "hello");
+        assert_eq!(normalize(b"<p>hello</p>", Some("text/plain"), &[]).unwrap(), "<p>hello</p>");
+        assert_eq!(normalize(b"{\"a\":1}", None, &[]).unwrap(), "{\"a\":1}");
+    }
+
+    /// Serve the current body as html on every request
+    fn serve_page(body: Arc<Mutex<String>>) -> u16 {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use std::thread;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 4096];
+                let _ = stream.read(&mut buf);
+                let body = body.lock().unwrap().clone();
+                let _ = stream.write_all(format!("HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Type: text/html\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).as_bytes());
+            }
+        });
+
+        port
+    }
+
+    #[test]
+    fn verify_snapshot_changes() {
+        let body = Arc::new(Mutex::new("<h1>Status</h1><p>all good</p><p class=\"ts\">12:03:11</p>".to_string()));
+        let port = serve_page(body.clone());
+        let reporter = shared(Box::new(StateReporter::default()));
+
+        let script = |expect: &str| Script::load_unchecked(format!(r#"
+        function run()
+            x = snapshot('http://127.0.0.1:{}/', {{
+                ignore={{'.ts'}},
+            }})
+            if last_err() then return end
+            {}
+        end
+        "#, port, expect)).expect("Failed to load script");
+
+        // the first snapshot has nothing to compare to
+        script(r#"
+            if x['changed'] or x['previous'] ~= nil or x['diff'] ~= '' then
+                return 'unexpected first snapshot'
+            end
+            if x['text'] ~= 'Status\n\nall good' then
+                return 'unexpected text: ' .. x['text']
+            end
+        "#).test_with_reporter(reporter.clone()).expect("Script failed");
+
+        // ignored elements don't count as change
+        *body.lock().unwrap() = "<h1>Status</h1><p>all good</p><p class=\"ts\">12:04:52</p>".to_string();
+        script(r#"
+            if x['changed'] or x['previous'] ~= x['hash'] or x['diff'] ~= '' then
+                return 'unexpected change'
+            end
+        "#).test_with_reporter(reporter.clone()).expect("Script failed");
+
+        *body.lock().unwrap() = "<h1>Status</h1><p>degraded</p><p class=\"ts\">12:05:30</p>".to_string();
+        script(r#"
+            if not x['changed'] or x['previous'] == x['hash'] then
+                return 'change not detected'
+            end
+            if x['diff'] ~= '--- a\n+++ b\n@@ -1,3 +1,3 @@\n Status\n \n-all good\n\\ No newline at end of file\n+degraded\n\\ No newline at end of file\n' then
+                return 'unexpected diff: ' .. x['diff']
+            end
+        "#).test_with_reporter(reporter).expect("Script failed");
+    }
+
+    #[test]
+    fn verify_snapshot_invalid_selector() {
+        let port = serve_page(Arc::new(Mutex::new("<p>hello</p>".to_string())));
+        let script = Script::load_unchecked(format!(r#"
+        function run()
+            snapshot('http://127.0.0.1:{}/', {{
+                ignore={{'<'}},
+            }})
+        end
+        "#, port)).expect("Failed to load script");
+        assert!(script.test_with_reporter(shared(Box::new(StateReporter::default()))).is_err());
+    }
+}
@@ -1,14 +1,23 @@
 use crate::errors::*;
 
 use crate::engine::ctx::State;
-use crate::engine::structs::{byte_array, lua_bytes};
+use crate::engine::structs::{self, byte_array, lua_bytes};
 use crate::hlua::{self, AnyLuaValue};
+use crate::sockets::{self, BannerOptions, SocketOptions};
+use sn0int_common::metadata::Capability;
 use std::sync::Arc;
 
 
 pub fn sock_connect(lua: &mut hlua::Lua, state: Arc<State>) {
-    lua.set("sock_connect", hlua::function3(move |host: String, port: u16, _settings: AnyLuaValue| -> Result<String> {
-        state.sock_connect(&host, port)
+    lua.set("sock_connect", hlua::function3(move |host: String, port: u16, options: AnyLuaValue| -> Result<String> {
+        state.require(Capability::Network)
+            .map_err(|err| state.set_error(err))?;
+
+        let options = SocketOptions::try_from(options)
+            .context("invalid socket options")
+            .map_err(|err| state.set_error(Error::from(err)))?;
+
+        state.sock_connect(&host, port, &options)
             .map_err(|err| state.set_error(err))
     }))
 }
@@ -153,3 +162,23 @@ pub fn sock_newline(lua: &mut hlua::Lua, state: Arc<State>) {
         sock.newline(newline);
     }))
 }
+
+pub fn grab_banner(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("grab_banner", hlua::function3(move |host: String, port: u16, options: AnyLuaValue| -> Result<AnyLuaValue> {
+        state.require(Capability::Network)
+            .map_err(|err| state.set_error(err))?;
+
+        let options = structs::from_lua::<BannerOptions>(options.into())
+            .context("invalid banner options")
+            .map_err(|err| state.set_error(err.into()))?;
+
+        let sock = state.sock_open(&host, port, &options.socket_options())
+            .map_err(|err| state.set_error(err))?;
+
+        let tls = options.tls(state.tls());
+        let banner = sockets::grab_banner(sock, &host, &options, &tls)
+            .map_err(|err| state.set_error(err))?;
+
+        Ok(lua_bytes(&banner))
+    }))
+}
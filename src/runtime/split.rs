@@ -0,0 +1,170 @@
+use crate::errors::*;
+
+use crate::engine::ctx::State;
+use crate::engine::structs::{byte_array, lua_bytes, LuaList};
+use crate::hlua::{self, AnyLuaValue};
+use std::sync::Arc;
+
+
+/// Yields one line at a time, without `\n` or `\r\n`. A trailing newline
+/// doesn't start another line, empty lines in between are kept.
+#[derive(Debug)]
+struct Lines {
+    bytes: Vec<u8>,
+    pos: usize,
+}
+
+impl Lines {
+    fn new(bytes: Vec<u8>) -> Lines {
+        Lines {
+            bytes,
+            pos: 0,
+        }
+    }
+}
+
+impl Iterator for Lines {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        if self.pos >= self.bytes.len() {
+            return None;
+        }
+
+        let rest = &self.bytes[self.pos..];
+        let (mut line, consumed) = match rest.iter().position(|b| *b == b'\n') {
+            Some(idx) => (&rest[..idx], idx + 1),
+            None => (rest, rest.len()),
+        };
+        if line.ends_with(b"\r") {
+            line = &line[..line.len() - 1];
+        }
+
+        let line = line.to_vec();
+        self.pos += consumed;
+        Some(line)
+    }
+}
+
+/// Split at every `sep`, empty fields are kept. With a `limit` there are at
+/// most that many fields and the last one contains the rest of the string.
+fn split_str<'a>(s: &'a str, sep: &str, limit: Option<usize>) -> Result<Vec<&'a str>> {
+    if sep.is_empty() {
+        bail!("Separator can't be empty");
+    }
+    match limit {
+        Some(0) => bail!("Limit must be at least 1"),
+        Some(limit) => Ok(s.splitn(limit, sep).collect()),
+        None => Ok(s.split(sep).collect()),
+    }
+}
+
+pub fn lines(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("lines", hlua::function1(move |bytes: AnyLuaValue| -> Result<hlua::Function<_, (), _>> {
+        let bytes = byte_array(bytes)
+            .map_err(|err| state.set_error(err))?;
+
+        let mut lines = Lines::new(bytes);
+        Ok(hlua::function0(move || -> AnyLuaValue {
+            match lines.next() {
+                Some(line) => lua_bytes(&line),
+                None => AnyLuaValue::LuaNil,
+            }
+        }))
+    }))
+}
+
+pub fn split(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("split", hlua::function3(move |s: String, sep: String, limit: AnyLuaValue| -> Result<AnyLuaValue> {
+        let limit = match limit {
+            AnyLuaValue::LuaNil => None,
+            AnyLuaValue::LuaNumber(x) if x >= 0.0 && x % 1.0 == 0.0 => Some(x as usize),
+            x => return Err(state.set_error(format_err!("Limit must be a positive integer: {:?}", x))),
+        };
+
+        let fields = split_str(&s, &sep, limit)
+            .map_err(|err| state.set_error(err))?;
+
+        let mut list = LuaList::new();
+        for field in fields {
+            list.push_str(field);
+        }
+        Ok(list.into())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ctx::Script;
+
+    fn lines(input: &str) -> Vec<String> {
+        Lines::new(input.as_bytes().to_vec())
+            .map(|line| String::from_utf8(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_lines() {
+        assert_eq!(lines("a\nb\nc"), vec!["a", "b", "c"]);
+        assert_eq!(lines("a\r\nb\r\n"), vec!["a", "b"]);
+        // only the trailing newline doesn't start a line
+        assert_eq!(lines("a\n\nb\n\n"), vec!["a", "", "b", ""]);
+        assert_eq!(lines("\n"), vec![""]);
+        assert_eq!(lines(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split_str("a,b,c", ",", None).unwrap(), vec!["a", "b", "c"]);
+        assert_eq!(split_str("a,,b,", ",", None).unwrap(), vec!["a", "", "b", ""]);
+        assert_eq!(split_str("", ",", None).unwrap(), vec![""]);
+        assert_eq!(split_str("a::b::c", "::", None).unwrap(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_split_limit() {
+        assert_eq!(split_str("a,b,c", ",", Some(2)).unwrap(), vec!["a", "b,c"]);
+        assert_eq!(split_str("a,b,c", ",", Some(1)).unwrap(), vec!["a,b,c"]);
+        assert_eq!(split_str("a,b", ",", Some(5)).unwrap(), vec!["a", "b"]);
+        assert!(split_str("a,b", ",", Some(0)).is_err());
+        assert!(split_str("a,b", "", None).is_err());
+    }
+
+    #[test]
+    fn verify_lines() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            n = 0
+            for line in lines("a\r\n\nb\n") do
+                n = n + 1
+                if n == 1 and line ~= 'a' then return 'unexpected first line' end
+                if n == 2 and line ~= '' then return 'unexpected second line' end
+                if n == 3 and line ~= 'b' then return 'unexpected third line' end
+            end
+            if n ~= 3 then
+                return 'unexpected number of lines: ' .. n
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_split() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = split("key=value=more", "=", 2)
+            if #x ~= 2 or x[1] ~= 'key' or x[2] ~= 'value=more' then
+                return 'unexpected split with limit'
+            end
+
+            x = split("a,,b", ",", nil)
+            if #x ~= 3 or x[2] ~= '' then
+                return 'unexpected split'
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+}
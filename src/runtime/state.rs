@@ -0,0 +1,29 @@
+use crate::errors::*;
+use crate::engine::ctx::State;
+use crate::hlua::{self, AnyLuaValue};
+use crate::json::LuaJsonValue;
+use std::sync::Arc;
+
+
+pub fn state_get(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("state_get", hlua::function1(move |key: String| -> Result<AnyLuaValue> {
+        let value = state.state_get(key)
+            .map_err(|err| state.set_error(err))?;
+
+        Ok(value.map(|x| LuaJsonValue::from(x).into())
+            .unwrap_or(AnyLuaValue::LuaNil))
+    }))
+}
+
+pub fn state_set(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("state_set", hlua::function2(move |key: String, value: AnyLuaValue| -> Result<()> {
+        // setting a key to nil deletes it
+        let value = match value {
+            AnyLuaValue::LuaNil => None,
+            value => Some(LuaJsonValue::from(value).into()),
+        };
+
+        state.state_set(key, value)
+            .map_err(|err| state.set_error(err))
+    }))
+}
@@ -0,0 +1,233 @@
+use crate::errors::*;
+
+use crate::engine::ctx::State;
+use crate::engine::structs;
+use crate::json::LuaJsonValue;
+use crate::hlua::{self, AnyLuaValue};
+use chrono::Utc;
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use serde_json;
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use std::sync::Arc;
+
+
+/// Decode rfc 4648 base32, this is how totp secrets are usually shared.
+/// Whitespace, dashes and padding are ignored and lowercase is accepted.
+fn base32_decode(x: &str) -> Result<Vec<u8>> {
+    let x = x.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-' && *c != '=')
+        .collect::<String>()
+        .to_uppercase();
+    BASE32_NOPAD.decode(x.as_bytes())
+        .map_err(Error::from)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    fn from_str(x: &str) -> Result<Algorithm> {
+        match x.to_lowercase().as_str() {
+            "sha1" => Ok(Algorithm::Sha1),
+            "sha256" => Ok(Algorithm::Sha256),
+            "sha512" => Ok(Algorithm::Sha512),
+            _ => bail!("Unknown algorithm: {:?}", x),
+        }
+    }
+
+    fn hmac(self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        macro_rules! hmac {
+            ($hash:ty) => {{
+                let mut mac = Hmac::<$hash>::new_varkey(key)
+                    .expect("hmac accepts keys of any size");
+                mac.input(data);
+                mac.result().code().to_vec()
+            }}
+        }
+
+        match self {
+            Algorithm::Sha1 => hmac!(Sha1),
+            Algorithm::Sha256 => hmac!(Sha256),
+            Algorithm::Sha512 => hmac!(Sha512),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct TotpOptions {
+    digits: Option<u32>,
+    period: Option<u64>,
+    algorithm: Option<String>,
+    /// unix timestamp, defaults to now
+    time: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Totp {
+    code: String,
+    /// seconds until the next code is valid
+    remaining: u64,
+}
+
+/// rfc 6238
+fn generate(secret: &str, options: TotpOptions) -> Result<Totp> {
+    let secret = base32_decode(secret)
+        .context("Secret isn't valid base32")?;
+    if secret.is_empty() {
+        bail!("Secret is empty");
+    }
+
+    let digits = options.digits.unwrap_or(6);
+    match digits {
+        6..=8 => (),
+        _ => bail!("Digits need to be between 6 and 8, got {}", digits),
+    }
+    let period = options.period.unwrap_or(30);
+    if period == 0 {
+        bail!("Period needs to be at least one second");
+    }
+    let algorithm = match options.algorithm {
+        Some(algorithm) => Algorithm::from_str(&algorithm)?,
+        None => Algorithm::Sha1,
+    };
+    let time = match options.time {
+        Some(time) => time,
+        None => Utc::now().timestamp() as u64,
+    };
+
+    let counter = time / period;
+    let hash = algorithm.hmac(&secret, &counter.to_be_bytes());
+
+    // rfc 4226 dynamic truncation
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let code = u32::from_be_bytes([hash[offset], hash[offset+1], hash[offset+2], hash[offset+3]]) & 0x7fff_ffff;
+    let code = code % 10u32.pow(digits);
+
+    Ok(Totp {
+        code: format!("{:0width$}", code, width=digits as usize),
+        remaining: period - time % period,
+    })
+}
+
+pub fn totp(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("totp", hlua::function2(move |secret: String, options: AnyLuaValue| -> Result<AnyLuaValue> {
+        let options = structs::from_lua::<TotpOptions>(options.into())
+            .context("invalid totp options")
+            .map_err(|e| state.set_error(e.into()))?;
+
+        let totp = generate(&secret, options)
+            .map_err(|e| state.set_error(e))?;
+
+        let totp = serde_json::to_value(totp)
+            .map_err(|e| state.set_error(e.into()))?;
+
+        Ok(LuaJsonValue::from(totp).into())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ctx::Script;
+
+    // rfc 6238 appendix b, the seed is padded to the size of the hash
+    const SEED_SHA1: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+    const SEED_SHA256: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA";
+    const SEED_SHA512: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNA";
+
+    fn code(secret: &str, algorithm: &str, time: u64) -> String {
+        generate(secret, TotpOptions {
+            digits: Some(8),
+            algorithm: Some(algorithm.to_string()),
+            time: Some(time),
+            ..Default::default()
+        }).unwrap().code
+    }
+
+    #[test]
+    fn test_base32_decode() {
+        assert_eq!(base32_decode("JBSWY3DPEHPK3PXP").unwrap(), b"Hello!\xde\xad\xbe\xef");
+        assert_eq!(base32_decode("jbsw y3dp ehpk 3pxp").unwrap(), b"Hello!\xde\xad\xbe\xef");
+        assert_eq!(base32_decode("MZXW6===").unwrap(), b"foo");
+        assert!(base32_decode("JBSWY3DP1").is_err());
+    }
+
+    #[test]
+    fn test_rfc6238() {
+        assert_eq!(code(SEED_SHA1, "sha1", 59), "94287082");
+        assert_eq!(code(SEED_SHA256, "sha256", 59), "46119246");
+        assert_eq!(code(SEED_SHA512, "sha512", 59), "90693936");
+        assert_eq!(code(SEED_SHA1, "sha1", 1_111_111_109), "07081804");
+        assert_eq!(code(SEED_SHA256, "SHA256", 1_111_111_109), "68084774");
+        assert_eq!(code(SEED_SHA512, "sha512", 1_111_111_109), "25091201");
+        assert_eq!(code(SEED_SHA1, "sha1", 20_000_000_000), "65353130");
+        assert_eq!(code(SEED_SHA512, "sha512", 20_000_000_000), "47863826");
+    }
+
+    #[test]
+    fn test_remaining() {
+        let totp = generate(SEED_SHA1, TotpOptions {
+            time: Some(59),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(totp, Totp {
+            code: "287082".to_string(),
+            remaining: 1,
+        });
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        assert!(generate("", TotpOptions::default()).is_err());
+        assert!(generate("not base32!", TotpOptions::default()).is_err());
+        assert!(generate(SEED_SHA1, TotpOptions {
+            digits: Some(12),
+            ..Default::default()
+        }).is_err());
+        assert!(generate(SEED_SHA1, TotpOptions {
+            period: Some(0),
+            ..Default::default()
+        }).is_err());
+        assert!(generate(SEED_SHA1, TotpOptions {
+            algorithm: Some("md5".to_string()),
+            ..Default::default()
+        }).is_err());
+    }
+
+    #[test]
+    fn verify_totp() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = totp('GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ', {time=1111111109, digits=8})
+            if x['code'] ~= '07081804' then
+                return 'unexpected code: ' .. x['code']
+            end
+            if x['remaining'] ~= 1 then
+                return 'unexpected remaining: ' .. x['remaining']
+            end
+
+            x = totp('GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ', {})
+            if #x['code'] ~= 6 or x['remaining'] < 1 or x['remaining'] > 30 then
+                return 'unexpected totp'
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_totp_invalid() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            totp('GEZDGNBVGY3TQOJQ=1', {})
+        end
+        "#).expect("Failed to load script");
+        assert!(script.test().is_err());
+    }
+}
@@ -3,9 +3,10 @@ use crate::engine::ctx::State;
 use crate::engine::structs::LuaMap;
 use crate::hlua::{self, AnyLuaValue};
 use crate::json::LuaJsonValue;
-use url::Url;
+use crate::url_canonical;
+use url::{form_urlencoded, Url};
 use url::percent_encoding::{percent_encode, percent_decode, DEFAULT_ENCODE_SET};
-use serde_json::Value;
+use serde_json::{Map, Value};
 use serde_urlencoded;
 use std::sync::Arc;
 
@@ -20,6 +21,19 @@ pub fn url_join(lua: &mut hlua::Lua, state: Arc<State>) {
     }))
 }
 
+pub fn url_canonical(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("url_canonical", hlua::function2(move |url: String, sort_query: AnyLuaValue| -> Result<String> {
+        let sort_query = match sort_query {
+            AnyLuaValue::LuaNil => false,
+            AnyLuaValue::LuaBoolean(x) => x,
+            x => return Err(state.set_error(format_err!("sort_query must be a boolean: {:?}", x))),
+        };
+
+        url_canonical::canonical(&url, sort_query)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
 pub fn url_parse(lua: &mut hlua::Lua, state: Arc<State>) {
     lua.set("url_parse", hlua::function1(move |url: String| -> Result<AnyLuaValue> {
         let url = Url::parse(&url)
@@ -78,6 +92,48 @@ pub fn url_decode(lua: &mut hlua::Lua, state: Arc<State>) {
     }))
 }
 
+/// Decode urlencoded pairs into a map. Keys that show up once are mapped to
+/// a string, keys that are repeated are mapped to a list of all values in the
+/// order they appeared. Keys are taken literally, `a[]` isn't treated special.
+fn decode_pairs(input: &str) -> Value {
+    let mut map = Map::new();
+
+    for (key, value) in form_urlencoded::parse(input.as_bytes()) {
+        let value = Value::String(value.into_owned());
+
+        if let Some(existing) = map.get_mut(key.as_ref()) {
+            match existing {
+                Value::Array(list) => list.push(value),
+                _ => {
+                    let first = existing.take();
+                    *existing = Value::Array(vec![first, value]);
+                },
+            }
+            continue;
+        }
+
+        map.insert(key.into_owned(), value);
+    }
+
+    Value::Object(map)
+}
+
+pub fn query_decode(lua: &mut hlua::Lua, _state: Arc<State>) {
+    lua.set("query_decode", hlua::function1(move |v: String| -> AnyLuaValue {
+        let v = v.trim_start_matches('?');
+        let v: LuaJsonValue = decode_pairs(v).into();
+        v.into()
+    }))
+}
+
+pub fn form_decode(lua: &mut hlua::Lua, _state: Arc<State>) {
+    lua.set("form_decode", hlua::function1(move |v: String| -> AnyLuaValue {
+        // bodies copied from a request often end with a newline
+        let v: LuaJsonValue = decode_pairs(v.trim_end()).into();
+        v.into()
+    }))
+}
+
 pub fn url_escape(lua: &mut hlua::Lua, _state: Arc<State>) {
     lua.set("url_escape", hlua::function1(move |v: String| -> String {
         percent_encode(v.as_bytes(), DEFAULT_ENCODE_SET)
@@ -96,7 +152,49 @@ pub fn url_unescape(lua: &mut hlua::Lua, state: Arc<State>) {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
     use crate::engine::ctx::Script;
+    use serde_json::json;
+
+    #[test]
+    fn test_decode_pairs() {
+        let v = decode_pairs("a=b&x=1&x=2&x=3&empty=&flag&&y[]=asdf");
+        assert_eq!(v, json!({
+            "a": "b",
+            "x": ["1", "2", "3"],
+            "empty": "",
+            "flag": "",
+            "y[]": "asdf",
+        }));
+    }
+
+    #[test]
+    fn test_decode_pairs_escapes() {
+        let v = decode_pairs("q=foo+bar%20%2B%26%3D&%F0%9F%A6%80=%zz&bin=%ff");
+        assert_eq!(v, json!({
+            "q": "foo bar +&=",
+            "\u{1f980}": "%zz",
+            "bin": "\u{fffd}",
+        }));
+    }
+
+    #[test]
+    fn verify_url_canonical() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            url = url_canonical("HTTPS://Example.COM:443/a/../b?z=1&a=2", nil)
+            if url ~= "https://example.com/b?z=1&a=2" then
+                return 'unexpected url: ' .. url
+            end
+
+            url = url_canonical("https://example.com/?z=1&a=2", true)
+            if url ~= "https://example.com/?a=2&z=1" then
+                return 'unexpected sorted url: ' .. url
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
 
     #[test]
     fn verify_relative_path() {
@@ -276,6 +374,30 @@ mod tests {
         script.test().expect("Script failed");
     }
 
+    #[test]
+    fn verify_query_decode() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            v = query_decode('?a=b&x=1&x=2')
+            if v['a'] ~= 'b' then return 'wrong a value' end
+            if v['x'][1] ~= '1' or v['x'][2] ~= '2' then return 'wrong x value' end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_form_decode() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            v = form_decode('user=admin%40example.com&pass=hunter+2\r\n')
+            if v['user'] ~= 'admin@example.com' then return 'wrong user value' end
+            if v['pass'] ~= 'hunter 2' then return 'wrong pass value' end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
     #[test]
     fn verify_url_escape() {
         let script = Script::load_unchecked(r#"
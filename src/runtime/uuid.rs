@@ -0,0 +1,103 @@
+use crate::engine::ctx::State;
+use crate::hlua;
+use chrono::Utc;
+use rand::prelude::*;
+use std::sync::Arc;
+
+
+/// Set the version and the rfc 4122 variant and format as lowercase hex
+fn format_uuid(mut bytes: [u8; 16], version: u8) -> String {
+    bytes[6] = (bytes[6] & 0x0f) | (version << 4);
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex = bytes.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+fn v4() -> String {
+    let mut bytes = [0u8; 16];
+    thread_rng().fill(&mut bytes);
+    format_uuid(bytes, 4)
+}
+
+/// Starts with the unix timestamp in milliseconds, so they are sortable by
+/// creation time
+fn v7(millis: u64) -> String {
+    let mut bytes = [0u8; 16];
+    thread_rng().fill(&mut bytes[6..]);
+    bytes[..6].copy_from_slice(&millis.to_be_bytes()[2..]);
+    format_uuid(bytes, 7)
+}
+
+pub fn uuid_v4(lua: &mut hlua::Lua, _: Arc<State>) {
+    lua.set("uuid_v4", hlua::function0(move || -> String {
+        v4()
+    }))
+}
+
+pub fn uuid_v7(lua: &mut hlua::Lua, _: Arc<State>) {
+    lua.set("uuid_v7", hlua::function0(move || -> String {
+        v7(Utc::now().timestamp_millis() as u64)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ctx::Script;
+
+    fn assert_format(uuid: &str, version: char) {
+        assert_eq!(uuid.len(), 36, "{:?}", uuid);
+        for (i, c) in uuid.chars().enumerate() {
+            match i {
+                8 | 13 | 18 | 23 => assert_eq!(c, '-', "{:?}", uuid),
+                14 => assert_eq!(c, version, "{:?}", uuid),
+                19 => assert!("89ab".contains(c), "{:?}", uuid),
+                _ => assert!("0123456789abcdef".contains(c), "{:?}", uuid),
+            }
+        }
+    }
+
+    #[test]
+    fn test_format_uuid() {
+        assert_eq!(format_uuid([0xff; 16], 4), "ffffffff-ffff-4fff-bfff-ffffffffffff");
+        assert_eq!(format_uuid([0; 16], 7), "00000000-0000-7000-8000-000000000000");
+    }
+
+    #[test]
+    fn test_v4() {
+        let a = v4();
+        assert_format(&a, '4');
+        assert_ne!(a, v4());
+    }
+
+    #[test]
+    fn test_v7() {
+        // rfc 9562 appendix a.6
+        let uuid = v7(0x0189_7f4e_9a74);
+        assert_format(&uuid, '7');
+        assert!(uuid.starts_with("01897f4e-9a74-7"), "{:?}", uuid);
+
+        assert!(v7(1_000) < v7(1_001));
+    }
+
+    #[test]
+    fn verify_uuid() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            a = uuid_v4()
+            b = uuid_v7()
+            if not regex_find('^[0-9a-f]{8}-[0-9a-f]{4}-4[0-9a-f]{3}-[89ab][0-9a-f]{3}-[0-9a-f]{12}$', a) then
+                return 'unexpected v4: ' .. a
+            end
+            if not regex_find('^[0-9a-f]{8}-[0-9a-f]{4}-7[0-9a-f]{3}-[89ab][0-9a-f]{3}-[0-9a-f]{12}$', b) then
+                return 'unexpected v7: ' .. b
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+}
@@ -0,0 +1,78 @@
+use crate::errors::*;
+use crate::engine::ctx::State;
+use crate::hlua::{self, AnyLuaValue};
+use crate::json::LuaJsonValue;
+use crate::yaml;
+use serde_json::Value;
+use std::sync::Arc;
+
+
+/// Documents that contain multiple documents are returned as a list
+pub fn yaml_decode(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("yaml_decode", hlua::function1(move |x: String| -> Result<AnyLuaValue> {
+        let mut docs = yaml::decode(&x)
+            .context("Failed to parse yaml")
+            .map_err(|err| state.set_error(err.into()))?;
+
+        let v = match docs.len() {
+            0 => Value::Null,
+            1 => docs.remove(0),
+            _ => Value::Array(docs),
+        };
+        Ok(LuaJsonValue::from(v).into())
+    }))
+}
+
+pub fn yaml_encode(lua: &mut hlua::Lua, state: Arc<State>) {
+    lua.set("yaml_encode", hlua::function1(move |x: AnyLuaValue| -> Result<String> {
+        let v: Value = LuaJsonValue::from(x).into();
+        yaml::encode(&v)
+            .map_err(|err| state.set_error(err))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::ctx::Script;
+
+    #[test]
+    fn verify_yaml_decode() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = yaml_decode('name: web\nports: [80, 443]\nenv:\n  DEBUG: true\n')
+            if not (x['name'] == 'web' and x['ports'][2] == 443 and x['env']['DEBUG'] == true) then
+                return 'decode failed: ' .. json_encode(x)
+            end
+
+            x = yaml_decode('--- a\n--- b\n')
+            if not (x[1] == 'a' and x[2] == 'b') then
+                return 'decode of multiple documents failed: ' .. json_encode(x)
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+
+    #[test]
+    fn verify_yaml_decode_invalid() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            yaml_decode('a: [1, 2\n')
+        end
+        "#).expect("Failed to load script");
+        assert!(script.test().is_err());
+    }
+
+    #[test]
+    fn verify_yaml_encode() {
+        let script = Script::load_unchecked(r#"
+        function run()
+            x = yaml_encode({a={1,2}, b='yes'})
+            if x ~= 'a:\n  - 1\n  - 2\nb: "yes"\n' then
+                return 'encode failed: ' .. x
+            end
+        end
+        "#).expect("Failed to load script");
+        script.test().expect("Script failed");
+    }
+}
@@ -0,0 +1,305 @@
+use crate::errors::*;
+
+use crate::db::Database;
+use crate::db::findings::{Finding, Severity};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+
+pub const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+pub const VERSION: &str = "2.1.0";
+
+/// Used for findings from before the module was recorded
+const UNKNOWN_MODULE: &str = "unknown";
+
+#[derive(Debug, Serialize)]
+pub struct Log {
+    #[serde(rename="$schema")]
+    pub schema: &'static str,
+    pub version: &'static str,
+    pub runs: Vec<Run>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Run {
+    pub tool: Tool,
+    pub results: Vec<SarifResult>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Tool {
+    pub driver: Driver,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all="camelCase")]
+pub struct Driver {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub information_uri: &'static str,
+    pub rules: Vec<Rule>,
+}
+
+/// Every module that reported a finding is a rule
+#[derive(Debug, Serialize)]
+#[serde(rename_all="camelCase")]
+pub struct Rule {
+    pub id: String,
+    pub name: String,
+    pub short_description: Message,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Message {
+    pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all="camelCase")]
+pub struct SarifResult {
+    pub rule_id: String,
+    pub rule_index: usize,
+    pub level: &'static str,
+    pub message: Message,
+    #[serde(skip_serializing_if="Vec::is_empty")]
+    pub locations: Vec<Location>,
+    pub properties: Properties,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all="camelCase")]
+pub struct Location {
+    pub logical_locations: Vec<LogicalLocation>,
+}
+
+/// The entity a finding points to
+#[derive(Debug, Serialize)]
+#[serde(rename_all="camelCase")]
+pub struct LogicalLocation {
+    #[serde(skip_serializing_if="Option::is_none")]
+    pub name: Option<String>,
+    pub fully_qualified_name: String,
+    pub kind: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Properties {
+    pub id: i32,
+    pub severity: String,
+    pub created: String,
+}
+
+/// Map our severities to the levels defined by sarif
+pub fn level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Critical | Severity::High => "error",
+        Severity::Medium => "warning",
+        Severity::Low | Severity::Info => "note",
+    }
+}
+
+impl Log {
+    /// Build the log from findings and the label of the entity they point
+    /// to, `None` if it has been deleted in the meantime
+    pub fn new(findings: &[(Finding, Option<String>)]) -> Result<Log> {
+        let mut rules = Vec::new();
+        let mut rule_indexes = HashMap::new();
+        let mut results = Vec::new();
+
+        for (finding, label) in findings {
+            let module = finding.module.as_deref()
+                .unwrap_or(UNKNOWN_MODULE);
+
+            let rule_index = *rule_indexes.entry(module.to_string())
+                .or_insert_with(|| {
+                    rules.push(Rule {
+                        id: module.to_string(),
+                        name: module.to_string(),
+                        short_description: Message {
+                            text: format!("Findings reported by {}", module),
+                        },
+                    });
+                    rules.len() - 1
+                });
+
+            let severity = Severity::from_str(&finding.severity)?;
+
+            let mut text = finding.title.clone();
+            if let Some(details) = &finding.details {
+                text.push_str("\n\n");
+                text.push_str(details);
+            }
+
+            let locations = match (&finding.family, finding.key) {
+                (Some(family), Some(key)) => vec![Location {
+                    logical_locations: vec![LogicalLocation {
+                        name: label.clone(),
+                        fully_qualified_name: format!("{}#{}", family, key),
+                        kind: family.clone(),
+                    }],
+                }],
+                _ => Vec::new(),
+            };
+
+            results.push(SarifResult {
+                rule_id: module.to_string(),
+                rule_index,
+                level: level(severity),
+                message: Message {
+                    text,
+                },
+                locations,
+                properties: Properties {
+                    id: finding.id,
+                    severity: finding.severity.clone(),
+                    created: finding.created.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                },
+            });
+        }
+
+        Ok(Log {
+            schema: SCHEMA,
+            version: VERSION,
+            runs: vec![Run {
+                tool: Tool {
+                    driver: Driver {
+                        name: "sn0int",
+                        version: env!("CARGO_PKG_VERSION"),
+                        information_uri: "https://github.com/kpcyrd/sn0int",
+                        rules,
+                    },
+                },
+                results,
+            }],
+        })
+    }
+}
+
+pub fn export(db: &Database, findings: Vec<Finding>) -> Result<Log> {
+    let findings = findings.into_iter()
+        .map(|finding| {
+            let label = finding.entity(db).and_then(|x| x.ok());
+            (finding, label)
+        })
+        .collect::<Vec<_>>();
+    Log::new(&findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use serde_json::{self, Value};
+
+    fn finding(id: i32, severity: &str, module: Option<&str>, entity: Option<(&str, i32)>) -> Finding {
+        Finding {
+            id,
+            severity: severity.into(),
+            title: "Exposed .git directory".into(),
+            family: entity.map(|x| x.0.to_string()),
+            key: entity.map(|x| x.1),
+            details: Some("/.git/config is readable".into()),
+            created: NaiveDate::from_ymd(2019, 4, 6).and_hms(13, 37, 0),
+            module: module.map(String::from),
+        }
+    }
+
+    fn object<'a>(v: &'a Value, required: &[&str]) -> &'a serde_json::Map<String, Value> {
+        let obj = v.as_object().expect("expected an object");
+        for key in required {
+            assert!(obj.contains_key(*key), "missing required property {:?} in {}", key, v);
+        }
+        obj
+    }
+
+    /// Check the properties and restrictions the sarif 2.1.0 schema puts
+    /// on everything we emit
+    fn validate(v: &Value) {
+        let log = object(v, &["version", "runs"]);
+        assert_eq!(log["version"], "2.1.0");
+        assert!(log["$schema"].as_str().unwrap().starts_with("https://"));
+
+        for run in log["runs"].as_array().unwrap() {
+            let run = object(run, &["tool"]);
+            let driver = object(&object(&run["tool"], &["driver"])["driver"], &["name"]);
+            assert!(driver["name"].is_string());
+            assert!(driver["informationUri"].as_str().unwrap().starts_with("https://"));
+
+            let rules = driver["rules"].as_array().unwrap();
+            for rule in rules {
+                let rule = object(rule, &["id"]);
+                assert!(rule["id"].is_string());
+                let desc = object(&rule["shortDescription"], &["text"]);
+                assert!(desc["text"].is_string());
+            }
+
+            for result in run["results"].as_array().unwrap() {
+                let result = object(result, &["message"]);
+                assert!(object(&result["message"], &["text"])["text"].is_string());
+                assert!(["none", "note", "warning", "error"].contains(&result["level"].as_str().unwrap()));
+
+                let index = result["ruleIndex"].as_u64().unwrap() as usize;
+                assert!(index < rules.len());
+                assert_eq!(rules[index]["id"], result["ruleId"]);
+
+                if let Some(locations) = result.get("locations") {
+                    for location in locations.as_array().unwrap() {
+                        for logical in location["logicalLocations"].as_array().unwrap() {
+                            let logical = logical.as_object().unwrap();
+                            assert!(logical["fullyQualifiedName"].is_string());
+                            assert!(logical["kind"].is_string());
+                        }
+                    }
+                }
+
+                assert!(result["properties"].is_object());
+            }
+        }
+    }
+
+    #[test]
+    fn test_sarif_export() {
+        let log = Log::new(&[
+            (finding(1, "high", Some("kpcyrd/git"), Some(("url", 3))), Some("https://example.com/.git/".into())),
+            (finding(2, "info", Some("kpcyrd/dirlist"), None), None),
+            (finding(3, "medium", Some("kpcyrd/git"), Some(("url", 4))), None),
+            (finding(4, "critical", None, None), None),
+        ]).unwrap();
+
+        let v = serde_json::to_value(&log).unwrap();
+        validate(&v);
+
+        let run = &v["runs"][0];
+        let rules = run["tool"]["driver"]["rules"].as_array().unwrap();
+        assert_eq!(rules.iter().map(|x| x["id"].as_str().unwrap()).collect::<Vec<_>>(),
+                   &["kpcyrd/git", "kpcyrd/dirlist", "unknown"]);
+
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.iter().map(|x| x["level"].as_str().unwrap()).collect::<Vec<_>>(),
+                   &["error", "note", "warning", "error"]);
+        assert_eq!(results[2]["ruleIndex"], 0);
+        assert_eq!(results[0]["message"]["text"], "Exposed .git directory\n\n/.git/config is readable");
+        assert_eq!(results[0]["locations"][0]["logicalLocations"][0], serde_json::json!({
+            "name": "https://example.com/.git/",
+            "fullyQualifiedName": "url#3",
+            "kind": "url",
+        }));
+        // the entity has been deleted
+        assert!(results[2]["locations"][0]["logicalLocations"][0].get("name").is_none());
+        assert!(results[1].get("locations").is_none());
+        assert_eq!(results[3]["properties"]["created"], "2019-04-06T13:37:00Z");
+    }
+
+    #[test]
+    fn test_sarif_empty() {
+        let log = Log::new(&[]).unwrap();
+        let v = serde_json::to_value(&log).unwrap();
+        validate(&v);
+        assert_eq!(v["runs"][0]["results"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_sarif_invalid_severity() {
+        assert!(Log::new(&[(finding(1, "urgent", None, None), None)]).is_err());
+    }
+}
@@ -0,0 +1,180 @@
+use crate::errors::*;
+
+use std::str::FromStr;
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(char),
+    /// Matches any amount of whitespace, including none
+    Whitespace,
+    Decimal,
+    Hex,
+    Word,
+}
+
+/// A format like `SSH-%d.%d-%s`, similar to sscanf
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    tokens: Vec<Token>,
+}
+
+impl FromStr for Pattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Pattern> {
+        let mut tokens = Vec::new();
+        let mut chars = s.chars();
+
+        while let Some(c) = chars.next() {
+            let token = match c {
+                '%' => match chars.next() {
+                    Some('d') => Token::Decimal,
+                    Some('x') => Token::Hex,
+                    Some('s') => Token::Word,
+                    Some('%') => Token::Literal('%'),
+                    Some(c) => bail!("Unknown directive: %{}", c),
+                    None => bail!("Pattern ends with an incomplete directive"),
+                },
+                c if c.is_whitespace() => {
+                    if tokens.last() == Some(&Token::Whitespace) {
+                        continue;
+                    }
+                    Token::Whitespace
+                },
+                c => Token::Literal(c),
+            };
+            tokens.push(token);
+        }
+
+        Ok(Pattern {
+            tokens,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Num(i64),
+    Str(String),
+}
+
+/// Take the longest prefix that matches
+fn take_while<F: Fn(char) -> bool>(input: &str, f: F) -> (&str, &str) {
+    let idx = input.find(|c| !f(c))
+        .unwrap_or(input.len());
+    input.split_at(idx)
+}
+
+impl Pattern {
+    /// Match the input against the pattern and return the captured values,
+    /// None if it doesn't match. Numbers and words skip leading whitespace
+    /// and input after the end of the pattern is ignored.
+    pub fn scan(&self, mut input: &str) -> Option<Vec<Value>> {
+        let mut values = Vec::new();
+
+        for token in &self.tokens {
+            match token {
+                Token::Literal(c) => {
+                    if !input.starts_with(*c) {
+                        return None;
+                    }
+                    input = &input[c.len_utf8()..];
+                },
+                Token::Whitespace => input = input.trim_start(),
+                Token::Decimal => {
+                    input = input.trim_start();
+                    let sign = if input.starts_with('-') || input.starts_with('+') { 1 } else { 0 };
+                    let (digits, _) = take_while(&input[sign..], |c| c.is_ascii_digit());
+                    if digits.is_empty() {
+                        return None;
+                    }
+                    let (num, rest) = input.split_at(sign + digits.len());
+                    values.push(Value::Num(num.parse().ok()?));
+                    input = rest;
+                },
+                Token::Hex => {
+                    input = input.trim_start();
+                    if input.starts_with("0x") || input.starts_with("0X") {
+                        let (digits, _) = take_while(&input[2..], |c| c.is_ascii_hexdigit());
+                        if !digits.is_empty() {
+                            input = &input[2..];
+                        }
+                    }
+                    let (digits, rest) = take_while(input, |c| c.is_ascii_hexdigit());
+                    if digits.is_empty() {
+                        return None;
+                    }
+                    values.push(Value::Num(i64::from_str_radix(digits, 16).ok()?));
+                    input = rest;
+                },
+                Token::Word => {
+                    input = input.trim_start();
+                    let (word, rest) = take_while(input, |c| !c.is_whitespace());
+                    if word.is_empty() {
+                        return None;
+                    }
+                    values.push(Value::Str(word.to_string()));
+                    input = rest;
+                },
+            }
+        }
+
+        Some(values)
+    }
+}
+
+pub fn scan(input: &str, pattern: &str) -> Result<Option<Vec<Value>>> {
+    let pattern = pattern.parse::<Pattern>()?;
+    Ok(pattern.scan(input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s(x: &str) -> Value {
+        Value::Str(x.to_string())
+    }
+
+    #[test]
+    fn test_scan_mixed() {
+        let values = scan("SSH-2.0-OpenSSH_7.9p1 Debian-10", "SSH-%d.%d-%s %s").unwrap();
+        assert_eq!(values, Some(vec![Value::Num(2), Value::Num(0), s("OpenSSH_7.9p1"), s("Debian-10")]));
+
+        let values = scan("port=8080 flags=0x1F user=root", "port=%d flags=%x user=%s").unwrap();
+        assert_eq!(values, Some(vec![Value::Num(8080), Value::Num(31), s("root")]));
+    }
+
+    #[test]
+    fn test_scan_whitespace() {
+        let values = scan("220   mail.example.com\tESMTP Postfix", "%d %s ESMTP").unwrap();
+        assert_eq!(values, Some(vec![Value::Num(220), s("mail.example.com")]));
+
+        // whitespace in the pattern also matches no whitespace at all
+        assert_eq!(scan("a=1", "a = %d").unwrap(), Some(vec![Value::Num(1)]));
+        // numbers and words skip leading whitespace
+        assert_eq!(scan("x:   -42", "x:%d").unwrap(), Some(vec![Value::Num(-42)]));
+    }
+
+    #[test]
+    fn test_scan_no_match() {
+        assert_eq!(scan("HTTP/1.1 OK", "HTTP/%d.%d %d").unwrap(), None);
+        assert_eq!(scan("SMTP", "SSH-%d").unwrap(), None);
+        assert_eq!(scan("", "%s").unwrap(), None);
+        assert_eq!(scan("99999999999999999999", "%d").unwrap(), None);
+    }
+
+    #[test]
+    fn test_scan_literals() {
+        assert_eq!(scan("100% done", "%d%% %s").unwrap(), Some(vec![Value::Num(100), s("done")]));
+        assert_eq!(scan("ff trailing", "%x").unwrap(), Some(vec![Value::Num(255)]));
+        assert_eq!(scan("0xzz", "%x%s").unwrap(), Some(vec![Value::Num(0), s("xzz")]));
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        assert!(scan("foo", "%q").is_err());
+        assert!(scan("foo", "foo%").is_err());
+    }
+}
@@ -29,6 +29,25 @@ table! {
     }
 }
 
+table! {
+    confidences (id) {
+        id -> Integer,
+        family -> Text,
+        key -> Integer,
+        confidence -> Integer,
+    }
+}
+
+table! {
+    cryptoaddrs (id) {
+        id -> Integer,
+        value -> Text,
+        currency -> Text,
+        label -> Nullable<Text>,
+        unscoped -> Bool,
+    }
+}
+
 table! {
     devices (id) {
         id -> Integer,
@@ -58,6 +77,19 @@ table! {
     }
 }
 
+table! {
+    findings (id) {
+        id -> Integer,
+        severity -> Text,
+        title -> Text,
+        family -> Nullable<Text>,
+        key -> Nullable<Integer>,
+        details -> Nullable<Text>,
+        created -> Timestamp,
+        module -> Nullable<Text>,
+    }
+}
+
 table! {
     ipaddrs (id) {
         id -> Integer,
@@ -78,6 +110,16 @@ table! {
     }
 }
 
+table! {
+    module_state (id) {
+        id -> Integer,
+        module -> Text,
+        key -> Text,
+        value -> Text,
+        version -> Text,
+    }
+}
+
 table! {
     network_devices (id) {
         id -> Integer,
@@ -116,6 +158,22 @@ table! {
     }
 }
 
+table! {
+    ports (id) {
+        id -> Integer,
+        ip_addr_id -> Integer,
+        value -> Text,
+        ip_addr -> Text,
+        port -> Integer,
+        protocol -> Text,
+        status -> Nullable<Text>,
+        unscoped -> Bool,
+        banner -> Nullable<Text>,
+        service -> Nullable<Text>,
+        version -> Nullable<Text>,
+    }
+}
+
 table! {
     subdomain_ipaddrs (id) {
         id -> Integer,
@@ -162,6 +220,7 @@ joinable!(breach_emails -> breaches (breach_id));
 joinable!(breach_emails -> emails (email_id));
 joinable!(network_devices -> devices (device_id));
 joinable!(network_devices -> networks (network_id));
+joinable!(ports -> ipaddrs (ip_addr_id));
 joinable!(subdomain_ipaddrs -> ipaddrs (ip_addr_id));
 joinable!(subdomain_ipaddrs -> subdomains (subdomain_id));
 joinable!(subdomains -> domains (domain_id));
@@ -171,13 +230,18 @@ allow_tables_to_appear_in_same_query!(
     accounts,
     breach_emails,
     breaches,
+    confidences,
+    cryptoaddrs,
     devices,
     domains,
     emails,
+    findings,
     ipaddrs,
+    module_state,
     network_devices,
     networks,
     phonenumbers,
+    ports,
     subdomain_ipaddrs,
     subdomains,
     ttls,
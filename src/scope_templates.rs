@@ -0,0 +1,239 @@
+use crate::errors::*;
+
+use crate::paths;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+use std::path::{Path, PathBuf};
+
+
+pub const FAMILIES: &[&str] = &[
+    "domains",
+    "subdomains",
+    "ipaddrs",
+    "urls",
+    "emails",
+    "phonenumbers",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all="lowercase")]
+pub enum RuleAction {
+    Scope,
+    Noscope,
+}
+
+impl RuleAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RuleAction::Scope => "scope",
+            RuleAction::Noscope => "noscope",
+        }
+    }
+}
+
+impl FromStr for RuleAction {
+    type Err = Error;
+
+    fn from_str(x: &str) -> Result<RuleAction> {
+        match x {
+            "scope" => Ok(RuleAction::Scope),
+            "noscope" => Ok(RuleAction::Noscope),
+            _ => bail!("Unknown action: {:?}", x),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TemplateParam {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for TemplateParam {
+    type Err = Error;
+
+    fn from_str(x: &str) -> Result<TemplateParam> {
+        if let Some(idx) = x.find('=') {
+            let (key, value) = x.split_at(idx);
+
+            if key.is_empty() {
+                bail!("Parameter name can not be empty");
+            }
+
+            Ok(TemplateParam {
+                key: key.to_string(),
+                value: value[1..].to_string(),
+            })
+        } else {
+            bail!("Parameter is missing a value, expected key=value")
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScopeRule {
+    pub action: RuleAction,
+    pub family: String,
+    pub filter: Vec<String>,
+}
+
+impl ScopeRule {
+    pub fn new(action: RuleAction, family: String, filter: Vec<String>) -> Result<ScopeRule> {
+        if !FAMILIES.contains(&family.as_str()) {
+            bail!("Unknown entity family: {:?}", family);
+        }
+
+        Ok(ScopeRule {
+            action,
+            family,
+            filter,
+        })
+    }
+
+    /// Replace all `{{key}}` placeholders and return the arguments that
+    /// would be passed to the scope/noscope command.
+    pub fn instantiate(&self, params: &[TemplateParam]) -> Result<Vec<String>> {
+        let mut args = vec![
+            self.action.as_str().to_string(),
+            self.family.clone(),
+        ];
+
+        for arg in &self.filter {
+            let mut arg = arg.clone();
+            for param in params {
+                arg = arg.replace(&format!("{{{{{}}}}}", param.key), &param.value);
+            }
+
+            if let Some(idx) = arg.find("{{") {
+                let name = arg[idx+2..].split("}}").next().unwrap_or("");
+                bail!("Missing template parameter: {:?}", name);
+            }
+
+            args.push(arg);
+        }
+
+        Ok(args)
+    }
+}
+
+impl fmt::Display for ScopeRule {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        write!(w, "{} {}", self.action.as_str(), self.family)?;
+        for arg in &self.filter {
+            write!(w, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ScopeTemplates {
+    templates: HashMap<String, Vec<ScopeRule>>,
+}
+
+impl ScopeTemplates {
+    pub fn path() -> Result<PathBuf> {
+        let path = paths::data_dir()?;
+        let path = path.join("scope-templates.json");
+        Ok(path)
+    }
+
+    pub fn init() -> Result<ScopeTemplates> {
+        let path = Self::path()?;
+
+        if path.exists() {
+            Self::load(&path)
+        } else {
+            Ok(ScopeTemplates::default())
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<ScopeTemplates> {
+        let buf = fs::read(path)?;
+        serde_json::from_slice(&buf)
+            .map_err(Error::from)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        let buf = serde_json::to_string(&self)?;
+        fs::write(&path, buf)?;
+        Ok(())
+    }
+
+    pub fn insert(&mut self, name: String, rule: ScopeRule) -> Result<()> {
+        self.templates.entry(name)
+            .or_default()
+            .push(rule);
+        self.save()
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<()> {
+        if self.templates.remove(name).is_none() {
+            bail!("Template doesn't exist: {:?}", name);
+        }
+        self.save()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Vec<ScopeRule>> {
+        self.templates.get(name)
+    }
+
+    pub fn list(&self) -> Vec<(&String, &Vec<ScopeRule>)> {
+        let mut list = self.templates.iter()
+            .collect::<Vec<_>>();
+        list.sort_by(|a, b| a.0.cmp(b.0));
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(filter: &[&str]) -> ScopeRule {
+        let filter = filter.iter()
+            .map(|x| x.to_string())
+            .collect();
+        ScopeRule::new(RuleAction::Scope, "domains".into(), filter).unwrap()
+    }
+
+    #[test]
+    fn test_valid_param() {
+        let x = TemplateParam::from_str("domain=example.com").unwrap();
+        assert_eq!(x, TemplateParam {
+            key: "domain".into(),
+            value: "example.com".into(),
+        });
+    }
+
+    #[test]
+    fn test_invalid_param() {
+        assert!(TemplateParam::from_str("=a").is_err());
+        assert!(TemplateParam::from_str("a").is_err());
+        assert!(TemplateParam::from_str("").is_err());
+    }
+
+    #[test]
+    fn test_invalid_family() {
+        let x = ScopeRule::new(RuleAction::Scope, "foo".into(), vec![]);
+        assert!(x.is_err());
+    }
+
+    #[test]
+    fn test_instantiate() {
+        let params = vec![
+            TemplateParam::from_str("domain=example.com").unwrap(),
+        ];
+        let args = rule(&["where", "value={{domain}}"]).instantiate(&params).unwrap();
+        assert_eq!(args, vec!["scope", "domains", "where", "value=example.com"]);
+    }
+
+    #[test]
+    fn test_instantiate_missing_param() {
+        let args = rule(&["where", "value={{domain}}"]).instantiate(&[]);
+        assert!(args.is_err());
+    }
+}
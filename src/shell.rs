@@ -8,7 +8,7 @@ use crate::db::ttl;
 use crate::keyring::KeyRing;
 use colored::Colorize;
 use ctrlc;
-use crate::db::{self, Database};
+use crate::db::{self, encryption, Database};
 use crate::engine::{Engine, Module};
 use crate::geoip::{GeoIP, AsnDB, Maxmind};
 use crate::update::AutoUpdater;
@@ -30,7 +30,9 @@ pub enum Command {
     Add,
     Back,
     Delete,
+    Findings,
     Help,
+    Import,
     Keyring,
     Mod,
     Noscope,
@@ -52,7 +54,9 @@ impl Command {
             Command::Add => "add",
             Command::Back => "back",
             Command::Delete => "delete",
+            Command::Findings => "findings",
             Command::Help => "help",
+            Command::Import => "import",
             Command::Keyring => "keyring",
             Command::Mod => "mod",
             Command::Noscope => "noscope",
@@ -74,7 +78,9 @@ impl Command {
                 Command::Add.as_str(),
                 Command::Back.as_str(),
                 Command::Delete.as_str(),
+                Command::Findings.as_str(),
                 Command::Help.as_str(),
+                Command::Import.as_str(),
                 Command::Keyring.as_str(),
                 Command::Mod.as_str(),
                 Command::Noscope.as_str(),
@@ -101,7 +107,9 @@ impl FromStr for Command {
             "add" => Ok(Command::Add),
             "back" => Ok(Command::Back),
             "delete" => Ok(Command::Delete),
+            "findings" => Ok(Command::Findings),
             "help" => Ok(Command::Help),
+            "import" => Ok(Command::Import),
             "keyring" => Ok(Command::Keyring),
             "mod" => Ok(Command::Mod),
             "noscope" => Ok(Command::Noscope),
@@ -363,7 +371,9 @@ pub fn run_once(rl: &mut Readline) -> Result<bool> {
             return Ok(true);
         },
         Some((Command::Delete, args)) => delete_cmd::run(rl, &args)?,
+        Some((Command::Findings, args)) => findings_cmd::run(rl, &args)?,
         Some((Command::Help, args)) => help_cmd::run(rl, &args)?,
+        Some((Command::Import, args)) => import_cmd::run(rl, &args)?,
         Some((Command::Keyring, args)) => keyring_cmd::run(rl, &args)?,
         Some((Command::Mod, args)) => mod_cmd::run(rl, &args)?,
         Some((Command::Noscope, args)) => noscope_cmd::run(rl, &args)?,
@@ -388,10 +398,13 @@ pub fn init<'a>(args: &Args, config: &'a Config, verbose_init: bool) -> Result<R
         None => Workspace::from_str("default").unwrap(),
     };
 
+    let keyring = KeyRing::init()?;
+    let passphrase = encryption::passphrase(config, &keyring, &workspace)?;
+
     let db = if verbose_init {
-        Database::establish(workspace)?
+        Database::establish(workspace, passphrase)?
     } else {
-        Database::establish_quiet(workspace)?
+        Database::establish_quiet(workspace, passphrase)?
     };
     ttl::reap_expired(&db)?;
 
@@ -402,7 +415,6 @@ pub fn init<'a>(args: &Args, config: &'a Config, verbose_init: bool) -> Result<R
     let _asndb = AsnDB::open_or_download()
         .context("Failed to download ASN database")?;
     let engine = Engine::new(verbose_init, &config)?;
-    let keyring = KeyRing::init()?;
 
     if verbose_init && engine.list().is_empty() {
         term::success("No modules found, run quickstart to install default modules");
@@ -0,0 +1,252 @@
+use crate::errors::*;
+
+use chrono::{DateTime, Utc};
+use chrootable_https::{Body, Request, Uri};
+use chrootable_https::header::{HeaderName, HeaderValue};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use url::form_urlencoded;
+use url::percent_encoding::percent_decode;
+
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+
+/// Credentials and scope used to sign a request with AWS Signature Version 4
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AwsSigV4 {
+    pub access_key: String,
+    pub secret_key: String,
+    pub region: String,
+    pub service: String,
+    pub session_token: Option<String>,
+}
+
+// never log the secret key or the session token
+impl fmt::Debug for AwsSigV4 {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        w.debug_struct("AwsSigV4")
+            .field("access_key", &self.access_key)
+            .field("region", &self.region)
+            .field("service", &self.service)
+            .finish()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key)
+        .expect("hmac accepts keys of any size");
+    mac.input(data);
+    mac.result().code().to_vec()
+}
+
+fn uri_encode(x: &[u8], keep_slash: bool) -> String {
+    let mut out = String::new();
+    for &b in x {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(b as char),
+            b'/' if keep_slash => out.push('/'),
+            _ => out += &format!("%{:02X}", b),
+        }
+    }
+    out
+}
+
+/// The path of a uri is already percent encoded. For s3 every segment is
+/// decoded and encoded once, so `%2F` stays part of the key. Every other
+/// service expects the encoded path to be encoded a second time, eg.
+/// `%20` becomes `%2520`.
+fn canonical_path(path: &str, service: &str) -> String {
+    let path = match path {
+        "" => "/",
+        path => path,
+    };
+
+    if service == "s3" {
+        path.split('/')
+            .map(|segment| uri_encode(&percent_decode(segment.as_bytes()).collect::<Vec<_>>(), false))
+            .collect::<Vec<_>>()
+            .join("/")
+    } else {
+        uri_encode(path.as_bytes(), true)
+    }
+}
+
+fn canonical_query(query: Option<&str>) -> String {
+    let mut pairs = form_urlencoded::parse(query.unwrap_or("").as_bytes())
+        .map(|(k, v)| (uri_encode(k.as_bytes(), false), uri_encode(v.as_bytes(), false)))
+        .collect::<Vec<_>>();
+    pairs.sort();
+
+    pairs.into_iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+impl AwsSigV4 {
+    /// Compute the Authorization header value. `headers` are the headers
+    /// that should be signed, they need to include host and x-amz-date.
+    pub fn authorization(&self, method: &str, uri: &Uri, headers: &[(String, String)], body: &[u8], now: &DateTime<Utc>) -> String {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+
+        let mut headers = headers.iter()
+            .map(|(k, v)| (k.to_lowercase(), v.trim().to_string()))
+            .collect::<Vec<_>>();
+        headers.sort();
+
+        let canonical_headers = headers.iter()
+            .map(|(k, v)| format!("{}:{}\n", k, v))
+            .collect::<String>();
+        let signed_headers = headers.iter()
+            .map(|(k, _)| k.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+
+        let canonical_request = format!("{}\n{}\n{}\n{}\n{}\n{}",
+            method,
+            canonical_path(uri.path(), &self.service),
+            canonical_query(uri.query()),
+            canonical_headers,
+            signed_headers,
+            hex(&Sha256::digest(body)),
+        );
+        debug!("sigv4 canonical request: {:?}", canonical_request);
+
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, self.service);
+        let string_to_sign = format!("{}\n{}\n{}\n{}",
+            ALGORITHM,
+            amz_date,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes())),
+        );
+
+        let key = hmac(format!("AWS4{}", self.secret_key).as_bytes(), date.as_bytes());
+        let key = hmac(&key, self.region.as_bytes());
+        let key = hmac(&key, self.service.as_bytes());
+        let key = hmac(&key, b"aws4_request");
+        let signature = hex(&hmac(&key, string_to_sign.as_bytes()));
+
+        format!("{} Credential={}/{}, SignedHeaders={}, Signature={}",
+            ALGORITHM, self.access_key, scope, signed_headers, signature)
+    }
+
+    /// Add the x-amz-* and Authorization headers to the request
+    pub fn sign(&self, req: &mut Request<Body>, body: &[u8], now: &DateTime<Utc>) -> Result<()> {
+        let host = match (req.uri().host(), req.uri().port_part()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            _ => bail!("Url has no host"),
+        };
+
+        let mut sign = vec![
+            ("host".to_string(), host),
+            ("x-amz-date".to_string(), now.format("%Y%m%dT%H%M%SZ").to_string()),
+        ];
+
+        // s3 refuses requests without a payload hash
+        if self.service == "s3" {
+            sign.push(("x-amz-content-sha256".to_string(), hex(&Sha256::digest(body))));
+        }
+
+        if let Some(token) = &self.session_token {
+            sign.push(("x-amz-security-token".to_string(), token.clone()));
+        }
+
+        for (k, v) in req.headers() {
+            let k = k.as_str();
+            if k == "content-type" || (k.starts_with("x-amz-") && !sign.iter().any(|(x, _)| x == k)) {
+                sign.push((k.to_string(), v.to_str()?.to_string()));
+            }
+        }
+
+        let auth = self.authorization(req.method().as_str(), req.uri(), &sign, body, now);
+
+        let headers = req.headers_mut();
+        for (k, v) in sign {
+            headers.insert(HeaderName::from_bytes(k.as_bytes())?, HeaderValue::from_str(&v)?);
+        }
+        headers.insert("authorization", HeaderValue::from_str(&auth)?);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    // test vectors from the aws sigv4 test suite
+    fn example() -> AwsSigV4 {
+        AwsSigV4 {
+            access_key: "AKIDEXAMPLE".into(),
+            secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".into(),
+            region: "us-east-1".into(),
+            service: "service".into(),
+            session_token: None,
+        }
+    }
+
+    fn example_headers() -> Vec<(String, String)> {
+        vec![
+            ("Host".into(), "example.amazonaws.com".into()),
+            ("X-Amz-Date".into(), "20150830T123600Z".into()),
+        ]
+    }
+
+    #[test]
+    fn test_get_vanilla() {
+        let now = Utc.ymd(2015, 8, 30).and_hms(12, 36, 0);
+        let uri = "https://example.amazonaws.com/".parse::<Uri>().unwrap();
+        let auth = example().authorization("GET", &uri, &example_headers(), b"", &now);
+        assert_eq!(auth, "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31");
+    }
+
+    #[test]
+    fn test_get_vanilla_query_order() {
+        let now = Utc.ymd(2015, 8, 30).and_hms(12, 36, 0);
+        let uri = "https://example.amazonaws.com/?Param2=value2&Param1=value1".parse::<Uri>().unwrap();
+        let auth = example().authorization("GET", &uri, &example_headers(), b"", &now);
+        assert_eq!(auth, "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, Signature=b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500");
+    }
+
+    #[test]
+    fn test_post_vanilla() {
+        let now = Utc.ymd(2015, 8, 30).and_hms(12, 36, 0);
+        let uri = "https://example.amazonaws.com/".parse::<Uri>().unwrap();
+        let auth = example().authorization("POST", &uri, &example_headers(), b"", &now);
+        assert_eq!(auth, "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=host;x-amz-date, Signature=5da7c1a2acd57cee7505fc6676e4e544621c30862966e37dddb68e92efbe5d6b");
+    }
+
+    #[test]
+    fn test_post_x_www_form_urlencoded() {
+        let now = Utc.ymd(2015, 8, 30).and_hms(12, 36, 0);
+        let uri = "https://example.amazonaws.com/".parse::<Uri>().unwrap();
+        let mut headers = example_headers();
+        headers.push(("Content-Type".into(), "application/x-www-form-urlencoded".into()));
+        let auth = example().authorization("POST", &uri, &headers, b"Param1=value1", &now);
+        assert_eq!(auth, "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, SignedHeaders=content-type;host;x-amz-date, Signature=ff11897932ad3f4e8b18135d722051e5ac45fc38421b1da7b9d196a0fe09473a");
+    }
+
+    #[test]
+    fn test_canonical_path() {
+        assert_eq!(canonical_path("", "service"), "/");
+        assert_eq!(canonical_path("/", "s3"), "/");
+        assert_eq!(canonical_path("/my%20docs/a%2Fb/%7Ex", "s3"), "/my%20docs/a%2Fb/~x");
+        assert_eq!(canonical_path("/my%20docs/a%2Fb/%7Ex", "service"), "/my%2520docs/a%252Fb/%257Ex");
+    }
+
+    #[test]
+    fn test_debug_hides_secret() {
+        let x = format!("{:?}", example());
+        assert!(!x.contains("EXAMPLEKEY"));
+    }
+}
@@ -0,0 +1,176 @@
+use crate::errors::*;
+
+use crate::archive;
+use crate::xml::{self, next_tag};
+
+
+/// Sitemaps are limited to 50MiB uncompressed
+pub const MAX_SITEMAP_SIZE: usize = 50 * 1024 * 1024;
+
+/// A `<url>` of a sitemap or a `<sitemap>` of a sitemap index
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Entry {
+    pub loc: String,
+    pub lastmod: Option<String>,
+    pub changefreq: Option<String>,
+    pub priority: Option<f64>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Sitemap {
+    pub urls: Vec<Entry>,
+    /// Child sitemaps if this is a sitemap index
+    pub sitemaps: Vec<Entry>,
+}
+
+/// Ignore namespace prefixes like `<sm:url>`
+fn local_name(name: &str) -> &str {
+    match name.rfind(':') {
+        Some(idx) => &name[idx+1..],
+        None => name,
+    }
+}
+
+/// Parse a sitemap or a sitemap index, gzip compressed sitemaps are
+/// decompressed first. Entries without a `<loc>` are skipped.
+pub fn parse(bytes: &[u8]) -> Result<Sitemap> {
+    let bytes = if bytes.starts_with(&[0x1f, 0x8b]) {
+        archive::gunzip(bytes, MAX_SITEMAP_SIZE)?
+    } else {
+        bytes.to_vec()
+    };
+    let input = String::from_utf8(bytes)
+        .context("Sitemap is invalid utf8")?;
+
+    let mut sitemap = Sitemap::default();
+    let mut seen_root = false;
+    let mut entry: Option<Entry> = None;
+
+    let mut input = input.as_str();
+    while let Some(idx) = input.find('<') {
+        let text = &input[..idx];
+        let (tag, rest) = match next_tag(input) {
+            Some(x) => x,
+            None => break,
+        };
+        input = rest;
+
+        let tag = match tag {
+            Some(tag) => tag,
+            None => continue,
+        };
+
+        match (local_name(&tag.name), tag.closing) {
+            ("urlset", false) | ("sitemapindex", false) => seen_root = true,
+            ("url", false) | ("sitemap", false) => entry = Some(Entry::default()),
+            (name @ "url", true) | (name @ "sitemap", true) => {
+                if let Some(entry) = entry.take() {
+                    if entry.loc.is_empty() {
+                        continue;
+                    }
+                    match name {
+                        "url" => sitemap.urls.push(entry),
+                        _ => sitemap.sitemaps.push(entry),
+                    }
+                }
+            },
+            (name, true) => if let Some(entry) = &mut entry {
+                let value = xml::unescape(text.trim());
+                match name {
+                    "loc" => entry.loc = value,
+                    "lastmod" => entry.lastmod = Some(value),
+                    "changefreq" => entry.changefreq = Some(value),
+                    "priority" => entry.priority = value.parse().ok(),
+                    _ => (),
+                }
+            },
+            _ => (),
+        }
+    }
+
+    if !seen_root {
+        bail!("Document is not a sitemap");
+    }
+
+    Ok(sitemap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libflate::gzip;
+    use std::io::Write;
+
+    #[test]
+    fn test_parse() {
+        let sitemap = parse(br#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url>
+    <loc>https://example.com/</loc>
+    <lastmod>2019-04-01</lastmod>
+    <changefreq>daily</changefreq>
+    <priority>0.8</priority>
+  </url>
+  <url>
+    <loc>https://example.com/?a=1&amp;b=2</loc>
+  </url>
+  <url>
+    <lastmod>2019-04-01</lastmod>
+  </url>
+</urlset>
+"#).unwrap();
+        assert_eq!(sitemap, Sitemap {
+            urls: vec![
+                Entry {
+                    loc: "https://example.com/".to_string(),
+                    lastmod: Some("2019-04-01".to_string()),
+                    changefreq: Some("daily".to_string()),
+                    priority: Some(0.8),
+                },
+                Entry {
+                    loc: "https://example.com/?a=1&b=2".to_string(),
+                    ..Default::default()
+                },
+            ],
+            sitemaps: vec![],
+        });
+    }
+
+    #[test]
+    fn test_parse_index() {
+        let sitemap = parse(br#"<?xml version="1.0" encoding="UTF-8"?>
+<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <sitemap>
+    <loc>https://example.com/sitemap1.xml.gz</loc>
+    <lastmod>2019-04-01T18:23:17+00:00</lastmod>
+  </sitemap>
+</sitemapindex>
+"#).unwrap();
+        assert_eq!(sitemap, Sitemap {
+            urls: vec![],
+            sitemaps: vec![Entry {
+                loc: "https://example.com/sitemap1.xml.gz".to_string(),
+                lastmod: Some("2019-04-01T18:23:17+00:00".to_string()),
+                ..Default::default()
+            }],
+        });
+    }
+
+    #[test]
+    fn test_parse_gzip() {
+        let mut encoder = gzip::Encoder::new(Vec::new()).unwrap();
+        encoder.write_all(b"<urlset><url><loc>https://example.com/</loc></url></urlset>").unwrap();
+        let bytes = encoder.finish().into_result().unwrap();
+
+        let sitemap = parse(&bytes).unwrap();
+        assert_eq!(sitemap.urls.len(), 1);
+        assert_eq!(sitemap.urls[0].loc, "https://example.com/");
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(parse(b"").is_err());
+        assert!(parse(b"<html><body>not found</body></html>").is_err());
+        assert!(parse(&[0x1f, 0x8b, 0, 0]).is_err());
+    }
+}
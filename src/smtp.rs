@@ -0,0 +1,440 @@
+use crate::errors::*;
+
+use crate::connector::TlsConfig;
+use crate::engine::structs::{LuaList, LuaMap};
+use crate::hlua::AnyLuaValue;
+use crate::sockets::{self, Socket, SocketOptions};
+use rustls::{ClientSession, StreamOwned};
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::TcpStream;
+use std::time::Duration;
+
+
+/// Replies with more lines than this are rejected
+const MAX_REPLY_LINES: usize = 256;
+const MAX_LINE_LEN: usize = 4096;
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SmtpOptions {
+    timeout: Option<u64>,
+    connect_timeout: Option<u64>,
+    read_timeout: Option<u64>,
+    /// The name we introduce ourselves with, defaults to `sn0int`
+    helo: Option<String>,
+    #[serde(default)]
+    starttls: bool,
+    /// pem encoded certificates, overrides the global config for starttls
+    ca_bundle: Option<String>,
+    ca_bundle_only: Option<bool>,
+}
+
+impl SmtpOptions {
+    pub fn socket_options(&self) -> SocketOptions {
+        SocketOptions {
+            timeout: self.timeout,
+            connect_timeout: self.connect_timeout,
+            read_timeout: self.read_timeout,
+        }
+    }
+
+    pub fn tls(&self, default: &TlsConfig) -> TlsConfig {
+        default.with_overrides(&self.ca_bundle, self.ca_bundle_only)
+    }
+
+    fn helo(&self) -> &str {
+        match &self.helo {
+            Some(helo) => helo.as_str(),
+            None => "sn0int",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SmtpReply {
+    pub code: u16,
+    pub lines: Vec<String>,
+}
+
+impl SmtpReply {
+    /// The text of all lines, without the reply codes
+    pub fn message(&self) -> String {
+        self.lines.join("\n")
+    }
+}
+
+impl From<SmtpReply> for AnyLuaValue {
+    fn from(reply: SmtpReply) -> AnyLuaValue {
+        let mut map = LuaMap::new();
+
+        map.insert_num("code", f64::from(reply.code));
+        map.insert_str("message", reply.message());
+
+        let mut lines = LuaList::new();
+        for line in reply.lines {
+            lines.push_str(line);
+        }
+        map.insert("lines", lines);
+
+        map.into()
+    }
+}
+
+/// Parse a line like `250-foo` or `250 bar`. Returns the reply code, if
+/// more lines follow and the text.
+pub fn parse_reply_line(line: &str) -> Result<(u16, bool, &str)> {
+    let valid = line.len() >= 3
+        && line.is_char_boundary(3)
+        && line[..3].chars().all(|c| c.is_ascii_digit())
+        && b"2345".contains(&line.as_bytes()[0]);
+    if !valid {
+        bail!("Invalid smtp reply: {:?}", line);
+    }
+
+    let code = line[..3].parse::<u16>()?;
+    match line.as_bytes().get(3) {
+        None => Ok((code, false, "")),
+        Some(b' ') => Ok((code, false, &line[4..])),
+        Some(b'-') => Ok((code, true, &line[4..])),
+        Some(_) => bail!("Invalid smtp reply: {:?}", line),
+    }
+}
+
+enum SmtpStream {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ClientSession, TcpStream>>),
+}
+
+impl fmt::Debug for SmtpStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SmtpStream::Plain(stream) => write!(f, "Plain({:?})", stream),
+            SmtpStream::Tls(stream) => write!(f, "Tls({:?})", stream.sock),
+        }
+    }
+}
+
+impl Read for SmtpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            SmtpStream::Plain(stream) => stream.read(buf),
+            SmtpStream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for SmtpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            SmtpStream::Plain(stream) => stream.write(buf),
+            SmtpStream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            SmtpStream::Plain(stream) => stream.flush(),
+            SmtpStream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SmtpClient {
+    stream: BufReader<SmtpStream>,
+    /// Extensions announced in the EHLO reply, uppercase
+    extensions: Vec<String>,
+}
+
+impl SmtpClient {
+    /// Read the greeting and introduce ourselves, optionally upgrade the
+    /// connection with STARTTLS
+    pub fn connect(sock: Socket, host: &str, options: &SmtpOptions, tls: &TlsConfig) -> Result<SmtpClient> {
+        let stream = sock.into_inner()?;
+        // an explicit 0 disables the timeout
+        if options.read_timeout.or(options.timeout).is_none() {
+            stream.set_read_timeout(Some(DEFAULT_READ_TIMEOUT))?;
+        }
+
+        let mut client = SmtpClient {
+            stream: BufReader::new(SmtpStream::Plain(stream)),
+            extensions: Vec::new(),
+        };
+
+        let greeting = client.read_reply()?;
+        if greeting.code != 220 {
+            bail!("Server rejected the connection: {} {}", greeting.code, greeting.message());
+        }
+        client.hello(options.helo())?;
+
+        if options.starttls {
+            if !client.supports("STARTTLS") {
+                bail!("Server doesn't support STARTTLS");
+            }
+            let reply = client.cmd("STARTTLS")?;
+            if reply.code != 220 {
+                bail!("STARTTLS was rejected: {} {}", reply.code, reply.message());
+            }
+            client = client.upgrade(host, tls)?;
+            client.hello(options.helo())?;
+        }
+
+        Ok(client)
+    }
+
+    /// Try EHLO and fall back to HELO for servers without extensions
+    fn hello(&mut self, helo: &str) -> Result<()> {
+        let reply = self.cmd(&format!("EHLO {}", helo))?;
+        if reply.code == 250 {
+            self.extensions = reply.lines.iter()
+                .skip(1)
+                .filter_map(|x| x.split_whitespace().next())
+                .map(|x| x.to_uppercase())
+                .collect();
+            return Ok(());
+        }
+
+        let reply = self.cmd(&format!("HELO {}", helo))?;
+        if reply.code != 250 {
+            bail!("Server rejected HELO: {} {}", reply.code, reply.message());
+        }
+        self.extensions = Vec::new();
+        Ok(())
+    }
+
+    fn upgrade(self, host: &str, tls: &TlsConfig) -> Result<SmtpClient> {
+        if !self.stream.buffer().is_empty() {
+            bail!("Server sent data before the tls handshake");
+        }
+
+        let stream = match self.stream.into_inner() {
+            SmtpStream::Plain(stream) => stream,
+            SmtpStream::Tls(_) => bail!("Connection is already encrypted"),
+        };
+        let stream = sockets::tls_handshake(stream, host, tls)?;
+
+        Ok(SmtpClient {
+            stream: BufReader::new(SmtpStream::Tls(Box::new(stream))),
+            extensions: Vec::new(),
+        })
+    }
+
+    pub fn supports(&self, extension: &str) -> bool {
+        let extension = extension.to_uppercase();
+        self.extensions.contains(&extension)
+    }
+
+    pub fn is_tls(&self) -> bool {
+        match self.stream.get_ref() {
+            SmtpStream::Tls(_) => true,
+            SmtpStream::Plain(_) => false,
+        }
+    }
+
+    fn read_line(&mut self) -> Result<String> {
+        let mut line = Vec::new();
+        (&mut self.stream).take(MAX_LINE_LEN as u64)
+            .read_until(b'\n', &mut line)?;
+
+        if line.is_empty() {
+            bail!("Connection closed");
+        }
+        if !line.ends_with(b"\n") {
+            bail!("Line is too long or incomplete");
+        }
+
+        let line = String::from_utf8_lossy(&line).trim_end().to_string();
+        debug!("smtp recv: {:?}", line);
+        Ok(line)
+    }
+
+    /// Read a reply that may span multiple lines
+    pub fn read_reply(&mut self) -> Result<SmtpReply> {
+        let mut reply: Option<SmtpReply> = None;
+
+        loop {
+            let line = self.read_line()?;
+            let (code, more, text) = parse_reply_line(&line)?;
+
+            let reply = reply.get_or_insert_with(|| SmtpReply {
+                code,
+                lines: Vec::new(),
+            });
+            if reply.code != code {
+                bail!("Reply code changed in multiline reply: {:?}", line);
+            }
+            if reply.lines.len() >= MAX_REPLY_LINES {
+                bail!("Reply has too many lines");
+            }
+            reply.lines.push(text.to_string());
+
+            if !more {
+                break;
+            }
+        }
+
+        Ok(reply.expect("reply has at least one line"))
+    }
+
+    /// Send a command and read the reply
+    pub fn cmd(&mut self, cmd: &str) -> Result<SmtpReply> {
+        if cmd.contains('\r') || cmd.contains('\n') {
+            bail!("Command can't contain newlines");
+        }
+
+        debug!("smtp send: {:?}", cmd);
+        let stream = self.stream.get_mut();
+        stream.write_all(format!("{}\r\n", cmd).as_bytes())?;
+        stream.flush()?;
+
+        self.read_reply()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrootable_https::Resolver;
+    use crate::sockets::tests::{TEST_CA, TEST_CERT, TEST_KEY};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    #[test]
+    fn verify_parse_reply_line() {
+        assert_eq!(parse_reply_line("250-mx.example.com").unwrap(), (250, true, "mx.example.com"));
+        assert_eq!(parse_reply_line("550 5.1.1 unknown user").unwrap(), (550, false, "5.1.1 unknown user"));
+        assert_eq!(parse_reply_line("354").unwrap(), (354, false, ""));
+        assert!(parse_reply_line("").is_err());
+        assert!(parse_reply_line("OK").is_err());
+        assert!(parse_reply_line("650 nope").is_err());
+        assert!(parse_reply_line("250_foo").is_err());
+        assert!(parse_reply_line("2ö0 foo").is_err());
+    }
+
+    /// Answers VRFY and RCPT like a server that only knows root@example.com,
+    /// returns after STARTTLS was accepted
+    fn handle_commands<S: Read + Write>(stream: &mut BufReader<S>) {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if stream.read_line(&mut line).unwrap() == 0 {
+                return;
+            }
+
+            let reply: &[u8] = match line.trim_end() {
+                "EHLO sn0int" => b"250-mx.example.com\r\n250-VRFY\r\n250 STARTTLS\r\n",
+                "VRFY root" => b"250 2.1.5 root <root@example.com>\r\n",
+                "VRFY nobody" => b"550 5.1.1 nobody... User unknown\r\n",
+                "MAIL FROM:<>" => b"250 2.1.0 Ok\r\n",
+                "RCPT TO:<root@example.com>" => b"250 2.1.5 Ok\r\n",
+                "RCPT TO:<nobody@example.com>" => b"550-5.1.1 The email account that you tried to reach does\r\n550 5.1.1 not exist\r\n",
+                "STARTTLS" => b"220 2.0.0 Ready to start TLS\r\n",
+                "QUIT" => b"221 2.0.0 Bye\r\n",
+                _ => b"502 5.5.2 Error: command not recognized\r\n",
+            };
+            let stream = stream.get_mut();
+            stream.write_all(reply).unwrap();
+            stream.flush().unwrap();
+
+            if reply.starts_with(b"220") || reply.starts_with(b"221") {
+                return;
+            }
+        }
+    }
+
+    fn smtp_server(greeting: &'static [u8], tls: bool) -> u16 {
+        use rustls::internal::pemfile;
+        use rustls::{NoClientAuth, ServerConfig, ServerSession};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(greeting).unwrap();
+
+            let mut stream = BufReader::new(stream);
+            handle_commands(&mut stream);
+
+            if tls {
+                let certs = pemfile::certs(&mut TEST_CERT.as_bytes()).unwrap();
+                let mut keys = pemfile::pkcs8_private_keys(&mut TEST_KEY.as_bytes()).unwrap();
+                let mut config = ServerConfig::new(NoClientAuth::new());
+                config.set_single_cert(certs, keys.remove(0)).unwrap();
+
+                let session = ServerSession::new(&Arc::new(config));
+                let stream = StreamOwned::new(session, stream.into_inner());
+                handle_commands(&mut BufReader::new(stream));
+            }
+        });
+
+        port
+    }
+
+    fn connect(port: u16, options: &SmtpOptions) -> Result<SmtpClient> {
+        let resolver = Resolver::empty();
+        let sock = Socket::connect(&resolver, "127.0.0.1", port, &options.socket_options())?;
+        let tls = options.tls(&TlsConfig::default());
+        SmtpClient::connect(sock, "localhost", options, &tls)
+    }
+
+    fn options() -> SmtpOptions {
+        SmtpOptions {
+            read_timeout: Some(2000),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn verify_vrfy_rcpt() {
+        let port = smtp_server(b"220-mx.example.com ESMTP\r\n220 no spam\r\n", false);
+        let mut client = connect(port, &options()).unwrap();
+        assert!(client.supports("vrfy"));
+        assert!(!client.is_tls());
+
+        assert_eq!(client.cmd("VRFY root").unwrap(), SmtpReply {
+            code: 250,
+            lines: vec!["2.1.5 root <root@example.com>".to_string()],
+        });
+        assert_eq!(client.cmd("VRFY nobody").unwrap().code, 550);
+
+        assert_eq!(client.cmd("MAIL FROM:<>").unwrap().code, 250);
+        assert_eq!(client.cmd("RCPT TO:<root@example.com>").unwrap().code, 250);
+        let reply = client.cmd("RCPT TO:<nobody@example.com>").unwrap();
+        assert_eq!(reply.code, 550);
+        assert_eq!(reply.message(), "5.1.1 The email account that you tried to reach does\n5.1.1 not exist");
+
+        assert_eq!(client.cmd("QUIT").unwrap().code, 221);
+        assert!(client.cmd("NOOP").is_err());
+    }
+
+    #[test]
+    fn verify_rejected_greeting() {
+        let port = smtp_server(b"554 go away\r\n", false);
+        let err = connect(port, &options()).unwrap_err();
+        assert_eq!(err.to_string(), "Server rejected the connection: 554 go away");
+    }
+
+    #[test]
+    fn verify_no_newlines() {
+        let port = smtp_server(b"220 mx.example.com ESMTP\r\n", false);
+        let mut client = connect(port, &options()).unwrap();
+        assert!(client.cmd("VRFY root\r\nQUIT").is_err());
+    }
+
+    #[test]
+    fn verify_starttls() {
+        let port = smtp_server(b"220 mx.example.com ESMTP\r\n", true);
+        let mut client = connect(port, &SmtpOptions {
+            read_timeout: Some(2000),
+            starttls: true,
+            ca_bundle: Some(TEST_CA.to_string()),
+            ..Default::default()
+        }).unwrap();
+        assert!(client.is_tls());
+        assert_eq!(client.cmd("VRFY root").unwrap().code, 250);
+    }
+}
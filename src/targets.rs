@@ -0,0 +1,334 @@
+use crate::errors::*;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+
+/// Ask before running a module on more targets than this
+pub const CONFIRM_THRESHOLD: u64 = 10_000;
+
+/// A target expression passed to `run`. Generators are expanded lazily, so
+/// large ranges don't need to fit into memory.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Generator {
+    /// A single target
+    Literal(String),
+    /// Every address in a network, `192.168.1.0/24`
+    Cidr(IpAddr, u8),
+    /// `host{1..100}.example.com`, zero padded if start has a leading zero
+    Range {
+        prefix: String,
+        start: u64,
+        end: u64,
+        width: usize,
+        rest: Box<Generator>,
+    },
+    /// One expression per line, `@targets.txt`
+    File(PathBuf),
+}
+
+fn parse_cidr(s: &str) -> Option<(IpAddr, u8)> {
+    let idx = s.find('/')?;
+    let (addr, prefix) = (&s[..idx], &s[idx+1..]);
+
+    let addr = addr.parse::<IpAddr>().ok()?;
+    let prefix = prefix.parse::<u8>().ok()?;
+
+    let max = match addr {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    };
+    if prefix > max {
+        return None;
+    }
+
+    Some((addr, prefix))
+}
+
+fn parse_range(s: &str, cidr: bool) -> Result<Option<Generator>> {
+    let open = match s.find('{') {
+        Some(idx) => idx,
+        None => return Ok(None),
+    };
+    let close = match s[open..].find('}') {
+        Some(idx) => open + idx,
+        None => return Ok(None),
+    };
+
+    let inner = &s[open+1..close];
+    let sep = match inner.find("..") {
+        Some(idx) => idx,
+        None => return Ok(None),
+    };
+    let (start, end) = (&inner[..sep], &inner[sep+2..]);
+
+    let (start_n, end_n) = match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Ok(start), Ok(end)) => (start, end),
+        _ => return Ok(None),
+    };
+
+    if start_n > end_n {
+        bail!("Range start is greater than end: {:?}", inner);
+    }
+
+    let width = if start.len() > 1 && start.starts_with('0') {
+        start.len()
+    } else {
+        0
+    };
+
+    Ok(Some(Generator::Range {
+        prefix: s[..open].to_string(),
+        start: start_n,
+        end: end_n,
+        width,
+        rest: Box::new(Generator::parse_expr(&s[close+1..], cidr)?),
+    }))
+}
+
+impl FromStr for Generator {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Generator> {
+        Generator::parse_expr(s, true)
+    }
+}
+
+/// Parse a line of a target list, None for comments and empty lines
+fn parse_line(line: &str, cidr: bool) -> Option<Result<Generator>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    match Generator::parse_expr(line, cidr) {
+        Ok(Generator::File(_)) => Some(Err(format_err!("Nested target lists are not supported: {:?}", line))),
+        gen => Some(gen),
+    }
+}
+
+fn open_list(path: &PathBuf) -> Result<BufReader<File>> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|err| format_err!("Failed to open {:?}: {}", path, err))
+}
+
+impl Generator {
+    /// Parse a target expression, networks are kept as literals if `cidr` is false
+    pub fn parse_expr(s: &str, cidr: bool) -> Result<Generator> {
+        if s.starts_with('@') {
+            let path = s.trim_start_matches('@');
+            return Ok(Generator::File(PathBuf::from(path)));
+        }
+
+        if cidr {
+            if let Some((addr, prefix)) = parse_cidr(s) {
+                return Ok(Generator::Cidr(addr, prefix));
+            }
+        }
+
+        if let Some(range) = parse_range(s, cidr)? {
+            return Ok(range);
+        }
+
+        Ok(Generator::Literal(s.to_string()))
+    }
+
+    /// Number of targets this expands to, None if it depends on a file
+    pub fn size(&self) -> Option<u64> {
+        match self {
+            Generator::Literal(_) => Some(1),
+            Generator::Cidr(IpAddr::V4(_), prefix) => Some(1 << (32 - u32::from(*prefix))),
+            Generator::Cidr(IpAddr::V6(_), prefix) => {
+                let bits = 128 - u32::from(*prefix);
+                // saturate, nobody is going to scan this anyway
+                if bits >= 64 {
+                    Some(!0)
+                } else {
+                    Some(1 << bits)
+                }
+            },
+            Generator::Range { start, end, rest, .. } => {
+                let n = (end - start).saturating_add(1);
+                rest.size().map(|x| x.saturating_mul(n))
+            },
+            Generator::File(_) => None,
+        }
+    }
+
+    fn expand(self) -> Box<dyn Iterator<Item=String>> {
+        match self {
+            Generator::Literal(x) => Box::new(Some(x).into_iter()),
+            Generator::Cidr(IpAddr::V4(addr), prefix) => {
+                let size = 1u64 << (32 - u32::from(prefix));
+                let start = u64::from(u32::from(addr)) & !(size - 1);
+                Box::new((start..start + size)
+                    .map(|x| Ipv4Addr::from(x as u32).to_string()))
+            },
+            Generator::Cidr(IpAddr::V6(addr), prefix) => {
+                let bits = 128 - u32::from(prefix);
+                let mask = if bits == 128 { 0 } else { !0u128 << bits };
+                let start = u128::from(addr) & mask;
+                let end = start | !mask;
+                Box::new((start..=end)
+                    .map(|x| Ipv6Addr::from(x).to_string()))
+            },
+            Generator::Range { prefix, start, end, width, rest } => {
+                Box::new((start..=end)
+                    .flat_map(move |i| {
+                        let head = format!("{}{:0width$}", prefix, i, width=width);
+                        rest.clone().expand()
+                            .map(move |tail| format!("{}{}", head, tail))
+                    }))
+            },
+            // files are read by iter
+            Generator::File(_) => Box::new(None.into_iter()),
+        }
+    }
+
+    /// Number of targets this expands to, file lists are read to count their lines
+    pub fn count(&self, cidr: bool) -> Result<u64> {
+        let path = match self {
+            Generator::File(path) => path,
+            gen => return Ok(gen.size().unwrap_or(0)),
+        };
+
+        let mut sum = 0u64;
+        for line in open_list(path)?.lines() {
+            if let Some(gen) = parse_line(&line?, cidr) {
+                sum = sum.saturating_add(gen?.count(cidr)?);
+            }
+        }
+        Ok(sum)
+    }
+
+    /// Expand into targets, lines of a file list are expressions themselves
+    pub fn iter(self, cidr: bool) -> Box<dyn Iterator<Item=Result<String>>> {
+        let path = match self {
+            Generator::File(path) => path,
+            gen => return Box::new(gen.expand().map(Ok)),
+        };
+
+        let file = match open_list(&path) {
+            Ok(file) => file,
+            Err(err) => return Box::new(Some(Err(err)).into_iter()),
+        };
+
+        let lines = file
+            .lines()
+            .flat_map(move |line| -> Box<dyn Iterator<Item=Result<String>>> {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(err) => return Box::new(Some(Err(err.into())).into_iter()),
+                };
+
+                match parse_line(&line, cidr) {
+                    Some(Ok(gen)) => Box::new(gen.expand().map(Ok)),
+                    Some(Err(err)) => Box::new(Some(Err(err)).into_iter()),
+                    None => Box::new(None.into_iter()),
+                }
+            });
+
+        Box::new(lines)
+    }
+}
+
+/// Parse all expressions upfront so syntax errors are reported before anything runs.
+/// If `cidr` is false, networks are passed through instead of being expanded
+pub fn parse(exprs: &[String], cidr: bool) -> Result<Vec<Generator>> {
+    exprs.iter()
+        .map(|x| Generator::parse_expr(x, cidr))
+        .collect()
+}
+
+/// Number of targets the generators expand to, including the lines of file lists
+pub fn count(generators: &[Generator], cidr: bool) -> Result<u64> {
+    generators.iter()
+        .map(|x| x.count(cidr))
+        .try_fold(0u64, |a, b| b.map(|b| a.saturating_add(b)))
+}
+
+pub fn chain(generators: Vec<Generator>, cidr: bool) -> impl Iterator<Item=Result<String>> {
+    generators.into_iter()
+        .flat_map(move |x| x.iter(cidr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn expand(s: &str) -> Vec<String> {
+        Generator::from_str(s).unwrap()
+            .iter(true)
+            .collect::<Result<Vec<_>>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_cidr() {
+        let gen = Generator::from_str("192.168.1.7/30").unwrap();
+        assert_eq!(gen.size(), Some(4));
+        assert_eq!(expand("192.168.1.7/30"), vec!["192.168.1.4", "192.168.1.5", "192.168.1.6", "192.168.1.7"]);
+        assert_eq!(expand("10.0.0.1/32"), vec!["10.0.0.1"]);
+        assert_eq!(expand("2001:db8::/127"), vec!["2001:db8::", "2001:db8::1"]);
+        assert_eq!(Generator::from_str("::/0").unwrap().size(), Some(!0));
+    }
+
+    #[test]
+    fn test_range() {
+        assert_eq!(expand("host{1..3}.example.com"), vec!["host1.example.com", "host2.example.com", "host3.example.com"]);
+        assert_eq!(expand("{08..10}"), vec!["08", "09", "10"]);
+        assert_eq!(expand("a{1..2}b{1..2}"), vec!["a1b1", "a1b2", "a2b1", "a2b2"]);
+        assert_eq!(Generator::from_str("a{1..10}b{1..10}").unwrap().size(), Some(100));
+        assert!(Generator::from_str("{10..1}").is_err());
+    }
+
+    #[test]
+    fn test_literal() {
+        assert_eq!(expand("example.com"), vec!["example.com"]);
+        assert_eq!(expand("https://example.com/{x}"), vec!["https://example.com/{x}"]);
+        assert_eq!(expand("192.168.1.1/33"), vec!["192.168.1.1/33"]);
+    }
+
+    #[test]
+    fn test_file() {
+        let path = std::env::temp_dir().join("sn0int-test-targets.txt");
+        fs::write(&path, "# comment\nexample.com\n\n10.0.0.0/31\n  www{1..2}.example.com  \n").unwrap();
+
+        let gens = parse(&[format!("@{}", path.display()), "example.org".into()], true).unwrap();
+        assert_eq!(count(&gens, true).unwrap(), 6);
+        assert_eq!(count(&gens, false).unwrap(), 5);
+        let targets = chain(gens.clone(), true).collect::<Result<Vec<_>>>().unwrap();
+        let networks = chain(gens, false).collect::<Result<Vec<_>>>().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(targets, vec!["example.com", "10.0.0.0", "10.0.0.1",
+                                 "www1.example.com", "www2.example.com", "example.org"]);
+        assert_eq!(networks, vec!["example.com", "10.0.0.0/31",
+                                  "www1.example.com", "www2.example.com", "example.org"]);
+    }
+
+    #[test]
+    fn test_networks() {
+        let gens = parse(&["10.0.0.0/24".into(), "10.{1..2}.0.0/16".into()], false).unwrap();
+        assert_eq!(count(&gens, false).unwrap(), 3);
+        let targets = chain(gens, false).collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(targets, vec!["10.0.0.0/24", "10.1.0.0/16", "10.2.0.0/16"]);
+    }
+
+    #[test]
+    fn test_range_overflow() {
+        let gen = Generator::from_str("{0..18446744073709551615}").unwrap();
+        assert_eq!(gen.size(), Some(!0));
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let gens = parse(&["@/nonexistent/sn0int-targets.txt".into()], true).unwrap();
+        assert!(count(&gens, true).is_err());
+    }
+}
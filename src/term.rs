@@ -1,11 +1,16 @@
 use atty::{self, Stream};
+use colored;
+use crate::errors::*;
 use crate::db;
 use crate::engine::Module;
 use rand::prelude::*;
 use std::collections::HashMap;
+use std::env;
 use std::fmt;
 use std::io;
 use std::io::prelude::*;
+use std::str::FromStr;
+use crate::utils;
 
 // https://github.com/Gallopsled/pwntools/blob/dev/pwnlib/term/spinners.py
 // https://github.com/gernest/wow/blob/master/spin/spinners.go
@@ -50,21 +55,124 @@ pub static SPINNERS: &[&[&str]] = &[
     &[" ", "⠁", "⠉", "⠙", "⠚", "⠖", "⠦", "⠤", "⠠"],
 ];
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Theme {
+    Dark,
+    Light,
+    None,
+}
+
+impl FromStr for Theme {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Theme> {
+        match s {
+            "dark" => Ok(Theme::Dark),
+            "light" => Ok(Theme::Light),
+            "none" => Ok(Theme::None),
+            _ => bail!("Unknown theme: {:?}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Info,
+    Success,
+    Debug,
+    Warn,
+    Error,
+    Progress,
+    Prompt,
+}
+
+impl Theme {
+    fn color(self, level: Level) -> Option<&'static str> {
+        match (self, level) {
+            (Theme::None, _) => None,
+            (_, Level::Info) | (_, Level::Progress) => Some("32"),
+            (_, Level::Success) | (_, Level::Debug) | (_, Level::Prompt) => Some("34"),
+            // yellow is hard to read on a white background
+            (Theme::Dark, Level::Warn) => Some("33"),
+            (Theme::Light, Level::Warn) => Some("35"),
+            (_, Level::Error) => Some("31"),
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct TermSettings {
-    // colors: bool,
+    theme: Theme,
     indicate_progress: bool,
 }
 
 impl TermSettings {
     pub fn from_env() -> TermSettings {
-        if atty::is(Stream::Stdout) {
-            TermSettings {
-                indicate_progress: true,
-            }
+        let no_color = env::var_os("NO_COLOR")
+            .map(|x| !x.is_empty())
+            .unwrap_or(false);
+        let tty = atty::is(Stream::Stdout);
+
+        match env::var("SN0INT_THEME") {
+            Ok(theme) => TermSettings::resolve(Some(&theme), no_color, tty),
+            Err(_) => TermSettings::resolve(None, no_color, tty),
+        }
+    }
+
+    fn resolve(theme: Option<&str>, no_color: bool, tty: bool) -> TermSettings {
+        let theme = match theme.map(Theme::from_str) {
+            Some(Ok(theme)) => theme,
+            Some(Err(err)) => {
+                // can't use warn() here, it depends on the settings we're loading
+                eprintln!("[!] {}, falling back to dark", err);
+                Theme::Dark
+            },
+            None => Theme::Dark,
+        };
+
+        let theme = if no_color || !tty {
+            Theme::None
         } else {
-            TermSettings {
-                indicate_progress: false,
-            }
+            theme
+        };
+
+        TermSettings {
+            theme,
+            indicate_progress: tty,
+        }
+    }
+
+    #[inline]
+    pub fn theme(&self) -> Theme {
+        self.theme
+    }
+
+    #[inline]
+    pub fn colors(&self) -> bool {
+        self.theme != Theme::None
+    }
+
+    /// `[+]` style tag in front of a line
+    pub fn tag(&self, level: Level, symbol: &str) -> String {
+        match self.theme.color(level) {
+            Some(color) => format!("\x1b[1m[\x1b[{}m{}\x1b[0;1m]\x1b[0m", color, symbol),
+            None => format!("[{}]", symbol),
+        }
+    }
+
+    pub fn paint(&self, level: Level, text: &str) -> String {
+        match self.theme.color(level) {
+            Some(color) => format!("\x1b[{}m{}\x1b[0m", color, text),
+            None => text.to_string(),
+        }
+    }
+
+    /// Clear the current line, this is only needed if we draw progress indicators
+    fn clear_line(&self) -> &'static str {
+        if self.indicate_progress {
+            "\r\x1b[2K"
+        } else {
+            ""
         }
     }
 }
@@ -73,6 +181,14 @@ lazy_static! {
     pub static ref TERM_SETTINGS: TermSettings = TermSettings::from_env();
 }
 
+/// Load the terminal settings and make sure output of the colored crate
+/// follows the same theme.
+pub fn init() {
+    if !TERM_SETTINGS.colors() {
+        colored::control::set_override(false);
+    }
+}
+
 pub trait SpinLogger {
     fn log(&mut self, line: &str);
 
@@ -81,6 +197,9 @@ pub trait SpinLogger {
     fn error(&mut self, line: &str);
 
     fn status(&mut self, status: String);
+
+    /// Ask a yes/no question on the terminal, defaults to no
+    fn confirm(&mut self, question: &str) -> Result<bool>;
 }
 
 pub struct Spinner {
@@ -117,7 +236,8 @@ impl Spinner {
             self.i = 0;
         }
 
-        let s = format!("\r\x1b[2K\x1b[1m[\x1b[32m{}\x1b[0;1m]\x1b[0m {}...", self.indicator[self.i], self.status);
+        let tag = TERM_SETTINGS.tag(Level::Progress, self.indicator[self.i]);
+        let s = format!("\r\x1b[2K{} {}...", tag, self.status);
         self.i += 1;
 
         s
@@ -125,7 +245,7 @@ impl Spinner {
 
     pub fn done(&self) {
         if self.dummy { return; }
-        println!("\r\x1b[2K\x1b[1m[\x1b[32m{}\x1b[0;1m]\x1b[0m {}", '+', self.status);
+        println!("\r\x1b[2K{} {}", TERM_SETTINGS.tag(Level::Progress, "+"), self.status);
         io::stdout().flush().unwrap();
     }
 
@@ -150,43 +270,48 @@ impl Spinner {
 impl SpinLogger for Spinner {
     fn log(&mut self, line: &str) {
         if self.dummy { return; }
-        println!("\r\x1b[2K\x1b[1m[\x1b[34m{}\x1b[0;1m]\x1b[0m {}", '*', line);
+        println!("{}{} {}", TERM_SETTINGS.clear_line(), TERM_SETTINGS.tag(Level::Success, "*"), line);
     }
 
     fn debug(&mut self, line: &str) {
         if self.dummy { return; }
-        println!("\r\x1b[2K\x1b[1m[\x1b[34m{}\x1b[0;1m]\x1b[0m {}", '#', line);
+        println!("{}{} {}", TERM_SETTINGS.clear_line(), TERM_SETTINGS.tag(Level::Debug, "#"), line);
     }
 
     fn error(&mut self, line: &str) {
         if self.dummy { return; }
-        println!("\r\x1b[2K\x1b[1m[\x1b[31m{}\x1b[0;1m]\x1b[0m {}", '-', line);
+        println!("{}{} {}", TERM_SETTINGS.clear_line(), TERM_SETTINGS.tag(Level::Error, "-"), line);
     }
 
     #[inline]
     fn status(&mut self, status: String) {
         self.status = status;
     }
+
+    fn confirm(&mut self, question: &str) -> Result<bool> {
+        self.clear();
+        utils::no_else_yes(question)
+    }
 }
 
 pub fn success(line: &str) {
-    println!("\x1b[1m[\x1b[34m{}\x1b[0;1m]\x1b[0m {}", '*', line);
+    println!("{} {}", TERM_SETTINGS.tag(Level::Success, "*"), line);
 }
 
 pub fn info(line: &str) {
-    println!("\x1b[1m[\x1b[32m{}\x1b[0;1m]\x1b[0m {}", '+', line);
+    println!("{} {}", TERM_SETTINGS.tag(Level::Info, "+"), line);
 }
 
 pub fn debug(line: &str) {
-    println!("\x1b[2K\x1b[1m[\x1b[34m{}\x1b[0;1m]\x1b[0m {}", '#', line);
+    println!("{}{} {}", TERM_SETTINGS.clear_line(), TERM_SETTINGS.tag(Level::Debug, "#"), line);
 }
 
 pub fn warn(line: &str) {
-    eprintln!("\x1b[1m[\x1b[33m{}\x1b[0;1m]\x1b[0m {}", '!', line);
+    eprintln!("{} {}", TERM_SETTINGS.tag(Level::Warn, "!"), line);
 }
 
 pub fn error(line: &str) {
-    eprintln!("\x1b[1m[\x1b[31m{}\x1b[0;1m]\x1b[0m {}", '-', line);
+    eprintln!("{} {}", TERM_SETTINGS.tag(Level::Error, "-"), line);
 }
 
 pub struct Prompt {
@@ -293,22 +418,28 @@ impl StackedSpinners {
 impl SpinLogger for StackedSpinners {
     fn log(&mut self, line: &str) {
         self.jump2start();
-        println!("\r\x1b[2K\x1b[1m[\x1b[34m{}\x1b[0;1m]\x1b[0m {}", '*', line);
+        println!("{}{} {}", TERM_SETTINGS.clear_line(), TERM_SETTINGS.tag(Level::Success, "*"), line);
     }
 
     fn debug(&mut self, line: &str) {
         self.jump2start();
-        println!("\r\x1b[2K\x1b[1m[\x1b[34m{}\x1b[0;1m]\x1b[0m {}", '#', line);
+        println!("{}{} {}", TERM_SETTINGS.clear_line(), TERM_SETTINGS.tag(Level::Debug, "#"), line);
     }
 
     fn error(&mut self, line: &str) {
         self.jump2start();
-        println!("\r\x1b[2K\x1b[1m[\x1b[31m{}\x1b[0;1m]\x1b[0m {}", '-', line);
+        println!("{}{} {}", TERM_SETTINGS.clear_line(), TERM_SETTINGS.tag(Level::Error, "-"), line);
     }
 
     fn status(&mut self, status: String) {
         self.error(&format!("TODO: set status: {:?}", status));
     }
+
+    fn confirm(&mut self, question: &str) -> Result<bool> {
+        self.jump2start();
+        print!("{}", TERM_SETTINGS.clear_line());
+        utils::no_else_yes(question)
+    }
 }
 
 pub struct PrefixedLogger<'a, T: 'a + SpinLogger> {
@@ -341,4 +472,35 @@ impl<'a, T: SpinLogger> SpinLogger for PrefixedLogger<'a, T> {
     fn status(&mut self, status: String) {
         self.s.status(format!("{:50}: {}", self.prefix, status))
     }
+
+    fn confirm(&mut self, question: &str) -> Result<bool> {
+        self.s.confirm(&format!("{:50}: {}", self.prefix, question))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_theme_tags() {
+        let dark = TermSettings::resolve(None, false, true);
+        assert_eq!(dark.tag(Level::Warn, "!"), "\x1b[1m[\x1b[33m!\x1b[0;1m]\x1b[0m");
+        let light = TermSettings::resolve(Some("light"), false, true);
+        assert_eq!(light.tag(Level::Warn, "!"), "\x1b[1m[\x1b[35m!\x1b[0;1m]\x1b[0m");
+        let none = TermSettings::resolve(Some("none"), false, true);
+        assert_eq!(none.tag(Level::Warn, "!"), "[!]");
+        assert_eq!(none.paint(Level::Error, "high"), "high");
+    }
+
+    #[test]
+    fn test_colors_disabled() {
+        let no_color = TermSettings::resolve(Some("light"), true, true);
+        assert_eq!(no_color.theme(), Theme::None);
+        assert!(no_color.indicate_progress);
+
+        let pipe = TermSettings::resolve(Some("dark"), false, false);
+        assert_eq!(pipe.theme(), Theme::None);
+        assert_eq!(pipe.clear_line(), "");
+    }
 }
@@ -3,6 +3,7 @@ use crate::api::Client;
 use crate::config::Config;
 use crate::engine;
 use crate::paths;
+use sn0int_common::api::MAX_BATCH_SIZE;
 use std::fs;
 use std::path::PathBuf;
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -92,17 +93,38 @@ impl AutoUpdater {
         if latest.time != self.registry {
             let mut outdated = 0;
 
-            for module in modules {
-                if module.is_private() {
-                    debug!("{} is a private module, skipping", module.canonical());
-                    continue;
-                }
-
-                let installed = module.version();
-                if let Ok(infos) = client.query_module(&module.id()) {
+            let modules = modules.into_iter()
+                .filter(|module| {
+                    if module.is_private() {
+                        debug!("{} is a private module, skipping", module.canonical());
+                        false
+                    } else {
+                        true
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            for chunk in modules.chunks(MAX_BATCH_SIZE) {
+                let ids = chunk.iter()
+                    .map(|module| module.id())
+                    .collect();
+
+                let infos = match client.query_modules(ids) {
+                    Ok(infos) => infos,
+                    Err(err) => {
+                        debug!("Failed to query modules: {}", err);
+                        continue;
+                    },
+                };
+
+                for infos in infos.modules {
                     debug!("Latest version: {:?}", infos);
 
-                    if let Some(latest) = infos.latest {
+                    let module = chunk.iter()
+                        .find(|module| module.author() == infos.author && module.name() == infos.name);
+
+                    if let (Some(module), Some(latest)) = (module, infos.latest) {
+                        let installed = module.version();
                         if installed != latest {
                             debug!("Outdated: {}: {:?} -> {:?}", module.canonical(), installed, latest);
                             outdated += 1;
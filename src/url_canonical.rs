@@ -0,0 +1,146 @@
+use crate::errors::*;
+
+use url::Url;
+
+
+fn is_unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"-._~".contains(&b)
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Decode percent encoded characters that don't need to be encoded and
+/// uppercase the hex digits of everything else, eg. `%7e%2f` becomes `~%2F`
+fn normalize_percent(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = String::with_capacity(s.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(hi), Some(lo)) = (hex_value(bytes[i+1]), hex_value(bytes[i+2])) {
+                let b = hi << 4 | lo;
+                if is_unreserved(b) {
+                    out.push(b as char);
+                } else {
+                    out.push_str(&format!("%{:02X}", b));
+                }
+                i += 3;
+                continue;
+            }
+        }
+
+        // everything else is copied as is, this keeps multi byte characters intact
+        let len = s[i..].chars().next().map(|c| c.len_utf8()).unwrap_or(1);
+        out.push_str(&s[i..i+len]);
+        i += len;
+    }
+
+    out
+}
+
+/// Bring a url into a canonical form so equivalent urls compare equal. The
+/// scheme and host are lowercased, default ports are removed and `.` and `..`
+/// in the path are resolved. Unnecessary percent encoding is decoded, empty
+/// query strings and fragments are removed. If `sort_query` is set the query
+/// parameters are sorted, this is optional because some servers depend on the
+/// order.
+pub fn canonical(url: &str, sort_query: bool) -> Result<String> {
+    let mut url = Url::parse(url.trim())?;
+
+    if url.cannot_be_a_base() {
+        bail!("Url can't be canonicalized: {:?}", url.as_str());
+    }
+
+    let path = normalize_percent(url.path());
+    url.set_path(&path);
+
+    let query = url.query().map(|query| {
+        let mut params = query.split('&')
+            .filter(|param| !param.is_empty())
+            .map(normalize_percent)
+            .collect::<Vec<_>>();
+        if sort_query {
+            params.sort();
+        }
+        params.join("&")
+    });
+    match query {
+        Some(ref query) if !query.is_empty() => url.set_query(Some(query)),
+        _ => url.set_query(None),
+    }
+
+    if url.fragment() == Some("") {
+        url.set_fragment(None);
+    }
+
+    Ok(url.into_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn canon(url: &str) -> String {
+        canonical(url, false).unwrap()
+    }
+
+    #[test]
+    fn test_scheme_host_case() {
+        assert_eq!(canon("HTTPS://WWW.Example.COM/Path"), "https://www.example.com/Path");
+    }
+
+    #[test]
+    fn test_default_ports() {
+        assert_eq!(canon("http://example.com:80/"), "http://example.com/");
+        assert_eq!(canon("https://example.com:443/"), "https://example.com/");
+        assert_eq!(canon("https://example.com:8443/"), "https://example.com:8443/");
+        assert_eq!(canon("http://example.com:443/"), "http://example.com:443/");
+    }
+
+    #[test]
+    fn test_empty_path() {
+        assert_eq!(canon("https://example.com"), "https://example.com/");
+    }
+
+    #[test]
+    fn test_dot_segments() {
+        assert_eq!(canon("https://example.com/a/./b/../c"), "https://example.com/a/c");
+        assert_eq!(canon("https://example.com/../../a"), "https://example.com/a");
+        assert_eq!(canon("https://example.com/a/%2e%2E/b"), "https://example.com/b");
+    }
+
+    #[test]
+    fn test_percent_encoding() {
+        assert_eq!(canon("https://example.com/%7euser/%2f%41"), "https://example.com/~user/%2FA");
+        assert_eq!(canon("https://example.com/?q=%3d%7E"), "https://example.com/?q=%3D~");
+        assert_eq!(canon("https://example.com/100%"), "https://example.com/100%");
+        assert_eq!(canon("https://example.com/ä"), "https://example.com/%C3%A4");
+    }
+
+    #[test]
+    fn test_query() {
+        assert_eq!(canon("https://example.com/?"), "https://example.com/");
+        assert_eq!(canon("https://example.com/?b=2&&a=1&"), "https://example.com/?b=2&a=1");
+        assert_eq!(canonical("https://example.com/?b=2&a=1&a=0", true).unwrap(), "https://example.com/?a=0&a=1&b=2");
+    }
+
+    #[test]
+    fn test_fragment() {
+        assert_eq!(canon("https://example.com/#"), "https://example.com/");
+        assert_eq!(canon("https://example.com/#top"), "https://example.com/#top");
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(canonical("example.com/foo", false).is_err());
+        assert!(canonical("mailto:root@example.com", false).is_err());
+    }
+}
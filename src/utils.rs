@@ -1,5 +1,6 @@
 use crate::errors::*;
 
+use crate::term::{Level, TERM_SETTINGS};
 use std::io::{self, Write};
 use std::str::FromStr;
 
@@ -12,12 +13,38 @@ pub fn read_line() -> Result<String> {
 }
 
 pub fn question(text: &str) -> Result<String> {
-    print!("\x1b[1m[\x1b[34m?\x1b[0;1m]\x1b[0m {}: ", text);
+    print!("{} {}: ", TERM_SETTINGS.tag(Level::Prompt, "?"), text);
     io::stdout().flush()?;
 
     read_line()
 }
 
+/// Like `question`, but the answer isn't echoed, eg. for passphrases
+#[cfg(unix)]
+pub fn question_secret(text: &str) -> Result<String> {
+    use nix::sys::termios::{self, LocalFlags, SetArg};
+    use std::os::unix::io::AsRawFd;
+
+    let fd = io::stdin().as_raw_fd();
+    let original = termios::tcgetattr(fd)?;
+
+    let mut hidden = original.clone();
+    hidden.local_flags.remove(LocalFlags::ECHO);
+    termios::tcsetattr(fd, SetArg::TCSANOW, &hidden)?;
+
+    let answer = question(text);
+    termios::tcsetattr(fd, SetArg::TCSANOW, &original)?;
+    println!();
+
+    answer
+}
+
+/// Echo can't be disabled on this platform, the answer is visible
+#[cfg(not(unix))]
+pub fn question_secret(text: &str) -> Result<String> {
+    question(text)
+}
+
 pub fn question_opt(text: &str) -> Result<Option<String>> {
     let answer = question(text)?;
 
@@ -3,22 +3,57 @@ pub use chrootable_https::{Client, HttpClient, Resolver};
 use std::collections::{HashMap, HashSet};
 use std::time::Duration;
 use std::ops::Deref;
+use std::sync::Arc;
 use crate::errors::*;
 use crate::hlua::AnyLuaValue;
+use crate::connector::{self, HttpPool, TlsProfile};
 use crate::engine::ctx::State;
 use serde_json;
 use rand::{Rng, thread_rng};
 use rand::distributions::Alphanumeric;
 use std::fmt;
 use serde::Serialize;
-use crate::engine::structs::LuaMap;
+use crate::engine::structs::{lua_bytes, LuaList, LuaMap};
 use crate::json::LuaJsonValue;
+use crate::sigv4::AwsSigV4;
+use crate::sockets;
+use chrono::Utc;
 use chrootable_https::http::uri::Parts;
-use chrootable_https::{Request, Body, Uri};
+use chrootable_https::{Request, Body, Response, Uri};
+use chrootable_https::header::CONTENT_LENGTH;
+use futures::{future, Future, Stream};
+use hyper::Chunk;
+use tokio::prelude::{FutureExt, StreamExt};
+use tokio::runtime::Runtime;
 use serde_urlencoded;
 use base64;
+use url::Url;
 
 
+/// Returned if a response body exceeds `max_body_bytes`
+#[derive(Debug, Fail, PartialEq)]
+#[fail(display="Response body exceeds max_body_bytes ({} bytes)", limit)]
+pub struct BodyTooLarge {
+    pub limit: usize,
+}
+
+fn body_too_large(limit: usize) -> Error {
+    BodyTooLarge { limit }.into()
+}
+
+/// Collect a response body, fail with `BodyTooLarge` once it exceeds `limit`
+pub fn read_body_limited<S>(body: S, limit: usize) -> impl Future<Item=Vec<u8>, Error=Error> + Send
+    where S: Stream<Item=Chunk, Error=Error> + Send
+{
+    body.fold(Vec::new(), move |mut buf, chunk| {
+        if buf.len() + chunk.len() > limit {
+            return Err(body_too_large(limit));
+        }
+        buf.extend_from_slice(&chunk);
+        Ok(buf)
+    })
+}
+
 pub fn url_set_qs<S: Serialize + fmt::Debug>(url: Uri, query: &S) -> Result<Uri> {
     let mut parts = Parts::from(url);
 
@@ -39,6 +74,7 @@ pub fn url_set_qs<S: Serialize + fmt::Debug>(url: Uri, query: &S) -> Result<Uri>
 pub struct HttpSession {
     id: String,
     pub cookies: CookieJar,
+    pub pool: Option<Arc<HttpPool>>,
 }
 
 impl HttpSession {
@@ -47,11 +83,12 @@ impl HttpSession {
         (id.clone(), HttpSession {
             id,
             cookies: CookieJar::default(),
+            pool: None,
         })
     }
 }
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Clone, Deserialize)]
 pub struct RequestOptions {
     query: Option<HashMap<String, String>>,
     headers: Option<HashMap<String, String>>,
@@ -61,6 +98,14 @@ pub struct RequestOptions {
     form: Option<serde_json::Value>,
     body: Option<String>,
     timeout: Option<u64>,
+    connect_timeout: Option<u64>,
+    read_timeout: Option<u64>,
+    max_body_bytes: Option<usize>,
+    aws_sigv4: Option<AwsSigV4>,
+    ca_bundle: Option<String>,
+    ca_bundle_only: Option<bool>,
+    tls_profile: Option<TlsProfile>,
+    sni: Option<String>,
 }
 
 impl RequestOptions {
@@ -69,8 +114,63 @@ impl RequestOptions {
         let x = serde_json::from_value(x.into())?;
         Ok(x)
     }
+
+    /// The user agent that is going to be sent
+    pub fn user_agent(&self) -> String {
+        self.user_agent.clone()
+            .unwrap_or_else(default_user_agent)
+    }
 }
 
+/// Options of http_head, the remaining keys are regular request options
+#[derive(Debug, Default, Deserialize)]
+pub struct HeadOptions {
+    pub session: Option<String>,
+    pub max_redirects: Option<usize>,
+    #[serde(flatten)]
+    pub request: RequestOptions,
+}
+
+impl HeadOptions {
+    pub fn try_from(x: AnyLuaValue) -> Result<HeadOptions> {
+        let x = LuaJsonValue::from(x);
+        let x = serde_json::from_value(x.into())?;
+        Ok(x)
+    }
+}
+
+/// Options of http_range, the remaining keys are regular request options
+#[derive(Debug, Default, Deserialize)]
+pub struct RangeOptions {
+    pub session: Option<String>,
+    #[serde(flatten)]
+    pub request: RequestOptions,
+}
+
+impl RangeOptions {
+    pub fn try_from(x: AnyLuaValue) -> Result<RangeOptions> {
+        let x = LuaJsonValue::from(x);
+        let x = serde_json::from_value(x.into())?;
+        Ok(x)
+    }
+}
+
+/// How much of the response body should be read
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ReadBody {
+    Skip,
+    Full,
+    /// Stop reading after this many bytes
+    Prefix(usize),
+}
+
+/// Redirects that are followed by http_head unless `max_redirects` is set
+pub const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+/// Responses are aborted once the body grows past this limit, unless the
+/// request sets a different one
+pub const DEFAULT_MAX_BODY_BYTES: usize = 32 * 1024 * 1024;
+
 pub fn default_user_agent() -> String {
     format!("sn0int/{}", env!("CARGO_PKG_VERSION"))
 }
@@ -88,6 +188,16 @@ pub struct HttpRequest {
     user_agent: Option<String>,
     body: Option<ReqBody>,
     timeout: Option<Duration>,
+    /// Until the response headers arrived
+    connect_timeout: Option<Duration>,
+    /// Between two chunks of the response body
+    read_timeout: Option<Duration>,
+    max_body_bytes: Option<usize>,
+    aws_sigv4: Option<AwsSigV4>,
+    ca_bundle: Option<String>,
+    ca_bundle_only: Option<bool>,
+    tls_profile: Option<TlsProfile>,
+    sni: Option<String>,
 }
 
 impl HttpRequest {
@@ -95,7 +205,9 @@ impl HttpRequest {
         let cookies = session.cookies.clone();
 
         let user_agent = options.user_agent.or_else(|| Some(default_user_agent()));
-        let timeout = options.timeout.map(Duration::from_millis);
+        let timeout = sockets::millis(options.timeout);
+        let connect_timeout = sockets::millis(options.connect_timeout);
+        let read_timeout = sockets::millis(options.read_timeout);
 
         let mut request = HttpRequest {
             session: session.id.clone(),
@@ -108,6 +220,14 @@ impl HttpRequest {
             user_agent,
             body: None,
             timeout,
+            connect_timeout,
+            read_timeout,
+            max_body_bytes: options.max_body_bytes,
+            aws_sigv4: options.aws_sigv4,
+            ca_bundle: options.ca_bundle,
+            ca_bundle_only: options.ca_bundle_only,
+            tls_profile: options.tls_profile,
+            sni: options.sni,
         };
 
         if let Some(json) = options.json {
@@ -125,7 +245,9 @@ impl HttpRequest {
         request
     }
 
-    pub fn send(&self, state: &State) -> Result<LuaMap> {
+    /// Send the request and read the response, `read_body` selects how much
+    /// of the body is read. Cookies are added to the session.
+    fn fetch(&self, state: &State, method: &str, extra_headers: &[(&str, &str)], read_body: ReadBody) -> Result<Response> {
         let mut url = self.url.parse::<Uri>()?;
 
         // set query string
@@ -133,9 +255,29 @@ impl HttpRequest {
             url = url_set_qs(url, query)?;
         }
 
+        // point the url to the sni, the connection still goes to the original host
+        let connect_to = match &self.sni {
+            Some(sni) => {
+                connector::validate_sni(sni)?;
+                let mut parts = Parts::from(url);
+                let authority = match parts.authority.take() {
+                    Some(authority) => authority,
+                    None => bail!("Url has no host to connect to"),
+                };
+                let sni_authority = match authority.port_part() {
+                    Some(port) => format!("{}:{}", sni, port),
+                    None => sni.to_string(),
+                };
+                parts.authority = Some(sni_authority.parse()?);
+                url = Uri::from_parts(parts)?;
+                Some(authority)
+            },
+            None => None,
+        };
+
         // start setting up request
         let mut req = Request::builder();
-        req.method(self.method.as_str());
+        req.method(method);
         req.uri(url);
 
         let mut observed_headers = HashSet::new();
@@ -182,49 +324,301 @@ impl HttpRequest {
             }
         }
 
+        for (k, v) in extra_headers {
+            req.header(*k, *v);
+        }
+
+        // the server gets the host of the url unless the module sets one
+        if let Some(authority) = &connect_to {
+            if !observed_headers.contains("host") {
+                req.header("Host", authority.as_str());
+            }
+        }
+
         // finalize request
         let body = match self.body {
-            Some(ReqBody::Raw(ref x))  => { Body::from(x.clone()) },
+            Some(ReqBody::Raw(ref x))  => { Some(x.clone()) },
             Some(ReqBody::Form(ref x)) => {
                 // if Content-Type is not set, set header
                 if !observed_headers.contains("content-type") {
                     req.header("Content-Type", "application/x-www-form-urlencoded");
                 }
-                Body::from(serde_urlencoded::to_string(x)?)
+                Some(serde_urlencoded::to_string(x)?)
             },
             Some(ReqBody::Json(ref x)) => {
                 // if Content-Type is not set, set header
                 if !observed_headers.contains("content-type") {
                     req.header("Content-Type", "application/json");
                 }
-                Body::from(serde_json::to_string(x)?)
+                Some(serde_json::to_string(x)?)
             },
-            None => Body::empty(),
+            None => None,
+        };
+        let payload = body.clone().unwrap_or_default();
+        let mut req = match body {
+            Some(body) => req.body(Body::from(body))?,
+            None => req.body(Body::empty())?,
         };
-        let req = req.body(body)?;
 
-        // send request
+        // log before signing, so the credentials don't end up in the logs
         debug!("Sending http request: {:?}", req);
 
-        let res = state.http().request(req)
-            .with_timeout(self.timeout)
-            .wait_for_response()?;
+        // sign request
+        if let Some(ref aws) = self.aws_sigv4 {
+            aws.sign(&mut req, payload.as_bytes(), &Utc::now())?;
+        }
+
+        let max_body_bytes = self.max_body_bytes.unwrap_or(DEFAULT_MAX_BODY_BYTES);
+        // a custom trust store, tls profile or sni needs its own client, so it
+        // can't use the pool
+        let custom = if self.ca_bundle.is_some() || self.ca_bundle_only.is_some() || self.tls_profile.is_some() || connect_to.is_some() {
+            let tls = state.tls().with_overrides(&self.ca_bundle, self.ca_bundle_only)
+                .with_profile(self.tls_profile);
+            let resolver = state.dns_config().clone();
+            let proxy = state.proxy().cloned();
+            Some(match &connect_to {
+                Some(authority) => connector::sni_client(resolver, proxy, &tls, authority.host())?,
+                None => connector::client(resolver, proxy, &tls)?,
+            })
+        } else {
+            None
+        };
+        let pool = match custom {
+            Some(_) => None,
+            None => state.http_pool(&self.session),
+        };
+        let client = match (&custom, &pool) {
+            (Some(client), _) => client,
+            (None, Some(pool)) => pool.client(),
+            (None, None) => state.http(),
+        };
 
-        // map result to LuaMap
-        let mut resp = LuaMap::new();
-        resp.insert_num("status", f64::from(res.status));
+        let connect_timeout = self.connect_timeout;
+        let read_timeout = self.read_timeout;
+
+        let fut = client.request(req)
+            .map_err(Error::from);
+        let fut: Box<Future<Item=_, Error=Error> + Send> = match connect_timeout {
+            Some(timeout) => Box::new(fut.timeout(timeout)
+                .map_err(|err| match err.into_inner() {
+                    Some(err) => err,
+                    _ => format_err!("Connection timed out"),
+                })),
+            None => Box::new(fut),
+        };
+
+        let fut = fut
+            .and_then(move |res| {
+                let (parts, body) = res.into_parts();
+
+                let prefix = match read_body {
+                    ReadBody::Skip => {
+                        return future::Either::A(future::ok(Response::from((parts, Chunk::from(Vec::new()).into_bytes()))));
+                    },
+                    ReadBody::Full => None,
+                    ReadBody::Prefix(len) => Some(len),
+                };
+
+                // bail early if the server announces a body that is too large
+                let announced = parts.headers.get(CONTENT_LENGTH)
+                    .and_then(|x| x.to_str().ok())
+                    .and_then(|x| x.parse::<usize>().ok());
+                if let (Some(len), None) = (announced, prefix) {
+                    if len > max_body_bytes {
+                        return future::Either::A(future::err(body_too_large(max_body_bytes)));
+                    }
+                }
+
+                let body: Box<Stream<Item=_, Error=Error> + Send> = match read_timeout {
+                    Some(timeout) => Box::new(body.timeout(timeout)
+                        .map_err(|err| match err.into_inner() {
+                            Some(err) => Error::from(err),
+                            _ => format_err!("Read timed out"),
+                        })),
+                    None => Box::new(body.map_err(Error::from)),
+                };
+                let body = match prefix {
+                    // the rest of a body is never downloaded once the prefix is complete
+                    Some(len) => {
+                        let mut received = 0;
+                        future::Either::A(body
+                            .take_while(move |chunk| {
+                                let more = received < len;
+                                received += chunk.len();
+                                Ok(more)
+                            })
+                            .fold(Vec::new(), move |mut buf, chunk| {
+                                let missing = len - buf.len();
+                                buf.extend_from_slice(&chunk[..chunk.len().min(missing)]);
+                                Ok::<_, Error>(buf)
+                            }))
+                    },
+                    None => future::Either::B(read_body_limited(body, max_body_bytes)),
+                };
+                let body = body
+                    .map(|body| Response::from((parts, Chunk::from(body).into_bytes())));
+                future::Either::B(body)
+            });
+
+        let fut: Box<Future<Item=_, Error=Error> + Send> = match self.timeout {
+            Some(timeout) => Box::new(fut.timeout(timeout)
+                .map_err(|err| match err.into_inner() {
+                    Some(err) => err,
+                    _ => format_err!("Request timed out"),
+                })),
+            None => Box::new(fut),
+        };
+
+        // pooled connections are driven by the runtime of the session
+        let res = match pool {
+            Some(pool) => pool.block_on(fut)?,
+            None => Runtime::new()?.block_on(fut)?,
+        };
 
         for cookie in &res.cookies {
             HttpRequest::register_cookies_on_state(&self.session, state, cookie);
         }
 
+        Ok(res)
+    }
+
+    /// Send the request and return the response without converting it for lua
+    pub fn send_raw(&self, state: &State) -> Result<Response> {
+        self.fetch(state, &self.method, &[], ReadBody::Full)
+    }
+
+    pub fn send(&self, state: &State) -> Result<LuaMap> {
+        let res = self.send_raw(state)?;
+
+        // map result to LuaMap
+        let mut resp = LuaMap::new();
+        resp.insert_num("status", f64::from(res.status));
+
         let mut headers = LuaMap::new();
         for (key, value) in res.headers {
             headers.insert_str(key.to_lowercase(), value);
         }
         resp.insert("headers", headers);
 
+        // every Set-Cookie header, the headers map only keeps one
+        let mut cookies = LuaList::new();
+        for cookie in res.cookies {
+            cookies.push_str(cookie);
+        }
+        resp.insert("cookies", cookies);
+
         resp.insert_str("text", String::from_utf8_lossy(&res.body));
+        // the raw body, eg. for compressed files
+        resp.insert("binary", lua_bytes(&res.body));
+
+        Ok(resp)
+    }
+
+    /// Read the status and headers of the url without downloading the body.
+    /// Servers that reject HEAD are asked for the first byte with a ranged
+    /// GET instead. Redirects are followed up to `max_redirects` times.
+    pub fn head(mut self, state: &State, max_redirects: usize) -> Result<LuaMap> {
+        let mut redirects = LuaList::new();
+        let mut hops = 0;
+        let mut ranged = false;
+
+        let (res, method) = loop {
+            let res = if ranged {
+                self.fetch(state, "GET", &[("Range", "bytes=0-0")], ReadBody::Skip)?
+            } else {
+                let res = self.fetch(state, "HEAD", &[], ReadBody::Skip)?;
+                if res.status == 405 || res.status == 501 {
+                    ranged = true;
+                    self.fetch(state, "GET", &[("Range", "bytes=0-0")], ReadBody::Skip)?
+                } else {
+                    res
+                }
+            };
+            let method = if ranged { "GET" } else { "HEAD" };
+
+            let location = match res.headers.get("location") {
+                Some(location) if is_redirect(res.status) && hops < max_redirects => location,
+                _ => break (res, method),
+            };
+
+            let next = Url::parse(&self.url)?
+                .join(location)
+                .context("Invalid redirect")?;
+
+            let mut redirect = LuaMap::new();
+            redirect.insert_str("url", self.url.clone());
+            redirect.insert_num("status", f64::from(res.status));
+            redirects.push(redirect);
+
+            // the query string is part of the first url only
+            self.url = next.into_string();
+            self.query = None;
+            hops += 1;
+        };
+
+        let mut resp = LuaMap::new();
+        resp.insert_num("status", f64::from(res.status));
+        resp.insert_str("url", self.url);
+        resp.insert_str("method", method);
+        resp.insert("redirects", redirects);
+
+        if let Some(len) = content_length(res.status, &res.headers) {
+            resp.insert_num("content_length", len as f64);
+        }
+        if let Some(content_type) = res.headers.get("content-type") {
+            resp.insert_str("content_type", content_type.as_str());
+        }
+
+        let mut headers = LuaMap::new();
+        for (key, value) in res.headers {
+            headers.insert_str(key.to_lowercase(), value);
+        }
+        resp.insert("headers", headers);
+
+        Ok(resp)
+    }
+
+    /// Fetch the bytes from `start` to `end`, both inclusive. If the server
+    /// ignores the Range header the start of the full response is read and
+    /// the range is cut out of it.
+    pub fn range(&self, state: &State, start: u64, end: u64) -> Result<LuaMap> {
+        if start > end {
+            bail!("Range start can't be after the end: {}-{}", start, end);
+        }
+
+        // a full response needs to be read up to the end of the range
+        let max_body_bytes = self.max_body_bytes.unwrap_or(DEFAULT_MAX_BODY_BYTES);
+        if end >= max_body_bytes as u64 {
+            return Err(body_too_large(max_body_bytes));
+        }
+        let prefix = end as usize + 1;
+
+        let range = format!("bytes={}-{}", start, end);
+        let res = self.fetch(state, "GET", &[("Range", &range)], ReadBody::Prefix(prefix))?;
+
+        let honored = res.status == 206;
+        let body = if res.status == 200 {
+            let start = (start as usize).min(res.body.len());
+            &res.body[start..]
+        } else {
+            &res.body[..]
+        };
+
+        let mut resp = LuaMap::new();
+        resp.insert_num("status", f64::from(res.status));
+        resp.insert("honored", AnyLuaValue::LuaBoolean(honored));
+        if let Some(len) = content_length(res.status, &res.headers) {
+            resp.insert_num("content_length", len as f64);
+        }
+
+        resp.insert_str("text", String::from_utf8_lossy(body));
+        resp.insert("binary", lua_bytes(body));
+
+        let mut headers = LuaMap::new();
+        for (key, value) in res.headers {
+            headers.insert_str(key.to_lowercase(), value);
+        }
+        resp.insert("headers", headers);
 
         Ok(resp)
     }
@@ -247,6 +641,24 @@ impl HttpRequest {
     }
 }
 
+fn is_redirect(status: u16) -> bool {
+    [301, 302, 303, 307, 308].contains(&status)
+}
+
+/// The size of the resource, a partial response knows it from Content-Range
+fn content_length(status: u16, headers: &HashMap<String, String>) -> Option<u64> {
+    if status == 206 || status == 416 {
+        let range = headers.get("content-range")?;
+        let idx = range.rfind('/')?;
+        return range[idx+1..].trim().parse().ok();
+    }
+
+    headers.get("content-length")?
+        .trim()
+        .parse()
+        .ok()
+}
+
 impl HttpRequest {
     pub fn try_from(x: AnyLuaValue) -> Result<HttpRequest> {
         let x = LuaJsonValue::from(x);
@@ -2,31 +2,48 @@ use crate::errors::*;
 
 use crate::channel;
 use crate::cmd::run_cmd::Params;
-use crate::db::{Database, DbChange, Family};
+use crate::db::{self, Conflict, Database, DbChange, Family, InsertOptions};
+use crate::db::confidence::Confidence;
+use crate::db::findings::{Finding, NewFinding};
+use crate::db::state::ModuleState;
 use crate::db::ttl::Ttl;
 use crate::engine::{self, Module};
-use crate::engine::isolation::Supervisor;
+use crate::engine::isolation::{SpawnOptions, Supervisor};
+use crate::fifo::{Fifo, PrefixedFifo};
+use crate::filesystem;
+use failure::Fail;
 use crate::models::*;
+use crate::proxy::ProxyChain;
 use serde_json;
 use crate::shell::Readline;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::result;
 use std::sync::{mpsc, Arc, Mutex};
 use std::time::Duration;
 use std::thread;
 use std::io::{Stdin, BufRead, BufReader};
-use std::net::SocketAddr;
-use crate::term::{Spinner, StackedSpinners, SpinLogger};
+use std::path::PathBuf;
+use crate::term::{self, Spinner, StackedSpinners, SpinLogger};
 use threadpool::ThreadPool;
 
 
 type DbSender = mpsc::Sender<result::Result<Option<i32>, String>>;
+type QuerySender = mpsc::Sender<result::Result<Vec<serde_json::Value>, String>>;
+type StateSender = mpsc::Sender<result::Result<Option<serde_json::Value>, String>>;
+type CounterSender = mpsc::Sender<result::Result<i32, String>>;
+type BatchSender = mpsc::Sender<result::Result<Vec<result::Result<Option<i32>, String>>, String>>;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Event {
     Log(LogEvent),
     Database(DatabaseEvent),
+    InsertBatch(InsertBatchEvent),
+    Query(QueryEvent),
+    State(StateEvent),
+    Counter(CounterEvent),
     Stdio(StdioEvent),
+    Fs(FsEvent),
     Exit(ExitEvent),
 }
 
@@ -35,6 +52,10 @@ pub enum Event2 {
     Start,
     Log(LogEvent),
     Database((DatabaseEvent, DbSender)),
+    InsertBatch((InsertBatchEvent, BatchSender)),
+    Query((QueryEvent, QuerySender)),
+    State((StateEvent, StateSender)),
+    Counter((CounterEvent, CounterSender)),
     Exit(ExitEvent),
 }
 
@@ -81,10 +102,57 @@ pub trait EventWithCallback {
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum ExitEvent {
     Ok,
-    Err(String),
+    Err(ModuleError),
     SetupFailed(String),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all="lowercase")]
+pub enum ModuleErrorKind {
+    /// The module failed to load or has no run function
+    Load,
+    /// A lua error was raised while the module was running
+    Runtime,
+    /// A function reported an error that the module didn't handle
+    Function,
+    /// The run function returned an error message
+    Returned,
+}
+
+/// Why a module failed, this is sent from the sandboxed process to the supervisor
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModuleError {
+    pub kind: ModuleErrorKind,
+    pub message: String,
+    pub traceback: Option<String>,
+    /// This is only known by the supervisor and is set after the module exited
+    pub target: Option<String>,
+}
+
+impl ModuleError {
+    pub fn new<I: Into<String>>(kind: ModuleErrorKind, message: I) -> ModuleError {
+        ModuleError {
+            kind,
+            message: message.into(),
+            traceback: None,
+            target: None,
+        }
+    }
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, w: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ModuleErrorKind::Load => write!(w, "Failed to load module: {}", self.message),
+            ModuleErrorKind::Runtime => write!(w, "Execution failed: {}", self.message),
+            ModuleErrorKind::Function => write!(w, "{}", self.message),
+            ModuleErrorKind::Returned => write!(w, "Script returned error: {:?}", self.message),
+        }
+    }
+}
+
+impl Fail for ModuleError {}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum LogEvent {
     Info(String),
@@ -108,8 +176,11 @@ impl LogEvent {
 pub enum DatabaseEvent {
     Insert(Insert),
     InsertTtl((Insert, i32)),
+    InsertOpts((Insert, InsertOptions)),
     Select((Family, String)),
     Update((String, Update)),
+    Delete((Family, i32)),
+    Report(NewFinding),
 }
 
 impl EventWithCallback for DatabaseEvent {
@@ -120,22 +191,117 @@ impl EventWithCallback for DatabaseEvent {
     }
 }
 
+/// Structured events that are written to the fifo, if one is configured
+#[derive(Debug, Serialize)]
+#[serde(tag="event", rename_all="lowercase")]
+enum FifoEvent<'a> {
+    Insert {
+        id: i32,
+        object: &'a Insert,
+    },
+    Update {
+        id: i32,
+        label: &'a str,
+        update: &'a Update,
+    },
+    Delete {
+        family: &'a Family,
+        id: i32,
+    },
+    Finding {
+        finding: &'a NewFinding,
+    },
+    Error {
+        error: &'a ModuleError,
+    },
+}
+
+/// Settings of the current run that database events need
+#[derive(Clone, Copy, Default)]
+pub struct InsertContext<'a> {
+    pub verbose: u64,
+    pub review: Option<Review>,
+    pub fifo: Option<&'a PrefixedFifo<'a>>,
+}
+
+/// Inserts are held back and shown to the user before they are committed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Review {
+    /// Ask for every insert on the terminal
+    Confirm,
+    /// Only log what would be inserted, nothing is committed
+    Log,
+}
+
+impl Review {
+    /// Returns true if the insert should be committed
+    fn approve<T: SpinLogger>(self, object: &Insert, spinner: &mut T, db: &Database) -> bool {
+        let label = match object.printable(db) {
+            Ok(obj) => obj.to_string(),
+            Err(_) => format!("{:?}", object),
+        };
+
+        match self {
+            Review::Confirm => match spinner.confirm(&format!("Insert {}?", label)) {
+                Ok(approved) => approved,
+                Err(err) => {
+                    spinner.error(&format!("Not inserting {}: {}", label, err));
+                    false
+                },
+            },
+            Review::Log => {
+                spinner.log(&format!("Would insert: {}", label));
+                false
+            },
+        }
+    }
+}
+
 impl DatabaseEvent {
-    pub fn insert<T: SpinLogger>(object: Insert, ttl: Option<i32>, tx: DbSender, spinner: &mut T, db: &Database, verbose: u64) {
-        if verbose >= 1 {
+    /// New entities get the confidence as it is, existing entities are only
+    /// lowered if this is explicitly requested
+    fn set_confidence<T: SpinLogger>(object: &Insert, id: i32, options: &InsertOptions, created: bool, spinner: &mut T, db: &Database) {
+        let confidence = match options.confidence {
+            Some(confidence) => confidence,
+            None => return,
+        };
+
+        let result = if created {
+            Confidence::create(object, id, confidence, db)
+        } else if options.conflict == Conflict::Skip {
+            return;
+        } else {
+            Confidence::set(object, id, confidence, options.override_confidence, db)
+        };
+
+        if let Err(err) = result {
+            spinner.error(&format!("Failed to set confidence: {:?}", err));
+        }
+    }
+
+    fn insert<T: SpinLogger>(object: Insert, ttl: Option<i32>, options: InsertOptions, spinner: &mut T, db: &Database, ctx: InsertContext<'_>) -> result::Result<Option<i32>, String> {
+        if ctx.verbose >= 1 {
             spinner.debug(&format!("Inserting: {:?}", object));
         }
 
-        let result = db.insert_generic(object.clone());
+        // a rejected insert looks like an entity that is out of scope
+        if let Some(review) = ctx.review {
+            if !review.approve(&object, spinner, db) {
+                return Ok(None);
+            }
+        }
+
+        let result = db.insert_generic_with(object.clone(), options.conflict);
         debug!("{:?} => {:?}", object, result);
 
-        let result = match result {
+        match result {
             Ok(Some((DbChange::Insert, id))) => {
                 if let Some(ttl) = ttl {
                     if let Err(err) = Ttl::create(&object, id, ttl, db) {
                         spinner.error(&format!("Failed to set ttl: {:?}", err));
                     }
                 }
+                Self::set_confidence(&object, id, &options, true, spinner, db);
 
                 // TODO: replace id with actual object(?)
                 if let Ok(obj) = object.printable(db) {
@@ -143,6 +309,13 @@ impl DatabaseEvent {
                 } else {
                     spinner.error(&format!("Failed to query necessary fields for {:?}", object));
                 }
+
+                if let Some(fifo) = ctx.fifo {
+                    fifo.send(FifoEvent::Insert {
+                        id,
+                        object: &object,
+                    });
+                }
                 Ok(Some(id))
             },
             Ok(Some((DbChange::Update(update), id))) => {
@@ -151,11 +324,20 @@ impl DatabaseEvent {
                         spinner.error(&format!("Failed to set ttl: {:?}", err));
                     }
                 }
+                Self::set_confidence(&object, id, &options, false, spinner, db);
 
                 // TODO: replace id with actual object(?)
                 match object.label(&db) {
                     Ok(label) => {
                         spinner.log(&format!("Updating {} ({})", label, update));
+
+                        if let Some(fifo) = ctx.fifo {
+                            fifo.send(FifoEvent::Update {
+                                id,
+                                label: &label,
+                                update: &update,
+                            });
+                        }
                     },
                     Err(err) => {
                         // TODO: this should be unreachable
@@ -170,6 +352,7 @@ impl DatabaseEvent {
                         spinner.error(&format!("Failed to set ttl: {:?}", err));
                     }
                 }
+                Self::set_confidence(&object, id, &options, false, spinner, db);
 
                 Ok(Some(id))
             },
@@ -179,26 +362,29 @@ impl DatabaseEvent {
                 spinner.error(&err);
                 Err(err)
             },
-        };
-
-        tx.send(result).expect("Failed to send db result to channel");
+        }
     }
 
-    pub fn apply<T: SpinLogger>(self, tx: DbSender, spinner: &mut T, db: &Database, verbose: u64) {
-        match self {
-            DatabaseEvent::Insert(object) => Self::insert(object, None, tx, spinner, db, verbose),
-            DatabaseEvent::InsertTtl((object, ttl)) => Self::insert(object, Some(ttl), tx, spinner, db, verbose),
+    pub fn apply<T: SpinLogger>(self, tx: DbSender, spinner: &mut T, module: &Module, db: &Database, ctx: InsertContext<'_>) {
+        let result = match self {
+            DatabaseEvent::Insert(object) => Self::insert(object, None, InsertOptions::default(), spinner, db, ctx),
+            DatabaseEvent::InsertTtl((object, ttl)) => Self::insert(object, Some(ttl), InsertOptions::default(), spinner, db, ctx),
+            DatabaseEvent::InsertOpts((object, options)) => Self::insert(object, None, options, spinner, db, ctx),
             DatabaseEvent::Select((family, value)) => {
-                let result = db.get_opt(&family, &value)
-                    .map_err(|e| e.to_string());
-
-                tx.send(result).expect("Failed to send db result to channel");
+                db.get_opt(&family, &value)
+                    .map_err(|e| e.to_string())
             },
             DatabaseEvent::Update((object, update)) => {
-                if verbose >= 1 {
+                if ctx.verbose >= 1 {
                     spinner.debug(&format!("Updating: {:?}", update));
                 }
 
+                if !db.update_target_exists(&update) {
+                    spinner.error(&format!("Skipping update of {:?}, entity doesn't exist", object));
+                    tx.send(Ok(None)).expect("Failed to send db result to channel");
+                    return;
+                }
+
                 let result = db.update_generic(&update);
                 debug!("{:?}: {:?} => {:?}", object, update, result);
                 let result = result
@@ -210,11 +396,190 @@ impl DatabaseEvent {
                 } else {
                     // TODO: bring this somewhat closer to upsert code
                     spinner.log(&format!("Updating {:?} ({})", object, update));
+
+                    if let Some(fifo) = ctx.fifo {
+                        fifo.send(FifoEvent::Update {
+                            id: update.id(),
+                            label: &format!("{:?}", object),
+                            update: &update,
+                        });
+                    }
+                }
+
+                result
+            },
+            DatabaseEvent::Delete((family, id)) => {
+                if ctx.verbose >= 1 {
+                    spinner.debug(&format!("Deleting: {:?} #{}", family, id));
+                }
+
+                match db.delete_id(&family, id) {
+                    Ok(true) => {
+                        spinner.log(&format!("Deleted {:?} #{}", family, id));
+
+                        if let Some(fifo) = ctx.fifo {
+                            fifo.send(FifoEvent::Delete {
+                                family: &family,
+                                id,
+                            });
+                        }
+                        Ok(Some(id))
+                    },
+                    Ok(false) => Ok(None),
+                    Err(err) => {
+                        let err = err.to_string();
+                        spinner.error(&err);
+                        Err(err)
+                    },
+                }
+            },
+            DatabaseEvent::Report(finding) => {
+                let result = Finding::create(&finding, &module.canonical(), db)
+                    .map(Some)
+                    .map_err(|e| e.to_string());
+
+                match result {
+                    Ok(_) => {
+                        spinner.log(&format!("Finding [{}]: {}", finding.severity, finding.title));
+
+                        if let Some(fifo) = ctx.fifo {
+                            fifo.send(FifoEvent::Finding {
+                                finding: &finding,
+                            });
+                        }
+                    },
+                    Err(ref err) => spinner.error(&format!("Failed to report finding: {}", err)),
                 }
 
-                tx.send(result).expect("Failed to send db result to channel");
+                result
             },
+        };
+
+        tx.send(result).expect("Failed to send db result to channel");
+    }
+}
+
+/// Insert many entities at once, the result of every row is reported individually
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InsertBatchEvent {
+    pub objects: Vec<Insert>,
+    pub options: InsertOptions,
+}
+
+impl EventWithCallback for InsertBatchEvent {
+    type Payload = Vec<result::Result<Option<i32>, String>>;
+
+    fn with_callback(self, tx: mpsc::Sender<result::Result<Self::Payload, String>>) -> Event2 {
+        Event2::InsertBatch((self, tx))
+    }
+}
+
+impl InsertBatchEvent {
+    pub fn apply<T: SpinLogger>(self, tx: BatchSender, spinner: &mut T, db: &Database, batch_size: usize, ctx: InsertContext<'_>) {
+        let options = self.options;
+        let result = db.batched(self.objects, batch_size, |object| {
+            Ok(DatabaseEvent::insert(object, None, options, spinner, db, ctx))
+        }).map_err(|e| e.to_string());
+
+        if let Err(ref err) = result {
+            spinner.error(&format!("Failed to insert batch: {}", err));
         }
+
+        tx.send(result).expect("Failed to send db result to channel");
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QueryEvent {
+    pub family: Family,
+    #[serde(rename="where", default)]
+    pub fields: HashMap<String, serde_json::Value>,
+    pub limit: Option<usize>,
+    /// Only return entities with a higher id, used by db_cursor
+    #[serde(default)]
+    pub after: Option<i32>,
+}
+
+impl EventWithCallback for QueryEvent {
+    type Payload = Vec<serde_json::Value>;
+
+    fn with_callback(self, tx: mpsc::Sender<result::Result<Self::Payload, String>>) -> Event2 {
+        Event2::Query((self, tx))
+    }
+}
+
+impl QueryEvent {
+    pub fn apply(self, tx: QuerySender, db: &Database) {
+        let result = db::Filter::from_fields(&self.fields)
+            .and_then(|filter| db.query(&self.family, &filter, self.limit, self.after))
+            .map_err(|e| e.to_string());
+
+        tx.send(result).expect("Failed to send db result to channel");
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum StateEvent {
+    Get(String),
+    Set((String, Option<serde_json::Value>)),
+}
+
+impl EventWithCallback for StateEvent {
+    type Payload = Option<serde_json::Value>;
+
+    fn with_callback(self, tx: mpsc::Sender<result::Result<Self::Payload, String>>) -> Event2 {
+        Event2::State((self, tx))
+    }
+}
+
+impl StateEvent {
+    pub fn apply(self, tx: StateSender, module: &Module, db: &Database) {
+        let name = module.canonical();
+        let result = match self {
+            StateEvent::Get(key) => ModuleState::get(db, &name, &key),
+            StateEvent::Set((key, value)) => ModuleState::set(db, &name, module.version(), &key, value.as_ref())
+                .map(|_| None),
+        };
+
+        tx.send(result.map_err(|e| e.to_string()))
+            .expect("Failed to send db result to channel");
+    }
+}
+
+/// Counters are shared by every execution of a run, they are only touched by
+/// the main thread so updates from parallel workers can't get lost
+pub type Counters = BTreeMap<String, i32>;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum CounterEvent {
+    Inc((String, i32)),
+    Get(String),
+}
+
+impl EventWithCallback for CounterEvent {
+    type Payload = i32;
+
+    fn with_callback(self, tx: mpsc::Sender<result::Result<Self::Payload, String>>) -> Event2 {
+        Event2::Counter((self, tx))
+    }
+}
+
+impl CounterEvent {
+    fn inc(counters: &mut Counters, name: String, by: i32) -> Result<i32> {
+        let counter = counters.entry(name).or_insert(0);
+        *counter = counter.checked_add(by)
+            .ok_or_else(|| format_err!("Counter overflow"))?;
+        Ok(*counter)
+    }
+
+    pub fn apply(self, tx: CounterSender, counters: &mut Counters) {
+        let result = match self {
+            CounterEvent::Inc((name, by)) => Self::inc(counters, name, by),
+            CounterEvent::Get(name) => Ok(counters.get(&name).cloned().unwrap_or(0)),
+        };
+
+        tx.send(result.map_err(|e| e.to_string()))
+            .expect("Failed to send counter result to channel");
     }
 }
 
@@ -246,53 +611,173 @@ impl StdioEvent {
     }
 }
 
-pub fn spawn(rl: &mut Readline, module: &Module, args: Vec<(serde_json::Value, Option<String>)>, params: &Params, proxy: Option<SocketAddr>, options: HashMap<String, String>) -> usize {
-    // This function hangs if args is empty, so return early if that's the case
-    if args.is_empty() {
-        return 0;
+#[derive(Debug, Serialize, Deserialize)]
+pub enum FsEvent {
+    Read(String),
+    List(String),
+    Lines(String),
+    ReadLine(String),
+}
+
+impl FsEvent {
+    fn sandbox(sandbox: &Option<PathBuf>) -> Result<&PathBuf> {
+        sandbox.as_ref()
+            .ok_or_else(|| format_err!("No sandbox directory configured, set filesystem.sandbox in sn0int.toml"))
+    }
+
+    pub fn apply(self, supervisor: &mut Supervisor, tx: &EventSender, sandbox: &Option<PathBuf>, readers: &mut filesystem::LineReaders) {
+        match self {
+            FsEvent::Read(path) => {
+                let reply = Self::sandbox(sandbox)
+                    .and_then(|root| filesystem::read(root, &path))
+                    .map_err(|e| e.to_string());
+                supervisor.send_struct(reply, tx);
+            },
+            FsEvent::List(path) => {
+                let reply = Self::sandbox(sandbox)
+                    .and_then(|root| filesystem::list(root, &path))
+                    .map_err(|e| e.to_string());
+                supervisor.send_struct(reply, tx);
+            },
+            FsEvent::Lines(path) => {
+                let reply = Self::sandbox(sandbox)
+                    .and_then(|root| readers.open(root, &path))
+                    .map_err(|e| e.to_string());
+                supervisor.send_struct(reply, tx);
+            },
+            FsEvent::ReadLine(id) => {
+                let reply = readers.next_line(&id)
+                    .map_err(|e| e.to_string());
+                supervisor.send_struct(reply, tx);
+            },
+        }
     }
+}
 
+pub fn spawn<I>(rl: &Readline, module: &Module, args: I, params: &Params, proxy: Option<ProxyChain>, options: HashMap<String, String>, fifo: Option<&Fifo>) -> usize
+    where I: IntoIterator<Item=Result<(serde_json::Value, Option<String>)>>
+{
     let verbose = params.verbose;
     let has_stdin = params.stdin;
     let keyring = rl.keyring().request_keys(&module);
+    let sandbox = match rl.config().filesystem.sandbox() {
+        Ok(sandbox) => sandbox,
+        Err(err) => {
+            term::error(&format!("Failed to resolve sandbox directory: {}", err));
+            None
+        },
+    };
+    let tls = match rl.config().network.tls() {
+        Ok(tls) => tls,
+        Err(err) => {
+            term::error(&format!("Failed to load ca bundle: {}", err));
+            return 1;
+        },
+    };
+    let insert_hook = match rl.config().insert_hook(rl.db().name()) {
+        Ok(insert_hook) => insert_hook,
+        Err(err) => {
+            term::error(&format!("Failed to load insert hook: {}", err));
+            return 1;
+        },
+    };
+    let dns_config = match rl.config().resolver(rl.db().name(), &params.resolvers) {
+        Ok(dns_config) => dns_config,
+        Err(err) => {
+            term::error(&format!("Invalid resolver config: {}", err));
+            return 1;
+        },
+    };
+    let signal_register = rl.signal_register();
 
     let mut stack = StackedSpinners::new();
 
     let (tx, rx) = channel::bounded(1);
     let pool = ThreadPool::new(params.threads);
 
-    let mut expected = 0;
-    for (arg, pretty_arg) in args {
-        let name = match pretty_arg {
-            Some(pretty_arg) => format!("{:?}", pretty_arg),
-            None => module.canonical(),
-        };
-
-        let tx = tx.clone();
-        let module = module.clone();
-        let keyring = keyring.clone();
-        let options = options.clone();
-        let signal_register = rl.signal_register().clone();
-        pool.execute(move || {
-            let tx = EventSender::new(name, tx);
-
+    // args may be a generator that expands to a huge number of targets, so
+    // only pull the next one once a slot in the pool frees up
+    let mut args = args.into_iter();
+    let mut dispatch = |stack: &mut StackedSpinners, errors: &mut usize| -> bool {
+        loop {
             if signal_register.ctrlc_received() {
-                tx.send(Event2::Exit(ExitEvent::Ok));
-                return;
+                return false;
             }
 
-            tx.send(Event2::Start);
-            let event = match engine::isolation::spawn_module(module, &tx, arg, keyring, verbose, has_stdin, proxy, options) {
-                Ok(exit) => exit,
-                Err(err) => ExitEvent::SetupFailed(err.to_string()),
+            let (arg, pretty_arg) = match args.next() {
+                Some(Ok(arg)) => arg,
+                Some(Err(err)) => {
+                    stack.error(&format!("Failed to prepare target: {}", err));
+                    *errors += 1;
+                    continue;
+                },
+                None => return false,
             };
-            tx.send(Event2::Exit(event));
-        });
+
+            let target = pretty_arg.clone();
+            let name = match pretty_arg {
+                Some(pretty_arg) => format!("{:?}", pretty_arg),
+                None => module.canonical(),
+            };
+
+            let tx = tx.clone();
+            let module = module.clone();
+            let keyring = keyring.clone();
+            let proxy = proxy.clone();
+            let tls = tls.clone();
+            let options = options.clone();
+            let insert_hook = insert_hook.clone();
+            let dns_config = dns_config.clone();
+            let sandbox = sandbox.clone();
+            let signal_register = signal_register.clone();
+            pool.execute(move || {
+                let tx = EventSender::new(name, tx);
+
+                if signal_register.ctrlc_received() {
+                    tx.send(Event2::Exit(ExitEvent::Ok));
+                    return;
+                }
+
+                tx.send(Event2::Start);
+                let options = SpawnOptions {
+                    arg,
+                    keyring,
+                    verbose,
+                    has_stdin,
+                    dns_config,
+                    proxy,
+                    tls,
+                    options,
+                    insert_hook,
+                    sandbox,
+                };
+                let event = match engine::isolation::spawn_module(module, &tx, options) {
+                    Ok(ExitEvent::Err(mut err)) => {
+                        err.target = target;
+                        ExitEvent::Err(err)
+                    },
+                    Ok(exit) => exit,
+                    Err(err) => ExitEvent::SetupFailed(err.to_string()),
+                };
+                tx.send(Event2::Exit(event));
+            });
+            return true;
+        }
+    };
+
+    let mut errors = 0;
+    let mut expected = 0;
+    while expected < params.threads * 2 && dispatch(&mut stack, &mut errors) {
         expected += 1;
     }
 
-    let mut errors = 0;
+    // This function hangs if nothing was started, so return early if that's the case
+    if expected == 0 {
+        return errors;
+    }
+
     let mut failed = Vec::new();
+    let mut counters = Counters::new();
     let timeout = Duration::from_millis(100);
     loop {
         select! {
@@ -306,7 +791,20 @@ pub fn spawn(rl: &mut Readline, module: &Module, args: Vec<(serde_json::Value, O
                             stack.add(name, label);
                         },
                         Event2::Log(log) => log.apply(&mut stack.prefixed(name)),
-                        Event2::Database((db, tx)) => db.apply(tx, &mut stack.prefixed(name), rl.db(), verbose),
+                        Event2::Database((db, tx)) => {
+                            let fifo = fifo.map(|fifo| PrefixedFifo::new(fifo, &name));
+                            let ctx = InsertContext { verbose, review: params.review, fifo: fifo.as_ref() };
+                            db.apply(tx, &mut stack.prefixed(name.clone()), module, rl.db(), ctx)
+                        },
+                        Event2::InsertBatch((batch, tx)) => {
+                            let fifo = fifo.map(|fifo| PrefixedFifo::new(fifo, &name));
+                            let batch_size = rl.config().core.insert_batch_size();
+                            let ctx = InsertContext { verbose, review: params.review, fifo: fifo.as_ref() };
+                            batch.apply(tx, &mut stack.prefixed(name.clone()), rl.db(), batch_size, ctx)
+                        },
+                        Event2::Query((query, tx)) => query.apply(tx, rl.db()),
+                        Event2::State((state, tx)) => state.apply(tx, module, rl.db()),
+                        Event2::Counter((counter, tx)) => counter.apply(tx, &mut counters),
                         Event2::Exit(event) => {
                             debug!("Received exit: {:?} -> {:?}", name, event);
                             stack.remove(&name);
@@ -316,12 +814,22 @@ pub fn spawn(rl: &mut Readline, module: &Module, args: Vec<(serde_json::Value, O
                                 errors += 1;
                             }
 
+                            if let (ExitEvent::Err(error), Some(fifo)) = (&event, fifo) {
+                                PrefixedFifo::new(fifo, &name).send(FifoEvent::Error {
+                                    error,
+                                });
+                            }
+
                             if let ExitEvent::SetupFailed(error) = event {
                                 failed.push((name, error));
                             }
 
-                            // if every task reported back, exit
                             expected -= 1;
+                            if dispatch(&mut stack, &mut errors) {
+                                expected += 1;
+                            }
+
+                            // if every task reported back, exit
                             info!("spawn_all is expecting {} more results", expected);
                             if expected == 0 {
                                 break;
@@ -340,6 +848,10 @@ pub fn spawn(rl: &mut Readline, module: &Module, args: Vec<(serde_json::Value, O
         stack.error(&format!("Failed {}: {}", name, fail));
     }
 
+    for (name, value) in &counters {
+        stack.log(&format!("Counter {:?}: {}", name, value));
+    }
+
     stack.clear();
 
     errors
@@ -360,10 +872,15 @@ pub fn spawn_fn<F, T>(label: &str, f: F, clear: bool) -> Result<T>
                 recv(rx) -> msg => match msg.ok() {
                     Some(Event::Log(log)) => log.apply(&mut *spinner),
                     Some(Event::Database(_)) => (),
+                    Some(Event::InsertBatch(_)) => (),
+                    Some(Event::Query(_)) => (),
+                    Some(Event::State(_)) => (),
+                    Some(Event::Counter(_)) => (),
                     Some(Event::Stdio(_)) => (),
+                    Some(Event::Fs(_)) => (),
                     // TODO: refactor
                     Some(Event::Exit(ExitEvent::Ok)) => break,
-                    Some(Event::Exit(ExitEvent::Err(error))) => spinner.error(&error),
+                    Some(Event::Exit(ExitEvent::Err(error))) => spinner.error(&error.to_string()),
                     Some(Event::Exit(ExitEvent::SetupFailed(error))) => spinner.error(&error),
                     None => break, // channel closed
                 },
@@ -389,3 +906,150 @@ pub fn spawn_fn<F, T>(label: &str, f: F, clear: bool) -> Result<T>
 
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters_threaded() {
+        let (tx, rx) = mpsc::channel();
+
+        let threads = (0..8)
+            .map(|_| {
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        let (tx2, rx2) = mpsc::channel();
+                        tx.send(CounterEvent::Inc(("findings".into(), 2)).with_callback(tx2)).unwrap();
+                        rx2.recv().unwrap().unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(tx);
+
+        let mut counters = Counters::new();
+        for event in rx {
+            match event {
+                Event2::Counter((counter, tx)) => counter.apply(tx, &mut counters),
+                _ => panic!("unexpected event"),
+            }
+        }
+
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(counters.get("findings"), Some(&1600));
+
+        let (tx, rx) = mpsc::channel();
+        CounterEvent::Get("missing".into()).apply(tx, &mut counters);
+        assert_eq!(rx.recv().unwrap(), Ok(0));
+
+        let (tx, rx) = mpsc::channel();
+        CounterEvent::Inc(("findings".into(), 2_147_483_647)).apply(tx, &mut counters);
+        assert!(rx.recv().unwrap().is_err());
+        assert_eq!(counters.get("findings"), Some(&1600));
+    }
+
+    /// Records what would be printed and answers every question the same way
+    struct ReviewLogger {
+        approve: bool,
+        lines: Vec<String>,
+    }
+
+    impl SpinLogger for ReviewLogger {
+        fn log(&mut self, line: &str) {
+            self.lines.push(line.to_string());
+        }
+
+        fn debug(&mut self, line: &str) {
+            self.lines.push(line.to_string());
+        }
+
+        fn error(&mut self, line: &str) {
+            self.lines.push(line.to_string());
+        }
+
+        fn status(&mut self, _status: String) {
+        }
+
+        fn confirm(&mut self, question: &str) -> Result<bool> {
+            self.lines.push(question.to_string());
+            Ok(self.approve)
+        }
+    }
+
+    fn review_insert(review: Review, approve: bool, value: &str, db: &Database) -> (result::Result<Option<i32>, String>, Vec<String>) {
+        let mut spinner = ReviewLogger {
+            approve,
+            lines: Vec::new(),
+        };
+        let object = Insert::Domain(NewDomain {
+            value: value.into(),
+        });
+        let result = DatabaseEvent::insert(object, None, InsertOptions::default(), &mut spinner, db, InsertContext { review: Some(review), ..Default::default() });
+        (result, spinner.lines)
+    }
+
+    #[test]
+    fn test_review_inserts() {
+        let db = Database::in_memory().unwrap();
+
+        let (result, lines) = review_insert(Review::Log, true, "example.com", &db);
+        assert_eq!(result, Ok(None));
+        assert_eq!(lines, vec!["Would insert: Domain: \"example.com\""]);
+
+        let (result, lines) = review_insert(Review::Confirm, false, "example.com", &db);
+        assert_eq!(result, Ok(None));
+        assert_eq!(lines, vec!["Insert Domain: \"example.com\"?"]);
+        assert!(db.list::<Domain>().unwrap().is_empty());
+
+        let (result, _) = review_insert(Review::Confirm, true, "example.com", &db);
+        assert!(result.unwrap().is_some());
+        assert_eq!(db.list::<Domain>().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_insert_confidence() {
+        let db = Database::in_memory().unwrap();
+        let mut spinner = ReviewLogger {
+            approve: true,
+            lines: Vec::new(),
+        };
+        let object = Insert::Domain(NewDomain {
+            value: "example.com".into(),
+        });
+        let mut insert = |confidence, conflict, override_confidence| {
+            let options = InsertOptions {
+                conflict,
+                confidence: Some(confidence),
+                override_confidence,
+            };
+            let id = DatabaseEvent::insert(object.clone(), None, options, &mut spinner, &db, InsertContext::default())
+                .unwrap().unwrap();
+            Confidence::get(&object, id, &db).unwrap()
+        };
+
+        assert_eq!(insert(80, Conflict::Update, false), 80);
+        // a lower confidence doesn't replace a higher one
+        assert_eq!(insert(40, Conflict::Update, false), 80);
+        assert_eq!(insert(95, Conflict::Skip, false), 80);
+        assert_eq!(insert(90, Conflict::Update, false), 90);
+        assert_eq!(insert(40, Conflict::Update, true), 40);
+    }
+
+    #[test]
+    fn test_review_mode() {
+        use crate::cmd::run_cmd;
+        use structopt::StructOpt;
+
+        let args = run_cmd::Args::from_iter_safe(&["run", "--review-inserts"]).unwrap();
+        let params = Params::from(args);
+        assert!(params.review.is_some());
+
+        let args = run_cmd::Args::from_iter_safe(&["run"]).unwrap();
+        let params = Params::from(args);
+        assert_eq!(params.review, None);
+    }
+}
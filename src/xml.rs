@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+
+/// A single xml tag, the documents we parse are flat enough that we don't
+/// need a tree
+#[derive(Debug, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    pub attrs: HashMap<String, String>,
+    pub closing: bool,
+}
+
+impl Tag {
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs.get(name).map(|x| x.as_str())
+    }
+}
+
+pub fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(idx) = rest.find('&') {
+        out.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+
+        let entity = &rest[1..end];
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok()
+                .and_then(::std::char::from_u32),
+            _ if entity.starts_with('#') => entity[1..].parse().ok()
+                .and_then(::std::char::from_u32),
+            _ => None,
+        };
+
+        match c {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end+1..];
+            },
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+fn parse_tag(inner: &str) -> Tag {
+    let closing = inner.starts_with('/');
+    let inner = inner.trim_start_matches('/');
+    let inner = inner.trim_end_matches('/');
+
+    let idx = inner.find(char::is_whitespace).unwrap_or(inner.len());
+    let name = inner[..idx].to_string();
+    let mut rest = inner[idx..].trim_start();
+
+    let mut attrs = HashMap::new();
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim().to_string();
+        let value = rest[eq+1..].trim_start();
+
+        let quote = match value.chars().next() {
+            Some(c @ '"') | Some(c @ '\'') => c,
+            _ => break,
+        };
+        let value = &value[1..];
+        let end = match value.find(quote) {
+            Some(end) => end,
+            None => break,
+        };
+
+        attrs.insert(key, unescape(&value[..end]));
+        rest = value[end+1..].trim_start();
+    }
+
+    Tag {
+        name,
+        attrs,
+        closing,
+    }
+}
+
+/// Split a document into tags, text between tags is ignored. Returns None if
+/// the document ends in the middle of a tag.
+pub fn next_tag(input: &str) -> Option<(Option<Tag>, &str)> {
+    let start = input.find('<')?;
+    let input = &input[start..];
+
+    for (open, close) in &[("<!--", "-->"), ("<?", "?>"), ("<!", ">")] {
+        if input.starts_with(open) {
+            let end = input.find(close)?;
+            return Some((None, &input[end+close.len()..]));
+        }
+    }
+
+    // attribute values may contain '>'
+    let mut quote = None;
+    for (i, c) in input.char_indices().skip(1) {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, '>') => return Some((Some(parse_tag(&input[1..i])), &input[i+1..])),
+            _ => (),
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("a &amp; b &lt;&gt; &#65;&#x42; &foo; &"), "a & b <> AB &foo; &");
+    }
+}
@@ -0,0 +1,422 @@
+//! Convert between yaml documents and json values with serde_yaml. Types are
+//! mapped like this:
+//!
+//! - null, booleans, integers and floats are detected by serde_yaml, everything
+//!   else is a string. Timestamps are kept as strings
+//! - `.inf` and `.nan` can't be represented and are kept as strings
+//! - aliases are replaced with a copy of their anchor and `<<` merge keys are
+//!   applied
+//! - keys are always strings, collections can't be used as keys. If a key is
+//!   repeated the last value wins
+use crate::errors::*;
+
+use serde::Deserialize;
+use serde_json::{self, Map, Number, Value};
+use serde_yaml::{self, Mapping};
+use std::collections::HashMap;
+use yaml_rust::parser::{Event, EventReceiver, Parser};
+
+
+/// Protect against deeply nested documents and alias bombs
+const MAX_DEPTH: usize = 128;
+const MAX_ALIAS_NODES: u64 = 1_000_000;
+
+/// Measures the document before serde_yaml expands the aliases, an alias bomb
+/// would run out of memory long before the decoded value could be checked
+#[derive(Default)]
+struct Limits {
+    /// Number of nodes every anchor expands to
+    anchors: HashMap<usize, u64>,
+    /// Anchor and number of nodes of every collection that is still open
+    open: Vec<(usize, u64)>,
+    aliased: u64,
+    too_deep: bool,
+}
+
+impl Limits {
+    fn add(&mut self, anchor: usize, nodes: u64) {
+        if anchor > 0 {
+            self.anchors.insert(anchor, nodes);
+        }
+        if let Some((_, parent)) = self.open.last_mut() {
+            *parent = parent.saturating_add(nodes);
+        }
+    }
+
+    fn check(text: &str) -> Result<()> {
+        let mut limits = Limits::default();
+        Parser::new(text.chars()).load(&mut limits, true)?;
+
+        if limits.too_deep {
+            bail!("Document is nested too deeply");
+        }
+        if limits.aliased > MAX_ALIAS_NODES {
+            bail!("Aliases expand to too many nodes");
+        }
+        Ok(())
+    }
+}
+
+impl EventReceiver for Limits {
+    fn on_event(&mut self, ev: Event) {
+        match ev {
+            Event::Scalar(_, _, anchor, _) => self.add(anchor, 1),
+            Event::Alias(anchor) => {
+                let nodes = self.anchors.get(&anchor).cloned().unwrap_or(1);
+                self.aliased = self.aliased.saturating_add(nodes);
+                self.add(0, nodes);
+            },
+            Event::SequenceStart(anchor) | Event::MappingStart(anchor) => {
+                self.open.push((anchor, 1));
+                if self.open.len() > MAX_DEPTH {
+                    self.too_deep = true;
+                }
+            },
+            Event::SequenceEnd | Event::MappingEnd => {
+                if let Some((anchor, nodes)) = self.open.pop() {
+                    self.add(anchor, nodes);
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+fn key_to_string(key: serde_yaml::Value) -> Result<String> {
+    match key {
+        serde_yaml::Value::Null => Ok("null".to_string()),
+        serde_yaml::Value::Bool(b) => Ok(b.to_string()),
+        serde_yaml::Value::Number(n) => Ok(n.to_string()),
+        serde_yaml::Value::String(s) => Ok(s),
+        _ => bail!("Collections can't be used as keys"),
+    }
+}
+
+/// Values of `<<` are merged into the mapping, keys that are set explicitly win
+fn to_object(map: Mapping) -> Result<Map<String, Value>> {
+    let mut obj = Map::new();
+    let mut merge = Vec::new();
+
+    for (key, value) in map {
+        if key.as_str() != Some("<<") {
+            obj.insert(key_to_string(key)?, to_json(value)?);
+            continue;
+        }
+
+        match value {
+            serde_yaml::Value::Mapping(map) => merge.push(map),
+            serde_yaml::Value::Sequence(list) => for x in list {
+                match x {
+                    serde_yaml::Value::Mapping(map) => merge.push(map),
+                    _ => bail!("Merge keys need a mapping or a list of mappings"),
+                }
+            },
+            _ => bail!("Merge keys need a mapping or a list of mappings"),
+        }
+    }
+
+    for map in merge {
+        for (key, value) in to_object(map)? {
+            obj.entry(key).or_insert(value);
+        }
+    }
+
+    Ok(obj)
+}
+
+fn to_json(v: serde_yaml::Value) -> Result<Value> {
+    Ok(match v {
+        serde_yaml::Value::Null => Value::Null,
+        serde_yaml::Value::Bool(b) => Value::Bool(b),
+        serde_yaml::Value::Number(n) => {
+            if let Some(x) = n.as_i64() {
+                Value::from(x)
+            } else if let Some(x) = n.as_u64() {
+                Value::from(x)
+            } else {
+                // .inf and .nan can't be represented
+                n.as_f64()
+                    .and_then(Number::from_f64)
+                    .map(Value::Number)
+                    .unwrap_or_else(|| Value::String(n.to_string()))
+            }
+        },
+        serde_yaml::Value::String(s) => Value::String(s),
+        serde_yaml::Value::Sequence(list) => Value::Array(list.into_iter()
+            .map(to_json)
+            .collect::<Result<_>>()?),
+        serde_yaml::Value::Mapping(map) => Value::Object(to_object(map)?),
+    })
+}
+
+/// Parse all documents in the input
+pub fn decode(text: &str) -> Result<Vec<Value>> {
+    Limits::check(text)?;
+
+    serde_yaml::Deserializer::from_str(text)
+        .map(|doc| {
+            let v = serde_yaml::Value::deserialize(doc)?;
+            to_json(v)
+        })
+        .collect()
+}
+
+/// Encode a value as a single block style document
+pub fn encode(v: &Value) -> Result<String> {
+    let text = serde_yaml::to_string(v)?;
+    // serde_yaml starts every document with a marker
+    let text = text.trim_start_matches("---\n");
+    Ok(format!("{}\n", text.trim_end_matches('\n')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn decode1(text: &str) -> Value {
+        let mut docs = decode(text).unwrap();
+        assert_eq!(docs.len(), 1, "{:?}", docs);
+        docs.remove(0)
+    }
+
+    #[test]
+    fn test_block_collections() {
+        let v = decode1(r#"
+# service manifest
+name: web
+replicas: 3
+ports:
+- 80
+- 443
+env:
+  DEBUG: false
+  ratio: 0.5
+  empty:
+volumes:
+  - name: data
+    path: /var/lib/data # trailing comment
+  - name: logs
+"#);
+        assert_eq!(v, json!({
+            "name": "web",
+            "replicas": 3,
+            "ports": [80, 443],
+            "env": {
+                "DEBUG": false,
+                "ratio": 0.5,
+                "empty": null,
+            },
+            "volumes": [
+                {"name": "data", "path": "/var/lib/data"},
+                {"name": "logs"},
+            ],
+        }));
+    }
+
+    #[test]
+    fn test_scalars() {
+        let v = decode1(r#"
+a: ~
+b: 0x1f
+c: -12
+d: 1e3
+e: 2019-03-24
+f: "quoted\tstring é"
+g: 'it''s'
+h: !!str 123
+i: .inf
+j: plain text
+  that continues
+k: http://example.com/#frag
+"#);
+        assert_eq!(v, json!({
+            "a": null,
+            "b": 31,
+            "c": -12,
+            "d": 1000.0,
+            "e": "2019-03-24",
+            "f": "quoted\tstring é",
+            "g": "it's",
+            "h": "123",
+            "i": ".inf",
+            "j": "plain text that continues",
+            "k": "http://example.com/#frag",
+        }));
+    }
+
+    #[test]
+    fn test_block_scalars() {
+        let v = decode1("literal: |\n  line 1\n    indented\n\n  line 3\nfolded: >-\n  a\n  b\n\n  c\nkeep: |+\n  x\n\nlast: end\n");
+        assert_eq!(v, json!({
+            "literal": "line 1\n  indented\n\nline 3\n",
+            "folded": "a b\nc",
+            "keep": "x\n\n",
+            "last": "end",
+        }));
+    }
+
+    #[test]
+    fn test_flow_collections() {
+        let v = decode1(r#"{"a": [1, two, {b: c}], d: [], e: {}, f: [x: 1]}"#);
+        assert_eq!(v, json!({
+            "a": [1, "two", {"b": "c"}],
+            "d": [],
+            "e": {},
+            "f": [{"x": 1}],
+        }));
+    }
+
+    #[test]
+    fn test_anchors_and_merge() {
+        let v = decode1(r#"
+base: &base
+  image: nginx
+  port: 80
+web:
+  <<: *base
+  port: 8080
+list: [&x 1, *x]
+"#);
+        assert_eq!(v, json!({
+            "base": {"image": "nginx", "port": 80},
+            "web": {"image": "nginx", "port": 8080},
+            "list": [1, 1],
+        }));
+    }
+
+    #[test]
+    fn test_multiple_documents() {
+        let docs = decode("%YAML 1.2\n---\na: 1\n...\n---\n- b\n--- c\n").unwrap();
+        assert_eq!(docs, vec![json!({"a": 1}), json!(["b"]), json!("c")]);
+
+        assert_eq!(decode("---\n---\n").unwrap(), vec![Value::Null, Value::Null]);
+        assert!(decode("# nothing here\n").unwrap().is_empty());
+    }
+
+    /// Examples from chapter 2 of the yaml 1.2 spec
+    #[test]
+    fn test_spec_examples() {
+        // 2.3 mapping scalars to sequences
+        assert_eq!(decode1("american:\n  - Boston Red Sox\n  - Detroit Tigers\nnational:\n  - New York Mets\n"), json!({
+            "american": ["Boston Red Sox", "Detroit Tigers"],
+            "national": ["New York Mets"],
+        }));
+
+        // 2.4 sequence of mappings
+        assert_eq!(decode1("-\n  name: Mark McGwire\n  hr:   65\n-\n  name: Sammy Sosa\n  hr:   63\n"), json!([
+            {"name": "Mark McGwire", "hr": 65},
+            {"name": "Sammy Sosa", "hr": 63},
+        ]));
+
+        // 2.6 mapping of mappings
+        assert_eq!(decode1("Mark McGwire: {hr: 65, avg: 0.278}\nSammy Sosa: {\n    hr: 63,\n    avg: 0.288\n  }\n"), json!({
+            "Mark McGwire": {"hr": 65, "avg": 0.278},
+            "Sammy Sosa": {"hr": 63, "avg": 0.288},
+        }));
+
+        // 2.7 two documents in a stream
+        assert_eq!(decode("# Ranking of 1998 home runs\n---\n- Mark McGwire\n- Sammy Sosa\n\n# Team ranking\n---\n- Chicago Cubs\n").unwrap(), vec![
+            json!(["Mark McGwire", "Sammy Sosa"]),
+            json!(["Chicago Cubs"]),
+        ]);
+
+        // 2.10 node for Sammy Sosa appears twice
+        assert_eq!(decode1("---\nhr:\n  - Mark McGwire\n  - &SS Sammy Sosa\nrbi:\n  - *SS\n  - Ken Griffey\n"), json!({
+            "hr": ["Mark McGwire", "Sammy Sosa"],
+            "rbi": ["Sammy Sosa", "Ken Griffey"],
+        }));
+
+        // 2.16 indentation determines scope
+        assert_eq!(decode1("name: Mark McGwire\naccomplishment: >\n  Mark set a major league\n  home run record in 1998.\nstats: |\n  65 Home Runs\n  0.278 Batting Average\n"), json!({
+            "name": "Mark McGwire",
+            "accomplishment": "Mark set a major league home run record in 1998.\n",
+            "stats": "65 Home Runs\n0.278 Batting Average\n",
+        }));
+
+        // 2.17 quoted scalars
+        assert_eq!(decode1("unicode: \"Sosa did fine.\\u263A\"\ncontrol: \"\\b1998\\t1999\\t2000\\n\"\nsingle: '\"Howdy!\" he cried.'\nquoted: ' # Not a ''comment''.'\n"), json!({
+            "unicode": "Sosa did fine.\u{263A}",
+            "control": "\u{8}1998\t1999\t2000\n",
+            "single": "\"Howdy!\" he cried.",
+            "quoted": " # Not a 'comment'.",
+        }));
+
+        // 2.22 timestamps are kept as strings
+        assert_eq!(decode1("canonical: 2001-12-15T02:59:43.1Z\ndate: 2002-12-14\n"), json!({
+            "canonical": "2001-12-15T02:59:43.1Z",
+            "date": "2002-12-14",
+        }));
+
+        // 2.25 explicit keys
+        assert_eq!(decode1("--- !!set\n? Mark McGwire\n? Sammy Sosa\n? Ken Griff\n"), json!({
+            "Mark McGwire": null,
+            "Sammy Sosa": null,
+            "Ken Griff": null,
+        }));
+
+        // 2.11 collections can't be keys
+        assert!(decode("? - Detroit Tigers\n  - Chicago cubs\n:\n  - 2001-07-23\n").is_err());
+    }
+
+    #[test]
+    fn test_invalid() {
+        for text in &[
+            "a: 1\n  b: 2\n",
+            "a: b: c\n",
+            "a: [1, 2\n",
+            "a: \"unterminated\n",
+            "a: *missing\n",
+            "- a\nb: c\n",
+            "a: !!int nope\n",
+        ] {
+            let err = decode(text).unwrap_err();
+            assert!(err.to_string().contains(" at line "), "{:?}: {}", text, err);
+        }
+    }
+
+    #[test]
+    fn test_alias_bomb() {
+        let mut text = String::from("a0: &a0 [x, x, x, x, x, x, x, x, x, x]\n");
+        for i in 1..10 {
+            text += &format!("a{}: &a{} [*a{p}, *a{p}, *a{p}, *a{p}, *a{p}, *a{p}, *a{p}, *a{p}, *a{p}, *a{p}]\n", i, i, p=i - 1);
+        }
+        let err = decode(&text).unwrap_err();
+        assert!(err.to_string().contains("too many nodes"), "{}", err);
+
+        let text = format!("{}{}", "[".repeat(MAX_DEPTH + 1), "]".repeat(MAX_DEPTH + 1));
+        let err = decode(&text).unwrap_err();
+        assert!(err.to_string().contains("nested too deeply"), "{}", err);
+    }
+
+    #[test]
+    fn test_encode() {
+        let v = json!({
+            "name": "web",
+            "ports": [80, 443],
+            "env": {"DEBUG": "true", "empty": "", "list": []},
+            "volumes": [{"name": "data", "path": "/var/lib/data"}],
+            "nested": [[1, 2], "- dash", "multi\nline", null],
+        });
+        let text = encode(&v).unwrap();
+        assert_eq!(text, r#"env:
+  DEBUG: "true"
+  empty: ""
+  list: []
+name: web
+nested:
+  - - 1
+    - 2
+  - "- dash"
+  - "multi\nline"
+  - ~
+ports:
+  - 80
+  - 443
+volumes:
+  - name: data
+    path: /var/lib/data
+"#);
+        assert_eq!(decode1(&text), v);
+    }
+}